@@ -0,0 +1,264 @@
+//! Stand-in for a provider CLI in ralph's end-to-end tests (`tests/cli.rs`).
+//! `cargo test` builds it to `target/debug/examples/fake-agent`; point
+//! `RALPH_PROVIDER_CMD` at it and ralph runs it in place of the provider,
+//! appending the prompt as the last argument (or, for a long prompt,
+//! passing it on stdin):
+//!
+//! ```bash
+//! cargo build --example fake-agent
+//! RALPH_PROVIDER_CMD=target/debug/examples/fake-agent FAKE_AGENT_COMPLETE_ON=1 ralph once
+//! ```
+//!
+//! Every option can be given as a flag (inside `RALPH_PROVIDER_CMD`) or as
+//! the `FAKE_AGENT_*` variable named in `--help`. Whatever else it's told to
+//! do, each call first prints a `fake-agent call <n>` message.
+
+use clap::Parser;
+use clap::builder::BoolishValueParser;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "fake-agent",
+    version,
+    about = "Scriptable stand-in for a provider CLI"
+)]
+struct Args {
+    /// File counting calls across iterations (without it every call is call 1)
+    #[arg(long, env = "FAKE_AGENT_STATE")]
+    state: Option<PathBuf>,
+
+    /// Extra stream-json messages to print
+    #[arg(long, env = "FAKE_AGENT_EVENTS", default_value_t = 0)]
+    events: u32,
+
+    /// Also write a `warning <i> of <n>` line to stderr with each extra
+    /// message
+    #[arg(
+        long,
+        env = "FAKE_AGENT_EVENT_WARNINGS",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    event_warnings: bool,
+
+    /// Milliseconds to wait before each extra message
+    #[arg(long, env = "FAKE_AGENT_DELAY_MS", default_value_t = 0)]
+    delay_ms: u64,
+
+    /// File of canned stream-json lines to print
+    #[arg(long, env = "FAKE_AGENT_REPLAY")]
+    replay: Option<PathBuf>,
+
+    /// Print the COMPLETE marker on this call
+    #[arg(long, env = "FAKE_AGENT_COMPLETE_ON")]
+    complete_on: Option<u32>,
+
+    /// Model asked for, echoed back
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Line to write to stderr
+    #[arg(long, env = "FAKE_AGENT_STDERR")]
+    stderr: Option<String>,
+
+    /// Exit code
+    #[arg(long, env = "FAKE_AGENT_EXIT", default_value_t = 0)]
+    exit: u8,
+
+    /// Only use --exit on this call; other calls exit 0
+    #[arg(long, env = "FAKE_AGENT_EXIT_ON")]
+    exit_on: Option<u32>,
+    /// Kill itself with this signal instead of exiting, e.g. 9 (unix);
+    /// --exit-on limits it to one call too
+    #[arg(long, env = "FAKE_AGENT_SIGNAL")]
+    signal: Option<i32>,
+
+    /// Sleep this many seconds before exiting
+    #[arg(long, env = "FAKE_AGENT_HANG")]
+    hang: Option<u64>,
+
+    /// Ignore SIGTERM, so only SIGKILL stops it (unix)
+    #[arg(
+        long,
+        env = "FAKE_AGENT_IGNORE_TERM",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    ignore_term: bool,
+
+    /// Print the prompt it was given as a message
+    #[arg(
+        long,
+        env = "FAKE_AGENT_ECHO_PROMPT",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    echo_prompt: bool,
+
+    /// Print a message with the value of each of these (comma-separated)
+    /// environment variables, or that it's unset
+    #[arg(long, env = "FAKE_AGENT_ECHO_ENV", value_delimiter = ',')]
+    echo_env: Vec<String>,
+
+    /// Before anything else, write this to stderr without a newline and wait
+    /// a minute, like a CLI asking someone to log in
+    #[arg(long, env = "FAKE_AGENT_LOGIN_PROMPT")]
+    login_prompt: Option<String>,
+
+    /// Append a `call <n>` line to this file, e.g. to edit the system prompt
+    /// between iterations
+    #[arg(long, env = "FAKE_AGENT_APPEND_TO")]
+    append_to: Option<PathBuf>,
+
+    /// Start a long-lived background process (like a dev server) and write
+    /// its pid to this file
+    #[arg(long, env = "FAKE_AGENT_CHILD")]
+    child: Option<PathBuf>,
+
+    /// The prompt ralph appends; read from stdin when missing
+    #[arg(allow_hyphen_values = true)]
+    prompt: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let mut args = Args::parse();
+    if args.prompt.is_none() {
+        let mut prompt = String::new();
+        io::stdin()
+            .read_to_string(&mut prompt)
+            .expect("read prompt from stdin");
+        args.prompt = Some(prompt);
+    }
+    if args.ignore_term {
+        ignore_sigterm();
+    }
+
+    if let Some(prompt) = &args.login_prompt {
+        eprint!("{prompt}");
+        let _ = io::stderr().flush();
+        thread::sleep(Duration::from_secs(60));
+        return ExitCode::FAILURE;
+    }
+
+    let call = match &args.state {
+        Some(path) => {
+            let call = fs::read_to_string(path)
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok())
+                .unwrap_or(0)
+                + 1;
+            fs::write(path, format!("{call}\n")).expect("write call counter");
+            call
+        }
+        None => 1,
+    };
+
+    message(&format!("fake-agent call {call}"));
+    if let Some(model) = &args.model {
+        message(&format!("model {model}"));
+    }
+    if args.echo_prompt {
+        message(args.prompt.as_deref().unwrap_or_default());
+    }
+    for name in &args.echo_env {
+        match std::env::var(name) {
+            Ok(value) => message(&format!("{name}={value}")),
+            Err(_) => message(&format!("{name} is unset")),
+        }
+    }
+    if let Some(path) = &args.append_to {
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .expect("open file to append to");
+        writeln!(file, "call {call}").expect("append to file");
+    }
+    for i in 1..=args.events {
+        thread::sleep(Duration::from_millis(args.delay_ms));
+        message(&format!("event {i} of {}", args.events));
+        if args.event_warnings {
+            eprintln!("warning {i} of {}", args.events);
+        }
+    }
+    if let Some(path) = &args.replay {
+        print!("{}", fs::read_to_string(path).expect("read replay file"));
+    }
+    if args.complete_on == Some(call) {
+        println!(
+            "{}",
+            serde_json::json!({
+                "type": "completion",
+                "finalText": "done <promise>COMPLETE</promise>",
+            })
+        );
+    }
+    let _ = io::stdout().flush();
+    if let Some(line) = &args.stderr {
+        eprintln!("{line}");
+    }
+    if let Some(path) = &args.child {
+        // Left running on purpose, like a dev server the agent started.
+        #[allow(clippy::zombie_processes)]
+        let child = Command::new("sleep")
+            .arg("300")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start background process");
+        fs::write(path, child.id().to_string()).expect("write child pid");
+    }
+    if let Some(secs) = args.hang {
+        thread::sleep(Duration::from_secs(secs));
+    }
+
+    match args.exit_on {
+        Some(on) if on != call => ExitCode::SUCCESS,
+        _ => {
+            if let Some(signal) = args.signal {
+                kill_self(signal);
+            }
+            ExitCode::from(args.exit)
+        }
+    }
+}
+
+/// One stream-json assistant message.
+fn message(text: &str) {
+    println!(
+        "{}",
+        serde_json::json!({ "type": "message", "role": "assistant", "text": text })
+    );
+}
+
+#[cfg(unix)]
+fn ignore_sigterm() {
+    // SAFETY: SIG_IGN is a valid disposition and nothing else in this
+    // process installs signal handlers.
+    unsafe { libc::signal(libc::SIGTERM, libc::SIG_IGN) };
+}
+
+#[cfg(not(unix))]
+fn ignore_sigterm() {}
+
+#[cfg(unix)]
+fn kill_self(signal: i32) {
+    // SAFETY: raise(3) has no memory-safety preconditions.
+    unsafe { libc::raise(signal) };
+}
+
+#[cfg(not(unix))]
+fn kill_self(_signal: i32) {}
@@ -0,0 +1,448 @@
+use crate::config;
+use crate::crash;
+use crate::error::{RalphError, Result};
+use crate::exec::{self, Output};
+use crate::reporter;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Once, OnceLock};
+
+/// File name of the audit log inside the config directory.
+pub const AUDIT_FILE: &str = "audit.log";
+
+/// `prev` of the log's first entry, which has no line before it.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Why ralph ran a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// An AI provider working an iteration
+    Provider,
+    /// A task tracker command: `bd`, the sync command, finding the GitHub
+    /// repository
+    Tracker,
+    /// A gate after an iteration
+    Gate,
+    /// Creating, inspecting or removing a `loop --sandbox` worktree
+    Sandbox,
+    /// A step of `ralph upgrade`
+    Upgrade,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Provider => "provider",
+            Kind::Tracker => "tracker",
+            Kind::Gate => "gate",
+            Kind::Sandbox => "sandbox",
+            Kind::Upgrade => "upgrade",
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// RFC 3339 timestamp (UTC)
+    pub ts: String,
+    /// Why the command ran
+    pub kind: Kind,
+    /// The command line, with secret-looking options masked and long
+    /// arguments cut short (see [`crash::sanitize_args`]); for an upgrade
+    /// step that isn't a command, what was done, e.g. `download <url>`
+    pub argv: Vec<String>,
+    /// Environment variables set for the command, as `NAME=***`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Directory it ran in
+    pub cwd: String,
+    /// Its exit code; `None` when it couldn't be started or was stopped
+    /// for running too long
+    pub exit_code: Option<i32>,
+    /// The signal that killed it, if one did (unix)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// Why it couldn't be started or finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The session it ran for, if any
+    pub session: Option<String>,
+    /// SHA-256 of the previous line, or [`GENESIS`]
+    pub prev: String,
+}
+
+impl Entry {
+    fn new(kind: Kind, argv: Vec<String>) -> Entry {
+        Entry {
+            ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            kind,
+            argv: crash::sanitize_args(&argv),
+            env: Vec::new(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            exit_code: None,
+            signal: None,
+            error: None,
+            session: crash::session(),
+            prev: String::new(),
+        }
+    }
+
+    /// The entry for `cmd`, which ended as `result` says.
+    fn command<T: Outcome>(kind: Kind, cmd: &Command, result: &io::Result<T>) -> Entry {
+        let argv = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect();
+        let mut entry = Entry::new(kind, argv);
+        entry.env = cmd
+            .get_envs()
+            .filter(|(_, value)| value.is_some())
+            .map(|(name, _)| format!("{}=***", name.to_string_lossy()))
+            .collect();
+        if let Some(dir) = cmd.get_current_dir() {
+            let dir = std::env::current_dir()
+                .map(|cwd| cwd.join(dir))
+                .unwrap_or_else(|_| dir.to_path_buf());
+            entry.cwd = dir.display().to_string();
+        }
+        match result {
+            Ok(outcome) => {
+                let (code, signal) = outcome.exit();
+                entry.exit_code = Some(code);
+                entry.signal = signal;
+            }
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        entry
+    }
+
+    /// "exit 0", "SIGKILL" or "failed".
+    fn status(&self) -> String {
+        match (self.signal, self.exit_code) {
+            (Some(signal), _) => exec::signal_name(signal),
+            (None, Some(code)) => format!("exit {code}"),
+            (None, None) => "failed".to_string(),
+        }
+    }
+}
+
+/// How a command run through [`run`] ended.
+pub trait Outcome {
+    /// Its exit code and the signal that killed it, if any.
+    fn exit(&self) -> (i32, Option<i32>);
+}
+
+impl Outcome for Output {
+    fn exit(&self) -> (i32, Option<i32>) {
+        (self.code, self.signal)
+    }
+}
+
+impl Outcome for std::process::Output {
+    fn exit(&self) -> (i32, Option<i32>) {
+        exec::exit_status(self.status)
+    }
+}
+
+impl Outcome for i32 {
+    fn exit(&self) -> (i32, Option<i32>) {
+        (*self, None)
+    }
+}
+
+static LOG: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Record commands in the audit log at `path` from now on, or in none with
+/// `None`. Until this is called nothing is recorded, so embedding the
+/// library (or testing it) leaves no log behind. Only the first call has
+/// an effect.
+pub fn set(path: Option<PathBuf>) {
+    let _ = LOG.set(path);
+}
+
+/// `audit.log` in the config directory (`~/.Ralph/`).
+pub fn default_path() -> io::Result<PathBuf> {
+    Ok(config::get_config_dir()?.join(AUDIT_FILE))
+}
+
+/// Run `cmd` through `run` and record it in the audit log as a `kind`
+/// command, however it ends. Returns what `run` did.
+pub fn run<T: Outcome>(
+    kind: Kind,
+    cmd: &mut Command,
+    run: impl FnOnce(&mut Command) -> io::Result<T>,
+) -> io::Result<T> {
+    let result = run(cmd);
+    if let Some(path) = LOG.get().and_then(Option::as_ref) {
+        record(path, Entry::command(kind, cmd, &result));
+    }
+    result
+}
+
+/// Record a `kind` step that isn't a command, such as replacing the
+/// binary: `argv` says what was done, `error` why it failed.
+pub fn action(kind: Kind, argv: &[&str], error: Option<String>) {
+    let Some(path) = LOG.get().and_then(Option::as_ref) else {
+        return;
+    };
+    let mut entry = Entry::new(kind, argv.iter().map(|a| a.to_string()).collect());
+    entry.exit_code = error.is_none().then_some(0);
+    entry.error = error;
+    record(path, entry);
+}
+
+/// Append `entry`, warning (once) when the log can't be written. Nothing
+/// is written while the config directory doesn't exist: commands that
+/// don't create it shouldn't by way of the log.
+fn record(path: &Path, entry: Entry) {
+    if !path.parent().is_some_and(Path::is_dir) {
+        return;
+    }
+    if let Err(e) = append(path, entry) {
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| {
+            reporter::warn(&format!(
+                "Failed to write the audit log {}: {}",
+                path.display(),
+                e
+            ));
+        });
+    }
+}
+
+/// Append `entry` to the log at `path`, chained to the line before it. The
+/// file is locked while its last line is read and the new one written, so
+/// two ralphs appending at once can't fork the chain.
+pub fn append(path: &Path, mut entry: Entry) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    file.lock()?;
+    entry.prev = last_line(&mut file)?.map_or_else(|| GENESIS.to_string(), |l| line_hash(&l));
+    let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())
+}
+
+/// The last line of `file`, read from the end so a long log costs no more
+/// than a short one.
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk = 4096;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let tail = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(newline) = tail.iter().rposition(|b| *b == b'\n') {
+            return Ok(Some(
+                String::from_utf8_lossy(&tail[newline + 1..]).into_owned(),
+            ));
+        }
+        if start == 0 {
+            return Ok((!tail.is_empty()).then(|| String::from_utf8_lossy(tail).into_owned()));
+        }
+        chunk *= 4;
+    }
+}
+
+/// SHA-256 of `line` (without its newline), as the next line's `prev`.
+pub fn line_hash(line: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The entries in the log at `path` recorded since `since` (all of them
+/// without), oldest first. A missing log has none.
+pub fn entries(path: &Path, since: Option<DateTime<Utc>>) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in lines(path)?.iter().enumerate() {
+        let entry = parse(number + 1, line)?;
+        let recent = since.is_none_or(|since| {
+            DateTime::parse_from_rfc3339(&entry.ts).is_ok_and(|ts| ts.to_utc() >= since)
+        });
+        if recent {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// `entries` one per line: when, why, how it ended, where and what ran.
+pub fn render_text(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|e| {
+            let session = e
+                .session
+                .as_deref()
+                .map(|s| format!(" [{s}]"))
+                .unwrap_or_default();
+            format!(
+                "{} {:<8} {:<8}{} {}: {}\n",
+                e.ts,
+                e.kind.name(),
+                e.status(),
+                session,
+                e.cwd,
+                e.argv.join(" ")
+            )
+        })
+        .collect()
+}
+
+/// Check the log at `path`: every line must be an entry whose `prev` is
+/// the hash of the line before it, the first one's [`GENESIS`]. Returns how
+/// many entries there are; an edited, inserted or removed line is an error
+/// naming where the chain breaks.
+pub fn verify(path: &Path) -> Result<usize> {
+    let lines = lines(path)?;
+    let mut expected = GENESIS.to_string();
+    for (number, line) in lines.iter().enumerate() {
+        let number = number + 1;
+        let entry = parse(number, line)?;
+        if entry.prev != expected {
+            return Err(RalphError::Audit(if number == 1 {
+                "line 1 isn't the start of the log; lines were removed from the top".to_string()
+            } else {
+                format!(
+                    "line {number} doesn't follow line {}; the log was edited or lines were removed",
+                    number - 1
+                )
+            }));
+        }
+        expected = line_hash(line);
+    }
+    Ok(lines.len())
+}
+
+fn lines(path: &Path) -> Result<Vec<String>> {
+    match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file).lines().collect::<io::Result<_>>()?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn parse(number: usize, line: &str) -> Result<Entry> {
+    serde_json::from_str(line)
+        .map_err(|e| RalphError::Audit(format!("line {number} isn't an audit entry: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn entry(kind: Kind, argv: &[&str]) -> Entry {
+        Entry::new(kind, argv.iter().map(|a| a.to_string()).collect())
+    }
+
+    #[test]
+    fn entries_are_chained_to_the_line_before() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        append(&path, entry(Kind::Provider, &["claude", "-p", "hi"])).unwrap();
+        append(&path, entry(Kind::Gate, &["sh", "-c", "cargo test"])).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let recorded = entries(&path, None).unwrap();
+        assert_eq!(recorded[0].prev, GENESIS);
+        assert_eq!(recorded[1].prev, line_hash(lines[0]));
+        assert_eq!(verify(&path).unwrap(), 2);
+    }
+
+    #[test]
+    fn edited_and_removed_lines_break_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        for program in ["bd", "git", "sh"] {
+            append(&path, entry(Kind::Tracker, &[program])).unwrap();
+        }
+        let text = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, text.replacen("\"git\"", "\"gut\"", 1)).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 3 doesn't follow line 2"), "{err}");
+
+        let lines: Vec<&str> = text.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[1], lines[2])).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 1 isn't the start of the log"), "{err}");
+
+        fs::write(&path, format!("{}\nnot json\n", lines[0])).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2 isn't an audit entry"), "{err}");
+    }
+
+    #[test]
+    fn a_missing_log_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        assert_eq!(verify(&path).unwrap(), 0);
+        assert!(entries(&path, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn environment_values_and_secrets_are_left_out() {
+        let mut cmd = Command::new("claude");
+        cmd.args(["--api-key", "sk-123", "-p"])
+            .env("ANTHROPIC_API_KEY", "sk-456")
+            .env_remove("DEBUG")
+            .current_dir("/tmp");
+        let entry = Entry::command(Kind::Provider, &cmd, &Ok(0));
+        assert_eq!(entry.argv, ["claude", "--api-key", "***", "-p"]);
+        assert_eq!(entry.env, ["ANTHROPIC_API_KEY=***"]);
+        #[cfg(unix)]
+        assert_eq!(entry.cwd, "/tmp");
+        assert_eq!(entry.exit_code, Some(0));
+
+        let failed: io::Result<i32> = Err(io::Error::new(io::ErrorKind::NotFound, "not found"));
+        let entry = Entry::command(Kind::Provider, &cmd, &failed);
+        assert_eq!(
+            (entry.exit_code, entry.error.as_deref()),
+            (None, Some("not found"))
+        );
+    }
+
+    #[test]
+    fn entries_can_be_limited_to_recent_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(AUDIT_FILE);
+        let mut old = entry(Kind::Upgrade, &["replace", "/usr/local/bin/ralph"]);
+        old.ts = "2020-01-01T00:00:00Z".to_string();
+        old.exit_code = Some(0);
+        append(&path, old).unwrap();
+        let mut new = entry(Kind::Gate, &["sh", "-c", "cargo test"]);
+        new.exit_code = Some(101);
+        new.cwd = "/repo".to_string();
+        new.session = Some("20260101-000000-1".to_string());
+        append(&path, new).unwrap();
+
+        let since = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let recent = entries(&path, Some(since)).unwrap();
+        assert_eq!(recent.len(), 1);
+        let text = render_text(&recent);
+        assert!(
+            text.ends_with(" gate     exit 101 [20260101-000000-1] /repo: sh -c cargo test\n"),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::error::Result;
+use crate::exec::CommandRunner;
+use crate::tracker::Tracker;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Statuses listed to take a [`Snapshot`]; together they cover the backlog.
+pub const STATUSES: &[&str] = &["open", "in_progress", "blocked", "closed"];
+
+/// An issue as it appears in a backlog diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BacklogItem {
+    /// Tracker id
+    pub id: String,
+    /// One-line summary
+    pub title: String,
+    /// Status when the loop ended
+    pub status: String,
+}
+
+/// Every issue in the backlog by id, at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<String, BacklogItem>);
+
+impl Snapshot {
+    /// Snapshot of `items`.
+    pub fn new(items: impl IntoIterator<Item = BacklogItem>) -> Self {
+        Snapshot(items.into_iter().map(|i| (i.id.clone(), i)).collect())
+    }
+
+    /// The issues, by id.
+    pub fn items(&self) -> impl Iterator<Item = &BacklogItem> {
+        self.0.values()
+    }
+}
+
+/// List the whole backlog through the tracker, one status at a time.
+pub fn snapshot(exec: &dyn CommandRunner, tracker: &dyn Tracker) -> Result<Snapshot> {
+    let mut items = Vec::new();
+    for status in STATUSES {
+        items.extend(
+            tracker
+                .list(exec, status)?
+                .into_iter()
+                .map(|i| BacklogItem {
+                    id: i.id,
+                    title: i.title,
+                    status: i.status,
+                }),
+        );
+    }
+    Ok(Snapshot::new(items))
+}
+
+/// What a loop changed in the backlog.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BacklogDiff {
+    /// Issues closed during the loop
+    pub closed: Vec<BacklogItem>,
+    /// Issues that didn't exist before the loop
+    pub created: Vec<BacklogItem>,
+    /// Issues moved to `in_progress` and left there: started but not
+    /// finished
+    pub stalled: Vec<BacklogItem>,
+}
+
+impl BacklogDiff {
+    /// Compare the backlog before and after a loop.
+    pub fn between(before: &Snapshot, after: &Snapshot) -> Self {
+        let mut diff = BacklogDiff::default();
+        for (id, item) in &after.0 {
+            let was = before.0.get(id).map(|i| i.status.as_str());
+            if was.is_none() {
+                diff.created.push(item.clone());
+            }
+            if item.status == "closed" && was.is_some_and(|s| s != "closed") {
+                diff.closed.push(item.clone());
+            }
+            if item.status == "in_progress" && was != Some("in_progress") {
+                diff.stalled.push(item.clone());
+            }
+        }
+        diff
+    }
+
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty() && self.created.is_empty() && self.stalled.is_empty()
+    }
+
+    /// The diff as the lines printed at the end of a loop.
+    pub fn render_text(&self) -> String {
+        if self.is_empty() {
+            return "Backlog unchanged.".to_string();
+        }
+        let mut out = "Backlog changes:".to_string();
+        for (heading, items) in [
+            ("Closed", &self.closed),
+            ("Created", &self.created),
+            ("Started but not finished", &self.stalled),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            let _ = write!(out, "\n  {heading} ({}):", items.len());
+            for item in items {
+                let _ = write!(out, "\n    {}  {}", item.id, item.title);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::ScriptedRunner;
+    use crate::tracker::Beads;
+
+    fn item(id: &str, status: &str) -> BacklogItem {
+        BacklogItem {
+            id: id.to_string(),
+            title: format!("Title {id}"),
+            status: status.to_string(),
+        }
+    }
+
+    #[test]
+    fn snapshot_lists_every_status() {
+        let exec = ScriptedRunner::new()
+            .reply(0, r#"[{"id":"bd-1","title":"One","status":"open"}]"#)
+            .reply(0, "null")
+            .reply(0, "[]")
+            .reply(0, r#"[{"id":"bd-2","title":"Two","status":"closed"}]"#);
+        let snapshot = snapshot(&exec, &Beads).unwrap();
+        assert_eq!(
+            snapshot,
+            Snapshot::new([
+                BacklogItem {
+                    id: "bd-1".to_string(),
+                    title: "One".to_string(),
+                    status: "open".to_string(),
+                },
+                BacklogItem {
+                    id: "bd-2".to_string(),
+                    title: "Two".to_string(),
+                    status: "closed".to_string(),
+                },
+            ])
+        );
+        assert_eq!(exec.calls().len(), 4);
+        assert_eq!(
+            exec.calls()[1],
+            ["bd", "list", "--status", "in_progress", "--json"]
+        );
+    }
+
+    #[test]
+    fn diff_finds_closed_created_and_stalled_issues() {
+        let before = Snapshot::new([
+            item("bd-1", "open"),
+            item("bd-2", "open"),
+            item("bd-3", "in_progress"),
+            item("bd-4", "closed"),
+        ]);
+        let after = Snapshot::new([
+            item("bd-1", "closed"),
+            item("bd-2", "in_progress"),
+            item("bd-3", "in_progress"),
+            item("bd-4", "closed"),
+            item("bd-5", "open"),
+            item("bd-6", "closed"),
+        ]);
+        let diff = BacklogDiff::between(&before, &after);
+        let ids = |items: &[BacklogItem]| items.iter().map(|i| i.id.clone()).collect::<Vec<_>>();
+        // bd-6 was created and closed within the loop: it's new, not "closed".
+        assert_eq!(ids(&diff.closed), ["bd-1"]);
+        assert_eq!(ids(&diff.created), ["bd-5", "bd-6"]);
+        assert_eq!(ids(&diff.stalled), ["bd-2"]);
+        assert_eq!(
+            diff.render_text(),
+            "Backlog changes:\n  \
+             Closed (1):\n    bd-1  Title bd-1\n  \
+             Created (2):\n    bd-5  Title bd-5\n    bd-6  Title bd-6\n  \
+             Started but not finished (1):\n    bd-2  Title bd-2"
+        );
+    }
+
+    #[test]
+    fn unchanged_backlog() {
+        let same = Snapshot::new([item("bd-1", "open")]);
+        let diff = BacklogDiff::between(&same, &same);
+        assert!(diff.is_empty());
+        assert_eq!(diff.render_text(), "Backlog unchanged.");
+    }
+}
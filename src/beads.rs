@@ -0,0 +1,640 @@
+use crate::audit::{self, Kind};
+use crate::error::{RalphError, Result};
+use crate::exec::CommandRunner;
+use crate::exec::Output;
+use crate::reporter;
+use crate::tracker;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A beads issue as reported by `bd show <id> --json`. Only the fields ralph
+/// uses are modelled; unknown fields are ignored so newer bd versions parse.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Issue {
+    /// Issue id, e.g. `bd-a1b2`
+    pub id: String,
+    /// One-line summary
+    pub title: String,
+    /// Full description (may be empty)
+    pub description: String,
+    /// Acceptance criteria (may be empty)
+    pub acceptance_criteria: String,
+    /// `open`, `in_progress`, `blocked`, `closed`, ...
+    pub status: String,
+    /// `task`, `bug`, `feature`, `epic`, ...
+    pub issue_type: String,
+    /// Issues this one depends on or is linked to
+    pub dependencies: Vec<Dependency>,
+    /// Issues depending on this one, e.g. an epic's children (`bd show` only)
+    pub dependents: Vec<Dependency>,
+    /// RFC 3339 timestamp, for closed issues
+    pub closed_at: Option<String>,
+}
+
+/// An edge from an issue to something it depends on.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Dependency {
+    /// Id of the issue depended on
+    #[serde(alias = "depends_on_id")]
+    pub id: String,
+    /// Status of the issue depended upon, when bd includes it
+    pub status: Option<String>,
+    /// `blocks`, `parent-child`, `related`, ...
+    #[serde(alias = "type")]
+    pub dependency_type: String,
+}
+
+impl Issue {
+    /// Whether bd reports the issue as closed.
+    pub fn is_closed(&self) -> bool {
+        self.status == "closed"
+    }
+
+    /// Ids of unfinished issues that block this one. An issue whose status
+    /// is `blocked` counts as blocked even when bd omits the blockers.
+    pub fn open_blockers(&self) -> Vec<&str> {
+        self.dependencies
+            .iter()
+            .filter(|d| d.dependency_type == "blocks")
+            .filter(|d| d.status.as_deref() != Some("closed"))
+            .map(|d| d.id.as_str())
+            .collect()
+    }
+
+    /// Whether this issue is a child of `parent` (a `parent-child` edge).
+    pub fn is_child_of(&self, parent: &str) -> bool {
+        self.dependencies
+            .iter()
+            .any(|d| d.dependency_type == "parent-child" && d.id == parent)
+    }
+
+    /// The issue's children, from [`dependents`](Issue::dependents).
+    pub fn children(&self) -> impl Iterator<Item = &Dependency> {
+        self.dependents
+            .iter()
+            .filter(|d| d.dependency_type == "parent-child")
+    }
+
+    /// Whether the issue can't be worked on yet: explicitly blocked or
+    /// waiting on an open blocker.
+    pub fn is_blocked(&self) -> bool {
+        self.status == "blocked" || !self.open_blockers().is_empty()
+    }
+}
+
+/// The error for bd output that doesn't parse. The raw output goes to the
+/// debug log, since the message alone rarely shows what bd changed.
+fn unexpected(stdout: &str, e: impl std::fmt::Display) -> String {
+    reporter::debug(&format!("Unparsed bd output: {}", stdout.trim()));
+    format!("Unexpected bd output: {e}")
+}
+
+/// Parse `bd show --json` output, which is a single object in some bd
+/// versions and a one-element array in others.
+pub fn parse_show_output(stdout: &str) -> Result<Issue, String> {
+    let value: Value = serde_json::from_str(stdout).map_err(|e| unexpected(stdout, e))?;
+    let value = match value {
+        Value::Array(mut items) if !items.is_empty() => items.swap_remove(0),
+        Value::Array(_) => return Err("bd returned no issue".to_string()),
+        other => other,
+    };
+    serde_json::from_value(value).map_err(|e| unexpected(stdout, e))
+}
+
+/// Parse the array printed by `bd ready --json` / `bd list --json`. bd
+/// prints `null` rather than `[]` for an empty result in some versions.
+/// Entries that don't parse as an issue are skipped (and logged at debug
+/// level) rather than failing the whole list.
+pub fn parse_list_output(stdout: &str) -> Result<Vec<Issue>, String> {
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() || trimmed == "null" {
+        return Ok(Vec::new());
+    }
+    let items: Vec<Value> = serde_json::from_str(trimmed).map_err(|e| unexpected(stdout, e))?;
+    Ok(items
+        .into_iter()
+        .filter_map(|item| match Issue::deserialize(&item) {
+            Ok(issue) => Some(issue),
+            Err(e) => {
+                reporter::debug(&format!("Skipped bd issue that didn't parse ({e}): {item}"));
+                None
+            }
+        })
+        .collect())
+}
+
+fn tracker_error(args: &[&str], message: impl ToString) -> RalphError {
+    RalphError::Tracker {
+        command: format!("bd {}", args.join(" ")),
+        message: message.to_string(),
+    }
+}
+
+/// What bd prints when another process holds the database. Matched
+/// case-insensitively against stderr.
+const LOCKED_ERRORS: &[&str] = &["database is locked", "sqlite_busy"];
+
+/// Whether bd's stderr says the database is locked by another process, so
+/// the same call may well succeed in a moment.
+pub fn is_locked(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    LOCKED_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// How bd calls are retried while the database is locked, e.g. by a
+/// parallel worker or by bd running in another terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockRetry {
+    /// Calls in all, the first included
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each later one; up to as
+    /// much again is added at random so contending callers spread out
+    pub base_delay: Duration,
+}
+
+impl LockRetry {
+    /// Five attempts, about 1.5s of waiting in all.
+    pub const DEFAULT: LockRetry = LockRetry {
+        attempts: 5,
+        base_delay: Duration::from_millis(100),
+    };
+
+    /// Wait before retry number `retry` (0 for the first).
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(retry));
+        let max_jitter = self.base_delay.as_nanos() as u64;
+        if max_jitter == 0 {
+            return backoff;
+        }
+        // Clock noise is random enough to keep two workers out of step.
+        let noise = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        backoff + Duration::from_nanos(u64::from(noise) % max_jitter)
+    }
+}
+
+/// Run `bd <args>`, retrying per `retry` while the database is locked. The
+/// attempts and the waits between them share one
+/// [`tracker::timeout`]: a call still running when it's up is killed, and
+/// a call that's still locked after the last attempt that fits in it is
+/// returned as is.
+fn output(exec: &dyn CommandRunner, args: &[&str], retry: LockRetry) -> io::Result<Output> {
+    let deadline = Instant::now() + tracker::timeout();
+    let mut attempt = 1;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let output = audit::run(Kind::Tracker, tracker::command("bd").args(args), |cmd| {
+            exec.output_timeout(cmd, left)
+        })?;
+        if output.success() || !is_locked(&output.stderr) || attempt >= retry.attempts {
+            return Ok(output);
+        }
+        let delay = retry.delay(attempt - 1);
+        if Instant::now() + delay >= deadline {
+            return Ok(output);
+        }
+        reporter::debug(&format!(
+            "bd {} found the database locked; retrying in {}ms",
+            args.join(" "),
+            delay.as_millis()
+        ));
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+/// Run a bd command and return its stdout, turning a non-zero exit into an
+/// error carrying bd's stderr.
+fn run_bd(exec: &dyn CommandRunner, args: &[&str]) -> Result<String> {
+    let retry = LockRetry::DEFAULT;
+    let output = output(exec, args, retry)
+        .map_err(|e| tracker::command_error(format!("bd {}", args.join(" ")), e))?;
+    if !output.success() {
+        let mut message = format!("exited with code {}: {}", output.code, output.stderr.trim());
+        if is_locked(&output.stderr) {
+            message.push_str(&format!(
+                " (still locked after {} attempts)",
+                retry.attempts
+            ));
+        }
+        return Err(tracker_error(args, message));
+    }
+    Ok(output.stdout)
+}
+
+/// Where to get bd, for the message shown when it isn't installed.
+pub const INSTALL_URL: &str = "https://github.com/steveyegge/beads";
+
+/// What bd prints when the directory has no beads database, across the
+/// versions ralph has seen. Matched case-insensitively against stderr.
+const MISSING_DATABASE_ERRORS: &[&str] = &["no beads database found", "no .beads directory found"];
+
+/// Whether bd's stderr says there's no beads database here, i.e. that
+/// `bd init` would fix it.
+pub fn is_missing_database(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    MISSING_DATABASE_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// Check that `bd` is on PATH and that `bd ready` works in the current
+/// directory, before a run that relies on it. A missing database is
+/// [`RalphError::TrackerNotInitialized`], so the caller can offer `bd init`.
+pub fn preflight(exec: &dyn CommandRunner) -> Result<()> {
+    let output = match output(exec, &["ready", "--json"], LockRetry::DEFAULT) {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(RalphError::TrackerUnavailable(format!(
+                "bd (beads) is not installed or not on PATH\n\
+                 Install it from {INSTALL_URL}, or set `tracker = \"none\"` in config.toml"
+            )));
+        }
+        Err(e) => return Err(tracker::command_error("bd ready --json".to_string(), e)),
+    };
+    if !output.success() && is_missing_database(&output.stderr) {
+        return Err(RalphError::TrackerNotInitialized(
+            "No beads database in this directory\n\
+             Run `bd init` (or pass --auto-init-bd) to set up beads here, or set `tracker = \"none\"` in config.toml"
+                .to_string(),
+        ));
+    }
+    if !output.success() {
+        let detail = output.stderr.trim();
+        return Err(RalphError::TrackerUnavailable(format!(
+            "`bd ready` failed in this directory{}\n\
+             Run `bd init` to set up beads here, or set `tracker = \"none\"` in config.toml",
+            if detail.is_empty() {
+                format!(" (exit code {})", output.code)
+            } else {
+                format!(": {detail}")
+            }
+        )));
+    }
+    Ok(())
+}
+
+/// Prefix of the keys ralph reads from the project's beads config, e.g.
+/// `bd config set ralph.provider claude`.
+pub const SETTINGS_PREFIX: &str = "ralph.";
+
+/// The `ralph.*` keys of the project's beads config, without the prefix,
+/// from a single `bd config list --json`. Whatever goes wrong (no bd, no
+/// database, a bd too old for `bd config`, output that doesn't parse)
+/// just means no settings: they are defaults, never a reason to fail.
+pub fn project_settings(exec: &dyn CommandRunner) -> BTreeMap<String, String> {
+    match output(exec, &["config", "list", "--json"], LockRetry::DEFAULT) {
+        Ok(output) if output.success() => parse_config_list(&output.stdout),
+        Ok(output) => {
+            reporter::debug(&format!(
+                "Ignoring the beads config: bd config list exited with code {}: {}",
+                output.code,
+                output.stderr.trim()
+            ));
+            BTreeMap::new()
+        }
+        Err(e) => {
+            reporter::debug(&format!("Ignoring the beads config: {e}"));
+            BTreeMap::new()
+        }
+    }
+}
+
+/// The `ralph.*` entries of `bd config list --json`, which is an object of
+/// keys to values, or in some versions an array of `{"key", "value"}`
+/// objects. Values that aren't strings are kept as their JSON text.
+fn parse_config_list(stdout: &str) -> BTreeMap<String, String> {
+    if stdout.trim().is_empty() {
+        return BTreeMap::new();
+    }
+    let value: Value = match serde_json::from_str(stdout.trim()) {
+        Ok(value) => value,
+        Err(e) => {
+            unexpected(stdout, e);
+            return BTreeMap::new();
+        }
+    };
+    let entries: Vec<(String, Value)> = match value {
+        Value::Object(map) => map.into_iter().collect(),
+        Value::Array(items) => items
+            .into_iter()
+            .filter_map(|item| {
+                let key = item.get("key")?.as_str()?.to_string();
+                Some((key, item.get("value")?.clone()))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    entries
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = key.strip_prefix(SETTINGS_PREFIX)?.to_string();
+            let value = match value {
+                Value::String(value) => value,
+                Value::Null => return None,
+                other => other.to_string(),
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Fetch an issue with `bd show <id> --json`.
+pub fn show(exec: &dyn CommandRunner, id: &str) -> Result<Issue> {
+    let args = ["show", id, "--json"];
+    parse_show_output(&run_bd(exec, &args)?).map_err(|e| tracker_error(&args, e))
+}
+
+/// Issues with no open blockers, from `bd ready --json`.
+pub fn ready(exec: &dyn CommandRunner) -> Result<Vec<Issue>> {
+    let args = ["ready", "--json"];
+    parse_list_output(&run_bd(exec, &args)?).map_err(|e| tracker_error(&args, e))
+}
+
+/// Issues with `status`, from `bd list --status <status> --json`.
+pub fn list(exec: &dyn CommandRunner, status: &str) -> Result<Vec<Issue>> {
+    let args = ["list", "--status", status, "--json"];
+    parse_list_output(&run_bd(exec, &args)?).map_err(|e| tracker_error(&args, e))
+}
+
+/// Set an issue's status with `bd update <id> --status <status>`.
+pub fn update_status(exec: &dyn CommandRunner, id: &str, status: &str) -> Result<()> {
+    run_bd(exec, &["update", id, "--status", status]).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::ScriptedRunner;
+
+    #[test]
+    fn show_runs_bd_show_json() {
+        let exec = ScriptedRunner::new().reply(0, r#"[{"id":"bd-9","status":"open"}]"#);
+        assert_eq!(show(&exec, "bd-9").unwrap().id, "bd-9");
+        assert_eq!(exec.calls(), [vec!["bd", "show", "bd-9", "--json"]]);
+    }
+
+    #[test]
+    fn bd_failures_carry_the_command_and_stderr() {
+        let exec = ScriptedRunner::new().fail(2, "no such issue\n");
+        let err = show(&exec, "bd-404").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "bd show bd-404 --json failed: exited with code 2: no such issue"
+        );
+
+        let exec = ScriptedRunner::new().spawn_error(std::io::ErrorKind::NotFound);
+        let err = ready(&exec).unwrap_err().to_string();
+        assert!(err.starts_with("bd ready --json failed: "), "{err}");
+    }
+
+    #[test]
+    fn preflight_explains_how_to_get_bd_working() {
+        let exec = ScriptedRunner::new().reply(0, "[]");
+        preflight(&exec).unwrap();
+        assert_eq!(exec.calls(), [vec!["bd", "ready", "--json"]]);
+
+        let exec = ScriptedRunner::new().spawn_error(io::ErrorKind::NotFound);
+        let err = preflight(&exec).unwrap_err().to_string();
+        assert!(err.starts_with("bd (beads) is not installed"), "{err}");
+        assert!(err.contains(INSTALL_URL), "{err}");
+
+        let exec = ScriptedRunner::new().fail(1, "Error: permission denied\n");
+        let err = preflight(&exec).unwrap_err().to_string();
+        assert!(
+            err.starts_with("`bd ready` failed in this directory: Error: permission denied\n"),
+            "{err}"
+        );
+        assert!(err.contains("bd init"), "{err}");
+
+        let exec = ScriptedRunner::new().fail(1, "Error: no beads database found\n");
+        assert!(matches!(
+            preflight(&exec),
+            Err(RalphError::TrackerNotInitialized(_))
+        ));
+    }
+
+    #[test]
+    fn project_settings_are_the_ralph_keys_of_bd_config_list() {
+        let exec = ScriptedRunner::new().reply(
+            0,
+            r#"{"issue_prefix":"app","ralph.provider":"claude","ralph.iterations":25}"#,
+        );
+        let settings = project_settings(&exec);
+        assert_eq!(exec.calls(), [vec!["bd", "config", "list", "--json"]]);
+        assert_eq!(
+            settings.into_iter().collect::<Vec<_>>(),
+            [
+                ("iterations".to_string(), "25".to_string()),
+                ("provider".to_string(), "claude".to_string())
+            ]
+        );
+
+        let listed =
+            r#"[{"key":"ralph.marker","value":"DONE"},{"key":"sync.branch","value":"main"}]"#;
+        let exec = ScriptedRunner::new().reply(0, listed);
+        assert_eq!(project_settings(&exec)["marker"], "DONE");
+
+        // A bd without `config`, or without a database, has no settings.
+        let exec = ScriptedRunner::new().fail(1, "Error: unknown command \"config\" for \"bd\"\n");
+        assert!(project_settings(&exec).is_empty());
+        let exec = ScriptedRunner::new().spawn_error(io::ErrorKind::NotFound);
+        assert!(project_settings(&exec).is_empty());
+        for stdout in ["", "Usage: bd [command]", "[]", "null"] {
+            let exec = ScriptedRunner::new().reply(0, stdout);
+            assert!(project_settings(&exec).is_empty(), "{stdout}");
+        }
+    }
+
+    /// Captured `bd ready` stderr. A new bd wording for "no database" needs
+    /// a fixture here and an entry in `MISSING_DATABASE_ERRORS`.
+    #[test]
+    fn missing_database_is_recognized_across_bd_versions() {
+        for fixture in [
+            include_str!("../tests/fixtures/bd/no-database-old.txt"),
+            include_str!("../tests/fixtures/bd/no-database-new.txt"),
+        ] {
+            assert!(is_missing_database(fixture), "{fixture}");
+        }
+        assert!(!is_missing_database(include_str!(
+            "../tests/fixtures/bd/database-locked.txt"
+        )));
+        assert!(!is_missing_database(""));
+    }
+
+    const NO_WAIT: LockRetry = LockRetry {
+        attempts: 3,
+        base_delay: Duration::ZERO,
+    };
+
+    #[test]
+    fn locked_database_is_retried() {
+        let locked = include_str!("../tests/fixtures/bd/database-locked.txt");
+        assert!(is_locked(locked));
+        assert!(!is_locked(include_str!(
+            "../tests/fixtures/bd/no-database-new.txt"
+        )));
+
+        let exec = ScriptedRunner::new()
+            .fail(1, locked)
+            .fail(1, locked)
+            .reply(0, "[]");
+        assert!(
+            output(&exec, &["ready", "--json"], NO_WAIT)
+                .unwrap()
+                .success()
+        );
+        assert_eq!(exec.calls().len(), 3);
+
+        // Other failures aren't retried, and retries stop at the limit.
+        let exec = ScriptedRunner::new().fail(2, "no such issue");
+        assert_eq!(output(&exec, &["show", "x"], NO_WAIT).unwrap().code, 2);
+        assert_eq!(exec.calls().len(), 1);
+        let exec = ScriptedRunner::new()
+            .fail(1, locked)
+            .fail(1, locked)
+            .fail(1, locked)
+            .reply(0, "[]");
+        assert!(
+            !output(&exec, &["ready", "--json"], NO_WAIT)
+                .unwrap()
+                .success()
+        );
+        assert_eq!(exec.calls().len(), 3);
+    }
+
+    #[test]
+    fn lock_retries_back_off() {
+        let retry = LockRetry::DEFAULT;
+        for (n, min) in [(0, 100), (1, 200), (3, 800)] {
+            let delay = retry.delay(n);
+            let min = Duration::from_millis(min);
+            assert!(delay >= min && delay < min + retry.base_delay, "{delay:?}");
+        }
+        assert_eq!(NO_WAIT.delay(2), Duration::ZERO);
+    }
+
+    #[test]
+    fn bd_calls_that_stay_locked_fail() {
+        let locked = include_str!("../tests/fixtures/bd/database-locked.txt");
+        let mut exec = ScriptedRunner::new().fail(1, locked);
+        for _ in 1..LockRetry::DEFAULT.attempts {
+            exec = exec.fail(1, locked);
+        }
+        let err = ready(&exec).unwrap_err().to_string();
+        assert!(err.ends_with("(still locked after 5 attempts)"), "{err}");
+        assert_eq!(exec.calls().len(), 5);
+
+        let exec = ScriptedRunner::new().spawn_error(io::ErrorKind::TimedOut);
+        let err = list(&exec, "open").unwrap_err().to_string();
+        assert!(
+            err.starts_with("bd list --status open --json timed out after "),
+            "{err}"
+        );
+        assert_eq!(exec.calls().len(), 1);
+    }
+
+    #[test]
+    fn lock_retries_stop_at_the_timeout() {
+        let locked = include_str!("../tests/fixtures/bd/database-locked.txt");
+        let exec = ScriptedRunner::new()
+            .fail(1, locked)
+            .fail(1, locked)
+            .reply(0, "[]");
+        // A backoff longer than the whole timeout isn't waited out.
+        let slow = LockRetry {
+            attempts: 3,
+            base_delay: tracker::timeout() * 2,
+        };
+        let output = output(&exec, &["ready", "--json"], slow).unwrap();
+        assert!(!output.success());
+        assert_eq!(exec.calls().len(), 1);
+    }
+
+    #[test]
+    fn parse_show_output_accepts_object_and_array() {
+        let object = r#"{"id":"bd-1","title":"T","status":"open","extra":true}"#;
+        assert_eq!(parse_show_output(object).unwrap().id, "bd-1");
+
+        let array = r#"[{"id":"bd-2","title":"T","status":"open"}]"#;
+        assert_eq!(parse_show_output(array).unwrap().id, "bd-2");
+
+        assert!(parse_show_output("[]").is_err());
+        assert!(parse_show_output("not json").is_err());
+    }
+
+    #[test]
+    fn parse_list_output_handles_empty_and_null() {
+        assert!(parse_list_output("").unwrap().is_empty());
+        assert!(parse_list_output("null\n").unwrap().is_empty());
+        assert!(parse_list_output("[]").unwrap().is_empty());
+        let issues = parse_list_output(r#"[{"id":"bd-1"},{"id":"bd-2"}]"#).unwrap();
+        assert_eq!(issues.len(), 2);
+    }
+
+    #[test]
+    fn parses_ready_output_from_older_bd() {
+        let issues =
+            parse_list_output(include_str!("../tests/fixtures/bd/ready-old.json")).unwrap();
+        let ids: Vec<&str> = issues.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["bd-a1", "bd-a2"]);
+        assert_eq!(issues[0].dependencies[0].id, "bd-epic");
+        assert_eq!(issues[0].dependencies[0].dependency_type, "parent-child");
+        assert_eq!(issues[1].issue_type, "bug");
+        assert!(issues.iter().all(|i| !i.is_blocked()));
+    }
+
+    #[test]
+    fn parses_list_output_from_newer_bd_and_skips_bad_entries() {
+        let issues =
+            parse_list_output(include_str!("../tests/fixtures/bd/list-closed-new.json")).unwrap();
+        // The third entry has a numeric id and is skipped, not fatal.
+        let ids: Vec<&str> = issues.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["proj-7f3", "proj-8c1"]);
+        assert!(issues.iter().all(Issue::is_closed));
+        assert_eq!(
+            issues[0].acceptance_criteria,
+            "- Email can be changed\n- Change is confirmed by mail"
+        );
+        assert_eq!(issues[1].closed_at.as_deref(), Some("2025-11-04T11:40:00Z"));
+    }
+
+    #[test]
+    fn open_blocking_dependency_blocks() {
+        let issue = parse_show_output(
+            r#"{"id":"bd-3","status":"open","dependencies":[
+                {"id":"bd-1","status":"open","dependency_type":"blocks"},
+                {"id":"bd-2","status":"closed","dependency_type":"blocks"},
+                {"id":"bd-epic","status":"open","dependency_type":"parent-child"}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(issue.is_blocked());
+        assert_eq!(issue.open_blockers(), ["bd-1"]);
+    }
+
+    #[test]
+    fn jsonl_style_dependencies_parse() {
+        let issue = parse_show_output(
+            r#"{"id":"bd-3","status":"open","dependencies":[
+                {"issue_id":"bd-3","depends_on_id":"bd-epic","type":"parent-child"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(issue.dependencies[0].id, "bd-epic");
+        assert!(!issue.is_blocked());
+    }
+
+    #[test]
+    fn blocked_status_counts_as_blocked() {
+        let issue = parse_show_output(r#"{"id":"bd-4","status":"blocked"}"#).unwrap();
+        assert!(issue.is_blocked());
+        assert!(!issue.is_closed());
+    }
+}
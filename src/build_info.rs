@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// What exactly this binary is, captured at compile time by `build.rs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Crate version from Cargo.toml
+    pub version: String,
+    /// Full commit hash; empty when built outside a git checkout
+    pub commit: String,
+    /// Whether tracked files had uncommitted changes
+    pub dirty: bool,
+    /// `YYYY-MM-DD` (UTC), or `SOURCE_DATE_EPOCH` when set
+    pub build_date: String,
+    /// Target triple the binary was built for
+    pub target: String,
+    /// `rustc --version` of the compiler used
+    pub rustc: String,
+}
+
+impl BuildInfo {
+    /// Build metadata of the running binary.
+    pub fn current() -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            commit: env!("RALPH_GIT_COMMIT").to_string(),
+            dirty: env!("RALPH_GIT_DIRTY") == "true",
+            build_date: env!("RALPH_BUILD_DATE").to_string(),
+            target: env!("RALPH_TARGET").to_string(),
+            rustc: env!("RALPH_RUSTC_VERSION").to_string(),
+        }
+    }
+
+    /// Abbreviated commit with a `-dirty` suffix, or `unknown`.
+    pub fn short_commit(&self) -> String {
+        if self.commit.is_empty() {
+            return "unknown".to_string();
+        }
+        let short: String = self.commit.chars().take(7).collect();
+        if self.dirty {
+            format!("{short}-dirty")
+        } else {
+            short
+        }
+    }
+
+    /// Output of plain `ralph version`; the first line stays `ralph <version>`
+    /// followed by commit and date.
+    pub fn render_text(&self) -> String {
+        format!(
+            "ralph {} ({} {})\ntarget: {}\nrustc: {}\n",
+            self.version,
+            self.short_commit(),
+            self.build_date,
+            self.target,
+            self.rustc
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_has_version_and_target() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.target.is_empty());
+        assert_eq!(info.build_date.len(), 10);
+        assert!(
+            info.render_text()
+                .starts_with(&format!("ralph {} (", info.version))
+        );
+    }
+
+    #[test]
+    fn short_commit_marks_dirty_builds() {
+        let mut info = BuildInfo::current();
+        info.commit = "0123456789abcdef".to_string();
+        info.dirty = true;
+        assert_eq!(info.short_commit(), "0123456-dirty");
+        info.commit.clear();
+        assert_eq!(info.short_commit(), "unknown");
+    }
+}
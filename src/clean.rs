@@ -0,0 +1,392 @@
+use crate::config;
+use crate::error::{RalphError, Result};
+use crate::reporter;
+use crate::session;
+use crate::upgrade;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// Age threshold used when neither `--older-than` nor `[clean] older_than`
+/// is given.
+pub const DEFAULT_OLDER_THAN: &str = "30d";
+
+/// Kinds of artifacts `ralph clean` knows how to prune.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    /// Per-iteration provider output under `logs/`
+    Logs,
+    /// Session transcripts under `sessions/`
+    Sessions,
+    /// Update-check caches under `cache/`
+    Cache,
+    /// `.old` binaries left behind by `ralph upgrade`
+    UpgradeBackups,
+    /// Orphaned `.ralph/worktrees` directories
+    Worktrees,
+}
+
+impl Category {
+    const ALL: [Category; 5] = [
+        Category::Logs,
+        Category::Sessions,
+        Category::Cache,
+        Category::UpgradeBackups,
+        Category::Worktrees,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Category::Logs => "Logs",
+            Category::Sessions => "Sessions",
+            Category::Cache => "Caches",
+            Category::UpgradeBackups => "Upgrade backups",
+            Category::Worktrees => "Orphaned worktrees",
+        }
+    }
+}
+
+/// One deletable file or directory.
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// What kind of file this is
+    pub category: Category,
+    /// File or directory to delete
+    pub path: PathBuf,
+    /// Size in bytes (recursive for directories)
+    pub size: u64,
+    /// Last modification time
+    pub modified: SystemTime,
+    /// Belongs to a session that is still running; never deleted
+    pub protected: bool,
+}
+
+/// Options for `ralph clean`.
+#[derive(Debug)]
+pub struct CleanOptions {
+    /// Only items last modified longer ago than this are deleted
+    pub older_than: Duration,
+    /// List what would be deleted without deleting it
+    pub dry_run: bool,
+    /// Don't ask for confirmation
+    pub yes: bool,
+}
+
+fn invalid_age(s: &str) -> RalphError {
+    RalphError::InvalidInput(format!("Invalid age '{s}' (expected e.g. 30d, 12h, 45m)"))
+}
+
+/// Parse an age like `30d`, `12h`, `45m`, `2w` or plain seconds.
+pub fn parse_age(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = number.parse().map_err(|_| invalid_age(s))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid_age(s)),
+    };
+    Ok(Duration::from_secs(n.saturating_mul(secs)))
+}
+
+/// Human-readable byte count.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Total size of a file, or of everything under a directory.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|e| disk_usage(&e.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+fn item(category: Category, path: PathBuf, protected: bool) -> Option<Item> {
+    let modified = fs::symlink_metadata(&path).ok()?.modified().ok()?;
+    Some(Item {
+        category,
+        size: disk_usage(&path),
+        path,
+        modified,
+        protected,
+    })
+}
+
+fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(Result::ok).map(|e| e.path()).collect())
+        .unwrap_or_default()
+}
+
+/// Find everything `ralph clean` could remove.
+pub fn collect() -> io::Result<Vec<Item>> {
+    let mut items = Vec::new();
+
+    let running: Vec<String> = session::list_sessions()?
+        .into_iter()
+        .filter(|s| s.is_running())
+        .map(|s| s.id)
+        .collect();
+    let is_running = |path: &Path| {
+        path.file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|id| running.iter().any(|r| r == id))
+    };
+
+    for path in dir_entries(&session::get_logs_dir()?) {
+        let protected = is_running(&path);
+        items.extend(item(Category::Logs, path, protected));
+    }
+    for path in dir_entries(&session::get_sessions_dir()?) {
+        // Lock files mark running sessions; they go away on their own.
+        if path.extension().and_then(|e| e.to_str()) == Some("lock") {
+            continue;
+        }
+        let protected = is_running(&path);
+        items.extend(item(Category::Sessions, path, protected));
+    }
+    for path in dir_entries(&config::get_config_dir()?.join("cache")) {
+        items.extend(item(Category::Cache, path, false));
+    }
+    // An interrupted upgrade's backup is what `ralph upgrade --repair`
+    // restores.
+    let repair_pending = upgrade::interrupted_upgrade().is_some();
+    for path in upgrade_backups() {
+        items.extend(item(Category::UpgradeBackups, path, repair_pending));
+    }
+    for path in orphaned_worktrees() {
+        items.extend(item(Category::Worktrees, path, false));
+    }
+
+    Ok(items)
+}
+
+/// Leftover `<exe>.old` files next to the running binary from interrupted
+/// upgrades.
+fn upgrade_backups() -> Vec<PathBuf> {
+    let Ok(exe) = std::env::current_exe() else {
+        return Vec::new();
+    };
+    let Some(dir) = exe.parent() else {
+        return Vec::new();
+    };
+    dir_entries(dir)
+        .into_iter()
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("old"))
+        .collect()
+}
+
+/// Directories under `.ralph/worktrees` that git no longer knows about.
+/// Without a working `git`, nothing is considered orphaned.
+fn orphaned_worktrees() -> Vec<PathBuf> {
+    let Some(project) = config::find_project_dir() else {
+        return Vec::new();
+    };
+    let candidates = dir_entries(&project.join("worktrees"));
+    if candidates.is_empty() {
+        return candidates;
+    }
+    let Ok(output) = Command::new("git")
+        .args(["worktree", "list", "--porcelain"])
+        .output()
+    else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    let registered: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.strip_prefix("worktree "))
+        .map(|p| fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p)))
+        .collect();
+    candidates
+        .into_iter()
+        .filter(|c| {
+            let c = fs::canonicalize(c).unwrap_or_else(|_| c.clone());
+            !registered.contains(&c)
+        })
+        .collect()
+}
+
+/// Items old enough to delete and not protected.
+pub fn select(items: &[Item], older_than: Duration, now: SystemTime) -> Vec<&Item> {
+    items
+        .iter()
+        .filter(|i| !i.protected)
+        .filter(|i| {
+            now.duration_since(i.modified)
+                .is_ok_and(|age| age >= older_than)
+        })
+        .collect()
+}
+
+/// Print a per-category usage summary.
+fn print_summary(items: &[Item], selected: &[&Item], older_than: &str) {
+    for category in Category::ALL {
+        let all: Vec<_> = items.iter().filter(|i| i.category == category).collect();
+        let old: Vec<_> = selected.iter().filter(|i| i.category == category).collect();
+        println!(
+            "{:<20} {:>4} items, {:>9}  ({} older than {}, {})",
+            format!("{}:", category.label()),
+            all.len(),
+            format_size(all.iter().map(|i| i.size).sum()),
+            old.len(),
+            older_than,
+            format_size(old.iter().map(|i| i.size).sum())
+        );
+    }
+}
+
+/// Ask a yes/no question on stderr; anything but "y"/"yes" means no. When
+/// nobody can answer (see [`crate::term::Terminal::can_prompt`]) the
+/// answer is no, without waiting for input.
+pub fn confirm(question: &str) -> bool {
+    if !crate::term::get().can_prompt() {
+        eprintln!("{question} [y/N] no (not a terminal; set RALPH_INTERACTIVE=1 to ask anyway)");
+        return false;
+    }
+    eprint!("{question} [y/N] ");
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes" | "YES")
+}
+
+/// Report usage and delete old items.
+pub fn run(opts: &CleanOptions, older_than_label: &str) -> Result<()> {
+    let items = collect()?;
+    let selected = select(&items, opts.older_than, SystemTime::now());
+    print_summary(&items, &selected, older_than_label);
+
+    let protected = items.iter().filter(|i| i.protected).count();
+    if protected > 0 {
+        println!(
+            "Skipping {protected} item(s) still in use by running sessions or a pending upgrade repair."
+        );
+    }
+
+    if selected.is_empty() {
+        println!("Nothing to clean.");
+        return Ok(());
+    }
+
+    let total = format_size(selected.iter().map(|i| i.size).sum());
+    if opts.dry_run {
+        println!();
+        for item in &selected {
+            println!(
+                "Would delete {} ({})",
+                item.path.display(),
+                format_size(item.size)
+            );
+        }
+        println!("Would free {total}.");
+        return Ok(());
+    }
+
+    if !opts.yes && !confirm(&format!("Delete {} item(s), {total}?", selected.len())) {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let mut freed = 0;
+    for item in selected {
+        let result = if item.path.is_dir() {
+            fs::remove_dir_all(&item.path)
+        } else {
+            fs::remove_file(&item.path)
+        };
+        match result {
+            Ok(()) => freed += item.size,
+            Err(e) => reporter::warn(&format!("Failed to delete {}: {}", item.path.display(), e)),
+        }
+    }
+    println!("Freed {}.", format_size(freed));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_age_units() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::from_secs(30 * 86400));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(parse_age("45m").unwrap(), Duration::from_secs(45 * 60));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_secs(14 * 86400));
+        assert_eq!(parse_age("90").unwrap(), Duration::from_secs(90));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn format_size_scales() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2048), "2.0 KB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn select_skips_recent_and_protected_items() {
+        let now = SystemTime::now();
+        let old = now - Duration::from_secs(40 * 86400);
+        let mk = |name: &str, modified, protected| Item {
+            category: Category::Logs,
+            path: PathBuf::from(name),
+            size: 1,
+            modified,
+            protected,
+        };
+        let items = vec![
+            mk("old", old, false),
+            mk("new", now, false),
+            mk("running", old, true),
+        ];
+        let selected = select(&items, parse_age("30d").unwrap(), now);
+        let names: Vec<_> = selected.iter().map(|i| i.path.to_str().unwrap()).collect();
+        assert_eq!(names, ["old"]);
+    }
+
+    #[test]
+    fn disk_usage_sums_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), [0u8; 10]).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join("b"), [0u8; 5]).unwrap();
+        assert_eq!(disk_usage(dir.path()), 15);
+    }
+}
@@ -0,0 +1,215 @@
+use clap::CommandFactory;
+use clap_complete::Shell;
+use ralph::config::{self, Overrides};
+use ralph::exec::SystemRunner;
+use ralph::providers::{PROVIDERS, VALID_PROVIDERS};
+use ralph::tracker;
+use std::io;
+use std::time::Duration;
+
+/// How long `ralph __complete task` waits for the tracker before offering
+/// nothing: long enough for a local `bd ready`, short enough not to freeze
+/// the shell.
+const TASK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What `ralph __complete <kind>` lists for the completion scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Candidates {
+    /// Provider names and aliases, for `--provider`
+    Provider,
+    /// Ready task ids, for `ralph run <id>`
+    Task,
+    /// A kind this ralph doesn't know (the script is from another version)
+    Unknown,
+}
+
+/// The candidates asked for when `args` are `ralph __complete <kind>`.
+pub fn requested(mut args: impl Iterator<Item = String>) -> Option<Candidates> {
+    if args.nth(1)? != "__complete" {
+        return None;
+    }
+    Some(match args.next().as_deref() {
+        Some("provider") => Candidates::Provider,
+        Some("task") => Candidates::Task,
+        _ => Candidates::Unknown,
+    })
+}
+
+/// The candidates of `kind`, one per line. Nothing goes wrong here as far
+/// as the shell is concerned: a config or tracker that fails just means no
+/// candidates.
+pub fn candidates(kind: Candidates) -> Vec<String> {
+    match kind {
+        Candidates::Provider => providers(config::resolve(&Overrides::default()).ok()),
+        Candidates::Task => {
+            tracker::set_timeout(TASK_TIMEOUT);
+            let Ok(config) = config::resolve(&Overrides::default()) else {
+                return Vec::new();
+            };
+            tracker::from_config(&config)
+                .ready(&SystemRunner)
+                .map(|issues| issues.into_iter().map(|i| i.id).collect())
+                .unwrap_or_default()
+        }
+        Candidates::Unknown => Vec::new(),
+    }
+}
+
+/// What `--provider` takes: the built-in providers, those `config` defines,
+/// then the built-in aliases and `config`'s `[aliases]`. Without a config
+/// (it failed to load), just the built-in ones.
+fn providers(config: Option<config::EffectiveConfig>) -> Vec<String> {
+    let builtin_aliases = PROVIDERS.iter().flat_map(|spec| spec.aliases.iter());
+    let (templates, aliases) = config
+        .map(|c| (c.provider_templates.value, c.aliases.value))
+        .unwrap_or_default();
+    let mut names: Vec<String> = VALID_PROVIDERS
+        .iter()
+        .map(|p| p.to_string())
+        .chain(templates.into_keys())
+        .chain(builtin_aliases.map(|a| a.to_string()))
+        .chain(aliases.into_keys())
+        .collect();
+    // A configured alias may reuse a built-in one's name.
+    let mut seen = std::collections::BTreeSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    names
+}
+
+/// Write the completion script for `shell` to `out`. Bash, zsh and fish get
+/// clap's static script plus hooks asking `ralph __complete` for the
+/// values that depend on the machine and the repository; other shells get
+/// the static script alone.
+pub fn write(shell: Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut crate::Cli::command(), "ralph", &mut script);
+    let script = String::from_utf8_lossy(&script);
+    let script = match shell {
+        Shell::Bash => format!("{script}{BASH_HOOK}"),
+        Shell::Fish => format!("{script}{FISH_HOOK}"),
+        Shell::Zsh => zsh(&script),
+        _ => script.into_owned(),
+    };
+    out.write_all(script.as_bytes())
+}
+
+/// Completes `--provider` and `run`'s task id dynamically, leaving the rest
+/// to clap's `_ralph`.
+const BASH_HOOK: &str = r#"
+_ralph_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "${prev}" == --provider ]]; then
+        COMPREPLY=( $(compgen -W "$(ralph __complete provider 2>/dev/null)" -- "${cur}") )
+        return 0
+    fi
+    if [[ ${COMP_CWORD} -eq 2 && "${COMP_WORDS[1]}" == run && "${cur}" != -* ]]; then
+        COMPREPLY=( $(compgen -W "$(ralph __complete task 2>/dev/null)" -- "${cur}") )
+        return 0
+    fi
+    _ralph "$@"
+}
+
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _ralph_dynamic -o nosort -o bashdefault -o default ralph
+else
+    complete -F _ralph_dynamic -o bashdefault -o default ralph
+fi
+"#;
+
+const FISH_HOOK: &str = r#"
+complete -c ralph -n "__fish_seen_subcommand_from once loop run watch" -l provider -f -a "(ralph __complete provider 2>/dev/null)"
+complete -c ralph -n "__fish_seen_subcommand_from run" -f -a "(ralph __complete task 2>/dev/null)"
+"#;
+
+const ZSH_HELPERS: &str = r#"
+_ralph_providers() {
+    local -a providers
+    providers=(${(f)"$(ralph __complete provider 2>/dev/null)"})
+    _describe -t providers 'provider' providers
+}
+
+_ralph_tasks() {
+    local -a tasks
+    tasks=(${(f)"$(ralph __complete task 2>/dev/null)"})
+    _describe -t tasks 'ready task' tasks
+}
+"#;
+
+/// zsh's script lists the values inline, so point those specs at helper
+/// functions instead, defined after `#compdef`.
+fn zsh(script: &str) -> String {
+    let providers = format!("({})", VALID_PROVIDERS.join(" "));
+    let script = script
+        .replace(
+            &format!(":PROVIDER:{providers}'"),
+            ":PROVIDER:_ralph_providers'",
+        )
+        .replace(
+            "':id -- bd issue id, e.g. bd-123:_default'",
+            "':id -- bd issue id, e.g. bd-123:_ralph_tasks'",
+        );
+    match script.split_once('\n') {
+        Some((compdef, rest)) => format!("{compdef}\n{ZSH_HELPERS}{rest}"),
+        None => script,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(shell: Shell) -> String {
+        let mut out = Vec::new();
+        write(shell, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn scripts_ask_ralph_for_dynamic_values() {
+        let bash = script(Shell::Bash);
+        assert!(bash.contains("complete -F _ralph_dynamic"), "{bash}");
+        assert!(bash.contains("ralph __complete task"), "{bash}");
+
+        let zsh = script(Shell::Zsh);
+        assert!(zsh.starts_with("#compdef ralph\n\n_ralph_providers() {"));
+        assert!(zsh.contains(":PROVIDER:_ralph_providers'"), "{zsh}");
+        assert!(zsh.contains(":_ralph_tasks'"), "{zsh}");
+        assert!(!zsh.contains("(droid codex claude gemini)"), "{zsh}");
+
+        assert!(script(Shell::Fish).contains("(ralph __complete provider 2>/dev/null)"));
+        assert!(!script(Shell::PowerShell).contains("__complete"));
+    }
+
+    #[test]
+    fn only_complete_requests_are_intercepted() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let requested = |a: &[&str]| requested(args(a).into_iter());
+        assert_eq!(
+            requested(&["ralph", "__complete", "task"]),
+            Some(Candidates::Task)
+        );
+        assert_eq!(
+            requested(&["ralph", "__complete", "profile"]),
+            Some(Candidates::Unknown)
+        );
+        assert_eq!(requested(&["ralph", "run", "__complete"]), None);
+        assert_eq!(requested(&["ralph"]), None);
+    }
+
+    #[test]
+    fn providers_are_listed_with_their_aliases() {
+        let names = providers(None);
+        assert_eq!(names[..VALID_PROVIDERS.len()], *VALID_PROVIDERS);
+        assert_eq!(
+            names[VALID_PROVIDERS.len()..],
+            [
+                "factory",
+                "cc",
+                "claude-code",
+                "gemini-cli",
+                "gh-copilot",
+                "qwen-code"
+            ]
+        );
+    }
+}
@@ -0,0 +1,3562 @@
+use crate::env_policy::{EnvFilter, EnvPolicy};
+use crate::error::{RalphError, Result};
+use crate::exec::SystemRunner;
+use crate::fsutil;
+use crate::pricing;
+use crate::priority::{self, IoClass, Priority};
+use crate::providers::{PromptTransport, Template};
+use crate::reporter;
+use crate::tracker::{self, Tracker};
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once, PoisonError};
+
+/// Default system prompt content (equivalent to script's built-in PROMPT):
+/// the prompt template of the default tracker, beads.
+pub const DEFAULT_SYSTEM_PROMPT: &str = crate::prompts::BEADS;
+
+/// Provider used when neither the command line nor a config file picks one.
+pub const DEFAULT_PROVIDER: &str = "droid";
+
+/// Iteration budget used by `ralph loop` when none is configured.
+pub const DEFAULT_ITERATIONS: u32 = 10;
+
+/// Name of the project-local configuration directory (`./.ralph/`).
+pub const PROJECT_DIR_NAME: &str = ".ralph";
+
+/// File name of the system prompt inside the config directory.
+pub const SYSTEM_PROMPT_FILE_NAME: &str = "system-prompt.md";
+
+/// Name of the TOML config file inside both the global and project directories.
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Settings read from a `config.toml` file. Every key is optional so a file
+/// only needs to mention what it wants to change; unknown keys are ignored.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Default provider for `once` and `loop`
+    pub provider: Option<String>,
+    /// Default iteration budget for `loop`
+    pub iterations: Option<u32>,
+    /// Text in a provider's output that ends a loop as complete
+    pub marker: Option<String>,
+    /// Print a hint after `once`/`loop` when a newer release exists
+    pub update_check: Option<bool>,
+    /// `tracker`: the task tracker's name, or a `[tracker]` table
+    pub tracker: TrackerConfig,
+    /// Run `bd init` without asking when a run finds no beads database
+    pub auto_init_bd: Option<bool>,
+    /// Extra phrases meaning a provider is waiting for an interactive login
+    pub login_phrases: Option<Vec<String>>,
+    /// Longest provider output line kept whole, in bytes
+    pub max_line_bytes: Option<u64>,
+    /// Write over-long lines whole to the captured output log anyway
+    pub keep_long_lines: Option<bool>,
+    /// Shortest run of identical output lines shown as one, 0 for never
+    pub collapse_repeats: Option<u32>,
+    /// Most output one provider run may print before it's killed
+    pub max_output_bytes: Option<ByteSize>,
+    /// How much to lower providers' CPU priority, 0 to 19
+    pub nice: Option<u8>,
+    /// Providers' I/O scheduling class on Linux, e.g. `idle`
+    pub ionice: Option<String>,
+    /// Let providers act without asking for permission
+    pub auto_approve: Option<bool>,
+    /// Record every command ralph runs in `~/.Ralph/audit.log`
+    pub audit: Option<bool>,
+    /// Commands that must pass before a loop may stop as complete
+    pub gates: Option<Vec<String>>,
+    /// Where loops write Prometheus metrics, for node_exporter's textfile
+    /// collector
+    pub metrics_file: Option<String>,
+    /// `[provider_versions]` section: a semver requirement per provider
+    pub provider_versions: Option<BTreeMap<String, String>>,
+    /// `[provider_args]` section: arguments added to each provider's command
+    pub provider_args: Option<BTreeMap<String, Vec<String>>>,
+    /// `[env]` section
+    pub env: EnvConfig,
+    /// `[providers.<name>]` sections, by provider
+    pub providers: Option<BTreeMap<String, ProviderConfig>>,
+    /// `[aliases]` section: other names for providers, e.g. `oai = "codex"`
+    pub aliases: Option<BTreeMap<String, String>>,
+    /// `[clean]` section
+    pub clean: CleanConfig,
+}
+
+/// Task tracker settings. `tracker = "beads"` is short for a `[tracker]`
+/// table with just `name`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(from = "TrackerValue")]
+pub struct TrackerConfig {
+    /// Task tracker the agent works through: `beads`, `github` or `none`
+    pub name: Option<String>,
+    /// Run the tracker's sync command after a loop
+    pub sync_after_run: Option<bool>,
+    /// Command to run instead of the tracker's own sync (`bd sync`)
+    pub sync_command: Option<String>,
+    /// GitHub repository as `owner/name` (default: from the `origin` remote)
+    pub repo: Option<String>,
+    /// GitHub label marking issues as ready
+    pub label: Option<String>,
+    /// Seconds a tracker command may run before it's killed
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TrackerValue {
+    Name(String),
+    Table {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        sync_after_run: Option<bool>,
+        #[serde(default)]
+        sync_command: Option<String>,
+        #[serde(default)]
+        repo: Option<String>,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+    },
+}
+
+/// A size in a config file: a number of bytes, or a string with a unit such
+/// as `"512M"` (see [`parse_size`]).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ByteSize {
+    /// A plain number of bytes
+    Bytes(u64),
+    /// A size with an optional unit
+    Text(String),
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteSize::Bytes(n) => write!(f, "{n}"),
+            ByteSize::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+impl From<TrackerValue> for TrackerConfig {
+    fn from(value: TrackerValue) -> Self {
+        match value {
+            TrackerValue::Name(name) => TrackerConfig {
+                name: Some(name),
+                ..TrackerConfig::default()
+            },
+            TrackerValue::Table {
+                name,
+                sync_after_run,
+                sync_command,
+                repo,
+                label,
+                timeout_secs,
+            } => TrackerConfig {
+                name,
+                sync_after_run,
+                sync_command,
+                repo,
+                label,
+                timeout_secs,
+            },
+        }
+    }
+}
+
+/// `[env]` settings: which environment variables providers inherit, and
+/// which ones they get set.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    /// `inherit`, `allowlist` or `blocklist`
+    pub policy: Option<String>,
+    /// Variables kept under `allowlist`; `NAME*` matches a prefix
+    pub allow: Option<Vec<String>>,
+    /// Variables removed under `blocklist`; `NAME*` matches a prefix
+    pub block: Option<Vec<String>>,
+    /// Every other key: a variable set for all providers, to the value
+    /// given or, for `"env:NAME"`, to ralph's own `NAME`
+    #[serde(flatten)]
+    pub vars: BTreeMap<String, String>,
+}
+
+/// A `[providers.<name>]` section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    /// Command template that defines a provider of this name, e.g.
+    /// `"./scripts/agent.sh {prompt}"` (see [`Template`])
+    pub command: Option<String>,
+    /// Run `command` with `sh -c`
+    pub shell: bool,
+    /// How a built-in provider gets the prompt: `arg`, `stdin` or `file`
+    pub prompt_via: Option<String>,
+    /// `[providers.<name>.env]`: variables set for this provider only, over
+    /// those in `[env]`
+    pub env: BTreeMap<String, String>,
+}
+
+/// `[clean]` settings for `ralph clean`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct CleanConfig {
+    /// Default age threshold, e.g. `"30d"`
+    pub older_than: Option<String>,
+}
+
+impl Config {
+    /// Overlay `other` on top of `self`: keys set in `other` win.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            provider: other.provider.or(self.provider),
+            iterations: other.iterations.or(self.iterations),
+            marker: other.marker.or(self.marker),
+            update_check: other.update_check.or(self.update_check),
+            tracker: TrackerConfig {
+                name: other.tracker.name.or(self.tracker.name),
+                sync_after_run: other.tracker.sync_after_run.or(self.tracker.sync_after_run),
+                sync_command: other.tracker.sync_command.or(self.tracker.sync_command),
+                repo: other.tracker.repo.or(self.tracker.repo),
+                label: other.tracker.label.or(self.tracker.label),
+                timeout_secs: other.tracker.timeout_secs.or(self.tracker.timeout_secs),
+            },
+            auto_init_bd: other.auto_init_bd.or(self.auto_init_bd),
+            login_phrases: other.login_phrases.or(self.login_phrases),
+            max_line_bytes: other.max_line_bytes.or(self.max_line_bytes),
+            keep_long_lines: other.keep_long_lines.or(self.keep_long_lines),
+            collapse_repeats: other.collapse_repeats.or(self.collapse_repeats),
+            max_output_bytes: other.max_output_bytes.or(self.max_output_bytes),
+            nice: other.nice.or(self.nice),
+            ionice: other.ionice.or(self.ionice),
+            auto_approve: other.auto_approve.or(self.auto_approve),
+            audit: other.audit.or(self.audit),
+            gates: other.gates.or(self.gates),
+            metrics_file: other.metrics_file.or(self.metrics_file),
+            provider_versions: other.provider_versions.or(self.provider_versions),
+            provider_args: other.provider_args.or(self.provider_args),
+            env: EnvConfig {
+                policy: other.env.policy.or(self.env.policy),
+                allow: other.env.allow.or(self.env.allow),
+                block: other.env.block.or(self.env.block),
+                vars: match other.env.vars.is_empty() {
+                    true => self.env.vars,
+                    false => other.env.vars,
+                },
+            },
+            providers: other.providers.or(self.providers),
+            aliases: other.aliases.or(self.aliases),
+            clean: CleanConfig {
+                older_than: other.clean.older_than.or(self.clean.older_than),
+            },
+        }
+    }
+
+    /// The providers this file defines with a `command`, by name.
+    pub fn templates(&self) -> BTreeMap<String, Template> {
+        self.providers
+            .iter()
+            .flatten()
+            .filter_map(|(name, p)| {
+                let command = p.command.clone()?;
+                Some((
+                    name.clone(),
+                    Template {
+                        command,
+                        shell: p.shell,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Validate that iterations is a positive integer (>0).
+pub fn validate_iterations(iterations: &str) -> Result<u32> {
+    match iterations.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(RalphError::InvalidInput(
+            "iterations must be a positive integer".to_string(),
+        )),
+    }
+}
+
+/// The provider to use: the command-line value, else the config's, else
+/// [`DEFAULT_PROVIDER`], an alias replaced with the provider it stands for.
+/// Validated against the supported providers. Unlike [`resolve`],
+/// environment variables are not consulted.
+pub fn resolve_provider(flag: Option<String>, config: &Config) -> Result<String> {
+    let templates = config.templates();
+    let aliases = config.aliases.clone().unwrap_or_default();
+    let provider = layered(
+        DEFAULT_PROVIDER.to_string(),
+        [
+            (Source::ProjectConfig, config.provider.clone()),
+            (Source::Flag, flag),
+        ],
+    );
+    let provider = crate::providers::resolve_alias(&provider.value, &templates, &aliases);
+    crate::providers::validate_provider_in(&provider, &templates, &aliases)?;
+    Ok(provider)
+}
+
+/// The iteration budget to use: the command-line value, else the config's,
+/// else [`DEFAULT_ITERATIONS`]. Must be a positive integer. Unlike
+/// [`resolve`], environment variables are not consulted.
+pub fn resolve_iterations(flag: Option<String>, config: &Config) -> Result<u32> {
+    let iterations = layered(
+        DEFAULT_ITERATIONS.to_string(),
+        [
+            (
+                Source::ProjectConfig,
+                config.iterations.map(|n| n.to_string()),
+            ),
+            (Source::Flag, flag),
+        ],
+    );
+    validate_iterations(&iterations.value)
+}
+
+/// Environment variable overriding `provider` from the config files.
+pub const PROVIDER_ENV: &str = "RALPH_PROVIDER";
+
+/// Environment variable overriding `iterations` from the config files.
+pub const ITERATIONS_ENV: &str = "RALPH_ITERATIONS";
+
+/// Environment variable overriding `marker` from the config files.
+pub const MARKER_ENV: &str = "RALPH_MARKER";
+
+/// Environment variable overriding `update_check` from the config files
+/// (`1`/`true`/`yes`/`on` or `0`/`false`/`no`/`off`).
+pub const UPDATE_CHECK_ENV: &str = "RALPH_UPDATE_CHECK";
+
+/// Environment variable overriding `tracker` from the config files.
+pub const TRACKER_ENV: &str = "RALPH_TRACKER";
+
+/// Environment variable overriding `[tracker] sync_after_run` from the
+/// config files.
+pub const TRACKER_SYNC_ENV: &str = "RALPH_TRACKER_SYNC";
+
+/// Environment variable overriding `[tracker] sync_command` from the config
+/// files.
+pub const TRACKER_SYNC_COMMAND_ENV: &str = "RALPH_TRACKER_SYNC_COMMAND";
+
+/// Environment variable overriding `[tracker] repo` from the config files.
+pub const TRACKER_REPO_ENV: &str = "RALPH_TRACKER_REPO";
+
+/// Environment variable overriding `[tracker] label` from the config files.
+pub const TRACKER_LABEL_ENV: &str = "RALPH_TRACKER_LABEL";
+
+/// Environment variable overriding `[tracker] timeout_secs` from the config
+/// files.
+pub const TRACKER_TIMEOUT_ENV: &str = "RALPH_TRACKER_TIMEOUT_SECS";
+
+/// Environment variable overriding `auto_init_bd` from the config files.
+pub const AUTO_INIT_BD_ENV: &str = "RALPH_AUTO_INIT_BD";
+
+/// Environment variable overriding `login_phrases` from the config files,
+/// with the phrases separated by `|`.
+pub const LOGIN_PHRASES_ENV: &str = "RALPH_LOGIN_PHRASES";
+
+/// Environment variable overriding `max_line_bytes` from the config files.
+pub const MAX_LINE_BYTES_ENV: &str = "RALPH_MAX_LINE_BYTES";
+
+/// Environment variable overriding `collapse_repeats` from the config files.
+pub const COLLAPSE_REPEATS_ENV: &str = "RALPH_COLLAPSE_REPEATS";
+
+/// Environment variable overriding `max_output_bytes` from the config files.
+pub const MAX_OUTPUT_BYTES_ENV: &str = "RALPH_MAX_OUTPUT_BYTES";
+
+/// Environment variable overriding `keep_long_lines` from the config files.
+pub const KEEP_LONG_LINES_ENV: &str = "RALPH_KEEP_LONG_LINES";
+
+/// Environment variable overriding `nice` from the config files.
+pub const NICE_ENV: &str = "RALPH_NICE";
+
+/// Environment variable overriding `ionice` from the config files.
+pub const IONICE_ENV: &str = "RALPH_IONICE";
+
+/// Environment variable overriding `auto_approve` from the config files.
+pub const AUTO_APPROVE_ENV: &str = "RALPH_AUTO_APPROVE";
+
+/// Environment variable overriding `audit` from the config files.
+pub const AUDIT_ENV: &str = "RALPH_AUDIT";
+
+/// Environment variable overriding `metrics_file` from the config files.
+pub const METRICS_FILE_ENV: &str = "RALPH_METRICS_FILE";
+
+/// Environment variable overriding `[env] policy` from the config files.
+pub const ENV_POLICY_ENV: &str = "RALPH_ENV_POLICY";
+
+/// Environment variable overriding `[env] allow` from the config files,
+/// with the names separated by commas.
+pub const ENV_ALLOW_ENV: &str = "RALPH_ENV_ALLOW";
+
+/// Environment variable overriding `[env] block` from the config files,
+/// with the names separated by commas.
+pub const ENV_BLOCK_ENV: &str = "RALPH_ENV_BLOCK";
+
+/// Environment variable overriding `[clean] older_than` from the config files.
+pub const CLEAN_OLDER_THAN_ENV: &str = "RALPH_CLEAN_OLDER_THAN";
+
+/// Where an effective setting came from. Variants are in order of
+/// precedence: each layer overrides the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Built-in default
+    Default,
+    /// `~/.Ralph/config.toml`
+    GlobalConfig,
+    /// The `ralph.*` keys of the project's beads config (`bd config set
+    /// ralph.provider claude`), shared through the synced database
+    Beads,
+    /// `./.ralph/config.toml`
+    ProjectConfig,
+    /// A `RALPH_*` environment variable
+    Env,
+    /// A command-line flag
+    Flag,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::GlobalConfig => write!(f, "global config"),
+            Source::Beads => write!(f, "beads config"),
+            Source::ProjectConfig => write!(f, "project config"),
+            Source::Env => write!(f, "environment"),
+            Source::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+/// A resolved setting and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Setting<T> {
+    /// Effective value
+    pub value: T,
+    /// Where the value came from
+    pub source: Source,
+}
+
+/// Command-line values taking part in [`resolve`]; `None` where the flag
+/// wasn't given or the command has no such flag.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Overrides {
+    /// `--provider`
+    pub provider: Option<String>,
+    /// `--iterations`
+    pub iterations: Option<String>,
+    /// `--older-than`
+    pub older_than: Option<String>,
+    /// `--no-bd`, as `"none"` when given
+    pub tracker: Option<String>,
+    /// `--auto-init-bd`, as `"true"` when given
+    pub auto_init_bd: Option<String>,
+    /// `--bd-sync`, as `"true"` when given
+    pub tracker_sync: Option<String>,
+    /// `--env-policy`
+    pub env_policy: Option<String>,
+    /// `--nice`
+    pub nice: Option<String>,
+    /// `--ionice`
+    pub ionice: Option<String>,
+    /// `--unsafe`, as `"true"` when given
+    pub auto_approve: Option<String>,
+    /// `--gate`, when given at least once
+    pub gates: Option<Vec<String>>,
+    /// `--no-collapse-repeats`, as `"0"` when given
+    pub collapse_repeats: Option<String>,
+    /// `--max-output-bytes`
+    pub max_output_bytes: Option<String>,
+    /// `--metrics-file`
+    pub metrics_file: Option<String>,
+}
+
+/// Every setting with its value and source, as produced by [`resolve`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EffectiveConfig {
+    /// Provider for `once`, `loop`, `run` and `watch`
+    pub provider: Setting<String>,
+    /// Iteration budget for `loop` and `watch`
+    pub iterations: Setting<u32>,
+    /// Text in a provider's output that ends a loop as complete
+    pub marker: Setting<String>,
+    /// Mention newer releases after `once`/`loop`
+    pub update_check: Setting<bool>,
+    /// Task tracker, one of [`tracker::VALID_TRACKERS`](crate::tracker::VALID_TRACKERS)
+    pub tracker: Setting<String>,
+    /// Run the tracker's sync command after a loop
+    pub tracker_sync: Setting<bool>,
+    /// The sync command; empty when the tracker has none
+    pub tracker_sync_command: Setting<String>,
+    /// GitHub repository; empty to use the `origin` remote's
+    pub tracker_repo: Setting<String>,
+    /// GitHub label marking issues as ready
+    pub tracker_label: Setting<String>,
+    /// Seconds a tracker command may run before it's killed
+    pub tracker_timeout_secs: Setting<u64>,
+    /// Run `bd init` without asking when there's no beads database
+    pub auto_init_bd: Setting<bool>,
+    /// Phrases added to the providers' own that mean a provider is waiting
+    /// for an interactive login (see [`providers::login_watch`](crate::providers::login_watch))
+    pub login_phrases: Setting<Vec<String>>,
+    /// Longest provider output line kept whole (see
+    /// [`stream::LineLimit`](crate::stream::LineLimit))
+    pub max_line_bytes: Setting<usize>,
+    /// Write over-long stdout lines whole to the captured output log
+    pub keep_long_lines: Setting<bool>,
+    /// Shortest run of identical provider output lines shown and logged
+    /// as one (see [`stream::set_collapse_repeats`](crate::stream::set_collapse_repeats));
+    /// 0 never collapses them
+    pub collapse_repeats: Setting<usize>,
+    /// Most output, in bytes, one provider run may print before it's
+    /// killed (see [`stream::set_max_output`](crate::stream::set_max_output))
+    pub max_output_bytes: Setting<u64>,
+    /// How much lower providers' CPU priority is than ralph's
+    pub nice: Setting<u8>,
+    /// Providers' I/O scheduling class on Linux; `None` leaves it alone
+    pub ionice: Setting<Option<IoClass>>,
+    /// Run providers with their permission checks off (see
+    /// [`providers::set_auto_approve`](crate::providers::set_auto_approve))
+    pub auto_approve: Setting<bool>,
+    /// Record every command ralph runs in the audit log (see
+    /// [`audit`](crate::audit))
+    pub audit: Setting<bool>,
+    /// Commands a loop runs after each iteration; the loop only stops as
+    /// complete when all of them pass
+    pub gates: Setting<Vec<String>>,
+    /// Where a loop writes its Prometheus metrics (see
+    /// [`metrics`](crate::metrics)); empty for nowhere
+    pub metrics_file: Setting<String>,
+    /// Versions the provider CLIs must match before a run starts, by
+    /// provider (see [`probe::check_pin`](crate::probe::check_pin))
+    pub provider_versions: Setting<BTreeMap<String, VersionReq>>,
+    /// Arguments given to each provider after its own flags, by provider
+    /// (before any `--provider-arg`)
+    pub provider_args: Setting<BTreeMap<String, Vec<String>>>,
+    /// Providers defined by a command template, by name
+    pub provider_templates: Setting<BTreeMap<String, Template>>,
+    /// Other names for providers (`[aliases]`), each the alias and the
+    /// provider it stands for
+    pub aliases: Setting<BTreeMap<String, String>>,
+    /// How providers get the prompt, for those with a `prompt_via`
+    pub prompt_via: Setting<BTreeMap<String, PromptTransport>>,
+    /// Which environment variables providers inherit
+    pub env_policy: Setting<EnvPolicy>,
+    /// Variables kept under [`EnvPolicy::Allowlist`]
+    pub env_allow: Setting<Vec<String>>,
+    /// Variables removed under [`EnvPolicy::Blocklist`]
+    pub env_block: Setting<Vec<String>>,
+    /// Variables set for every provider, as written (`env:NAME` values are
+    /// read when a provider starts)
+    pub env_vars: Setting<BTreeMap<String, String>>,
+    /// Variables set for one provider, by provider, over [`env_vars`](Self::env_vars)
+    pub provider_env: Setting<BTreeMap<String, BTreeMap<String, String>>>,
+    /// Age threshold for `ralph clean`
+    pub clean_older_than: Setting<String>,
+}
+
+impl EffectiveConfig {
+    /// The configured task tracker.
+    pub fn tracker(&self) -> Box<dyn Tracker> {
+        tracker::from_config(self)
+    }
+
+    /// The `[env]` settings, for [`env_policy::set`](crate::env_policy::set).
+    pub fn env_filter(&self) -> EnvFilter {
+        EnvFilter {
+            policy: self.env_policy.value,
+            allow: self.env_allow.value.clone(),
+            block: self.env_block.value.clone(),
+            set: self.env_vars.value.clone(),
+            provider_set: self.provider_env.value.clone(),
+        }
+    }
+
+    /// The `nice` and `ionice` settings, for
+    /// [`priority::set`](crate::priority::set).
+    pub fn priority(&self) -> Priority {
+        Priority {
+            nice: self.nice.value,
+            io: self.ionice.value,
+        }
+    }
+
+    /// The settings as `config.toml` lines, each commented with its source.
+    pub fn render_text(&self) -> String {
+        let line = |key: &str, value: String, source: Source| {
+            format!("{:<24} # {}\n", format!("{key} = {value}"), source)
+        };
+        let mut out = String::new();
+        out += &line(
+            "provider",
+            format!("{:?}", self.provider.value),
+            self.provider.source,
+        );
+        out += &line(
+            "iterations",
+            self.iterations.value.to_string(),
+            self.iterations.source,
+        );
+        out += &line(
+            "marker",
+            format!("{:?}", self.marker.value),
+            self.marker.source,
+        );
+        out += &line(
+            "update_check",
+            self.update_check.value.to_string(),
+            self.update_check.source,
+        );
+        out += &line(
+            "auto_init_bd",
+            self.auto_init_bd.value.to_string(),
+            self.auto_init_bd.source,
+        );
+        out += &line(
+            "login_phrases",
+            format!("{:?}", self.login_phrases.value),
+            self.login_phrases.source,
+        );
+        out += &line(
+            "max_line_bytes",
+            self.max_line_bytes.value.to_string(),
+            self.max_line_bytes.source,
+        );
+        out += &line(
+            "keep_long_lines",
+            self.keep_long_lines.value.to_string(),
+            self.keep_long_lines.source,
+        );
+        out += &line(
+            "collapse_repeats",
+            self.collapse_repeats.value.to_string(),
+            self.collapse_repeats.source,
+        );
+        out += &line(
+            "max_output_bytes",
+            self.max_output_bytes.value.to_string(),
+            self.max_output_bytes.source,
+        );
+        out += &line("nice", self.nice.value.to_string(), self.nice.source);
+        out += &line(
+            "ionice",
+            format!(
+                "{:?}",
+                self.ionice
+                    .value
+                    .map_or("none".to_string(), |io| io.to_string())
+            ),
+            self.ionice.source,
+        );
+        out += &line(
+            "auto_approve",
+            self.auto_approve.value.to_string(),
+            self.auto_approve.source,
+        );
+        out += &line("audit", self.audit.value.to_string(), self.audit.source);
+        out += &line(
+            "gates",
+            format!("{:?}", self.gates.value),
+            self.gates.source,
+        );
+        out += &line(
+            "metrics_file",
+            format!("{:?}", self.metrics_file.value),
+            self.metrics_file.source,
+        );
+        if !self.provider_versions.value.is_empty() {
+            out += "\n[provider_versions]\n";
+            for (provider, req) in &self.provider_versions.value {
+                out += &line(
+                    provider,
+                    format!("{:?}", req.to_string()),
+                    self.provider_versions.source,
+                );
+            }
+        }
+        if !self.provider_args.value.is_empty() {
+            out += "\n[provider_args]\n";
+            for (provider, args) in &self.provider_args.value {
+                out += &line(provider, format!("{args:?}"), self.provider_args.source);
+            }
+        }
+        if !self.aliases.value.is_empty() {
+            out += "\n[aliases]\n";
+            for (alias, provider) in &self.aliases.value {
+                out += &line(alias, format!("{provider:?}"), self.aliases.source);
+            }
+        }
+        for (provider, via) in &self.prompt_via.value {
+            out += &format!("\n[providers.{provider}]\n");
+            out += &line(
+                "prompt_via",
+                format!("{:?}", via.name()),
+                self.prompt_via.source,
+            );
+        }
+        for (provider, template) in &self.provider_templates.value {
+            out += &format!("\n[providers.{provider}]\n");
+            let source = self.provider_templates.source;
+            out += &line("command", format!("{:?}", template.command), source);
+            out += &line("shell", template.shell.to_string(), source);
+        }
+        out += "\n[tracker]\n";
+        out += &line(
+            "name",
+            format!("{:?}", self.tracker.value),
+            self.tracker.source,
+        );
+        out += &line(
+            "sync_after_run",
+            self.tracker_sync.value.to_string(),
+            self.tracker_sync.source,
+        );
+        out += &line(
+            "sync_command",
+            format!("{:?}", self.tracker_sync_command.value),
+            self.tracker_sync_command.source,
+        );
+        out += &line(
+            "repo",
+            format!("{:?}", self.tracker_repo.value),
+            self.tracker_repo.source,
+        );
+        out += &line(
+            "label",
+            format!("{:?}", self.tracker_label.value),
+            self.tracker_label.source,
+        );
+        out += &line(
+            "timeout_secs",
+            self.tracker_timeout_secs.value.to_string(),
+            self.tracker_timeout_secs.source,
+        );
+        out += "\n[env]\n";
+        out += &line(
+            "policy",
+            format!("{:?}", self.env_policy.value.to_string()),
+            self.env_policy.source,
+        );
+        out += &line(
+            "allow",
+            format!("{:?}", self.env_allow.value),
+            self.env_allow.source,
+        );
+        out += &line(
+            "block",
+            format!("{:?}", self.env_block.value),
+            self.env_block.source,
+        );
+        for (name, value) in &self.env_vars.value {
+            out += &line(name, format!("{value:?}"), self.env_vars.source);
+        }
+        for (provider, vars) in &self.provider_env.value {
+            out += &format!("\n[providers.{provider}.env]\n");
+            for (name, value) in vars {
+                out += &line(name, format!("{value:?}"), self.provider_env.source);
+            }
+        }
+        out += "\n[clean]\n";
+        out += &line(
+            "older_than",
+            format!("{:?}", self.clean_older_than.value),
+            self.clean_older_than.source,
+        );
+        out
+    }
+}
+
+/// Resolve every setting from its layers, each overriding the last:
+/// built-in defaults, the global `config.toml`, the beads config's
+/// `ralph.*` keys (with the beads tracker, see [`beads_settings`]), the
+/// project `.ralph/config.toml`, `RALPH_*` environment variables, then
+/// `flags`. Only the winning value of each setting is validated; an
+/// invalid one is reported with where it came from.
+pub fn resolve(flags: &Overrides) -> Result<EffectiveConfig> {
+    let global = load_config_file(&get_config_file_path()?)?;
+    let project = match find_project_dir() {
+        Some(dir) => load_config_file(&dir.join(CONFIG_FILE_NAME))?,
+        None => Config::default(),
+    };
+    let env = |var: &str| std::env::var(var).ok();
+    let config = resolve_layers(&global, &project, &BTreeMap::new(), env, flags)?;
+    if config.tracker.value != tracker::DEFAULT_TRACKER {
+        return Ok(config);
+    }
+    let beads = beads_settings();
+    if beads.is_empty() {
+        return Ok(config);
+    }
+    resolve_layers(&global, &project, &beads, env, flags)
+}
+
+/// The `ralph.*` settings of the beads database in the current directory
+/// (or a parent), without the prefix. bd is asked once per directory per
+/// process, and only when there is a `.beads` directory to ask about.
+fn beads_settings() -> BTreeMap<String, String> {
+    static CACHE: Mutex<BTreeMap<PathBuf, BTreeMap<String, String>>> = Mutex::new(BTreeMap::new());
+    let Ok(cwd) = std::env::current_dir() else {
+        return BTreeMap::new();
+    };
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    cache
+        .entry(cwd)
+        .or_insert_with_key(
+            |cwd| match cwd.ancestors().any(|dir| dir.join(".beads").is_dir()) {
+                true => crate::beads::project_settings(&SystemRunner),
+                false => BTreeMap::new(),
+            },
+        )
+        .clone()
+}
+
+/// [`resolve`] with the config files and the beads settings already loaded
+/// and the environment read through `env`. An empty variable counts as
+/// unset.
+fn resolve_layers(
+    global: &Config,
+    project: &Config,
+    beads: &BTreeMap<String, String>,
+    env: impl Fn(&str) -> Option<String>,
+    flags: &Overrides,
+) -> Result<EffectiveConfig> {
+    let env = |var: &str| env(var).filter(|v| !v.is_empty());
+    let beads = |key: &str| beads.get(key).cloned();
+
+    // Provider definitions come first, as every provider name is checked
+    // against them. A project's replace the global ones as a whole, like
+    // any table.
+    let provider_templates = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.templates()),
+            (Source::ProjectConfig, project.templates()),
+        ]
+        .map(|(source, templates)| (source, (!templates.is_empty()).then_some(templates))),
+    );
+    for (name, template) in &provider_templates.value {
+        let problem = match crate::providers::spec(name) {
+            Some(_) => Some(format!(
+                "{name} is a built-in provider; give yours another name"
+            )),
+            None => template.argv("", 1, &[]).err().map(|e| e.to_string()),
+        };
+        if let Some(problem) = problem {
+            return Err(RalphError::InvalidInput(format!(
+                "{}: [providers.{name}]: {problem}",
+                provider_templates.source
+            )));
+        }
+    }
+    let templates = &provider_templates.value;
+    let aliases = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.aliases.clone()),
+            (Source::ProjectConfig, project.aliases.clone()),
+        ],
+    );
+    check_aliases(&aliases, templates)?;
+
+    let provider = layered(
+        DEFAULT_PROVIDER.to_string(),
+        [
+            (Source::GlobalConfig, global.provider.clone()),
+            (Source::Beads, beads("provider")),
+            (Source::ProjectConfig, project.provider.clone()),
+            (Source::Env, env(PROVIDER_ENV)),
+            (Source::Flag, flags.provider.clone()),
+        ],
+    );
+    let provider = typed(provider, PROVIDER_ENV, |p| {
+        let provider = crate::providers::resolve_alias(p, templates, &aliases.value);
+        crate::providers::validate_provider_in(&provider, templates, &aliases.value)
+            .map(|()| provider)
+    })?;
+
+    let iterations = layered(
+        DEFAULT_ITERATIONS.to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.iterations.map(|n| n.to_string()),
+            ),
+            (Source::Beads, beads("iterations")),
+            (
+                Source::ProjectConfig,
+                project.iterations.map(|n| n.to_string()),
+            ),
+            (Source::Env, env(ITERATIONS_ENV)),
+            (Source::Flag, flags.iterations.clone()),
+        ],
+    );
+    let iterations = typed(iterations, ITERATIONS_ENV, validate_iterations)?;
+
+    let marker = layered(
+        crate::runner::COMPLETE_MARKER.to_string(),
+        [
+            (Source::GlobalConfig, global.marker.clone()),
+            (Source::Beads, beads("marker")),
+            (Source::ProjectConfig, project.marker.clone()),
+            (Source::Env, env(MARKER_ENV)),
+        ],
+    );
+    let marker = typed(marker, MARKER_ENV, |m| match m.trim() {
+        "" => Err(RalphError::InvalidInput(
+            "marker must not be blank".to_string(),
+        )),
+        _ => Ok(m.to_string()),
+    })?;
+
+    let update_check = layered(
+        "false".to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.update_check.map(|b| b.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.update_check.map(|b| b.to_string()),
+            ),
+            (Source::Env, env(UPDATE_CHECK_ENV)),
+        ],
+    );
+    let update_check = typed(update_check, UPDATE_CHECK_ENV, parse_bool)?;
+
+    let tracker = layered(
+        tracker::DEFAULT_TRACKER.to_string(),
+        [
+            (Source::GlobalConfig, global.tracker.name.clone()),
+            (Source::ProjectConfig, project.tracker.name.clone()),
+            (Source::Env, env(TRACKER_ENV)),
+            (Source::Flag, flags.tracker.clone()),
+        ],
+    );
+    let tracker = typed(tracker, TRACKER_ENV, |t| {
+        tracker::validate_tracker(t).map(|()| t.to_string())
+    })?;
+
+    let tracker_sync = layered(
+        "false".to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.tracker.sync_after_run.map(|b| b.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.tracker.sync_after_run.map(|b| b.to_string()),
+            ),
+            (Source::Env, env(TRACKER_SYNC_ENV)),
+            (Source::Flag, flags.tracker_sync.clone()),
+        ],
+    );
+    let tracker_sync = typed(tracker_sync, TRACKER_SYNC_ENV, parse_bool)?;
+
+    let tracker_sync_command = layered(
+        tracker::get(&tracker.value)
+            .and_then(|t| t.sync_command())
+            .unwrap_or_default()
+            .to_string(),
+        [
+            (Source::GlobalConfig, global.tracker.sync_command.clone()),
+            (Source::ProjectConfig, project.tracker.sync_command.clone()),
+            (Source::Env, env(TRACKER_SYNC_COMMAND_ENV)),
+        ],
+    );
+
+    let tracker_repo = layered(
+        String::new(),
+        [
+            (Source::GlobalConfig, global.tracker.repo.clone()),
+            (Source::ProjectConfig, project.tracker.repo.clone()),
+            (Source::Env, env(TRACKER_REPO_ENV)),
+        ],
+    );
+    let tracker_repo = typed(tracker_repo, TRACKER_REPO_ENV, |repo| {
+        if repo.is_empty() || repo.split('/').filter(|p| !p.is_empty()).count() == 2 {
+            Ok(repo.to_string())
+        } else {
+            Err(RalphError::InvalidInput(format!(
+                "Invalid GitHub repository '{repo}': expected owner/name"
+            )))
+        }
+    })?;
+
+    let tracker_label = layered(
+        crate::github::DEFAULT_LABEL.to_string(),
+        [
+            (Source::GlobalConfig, global.tracker.label.clone()),
+            (Source::ProjectConfig, project.tracker.label.clone()),
+            (Source::Env, env(TRACKER_LABEL_ENV)),
+        ],
+    );
+
+    let tracker_timeout_secs = layered(
+        tracker::DEFAULT_TIMEOUT.as_secs().to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.tracker.timeout_secs.map(|n| n.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.tracker.timeout_secs.map(|n| n.to_string()),
+            ),
+            (Source::Env, env(TRACKER_TIMEOUT_ENV)),
+        ],
+    );
+    let tracker_timeout_secs = typed(tracker_timeout_secs, TRACKER_TIMEOUT_ENV, |v| {
+        match v.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(RalphError::InvalidInput(
+                "tracker.timeout_secs must be a positive number of seconds".to_string(),
+            )),
+        }
+    })?;
+
+    let auto_init_bd = layered(
+        "false".to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.auto_init_bd.map(|b| b.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.auto_init_bd.map(|b| b.to_string()),
+            ),
+            (Source::Env, env(AUTO_INIT_BD_ENV)),
+            (Source::Flag, flags.auto_init_bd.clone()),
+        ],
+    );
+    let auto_init_bd = typed(auto_init_bd, AUTO_INIT_BD_ENV, parse_bool)?;
+
+    let login_phrases = layered(
+        Vec::new(),
+        [
+            (Source::GlobalConfig, global.login_phrases.clone()),
+            (Source::ProjectConfig, project.login_phrases.clone()),
+            (
+                Source::Env,
+                env(LOGIN_PHRASES_ENV).map(|v| v.split('|').map(str::to_string).collect()),
+            ),
+        ],
+    );
+
+    let max_line_bytes = layered(
+        crate::stream::DEFAULT_MAX_LINE.to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.max_line_bytes.map(|n| n.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.max_line_bytes.map(|n| n.to_string()),
+            ),
+            (Source::Env, env(MAX_LINE_BYTES_ENV)),
+        ],
+    );
+    let max_line_bytes = typed(max_line_bytes, MAX_LINE_BYTES_ENV, |v| match v.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(RalphError::InvalidInput(
+            "max_line_bytes must be a positive number of bytes".to_string(),
+        )),
+    })?;
+
+    let keep_long_lines = layered(
+        "false".to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.keep_long_lines.map(|b| b.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.keep_long_lines.map(|b| b.to_string()),
+            ),
+            (Source::Env, env(KEEP_LONG_LINES_ENV)),
+        ],
+    );
+    let keep_long_lines = typed(keep_long_lines, KEEP_LONG_LINES_ENV, parse_bool)?;
+
+    let collapse_repeats = layered(
+        crate::stream::DEFAULT_COLLAPSE_REPEATS.to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.collapse_repeats.map(|n| n.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.collapse_repeats.map(|n| n.to_string()),
+            ),
+            (Source::Env, env(COLLAPSE_REPEATS_ENV)),
+            (Source::Flag, flags.collapse_repeats.clone()),
+        ],
+    );
+    let collapse_repeats = typed(collapse_repeats, COLLAPSE_REPEATS_ENV, |v| {
+        match v.parse() {
+            Ok(n) if n != 1 => Ok(n),
+            _ => Err(RalphError::InvalidInput(
+                "collapse_repeats must be 0 (off) or a number of lines from 2 up".to_string(),
+            )),
+        }
+    })?;
+
+    let max_output_bytes = layered(
+        crate::stream::DEFAULT_MAX_OUTPUT.to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.max_output_bytes.as_ref().map(ByteSize::to_string),
+            ),
+            (
+                Source::ProjectConfig,
+                project.max_output_bytes.as_ref().map(ByteSize::to_string),
+            ),
+            (Source::Env, env(MAX_OUTPUT_BYTES_ENV)),
+            (Source::Flag, flags.max_output_bytes.clone()),
+        ],
+    );
+    let max_output_bytes = typed(max_output_bytes, MAX_OUTPUT_BYTES_ENV, |v| {
+        parse_size(v).ok_or_else(|| {
+            RalphError::InvalidInput(format!(
+                "max_output_bytes must be a size such as 1048576, 64K, 512M or 2G, got '{v}'"
+            ))
+        })
+    })?;
+
+    let nice = layered(
+        "0".to_string(),
+        [
+            (Source::GlobalConfig, global.nice.map(|n| n.to_string())),
+            (Source::ProjectConfig, project.nice.map(|n| n.to_string())),
+            (Source::Env, env(NICE_ENV)),
+            (Source::Flag, flags.nice.clone()),
+        ],
+    );
+    let nice = typed(nice, NICE_ENV, priority::parse_nice)?;
+
+    let ionice = layered(
+        "none".to_string(),
+        [
+            (Source::GlobalConfig, global.ionice.clone()),
+            (Source::ProjectConfig, project.ionice.clone()),
+            (Source::Env, env(IONICE_ENV)),
+            (Source::Flag, flags.ionice.clone()),
+        ],
+    );
+    let ionice = typed(ionice, IONICE_ENV, IoClass::parse)?;
+
+    let auto_approve = layered(
+        "false".to_string(),
+        [
+            (
+                Source::GlobalConfig,
+                global.auto_approve.map(|b| b.to_string()),
+            ),
+            (
+                Source::ProjectConfig,
+                project.auto_approve.map(|b| b.to_string()),
+            ),
+            (Source::Env, env(AUTO_APPROVE_ENV)),
+            (Source::Flag, flags.auto_approve.clone()),
+        ],
+    );
+    let auto_approve = typed(auto_approve, AUTO_APPROVE_ENV, parse_bool)?;
+
+    let audit = layered(
+        "true".to_string(),
+        [
+            (Source::GlobalConfig, global.audit.map(|b| b.to_string())),
+            (Source::ProjectConfig, project.audit.map(|b| b.to_string())),
+            (Source::Env, env(AUDIT_ENV)),
+        ],
+    );
+    let audit = typed(audit, AUDIT_ENV, parse_bool)?;
+
+    let gates = layered(
+        Vec::new(),
+        [
+            (Source::GlobalConfig, global.gates.clone()),
+            (Source::ProjectConfig, project.gates.clone()),
+            (Source::Flag, flags.gates.clone()),
+        ],
+    );
+
+    let metrics_file = layered(
+        String::new(),
+        [
+            (Source::GlobalConfig, global.metrics_file.clone()),
+            (Source::ProjectConfig, project.metrics_file.clone()),
+            (Source::Env, env(METRICS_FILE_ENV)),
+            (Source::Flag, flags.metrics_file.clone()),
+        ],
+    );
+
+    // A project's table replaces the global one rather than adding to it.
+    let provider_versions = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.provider_versions.clone()),
+            (Source::ProjectConfig, project.provider_versions.clone()),
+        ],
+    );
+    let pins = by_provider(&provider_versions, templates, &aliases.value, |_| {
+        "[provider_versions]".to_string()
+    })?;
+    let provider_versions = Setting {
+        value: parse_provider_versions(&pins, templates).map_err(|e| {
+            RalphError::InvalidInput(format!("{}: {}", provider_versions.source, e))
+        })?,
+        source: provider_versions.source,
+    };
+
+    // Likewise for each provider's arguments.
+    let provider_args = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.provider_args.clone()),
+            (Source::ProjectConfig, project.provider_args.clone()),
+        ],
+    );
+    let provider_args = Setting {
+        value: by_provider(&provider_args, templates, &aliases.value, |_| {
+            "[provider_args]".to_string()
+        })?,
+        source: provider_args.source,
+    };
+
+    let env_policy = layered(
+        EnvPolicy::default().to_string(),
+        [
+            (Source::GlobalConfig, global.env.policy.clone()),
+            (Source::ProjectConfig, project.env.policy.clone()),
+            (Source::Env, env(ENV_POLICY_ENV)),
+            (Source::Flag, flags.env_policy.clone()),
+        ],
+    );
+    let env_policy = typed(env_policy, ENV_POLICY_ENV, EnvPolicy::parse)?;
+    let names = |v: String| v.split(',').map(|n| n.trim().to_string()).collect();
+    let env_allow = layered(
+        Vec::new(),
+        [
+            (Source::GlobalConfig, global.env.allow.clone()),
+            (Source::ProjectConfig, project.env.allow.clone()),
+            (Source::Env, env(ENV_ALLOW_ENV).map(names)),
+        ],
+    );
+    let env_block = layered(
+        Vec::new(),
+        [
+            (Source::GlobalConfig, global.env.block.clone()),
+            (Source::ProjectConfig, project.env.block.clone()),
+            (Source::Env, env(ENV_BLOCK_ENV).map(names)),
+        ],
+    );
+    // The variables to set are tables too: a project's replaces the global
+    // one as a whole.
+    let env_vars = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, Some(global.env.vars.clone())),
+            (Source::ProjectConfig, Some(project.env.vars.clone())),
+        ]
+        .map(|(source, vars)| (source, vars.filter(|v| !v.is_empty()))),
+    );
+    check_env_vars(env_vars.source, "[env]", &env_vars.value)?;
+    let provider_env = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.providers.as_ref()),
+            (Source::ProjectConfig, project.providers.as_ref()),
+        ]
+        .map(|(source, providers)| {
+            let env: BTreeMap<_, _> = providers
+                .into_iter()
+                .flatten()
+                .filter(|(_, p)| !p.env.is_empty())
+                .map(|(name, p)| (name.clone(), p.env.clone()))
+                .collect();
+            (source, (!env.is_empty()).then_some(env))
+        }),
+    );
+    for (provider, vars) in &provider_env.value {
+        check_env_vars(
+            provider_env.source,
+            &format!("[providers.{provider}.env]"),
+            vars,
+        )?;
+    }
+    let provider_env = Setting {
+        value: by_provider(&provider_env, templates, &aliases.value, |provider| {
+            format!("[providers.{provider}.env]")
+        })?,
+        source: provider_env.source,
+    };
+    let prompt_via = layered(
+        BTreeMap::new(),
+        [
+            (Source::GlobalConfig, global.providers.as_ref()),
+            (Source::ProjectConfig, project.providers.as_ref()),
+        ]
+        .map(|(source, providers)| {
+            let via: BTreeMap<_, _> = providers
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, p)| Some((name.clone(), p.prompt_via.clone()?)))
+                .collect();
+            (source, (!via.is_empty()).then_some(via))
+        }),
+    );
+    let section = |provider: &str| format!("[providers.{provider}]");
+    let prompt_via = Setting {
+        value: by_provider(&prompt_via, templates, &aliases.value, section)?
+            .into_iter()
+            .map(|(provider, via)| {
+                parse_prompt_via(&provider, &via, templates)
+                    .map(|via| (provider.clone(), via))
+                    .map_err(|e| {
+                        RalphError::InvalidInput(format!(
+                            "{}: {}: {e}",
+                            prompt_via.source,
+                            section(&provider)
+                        ))
+                    })
+            })
+            .collect::<Result<_>>()?,
+        source: prompt_via.source,
+    };
+
+    let clean_older_than = layered(
+        crate::clean::DEFAULT_OLDER_THAN.to_string(),
+        [
+            (Source::GlobalConfig, global.clean.older_than.clone()),
+            (Source::ProjectConfig, project.clean.older_than.clone()),
+            (Source::Env, env(CLEAN_OLDER_THAN_ENV)),
+            (Source::Flag, flags.older_than.clone()),
+        ],
+    );
+
+    Ok(EffectiveConfig {
+        provider,
+        iterations,
+        marker,
+        update_check,
+        tracker,
+        tracker_sync,
+        tracker_sync_command,
+        tracker_repo,
+        tracker_label,
+        tracker_timeout_secs,
+        auto_init_bd,
+        login_phrases,
+        max_line_bytes,
+        keep_long_lines,
+        collapse_repeats,
+        max_output_bytes,
+        nice,
+        ionice,
+        auto_approve,
+        audit,
+        gates,
+        metrics_file,
+        provider_versions,
+        provider_args,
+        provider_templates,
+        aliases,
+        prompt_via,
+        env_policy,
+        env_allow,
+        env_block,
+        env_vars,
+        provider_env,
+        clean_older_than,
+    })
+}
+
+/// Check each `[aliases]` entry stands for a provider (not another alias)
+/// and doesn't take a provider's own name.
+fn check_aliases(
+    aliases: &Setting<BTreeMap<String, String>>,
+    templates: &BTreeMap<String, Template>,
+) -> Result<()> {
+    let no_aliases = BTreeMap::new();
+    for (alias, provider) in &aliases.value {
+        let source = aliases.source;
+        if crate::providers::validate_provider_in(alias, templates, &no_aliases).is_ok() {
+            return Err(RalphError::InvalidInput(format!(
+                "{source}: [aliases] {alias}: {alias} is a provider's own name"
+            )));
+        }
+        crate::providers::validate_provider_in(provider, templates, &no_aliases).map_err(|e| {
+            RalphError::InvalidInput(format!("{source}: [aliases] {alias} = {provider:?}: {e}"))
+        })?;
+    }
+    Ok(())
+}
+
+/// A table keyed by provider (`table`) with each key that's an alias
+/// under the provider it stands for, once each is checked to name one.
+/// `section` is the table's name for errors, given the key as written.
+fn by_provider<T: Clone>(
+    table: &Setting<BTreeMap<String, T>>,
+    templates: &BTreeMap<String, Template>,
+    aliases: &BTreeMap<String, String>,
+    section: impl Fn(&str) -> String,
+) -> Result<BTreeMap<String, T>> {
+    let mut resolved = BTreeMap::new();
+    for (name, value) in &table.value {
+        let error = |message: String| {
+            RalphError::InvalidInput(format!("{}: {}: {message}", table.source, section(name)))
+        };
+        let provider = crate::providers::resolve_alias(name, templates, aliases);
+        crate::providers::validate_provider_in(&provider, templates, aliases)
+            .map_err(|e| error(e.to_string()))?;
+        if provider != *name && table.value.contains_key(&provider) {
+            return Err(error(format!(
+                "'{name}' is an alias of {provider}, which is set too; keep one of them"
+            )));
+        }
+        resolved.insert(provider, value.clone());
+    }
+    Ok(resolved)
+}
+
+/// The `prompt_via` of `provider`: a way it can take the prompt.
+fn parse_prompt_via(
+    provider: &str,
+    via: &str,
+    templates: &BTreeMap<String, Template>,
+) -> Result<PromptTransport> {
+    if templates.contains_key(provider) {
+        return Err(RalphError::InvalidInput(
+            "prompt_via is for built-in providers; a command takes the prompt where {prompt} is, \
+             or on stdin"
+                .to_string(),
+        ));
+    }
+    let via: PromptTransport = via.parse()?;
+    if via == PromptTransport::File
+        && crate::providers::spec(provider).is_some_and(|spec| spec.file_flag.is_none())
+    {
+        return Err(RalphError::InvalidInput(format!(
+            "prompt_via = \"file\": {provider} can't read its prompt from a file; use \"stdin\""
+        )));
+    }
+    Ok(via)
+}
+
+/// Check the names and values of the variables `section` sets, which
+/// `source` gave.
+fn check_env_vars(source: Source, section: &str, vars: &BTreeMap<String, String>) -> Result<()> {
+    for (name, value) in vars {
+        let problem = if name.is_empty() || name.contains(['=', '\0']) {
+            "is not a valid variable name"
+        } else if value.contains('\0') {
+            "contains a NUL byte"
+        } else if value == crate::env_policy::REFERENCE_PREFIX {
+            "names no variable to read"
+        } else {
+            continue;
+        };
+        return Err(RalphError::InvalidInput(format!(
+            "{source}: {section}: {name:?} {problem}"
+        )));
+    }
+    Ok(())
+}
+
+/// The value of the last layer that sets one, or `default`. Layers are
+/// given lowest precedence first.
+fn layered<T>(default: T, layers: impl IntoIterator<Item = (Source, Option<T>)>) -> Setting<T> {
+    layers.into_iter().fold(
+        Setting {
+            value: default,
+            source: Source::Default,
+        },
+        |winner, (source, value)| match value {
+            Some(value) => Setting { value, source },
+            None => winner,
+        },
+    )
+}
+
+/// Parse a layered setting, naming where an invalid value came from
+/// (`env_var` when it's the environment). Flag errors are left as they are.
+fn typed<T>(
+    setting: Setting<String>,
+    env_var: &str,
+    parse: impl FnOnce(&str) -> Result<T>,
+) -> Result<Setting<T>> {
+    let source = setting.source;
+    let value = parse(&setting.value).map_err(|e| match source {
+        Source::Flag => e,
+        Source::Env => RalphError::InvalidInput(format!("{env_var}: {e}")),
+        _ => RalphError::InvalidInput(format!("{source}: {e}")),
+    })?;
+    Ok(Setting { value, source })
+}
+
+/// Check each `[provider_versions]` entry is for a built-in provider (not
+/// one of `templates`) and holds a semver requirement such as `2.1.x` or
+/// `>=0.45`.
+fn parse_provider_versions(
+    pins: &BTreeMap<String, String>,
+    templates: &BTreeMap<String, Template>,
+) -> Result<BTreeMap<String, VersionReq>> {
+    pins.iter()
+        .map(|(provider, req)| {
+            if templates.contains_key(provider) {
+                return Err(RalphError::InvalidInput(format!(
+                    "[provider_versions] {provider}: command providers aren't probed for a \
+                     version and can't be pinned"
+                )));
+            }
+            let parsed = VersionReq::parse(req).map_err(|e| {
+                RalphError::InvalidInput(format!(
+                    "invalid version requirement '{req}' for {provider}: {e}"
+                ))
+            })?;
+            Ok((provider.clone(), parsed))
+        })
+        .collect()
+}
+
+/// Parse a size such as `1048576`, `64K`, `512M` or `2GiB`: a positive
+/// number of bytes, optionally with a K, M or G unit (powers of 1024,
+/// case-insensitive, with or without a trailing `B` or `iB`).
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let number: u64 = number.parse().ok()?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let scale: u64 = match unit.as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return None,
+    };
+    number.checked_mul(scale).filter(|bytes| *bytes > 0)
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(RalphError::InvalidInput(format!(
+            "expected true or false, got '{value}'"
+        ))),
+    }
+}
+
+/// Environment variable naming the directory to use instead of `~/.Ralph`
+/// for config, the system prompt, logs, sessions and caches.
+pub const HOME_ENV: &str = "RALPH_HOME";
+
+/// Get the Ralph configuration directory path: `$RALPH_HOME` when set,
+/// else `~/.Ralph/`. Without either (e.g. a sandbox with no home
+/// directory) falls back to `.ralph/` in the current directory, with a
+/// one-time warning.
+pub fn get_config_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = config_dir_from(std::env::var_os(HOME_ENV), dirs::home_dir()) {
+        return Ok(dir);
+    }
+    let dir = std::env::current_dir()?.join(PROJECT_DIR_NAME);
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        reporter::warn(&format!(
+            "No home directory and {} is not set; using {}",
+            HOME_ENV,
+            dir.display()
+        ));
+    });
+    Ok(dir)
+}
+
+/// The config directory for a `RALPH_HOME` value and home directory, or
+/// `None` when neither is usable. An empty `RALPH_HOME` counts as unset.
+fn config_dir_from(ralph_home: Option<OsString>, home: Option<PathBuf>) -> Option<PathBuf> {
+    match ralph_home {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home.map(|home| home.join(".Ralph")),
+    }
+}
+
+/// Get the system prompt file path: the project's
+/// `.ralph/system-prompt.md` when it has one, else
+/// ~/.Ralph/system-prompt.md
+pub fn get_system_prompt_path() -> io::Result<PathBuf> {
+    if let Some(path) = find_project_dir()
+        .map(|dir| dir.join(SYSTEM_PROMPT_FILE_NAME))
+        .filter(|path| path.is_file())
+    {
+        return Ok(path);
+    }
+    Ok(get_config_dir()?.join(SYSTEM_PROMPT_FILE_NAME))
+}
+
+/// Get the global config file path (~/.Ralph/config.toml)
+pub fn get_config_file_path() -> io::Result<PathBuf> {
+    Ok(get_config_dir()?.join(CONFIG_FILE_NAME))
+}
+
+/// Locate the project-local `.ralph/` directory in the current working
+/// directory, if there is one. On case-insensitive filesystems running from
+/// the home directory would otherwise pick up `~/.Ralph` itself, so that
+/// case is excluded.
+pub fn find_project_dir() -> Option<PathBuf> {
+    let dir = std::env::current_dir().ok()?.join(PROJECT_DIR_NAME);
+    if !dir.is_dir() {
+        return None;
+    }
+    let same_as_global = get_config_dir()
+        .ok()
+        .and_then(|global| fs::canonicalize(global).ok())
+        .zip(fs::canonicalize(&dir).ok())
+        .is_some_and(|(global, project)| global == project);
+    if same_as_global { None } else { Some(dir) }
+}
+
+/// Ensure the configuration directory and default system prompt file exist.
+/// Creates them if they don't exist; a new prompt is the configured
+/// tracker's template.
+pub fn ensure_config() -> Result<()> {
+    let template = resolve(&Overrides::default())
+        .map(|config| config.tracker().prompt_template())
+        .unwrap_or(DEFAULT_SYSTEM_PROMPT);
+    ensure_config_in(&get_config_dir()?, template)
+}
+
+fn ensure_config_in(config_dir: &Path, default_prompt: &str) -> Result<()> {
+    // create_dir_all succeeds when another ralph creates the directory
+    // between our check and the call.
+    let existed = config_dir.is_dir();
+    fs::create_dir_all(config_dir).map_err(|source| RalphError::ConfigInit {
+        path: config_dir.to_path_buf(),
+        source,
+    })?;
+    if !existed {
+        reporter::info(&format!(
+            "Created configuration directory: {}",
+            config_dir.display()
+        ));
+    }
+
+    // Create the default system prompt unless it exists. An empty one is
+    // what an interrupted write left behind before writes were atomic.
+    let prompt_path = config_dir.join(SYSTEM_PROMPT_FILE_NAME);
+    let created = if fs::metadata(&prompt_path).is_ok_and(|m| m.len() == 0) {
+        fsutil::write_atomic(&prompt_path, default_prompt).map(|()| true)
+    } else {
+        fsutil::create_atomic(&prompt_path, default_prompt)
+    }
+    .map_err(|source| RalphError::ConfigInit {
+        path: prompt_path.clone(),
+        source,
+    })?;
+    if created {
+        reporter::info(&format!(
+            "Created default system prompt: {}",
+            prompt_path.display()
+        ));
+    }
+
+    // The pricing table only feeds cost estimates; failing to seed it
+    // mustn't stop a run.
+    let pricing_path = config_dir.join(pricing::PRICING_FILE);
+    if let Err(e) = pricing::seed(&pricing_path) {
+        reporter::warn(&format!(
+            "Failed to create {}: {}",
+            pricing_path.display(),
+            e
+        ));
+    }
+
+    Ok(())
+}
+
+/// Read the system prompt from the configuration file.
+/// This function assumes ensure_config() has been called first.
+pub fn read_system_prompt() -> Result<String> {
+    let path = get_system_prompt_path()?;
+    fs::read_to_string(&path).map_err(|source| RalphError::Prompt { path, source })
+}
+
+/// The system prompt for a run with `config`: the prompt file, unless it's
+/// an unedited built-in template, in which case the configured tracker's
+/// template.
+pub fn system_prompt(config: &EffectiveConfig) -> Result<String> {
+    Ok(prompt_for_tracker(
+        read_system_prompt()?,
+        config.tracker().as_ref(),
+    ))
+}
+
+fn prompt_for_tracker(prompt: String, tracker: &dyn Tracker) -> String {
+    if crate::prompts::is_builtin(&prompt) {
+        tracker.prompt_template().to_string()
+    } else {
+        prompt
+    }
+}
+
+/// Apply `edit` to the TOML table in `path` (empty if the file is missing)
+/// and write it back. Keys `edit` doesn't touch are preserved; comments are
+/// not.
+pub fn update_config_file(path: &Path, edit: impl FnOnce(&mut toml::Table)) -> Result<()> {
+    let mut table = match fs::read_to_string(path) {
+        Ok(content) => content
+            .parse::<toml::Table>()
+            .map_err(|e| invalid_config(path, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(e.into()),
+    };
+    edit(&mut table);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fsutil::write_atomic(path, toml::to_string(&table).map_err(io::Error::other)?)?;
+    Ok(())
+}
+
+fn invalid_config(path: &Path, err: impl std::fmt::Display) -> RalphError {
+    RalphError::Config {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    }
+}
+
+/// Parse a config file. A missing file is an empty config; a malformed one
+/// is an error naming the file.
+pub fn load_config_file(path: &Path) -> Result<Config> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e.into()),
+    };
+    toml::from_str(&content).map_err(|e| invalid_config(path, e))
+}
+
+/// Load the global config merged with the project config (project wins).
+pub fn load_config() -> Result<Config> {
+    let global = load_config_file(&get_config_file_path()?)?;
+    let project = match find_project_dir() {
+        Some(dir) => load_config_file(&dir.join(CONFIG_FILE_NAME))?,
+        None => Config::default(),
+    };
+    Ok(global.merge(project))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_system_prompt_not_empty() {
+        assert!(!DEFAULT_SYSTEM_PROMPT.is_empty());
+        assert!(DEFAULT_SYSTEM_PROMPT.contains("bd"));
+        assert!(DEFAULT_SYSTEM_PROMPT.contains("beads"));
+    }
+
+    #[test]
+    fn test_get_config_dir() {
+        let config_dir = get_config_dir().expect("Should get config dir");
+        assert!(config_dir.is_absolute());
+    }
+
+    #[test]
+    fn ralph_home_replaces_the_home_directory() {
+        let home = Some(PathBuf::from("/home/u"));
+        assert_eq!(
+            config_dir_from(Some("/tmp/rh".into()), home.clone()),
+            Some(PathBuf::from("/tmp/rh"))
+        );
+        assert_eq!(
+            config_dir_from(Some("".into()), home.clone()),
+            Some(PathBuf::from("/home/u/.Ralph"))
+        );
+        assert_eq!(
+            config_dir_from(None, home),
+            Some(PathBuf::from("/home/u/.Ralph"))
+        );
+        assert_eq!(config_dir_from(None, None), None);
+    }
+
+    #[test]
+    fn test_get_system_prompt_path() {
+        let prompt_path = get_system_prompt_path().expect("Should get prompt path");
+        assert!(prompt_path.ends_with("system-prompt.md"));
+    }
+
+    #[test]
+    fn unedited_default_prompt_follows_the_tracker() {
+        let none = tracker::get(tracker::NONE).unwrap();
+        assert_eq!(
+            prompt_for_tracker(DEFAULT_SYSTEM_PROMPT.to_string(), none),
+            crate::prompts::NONE
+        );
+        assert_eq!(prompt_for_tracker("mine".to_string(), none), "mine");
+        let beads = tracker::get(tracker::DEFAULT_TRACKER).unwrap();
+        assert_eq!(
+            prompt_for_tracker(crate::prompts::NONE.to_string(), beads),
+            DEFAULT_SYSTEM_PROMPT
+        );
+    }
+
+    #[test]
+    fn test_ensure_config_creates_dir_and_prompt() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_dir = dir.path().join("ralph-home");
+        ensure_config_in(&config_dir, DEFAULT_SYSTEM_PROMPT).expect("ensure_config should succeed");
+
+        let content = fs::read_to_string(config_dir.join(SYSTEM_PROMPT_FILE_NAME)).unwrap();
+        assert_eq!(content, DEFAULT_SYSTEM_PROMPT);
+        assert!(config_dir.join(pricing::PRICING_FILE).is_file());
+
+        // An edited prompt is left alone.
+        fs::write(config_dir.join(SYSTEM_PROMPT_FILE_NAME), "mine").unwrap();
+        ensure_config_in(&config_dir, DEFAULT_SYSTEM_PROMPT).unwrap();
+        let content = fs::read_to_string(config_dir.join(SYSTEM_PROMPT_FILE_NAME)).unwrap();
+        assert_eq!(content, "mine");
+    }
+
+    #[test]
+    fn test_ensure_config_survives_a_racing_first_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_dir = dir.path().join("ralph-home");
+        // Another ralph created the directory and is midway through its own
+        // prompt write; an older version left an empty prompt behind.
+        fs::create_dir(&config_dir).unwrap();
+        fs::write(config_dir.join(".system-prompt.md.99-0.tmp"), "Use bd").unwrap();
+        fs::write(config_dir.join(SYSTEM_PROMPT_FILE_NAME), "").unwrap();
+
+        ensure_config_in(&config_dir, DEFAULT_SYSTEM_PROMPT).unwrap();
+        let content = fs::read_to_string(config_dir.join(SYSTEM_PROMPT_FILE_NAME)).unwrap();
+        assert_eq!(content, DEFAULT_SYSTEM_PROMPT);
+    }
+
+    #[test]
+    fn test_ensure_config_failure_names_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-dir");
+        fs::write(&file, "").unwrap();
+        let config_dir = file.join("ralph-home");
+        match ensure_config_in(&config_dir, DEFAULT_SYSTEM_PROMPT) {
+            Err(RalphError::ConfigInit { path, .. }) => assert_eq!(path, config_dir),
+            other => panic!("expected ConfigInit, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_update_config_file_leaves_no_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "provider = \"claude\"\n").unwrap();
+        update_config_file(&path, |t| {
+            t.insert("iterations".into(), toml::Value::Integer(3));
+        })
+        .unwrap();
+        let config = load_config_file(&path).unwrap();
+        assert_eq!(config.iterations, Some(3));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_load_config_file_missing_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = load_config_file(&dir.path().join("config.toml")).unwrap();
+        assert!(config.provider.is_none());
+        assert!(config.iterations.is_none());
+    }
+
+    #[test]
+    fn test_load_config_file_parses_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "provider = \"claude\"\niterations = 3\nunknown = 1\n",
+        )
+        .unwrap();
+        let config = load_config_file(&path).unwrap();
+        assert_eq!(config.provider.as_deref(), Some("claude"));
+        assert_eq!(config.iterations, Some(3));
+    }
+
+    #[test]
+    fn test_load_config_file_invalid_names_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "provider = [").unwrap();
+        let err = load_config_file(&path).unwrap_err();
+        assert!(err.to_string().contains("config.toml"));
+    }
+
+    #[test]
+    fn test_update_config_file_preserves_other_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "provider = \"codex\"\n[clean]\nolder_than = \"7d\"\n",
+        )
+        .unwrap();
+        update_config_file(&path, |t| {
+            t.insert("iterations".to_string(), toml::Value::Integer(4));
+        })
+        .unwrap();
+        let config = load_config_file(&path).unwrap();
+        assert_eq!(config.provider.as_deref(), Some("codex"));
+        assert_eq!(config.iterations, Some(4));
+        assert_eq!(config.clean.older_than.as_deref(), Some("7d"));
+    }
+
+    #[test]
+    fn test_validate_iterations_valid() {
+        assert_eq!(validate_iterations("1").unwrap(), 1);
+        assert_eq!(validate_iterations("5").unwrap(), 5);
+        assert_eq!(validate_iterations("10").unwrap(), 10);
+        assert_eq!(validate_iterations("100").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_validate_iterations_zero() {
+        let result = validate_iterations("0");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("positive integer"));
+    }
+
+    #[test]
+    fn test_validate_iterations_negative() {
+        // "-1" would fail to parse as u32, so should error
+        let result = validate_iterations("-1");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("positive integer"));
+    }
+
+    #[test]
+    fn test_validate_iterations_non_numeric() {
+        let result = validate_iterations("abc");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("positive integer"));
+
+        let result = validate_iterations("10.5");
+        assert!(result.is_err());
+
+        let result = validate_iterations("");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_merge_prefers_overlay() {
+        let global = Config {
+            provider: Some("codex".to_string()),
+            iterations: Some(5),
+            ..Config::default()
+        };
+        let project = Config {
+            provider: Some("claude".to_string()),
+            iterations: None,
+            ..Config::default()
+        };
+        let merged = global.merge(project);
+        assert_eq!(merged.provider.as_deref(), Some("claude"));
+        assert_eq!(merged.iterations, Some(5));
+    }
+
+    /// One precedence case: the config files, environment and flag given,
+    /// and the expected value and source.
+    type Case = (
+        &'static str,
+        &'static str,
+        &'static [(&'static str, &'static str)],
+        Option<&'static str>,
+        (&'static str, Source),
+    );
+
+    fn resolve_case(
+        global: &str,
+        project: &str,
+        env: &[(&str, &str)],
+        flags: &Overrides,
+    ) -> Result<EffectiveConfig> {
+        resolve_layers(
+            &toml::from_str(global).unwrap(),
+            &toml::from_str(project).unwrap(),
+            &BTreeMap::new(),
+            |var| {
+                env.iter()
+                    .find(|(k, _)| *k == var)
+                    .map(|(_, v)| v.to_string())
+            },
+            flags,
+        )
+    }
+
+    struct Key {
+        name: &'static str,
+        set_flag: fn(&mut Overrides, String),
+        get: fn(&EffectiveConfig) -> (String, Source),
+        cases: [Case; 6],
+    }
+
+    /// Pins defaults < global < project < env < flag for every key.
+    #[test]
+    fn resolve_precedence_for_every_key() {
+        use Source::*;
+        let keys = [
+            Key {
+                name: "provider",
+                set_flag: |f, v| f.provider = Some(v),
+                get: |c| (c.provider.value.clone(), c.provider.source),
+                cases: [
+                    ("", "", &[], None, ("droid", Default)),
+                    (
+                        r#"provider = "codex""#,
+                        "",
+                        &[],
+                        None,
+                        ("codex", GlobalConfig),
+                    ),
+                    (
+                        r#"provider = "codex""#,
+                        r#"provider = "claude""#,
+                        &[],
+                        None,
+                        ("claude", ProjectConfig),
+                    ),
+                    (
+                        r#"provider = "codex""#,
+                        r#"provider = "claude""#,
+                        &[(PROVIDER_ENV, "gemini")],
+                        None,
+                        ("gemini", Env),
+                    ),
+                    (
+                        r#"provider = "codex""#,
+                        r#"provider = "claude""#,
+                        &[(PROVIDER_ENV, "gemini")],
+                        Some("droid"),
+                        ("droid", Flag),
+                    ),
+                    (
+                        r#"provider = "codex""#,
+                        "",
+                        &[(PROVIDER_ENV, "")],
+                        None,
+                        ("codex", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "iterations",
+                set_flag: |f, v| f.iterations = Some(v),
+                get: |c| (c.iterations.value.to_string(), c.iterations.source),
+                cases: [
+                    ("", "", &[], None, ("10", Default)),
+                    ("iterations = 2", "", &[], None, ("2", GlobalConfig)),
+                    (
+                        "iterations = 2",
+                        "iterations = 3",
+                        &[],
+                        None,
+                        ("3", ProjectConfig),
+                    ),
+                    (
+                        "iterations = 2",
+                        "iterations = 3",
+                        &[(ITERATIONS_ENV, "4")],
+                        None,
+                        ("4", Env),
+                    ),
+                    (
+                        "iterations = 2",
+                        "iterations = 3",
+                        &[(ITERATIONS_ENV, "4")],
+                        Some("5"),
+                        ("5", Flag),
+                    ),
+                    (
+                        "",
+                        "iterations = 3",
+                        &[(ITERATIONS_ENV, "")],
+                        None,
+                        ("3", ProjectConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "marker",
+                // There is no --marker flag.
+                set_flag: |_, _| {},
+                get: |c| (c.marker.value.clone(), c.marker.source),
+                cases: [
+                    ("", "", &[], None, ("<promise>COMPLETE</promise>", Default)),
+                    (r#"marker = "DONE""#, "", &[], None, ("DONE", GlobalConfig)),
+                    (
+                        r#"marker = "DONE""#,
+                        r#"marker = "SHIPPED""#,
+                        &[],
+                        None,
+                        ("SHIPPED", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        r#"marker = "SHIPPED""#,
+                        &[(MARKER_ENV, "ALL DONE")],
+                        None,
+                        ("ALL DONE", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(MARKER_ENV, "ALL DONE")],
+                        Some("x"),
+                        ("ALL DONE", Env),
+                    ),
+                    (
+                        r#"marker = "DONE""#,
+                        "",
+                        &[(MARKER_ENV, "")],
+                        None,
+                        ("DONE", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "update_check",
+                // There is no --update-check flag.
+                set_flag: |_, _| {},
+                get: |c| (c.update_check.value.to_string(), c.update_check.source),
+                cases: [
+                    ("", "", &[], None, ("false", Default)),
+                    ("update_check = true", "", &[], None, ("true", GlobalConfig)),
+                    (
+                        "update_check = true",
+                        "update_check = false",
+                        &[],
+                        None,
+                        ("false", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "update_check = false",
+                        &[(UPDATE_CHECK_ENV, "yes")],
+                        None,
+                        ("true", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(UPDATE_CHECK_ENV, "0")],
+                        Some("1"),
+                        ("false", Env),
+                    ),
+                    (
+                        "update_check = true",
+                        "",
+                        &[(UPDATE_CHECK_ENV, "")],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker",
+                // --no-bd is the only flag, and it gives "none".
+                set_flag: |f, v| f.tracker = Some(v),
+                get: |c| (c.tracker.value.clone(), c.tracker.source),
+                cases: [
+                    ("", "", &[], None, ("beads", Default)),
+                    (r#"tracker = "none""#, "", &[], None, ("none", GlobalConfig)),
+                    (
+                        r#"tracker = "none""#,
+                        r#"tracker = "beads""#,
+                        &[],
+                        None,
+                        ("beads", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        r#"tracker = "beads""#,
+                        &[(TRACKER_ENV, "none")],
+                        None,
+                        ("none", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(TRACKER_ENV, "beads")],
+                        Some("none"),
+                        ("none", Flag),
+                    ),
+                    (
+                        r#"tracker = "none""#,
+                        "",
+                        &[(TRACKER_ENV, "")],
+                        None,
+                        ("none", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker.sync_after_run",
+                set_flag: |f, v| f.tracker_sync = Some(v),
+                get: |c| (c.tracker_sync.value.to_string(), c.tracker_sync.source),
+                cases: [
+                    ("", "", &[], None, ("false", Default)),
+                    (
+                        "[tracker]\nsync_after_run = true",
+                        "",
+                        &[],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                    (
+                        "[tracker]\nsync_after_run = true",
+                        "[tracker]\nsync_after_run = false",
+                        &[],
+                        None,
+                        ("false", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[tracker]\nsync_after_run = false",
+                        &[(TRACKER_SYNC_ENV, "yes")],
+                        None,
+                        ("true", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(TRACKER_SYNC_ENV, "no")],
+                        Some("true"),
+                        ("true", Flag),
+                    ),
+                    (
+                        "[tracker]\nsync_after_run = true",
+                        "",
+                        &[(TRACKER_SYNC_ENV, "")],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker.sync_command",
+                // There is no flag for the sync command.
+                set_flag: |_, _| {},
+                get: |c| {
+                    (
+                        c.tracker_sync_command.value.clone(),
+                        c.tracker_sync_command.source,
+                    )
+                },
+                cases: [
+                    ("", "", &[], None, ("bd sync", Default)),
+                    (
+                        "[tracker]\nsync_command = \"bd sync --quiet\"",
+                        "",
+                        &[],
+                        None,
+                        ("bd sync --quiet", GlobalConfig),
+                    ),
+                    (
+                        "[tracker]\nsync_command = \"bd sync --quiet\"",
+                        "[tracker]\nname = \"beads\"\nsync_command = \"make sync\"",
+                        &[],
+                        None,
+                        ("make sync", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[tracker]\nsync_command = \"make sync\"",
+                        &[(TRACKER_SYNC_COMMAND_ENV, "just sync")],
+                        None,
+                        ("just sync", Env),
+                    ),
+                    ("tracker = \"none\"", "", &[], None, ("", Default)),
+                    (
+                        "",
+                        "[tracker]\nsync_command = \"make sync\"",
+                        &[(TRACKER_SYNC_COMMAND_ENV, "")],
+                        None,
+                        ("make sync", ProjectConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker.repo",
+                // There is no flag for the repository.
+                set_flag: |_, _| {},
+                get: |c| (c.tracker_repo.value.clone(), c.tracker_repo.source),
+                cases: [
+                    ("", "", &[], None, ("", Default)),
+                    (
+                        "[tracker]\nrepo = \"acme/app\"",
+                        "",
+                        &[],
+                        None,
+                        ("acme/app", GlobalConfig),
+                    ),
+                    (
+                        "[tracker]\nrepo = \"acme/app\"",
+                        "[tracker]\nname = \"github\"\nrepo = \"acme/fork\"",
+                        &[],
+                        None,
+                        ("acme/fork", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[tracker]\nrepo = \"acme/fork\"",
+                        &[(TRACKER_REPO_ENV, "me/app")],
+                        None,
+                        ("me/app", Env),
+                    ),
+                    (
+                        "tracker = \"github\"",
+                        "",
+                        &[(TRACKER_REPO_ENV, "me/app")],
+                        None,
+                        ("me/app", Env),
+                    ),
+                    (
+                        "",
+                        "[tracker]\nrepo = \"acme/fork\"",
+                        &[(TRACKER_REPO_ENV, "")],
+                        None,
+                        ("acme/fork", ProjectConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker.label",
+                // There is no flag for the label.
+                set_flag: |_, _| {},
+                get: |c| (c.tracker_label.value.clone(), c.tracker_label.source),
+                cases: [
+                    ("", "", &[], None, ("ready", Default)),
+                    (
+                        "[tracker]\nlabel = \"ralph\"",
+                        "",
+                        &[],
+                        None,
+                        ("ralph", GlobalConfig),
+                    ),
+                    (
+                        "[tracker]\nlabel = \"ralph\"",
+                        "[tracker]\nlabel = \"agent ready\"",
+                        &[],
+                        None,
+                        ("agent ready", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[tracker]\nlabel = \"agent ready\"",
+                        &[(TRACKER_LABEL_ENV, "todo")],
+                        None,
+                        ("todo", Env),
+                    ),
+                    ("tracker = \"github\"", "", &[], None, ("ready", Default)),
+                    (
+                        "",
+                        "[tracker]\nlabel = \"agent ready\"",
+                        &[(TRACKER_LABEL_ENV, "")],
+                        None,
+                        ("agent ready", ProjectConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "tracker.timeout_secs",
+                set_flag: |_, _| {},
+                get: |c| {
+                    (
+                        c.tracker_timeout_secs.value.to_string(),
+                        c.tracker_timeout_secs.source,
+                    )
+                },
+                cases: [
+                    ("", "", &[], None, ("30", Default)),
+                    (
+                        "[tracker]\ntimeout_secs = 5",
+                        "",
+                        &[],
+                        None,
+                        ("5", GlobalConfig),
+                    ),
+                    (
+                        "[tracker]\ntimeout_secs = 5",
+                        "[tracker]\ntimeout_secs = 120",
+                        &[],
+                        None,
+                        ("120", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[tracker]\ntimeout_secs = 120",
+                        &[(TRACKER_TIMEOUT_ENV, "10")],
+                        None,
+                        ("10", Env),
+                    ),
+                    ("tracker = \"beads\"", "", &[], None, ("30", Default)),
+                    (
+                        "",
+                        "[tracker]\ntimeout_secs = 120",
+                        &[(TRACKER_TIMEOUT_ENV, "")],
+                        None,
+                        ("120", ProjectConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "auto_init_bd",
+                set_flag: |f, v| f.auto_init_bd = Some(v),
+                get: |c| (c.auto_init_bd.value.to_string(), c.auto_init_bd.source),
+                cases: [
+                    ("", "", &[], None, ("false", Default)),
+                    ("auto_init_bd = true", "", &[], None, ("true", GlobalConfig)),
+                    (
+                        "auto_init_bd = true",
+                        "auto_init_bd = false",
+                        &[],
+                        None,
+                        ("false", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "auto_init_bd = false",
+                        &[(AUTO_INIT_BD_ENV, "on")],
+                        None,
+                        ("true", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(AUTO_INIT_BD_ENV, "0")],
+                        Some("true"),
+                        ("true", Flag),
+                    ),
+                    (
+                        "auto_init_bd = true",
+                        "",
+                        &[(AUTO_INIT_BD_ENV, "")],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "login_phrases",
+                // There is no flag for login phrases.
+                set_flag: |_, _| {},
+                get: |c| (c.login_phrases.value.join("|"), c.login_phrases.source),
+                cases: [
+                    ("", "", &[], None, ("", Default)),
+                    (
+                        "login_phrases = [\"sign in\"]",
+                        "",
+                        &[],
+                        None,
+                        ("sign in", GlobalConfig),
+                    ),
+                    (
+                        "login_phrases = [\"sign in\"]",
+                        "login_phrases = [\"a\", \"b\"]",
+                        &[],
+                        None,
+                        ("a|b", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "login_phrases = [\"a\"]",
+                        &[(LOGIN_PHRASES_ENV, "token expired|re-authenticate")],
+                        None,
+                        ("token expired|re-authenticate", Env),
+                    ),
+                    ("", "login_phrases = []", &[], None, ("", ProjectConfig)),
+                    (
+                        "login_phrases = [\"sign in\"]",
+                        "",
+                        &[(LOGIN_PHRASES_ENV, "")],
+                        None,
+                        ("sign in", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "max_line_bytes",
+                // There is no flag for the line limit.
+                set_flag: |_, _| {},
+                get: |c| (c.max_line_bytes.value.to_string(), c.max_line_bytes.source),
+                cases: [
+                    ("", "", &[], None, ("4194304", Default)),
+                    (
+                        "max_line_bytes = 1000",
+                        "",
+                        &[],
+                        None,
+                        ("1000", GlobalConfig),
+                    ),
+                    (
+                        "max_line_bytes = 1000",
+                        "max_line_bytes = 2000",
+                        &[],
+                        None,
+                        ("2000", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "max_line_bytes = 2000",
+                        &[(MAX_LINE_BYTES_ENV, "65536")],
+                        None,
+                        ("65536", Env),
+                    ),
+                    (
+                        "max_line_bytes = 1000",
+                        "",
+                        &[(MAX_LINE_BYTES_ENV, "")],
+                        None,
+                        ("1000", GlobalConfig),
+                    ),
+                    ("", "", &[], Some("ignored"), ("4194304", Default)),
+                ],
+            },
+            Key {
+                name: "keep_long_lines",
+                // There is no flag for keeping long lines.
+                set_flag: |_, _| {},
+                get: |c| {
+                    (
+                        c.keep_long_lines.value.to_string(),
+                        c.keep_long_lines.source,
+                    )
+                },
+                cases: [
+                    ("", "", &[], None, ("false", Default)),
+                    (
+                        "keep_long_lines = true",
+                        "",
+                        &[],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                    (
+                        "keep_long_lines = true",
+                        "keep_long_lines = false",
+                        &[],
+                        None,
+                        ("false", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "keep_long_lines = false",
+                        &[(KEEP_LONG_LINES_ENV, "yes")],
+                        None,
+                        ("true", Env),
+                    ),
+                    (
+                        "keep_long_lines = true",
+                        "",
+                        &[(KEEP_LONG_LINES_ENV, "")],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                    ("", "", &[], Some("true"), ("false", Default)),
+                ],
+            },
+            Key {
+                name: "collapse_repeats",
+                set_flag: |f, v| f.collapse_repeats = Some(v),
+                get: |c| {
+                    let value = c.collapse_repeats.value.to_string();
+                    (value, c.collapse_repeats.source)
+                },
+                cases: [
+                    ("", "", &[], None, ("5", Default)),
+                    ("collapse_repeats = 3", "", &[], None, ("3", GlobalConfig)),
+                    (
+                        "collapse_repeats = 3",
+                        "collapse_repeats = 0",
+                        &[],
+                        None,
+                        ("0", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "collapse_repeats = 0",
+                        &[(COLLAPSE_REPEATS_ENV, "20")],
+                        None,
+                        ("20", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(COLLAPSE_REPEATS_ENV, "20")],
+                        Some("0"),
+                        ("0", Flag),
+                    ),
+                    (
+                        "collapse_repeats = 3",
+                        "",
+                        &[(COLLAPSE_REPEATS_ENV, "")],
+                        None,
+                        ("3", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "max_output_bytes",
+                set_flag: |f, v| f.max_output_bytes = Some(v),
+                get: |c| {
+                    let value = c.max_output_bytes.value.to_string();
+                    (value, c.max_output_bytes.source)
+                },
+                cases: [
+                    ("", "", &[], None, ("536870912", Default)),
+                    (
+                        "max_output_bytes = 1000",
+                        "",
+                        &[],
+                        None,
+                        ("1000", GlobalConfig),
+                    ),
+                    (
+                        "max_output_bytes = 1000",
+                        r#"max_output_bytes = "64K""#,
+                        &[],
+                        None,
+                        ("65536", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        r#"max_output_bytes = "64K""#,
+                        &[(MAX_OUTPUT_BYTES_ENV, "2G")],
+                        None,
+                        ("2147483648", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(MAX_OUTPUT_BYTES_ENV, "2G")],
+                        Some("1MiB"),
+                        ("1048576", Flag),
+                    ),
+                    (
+                        "max_output_bytes = 1000",
+                        "",
+                        &[(MAX_OUTPUT_BYTES_ENV, "")],
+                        None,
+                        ("1000", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "metrics_file",
+                set_flag: |f, v| f.metrics_file = Some(v),
+                get: |c| (c.metrics_file.value.clone(), c.metrics_file.source),
+                cases: [
+                    ("", "", &[], None, ("", Default)),
+                    (
+                        r#"metrics_file = "/var/lib/node_exporter/ralph.prom""#,
+                        "",
+                        &[],
+                        None,
+                        ("/var/lib/node_exporter/ralph.prom", GlobalConfig),
+                    ),
+                    (
+                        r#"metrics_file = "/var/lib/node_exporter/ralph.prom""#,
+                        r#"metrics_file = "ralph.prom""#,
+                        &[],
+                        None,
+                        ("ralph.prom", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        r#"metrics_file = "ralph.prom""#,
+                        &[(METRICS_FILE_ENV, "env.prom")],
+                        None,
+                        ("env.prom", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(METRICS_FILE_ENV, "env.prom")],
+                        Some("flag.prom"),
+                        ("flag.prom", Flag),
+                    ),
+                    (
+                        r#"metrics_file = "ralph.prom""#,
+                        "",
+                        &[(METRICS_FILE_ENV, "")],
+                        None,
+                        ("ralph.prom", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "nice",
+                set_flag: |f, v| f.nice = Some(v),
+                get: |c| (c.nice.value.to_string(), c.nice.source),
+                cases: [
+                    ("", "", &[], None, ("0", Default)),
+                    ("nice = 10", "", &[], None, ("10", GlobalConfig)),
+                    ("nice = 10", "nice = 5", &[], None, ("5", ProjectConfig)),
+                    ("", "nice = 5", &[(NICE_ENV, "19")], None, ("19", Env)),
+                    ("", "", &[(NICE_ENV, "19")], Some("3"), ("3", Flag)),
+                    (
+                        "nice = 10",
+                        "",
+                        &[(NICE_ENV, "")],
+                        None,
+                        ("10", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "ionice",
+                set_flag: |f, v| f.ionice = Some(v),
+                get: |c| {
+                    let value = c
+                        .ionice
+                        .value
+                        .map_or("none".to_string(), |io| io.to_string());
+                    (value, c.ionice.source)
+                },
+                cases: [
+                    ("", "", &[], None, ("none", Default)),
+                    (r#"ionice = "idle""#, "", &[], None, ("idle", GlobalConfig)),
+                    (
+                        r#"ionice = "idle""#,
+                        r#"ionice = "none""#,
+                        &[],
+                        None,
+                        ("none", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        r#"ionice = "idle""#,
+                        &[(IONICE_ENV, "best-effort:7")],
+                        None,
+                        ("best-effort:7", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(IONICE_ENV, "best-effort:7")],
+                        Some("idle"),
+                        ("idle", Flag),
+                    ),
+                    (
+                        r#"ionice = "idle""#,
+                        "",
+                        &[(IONICE_ENV, "")],
+                        None,
+                        ("idle", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "auto_approve",
+                set_flag: |f, v| f.auto_approve = Some(v),
+                get: |c| (c.auto_approve.value.to_string(), c.auto_approve.source),
+                cases: [
+                    ("", "", &[], None, ("false", Default)),
+                    ("auto_approve = true", "", &[], None, ("true", GlobalConfig)),
+                    (
+                        "auto_approve = true",
+                        "auto_approve = false",
+                        &[],
+                        None,
+                        ("false", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "auto_approve = false",
+                        &[(AUTO_APPROVE_ENV, "yes")],
+                        None,
+                        ("true", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(AUTO_APPROVE_ENV, "false")],
+                        Some("true"),
+                        ("true", Flag),
+                    ),
+                    (
+                        "auto_approve = true",
+                        "",
+                        &[(AUTO_APPROVE_ENV, "")],
+                        None,
+                        ("true", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "audit",
+                // There is no flag for the audit log.
+                set_flag: |_, _| {},
+                get: |c| (c.audit.value.to_string(), c.audit.source),
+                cases: [
+                    ("", "", &[], None, ("true", Default)),
+                    ("audit = false", "", &[], None, ("false", GlobalConfig)),
+                    (
+                        "audit = false",
+                        "audit = true",
+                        &[],
+                        None,
+                        ("true", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "audit = true",
+                        &[(AUDIT_ENV, "no")],
+                        None,
+                        ("false", Env),
+                    ),
+                    (
+                        "audit = false",
+                        "",
+                        &[(AUDIT_ENV, "")],
+                        None,
+                        ("false", GlobalConfig),
+                    ),
+                    ("", "", &[], Some("false"), ("true", Default)),
+                ],
+            },
+            Key {
+                name: "env.policy",
+                set_flag: |f, v| f.env_policy = Some(v),
+                get: |c| (c.env_policy.value.to_string(), c.env_policy.source),
+                cases: [
+                    ("", "", &[], None, ("inherit", Default)),
+                    (
+                        "[env]\npolicy = \"blocklist\"",
+                        "",
+                        &[],
+                        None,
+                        ("blocklist", GlobalConfig),
+                    ),
+                    (
+                        "[env]\npolicy = \"blocklist\"",
+                        "[env]\npolicy = \"allowlist\"",
+                        &[],
+                        None,
+                        ("allowlist", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[env]\npolicy = \"allowlist\"",
+                        &[(ENV_POLICY_ENV, "inherit")],
+                        None,
+                        ("inherit", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(ENV_POLICY_ENV, "inherit")],
+                        Some("allowlist"),
+                        ("allowlist", Flag),
+                    ),
+                    (
+                        "[env]\npolicy = \"blocklist\"",
+                        "",
+                        &[(ENV_POLICY_ENV, "")],
+                        None,
+                        ("blocklist", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "env.allow",
+                // There is no flag for the lists.
+                set_flag: |_, _| {},
+                get: |c| (c.env_allow.value.join(","), c.env_allow.source),
+                cases: [
+                    ("", "", &[], None, ("", Default)),
+                    (
+                        "[env]\nallow = [\"EDITOR\"]",
+                        "",
+                        &[],
+                        None,
+                        ("EDITOR", GlobalConfig),
+                    ),
+                    (
+                        "[env]\nallow = [\"EDITOR\"]",
+                        "[env]\nallow = [\"GIT_*\", \"SSH_AUTH_SOCK\"]",
+                        &[],
+                        None,
+                        ("GIT_*,SSH_AUTH_SOCK", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[env]\nallow = [\"EDITOR\"]",
+                        &[(ENV_ALLOW_ENV, "GIT_*, CARGO_HOME")],
+                        None,
+                        ("GIT_*,CARGO_HOME", Env),
+                    ),
+                    ("", "[env]\nallow = []", &[], None, ("", ProjectConfig)),
+                    (
+                        "[env]\nallow = [\"EDITOR\"]",
+                        "",
+                        &[(ENV_ALLOW_ENV, "")],
+                        None,
+                        ("EDITOR", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "env.block",
+                set_flag: |_, _| {},
+                get: |c| (c.env_block.value.join(","), c.env_block.source),
+                cases: [
+                    ("", "", &[], None, ("", Default)),
+                    (
+                        "[env]\nblock = [\"AWS_*\"]",
+                        "",
+                        &[],
+                        None,
+                        ("AWS_*", GlobalConfig),
+                    ),
+                    (
+                        "[env]\nblock = [\"AWS_*\"]",
+                        "[env]\nblock = [\"DEPLOY_TOKEN\"]",
+                        &[],
+                        None,
+                        ("DEPLOY_TOKEN", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[env]\nblock = [\"AWS_*\"]",
+                        &[(ENV_BLOCK_ENV, "NPM_TOKEN,AWS_*")],
+                        None,
+                        ("NPM_TOKEN,AWS_*", Env),
+                    ),
+                    ("", "[env]\nblock = []", &[], None, ("", ProjectConfig)),
+                    (
+                        "[env]\nblock = [\"AWS_*\"]",
+                        "",
+                        &[(ENV_BLOCK_ENV, "")],
+                        None,
+                        ("AWS_*", GlobalConfig),
+                    ),
+                ],
+            },
+            Key {
+                name: "clean.older_than",
+                set_flag: |f, v| f.older_than = Some(v),
+                get: |c| (c.clean_older_than.value.clone(), c.clean_older_than.source),
+                cases: [
+                    ("", "", &[], None, ("30d", Default)),
+                    (
+                        "[clean]\nolder_than = \"7d\"",
+                        "",
+                        &[],
+                        None,
+                        ("7d", GlobalConfig),
+                    ),
+                    (
+                        "[clean]\nolder_than = \"7d\"",
+                        "[clean]\nolder_than = \"2d\"",
+                        &[],
+                        None,
+                        ("2d", ProjectConfig),
+                    ),
+                    (
+                        "",
+                        "[clean]\nolder_than = \"2d\"",
+                        &[(CLEAN_OLDER_THAN_ENV, "12h")],
+                        None,
+                        ("12h", Env),
+                    ),
+                    (
+                        "",
+                        "",
+                        &[(CLEAN_OLDER_THAN_ENV, "12h")],
+                        Some("1h"),
+                        ("1h", Flag),
+                    ),
+                    (
+                        "[clean]\nolder_than = \"7d\"",
+                        "",
+                        &[(CLEAN_OLDER_THAN_ENV, "")],
+                        None,
+                        ("7d", GlobalConfig),
+                    ),
+                ],
+            },
+        ];
+        for key in &keys {
+            for (i, (global, project, env, flag, expect)) in key.cases.iter().enumerate() {
+                let mut flags = Overrides::default();
+                if let Some(flag) = flag {
+                    (key.set_flag)(&mut flags, flag.to_string());
+                }
+                let config = resolve_case(global, project, env, &flags)
+                    .unwrap_or_else(|e| panic!("{} case {i}: {e}", key.name));
+                let (value, source) = (key.get)(&config);
+                assert_eq!((value.as_str(), source), *expect, "{} case {i}", key.name);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_values_name_their_layer() {
+        let err = |global: &str, env: &[(&str, &str)], flags: &Overrides| {
+            resolve_case(global, "", env, flags)
+                .unwrap_err()
+                .to_string()
+        };
+        let none = Overrides::default();
+        assert_eq!(
+            err("", &[(ITERATIONS_ENV, "many")], &none),
+            "RALPH_ITERATIONS: iterations must be a positive integer"
+        );
+        assert!(
+            err(r#"provider = "nope""#, &[], &none)
+                .starts_with("global config: Invalid provider 'nope'")
+        );
+        assert_eq!(
+            err("", &[(UPDATE_CHECK_ENV, "maybe")], &none),
+            "RALPH_UPDATE_CHECK: expected true or false, got 'maybe'"
+        );
+        assert_eq!(
+            err("[tracker]\nrepo = \"acme\"", &[], &none),
+            "global config: Invalid GitHub repository 'acme': expected owner/name"
+        );
+        assert_eq!(
+            err("max_line_bytes = 0", &[], &none),
+            "global config: max_line_bytes must be a positive number of bytes"
+        );
+        assert_eq!(
+            err("collapse_repeats = 1", &[], &none),
+            "global config: collapse_repeats must be 0 (off) or a number of lines from 2 up"
+        );
+        assert_eq!(
+            err("", &[(MAX_OUTPUT_BYTES_ENV, "lots")], &none),
+            "RALPH_MAX_OUTPUT_BYTES: max_output_bytes must be a size such as 1048576, 64K, 512M or 2G, got 'lots'"
+        );
+        let zero = Overrides {
+            iterations: Some("0".to_string()),
+            ..Overrides::default()
+        };
+        assert_eq!(err("", &[], &zero), "iterations must be a positive integer");
+
+        // Only the winning value is checked.
+        let two = Overrides {
+            iterations: Some("2".to_string()),
+            ..Overrides::default()
+        };
+        let config = resolve_case("iterations = 0", "", &[], &two).unwrap();
+        assert_eq!(config.iterations.value, 2);
+    }
+
+    #[test]
+    fn sizes_take_binary_units() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("64K"), Some(64 << 10));
+        assert_eq!(parse_size("512m"), Some(512 << 20));
+        assert_eq!(parse_size(" 2 GiB "), Some(2 << 30));
+        assert_eq!(parse_size("10MB"), Some(10 << 20));
+        for bad in ["", "0", "0K", "M", "1.5G", "-1", "12T", "99999999999G"] {
+            assert_eq!(parse_size(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn gates_from_the_command_line_replace_the_configured_ones() {
+        let project = r#"gates = ["cargo build", "cargo test"]"#;
+        let config = resolve_case(r#"gates = ["make"]"#, project, &[], &Overrides::default());
+        let gates = config.unwrap().gates;
+        assert_eq!(gates.value, ["cargo build", "cargo test"]);
+        assert_eq!(gates.source, Source::ProjectConfig);
+        let flags = Overrides {
+            gates: Some(vec!["npm test".to_string()]),
+            ..Overrides::default()
+        };
+        let gates = resolve_case("", project, &[], &flags).unwrap().gates;
+        assert_eq!(
+            (gates.value, gates.source),
+            (vec!["npm test".to_string()], Source::Flag)
+        );
+    }
+
+    #[test]
+    fn beads_settings_sit_between_the_global_and_project_config() {
+        let resolve = |global: &str, project: &str, env: &[(&str, &str)]| {
+            let beads = BTreeMap::from([
+                ("provider".to_string(), "claude".to_string()),
+                ("iterations".to_string(), "25".to_string()),
+                ("marker".to_string(), "DONE".to_string()),
+                ("unknown".to_string(), "ignored".to_string()),
+            ]);
+            resolve_layers(
+                &toml::from_str(global).unwrap(),
+                &toml::from_str(project).unwrap(),
+                &beads,
+                |var| {
+                    env.iter()
+                        .find(|(k, _)| *k == var)
+                        .map(|(_, v)| v.to_string())
+                },
+                &Overrides::default(),
+            )
+        };
+        let config = resolve(r#"provider = "codex""#, "iterations = 3", &[]).unwrap();
+        assert_eq!(
+            (config.provider.value.as_str(), config.provider.source),
+            ("claude", Source::Beads)
+        );
+        assert_eq!(
+            (config.iterations.value, config.iterations.source),
+            (3, Source::ProjectConfig)
+        );
+        assert_eq!(
+            (config.marker.value.as_str(), config.marker.source),
+            ("DONE", Source::Beads)
+        );
+        assert!(
+            config
+                .render_text()
+                .starts_with("provider = \"claude\"      # beads config\n")
+        );
+        let config = resolve("", "", &[(PROVIDER_ENV, "gemini")]).unwrap();
+        assert_eq!(config.provider.source, Source::Env);
+
+        let beads = BTreeMap::from([("iterations".to_string(), "lots".to_string())]);
+        let err = resolve_layers(
+            &Config::default(),
+            &Config::default(),
+            &beads,
+            |_| None,
+            &Overrides::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("beads config: "), "{err}");
+    }
+
+    #[test]
+    fn provider_version_pins_are_validated_where_they_came_from() {
+        let project = "[provider_versions]\nclaude = \"2.1.x\"\ncodex = \">=0.45\"\n";
+        let config = resolve_case(
+            "[provider_versions]\ngemini = \"1\"",
+            project,
+            &[],
+            &Overrides::default(),
+        )
+        .unwrap();
+        let pins = &config.provider_versions;
+        assert_eq!(pins.source, Source::ProjectConfig);
+        assert_eq!(pins.value.keys().collect::<Vec<_>>(), ["claude", "codex"]);
+        assert!(
+            config
+                .render_text()
+                .contains("\n[provider_versions]\nclaude = \"2.1.*\"")
+        );
+
+        let bad = "[provider_versions]\nclaude = \"two\"";
+        let err = resolve_case("", bad, &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("project config: invalid version requirement 'two' for claude"),
+            "{err}"
+        );
+        let unknown = "[provider_versions]\ncursor = \"1\"";
+        let err = resolve_case(unknown, "", &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("global config: [provider_versions]: Invalid provider 'cursor'"),
+            "{err}"
+        );
+
+        // Config-defined providers are known, but have no version to pin.
+        let template = "[providers.mywrapper]\ncommand = \"agent {prompt}\"\n\
+                        [provider_versions]\nmywrapper = \"1.x\"";
+        let err = resolve_case(template, "", &[], &Overrides::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "global config: [provider_versions] mywrapper: command providers aren't probed \
+             for a version and can't be pinned"
+        );
+        let alias = "[aliases]\noai = \"codex\"\n[provider_versions]\noai = \">=0.45\"";
+        let config = resolve_case(alias, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(
+            config.provider_versions.value.keys().collect::<Vec<_>>(),
+            ["codex"]
+        );
+    }
+
+    #[test]
+    fn provider_args_are_checked_and_rendered() {
+        let config = resolve_case("", "", &[], &Overrides::default()).unwrap();
+        assert!(config.provider_args.value.is_empty());
+        assert!(!config.render_text().contains("[provider_args]"));
+
+        let global = "[provider_args]\ncodex = [\"--sandbox\", \"workspace-write\"]\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        let args = &config.provider_args;
+        assert_eq!(args.source, Source::GlobalConfig);
+        assert_eq!(args.value["codex"], ["--sandbox", "workspace-write"]);
+        assert!(config.render_text().contains(
+            "\n[provider_args]\ncodex = [\"--sandbox\", \"workspace-write\"] # global config\n"
+        ));
+
+        let unknown = "[provider_args]\ncursor = [\"-v\"]";
+        let err = resolve_case("", unknown, &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("project config: [provider_args]: Invalid provider 'cursor'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn env_vars_are_layered_checked_and_rendered() {
+        let config = resolve_case("", "", &[], &Overrides::default()).unwrap();
+        assert!(config.env_vars.value.is_empty());
+        assert!(!config.render_text().contains("[providers."));
+
+        let global = "[env]\npolicy = \"allowlist\"\nOPENAI_BASE_URL = \"https://proxy\"\n\
+                      [providers.claude.env]\nANTHROPIC_API_KEY = \"env:WORK_KEY\"\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(config.env_policy.value, EnvPolicy::Allowlist);
+        assert_eq!(config.env_vars.source, Source::GlobalConfig);
+        assert_eq!(config.env_vars.value["OPENAI_BASE_URL"], "https://proxy");
+        assert_eq!(
+            config.provider_env.value["claude"]["ANTHROPIC_API_KEY"],
+            "env:WORK_KEY"
+        );
+        let text = config.render_text();
+        assert!(
+            text.contains("\nOPENAI_BASE_URL = \"https://proxy\" # global config\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains(
+                "\n[providers.claude.env]\nANTHROPIC_API_KEY = \"env:WORK_KEY\" # global config\n"
+            ),
+            "{text}"
+        );
+
+        // A project's tables replace the global ones as a whole.
+        let project = "[env]\nHTTP_PROXY = \"http://proxy\"\n";
+        let config = resolve_case(global, project, &[], &Overrides::default()).unwrap();
+        assert_eq!(config.env_vars.source, Source::ProjectConfig);
+        assert!(!config.env_vars.value.contains_key("OPENAI_BASE_URL"));
+        assert_eq!(config.provider_env.source, Source::GlobalConfig);
+
+        for (project, message) in [
+            (
+                "[providers.cursor.env]\nA = \"b\"",
+                "project config: [providers.cursor.env]: Invalid provider 'cursor'",
+            ),
+            (
+                "[env]\n\"A=B\" = \"c\"",
+                "project config: [env]: \"A=B\" is not a valid variable name",
+            ),
+            (
+                "[providers.codex.env]\nKEY = \"env:\"",
+                "project config: [providers.codex.env]: \"KEY\" names no variable to read",
+            ),
+        ] {
+            let err = resolve_case("", project, &[], &Overrides::default()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn providers_defined_by_a_command_are_layered_checked_and_rendered() {
+        let global = "provider = \"mywrapper\"\n\
+                      [providers.mywrapper]\ncommand = \"./scripts/agent.sh {prompt}\"\n\
+                      [providers.mywrapper.env]\nAGENT_MODE = \"ci\"\n\
+                      [provider_args]\nmywrapper = [\"--fast\"]\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(config.provider.value, "mywrapper");
+        assert_eq!(
+            config.provider_templates.value["mywrapper"],
+            Template {
+                command: "./scripts/agent.sh {prompt}".to_string(),
+                shell: false,
+            }
+        );
+        assert_eq!(config.provider_env.value["mywrapper"]["AGENT_MODE"], "ci");
+        let text = config.render_text();
+        assert!(
+            text.contains(
+                "\n[providers.mywrapper]\n\
+                 command = \"./scripts/agent.sh {prompt}\" # global config\n\
+                 shell = false            # global config\n"
+            ),
+            "{text}"
+        );
+
+        // A project's definitions replace the global ones as a whole.
+        let project = "[providers.other]\ncommand = \"other-agent\"\nshell = true\n";
+        let err = resolve_case(global, project, &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid provider 'mywrapper'"),
+            "{err}"
+        );
+        let config = resolve_case("", project, &[], &Overrides::default()).unwrap();
+        assert_eq!(config.provider_templates.source, Source::ProjectConfig);
+        assert!(config.provider_templates.value["other"].shell);
+
+        for (project, message) in [
+            (
+                "[providers.claude]\ncommand = \"my-claude {prompt}\"",
+                "project config: [providers.claude]: claude is a built-in provider",
+            ),
+            (
+                "[providers.mine]\ncommand = \"agent '{prompt}\"",
+                "project config: [providers.mine]: command \"agent '{prompt}\" has an unfinished quote",
+            ),
+            (
+                "[providers.mine]\ncommand = \"\"",
+                "project config: [providers.mine]: command is empty",
+            ),
+        ] {
+            let err = resolve_case("", project, &[], &Overrides::default()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn provider_aliases_resolve_to_the_provider_everywhere_it_is_chosen() {
+        let global = "provider = \"oai\"\n[aliases]\noai = \"codex\"\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(config.provider.value, "codex");
+        assert_eq!(config.provider.source, Source::GlobalConfig);
+        assert!(
+            config
+                .render_text()
+                .contains("\n[aliases]\noai = \"codex\"            # global config\n"),
+            "{}",
+            config.render_text()
+        );
+
+        // Built-in aliases work anywhere a provider is chosen, and the
+        // config's win over them.
+        let flag = |provider: &str| Overrides {
+            provider: Some(provider.to_string()),
+            ..Overrides::default()
+        };
+        let config = resolve_case(global, "", &[(PROVIDER_ENV, "cc")], &flag("cc")).unwrap();
+        assert_eq!(config.provider.value, "claude");
+        let project = "[aliases]\ncc = \"copilot\"\n";
+        let config = resolve_case(global, project, &[], &flag("cc")).unwrap();
+        assert_eq!(config.provider.value, "copilot");
+        // A project's table replaces the global one as a whole.
+        let err = resolve_case(global, project, &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("global config: Invalid provider 'oai'"),
+            "{err}"
+        );
+        let project = "[providers.cc]\ncommand = \"my-cc {prompt}\"\n";
+        let config = resolve_case("", project, &[], &flag("cc")).unwrap();
+        assert_eq!(config.provider.value, "cc");
+
+        let err = resolve_case("", "", &[], &flag("cladue")).unwrap_err();
+        assert!(
+            err.to_string().ends_with("\nDid you mean 'claude'?"),
+            "{err}"
+        );
+
+        for (project, message) in [
+            (
+                "[aliases]\nclaude = \"codex\"",
+                "project config: [aliases] claude: claude is a provider's own name",
+            ),
+            (
+                "[providers.mine]\ncommand = \"agent\"\n[aliases]\nmine = \"codex\"",
+                "project config: [aliases] mine: mine is a provider's own name",
+            ),
+            (
+                "[aliases]\noai = \"codx\"",
+                "project config: [aliases] oai = \"codx\": Invalid provider 'codx'",
+            ),
+            (
+                "[aliases]\noai = \"codex\"\nai = \"oai\"",
+                "project config: [aliases] ai = \"oai\": Invalid provider 'oai'",
+            ),
+        ] {
+            let err = resolve_case("", project, &[], &Overrides::default()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+        let err =
+            resolve_case("", "[aliases]\noai = \"codx\"", &[], &Overrides::default()).unwrap_err();
+        assert!(
+            err.to_string().ends_with("\nDid you mean 'codex'?"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn provider_tables_take_aliases_for_the_provider_they_stand_for() {
+        let global = "[aliases]\noai = \"codex\"\n\
+                      [provider_args]\noai = [\"--profile\", \"work\"]\ncc = [\"--model\", \"opus\"]\n\
+                      [providers.oai]\nprompt_via = \"stdin\"\n\
+                      [providers.oai.env]\nOPENAI_BASE_URL = \"https://llm.internal/v1\"\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(
+            config.provider_args.value,
+            BTreeMap::from([
+                (
+                    "claude".to_string(),
+                    vec!["--model".to_string(), "opus".to_string()]
+                ),
+                (
+                    "codex".to_string(),
+                    vec!["--profile".to_string(), "work".to_string()]
+                ),
+            ])
+        );
+        assert_eq!(
+            config.prompt_via.value,
+            BTreeMap::from([("codex".to_string(), PromptTransport::Stdin)])
+        );
+        assert_eq!(
+            config.provider_env.value["codex"]["OPENAI_BASE_URL"],
+            "https://llm.internal/v1"
+        );
+
+        for (table, message) in [
+            (
+                "[provider_args]\noai = [\"-v\"]\ncodex = [\"-q\"]",
+                "global config: [provider_args]: 'oai' is an alias of codex, which is set too",
+            ),
+            (
+                "[providers.oai]\nprompt_via = \"stdin\"\n[providers.codex]\nprompt_via = \"arg\"",
+                "global config: [providers.oai]: 'oai' is an alias of codex, which is set too",
+            ),
+            (
+                "[providers.cc.env]\nA = \"1\"\n[providers.claude.env]\nB = \"2\"",
+                "global config: [providers.cc.env]: 'cc' is an alias of claude, which is set too",
+            ),
+            (
+                "[provider_args]\noia = [\"-v\"]",
+                "global config: [provider_args]: Invalid provider 'oia'",
+            ),
+        ] {
+            let global = format!("[aliases]\noai = \"codex\"\n{table}\n");
+            let err = resolve_case(&global, "", &[], &Overrides::default()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn prompt_via_is_checked_against_what_the_provider_can_read() {
+        let global = "[providers.claude]\nprompt_via = \"stdin\"\n\
+                      [providers.droid]\nprompt_via = \"file\"\n";
+        let config = resolve_case(global, "", &[], &Overrides::default()).unwrap();
+        assert_eq!(
+            config.prompt_via.value,
+            BTreeMap::from([
+                ("claude".to_string(), PromptTransport::Stdin),
+                ("droid".to_string(), PromptTransport::File),
+            ])
+        );
+        let text = config.render_text();
+        assert!(
+            text.contains("\n[providers.claude]\nprompt_via = \"stdin\"     # global config\n"),
+            "{text}"
+        );
+
+        for (project, message) in [
+            (
+                "[providers.claude]\nprompt_via = \"pipe\"",
+                "project config: [providers.claude]: prompt_via must be arg, stdin or file, got 'pipe'",
+            ),
+            (
+                "[providers.codex]\nprompt_via = \"file\"",
+                "project config: [providers.codex]: prompt_via = \"file\": codex can't read its prompt from a file",
+            ),
+            (
+                "[providers.mine]\ncommand = \"agent\"\nprompt_via = \"stdin\"",
+                "project config: [providers.mine]: prompt_via is for built-in providers",
+            ),
+            (
+                "[providers.cursor]\nprompt_via = \"stdin\"",
+                "project config: [providers.cursor]: Invalid provider 'cursor'",
+            ),
+        ] {
+            let err = resolve_case("", project, &[], &Overrides::default()).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn effective_config_renders_as_commented_toml() {
+        let config = resolve_case(
+            "",
+            r#"provider = "claude""#,
+            &[(ITERATIONS_ENV, "4")],
+            &Overrides::default(),
+        )
+        .unwrap();
+        let text = config.render_text();
+        assert_eq!(
+            text,
+            "provider = \"claude\"      # project config\n\
+             iterations = 4           # environment\n\
+             marker = \"<promise>COMPLETE</promise>\" # default\n\
+             update_check = false     # default\n\
+             auto_init_bd = false     # default\n\
+             login_phrases = []       # default\n\
+             max_line_bytes = 4194304 # default\n\
+             keep_long_lines = false  # default\n\
+             collapse_repeats = 5     # default\n\
+             max_output_bytes = 536870912 # default\n\
+             nice = 0                 # default\n\
+             ionice = \"none\"          # default\n\
+             auto_approve = false     # default\n\
+             audit = true             # default\n\
+             gates = []               # default\n\
+             metrics_file = \"\"        # default\n\
+             \n\
+             [tracker]\n\
+             name = \"beads\"           # default\n\
+             sync_after_run = false   # default\n\
+             sync_command = \"bd sync\" # default\n\
+             repo = \"\"                # default\n\
+             label = \"ready\"          # default\n\
+             timeout_secs = 30        # default\n\
+             \n\
+             [env]\n\
+             policy = \"inherit\"       # default\n\
+             allow = []               # default\n\
+             block = []               # default\n\
+             \n\
+             [clean]\n\
+             older_than = \"30d\"       # default\n"
+        );
+        let parsed: Config = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.iterations, Some(4));
+    }
+}
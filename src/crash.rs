@@ -0,0 +1,215 @@
+use crate::build_info::BuildInfo;
+use crate::config;
+use std::backtrace::Backtrace;
+use std::fs;
+use std::io;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Exit code after a panic, once the crash report is written.
+pub const EXIT_CRASHED: u8 = 70;
+
+/// Options whose value is replaced by `***` in crash reports, matched as
+/// substrings of the option name.
+const SECRET_OPTIONS: &[&str] = &["token", "key", "secret", "password"];
+
+/// Arguments longer than this (a prompt, most likely) are cut short in
+/// crash reports.
+const MAX_ARG_CHARS: usize = 200;
+
+/// Sessions running, innermost (e.g. a loop started by `watch`) last.
+static SESSIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Note that session `id` is running, for crash reports.
+pub fn session_started(id: &str) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.push(id.to_string());
+    }
+}
+
+/// Note that session `id` has ended.
+pub fn session_ended(id: &str) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.retain(|s| s != id);
+    }
+}
+
+/// Install a panic hook that writes a crash report (see [`render`]) to
+/// `~/.Ralph/crashes/`, or the temporary directory when that isn't
+/// available, prints where it went and exits with [`EXIT_CRASHED`].
+/// `args` are the command line the report names.
+pub fn install(args: Vec<String>) {
+    std::panic::set_hook(Box::new(move |info| {
+        let message = panic_message(info);
+        let report = render(&message, &Backtrace::force_capture(), &args, session());
+        eprintln!("ralph crashed: {message}");
+        match write_report(&report) {
+            Ok(path) => eprintln!(
+                "A crash report was written to {}; please attach it to a bug report",
+                path.display()
+            ),
+            Err(e) => eprintln!("Failed to write a crash report ({e}):\n{report}"),
+        }
+        std::process::exit(i32::from(EXIT_CRASHED));
+    }));
+}
+
+/// The crash report for a panic with `message`: the backtrace, what was
+/// running, and the build.
+pub fn render(
+    message: &str,
+    backtrace: &Backtrace,
+    args: &[String],
+    session: Option<String>,
+) -> String {
+    let build = BuildInfo::current();
+    format!(
+        "ralph {} ({}), {}\n\
+         command: {}\n\
+         session: {}\n\
+         \n\
+         panic: {}\n\
+         \n\
+         backtrace:\n{}\n",
+        build.version,
+        build.short_commit(),
+        build.target,
+        sanitize_args(args).join(" "),
+        session.as_deref().unwrap_or("none"),
+        message,
+        backtrace
+    )
+}
+
+/// `args` with the values of secret-looking options masked and long
+/// arguments cut short.
+pub fn sanitize_args(args: &[String]) -> Vec<String> {
+    let is_secret = |option: &str| {
+        let option = option.to_ascii_lowercase();
+        option.starts_with('-') && SECRET_OPTIONS.iter().any(|s| option.contains(s))
+    };
+    let mut masked = Vec::with_capacity(args.len());
+    let mut mask_next = false;
+    for arg in args {
+        let arg = if mask_next {
+            "***".to_string()
+        } else if let Some((option, _)) = arg.split_once('=').filter(|(o, _)| is_secret(o)) {
+            format!("{option}=***")
+        } else if arg.chars().count() > MAX_ARG_CHARS {
+            let start: String = arg.chars().take(MAX_ARG_CHARS).collect();
+            format!("{start}... ({} bytes)", arg.len())
+        } else {
+            arg.clone()
+        };
+        mask_next = !mask_next && is_secret(&arg) && !arg.contains('=');
+        masked.push(arg);
+    }
+    masked
+}
+
+fn panic_message(info: &PanicHookInfo<'_>) -> String {
+    let payload = info.payload();
+    let text = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic payload".to_string());
+    match info.location() {
+        Some(location) => format!("{text} at {location}"),
+        None => text,
+    }
+}
+
+/// The innermost session running, if any.
+pub fn session() -> Option<String> {
+    SESSIONS
+        .lock()
+        .ok()
+        .and_then(|sessions| sessions.last().cloned())
+}
+
+/// Write `report` under the config directory's `crashes/`, or the
+/// temporary directory's `ralph-crashes/` when that fails.
+fn write_report(report: &str) -> io::Result<PathBuf> {
+    let name = format!("{}.txt", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+    config::get_config_dir()
+        .and_then(|dir| write_report_in(&dir.join("crashes"), &name, report))
+        .or_else(|_| write_report_in(&std::env::temp_dir().join("ralph-crashes"), &name, report))
+}
+
+fn write_report_in(dir: &Path, name: &str, report: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn secrets_and_long_arguments_are_left_out() {
+        let long = "x".repeat(300);
+        let args = strings(&[
+            "ralph",
+            "loop",
+            "--api-key",
+            "sk-123",
+            "--github-token=ghp_456",
+            "--iterations",
+            "5",
+            &long,
+        ]);
+        let sanitized = sanitize_args(&args);
+        assert_eq!(
+            sanitized[..7],
+            strings(&[
+                "ralph",
+                "loop",
+                "--api-key",
+                "***",
+                "--github-token=***",
+                "--iterations",
+                "5",
+            ])
+        );
+        assert!(
+            sanitized[7].ends_with("... (300 bytes)"),
+            "{}",
+            sanitized[7]
+        );
+    }
+
+    #[test]
+    fn reports_name_the_build_command_and_session() {
+        let report = render(
+            "boom at src/stream.rs:1:1",
+            &Backtrace::disabled(),
+            &strings(&["ralph", "once"]),
+            Some("20260101-000000-1".to_string()),
+        );
+        assert!(report.starts_with(&format!("ralph {} (", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains("\ncommand: ralph once\n"), "{report}");
+        assert!(
+            report.contains("\nsession: 20260101-000000-1\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("\npanic: boom at src/stream.rs:1:1\n"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn reports_go_to_the_directory_given() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_report_in(&dir.path().join("crashes"), "x.txt", "report").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "report");
+    }
+}
@@ -0,0 +1,188 @@
+use encoding_rs::Encoding;
+use std::sync::OnceLock;
+
+/// Decode bytes a child process wrote. Valid UTF-8 is taken as is. On
+/// Windows, anything else is decoded with the console's output code page,
+/// since providers started through `.cmd` shims can write in the console's
+/// OEM code page (CP850, CP936, ...) rather than UTF-8. Elsewhere, invalid
+/// bytes become U+FFFD.
+pub fn decode(bytes: &[u8]) -> String {
+    decode_with(bytes, console_code_page())
+}
+
+/// [`decode`] with `code_page` as the fallback for bytes that aren't
+/// UTF-8. A code page ralph can't decode, or none, falls back to lossy
+/// UTF-8.
+pub fn decode_with(bytes: &[u8], code_page: Option<u32>) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => return text.to_string(),
+        // Only cut off at the end, as an unfinished prompt read mid-character
+        // can be: still UTF-8.
+        Err(e) if e.error_len().is_none() => return String::from_utf8_lossy(bytes).into_owned(),
+        Err(_) => {}
+    }
+    match code_page.and_then(Decoder::for_code_page) {
+        Some(Decoder::Table(table)) => bytes
+            .iter()
+            .map(|&b| match b {
+                0..=0x7f => char::from(b),
+                _ => table
+                    .chars()
+                    .nth(usize::from(b - 0x80))
+                    .unwrap_or('\u{fffd}'),
+            })
+            .collect(),
+        Some(Decoder::Encoding(encoding)) => {
+            encoding.decode_without_bom_handling(bytes).0.into_owned()
+        }
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+enum Decoder {
+    /// The characters for bytes 0x80 to 0xFF of a single-byte code page
+    Table(&'static str),
+    Encoding(&'static Encoding),
+}
+
+impl Decoder {
+    fn for_code_page(code_page: u32) -> Option<Self> {
+        use encoding_rs::*;
+        let encoding = match code_page {
+            // The DOS code pages of US and Western European consoles, which
+            // encoding_rs doesn't cover.
+            437 => return Some(Self::Table(CP437)),
+            850 => return Some(Self::Table(CP850)),
+            866 => IBM866,
+            874 => WINDOWS_874,
+            932 => SHIFT_JIS,
+            936 => GBK,
+            949 => EUC_KR,
+            950 => BIG5,
+            1250 => WINDOWS_1250,
+            1251 => WINDOWS_1251,
+            1252 => WINDOWS_1252,
+            1253 => WINDOWS_1253,
+            1254 => WINDOWS_1254,
+            1255 => WINDOWS_1255,
+            1256 => WINDOWS_1256,
+            1257 => WINDOWS_1257,
+            1258 => WINDOWS_1258,
+            20866 => KOI8_R,
+            21866 => KOI8_U,
+            54936 => GB18030,
+            _ => return None,
+        };
+        Some(Self::Encoding(encoding))
+    }
+}
+
+const CP437: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+);
+
+const CP850: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜø£Ø×ƒ",
+    "áíóúñÑªº¿®¬½¼¡«»░▒▓│┤ÁÂÀ©╣║╗╝¢¥┐",
+    "└┴┬├─┼ãÃ╚╔╩╦╠═╬¤ðÐÊËÈıÍÎÏ┘┌█▄¦Ì▀",
+    "ÓßÔÒõÕµþÞÚÛÙýÝ¯´\u{ad}±‗¾¶§÷¸°¨·¹³²■\u{a0}",
+);
+
+/// The console's output code page, looked up once; `None` off Windows or
+/// without a console.
+fn console_code_page() -> Option<u32> {
+    static CODE_PAGE: OnceLock<Option<u32>> = OnceLock::new();
+    *CODE_PAGE.get_or_init(query_console_code_page)
+}
+
+#[cfg(windows)]
+fn query_console_code_page() -> Option<u32> {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetConsoleOutputCP() -> u32;
+    }
+    // SAFETY: GetConsoleOutputCP has no preconditions; it returns 0 when
+    // the process has no console.
+    let code_page = unsafe { GetConsoleOutputCP() };
+    (code_page != 0).then_some(code_page)
+}
+
+#[cfg(not(windows))]
+fn query_console_code_page() -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CP850_LINES: &[u8] = include_bytes!("../tests/fixtures/cp850.txt");
+    const CP936_LINES: &[u8] = include_bytes!("../tests/fixtures/cp936.txt");
+
+    #[test]
+    fn utf8_is_kept_whatever_the_code_page() {
+        for code_page in [None, Some(850), Some(936)] {
+            assert_eq!(
+                decode_with("tâche 任务".as_bytes(), code_page),
+                "tâche 任务"
+            );
+        }
+        // A character cut in half at the end is a short read, not CP850.
+        assert_eq!(decode_with(&"tâ".as_bytes()[..2], Some(850)), "t\u{fffd}");
+    }
+
+    #[test]
+    fn oem_code_pages_decode_to_utf8() {
+        assert_eq!(
+            decode_with(CP850_LINES, Some(850)),
+            "Compilación terminée, 3 tâches fermées\n\
+             Übersicht: alles grün <promise>COMPLETE</promise>\n"
+        );
+        assert_eq!(
+            decode_with(CP936_LINES, Some(936)),
+            "正在运行测试……\n任务已完成 <promise>COMPLETE</promise>\n"
+        );
+        assert_eq!(decode_with(&[0x9c, 0xe1], Some(437)), "£ß");
+    }
+
+    #[test]
+    fn unknown_code_pages_fall_back_to_lossy_utf8() {
+        for code_page in [None, Some(65001), Some(12345)] {
+            let text = decode_with(CP850_LINES, code_page);
+            assert!(text.starts_with("Compilaci\u{fffd}n"), "{code_page:?}");
+        }
+    }
+
+    #[test]
+    fn code_page_tables_cover_the_upper_half() {
+        assert_eq!(CP437.chars().count(), 128);
+        assert_eq!(CP850.chars().count(), 128);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn other_platforms_only_accept_utf8() {
+        assert!(decode(CP936_LINES).contains('\u{fffd}'));
+        assert_eq!(decode("任务 ✓".as_bytes()), "任务 ✓");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_output_decodes_with_the_console_code_page() {
+        let code_page = console_code_page();
+        assert_eq!(decode(CP936_LINES), decode_with(CP936_LINES, code_page));
+        assert_eq!(decode("任务 ✓".as_bytes()), "任务 ✓");
+    }
+
+    #[test]
+    fn the_completion_marker_survives_a_legacy_console() {
+        for (lines, code_page) in [(CP850_LINES, 850), (CP936_LINES, 936)] {
+            let text = decode_with(lines, Some(code_page));
+            let last = text.lines().last().unwrap();
+            assert!(last.ends_with(crate::COMPLETE_MARKER), "{last}");
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use crate::error::{RalphError, Result};
+use crate::providers;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Accepted values of `--env-policy` and `[env] policy`.
+pub const VALID_POLICIES: &[&str] = &["inherit", "allowlist", "blocklist"];
+
+/// Variables a provider keeps under every policy: without them it can't
+/// find programs, its own config or a temporary directory. Each provider's
+/// credentials ([`providers::auth_vars`]) are kept too.
+pub const ALWAYS_KEPT: &[&str] = &[
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "LANG",
+    "TERM",
+    // Windows can't start most programs without these.
+    "SYSTEMROOT",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "TEMP",
+    "TMP",
+    "PATHEXT",
+    "COMSPEC",
+];
+
+/// Which of ralph's environment variables providers inherit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvPolicy {
+    /// All of them
+    #[default]
+    Inherit,
+    /// Only [`ALWAYS_KEPT`], the provider's credentials and `[env] allow`
+    Allowlist,
+    /// All but `[env] block`
+    Blocklist,
+}
+
+impl EnvPolicy {
+    /// Parse one of [`VALID_POLICIES`].
+    pub fn parse(value: &str) -> Result<EnvPolicy> {
+        match value {
+            "inherit" => Ok(EnvPolicy::Inherit),
+            "allowlist" => Ok(EnvPolicy::Allowlist),
+            "blocklist" => Ok(EnvPolicy::Blocklist),
+            _ => Err(RalphError::InvalidInput(format!(
+                "Invalid env policy '{}'. Valid policies: {}",
+                value,
+                VALID_POLICIES.join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for EnvPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EnvPolicy::Inherit => "inherit",
+            EnvPolicy::Allowlist => "allowlist",
+            EnvPolicy::Blocklist => "blocklist",
+        })
+    }
+}
+
+/// The environment policy with its lists, and the variables set from the
+/// config. Names in the lists match exactly, or by prefix when they end in
+/// `*` (`AWS_*`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvFilter {
+    /// What to do with variables on neither list
+    pub policy: EnvPolicy,
+    /// Extra variables kept under [`EnvPolicy::Allowlist`]
+    pub allow: Vec<String>,
+    /// Variables removed under [`EnvPolicy::Blocklist`]
+    pub block: Vec<String>,
+    /// Variables set for every provider, as written in `[env]`
+    pub set: BTreeMap<String, String>,
+    /// Variables set for one provider, as written in `[providers.<name>.env]`;
+    /// they win over [`EnvFilter::set`]
+    pub provider_set: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl EnvFilter {
+    /// Whether `provider` gets the variable `name`.
+    pub fn keeps(&self, provider: &str, name: &str) -> bool {
+        let protected = || {
+            ALWAYS_KEPT
+                .iter()
+                .chain(providers::auth_vars(provider))
+                .any(|v| matches(v, name))
+        };
+        match self.policy {
+            EnvPolicy::Inherit => true,
+            EnvPolicy::Allowlist => protected() || self.allow.iter().any(|p| matches(p, name)),
+            EnvPolicy::Blocklist => protected() || !self.block.iter().any(|p| matches(p, name)),
+        }
+    }
+
+    /// The variables of `vars` that `provider` gets.
+    pub fn filter(
+        &self,
+        provider: &str,
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> Vec<(OsString, OsString)> {
+        vars.into_iter()
+            .filter(|(name, _)| self.keeps(provider, &name.to_string_lossy()))
+            .collect()
+    }
+
+    /// The variables the config sets for `provider`: its own over the
+    /// global ones, with each `env:NAME` value read from ralph's own
+    /// environment. A reference to a variable that isn't set is an error.
+    pub fn vars(&self, provider: &str) -> Result<BTreeMap<String, String>> {
+        let own = self.provider_set.get(provider);
+        let sections = [
+            ("[env]".to_string(), Some(&self.set)),
+            (format!("[providers.{provider}.env]"), own),
+        ];
+        let mut vars = BTreeMap::new();
+        for (section, set) in sections {
+            for (name, value) in set.into_iter().flatten() {
+                let value = match value.strip_prefix(REFERENCE_PREFIX) {
+                    Some(var) => std::env::var(var).map_err(|_| {
+                        RalphError::InvalidInput(format!(
+                            "{section} {name} = \"{value}\": {var} is not set"
+                        ))
+                    })?,
+                    None => value.clone(),
+                };
+                vars.insert(name.clone(), value);
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Start `cmd` (for `provider`) with only the variables it gets, from
+    /// ralph's own environment, plus those the config sets ([`vars`](Self::vars)).
+    pub fn apply(&self, cmd: &mut Command, provider: &str) -> Result<()> {
+        if self.policy != EnvPolicy::Inherit {
+            let vars = self.filter(provider, std::env::vars_os());
+            cmd.env_clear().envs(vars);
+        }
+        cmd.envs(self.vars(provider)?);
+        Ok(())
+    }
+
+    /// Sorted names (never values) of the variables `provider` would get.
+    pub fn names(&self, provider: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .filter(provider, std::env::vars_os())
+            .into_iter()
+            .map(|(name, _)| name.to_string_lossy().into_owned())
+            .chain(self.set.keys().cloned())
+            .chain(
+                self.provider_set
+                    .get(provider)
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, _)| name.clone()),
+            )
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// How a value in `[env]` or `[providers.<name>.env]` starts when it names
+/// a variable of ralph's own to read it from, e.g. `"env:OPENAI_KEY"`.
+pub const REFERENCE_PREFIX: &str = "env:";
+
+/// Whether `name` matches `pattern`: equal, or starting with what comes
+/// before a trailing `*`. Case-insensitive on Windows, like its variables.
+fn matches(pattern: &str, name: &str) -> bool {
+    let eq = |a: &str, b: &str| {
+        if cfg!(windows) {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
+        }
+    };
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name
+            .get(..prefix.len())
+            .is_some_and(|start| eq(start, prefix)),
+        None => eq(pattern, name),
+    }
+}
+
+static FILTER: OnceLock<EnvFilter> = OnceLock::new();
+
+/// Set the [`EnvFilter`] for providers started from now on. The first call
+/// wins; without one, providers inherit everything.
+pub fn set(filter: EnvFilter) {
+    let _ = FILTER.set(filter);
+}
+
+/// The filter set with [`set`].
+pub fn get() -> EnvFilter {
+    FILTER.get().cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(names: &[&str]) -> Vec<(OsString, OsString)> {
+        names
+            .iter()
+            .map(|n| (OsString::from(n), OsString::from("secret")))
+            .collect()
+    }
+
+    fn kept(filter: &EnvFilter, provider: &str, names: &[&str]) -> Vec<String> {
+        filter
+            .filter(provider, vars(names))
+            .into_iter()
+            .map(|(n, _)| n.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    const ENV: &[&str] = &[
+        "PATH",
+        "HOME",
+        "AWS_SECRET_ACCESS_KEY",
+        "AWS_REGION",
+        "DEPLOY_TOKEN",
+        "ANTHROPIC_API_KEY",
+        "FACTORY_API_KEY",
+        "EDITOR",
+    ];
+
+    #[test]
+    fn inherit_keeps_everything() {
+        assert_eq!(kept(&EnvFilter::default(), "claude", ENV), ENV);
+    }
+
+    #[test]
+    fn allowlists_keep_essentials_credentials_and_the_list() {
+        let filter = EnvFilter {
+            policy: EnvPolicy::Allowlist,
+            allow: vec!["EDITOR".to_string()],
+            block: vec!["EDITOR".to_string()],
+            ..EnvFilter::default()
+        };
+        assert_eq!(
+            kept(&filter, "claude", ENV),
+            ["PATH", "HOME", "ANTHROPIC_API_KEY", "EDITOR"]
+        );
+        // Only the running provider's credentials.
+        assert_eq!(
+            kept(&filter, "droid", ENV),
+            ["PATH", "HOME", "FACTORY_API_KEY", "EDITOR"]
+        );
+    }
+
+    #[test]
+    fn blocklists_remove_matches_but_never_essentials() {
+        let filter = EnvFilter {
+            policy: EnvPolicy::Blocklist,
+            block: vec![
+                "AWS_*".to_string(),
+                "DEPLOY_TOKEN".to_string(),
+                "PATH".to_string(),
+                "ANTHROPIC_API_KEY".to_string(),
+            ],
+            ..EnvFilter::default()
+        };
+        assert_eq!(
+            kept(&filter, "claude", ENV),
+            [
+                "PATH",
+                "HOME",
+                "ANTHROPIC_API_KEY",
+                "FACTORY_API_KEY",
+                "EDITOR"
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_match_whole_names_or_prefixes() {
+        assert!(matches("AWS_*", "AWS_REGION"));
+        assert!(matches("*", "ANYTHING"));
+        assert!(!matches("AWS_*", "AWS"));
+        assert!(!matches("AWS", "AWS_REGION"));
+        assert!(matches("DEPLOY_TOKEN", "DEPLOY_TOKEN"));
+    }
+
+    #[test]
+    fn apply_clears_the_environment_unless_inheriting() {
+        let mut cmd = Command::new("true");
+        EnvFilter::default().apply(&mut cmd, "claude").unwrap();
+        assert_eq!(cmd.get_envs().count(), 0);
+
+        let filter = EnvFilter {
+            policy: EnvPolicy::Allowlist,
+            ..EnvFilter::default()
+        };
+        let mut cmd = Command::new("true");
+        filter.apply(&mut cmd, "claude").unwrap();
+        assert!(cmd.get_envs().any(|(k, _)| k == "PATH"));
+        assert!(
+            cmd.get_envs()
+                .all(|(k, _)| filter.keeps("claude", &k.to_string_lossy()))
+        );
+    }
+
+    #[test]
+    fn configured_variables_are_set_with_the_providers_own_winning() {
+        let table = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let filter = EnvFilter {
+            policy: EnvPolicy::Allowlist,
+            set: table(&[("OPENAI_BASE_URL", "https://proxy"), ("HTTP_PROXY", "none")]),
+            provider_set: BTreeMap::from([(
+                "codex".to_string(),
+                table(&[("HTTP_PROXY", "http://codex"), ("REF_PATH", "env:PATH")]),
+            )]),
+            ..EnvFilter::default()
+        };
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(
+            filter.vars("codex").unwrap(),
+            table(&[
+                ("HTTP_PROXY", "http://codex"),
+                ("OPENAI_BASE_URL", "https://proxy"),
+                ("REF_PATH", &path),
+            ])
+        );
+        assert_eq!(filter.vars("claude").unwrap(), filter.set);
+
+        // Set even where the policy would have dropped them.
+        let mut cmd = Command::new("true");
+        filter.apply(&mut cmd, "codex").unwrap();
+        let value = |name: &str| {
+            cmd.get_envs()
+                .find(|(k, _)| *k == name)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_string_lossy().into_owned())
+        };
+        assert_eq!(value("OPENAI_BASE_URL").as_deref(), Some("https://proxy"));
+        assert_eq!(value("REF_PATH"), Some(path));
+        assert!(filter.names("codex").contains(&"REF_PATH".to_string()));
+
+        let filter = EnvFilter {
+            provider_set: BTreeMap::from([(
+                "claude".to_string(),
+                table(&[("ANTHROPIC_API_KEY", "env:RALPH_TEST_UNSET_SECRET")]),
+            )]),
+            ..EnvFilter::default()
+        };
+        assert_eq!(
+            filter.vars("claude").unwrap_err().to_string(),
+            "[providers.claude.env] ANTHROPIC_API_KEY = \"env:RALPH_TEST_UNSET_SECRET\": \
+             RALPH_TEST_UNSET_SECRET is not set"
+        );
+        assert!(filter.apply(&mut Command::new("true"), "claude").is_err());
+    }
+
+    #[test]
+    fn policies_parse_and_display() {
+        for name in VALID_POLICIES {
+            assert_eq!(EnvPolicy::parse(name).unwrap().to_string(), *name);
+        }
+        assert_eq!(
+            EnvPolicy::parse("deny").unwrap_err().to_string(),
+            "Invalid env policy 'deny'. Valid policies: inherit, allowlist, blocklist"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::beads::Issue;
+use crate::error::{RalphError, Result};
+use crate::exec::CommandRunner;
+use crate::tracker::Tracker;
+
+/// An epic a loop is scoped to with `--epic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Epic {
+    /// Issue id, e.g. `payments-v2`
+    pub id: String,
+    /// One-line summary
+    pub title: String,
+}
+
+/// How far along an epic is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Children that are closed
+    pub closed: usize,
+    /// All children
+    pub total: usize,
+}
+
+impl Progress {
+    /// Count `epic`'s closed children.
+    pub fn of(epic: &Issue) -> Self {
+        let (closed, total) = epic.children().fold((0, 0), |(closed, total), child| {
+            let done = child.status.as_deref() == Some("closed");
+            (closed + usize::from(done), total + 1)
+        });
+        Progress { closed, total }
+    }
+}
+
+/// Look up `id` in the tracker. Fails, before anything runs, when the
+/// tracker doesn't know it.
+pub fn find(exec: &dyn CommandRunner, tracker: &dyn Tracker, id: &str) -> Result<Epic> {
+    let issue = tracker
+        .show(exec, id)
+        .map_err(|e| RalphError::InvalidInput(format!("Unknown epic '{id}': {e}")))?;
+    Ok(Epic {
+        id: issue.id,
+        title: issue.title,
+    })
+}
+
+/// Append the instruction restricting the agent to `epic`'s tasks.
+pub fn scope_prompt(system_prompt: &str, epic: &Epic) -> String {
+    let mut prompt = system_prompt.trim_end().to_string();
+    prompt.push_str("\n\n## Scope\n\n");
+    prompt.push_str(&format!(
+        "Only work on tasks under the epic {} — {}. Skip ready tasks that aren't its children, \
+         and consider the work done when none of its tasks are left.\n",
+        epic.id, epic.title
+    ));
+    prompt
+}
+
+/// Ready tasks under `epic`: the tracker's ready list, filtered.
+pub fn ready(exec: &dyn CommandRunner, tracker: &dyn Tracker, epic: &Epic) -> Result<Vec<Issue>> {
+    Ok(tracker
+        .ready(exec)?
+        .into_iter()
+        .filter(|issue| issue.is_child_of(&epic.id))
+        .collect())
+}
+
+/// Closed and total children of `epic`, re-read from the tracker.
+pub fn progress(exec: &dyn CommandRunner, tracker: &dyn Tracker, epic: &Epic) -> Result<Progress> {
+    tracker
+        .show(exec, &epic.id)
+        .map(|issue| Progress::of(&issue))
+}
+
+/// The end-of-loop summary line for `epic`.
+pub fn summary_line(epic: &Epic, progress: Progress, ready: usize) -> String {
+    format!(
+        "Epic {} ({}): {} of {} tasks closed, {} ready",
+        epic.id, epic.title, progress.closed, progress.total, ready
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::ScriptedRunner;
+    use crate::tracker::Beads;
+
+    const EPIC: &str = r#"{"id":"pay-1","title":"Payments v2","issue_type":"epic","dependents":[
+        {"id":"pay-2","status":"closed","dependency_type":"parent-child"},
+        {"id":"pay-3","status":"open","dependency_type":"parent-child"},
+        {"id":"pay-4","status":"open","dependency_type":"parent-child"},
+        {"id":"ops-9","status":"open","dependency_type":"blocks"}
+    ]}"#;
+
+    fn epic() -> Epic {
+        Epic {
+            id: "pay-1".to_string(),
+            title: "Payments v2".to_string(),
+        }
+    }
+
+    #[test]
+    fn unknown_epics_fail() {
+        let exec = ScriptedRunner::new().fail(1, "Error: issue nope not found");
+        let err = find(&exec, &Beads, "nope").unwrap_err().to_string();
+        assert!(
+            err.starts_with("Unknown epic 'nope': bd show nope --json failed"),
+            "{err}"
+        );
+
+        let exec = ScriptedRunner::new().reply(0, EPIC);
+        assert_eq!(find(&exec, &Beads, "pay-1").unwrap(), epic());
+    }
+
+    #[test]
+    fn ready_keeps_only_the_epics_children() {
+        let exec = ScriptedRunner::new().reply(
+            0,
+            r#"[{"id":"pay-3","dependencies":[{"id":"pay-1","dependency_type":"parent-child"}]},
+                {"id":"ops-2","dependencies":[{"id":"ops-1","dependency_type":"parent-child"}]},
+                {"id":"misc"}]"#,
+        );
+        let ids: Vec<String> = ready(&exec, &Beads, &epic())
+            .unwrap()
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, ["pay-3"]);
+    }
+
+    #[test]
+    fn progress_counts_closed_children() {
+        let exec = ScriptedRunner::new().reply(0, EPIC);
+        let progress = progress(&exec, &Beads, &epic()).unwrap();
+        assert_eq!(
+            progress,
+            Progress {
+                closed: 1,
+                total: 3
+            }
+        );
+        assert_eq!(
+            summary_line(&epic(), progress, 2),
+            "Epic pay-1 (Payments v2): 1 of 3 tasks closed, 2 ready"
+        );
+    }
+
+    #[test]
+    fn scope_is_appended_to_the_prompt() {
+        let prompt = scope_prompt("Do the work.\n\n", &epic());
+        assert!(prompt.starts_with(
+            "Do the work.\n\n## Scope\n\nOnly work on tasks under the epic pay-1 — Payments v2."
+        ));
+    }
+}
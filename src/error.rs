@@ -0,0 +1,403 @@
+use crate::exec;
+use crate::stream;
+use crate::upgrade::{self, UpgradeError};
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can make a ralph command fail. The binary prints the
+/// `Display` text after `Error: ` and exits with [`RalphError::exit_code`].
+#[derive(Debug, thiserror::Error)]
+pub enum RalphError {
+    /// A config file exists but can't be parsed
+    #[error("Invalid config file {}: {message}", path.display())]
+    Config {
+        /// The offending `config.toml`
+        path: PathBuf,
+        /// What the TOML parser complained about
+        message: String,
+    },
+    /// A command-line argument or config value was rejected
+    #[error("{0}")]
+    InvalidInput(String),
+    /// The system prompt file couldn't be read
+    #[error("Failed to read system prompt {}: {source}", path.display())]
+    Prompt {
+        /// The prompt file
+        path: PathBuf,
+        /// Why reading it failed
+        source: io::Error,
+    },
+    /// The config directory or default system prompt couldn't be created
+    #[error(
+        "Failed to initialize configuration at {}: {source}\nFix its permissions or free up space, or set RALPH_HOME to use another directory",
+        path.display()
+    )]
+    ConfigInit {
+        /// The directory or file that couldn't be created
+        path: PathBuf,
+        /// Why creating it failed
+        source: io::Error,
+    },
+    /// The provider CLI couldn't be started or its output couldn't be read
+    #[error("Failed to execute provider '{provider}': {source}")]
+    ProviderSpawn {
+        /// Provider name
+        provider: String,
+        /// The underlying spawn or pipe error
+        source: io::Error,
+    },
+    /// The provider CLI isn't installed (not on PATH)
+    #[error("Provider '{provider}' not found: `{program}` is not on PATH; {hint}")]
+    ProviderNotFound {
+        /// Provider name
+        provider: String,
+        /// The program looked for
+        program: String,
+        /// What to do about it, e.g. "install it with `npm install -g ...`"
+        hint: String,
+    },
+    /// The provider ran but exited with a non-zero code
+    #[error("Provider '{provider}' exited with code {code}")]
+    ProviderFailed {
+        /// Provider name
+        provider: String,
+        /// Its exit code, passed through as ralph's own
+        code: i32,
+    },
+    /// The provider stopped to wait for an interactive login and was
+    /// terminated
+    #[error(
+        "Provider '{provider}' is waiting for an interactive login (\"{prompt}\"); {hint}, then try again"
+    )]
+    ProviderLogin {
+        /// Provider name
+        provider: String,
+        /// What it printed
+        prompt: String,
+        /// How to log in, e.g. "run `codex login`"
+        hint: String,
+    },
+    /// The provider was killed by a signal
+    #[error("Provider '{provider}' {}", exec::describe_signal(*signal))]
+    ProviderKilled {
+        /// Provider name
+        provider: String,
+        /// The signal, e.g. 9 for SIGKILL
+        signal: i32,
+    },
+    /// The provider printed more than the output limit and was killed
+    #[error(
+        "Provider '{provider}' printed more than the {limit}-byte output limit and was killed; raise it with --max-output-bytes if that much output is expected"
+    )]
+    ProviderOutputLimit {
+        /// Provider name
+        provider: String,
+        /// The limit, in bytes
+        limit: u64,
+    },
+    /// The provider ran longer than `--timeout` and was killed
+    #[error(
+        "Provider '{provider}' ran for longer than the {secs}s timeout and was killed; raise it with --timeout if it needs longer"
+    )]
+    ProviderTimeout {
+        /// Provider name
+        provider: String,
+        /// The timeout, in seconds
+        secs: u64,
+    },
+    /// The provider CLI doesn't match its pin in `[provider_versions]`
+    #[error(
+        "Provider '{provider}' is {found}, but the {origin} pins it to {required}; install a matching release, or pass --allow-version-drift to run anyway"
+    )]
+    ProviderVersion {
+        /// Provider name
+        provider: String,
+        /// What was found instead, e.g. "at version 2.0.5"
+        found: String,
+        /// The requirement, e.g. `>=0.45`
+        required: String,
+        /// The config layer the pin came from, e.g. "project config"
+        origin: String,
+    },
+    /// The task tracker (bd) failed or printed something unexpected
+    #[error("{command} failed: {message}")]
+    Tracker {
+        /// The command line, e.g. `bd show bd-1 --json`
+        command: String,
+        /// bd's stderr, or what couldn't be parsed
+        message: String,
+    },
+    /// A task tracker command ran longer than `[tracker] timeout_secs` and
+    /// was killed
+    #[error(
+        "{command} timed out after {secs}s (raise `timeout_secs` under [tracker] if it's just slow)"
+    )]
+    TrackerTimeout {
+        /// The command line, e.g. `bd list --status open --json`
+        command: String,
+        /// How long it was given
+        secs: u64,
+    },
+    /// The task tracker isn't installed or set up in this directory; the
+    /// message says how to fix that
+    #[error("{0}")]
+    TrackerUnavailable(String),
+    /// The task tracker is installed but was never set up in this
+    /// directory (no beads database)
+    #[error("{0}")]
+    TrackerNotInitialized(String),
+    /// Another ralph holds this repository's lock (`.ralph/lock`)
+    #[error(
+        "Another ralph is running in this repository ({}); wait for it to finish, or pass --force-lock if it isn't running",
+        holder.clone().unwrap_or_else(|| format!("{} is held", path.display()))
+    )]
+    Locked {
+        /// The lock file
+        path: PathBuf,
+        /// Who holds it, when the lock file says
+        holder: Option<String>,
+    },
+    /// `loop --sandbox` couldn't set up its copy of the repository
+    #[error("Failed to create a sandbox: {0}")]
+    Sandbox(String),
+    /// `ralph audit verify` found the audit log's hash chain broken
+    #[error("The audit log failed verification: {0}")]
+    Audit(String),
+    /// Replacing or removing the installed binary isn't permitted
+    #[error("{}", upgrade::permission_denied_suggestions(path, action))]
+    PermissionDenied {
+        /// The binary or directory that couldn't be written
+        path: PathBuf,
+        /// `upgrade` or `uninstall`, used in the suggested commands
+        action: &'static str,
+    },
+    /// `ralph upgrade` or `ralph uninstall` failed
+    #[error(transparent)]
+    Upgrade(UpgradeError),
+    /// `ralph check-update` couldn't determine the latest release
+    #[error("Failed to check for updates: {0}")]
+    UpdateCheck(UpgradeError),
+    /// Any other I/O failure
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// `Result` with [`RalphError`] as the error type.
+pub type Result<T, E = RalphError> = std::result::Result<T, E>;
+
+impl RalphError {
+    /// Process exit code for this error: the provider's own code for
+    /// [`RalphError::ProviderFailed`], 128 plus the signal for
+    /// [`RalphError::ProviderKilled`] (as a shell reports it), 3 for
+    /// [`RalphError::ProviderOutputLimit`], 124 for
+    /// [`RalphError::ProviderTimeout`] (as `timeout(1)` reports it), 20 for
+    /// a failed update check, and 1 for everything else.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RalphError::ProviderFailed { code, .. } => {
+                u8::try_from(*code).ok().filter(|c| *c != 0).unwrap_or(1)
+            }
+            RalphError::ProviderKilled { signal, .. } => u8::try_from(128 + *signal).unwrap_or(1),
+            RalphError::ProviderOutputLimit { .. } => stream::EXIT_OUTPUT_LIMIT,
+            RalphError::ProviderTimeout { .. } => stream::EXIT_TIMEOUT,
+            RalphError::UpdateCheck(_) => upgrade::EXIT_CHECK_FAILED,
+            _ => 1,
+        }
+    }
+
+    /// Wrap an upgrade failure, turning permission problems into
+    /// [`RalphError::PermissionDenied`] so the suggestions name `action`.
+    pub fn upgrade(action: &'static str, err: UpgradeError) -> Self {
+        match err {
+            UpgradeError::PermissionDenied { path } => {
+                RalphError::PermissionDenied { path, action }
+            }
+            other => RalphError::Upgrade(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(err: RalphError) -> String {
+        err.to_string()
+    }
+
+    #[test]
+    fn config_and_input_messages() {
+        assert_eq!(
+            display(RalphError::Config {
+                path: PathBuf::from("/home/u/.Ralph/config.toml"),
+                message: "expected a value".to_string(),
+            }),
+            "Invalid config file /home/u/.Ralph/config.toml: expected a value"
+        );
+        assert_eq!(
+            display(RalphError::InvalidInput(
+                "iterations must be a positive integer".to_string()
+            )),
+            "iterations must be a positive integer"
+        );
+    }
+
+    #[test]
+    fn prompt_and_provider_messages() {
+        assert_eq!(
+            display(RalphError::Prompt {
+                path: PathBuf::from("/p/system-prompt.md"),
+                source: io::Error::new(io::ErrorKind::NotFound, "not found"),
+            }),
+            "Failed to read system prompt /p/system-prompt.md: not found"
+        );
+        assert_eq!(
+            display(RalphError::ProviderLogin {
+                provider: "codex".to_string(),
+                prompt: "Press Enter to sign in".to_string(),
+                hint: "run `codex login`".to_string(),
+            }),
+            "Provider 'codex' is waiting for an interactive login (\"Press Enter to sign in\"); \
+             run `codex login`, then try again"
+        );
+        assert_eq!(
+            display(RalphError::ConfigInit {
+                path: PathBuf::from("/home/u/.Ralph"),
+                source: io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"),
+            }),
+            "Failed to initialize configuration at /home/u/.Ralph: permission denied\n\
+             Fix its permissions or free up space, or set RALPH_HOME to use another directory"
+        );
+        assert_eq!(
+            display(RalphError::ProviderSpawn {
+                provider: "claude".to_string(),
+                source: io::Error::new(io::ErrorKind::NotFound, "No such file or directory"),
+            }),
+            "Failed to execute provider 'claude': No such file or directory"
+        );
+        assert_eq!(
+            display(RalphError::ProviderNotFound {
+                provider: "codex".to_string(),
+                program: "codex".to_string(),
+                hint: "install it with `npm install -g @openai/codex`".to_string(),
+            }),
+            "Provider 'codex' not found: `codex` is not on PATH; \
+             install it with `npm install -g @openai/codex`"
+        );
+        assert_eq!(
+            display(RalphError::ProviderFailed {
+                provider: "codex".to_string(),
+                code: 3,
+            }),
+            "Provider 'codex' exited with code 3"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            display(RalphError::ProviderKilled {
+                provider: "claude".to_string(),
+                signal: libc::SIGKILL,
+            }),
+            "Provider 'claude' terminated by SIGKILL (possibly out of memory)"
+        );
+    }
+
+    #[test]
+    fn tracker_upgrade_and_io_messages() {
+        assert_eq!(
+            display(RalphError::Tracker {
+                command: "bd show bd-1 --json".to_string(),
+                message: "no issue found".to_string(),
+            }),
+            "bd show bd-1 --json failed: no issue found"
+        );
+        assert_eq!(
+            display(RalphError::TrackerTimeout {
+                command: "bd list --pretty".to_string(),
+                secs: 30,
+            }),
+            "bd list --pretty timed out after 30s (raise `timeout_secs` under [tracker] if it's just slow)"
+        );
+        let denied = display(RalphError::PermissionDenied {
+            path: PathBuf::from("/usr/local/bin/ralph"),
+            action: "uninstall",
+        });
+        assert!(denied.starts_with("Cannot write to /usr/local/bin/ralph (permission denied)"));
+        assert!(denied.contains("sudo ralph uninstall"));
+        assert_eq!(
+            display(RalphError::Upgrade(UpgradeError::ChecksumParse)),
+            "Failed to parse checksum file"
+        );
+        assert_eq!(
+            display(RalphError::UpdateCheck(UpgradeError::Network(
+                "timed out".to_string()
+            ))),
+            "Failed to check for updates: Network error: timed out"
+        );
+        assert_eq!(
+            display(RalphError::Io(io::Error::other("disk full"))),
+            "disk full"
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let failed = |code| RalphError::ProviderFailed {
+            provider: "droid".to_string(),
+            code,
+        };
+        assert_eq!(failed(3).exit_code(), 3);
+        assert_eq!(failed(-1).exit_code(), 1);
+        assert_eq!(failed(300).exit_code(), 1);
+        let killed = RalphError::ProviderKilled {
+            provider: "droid".to_string(),
+            signal: 9,
+        };
+        assert_eq!(killed.exit_code(), 137);
+        let over_limit = RalphError::ProviderOutputLimit {
+            provider: "droid".to_string(),
+            limit: 1024,
+        };
+        assert_eq!(over_limit.exit_code(), 3);
+        assert_eq!(
+            display(over_limit),
+            "Provider 'droid' printed more than the 1024-byte output limit and was killed; \
+             raise it with --max-output-bytes if that much output is expected"
+        );
+        let timed_out = RalphError::ProviderTimeout {
+            provider: "claude".to_string(),
+            secs: 600,
+        };
+        assert_eq!(timed_out.exit_code(), 124);
+        assert_eq!(
+            display(timed_out),
+            "Provider 'claude' ran for longer than the 600s timeout and was killed; \
+             raise it with --timeout if it needs longer"
+        );
+        assert_eq!(
+            RalphError::UpdateCheck(UpgradeError::ChecksumParse).exit_code(),
+            20
+        );
+        assert_eq!(RalphError::InvalidInput(String::new()).exit_code(), 1);
+    }
+
+    #[test]
+    fn permission_denied_upgrades_are_split_out() {
+        let err = RalphError::upgrade(
+            "upgrade",
+            UpgradeError::PermissionDenied {
+                path: PathBuf::from("/bin/ralph"),
+            },
+        );
+        assert!(matches!(
+            err,
+            RalphError::PermissionDenied {
+                action: "upgrade",
+                ..
+            }
+        ));
+        assert!(matches!(
+            RalphError::upgrade("upgrade", UpgradeError::ChecksumParse),
+            RalphError::Upgrade(_)
+        ));
+    }
+}
@@ -0,0 +1,526 @@
+use crate::encoding;
+use crate::session::StderrLog;
+use crate::stream::{self, LoginWatch, ProviderRun};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Exit code and captured output of a command run to completion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    /// Exit code (1 when killed by a signal)
+    pub code: i32,
+    /// The signal that killed the command, if one did (unix)
+    pub signal: Option<i32>,
+    /// Everything written to stdout
+    pub stdout: String,
+    /// Everything written to stderr
+    pub stderr: String,
+    /// What the command printed when it stopped to wait for an interactive
+    /// login, after which it was terminated (see [`stream::LoginWatch`])
+    pub login_prompt: Option<String>,
+    /// How many lines were too long to keep whole (see
+    /// [`stream::LineLimit`]); only their heads are in `stdout`/`stderr`
+    pub long_lines: usize,
+    /// The limit in bytes the command's output crossed, after which it was
+    /// killed (see [`stream::set_max_output`]); the captured output ends
+    /// there
+    pub output_limit: Option<u64>,
+    /// How long the command was allowed to run before it was killed for
+    /// running longer (see [`stream::set_timeout`])
+    pub timed_out: Option<Duration>,
+}
+
+impl Output {
+    /// Whether the command exited with code 0, without being stopped for
+    /// printing too much or running too long first.
+    pub fn success(&self) -> bool {
+        self.code == 0
+            && self.signal.is_none()
+            && self.output_limit.is_none()
+            && self.timed_out.is_none()
+    }
+
+    /// How the command ended, when that wasn't an ordinary exit:
+    /// "terminated by SIGKILL (possibly out of memory)", "printed more
+    /// than the 1024-byte output limit and was killed", or "ran for longer
+    /// than the 600s timeout and was killed".
+    pub fn termination(&self) -> Option<String> {
+        if let Some(timeout) = self.timed_out {
+            return Some(format!(
+                "ran for longer than the {}s timeout and was killed",
+                timeout.as_secs()
+            ));
+        }
+        if let Some(limit) = self.output_limit {
+            return Some(format!(
+                "printed more than the {limit}-byte output limit and was killed"
+            ));
+        }
+        self.signal.map(describe_signal)
+    }
+
+    /// The last line the command wrote to stderr that isn't blank, which
+    /// for a provider is usually what went wrong: an auth failure or a
+    /// rate-limit notice.
+    pub fn stderr_tail(&self) -> Option<&str> {
+        self.stderr
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+    }
+}
+
+/// The exit code of `status` (1 when killed by a signal) and the signal
+/// that killed it, if any.
+pub fn exit_status(status: ExitStatus) -> (i32, Option<i32>) {
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+    #[cfg(not(unix))]
+    let signal = None;
+    (status.code().unwrap_or(1), signal)
+}
+
+/// Conventional name of `signal`, e.g. `SIGKILL` for 9.
+pub fn signal_name(signal: i32) -> String {
+    #[cfg(unix)]
+    let name = match signal {
+        libc::SIGHUP => Some("SIGHUP"),
+        libc::SIGINT => Some("SIGINT"),
+        libc::SIGQUIT => Some("SIGQUIT"),
+        libc::SIGILL => Some("SIGILL"),
+        libc::SIGABRT => Some("SIGABRT"),
+        libc::SIGBUS => Some("SIGBUS"),
+        libc::SIGFPE => Some("SIGFPE"),
+        libc::SIGKILL => Some("SIGKILL"),
+        libc::SIGSEGV => Some("SIGSEGV"),
+        libc::SIGPIPE => Some("SIGPIPE"),
+        libc::SIGALRM => Some("SIGALRM"),
+        libc::SIGTERM => Some("SIGTERM"),
+        libc::SIGUSR1 => Some("SIGUSR1"),
+        libc::SIGUSR2 => Some("SIGUSR2"),
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    let name: Option<&str> = None;
+    name.map_or_else(|| format!("signal {signal}"), str::to_string)
+}
+
+/// "terminated by SIGKILL (possibly out of memory)": the kernel's OOM
+/// killer is the usual source of a SIGKILL nobody asked for.
+pub fn describe_signal(signal: i32) -> String {
+    let name = signal_name(signal);
+    if name == "SIGKILL" {
+        format!("terminated by {name} (possibly out of memory)")
+    } else {
+        format!("terminated by {name}")
+    }
+}
+
+/// A shareable flag for stopping a run from another thread (or a signal
+/// handler). Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask whatever is watching the token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`cancel`](CancelToken::cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs external commands (providers, bd, the installed binary) on ralph's
+/// behalf. [`SystemRunner`] spawns real processes; [`ScriptedRunner`]
+/// replays canned results so the logic around them can be tested without
+/// the real binaries.
+pub trait CommandRunner {
+    /// Run `cmd` to completion, capturing stdout and stderr.
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+
+    /// Like [`output`](CommandRunner::output), but kill `cmd` and fail with
+    /// [`io::ErrorKind::TimedOut`] if it runs longer than `timeout`.
+    fn output_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<Output> {
+        let _ = timeout;
+        self.output(cmd)
+    }
+
+    /// Run `cmd` (whose stdout must be piped) printing stdout as it arrives
+    /// and copying it to `log` when given, terminating it if `cancel` is
+    /// cancelled or `login` sees it waiting for a login. Stderr is passed
+    /// through, and captured next to `log` (see [`StderrLog`]). Returns how
+    /// it exited and the captured output.
+    fn stream(
+        &self,
+        cmd: &mut Command,
+        log: Option<&Path>,
+        cancel: &CancelToken,
+        login: Option<&LoginWatch>,
+    ) -> io::Result<Output>;
+
+    /// Run `cmd` with the terminal's stdio and return its exit code.
+    fn status(&self, cmd: &mut Command) -> io::Result<i32>;
+
+    /// Like [`status`](CommandRunner::status), but kill `cmd` and fail with
+    /// [`io::ErrorKind::TimedOut`] if it runs longer than `timeout`.
+    fn status_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<i32> {
+        let _ = timeout;
+        self.status(cmd)
+    }
+}
+
+/// Spawns real processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let output = cmd.output()?;
+        let (code, signal) = exit_status(output.status);
+        Ok(Output {
+            code,
+            signal,
+            stdout: encoding::decode(&output.stdout),
+            stderr: encoding::decode(&output.stderr),
+            ..Output::default()
+        })
+    }
+
+    fn output_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<Output> {
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = read_to_end(child.stdout.take());
+        let stderr = read_to_end(child.stderr.take());
+        let (code, signal) = exit_status(wait_timeout(&mut child, timeout)?);
+        Ok(Output {
+            code,
+            signal,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+            ..Output::default()
+        })
+    }
+
+    fn stream(
+        &self,
+        cmd: &mut Command,
+        log: Option<&Path>,
+        cancel: &CancelToken,
+        login: Option<&LoginWatch>,
+    ) -> io::Result<Output> {
+        stream::stream_run(ProviderRun::spawn(cmd)?, log, cancel, login)
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<i32> {
+        Ok(cmd.status()?.code().unwrap_or(1))
+    }
+
+    fn status_timeout(&self, cmd: &mut Command, timeout: Duration) -> io::Result<i32> {
+        let mut child = cmd.spawn()?;
+        Ok(wait_timeout(&mut child, timeout)?.code().unwrap_or(1))
+    }
+}
+
+/// Wait for `child`, killing it and failing with
+/// [`io::ErrorKind::TimedOut`] once it has run for `timeout`.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {}s", timeout.as_secs_f32()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Drain `pipe` on a thread, so a chatty child can't block on a full pipe
+/// while we wait for it.
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        encoding::decode(&bytes)
+    })
+}
+
+/// A fake [`CommandRunner`] that answers each call with the next scripted
+/// reply, in order, and remembers the command lines it was asked to run.
+/// Running out of replies is an error, like a missing binary.
+#[derive(Debug, Default)]
+pub struct ScriptedRunner {
+    replies: Mutex<VecDeque<io::Result<Output>>>,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl ScriptedRunner {
+    /// A runner with no replies queued.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a reply with exit code `code` and `stdout`.
+    pub fn reply(self, code: i32, stdout: &str) -> Self {
+        self.push(Ok(Output {
+            code,
+            stdout: stdout.to_string(),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a failing reply with exit code `code` and `stderr`.
+    pub fn fail(self, code: i32, stderr: &str) -> Self {
+        self.push(Ok(Output {
+            code,
+            stderr: stderr.to_string(),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a reply killed by `signal` after printing `stdout`.
+    pub fn killed(self, signal: i32, stdout: &str) -> Self {
+        self.push(Ok(Output {
+            code: 1,
+            signal: Some(signal),
+            stdout: stdout.to_string(),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a reply killed for printing more than `limit` bytes, of
+    /// which `stdout` is what was kept.
+    pub fn over_limit(self, limit: u64, stdout: &str) -> Self {
+        self.push(Ok(Output {
+            code: 1,
+            signal: Some(9),
+            stdout: stdout.to_string(),
+            output_limit: Some(limit),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a reply killed for running longer than `timeout`, after
+    /// printing `stdout`.
+    pub fn timed_out(self, timeout: Duration, stdout: &str) -> Self {
+        self.push(Ok(Output {
+            code: 1,
+            signal: Some(9),
+            stdout: stdout.to_string(),
+            timed_out: Some(timeout),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a reply that stopped to wait for a login after printing
+    /// `prompt`, and was terminated for it.
+    pub fn login_prompt(self, prompt: &str) -> Self {
+        self.push(Ok(Output {
+            code: 1,
+            stderr: format!("{prompt}\n"),
+            login_prompt: Some(prompt.to_string()),
+            ..Output::default()
+        }))
+    }
+
+    /// Queue a spawn failure, as if the program couldn't be started.
+    pub fn spawn_error(self, kind: io::ErrorKind) -> Self {
+        self.push(Err(io::Error::new(kind, "scripted spawn failure")))
+    }
+
+    fn push(self, reply: io::Result<Output>) -> Self {
+        self.replies.lock().unwrap().push_back(reply);
+        self
+    }
+
+    /// Command lines run so far, program first.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn next(&self, cmd: &Command) -> io::Result<Output> {
+        let line: Vec<String> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect();
+        let reply = self.replies.lock().unwrap().pop_front();
+        let reply = reply.unwrap_or_else(|| {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no scripted reply for `{}`", line.join(" ")),
+            ))
+        });
+        self.calls.lock().unwrap().push(line);
+        reply
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        self.next(cmd)
+    }
+
+    fn stream(
+        &self,
+        cmd: &mut Command,
+        log: Option<&Path>,
+        _cancel: &CancelToken,
+        _login: Option<&LoginWatch>,
+    ) -> io::Result<Output> {
+        let output = self.next(cmd)?;
+        if let Some(path) = log {
+            fs::write(path, &output.stdout)?;
+            let mut stderr_log = StderrLog::new(path);
+            for line in output.stderr.lines() {
+                stderr_log.write_line(line)?;
+            }
+        }
+        Ok(output)
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<i32> {
+        self.next(cmd).map(|o| o.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_runner_replays_in_order_and_records_calls() {
+        let runner = ScriptedRunner::new().reply(0, "one").fail(3, "bad");
+        let first = runner.output(Command::new("bd").arg("ready")).unwrap();
+        assert_eq!(first.stdout, "one");
+        assert!(first.success());
+        assert_eq!(runner.status(&mut Command::new("bd")).unwrap(), 3);
+
+        let err = runner.output(&mut Command::new("git")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("`git`"));
+
+        assert_eq!(
+            runner.calls(),
+            [vec!["bd", "ready"], vec!["bd"], vec!["git"]]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_timeout_kills_slow_commands() {
+        let output = SystemRunner
+            .output_timeout(
+                Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]),
+                Duration::from_secs(10),
+            )
+            .unwrap();
+        assert_eq!(
+            (output.code, output.stdout.as_str(), output.stderr.as_str()),
+            (3, "out\n", "err\n")
+        );
+
+        let started = Instant::now();
+        let err = SystemRunner
+            .output_timeout(Command::new("sleep").arg("10"), Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "timed out after 0.1s");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn status_timeout_kills_slow_commands() {
+        let code = SystemRunner
+            .status_timeout(
+                Command::new("sh").args(["-c", "exit 4"]),
+                Duration::from_secs(10),
+            )
+            .unwrap();
+        assert_eq!(code, 4);
+
+        let started = Instant::now();
+        let err = SystemRunner
+            .status_timeout(Command::new("sleep").arg("10"), Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stderr_tail_is_the_last_line_with_text() {
+        let output = Output {
+            stderr: "warming up\nError: 401 Unauthorized  \n\n".to_string(),
+            ..Output::default()
+        };
+        assert_eq!(output.stderr_tail(), Some("Error: 401 Unauthorized"));
+        assert_eq!(Output::default().stderr_tail(), None);
+    }
+
+    #[test]
+    fn scripted_stream_writes_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("iteration-1.log");
+        let runner = ScriptedRunner::new().reply(0, "hello\n");
+        let output = runner
+            .stream(
+                &mut Command::new("droid"),
+                Some(&log),
+                &CancelToken::new(),
+                None,
+            )
+            .unwrap();
+        assert_eq!((output.code, output.stdout.as_str()), (0, "hello\n"));
+        assert_eq!(fs::read_to_string(log).unwrap(), "hello\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_deaths_are_told_apart_from_exit_codes() {
+        let output = SystemRunner
+            .output(Command::new("sh").args(["-c", "kill -KILL $$"]))
+            .unwrap();
+        assert_eq!((output.code, output.signal), (1, Some(libc::SIGKILL)));
+        assert!(!output.success());
+        assert_eq!(
+            output.termination().as_deref(),
+            Some("terminated by SIGKILL (possibly out of memory)")
+        );
+        assert_eq!(describe_signal(libc::SIGSEGV), "terminated by SIGSEGV");
+        assert_eq!(signal_name(64), "signal 64");
+
+        let output = SystemRunner
+            .output(Command::new("sh").args(["-c", "exit 137"]))
+            .unwrap();
+        assert_eq!(
+            (output.code, output.signal, output.termination()),
+            (137, None, None)
+        );
+    }
+}
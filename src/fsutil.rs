@@ -0,0 +1,149 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Write `contents` to `path` so that readers see the old file or the new
+/// one, never a partial write: the data goes to a temporary file in the
+/// same directory, is synced to disk, and is then renamed over `path`.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp = write_temp(path, contents.as_ref())?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    sync_dir(path);
+    Ok(())
+}
+
+/// Like [`write_atomic`], but only when `path` doesn't exist yet. Returns
+/// whether this call created it. When several processes race, exactly one
+/// wins and the others leave its file alone.
+pub fn create_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<bool> {
+    if path.exists() {
+        return Ok(false);
+    }
+    let tmp = write_temp(path, contents.as_ref())?;
+    // A hard link fails if `path` appeared in the meantime, which a rename
+    // would silently replace.
+    let created = match fs::hard_link(&tmp, path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        // Filesystems without hard links: fall back to a plain rename.
+        Err(_) if !path.exists() => fs::rename(&tmp, path).map(|()| true),
+        Err(_) => Ok(false),
+    };
+    let _ = fs::remove_file(&tmp);
+    if created.as_ref().is_ok_and(|c| *c) {
+        sync_dir(path);
+    }
+    created
+}
+
+/// Write `contents` to a fresh temporary file next to `path` and sync it.
+fn write_temp(path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+    let tmp = temp_path(path);
+    let result = File::create_new(&tmp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match result {
+        Ok(()) => Ok(tmp),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// `.<name>.<pid>-<n>.tmp` beside `path`, unique within this process.
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.{}-{n}.tmp", std::process::id()))
+}
+
+/// Make the rename itself durable. Best-effort, and only possible on unix.
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().and_then(|d| File::open(d).ok()) {
+        let _ = dir.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|n| n.ends_with(".tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn write_atomic_replaces_partial_files_and_leaves_no_temp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system-prompt.md");
+        // What a crash mid-write used to leave behind.
+        fs::write(&path, "Use bd (beads) for task tr").unwrap();
+        fs::write(dir.path().join(".system-prompt.md.1-0.tmp"), "half").unwrap();
+
+        write_atomic(&path, "the whole prompt\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "the whole prompt\n");
+        assert_eq!(leftovers(dir.path()), [".system-prompt.md.1-0.tmp"]);
+
+        let err = write_atomic(&dir.path().join("missing/config.toml"), "x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn create_atomic_never_clobbers_an_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system-prompt.md");
+        fs::write(&path, "edited by hand").unwrap();
+        assert!(!create_atomic(&path, "template").unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "edited by hand");
+
+        fs::remove_file(&path).unwrap();
+        assert!(create_atomic(&path, "template").unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "template");
+        assert!(leftovers(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn racing_creators_leave_one_complete_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system-prompt.md");
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let (path, barrier) = (path.clone(), Arc::clone(&barrier));
+                thread::spawn(move || {
+                    barrier.wait();
+                    create_atomic(&path, format!("prompt {i}\n").repeat(1000)).unwrap()
+                })
+            })
+            .collect();
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|created| *created)
+            .count();
+        assert_eq!(winners, 1);
+        let content = fs::read_to_string(&path).unwrap();
+        let first = content.lines().next().unwrap();
+        assert_eq!(content, format!("{first}\n").repeat(1000));
+        assert!(leftovers(dir.path()).is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// A commit as shown in reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// Full commit hash
+    pub hash: String,
+    /// First line of the commit message
+    pub subject: String,
+}
+
+/// The commit checked out in the current directory, or `None` outside a
+/// git repository (or without git).
+pub fn head() -> Option<String> {
+    head_in(Path::new("."))
+}
+
+/// Like `head`, for the repository containing `dir`.
+pub fn head_in(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!hash.is_empty()).then_some(hash)
+}
+
+/// Commits reachable from `to` but not `from`, oldest first.
+pub fn log(dir: &Path, from: &str, to: &str) -> io::Result<Vec<Commit>> {
+    let output = Command::new("git")
+        .args(["log", "--reverse", "--format=%h%x09%s"])
+        .arg(format!("{from}..{to}"))
+        .current_dir(dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git log exited with code {}: {}",
+            output.status.code().unwrap_or(1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_log(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_log(stdout: &str) -> Vec<Commit> {
+    stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(hash, subject)| Commit {
+            hash: hash.to_string(),
+            subject: subject.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_splits_hash_and_subject() {
+        let commits = parse_log("abc1234\tFirst\ndef5678\tSecond: with\ttab\n\n");
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[1].hash, "def5678");
+        assert_eq!(commits[1].subject, "Second: with\ttab");
+    }
+}
@@ -0,0 +1,710 @@
+use crate::audit::{self, Kind};
+use crate::beads::Issue;
+use crate::error::{RalphError, Result};
+use crate::exec::CommandRunner;
+use crate::tracker;
+use chrono::DateTime;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fmt::Debug;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// Label marking an issue as ready to work on, unless `[tracker] label`
+/// says otherwise.
+pub const DEFAULT_LABEL: &str = "ready";
+
+/// Labels that keep an open issue out of the ready list.
+pub const BLOCKING_LABELS: &[&str] = &["blocked"];
+
+/// Label `ralph run` adds to claim an issue (its `in_progress` status).
+pub const IN_PROGRESS_LABEL: &str = "in progress";
+
+/// Environment variables holding the API token, in order of preference
+/// (the second is the gh CLI's).
+pub const TOKEN_ENV: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+const API_URL: &str = "https://api.github.com";
+
+/// Issues per page, GitHub's maximum.
+const PER_PAGE: u32 = 100;
+
+/// Pages fetched for one list before giving up on the rest, so a repo with
+/// thousands of closed issues doesn't burn the rate limit.
+const MAX_PAGES: usize = 10;
+
+/// Longest `Retry-After` (secondary rate limit) worth waiting out.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// What the tracker reads from an HTTP response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    /// HTTP status code
+    pub status: u16,
+    /// Response body
+    pub body: String,
+    /// `Link` header, for pagination
+    pub link: Option<String>,
+    /// `X-RateLimit-Remaining` header
+    pub rate_limit_remaining: Option<u64>,
+    /// `X-RateLimit-Reset` header, in seconds since the epoch
+    pub rate_limit_reset: Option<i64>,
+    /// `Retry-After` header, in seconds
+    pub retry_after: Option<u64>,
+}
+
+/// Sends requests to the GitHub API. [`ReqwestHttp`] is the real one; tests
+/// script the responses.
+pub trait Http: Debug + Send + Sync {
+    /// Send `method url` with `token` as bearer auth and `body` as JSON.
+    /// Only transport failures are errors; any HTTP status is a response.
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        token: Option<&str>,
+        body: Option<&Value>,
+    ) -> std::result::Result<Response, String>;
+}
+
+/// The reqwest blocking client, built on first use.
+#[derive(Debug, Default)]
+pub struct ReqwestHttp {
+    client: OnceLock<std::result::Result<Client, String>>,
+}
+
+impl Http for ReqwestHttp {
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        token: Option<&str>,
+        body: Option<&Value>,
+    ) -> std::result::Result<Response, String> {
+        let client = self
+            .client
+            .get_or_init(|| {
+                Client::builder()
+                    .user_agent(format!("ralph/{}", env!("CARGO_PKG_VERSION")))
+                    .timeout(tracker::timeout())
+                    .build()
+                    .map_err(|e| e.to_string())
+            })
+            .as_ref()?;
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+        let mut request = client
+            .request(method, url)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let resp = request.send().map_err(|e| e.to_string())?;
+        let header = |name: &str| {
+            resp.headers()
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(Response {
+            status: resp.status().as_u16(),
+            link: header("link"),
+            rate_limit_remaining: header("x-ratelimit-remaining").and_then(|v| v.parse().ok()),
+            rate_limit_reset: header("x-ratelimit-reset").and_then(|v| v.parse().ok()),
+            retry_after: header("retry-after").and_then(|v| v.parse().ok()),
+            body: resp.text().map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+/// An issue as the REST API returns it. Only the fields ralph uses are
+/// modelled.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ApiIssue {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    labels: Vec<ApiLabel>,
+    closed_at: Option<String>,
+    /// Present when the "issue" is a pull request
+    pull_request: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ApiLabel {
+    name: String,
+}
+
+impl ApiIssue {
+    fn has_label(&self, names: &[&str]) -> bool {
+        self.labels
+            .iter()
+            .any(|l| names.iter().any(|n| l.name.eq_ignore_ascii_case(n)))
+    }
+
+    /// The issue's status in bd's terms: `closed`, `blocked` (a blocking
+    /// label), `in_progress` (claimed) or `open`.
+    fn status(&self) -> &'static str {
+        if self.state == "closed" {
+            "closed"
+        } else if self.has_label(BLOCKING_LABELS) {
+            "blocked"
+        } else if self.has_label(&[IN_PROGRESS_LABEL]) {
+            "in_progress"
+        } else {
+            "open"
+        }
+    }
+
+    fn into_issue(self) -> Issue {
+        let status = self.status().to_string();
+        Issue {
+            id: self.number.to_string(),
+            title: self.title,
+            description: self.body.unwrap_or_default(),
+            status,
+            issue_type: "issue".to_string(),
+            closed_at: self.closed_at,
+            ..Issue::default()
+        }
+    }
+}
+
+/// `owner/name` from a GitHub remote URL in any of the forms git accepts:
+/// `git@github.com:o/r.git`, `https://github.com/o/r`,
+/// `ssh://git@github.com/o/r.git`.
+pub fn parse_remote(url: &str) -> Option<String> {
+    let url = url.trim();
+    let path = url
+        .strip_prefix("git@github.com:")
+        .or_else(|| url.split_once("github.com/").map(|(_, path)| path))?;
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, name) = path.split_once('/')?;
+    (!owner.is_empty() && !name.is_empty() && !name.contains('/'))
+        .then(|| format!("{owner}/{name}"))
+}
+
+/// The `rel="next"` URL of a `Link` header.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, rel) = part.split_once(';')?;
+        rel.contains("rel=\"next\"").then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// Percent-encode a query parameter value.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// GitHub Issues, through the REST API. The repository is `[tracker] repo`,
+/// or the one `git remote get-url origin` points at.
+#[derive(Debug)]
+pub struct GitHub {
+    repo: Option<String>,
+    label: String,
+    token: Option<String>,
+    http: Box<dyn Http>,
+    api_url: String,
+    detected_repo: OnceLock<std::result::Result<String, String>>,
+}
+
+impl GitHub {
+    /// A tracker for `repo` (`owner/name`; `None` to detect it), treating
+    /// issues labelled `label` as ready.
+    pub fn new(
+        repo: Option<String>,
+        label: impl Into<String>,
+        token: Option<String>,
+        http: Box<dyn Http>,
+    ) -> Self {
+        GitHub {
+            repo,
+            label: label.into(),
+            token,
+            http,
+            api_url: API_URL.to_string(),
+            detected_repo: OnceLock::new(),
+        }
+    }
+
+    /// The first of [`TOKEN_ENV`] that is set.
+    pub fn token_from_env() -> Option<String> {
+        TOKEN_ENV
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|token| !token.is_empty())
+    }
+
+    /// The repository as `owner/name`.
+    fn repo(&self, exec: &dyn CommandRunner) -> Result<String> {
+        if let Some(repo) = &self.repo {
+            return Ok(repo.clone());
+        }
+        self.detected_repo
+            .get_or_init(|| {
+                let output = audit::run(
+                    Kind::Tracker,
+                    Command::new("git").args(["remote", "get-url", "origin"]),
+                    |cmd| exec.output_timeout(cmd, tracker::timeout()),
+                )
+                .map_err(|e| e.to_string())?;
+                if !output.success() {
+                    return Err(output.stderr.trim().to_string());
+                }
+                parse_remote(&output.stdout)
+                    .ok_or_else(|| format!("origin ({}) is not a GitHub URL", output.stdout.trim()))
+            })
+            .clone()
+            .map_err(|e| {
+                RalphError::TrackerUnavailable(format!(
+                    "Couldn't tell which GitHub repository to use: {e}\n\
+                     Set `repo = \"owner/name\"` under [tracker] in config.toml"
+                ))
+            })
+    }
+
+    fn error(&self, method: &str, url: &str, message: impl ToString) -> RalphError {
+        let path = url.strip_prefix(&self.api_url).unwrap_or(url);
+        RalphError::Tracker {
+            command: format!("GitHub {method} {path}"),
+            message: message.to_string(),
+        }
+    }
+
+    /// Send a request, waiting out a short secondary rate limit once.
+    /// Anything but a 2xx status is an error.
+    fn request(&self, method: &str, url: &str, body: Option<&Value>) -> Result<Response> {
+        let mut waited = false;
+        loop {
+            let resp = self
+                .http
+                .send(method, url, self.token.as_deref(), body)
+                .map_err(|e| self.error(method, url, e))?;
+            if (200..300).contains(&resp.status) {
+                return Ok(resp);
+            }
+            let limited = matches!(resp.status, 403 | 429);
+            if limited && resp.rate_limit_remaining == Some(0) {
+                let reset = resp
+                    .rate_limit_reset
+                    .and_then(|t| DateTime::from_timestamp(t, 0))
+                    .map(|t| format!(" until {}", t.format("%H:%M UTC")))
+                    .unwrap_or_default();
+                let hint = if self.token.is_none() {
+                    "; set GITHUB_TOKEN for a higher limit"
+                } else {
+                    ""
+                };
+                return Err(self.error(method, url, format!("rate limit exceeded{reset}{hint}")));
+            }
+            if limited
+                && !waited
+                && let Some(secs) = resp.retry_after
+                && Duration::from_secs(secs) <= MAX_RETRY_AFTER
+            {
+                thread::sleep(Duration::from_secs(secs));
+                waited = true;
+                continue;
+            }
+            let message = serde_json::from_str::<Value>(&resp.body)
+                .ok()
+                .and_then(|v| v.get("message")?.as_str().map(str::to_string))
+                .unwrap_or_else(|| resp.body.trim().to_string());
+            let hint = match resp.status {
+                401 | 403 | 404 if self.token.is_none() => " (is GITHUB_TOKEN set?)",
+                _ => "",
+            };
+            return Err(self.error(
+                method,
+                url,
+                format!("HTTP {}: {message}{hint}", resp.status),
+            ));
+        }
+    }
+
+    /// Issues (not pull requests) matching `query`, following pagination
+    /// up to [`MAX_PAGES`].
+    fn issues(&self, exec: &dyn CommandRunner, query: &str) -> Result<Vec<ApiIssue>> {
+        let repo = self.repo(exec)?;
+        let mut url = Some(format!(
+            "{}/repos/{repo}/issues?{query}&per_page={PER_PAGE}",
+            self.api_url
+        ));
+        let mut issues = Vec::new();
+        for _ in 0..MAX_PAGES {
+            let Some(page) = url.take() else {
+                break;
+            };
+            let resp = self.request("GET", &page, None)?;
+            let batch: Vec<ApiIssue> =
+                serde_json::from_str(&resp.body).map_err(|e| self.error("GET", &page, e))?;
+            issues.extend(batch.into_iter().filter(|i| i.pull_request.is_none()));
+            url = resp.link.as_deref().and_then(next_page);
+        }
+        Ok(issues)
+    }
+
+    fn issue_url(&self, exec: &dyn CommandRunner, id: &str) -> Result<String> {
+        let number = id.trim_start_matches('#');
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(RalphError::InvalidInput(format!(
+                "'{id}' is not a GitHub issue number"
+            )));
+        }
+        Ok(format!(
+            "{}/repos/{}/issues/{number}",
+            self.api_url,
+            self.repo(exec)?
+        ))
+    }
+
+    /// Check the repository can be reached with the current token.
+    pub fn preflight(&self, exec: &dyn CommandRunner) -> Result<()> {
+        let repo = self.repo(exec)?;
+        self.request("GET", &format!("{}/repos/{repo}", self.api_url), None)
+            .map(|_| ())
+            .map_err(|e| {
+                RalphError::TrackerUnavailable(format!(
+                    "GitHub repository {repo} isn't reachable: {e}\n\
+                     Check `repo` under [tracker] in config.toml, and set GITHUB_TOKEN for private repositories"
+                ))
+            })
+    }
+
+    /// Open issues labelled ready, without a blocking label.
+    pub fn ready(&self, exec: &dyn CommandRunner) -> Result<Vec<Issue>> {
+        let query = format!("state=open&labels={}", encode(&self.label));
+        Ok(self
+            .issues(exec, &query)?
+            .into_iter()
+            .filter(|i| !i.has_label(BLOCKING_LABELS))
+            .map(ApiIssue::into_issue)
+            .collect())
+    }
+
+    /// Issues whose [`status`](ApiIssue::status) is `status`.
+    pub fn list(&self, exec: &dyn CommandRunner, status: &str) -> Result<Vec<Issue>> {
+        let state = if status == "closed" { "closed" } else { "open" };
+        Ok(self
+            .issues(exec, &format!("state={state}"))?
+            .into_iter()
+            .filter(|i| i.status() == status)
+            .map(ApiIssue::into_issue)
+            .collect())
+    }
+
+    /// A single issue, by number.
+    pub fn show(&self, exec: &dyn CommandRunner, id: &str) -> Result<Issue> {
+        let url = self.issue_url(exec, id)?;
+        let resp = self.request("GET", &url, None)?;
+        serde_json::from_str::<ApiIssue>(&resp.body)
+            .map(ApiIssue::into_issue)
+            .map_err(|e| self.error("GET", &url, e))
+    }
+
+    /// `in_progress` adds [`IN_PROGRESS_LABEL`]; `open` and `closed` set the
+    /// issue's state.
+    pub fn update_status(&self, exec: &dyn CommandRunner, id: &str, status: &str) -> Result<()> {
+        let url = self.issue_url(exec, id)?;
+        match status {
+            "in_progress" => self
+                .request(
+                    "POST",
+                    &format!("{url}/labels"),
+                    Some(&json!({ "labels": [IN_PROGRESS_LABEL] })),
+                )
+                .map(|_| ()),
+            "open" | "closed" => self
+                .request("PATCH", &url, Some(&json!({ "state": status })))
+                .map(|_| ()),
+            other => Err(RalphError::InvalidInput(format!(
+                "GitHub issues can't be set to status '{other}'"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::ScriptedRunner;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Replays canned responses and records `METHOD url body` per request.
+    #[derive(Debug, Default, Clone)]
+    struct ScriptedHttp {
+        responses: Arc<Mutex<VecDeque<Response>>>,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ScriptedHttp {
+        fn reply(self, status: u16, body: &str) -> Self {
+            self.push(Response {
+                status,
+                body: body.to_string(),
+                ..Response::default()
+            })
+        }
+
+        fn push(self, response: Response) -> Self {
+            self.responses.lock().unwrap().push_back(response);
+            self
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Http for ScriptedHttp {
+        fn send(
+            &self,
+            method: &str,
+            url: &str,
+            _token: Option<&str>,
+            body: Option<&Value>,
+        ) -> std::result::Result<Response, String> {
+            let url = url.strip_prefix(API_URL).unwrap_or(url);
+            let body = body.map(|b| format!(" {b}")).unwrap_or_default();
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("{method} {url}{body}"));
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or_else(|| "no scripted response".to_string())
+        }
+    }
+
+    fn github(http: &ScriptedHttp) -> GitHub {
+        GitHub::new(
+            Some("o/r".to_string()),
+            DEFAULT_LABEL,
+            Some("t".to_string()),
+            Box::new(http.clone()),
+        )
+    }
+
+    const ISSUES: &str = r#"[
+        {"number":1,"title":"Ready","body":"Do it","state":"open","labels":[{"name":"ready"}]},
+        {"number":2,"title":"Stuck","state":"open","labels":[{"name":"ready"},{"name":"Blocked"}]},
+        {"number":3,"title":"A PR","state":"open","labels":[],"pull_request":{}}
+    ]"#;
+
+    #[test]
+    fn remotes_in_every_form() {
+        for url in [
+            "git@github.com:o/r.git\n",
+            "https://github.com/o/r",
+            "https://github.com/o/r.git/",
+            "ssh://git@github.com/o/r.git",
+        ] {
+            assert_eq!(parse_remote(url).as_deref(), Some("o/r"), "{url}");
+        }
+        assert_eq!(parse_remote("https://gitlab.com/o/r.git"), None);
+        assert_eq!(parse_remote("https://github.com/o"), None);
+    }
+
+    #[test]
+    fn repo_is_detected_from_origin_once() {
+        let http = ScriptedHttp::default().reply(200, "[]").reply(200, "[]");
+        let gh = GitHub::new(None, "ready", None, Box::new(http.clone()));
+        let exec = ScriptedRunner::new().reply(0, "git@github.com:acme/app.git\n");
+        gh.ready(&exec).unwrap();
+        gh.list(&exec, "closed").unwrap();
+        assert_eq!(exec.calls(), [vec!["git", "remote", "get-url", "origin"]]);
+        assert_eq!(
+            http.requests(),
+            [
+                "GET /repos/acme/app/issues?state=open&labels=ready&per_page=100",
+                "GET /repos/acme/app/issues?state=closed&per_page=100",
+            ]
+        );
+
+        let gh = GitHub::new(None, "ready", None, Box::new(ScriptedHttp::default()));
+        let exec = ScriptedRunner::new().reply(0, "https://gitlab.com/o/r\n");
+        let err = gh.ready(&exec).unwrap_err().to_string();
+        assert!(
+            err.contains("origin (https://gitlab.com/o/r) is not a GitHub URL"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn ready_skips_blocked_issues_and_pull_requests() {
+        let http = ScriptedHttp::default().reply(200, ISSUES);
+        let gh = GitHub::new(
+            Some("o/r".to_string()),
+            "good first issue",
+            None,
+            Box::new(http.clone()),
+        );
+        let ready = gh.ready(&ScriptedRunner::new()).unwrap();
+        let ids: Vec<&str> = ready.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["1"]);
+        assert_eq!(ready[0].description, "Do it");
+        assert_eq!(
+            http.requests(),
+            ["GET /repos/o/r/issues?state=open&labels=good%20first%20issue&per_page=100"]
+        );
+    }
+
+    #[test]
+    fn lists_follow_pagination() {
+        let http = ScriptedHttp::default()
+            .push(Response {
+                status: 200,
+                body: ISSUES.to_string(),
+                link: Some(format!(
+                    "<{API_URL}/repos/o/r/issues?state=open&page=2>; rel=\"next\", \
+                     <{API_URL}/repos/o/r/issues?state=open&page=2>; rel=\"last\""
+                )),
+                ..Response::default()
+            })
+            .reply(
+                200,
+                r#"[{"number":4,"title":"Claimed","state":"open","labels":[{"name":"in progress"}]}]"#,
+            );
+        let gh = github(&http);
+        let blocked = gh.list(&ScriptedRunner::new(), "blocked").unwrap();
+        assert_eq!(blocked[0].id, "2");
+        assert_eq!(blocked[0].status, "blocked");
+        assert_eq!(
+            http.requests()[1],
+            "GET /repos/o/r/issues?state=open&page=2"
+        );
+
+        let http = ScriptedHttp::default().reply(200, ISSUES).reply(
+            200,
+            r#"[{"number":4,"title":"Claimed","state":"open","labels":[{"name":"in progress"}]}]"#,
+        );
+        let ids: Vec<String> = github(&http)
+            .list(&ScriptedRunner::new(), "in_progress")
+            .unwrap()
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        // Without a next link, the second page is never asked for.
+        assert!(ids.is_empty());
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[test]
+    fn closed_issues_carry_closed_at() {
+        let http = ScriptedHttp::default().reply(
+            200,
+            r#"[{"number":7,"title":"Done","state":"closed","closed_at":"2026-01-10T00:01:00Z"}]"#,
+        );
+        let closed = github(&http)
+            .list(&ScriptedRunner::new(), "closed")
+            .unwrap();
+        assert_eq!(closed[0].status, "closed");
+        assert_eq!(closed[0].closed_at.as_deref(), Some("2026-01-10T00:01:00Z"));
+    }
+
+    #[test]
+    fn show_and_update_status() {
+        let http = ScriptedHttp::default()
+            .reply(200, r#"{"number":5,"title":"T","body":"B","state":"open"}"#)
+            .reply(200, "[]")
+            .reply(200, "{}");
+        let gh = github(&http);
+        let exec = ScriptedRunner::new();
+        assert_eq!(gh.show(&exec, "#5").unwrap().description, "B");
+        gh.update_status(&exec, "5", "in_progress").unwrap();
+        gh.update_status(&exec, "5", "closed").unwrap();
+        assert_eq!(
+            http.requests(),
+            [
+                "GET /repos/o/r/issues/5",
+                r#"POST /repos/o/r/issues/5/labels {"labels":["in progress"]}"#,
+                r#"PATCH /repos/o/r/issues/5 {"state":"closed"}"#,
+            ]
+        );
+        assert!(gh.show(&exec, "bd-1").is_err());
+        assert!(gh.update_status(&exec, "5", "deferred").is_err());
+    }
+
+    #[test]
+    fn rate_limits_and_http_errors() {
+        let http = ScriptedHttp::default().push(Response {
+            status: 403,
+            body: r#"{"message":"API rate limit exceeded"}"#.to_string(),
+            rate_limit_remaining: Some(0),
+            rate_limit_reset: Some(1_768_003_200),
+            ..Response::default()
+        });
+        let gh = GitHub::new(Some("o/r".to_string()), "ready", None, Box::new(http));
+        let err = gh.ready(&ScriptedRunner::new()).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "GitHub GET /repos/o/r/issues?state=open&labels=ready&per_page=100 failed: \
+             rate limit exceeded until 00:00 UTC; set GITHUB_TOKEN for a higher limit"
+        );
+
+        // A short secondary limit is waited out.
+        let http = ScriptedHttp::default()
+            .push(Response {
+                status: 429,
+                retry_after: Some(0),
+                ..Response::default()
+            })
+            .reply(200, r#"{"number":5,"title":"T","state":"open"}"#);
+        assert_eq!(
+            github(&http).show(&ScriptedRunner::new(), "5").unwrap().id,
+            "5"
+        );
+
+        let http = ScriptedHttp::default().reply(404, r#"{"message":"Not Found"}"#);
+        let err = github(&http)
+            .show(&ScriptedRunner::new(), "5")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "GitHub GET /repos/o/r/issues/5 failed: HTTP 404: Not Found"
+        );
+    }
+
+    #[test]
+    fn preflight_checks_the_repository() {
+        let http = ScriptedHttp::default().reply(404, r#"{"message":"Not Found"}"#);
+        let gh = GitHub::new(Some("o/r".to_string()), "ready", None, Box::new(http));
+        let err = gh.preflight(&ScriptedRunner::new()).unwrap_err();
+        assert!(matches!(err, RalphError::TrackerUnavailable(_)));
+        assert!(
+            err.to_string()
+                .starts_with("GitHub repository o/r isn't reachable: GitHub GET /repos/o/r failed: HTTP 404: Not Found (is GITHUB_TOKEN set?)"),
+            "{err}"
+        );
+        let http = ScriptedHttp::default().reply(200, "{}");
+        github(&http).preflight(&ScriptedRunner::new()).unwrap();
+    }
+}
@@ -0,0 +1,480 @@
+use crate::error::{RalphError, Result};
+use crate::stats::{self, SessionSummary};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Options for `ralph history`.
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    /// Only sessions started at or after this instant
+    pub since: Option<DateTime<Utc>>,
+    /// Only sessions run with this `--label`
+    pub label: Option<String>,
+}
+
+/// Options for `ralph history export`.
+#[derive(Debug, Default)]
+pub struct ExportOptions {
+    /// Only sessions started at or after this instant
+    pub since: Option<DateTime<Utc>>,
+    /// Only sessions run with this `--label`
+    pub label: Option<String>,
+    /// One row per iteration instead of per run
+    pub per_iteration: bool,
+    /// JSON array instead of CSV
+    pub json: bool,
+}
+
+/// A value that can be written as a CSV row.
+trait Row: Serialize {
+    const HEADERS: &'static [&'static str];
+    fn values(&self) -> Vec<String>;
+}
+
+/// One `once`/`loop`/`run` session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunRow {
+    /// Session id
+    pub session: String,
+    /// `once`, `loop` or `run`
+    pub command: String,
+    /// Start time, RFC 3339 UTC
+    pub date: String,
+    /// Directory the session ran in
+    pub repo: String,
+    /// Provider name
+    pub provider: String,
+    /// Iterations run
+    pub iterations: u32,
+    /// Iterations whose provider exited non-zero
+    pub failures: u32,
+    /// Iterations whose provider was killed by a signal
+    pub killed: u32,
+    /// Wall-clock time from start to the last event
+    pub duration_secs: i64,
+    /// Input tokens, when the provider reported usage
+    pub input_tokens: Option<u64>,
+    /// Output tokens, when the provider reported usage
+    pub output_tokens: Option<u64>,
+    /// Cost in USD, when the provider reported it
+    pub cost_usd: Option<f64>,
+    /// Tasks closed during the session
+    pub tasks_closed: u32,
+    /// Whether the session finished its work
+    pub completed: bool,
+    /// `--label` text, empty when it had none
+    pub label: String,
+}
+
+/// One iteration of a session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IterationRow {
+    /// Session id
+    pub session: String,
+    /// Iteration number, from 1
+    pub iteration: u32,
+    /// Start time, RFC 3339 UTC
+    pub date: String,
+    /// Directory the session ran in
+    pub repo: String,
+    /// Provider name
+    pub provider: String,
+    /// Provider exit code
+    pub exit_code: i32,
+    /// Signal that killed the provider, if one did
+    pub signal: Option<i32>,
+    /// Wall-clock time, if the start was recorded
+    pub duration_secs: Option<i64>,
+    /// Input tokens, when the provider reported usage
+    pub input_tokens: Option<u64>,
+    /// Output tokens, when the provider reported usage
+    pub output_tokens: Option<u64>,
+    /// Cost in USD, when the provider reported it
+    pub cost_usd: Option<f64>,
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn rfc3339(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+impl Row for RunRow {
+    const HEADERS: &'static [&'static str] = &[
+        "session",
+        "command",
+        "date",
+        "repo",
+        "provider",
+        "iterations",
+        "failures",
+        "killed",
+        "duration_secs",
+        "input_tokens",
+        "output_tokens",
+        "cost_usd",
+        "tasks_closed",
+        "completed",
+        "label",
+    ];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.session.clone(),
+            self.command.clone(),
+            self.date.clone(),
+            self.repo.clone(),
+            self.provider.clone(),
+            self.iterations.to_string(),
+            self.failures.to_string(),
+            self.killed.to_string(),
+            self.duration_secs.to_string(),
+            opt(self.input_tokens),
+            opt(self.output_tokens),
+            opt(self.cost_usd),
+            self.tasks_closed.to_string(),
+            self.completed.to_string(),
+            self.label.clone(),
+        ]
+    }
+}
+
+impl Row for IterationRow {
+    const HEADERS: &'static [&'static str] = &[
+        "session",
+        "iteration",
+        "date",
+        "repo",
+        "provider",
+        "exit_code",
+        "signal",
+        "duration_secs",
+        "input_tokens",
+        "output_tokens",
+        "cost_usd",
+    ];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.session.clone(),
+            self.iteration.to_string(),
+            self.date.clone(),
+            self.repo.clone(),
+            self.provider.clone(),
+            self.exit_code.to_string(),
+            opt(self.signal),
+            opt(self.duration_secs),
+            opt(self.input_tokens),
+            opt(self.output_tokens),
+            opt(self.cost_usd),
+        ]
+    }
+}
+
+fn run_row(s: &SessionSummary) -> RunRow {
+    RunRow {
+        session: s.id.clone(),
+        command: s.command.clone(),
+        date: rfc3339(s.started),
+        repo: s.cwd.display().to_string(),
+        provider: s.provider.clone(),
+        iterations: s.iterations,
+        failures: s.failures(),
+        killed: s.killed(),
+        duration_secs: (s.ended - s.started).num_seconds(),
+        input_tokens: s.usage.map(|u| u.input_tokens),
+        output_tokens: s.usage.map(|u| u.output_tokens),
+        cost_usd: s.usage.and_then(|u| u.cost_usd),
+        tasks_closed: s.tasks_closed,
+        completed: s.completed,
+        label: s.label.clone().unwrap_or_default(),
+    }
+}
+
+fn iteration_rows(s: &SessionSummary) -> Vec<IterationRow> {
+    s.iteration_details
+        .iter()
+        .map(|i| IterationRow {
+            session: s.id.clone(),
+            iteration: i.iteration,
+            date: rfc3339(i.started.unwrap_or(i.ended)),
+            repo: s.cwd.display().to_string(),
+            provider: s.provider.clone(),
+            exit_code: i.exit_code,
+            signal: i.signal,
+            duration_secs: i.started.map(|start| (i.ended - start).num_seconds()),
+            input_tokens: i.usage.map(|u| u.input_tokens),
+            output_tokens: i.usage.map(|u| u.output_tokens),
+            cost_usd: i.usage.and_then(|u| u.cost_usd),
+        })
+        .collect()
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or line break
+/// (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv<R: Row>(rows: &[R], out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", R::HEADERS.join(","))?;
+    for row in rows {
+        let fields: Vec<String> = row.values().iter().map(|v| csv_field(v)).collect();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+fn write_rows<R: Row>(rows: &[R], json: bool, out: &mut dyn Write) -> io::Result<()> {
+    if json {
+        serde_json::to_writer_pretty(&mut *out, rows).map_err(io::Error::other)?;
+        writeln!(out)
+    } else {
+        write_csv(rows, out)
+    }
+}
+
+/// Parse `--since`: a date (`2024-01-01`, midnight UTC) or an age such as
+/// `30d`.
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc());
+    }
+    let invalid = || {
+        RalphError::InvalidInput(format!(
+            "Invalid --since '{value}' (expected YYYY-MM-DD or e.g. 30d)"
+        ))
+    };
+    let age = crate::clean::parse_age(value).map_err(|_| invalid())?;
+    let age = chrono::Duration::from_std(age).map_err(|_| invalid())?;
+    Ok(Utc::now() - age)
+}
+
+fn export_summaries(
+    summaries: &[SessionSummary],
+    opts: &ExportOptions,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let selected = summaries
+        .iter()
+        .filter(|s| selected(s, opts.since, opts.label.as_deref()));
+    if opts.per_iteration {
+        let rows: Vec<_> = selected.flat_map(iteration_rows).collect();
+        write_rows(&rows, opts.json, out)
+    } else {
+        let rows: Vec<_> = selected.map(run_row).collect();
+        write_rows(&rows, opts.json, out)
+    }
+}
+
+/// Whether `s` started at or after `since` with label `label`, for the
+/// filters that are given.
+fn selected(s: &SessionSummary, since: Option<DateTime<Utc>>, label: Option<&str>) -> bool {
+    since.is_none_or(|since| s.started >= since)
+        && label.is_none_or(|label| s.label.as_deref() == Some(label))
+}
+
+/// One line per session, oldest first, under a header.
+fn render_list(summaries: &[SessionSummary], opts: &ListOptions) -> String {
+    let rows: Vec<[String; 7]> = summaries
+        .iter()
+        .filter(|s| selected(s, opts.since, opts.label.as_deref()))
+        .map(|s| {
+            [
+                s.id.clone(),
+                s.started
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                s.command.clone(),
+                s.provider.clone(),
+                s.iterations.to_string(),
+                match s.completed {
+                    true => "complete",
+                    false => "incomplete",
+                }
+                .to_string(),
+                s.label.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        return match opts.since.is_some() || opts.label.is_some() {
+            true => "No sessions match.\n".to_string(),
+            false => "No sessions recorded.\n".to_string(),
+        };
+    }
+    let header = [
+        "SESSION", "STARTED", "COMMAND", "PROVIDER", "ITERS", "RESULT", "LABEL",
+    ];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: [&str; 7]| {
+        let mut out = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            match i {
+                6 => out.push_str(cell),
+                4 => out.push_str(&format!("{:>w$}  ", cell, w = widths[i])),
+                _ => out.push_str(&format!("{:<w$}  ", cell, w = widths[i])),
+            }
+        }
+        format!("{}\n", out.trim_end())
+    };
+    let mut out = line(header);
+    for row in &rows {
+        out.push_str(&line(row.each_ref().map(String::as_str)));
+    }
+    out
+}
+
+/// The recorded sessions as a table, for `ralph history`.
+pub fn list(opts: &ListOptions) -> Result<String> {
+    Ok(render_list(&stats::load_summaries()?, opts))
+}
+
+/// Write recorded sessions as CSV or JSON.
+pub fn export(opts: &ExportOptions, out: &mut dyn Write) -> Result<()> {
+    Ok(export_summaries(&stats::load_summaries()?, opts, out)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Record;
+
+    fn summary(cwd: &str) -> SessionSummary {
+        let records: Vec<Record> = [
+            format!(r#"{{"ts":"2026-01-10T00:00:00Z","event":"session_start","session":"s","command":"loop","provider":"claude","max_iterations":3,"cwd":{cwd:?}}}"#),
+            r#"{"ts":"2026-01-10T00:00:00Z","event":"iteration_start","iteration":1}"#.to_string(),
+            r#"{"ts":"2026-01-10T00:01:30Z","event":"iteration_end","iteration":1,"exit_code":1,"complete":false,"usage":{"input_tokens":10,"output_tokens":2,"cost_usd":0.5}}"#.to_string(),
+            r#"{"ts":"2026-01-10T00:01:30Z","event":"iteration_start","iteration":2}"#.to_string(),
+            r#"{"ts":"2026-01-10T00:02:00Z","event":"iteration_end","iteration":2,"exit_code":0,"complete":true}"#.to_string(),
+            r#"{"ts":"2026-01-10T00:02:00Z","event":"session_end","iterations":2,"completed":true}"#.to_string(),
+        ]
+        .iter()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+        stats::summarize("s", &records).unwrap()
+    }
+
+    fn export_string(summaries: &[SessionSummary], opts: &ExportOptions) -> String {
+        let mut out = Vec::new();
+        export_summaries(summaries, opts, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_commas_quotes_and_newlines() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn run_rows_flatten_sessions() {
+        let csv = export_string(&[summary("/work/a,b")], &ExportOptions::default());
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], RunRow::HEADERS.join(","));
+        assert_eq!(
+            lines[1],
+            "s,loop,2026-01-10T00:00:00Z,\"/work/a,b\",claude,2,1,0,120,10,2,0.5,0,true,"
+        );
+    }
+
+    #[test]
+    fn lists_filter_by_label() {
+        let mut nightly = summary("/repo");
+        nightly.label = Some("nightly".to_string());
+        nightly.id = "s-nightly".to_string();
+        let summaries = [summary("/repo"), nightly];
+
+        let all = render_list(&summaries, &ListOptions::default());
+        let lines: Vec<_> = all.lines().collect();
+        assert_eq!(lines.len(), 3, "{all}");
+        assert!(lines[0].starts_with("SESSION    STARTED  "), "{all}");
+        assert!(lines[0].ends_with("  ITERS  RESULT    LABEL"), "{all}");
+        assert!(lines[1].ends_with("  claude        2  complete"), "{all}");
+        assert!(
+            lines[2].ends_with("  claude        2  complete  nightly"),
+            "{all}"
+        );
+
+        let opts = ListOptions {
+            label: Some("nightly".to_string()),
+            ..ListOptions::default()
+        };
+        let only = render_list(&summaries, &opts);
+        assert_eq!(only.lines().count(), 2, "{only}");
+        assert!(only.contains("s-nightly"), "{only}");
+        let opts = ListOptions {
+            label: Some("weekly".to_string()),
+            ..ListOptions::default()
+        };
+        assert_eq!(render_list(&summaries, &opts), "No sessions match.\n");
+        assert_eq!(
+            render_list(&[], &ListOptions::default()),
+            "No sessions recorded.\n"
+        );
+        let csv = export_string(
+            &summaries,
+            &ExportOptions {
+                label: Some("nightly".to_string()),
+                ..ExportOptions::default()
+            },
+        );
+        assert!(
+            csv.lines().nth(1).unwrap().starts_with("s-nightly,"),
+            "{csv}"
+        );
+        assert!(
+            csv.lines().nth(1).unwrap().ends_with(",true,nightly"),
+            "{csv}"
+        );
+    }
+
+    #[test]
+    fn per_iteration_json_has_one_record_per_iteration() {
+        let opts = ExportOptions {
+            per_iteration: true,
+            json: true,
+            ..ExportOptions::default()
+        };
+        let json = export_string(&[summary("/repo")], &opts);
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["duration_secs"], 90);
+        assert_eq!(rows[1]["exit_code"], 0);
+        assert!(rows[1]["cost_usd"].is_null());
+        assert!(rows[1]["signal"].is_null());
+    }
+
+    #[test]
+    fn since_accepts_dates_and_filters() {
+        let since = parse_since("2026-01-11").unwrap();
+        assert_eq!(rfc3339(since), "2026-01-11T00:00:00Z");
+        assert!(parse_since("yesterday").is_err());
+        let opts = ExportOptions {
+            since: Some(since),
+            ..ExportOptions::default()
+        };
+        assert_eq!(export_string(&[summary("/repo")], &opts).lines().count(), 1);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::error::{RalphError, Result};
+use crate::lock::{self, Holder};
+use crate::reporter;
+use crate::session;
+use chrono::Utc;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `ralph kill` waits for a run to stop after the interrupt
+/// before killing it.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the lock is checked while waiting for its holder to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options for `ralph kill`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KillOptions {
+    /// Kill straight away rather than interrupting first
+    pub force: bool,
+    /// How long the interrupted run gets to stop on its own
+    pub timeout: Duration,
+}
+
+/// What `ralph kill` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The run stopped after the interrupt and released its lock, as
+    /// after Ctrl-C
+    Stopped(Holder),
+    /// The run was killed, along with the process groups of the providers
+    /// it had running
+    Killed {
+        /// The ralph killed
+        holder: Holder,
+        /// Process groups killed with it
+        groups: Vec<u32>,
+    },
+    /// The lock was left by a ralph that is gone, and has been removed
+    StaleLock(Option<Holder>),
+}
+
+impl Outcome {
+    /// One line saying what happened.
+    pub fn render_text(&self) -> String {
+        match self {
+            Outcome::Stopped(holder) => format!(
+                "Stopped `ralph {}` (pid {}); it wrapped up and released the lock",
+                holder.command, holder.pid
+            ),
+            Outcome::Killed { holder, groups } => {
+                let groups = match groups.as_slice() {
+                    [] => String::new(),
+                    groups => format!(
+                        " and the provider process group{} {}",
+                        if groups.len() == 1 { "" } else { "s" },
+                        groups
+                            .iter()
+                            .map(u32::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+                format!(
+                    "Killed `ralph {}` (pid {}){}, and removed its lock",
+                    holder.command, holder.pid, groups
+                )
+            }
+            Outcome::StaleLock(Some(holder)) => format!(
+                "No ralph is running: pid {} (`ralph {}`) is gone. Removed its stale lock",
+                holder.pid, holder.command
+            ),
+            Outcome::StaleLock(None) => {
+                "No ralph is running. Removed a stale lock whose holder couldn't be read"
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// Stop the ralph holding the lock at `path`: interrupt it as Ctrl-C would
+/// (its provider is stopped, the summary written and the lock released),
+/// and kill it with its providers' process groups if it's still running
+/// after `opts.timeout`, or straight away with `opts.force`. A stale lock
+/// is only removed; nothing is signalled.
+pub fn run(path: &Path, opts: &KillOptions) -> Result<Outcome> {
+    if !path.exists() {
+        return Err(RalphError::InvalidInput(format!(
+            "No ralph is running here (no lock at {})",
+            path.display()
+        )));
+    }
+    let holder = lock::read_holder(path);
+    if lock::is_stale(holder.as_ref(), lock::heartbeat_age(path)) {
+        remove(path)?;
+        return Ok(Outcome::StaleLock(holder));
+    }
+    let Some(holder) = holder else {
+        return Err(RalphError::InvalidInput(format!(
+            "Can't tell which ralph holds the lock {}; try again in a moment",
+            path.display()
+        )));
+    };
+    if holder.host != lock::hostname() {
+        return Err(RalphError::InvalidInput(format!(
+            "The lock {} is held by {}; run `ralph kill` on {}",
+            path.display(),
+            holder.describe(Utc::now()),
+            holder.host
+        )));
+    }
+    // Listed now: once ralph is gone its providers no longer say whose
+    // they were.
+    let groups = provider_groups(holder.pid);
+
+    if !opts.force {
+        reporter::info(&format!(
+            "Interrupting {}; waiting up to {}s for it to stop",
+            holder.describe(Utc::now()),
+            opts.timeout.as_secs()
+        ));
+        signal(holder.pid, Signal::Interrupt)?;
+        let deadline = Instant::now() + opts.timeout;
+        while Instant::now() < deadline {
+            // A stopped ralph removes its lock, even before its parent has
+            // reaped it.
+            if !path.exists() || !session::pid_is_alive(holder.pid) {
+                kill_groups(&groups);
+                let _ = remove(path);
+                return Ok(Outcome::Stopped(holder));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        reporter::warn(&format!(
+            "pid {} didn't stop within {}s; killing it",
+            holder.pid,
+            opts.timeout.as_secs()
+        ));
+    }
+
+    let groups = kill_groups(&groups);
+    signal(holder.pid, Signal::Kill)?;
+    remove(path)?;
+    Ok(Outcome::Killed { holder, groups })
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The signals `ralph kill` sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    /// SIGINT, as from Ctrl-C
+    Interrupt,
+    /// SIGKILL
+    Kill,
+}
+
+/// Send `signal` to `pid`; a process that has exited in the meantime is
+/// left alone.
+#[cfg(unix)]
+fn signal(pid: u32, signal: Signal) -> Result<()> {
+    let pid = libc::pid_t::try_from(pid)
+        .map_err(|_| RalphError::InvalidInput(format!("Invalid pid {pid}")))?;
+    let signal = match signal {
+        Signal::Interrupt => libc::SIGINT,
+        Signal::Kill => libc::SIGKILL,
+    };
+    // SAFETY: kill has no memory-safety preconditions.
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Signals are Unix-only; elsewhere `ralph kill` can only clean up stale
+/// locks.
+#[cfg(not(unix))]
+fn signal(_pid: u32, _signal: Signal) -> Result<()> {
+    Err(RalphError::InvalidInput(
+        "`ralph kill` can only stop a running ralph on Unix; press Ctrl-C in its terminal"
+            .to_string(),
+    ))
+}
+
+/// SIGKILL each of `groups` still around; returns those that were.
+#[cfg(unix)]
+fn kill_groups(groups: &[u32]) -> Vec<u32> {
+    groups
+        .iter()
+        .copied()
+        .filter(|&group| {
+            let Ok(group) = libc::pid_t::try_from(group) else {
+                return false;
+            };
+            // SAFETY: killpg has no memory-safety preconditions.
+            unsafe { libc::killpg(group, libc::SIGKILL) == 0 }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn kill_groups(_groups: &[u32]) -> Vec<u32> {
+    Vec::new()
+}
+
+/// The process groups of `pid`'s children that lead their own, i.e. the
+/// providers it started (see [`ProviderRun::spawn`](crate::stream::ProviderRun::spawn)).
+#[cfg(unix)]
+fn provider_groups(pid: u32) -> Vec<u32> {
+    children(pid)
+        .into_iter()
+        .filter(|&child| {
+            let Ok(child) = libc::pid_t::try_from(child) else {
+                return false;
+            };
+            // SAFETY: getpgid has no memory-safety preconditions.
+            unsafe { libc::getpgid(child) == child }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn provider_groups(_pid: u32) -> Vec<u32> {
+    Vec::new()
+}
+
+/// The pids whose parent is `pid`, from `/proc`.
+#[cfg(target_os = "linux")]
+fn children(pid: u32) -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let child: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            (parent_pid(&stat)? == pid).then_some(child)
+        })
+        .collect()
+}
+
+/// The pids whose parent is `pid`, from `pgrep -P`.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn children(pid: u32) -> Vec<u32> {
+    std::process::Command::new("pgrep")
+        .args(["-P", &pid.to_string()])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The parent pid in a `/proc/<pid>/stat` line: the second field after the
+/// command name, which is in parentheses and may itself hold spaces or
+/// parentheses.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parent_pid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parent_pids_survive_odd_command_names() {
+        assert_eq!(
+            parent_pid("4250 (claude) S 4242 4250 4100 0 -1 4194304"),
+            Some(4242)
+        );
+        assert_eq!(parent_pid("7 (a (b) c) R 1 7 7"), Some(1));
+        assert_eq!(parent_pid("garbage"), None);
+    }
+
+    #[test]
+    fn stale_locks_are_removed_without_signalling_anyone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let opts = KillOptions {
+            force: false,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        let err = run(&path, &opts).unwrap_err().to_string();
+        assert!(err.starts_with("No ralph is running here"), "{err}");
+
+        // pid_max is at most 2^22, so this pid can't exist.
+        let gone = Holder {
+            pid: 1 << 23,
+            host: lock::hostname(),
+            started: "2026-01-10T00:00:00Z".to_string(),
+            command: "loop".to_string(),
+        };
+        fs::write(&path, serde_json::to_string(&gone).unwrap()).unwrap();
+        let outcome = run(&path, &opts).unwrap();
+        assert_eq!(outcome, Outcome::StaleLock(Some(gone)));
+        assert!(!path.exists());
+        assert!(
+            outcome
+                .render_text()
+                .contains("pid 8388608 (`ralph loop`) is gone")
+        );
+
+        let elsewhere = Holder {
+            pid: 1,
+            host: "some-other-host".to_string(),
+            started: "2026-01-10T00:00:00Z".to_string(),
+            command: "watch".to_string(),
+        };
+        fs::write(&path, serde_json::to_string(&elsewhere).unwrap()).unwrap();
+        let err = run(&path, &opts).unwrap_err().to_string();
+        assert!(
+            err.ends_with("run `ralph kill` on some-other-host"),
+            "{err}"
+        );
+        assert!(path.exists());
+    }
+}
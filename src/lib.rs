@@ -0,0 +1,131 @@
+#![warn(missing_docs)]
+
+//! Ralph: run AI coding agents (droid, codex, claude, gemini, copilot,
+//! qwen) against a beads task list until the work is done.
+//!
+//! The `ralph` binary is a thin layer over this library. To embed the loop
+//! in another tool, configure a [`runner::LoopRunner`]:
+//!
+//! ```no_run
+//! use ralph::{config, runner::LoopRunner};
+//!
+//! let config = config::resolve(&config::Overrides::default())?;
+//! let summary = LoopRunner::builder()
+//!     .provider(config.provider.value)
+//!     .prompt(config::read_system_prompt()?)
+//!     .iterations(config.iterations.value)
+//!     .gate("cargo test")
+//!     .run()?;
+//! println!("{} iterations, complete: {}", summary.iterations, summary.completed);
+//! # Ok::<(), ralph::RalphError>(())
+//! ```
+
+/// The append-only log of every command ralph runs (`ralph audit`).
+pub mod audit;
+/// Diffing the tracker's backlog before and after a loop.
+pub mod backlog;
+/// The bd (beads) task tracker CLI.
+pub mod beads;
+/// Build metadata baked in by `build.rs`.
+pub mod build_info;
+/// `ralph clean`: pruning old logs, transcripts and caches.
+pub mod clean;
+/// Config files, defaults, and resolving command-line values against them.
+pub mod config;
+/// Crash reports written when ralph panics.
+pub mod crash;
+/// Decoding provider output that isn't UTF-8 (Windows console code pages).
+pub mod encoding;
+/// Which environment variables providers inherit (`--env-policy`).
+pub mod env_policy;
+/// `ralph loop --epic`: scoping a loop to one epic's tasks.
+pub mod epic;
+/// [`error::RalphError`], returned by every fallible command.
+pub mod error;
+/// Running external commands behind a fakeable [`exec::CommandRunner`].
+pub mod exec;
+/// Crash-safe file writes: write a temporary file, sync, rename.
+pub mod fsutil;
+/// Reading commits from git.
+pub mod git;
+/// GitHub Issues as a task tracker, through the REST API.
+pub mod github;
+/// `ralph history export`.
+pub mod history;
+/// `ralph kill`: stopping a run from another terminal.
+pub mod kill;
+/// The per-repository lock that keeps two runs out of the same checkout.
+pub mod lock;
+/// `--log-target`: sending ralph's messages to syslog, journald or a file.
+pub mod log_target;
+/// `ralph logs`.
+pub mod logs;
+/// `loop --metrics-file`: Prometheus metrics for node_exporter's textfile collector.
+pub mod metrics;
+/// Model prices (`~/.Ralph/pricing.toml`) for estimating cost from tokens.
+pub mod pricing;
+/// Running providers at a lower CPU and I/O priority (`--nice`, `--ionice`).
+pub mod priority;
+/// Finding provider CLIs and their versions, cached in `cache/providers.json`.
+pub mod probe;
+/// `loop --report-file`: a markdown report updated after every iteration.
+pub mod progress;
+/// `ralph prompt lint`.
+pub mod prompt;
+/// Built-in system prompt templates, one per tracker.
+pub mod prompts;
+/// The AI provider CLIs ralph knows how to drive.
+pub mod providers;
+/// Pretty-printing stream-json provider output.
+pub mod render;
+/// `ralph report`.
+pub mod report;
+/// [`reporter::Reporter`]: where banners, warnings and progress go.
+pub mod reporter;
+/// `ralph resume`.
+pub mod resume;
+/// `ralph run <id>`: working a single bd task.
+pub mod run;
+/// The `once` and `loop` run logic, usable without the CLI.
+pub mod runner;
+/// `loop --sandbox`: a throwaway worktree for a loop to run in.
+pub mod sandbox;
+/// `--validate-stream`: checking provider stream-json against the event
+/// shapes ralph expects.
+pub mod schema;
+/// Session transcripts under `~/.Ralph/sessions`.
+pub mod session;
+/// `ralph setup`.
+pub mod setup;
+/// `ralph prompt test`: a provider's round trip with a trivial prompt.
+pub mod smoke;
+/// `.ralph/state.json`: a loop's progress, for resuming it anywhere.
+pub mod state;
+/// `ralph stats`.
+pub mod stats;
+/// `ralph status`.
+pub mod status;
+/// [`stream::ProviderRun`]: a running provider as a stream of output and
+/// exit events, plus streaming that output to the terminal.
+pub mod stream;
+/// Project templates for `ralph init`.
+pub mod templates;
+/// Detecting whether ralph runs at an interactive terminal.
+pub mod term;
+/// Task trackers (`tracker` in config.toml) behind one trait.
+pub mod tracker;
+/// `ralph uninstall`.
+pub mod uninstall;
+/// `ralph upgrade` and `ralph check-update`.
+pub mod upgrade;
+/// Token usage reported by providers.
+pub mod usage;
+/// `ralph watch`.
+pub mod watch;
+/// `loop --workspace`: one loop after another across several repositories.
+pub mod workspace;
+
+pub use error::{RalphError, Result};
+pub use runner::{
+    COMPLETE_MARKER, LoopRunner, LoopSummary, RunOptions, RunSummary, run_loop, run_once,
+};
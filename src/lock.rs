@@ -0,0 +1,340 @@
+use crate::config;
+use crate::error::{RalphError, Result};
+use crate::reporter;
+use crate::session::{self, Heartbeat};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// Name of the lock file inside the repository's `.ralph/` directory.
+pub const LOCK_FILE: &str = "lock";
+
+/// Who holds a repository lock, as recorded in the lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    /// Process id of the ralph holding the lock
+    pub pid: u32,
+    /// Host it runs on
+    pub host: String,
+    /// When it took the lock, RFC 3339 UTC
+    pub started: String,
+    /// The subcommand, e.g. `loop`
+    pub command: String,
+}
+
+impl Holder {
+    fn current(command: &str) -> Holder {
+        Holder {
+            pid: std::process::id(),
+            host: hostname(),
+            started: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            command: command.to_string(),
+        }
+    }
+
+    /// "pid 4242 on devbox, `ralph loop` started 12m ago"
+    pub(crate) fn describe(&self, now: DateTime<Utc>) -> String {
+        let age = DateTime::parse_from_rfc3339(&self.started)
+            .ok()
+            .and_then(|started| (now - started.to_utc()).to_std().ok())
+            .map(|age| format!(" started {} ago", format_age(age)))
+            .unwrap_or_default();
+        format!(
+            "pid {} on {}, `ralph {}`{}",
+            self.pid, self.host, self.command, age
+        )
+    }
+}
+
+/// The lock of a running command, held until dropped: the file is removed
+/// then, which includes runs stopped with Ctrl-C since those end normally.
+/// While held, a heartbeat keeps the file's mtime fresh so a lock left by a
+/// crash can be told apart from a live one.
+pub struct RepoLock {
+    path: PathBuf,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl RepoLock {
+    /// The lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// `.ralph/lock` at the top of the git repository containing the current
+/// directory, or in the current directory outside a repository.
+pub fn lock_path() -> io::Result<PathBuf> {
+    lock_path_in(&std::env::current_dir()?)
+}
+
+/// [`lock_path`] for the repository containing `dir`.
+pub fn lock_path_in(dir: &Path) -> io::Result<PathBuf> {
+    if !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", dir.display()),
+        ));
+    }
+    let root = git_toplevel(dir).unwrap_or_else(|| dir.to_path_buf());
+    Ok(root.join(config::PROJECT_DIR_NAME).join(LOCK_FILE))
+}
+
+fn git_toplevel(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!root.is_empty()).then(|| PathBuf::from(root))
+}
+
+/// Take the lock at `path` for `command`. A lock held by another live
+/// ralph is an error naming its holder, unless `force` takes it over; a
+/// stale one (see [`is_stale`]) is replaced with a notice.
+pub fn acquire(path: &Path, command: &str, force: bool) -> Result<RepoLock> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            ignore_in_git(dir, name)?;
+        }
+    }
+    // Twice at most: once more after clearing a stale or overridden lock.
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let holder =
+                    serde_json::to_string(&Holder::current(command)).map_err(io::Error::other)?;
+                writeln!(file, "{holder}")?;
+                return Ok(RepoLock {
+                    path: path.to_path_buf(),
+                    heartbeat: Heartbeat::start(path.to_path_buf()),
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let holder = read_holder(path);
+        let holder_text = holder.as_ref().map(|h| h.describe(Utc::now()));
+        if force {
+            reporter::warn(&format!(
+                "Taking over the lock {} ({})",
+                path.display(),
+                holder_text.as_deref().unwrap_or("holder unknown")
+            ));
+        } else if is_stale(holder.as_ref(), heartbeat_age(path)) {
+            reporter::info(&format!(
+                "Removing a stale lock {} ({})",
+                path.display(),
+                holder_text.as_deref().unwrap_or("holder unknown")
+            ));
+        } else {
+            return Err(RalphError::Locked {
+                path: path.to_path_buf(),
+                holder: holder_text,
+            });
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Err(RalphError::Locked {
+        path: path.to_path_buf(),
+        holder: None,
+    })
+}
+
+/// List `name` (the lock file, the sandboxes' directory) in
+/// `dir/.gitignore`, so an agent committing everything doesn't commit it
+/// along with its work.
+pub(crate) fn ignore_in_git(dir: &Path, name: &str) -> io::Result<()> {
+    let gitignore = dir.join(".gitignore");
+    let existing = match fs::read_to_string(&gitignore) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if existing.lines().any(|l| l.trim() == name) {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(gitignore)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{name}")
+}
+
+/// The holder recorded in the lock file at `path`, if it can be read.
+pub fn read_holder(path: &Path) -> Option<Holder> {
+    serde_json::from_str(fs::read_to_string(path).ok()?.trim()).ok()
+}
+
+/// Time since the lock file was last touched; zero if that can't be told.
+pub fn heartbeat_age(path: &Path) -> Duration {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .unwrap_or_default()
+}
+
+/// Whether a lock belongs to a ralph that is gone: its heartbeat stopped
+/// [`session::STALE_AFTER`] ago, or it ran on this host under a pid that no
+/// longer exists. A lock whose holder can't be read (still being written)
+/// only goes stale by age.
+pub fn is_stale(holder: Option<&Holder>, heartbeat_age: Duration) -> bool {
+    if heartbeat_age >= session::STALE_AFTER {
+        return true;
+    }
+    holder.is_some_and(|h| h.host == hostname() && !session::pid_is_alive(h.pid))
+}
+
+/// "45s", "12m", "3h 5m" or "2d 4h".
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// This machine's name, for telling locks from other hosts (on a shared
+/// filesystem) apart from local ones.
+#[cfg(unix)]
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+/// This machine's name, for telling locks from other hosts (on a shared
+/// filesystem) apart from local ones.
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holder(pid: u32, host: &str) -> Holder {
+        Holder {
+            pid,
+            host: host.to_string(),
+            started: "2026-01-10T00:00:00Z".to_string(),
+            command: "loop".to_string(),
+        }
+    }
+
+    #[test]
+    fn a_live_lock_refuses_a_second_run_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".ralph").join(LOCK_FILE);
+        let lock = acquire(&path, "loop", false).unwrap();
+        let written = read_holder(&path).unwrap();
+        assert_eq!(written.pid, std::process::id());
+        assert_eq!(written.command, "loop");
+
+        let err = acquire(&path, "once", false).err().unwrap().to_string();
+        assert!(
+            err.contains(&format!("pid {} on {}", std::process::id(), hostname())),
+            "{err}"
+        );
+        assert!(err.contains("--force-lock"), "{err}");
+
+        drop(lock);
+        assert!(!path.exists());
+        drop(acquire(&path, "once", false).unwrap());
+
+        let gitignore = dir.path().join(".ralph").join(".gitignore");
+        assert_eq!(fs::read_to_string(&gitignore).unwrap(), "lock\n");
+        fs::write(&gitignore, "worktrees/").unwrap();
+        drop(acquire(&path, "once", false).unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            "worktrees/\nlock\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_and_forced_locks_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE);
+        let dead = serde_json::to_string(&holder(u32::MAX - 1, &hostname())).unwrap();
+        fs::write(&path, dead).unwrap();
+        let lock = acquire(&path, "loop", false).unwrap();
+        assert_eq!(read_holder(&path).unwrap().pid, std::process::id());
+        drop(lock);
+
+        fs::write(
+            &path,
+            serde_json::to_string(&holder(1, "elsewhere")).unwrap(),
+        )
+        .unwrap();
+        assert!(acquire(&path, "loop", false).is_err());
+        let lock = acquire(&path, "loop", true).unwrap();
+        assert_eq!(read_holder(&path).unwrap().host, hostname());
+        drop(lock);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn staleness_by_host_pid_and_heartbeat() {
+        let fresh = Duration::from_secs(1);
+        let me = holder(std::process::id(), &hostname());
+        assert!(!is_stale(Some(&me), fresh));
+        assert!(is_stale(Some(&me), session::STALE_AFTER));
+        assert!(is_stale(Some(&holder(u32::MAX - 1, &hostname())), fresh));
+        // Another host's pids can't be checked, so only age counts.
+        assert!(!is_stale(Some(&holder(u32::MAX - 1, "elsewhere")), fresh));
+        assert!(!is_stale(None, fresh));
+        assert!(is_stale(None, session::STALE_AFTER * 2));
+    }
+
+    #[test]
+    fn holders_describe_themselves() {
+        let now = DateTime::parse_from_rfc3339("2026-01-10T00:12:30Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            holder(4242, "devbox").describe(now),
+            "pid 4242 on devbox, `ralph loop` started 12m ago"
+        );
+        assert_eq!(format_age(Duration::from_secs(45)), "45s");
+        assert_eq!(format_age(Duration::from_secs(3 * 3600 + 300)), "3h 5m");
+        assert_eq!(
+            format_age(Duration::from_secs(2 * 86400 + 4 * 3600)),
+            "2d 4h"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use crate::reporter::Reporter;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Accepted values of `--log-target`, besides `file:<path>`.
+pub const VALID_TARGETS: &[&str] = &["stderr", "syslog", "journald"];
+
+/// Where ralph's own messages go (`--log-target`). Provider output stays on
+/// stdout whatever the target.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// The terminal, formatted per `--message-format`
+    #[default]
+    Stderr,
+    /// The local syslog daemon (Unix)
+    Syslog,
+    /// The systemd journal, with the context as journal fields (needs the
+    /// `journald` feature)
+    Journald,
+    /// Lines appended to a file
+    File(PathBuf),
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LogTarget, String> {
+        match value {
+            "stderr" => Ok(LogTarget::Stderr),
+            "syslog" if cfg!(unix) => Ok(LogTarget::Syslog),
+            "syslog" => Err("syslog is only available on Unix".to_string()),
+            "journald" if cfg!(all(feature = "journald", unix)) => Ok(LogTarget::Journald),
+            "journald" => Err(
+                "this ralph was built without journald support (the `journald` feature)"
+                    .to_string(),
+            ),
+            _ => match value.strip_prefix("file:") {
+                Some("") => Err("file: needs a path, e.g. file:/var/log/ralph.log".to_string()),
+                Some(path) => Ok(LogTarget::File(PathBuf::from(path))),
+                None => Err(format!(
+                    "expected one of {}, or file:<path>",
+                    VALID_TARGETS.join(", ")
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogTarget::Stderr => f.write_str("stderr"),
+            LogTarget::Syslog => f.write_str("syslog"),
+            LogTarget::Journald => f.write_str("journald"),
+            LogTarget::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl LogTarget {
+    /// A reporter sending messages to this target, or `None` for
+    /// [`LogTarget::Stderr`], which the caller formats itself. `verbose`
+    /// passes debug messages on.
+    pub fn reporter(&self, verbose: bool) -> io::Result<Option<Box<dyn Reporter>>> {
+        let sink: Box<dyn Sink> = match self {
+            LogTarget::Stderr => return Ok(None),
+            #[cfg(unix)]
+            LogTarget::Syslog => Box::new(syslog::Syslog::open()),
+            #[cfg(all(feature = "journald", unix))]
+            LogTarget::Journald => Box::new(journald::Journald::connect()?),
+            LogTarget::File(path) => Box::new(FileSink(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            #[allow(unreachable_patterns)]
+            _ => return Err(io::Error::other(format!("{self} isn't available here"))),
+        };
+        Ok(Some(Box::new(TargetReporter { sink, verbose })))
+    }
+}
+
+/// How much a message matters, as syslog priorities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// `LOG_ERR`
+    Error = 3,
+    /// `LOG_WARNING`
+    Warn = 4,
+    /// `LOG_NOTICE`: banners and summaries
+    Notice = 5,
+    /// `LOG_INFO`
+    Info = 6,
+    /// `LOG_DEBUG`
+    Debug = 7,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Notice => "notice",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+/// What ralph is working on, attached to every message sent to a target:
+/// as journal fields, or as `key=value` pairs in syslog and files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    /// Id of the running session
+    pub session: Option<String>,
+    /// Provider running it
+    pub provider: Option<String>,
+    /// Current iteration, from 1
+    pub iteration: Option<u32>,
+}
+
+impl Context {
+    /// The set fields as `(name, value)`, names in journal style.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if let Some(session) = &self.session {
+            fields.push(("RALPH_SESSION", session.clone()));
+        }
+        if let Some(provider) = &self.provider {
+            fields.push(("RALPH_PROVIDER", provider.clone()));
+        }
+        if let Some(iteration) = self.iteration {
+            fields.push(("RALPH_ITERATION", iteration.to_string()));
+        }
+        fields
+    }
+
+    /// `[session=… provider=… iteration=…] `, or nothing when no field is
+    /// set.
+    fn prefix(&self) -> String {
+        let pairs: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| {
+                let name = name.trim_start_matches("RALPH_").to_ascii_lowercase();
+                format!("{name}={value}")
+            })
+            .collect();
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            format!("[{}] ", pairs.join(" "))
+        }
+    }
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    session: None,
+    provider: None,
+    iteration: None,
+});
+
+/// Change the [`Context`] attached to messages from now on.
+pub fn update_context(change: impl FnOnce(&mut Context)) {
+    let mut context = CONTEXT.lock().unwrap_or_else(|e| e.into_inner());
+    change(&mut context);
+}
+
+/// The current [`Context`].
+pub fn context() -> Context {
+    CONTEXT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Somewhere a [`TargetReporter`] delivers messages.
+trait Sink: Send + Sync {
+    fn send(&self, level: Level, message: &str, context: &Context);
+}
+
+/// Sends each message, with the current [`Context`], to a [`Sink`]. Blank
+/// lines and progress updates other than the last are dropped.
+struct TargetReporter {
+    sink: Box<dyn Sink>,
+    verbose: bool,
+}
+
+impl TargetReporter {
+    fn send(&self, level: Level, message: &str) {
+        if !message.trim().is_empty() {
+            self.sink.send(level, message.trim(), &context());
+        }
+    }
+}
+
+impl Reporter for TargetReporter {
+    fn info(&self, message: &str) {
+        self.send(Level::Info, message);
+    }
+
+    fn warn(&self, message: &str) {
+        self.send(Level::Warn, message);
+    }
+
+    fn error(&self, message: &str) {
+        self.send(Level::Error, message);
+    }
+
+    fn banner(&self, title: &str) {
+        self.send(Level::Notice, title);
+    }
+
+    fn progress(&self, message: &str, finished: bool) {
+        if finished {
+            self.send(Level::Info, message);
+        }
+    }
+
+    fn summary(&self, message: &str) {
+        self.send(Level::Notice, message);
+    }
+
+    fn debug(&self, message: &str) {
+        if self.verbose {
+            self.send(Level::Debug, message);
+        }
+    }
+}
+
+/// `file:<path>`: one line per message, timestamped.
+struct FileSink(Mutex<File>);
+
+impl Sink for FileSink {
+    fn send(&self, level: Level, message: &str, context: &Context) {
+        let line = format_line(&now(), level, message, context);
+        let mut file = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// A line of a `file:` log.
+fn format_line(ts: &str, level: Level, message: &str, context: &Context) -> String {
+    format!(
+        "{ts} {:<6} {}{}\n",
+        level.name(),
+        context.prefix(),
+        message.replace('\n', " | ")
+    )
+}
+
+#[cfg(unix)]
+mod syslog {
+    use super::{Context, Level, Sink};
+    use std::ffi::CString;
+    use std::sync::Once;
+
+    /// The local syslog daemon, through `syslog(3)`.
+    pub(super) struct Syslog;
+
+    impl Syslog {
+        pub(super) fn open() -> Syslog {
+            static OPEN: Once = Once::new();
+            OPEN.call_once(|| unsafe {
+                libc::openlog(c"ralph".as_ptr(), libc::LOG_PID, libc::LOG_USER);
+            });
+            Syslog
+        }
+    }
+
+    impl Sink for Syslog {
+        fn send(&self, level: Level, message: &str, context: &Context) {
+            let text = format!("{}{}", context.prefix(), message).replace('\0', " ");
+            let Ok(text) = CString::new(text) else {
+                return;
+            };
+            unsafe { libc::syslog(level as libc::c_int, c"%s".as_ptr(), text.as_ptr()) };
+        }
+    }
+}
+
+#[cfg(all(feature = "journald", unix))]
+mod journald {
+    use super::{Context, Level, Sink};
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+
+    const SOCKET: &str = "/run/systemd/journal/socket";
+
+    /// The systemd journal, through its native datagram protocol.
+    pub(super) struct Journald(UnixDatagram);
+
+    impl Journald {
+        pub(super) fn connect() -> io::Result<Journald> {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(SOCKET)?;
+            Ok(Journald(socket))
+        }
+    }
+
+    impl Sink for Journald {
+        fn send(&self, level: Level, message: &str, context: &Context) {
+            let mut fields = vec![
+                ("MESSAGE", message.to_string()),
+                ("PRIORITY", (level as u8).to_string()),
+                ("SYSLOG_IDENTIFIER", "ralph".to_string()),
+            ];
+            fields.extend(context.fields());
+            let _ = self.0.send(&encode(&fields));
+        }
+    }
+
+    /// `fields` in the journal's native format: `NAME=value` lines, with
+    /// multi-line values length-prefixed.
+    pub(super) fn encode(fields: &[(&str, String)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, value) in fields {
+            out.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                out.push(b'\n');
+                out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                out.push(b'=');
+            }
+            out.extend_from_slice(value.as_bytes());
+            out.push(b'\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse_and_display() {
+        for target in ["stderr", "file:/var/log/ralph.log"] {
+            assert_eq!(target.parse::<LogTarget>().unwrap().to_string(), target);
+        }
+        #[cfg(unix)]
+        assert_eq!("syslog".parse::<LogTarget>(), Ok(LogTarget::Syslog));
+        assert!("file:".parse::<LogTarget>().is_err());
+        assert_eq!(
+            "stdout".parse::<LogTarget>().unwrap_err(),
+            "expected one of stderr, syslog, journald, or file:<path>"
+        );
+    }
+
+    #[test]
+    fn file_lines_carry_the_level_and_context() {
+        let context = Context {
+            session: Some("20260101-000000-1".to_string()),
+            provider: Some("claude".to_string()),
+            iteration: Some(2),
+        };
+        assert_eq!(
+            format_line("2026-01-01T00:00:00Z", Level::Warn, "a\nb", &context),
+            "2026-01-01T00:00:00Z warn   [session=20260101-000000-1 provider=claude iteration=2] a | b\n"
+        );
+        assert_eq!(
+            format_line(
+                "2026-01-01T00:00:00Z",
+                Level::Info,
+                "hi",
+                &Context::default()
+            ),
+            "2026-01-01T00:00:00Z info   hi\n"
+        );
+    }
+
+    #[test]
+    fn file_targets_append_ralphs_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ralph.log");
+        let reporter = LogTarget::File(path.clone())
+            .reporter(false)
+            .unwrap()
+            .unwrap();
+        reporter.info("");
+        reporter.banner("Iteration 1 / 3");
+        reporter.progress("Downloading…", false);
+        reporter.debug("hidden");
+        reporter.error("Provider 'droid' exited with code 3");
+        let text = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2, "{text}");
+        assert!(lines[0].ends_with(" notice Iteration 1 / 3"), "{text}");
+        assert!(
+            lines[1].ends_with(" error  Provider 'droid' exited with code 3"),
+            "{text}"
+        );
+        assert!(LogTarget::Stderr.reporter(false).unwrap().is_none());
+    }
+
+    #[cfg(all(feature = "journald", unix))]
+    #[test]
+    fn journal_fields_use_the_native_format() {
+        let encoded = journald::encode(&[
+            ("MESSAGE", "two\nlines".to_string()),
+            ("PRIORITY", "4".to_string()),
+        ]);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nPRIORITY=4\n");
+        assert_eq!(encoded, expected);
+    }
+}
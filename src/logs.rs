@@ -0,0 +1,196 @@
+use crate::error::Result;
+use crate::render;
+use crate::reporter;
+use crate::session::{self, StoredSession};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How often `--follow` checks for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Options for `ralph logs`.
+#[derive(Debug, Default)]
+pub struct LogsOptions {
+    /// Session id; the most recent session when `None`
+    pub session: Option<String>,
+    /// Only show this iteration
+    pub iteration: Option<u32>,
+    /// Keep printing output as the session writes it
+    pub follow: bool,
+    /// Render stream-json through the pretty printer
+    pub pretty: bool,
+    /// Show the captured stderr instead of stdout
+    pub stderr: bool,
+}
+
+/// Print a session's captured output to `out`.
+pub fn show(opts: &LogsOptions, out: &mut dyn Write) -> Result<()> {
+    let active = match (&opts.session, opts.follow) {
+        (None, true) => session::find_active_session()?,
+        _ => None,
+    };
+    let session = match active {
+        Some(session) => session,
+        None => session::find_session(opts.session.as_deref())?,
+    };
+
+    if opts.follow {
+        return Ok(follow(&session, opts, out)?);
+    }
+
+    let logs = selected_logs(&session, opts)?;
+    if logs.is_empty() {
+        return Err(no_output_error(&session, opts).into());
+    }
+    let mut announced = HashSet::new();
+    for (iteration, path) in logs {
+        if announced.insert(iteration) {
+            announce(&session, iteration, opts);
+        }
+        let mut content = Vec::new();
+        File::open(&path)?.read_to_end(&mut content)?;
+        write_chunk(&content, opts.pretty, out)?;
+    }
+    Ok(())
+}
+
+/// Tail the session's iteration logs until its transcript records the end,
+/// or until its heartbeat stops (the run crashed without recording one).
+fn follow(session: &StoredSession, opts: &LogsOptions, out: &mut dyn Write) -> io::Result<()> {
+    let mut offsets: HashMap<PathBuf, u64> = HashMap::new();
+    let mut announced = HashSet::new();
+
+    loop {
+        // Check before reading so output flushed just before the end isn't missed.
+        let finished = session.is_finished()?;
+        let crashed = !finished && !session.is_running();
+        let mut progressed = false;
+
+        for (iteration, path) in selected_logs(session, opts)? {
+            let offset = offsets.entry(path.clone()).or_insert(0);
+            let chunk = read_complete_lines(&path, *offset)?;
+            if chunk.is_empty() {
+                continue;
+            }
+            if announced.insert(iteration) {
+                announce(session, iteration, opts);
+            }
+            *offset += chunk.len() as u64;
+            write_chunk(&chunk, opts.pretty, out)?;
+            progressed = true;
+        }
+
+        if finished && !progressed {
+            return Ok(());
+        }
+        if crashed && !progressed {
+            reporter::info(&format!("==> {} is no longer running <==", session.id));
+            return Ok(());
+        }
+        if !progressed {
+            out.flush()?;
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+/// The `==> <session> iteration <n> <==` header before an iteration's
+/// output.
+fn announce(session: &StoredSession, iteration: u32, opts: &LogsOptions) {
+    let stream = if opts.stderr { " stderr" } else { "" };
+    reporter::info(&format!(
+        "==> {} iteration {}{} <==",
+        session.id, iteration, stream
+    ));
+}
+
+/// The files to show, in order: stdout logs, or stderr chunks with
+/// `--stderr`.
+fn selected_logs(session: &StoredSession, opts: &LogsOptions) -> io::Result<Vec<(u32, PathBuf)>> {
+    let logs = if opts.stderr {
+        session.stderr_logs()?
+    } else {
+        session.iteration_logs()?
+    };
+    Ok(match opts.iteration {
+        Some(n) => logs.into_iter().filter(|(i, _)| *i == n).collect(),
+        None => logs,
+    })
+}
+
+/// Read from `offset` up to (and including) the last newline, so a line
+/// still being written is picked up whole on the next poll.
+fn read_complete_lines(path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    match buf.iter().rposition(|b| *b == b'\n') {
+        Some(pos) => buf.truncate(pos + 1),
+        None => buf.clear(),
+    }
+    Ok(buf)
+}
+
+fn write_chunk(content: &[u8], pretty: bool, out: &mut dyn Write) -> io::Result<()> {
+    if !pretty {
+        return out.write_all(content);
+    }
+    for line in String::from_utf8_lossy(content).lines() {
+        if let Some(rendered) = render::render_line(line) {
+            writeln!(out, "{rendered}")?;
+        }
+    }
+    Ok(())
+}
+
+fn no_output_error(session: &StoredSession, opts: &LogsOptions) -> io::Error {
+    let output = if opts.stderr { "stderr" } else { "output" };
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        match opts.iteration {
+            Some(n) => format!("Session '{}' has no {output} for iteration {n}", session.id),
+            None => format!("Session '{}' has no captured {output}", session.id),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn read_complete_lines_stops_at_last_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("iteration-1.log");
+        fs::write(&path, "one\ntwo\npart").unwrap();
+        assert_eq!(read_complete_lines(&path, 0).unwrap(), b"one\ntwo\n");
+        assert_eq!(read_complete_lines(&path, 4).unwrap(), b"two\n");
+        assert!(read_complete_lines(&path, 8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_chunk_pretty_renders_events() {
+        let mut out = Vec::new();
+        let content = concat!(
+            r#"{"type":"system","subtype":"init"}"#,
+            "\n",
+            r#"{"type":"item.completed","item":{"type":"agent_message","text":"Hi"}}"#,
+            "\n"
+        );
+        write_chunk(content.as_bytes(), true, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Hi\n");
+    }
+
+    #[test]
+    fn write_chunk_raw_is_verbatim() {
+        let mut out = Vec::new();
+        write_chunk(b"{\"type\":\"x\"}\n", false, &mut out).unwrap();
+        assert_eq!(out, b"{\"type\":\"x\"}\n");
+    }
+}
@@ -1,383 +1,1972 @@
 use clap::Parser;
-use std::fs;
+use ralph::backlog::{self, BacklogDiff, Snapshot};
+use ralph::config::{self, EffectiveConfig, Overrides, ensure_config, read_system_prompt};
+use ralph::env_policy::{self, EnvPolicy};
+use ralph::error::{RalphError, Result};
+use ralph::exec::SystemRunner;
+use ralph::fsutil;
+use ralph::lock::{self, RepoLock};
+use ralph::log_target::LogTarget;
+use ralph::metrics::LoopMetrics;
+use ralph::priority;
+use ralph::progress::ProgressReport;
+use ralph::providers::{self, VALID_PROVIDERS};
+use ralph::reporter::{self, JsonReporter, QuietReporter, Reporter, TerminalReporter};
+use ralph::runner::{self, LoopRunner, RunOptions};
+use ralph::sandbox::{Sandbox, SandboxOptions};
+use ralph::stream::{self, LineLimit};
+use ralph::tracker::PreflightOptions;
+use ralph::workspace::{RepoOutcome, Status, WorkspaceSummary};
+use ralph::{
+    audit, build_info, clean, crash, epic, history, kill, logs, pricing, probe, prompt, prompts,
+    report, resume, run, schema, session, setup, smoke, state, stats, status, templates, tracker,
+    uninstall, upgrade, watch, workspace,
+};
+use std::ffi::OsStr;
+use std::fs::File;
 use std::io;
-use std::path::PathBuf;
-use std::process::{Command, ExitCode, Stdio};
-
-mod upgrade;
-
-/// Default system prompt content (equivalent to script's built-in PROMPT)
-const DEFAULT_SYSTEM_PROMPT: &str = r#"Use bd (beads) for task tracking. Follow these steps:
-
-1. Run 'bd ready' to find the next available task (not blocked by dependencies)
-2. Run 'bd show <id>' to read the task details and acceptance criteria
-3. Run 'bd update <id> --status in_progress' to claim the task
-4. Implement the task according to the acceptance criteria. You need to read docs under `tasks` for better understanding of whole context.
-5. Run quality gates (bun run build, cargo build if applicable)
-6. Commit your changes with a descriptive message
-7. Run `bd update <id> ...` to update beads more info. for future references: 
-  - Run `bd update <id> --design ...`: update design solution summary in markdown format, around 500 words
-  - Run `bd update <id> --notes ...`: update summaries of code change or document updates in markdown format, around 500 words
-8. Run 'bd close <id>' to mark the task as complete
-
-IMPORTANT:
-- ONLY DO ONE TASK AT A TIME
-- Do not start tasks that are blocked (have uncompleted dependencies)
-- Verify all acceptance criteria before closing the task
-"#;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+mod completions;
+mod man;
 
 /// Ralph CLI - A dispatcher for AI provider agents
 #[derive(Parser, Debug)]
 #[command(name = "ralph")]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Only print warnings and errors (provider output is unaffected)
+    #[arg(long, short = 'q', global = true)]
+    quiet: bool,
+
+    /// Also print diagnostics, such as tool output ralph couldn't parse
+    #[arg(long, short = 'v', global = true)]
+    verbose: bool,
+
+    /// How ralph's own messages are written to stderr
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    message_format: OutputFormat,
+
+    /// Where ralph's own messages go: stderr, syslog, journald or
+    /// file:<path> (provider output stays on stdout)
+    #[arg(long, global = true, value_name = "TARGET", default_value = "stderr")]
+    log_target: LogTarget,
+
     /// Subcommand to run
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
-/// Supported AI providers
-const VALID_PROVIDERS: &[&str] = &["droid", "codex", "claude", "gemini"];
+impl Cli {
+    /// The reporter selected by `--quiet`, `--verbose`, `--message-format`
+    /// and `--log-target`.
+    fn reporter(&self) -> Box<dyn Reporter> {
+        let text = || TerminalReporter::stderr().verbose(self.verbose);
+        let json = || JsonReporter::stderr().verbose(self.verbose);
+        let target = self.log_target.reporter(self.verbose).unwrap_or_else(|e| {
+            text().warn(&format!(
+                "Can't log to {} ({}); logging to stderr",
+                self.log_target, e
+            ));
+            None
+        });
+        if let Some(target) = target {
+            return if self.quiet {
+                Box::new(QuietReporter(target))
+            } else {
+                target
+            };
+        }
+        match (self.message_format, self.quiet) {
+            (OutputFormat::Text, false) => Box::new(text()),
+            (OutputFormat::Text, true) => Box::new(QuietReporter(text())),
+            (OutputFormat::Json, false) => Box::new(json()),
+            (OutputFormat::Json, true) => Box::new(QuietReporter(json())),
+        }
+    }
+}
+
+/// Value parser for `--provider`. Any string is accepted here so that
+/// `providers::validate_provider` keeps owning the error message (and config defaults
+/// go through the same check), but the known providers are advertised as
+/// possible values so shell completion can offer them.
+#[derive(Clone, Debug)]
+struct ProviderValueParser;
+
+impl clap::builder::TypedValueParser for ProviderValueParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        clap::builder::StringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(
+            VALID_PROVIDERS
+                .iter()
+                .map(|p| clap::builder::PossibleValue::new(*p)),
+        ))
+    }
+}
+
+/// Flags shared by every command that runs a provider (`once`, `loop`,
+/// `run` and `watch`), so their help text and defaults can't drift apart.
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+struct RunArgs {
+    /// AI provider to use (default: droid, or `provider` from config.toml);
+    /// `ralph providers` also lists the ones config.toml defines
+    #[arg(long, value_parser = ProviderValueParser)]
+    provider: Option<String>,
+    /// Print the effective configuration, and where each value came from,
+    /// instead of running
+    #[arg(long)]
+    print_config: bool,
+    /// Run even if the task tracker (bd) is missing or not initialized,
+    /// with a warning instead of an error
+    #[arg(long)]
+    ignore_missing_bd: bool,
+    /// Run `bd init` without asking when there's no beads database here
+    #[arg(long)]
+    auto_init_bd: bool,
+    /// Don't use a task tracker: same as `tracker = "none"`
+    #[arg(long)]
+    no_bd: bool,
+    /// Run even if this repository's lock (`.ralph/lock`) is held by
+    /// another ralph
+    #[arg(long)]
+    force_lock: bool,
+    /// Which environment variables the provider inherits: inherit, allowlist
+    /// or blocklist (default: inherit, or `[env] policy` from config.toml)
+    #[arg(long, value_name = "POLICY", value_parser = env_policy::VALID_POLICIES.to_vec())]
+    env_policy: Option<String>,
+    /// Lower the provider's CPU priority by this much, 0 to 19 (default: 0,
+    /// or `nice` from config.toml)
+    #[arg(long, value_name = "LEVEL")]
+    nice: Option<String>,
+    /// The provider's I/O scheduling class on Linux: none, idle or
+    /// best-effort[:0-7] (default: none, or `ionice` from config.toml)
+    #[arg(long, value_name = "CLASS")]
+    ionice: Option<String>,
+    /// Check the provider's stream-json against the format ralph expects
+    /// and summarize anything unrecognized at the end
+    #[arg(long)]
+    validate_stream: bool,
+    /// What the provider prints: stream-json, or its own plain text to
+    /// read as it works (default: stream-json)
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = providers::OUTPUT_FORMATS.to_vec(),
+        conflicts_with = "validate_stream"
+    )]
+    output_format: Option<String>,
+    /// Label the sessions as running this prompt variant, to compare
+    /// variants with `ralph stats --by-variant` (changes nothing about the run)
+    #[arg(long, value_name = "NAME", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    prompt_variant: Option<String>,
+    /// Label the sessions with TEXT, shown by `ralph history` (and filtered
+    /// with its --label), `ralph stats --by-label` and the metrics file, and
+    /// passed to the provider and gates as RALPH_LABEL
+    #[arg(long, value_name = "TEXT", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    label: Option<String>,
+    /// Let the provider act without asking for permission (claude's
+    /// --dangerously-skip-permissions, codex's --yolo, ...); same as
+    /// `auto_approve = true`
+    #[arg(long = "unsafe", visible_alias = "auto-approve")]
+    auto_approve: bool,
+    /// Model for the provider to use, passed with its own flag (e.g.
+    /// claude's --model, codex's -m)
+    #[arg(long, value_name = "NAME", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    model: Option<String>,
+    /// Pass ARG to the provider CLI after its own flags, e.g.
+    /// `--provider-arg=--model --provider-arg=opus` (repeatable)
+    #[arg(long = "provider-arg", value_name = "ARG", allow_hyphen_values = true)]
+    provider_args: Vec<String>,
+    /// Run the provider and the task tracker in PATH instead of the
+    /// current directory
+    #[arg(long, value_name = "PATH")]
+    cwd: Option<PathBuf>,
+    /// Kill the provider, and everything it started, when one iteration
+    /// runs longer than this many seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    /// Run even if the provider doesn't match its pin in
+    /// `[provider_versions]`, with a warning instead of an error
+    #[arg(long)]
+    allow_version_drift: bool,
+    /// Show and log every provider output line, instead of runs of
+    /// identical lines as one (same as `collapse_repeats = 0`)
+    #[arg(long)]
+    no_collapse_repeats: bool,
+    /// Kill the provider once a single run has printed more than SIZE
+    /// (e.g. 64M, 2G; default 512M), failing that iteration
+    #[arg(long, value_name = "SIZE")]
+    max_output_bytes: Option<String>,
+}
+
+impl RunArgs {
+    /// Resolve the configuration with these flags on top of the command's
+    /// own (`overrides`). With `--print-config`, print it and return `None`.
+    fn config(&self, overrides: Overrides) -> Result<Option<EffectiveConfig>> {
+        let config = config::resolve(&Overrides {
+            provider: self.provider.clone(),
+            auto_init_bd: self.auto_init_bd.then(|| "true".to_string()),
+            tracker: self.no_bd.then(|| tracker::NONE.to_string()),
+            env_policy: self.env_policy.clone(),
+            nice: self.nice.clone(),
+            ionice: self.ionice.clone(),
+            collapse_repeats: self.no_collapse_repeats.then(|| "0".to_string()),
+            max_output_bytes: self.max_output_bytes.clone(),
+            auto_approve: self.auto_approve.then(|| "true".to_string()),
+            ..overrides
+        })?;
+        if self.print_config {
+            print!("{}", config.render_text());
+            let env = config.env_filter();
+            if env.policy != EnvPolicy::Inherit {
+                let provider = &config.provider.value;
+                println!(
+                    "\n# Environment for {} ({}): {}",
+                    provider,
+                    env.policy,
+                    env.names(provider).join(", ")
+                );
+            }
+            return Ok(None);
+        }
+        Ok(Some(config))
+    }
+
+    /// Check that the provider is installed and can use `--model`, the
+    /// variables its config reads, its pinned version and the resolved
+    /// tracker before the first provider call; see
+    /// [`providers::resolve_provider_binary`], [`env_policy::EnvFilter::vars`],
+    /// [`check_provider_version`] and [`tracker::preflight`].
+    fn preflight(&self, config: &EffectiveConfig) -> Result<bool> {
+        providers::resolve_provider_binary(&config.provider.value)?;
+        config.env_filter().vars(&config.provider.value)?;
+        if let Some(model) = &self.model {
+            providers::model_args(&config.provider.value, model)?;
+        }
+        check_provider_version(config, &config.provider.value, self.allow_version_drift)?;
+        tracker::preflight(
+            config.tracker().as_ref(),
+            &SystemRunner,
+            PreflightOptions {
+                ignore_missing: self.ignore_missing_bd,
+                auto_init: config.auto_init_bd.value,
+            },
+        )
+    }
+
+    /// Take this repository's lock for `command`; see [`take_lock`].
+    fn lock(&self, command: &str) -> Result<RepoLock> {
+        take_lock(command, self.force_lock)
+    }
+
+    /// The error for a run whose last provider invocation failed as
+    /// `summary` says, if it did.
+    fn provider_error(
+        &self,
+        config: &EffectiveConfig,
+        summary: &runner::RunSummary,
+    ) -> Option<RalphError> {
+        let provider = config.provider.value.clone();
+        if summary.timed_out > 0 {
+            return Some(RalphError::ProviderTimeout {
+                provider,
+                secs: self.timeout.unwrap_or_default(),
+            });
+        }
+        if summary.over_limit > 0 {
+            return Some(RalphError::ProviderOutputLimit {
+                provider,
+                limit: config.max_output_bytes.value,
+            });
+        }
+        if let Some(signal) = summary.signal {
+            return Some(RalphError::ProviderKilled { provider, signal });
+        }
+        (summary.exit_code != 0).then_some(RalphError::ProviderFailed {
+            provider,
+            code: summary.exit_code,
+        })
+    }
+
+    /// Read the system prompt, giving the library's run options for the
+    /// resolved provider (with the default iteration budget).
+    fn resolve(config: &EffectiveConfig) -> Result<RunOptions> {
+        apply_provider_settings(config);
+        let prompt = config::system_prompt(config)?;
+        Ok(RunOptions::new(config.provider.value.as_str(), prompt))
+    }
+}
+
+/// Whether a loop appends instructions for the completion marker to a
+/// prompt that never mentions it; shared by `loop` and `watch`.
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+struct MarkerArgs {
+    /// Append instructions for printing the completion marker when the
+    /// prompt never mentions it (the default)
+    #[arg(long, overrides_with = "no_inject_marker_instructions")]
+    inject_marker_instructions: bool,
+    /// Send the prompt as it is even when it never mentions the completion
+    /// marker (ralph warns that the loop will likely run its whole budget)
+    #[arg(long, overrides_with = "inject_marker_instructions")]
+    no_inject_marker_instructions: bool,
+}
+
+impl MarkerArgs {
+    fn inject(&self) -> bool {
+        !self.no_inject_marker_instructions
+    }
+}
+
+/// The flags of `ralph loop` that apply to each loop it runs: the only
+/// one, or one per repository with `--workspace`.
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+#[command(group(clap::ArgGroup::new("planning").args(["plan_first", "plan_only"]).multiple(true)))]
+struct LoopArgs {
+    #[command(flatten)]
+    run: RunArgs,
+    /// Maximum number of iterations (default: 10, must be a positive integer)
+    #[arg(long)]
+    iterations: Option<String>,
+    /// Stop with an error after an iteration whose provider fails, is
+    /// killed or times out, instead of going on to the next one
+    #[arg(long)]
+    stop_on_failure: bool,
+    /// Only work on tasks under this epic (a bd issue id)
+    #[arg(long, value_name = "ID")]
+    epic: Option<String>,
+    /// Run `bd sync` (or `[tracker] sync_command`) after the loop
+    #[arg(long)]
+    bd_sync: bool,
+    /// Like --bd-sync, but fail if the sync fails
+    #[arg(long)]
+    bd_sync_required: bool,
+    /// Re-read the system prompt before every iteration, so edits take
+    /// effect without restarting the loop
+    #[arg(long)]
+    reload_prompt: bool,
+    /// Make the first iteration a planning pass: the agent reviews the
+    /// backlog and updates estimates and dependencies, writing no code
+    #[arg(long)]
+    plan_first: bool,
+    /// Stop after the planning iteration (implies --plan-first)
+    #[arg(long)]
+    plan_only: bool,
+    /// Planning prompt to use instead of the built-in one
+    #[arg(long, value_name = "FILE", requires = "planning")]
+    plan_prompt: Option<PathBuf>,
+    /// Add what the agent said while planning to the next iteration's
+    /// prompt
+    #[arg(long, requires = "plan_first", conflicts_with = "plan_only")]
+    carry_plan: bool,
+    /// Run in a throwaway git worktree of this repository (under
+    /// .ralph/worktrees/) instead of the checkout itself
+    #[arg(long, conflicts_with = "cwd")]
+    sandbox: bool,
+    /// Keep the sandbox afterwards instead of removing it
+    #[arg(long, requires = "sandbox")]
+    sandbox_keep: bool,
+    /// Carry uncommitted changes to tracked files into the sandbox
+    #[arg(long, requires = "sandbox")]
+    sandbox_carry_changes: bool,
+    /// Let the sandboxed loop use this checkout's task tracker rather
+    /// than a copy of it
+    #[arg(long, requires = "sandbox")]
+    sandbox_shared_tracker: bool,
+    #[command(flatten)]
+    marker: MarkerArgs,
+    /// Print the prompt the first working iteration would be sent,
+    /// including any injected marker instructions, instead of running
+    #[arg(long)]
+    print_prompt: bool,
+    /// Keep a markdown progress report in FILE: a section per iteration
+    /// under a status header, rewritten as the loop runs (with --workspace,
+    /// one per repository, relative to it)
+    #[arg(long, value_name = "FILE")]
+    report_file: Option<PathBuf>,
+    /// Write the loop's metrics to FILE in the Prometheus text format, for
+    /// node_exporter's textfile collector (default: `metrics_file` from
+    /// config.toml, if any)
+    #[arg(long, value_name = "FILE")]
+    metrics_file: Option<String>,
+    /// Update the metrics file after every iteration, not only at the end
+    #[arg(long)]
+    metrics_live: bool,
+    /// Run COMMAND after every iteration; the loop only stops as complete
+    /// once it passes. Repeatable, and replaces `gates` from config.toml
+    #[arg(long, value_name = "COMMAND")]
+    gate: Vec<String>,
+    /// Commit the loop's state (.ralph/state.json) after every iteration,
+    /// so `ralph resume` can continue it on another machine after a pull
+    #[arg(long)]
+    portable_state: bool,
+}
 
 #[derive(clap::Subcommand, Debug)]
 enum Commands {
     /// Display version information
-    Version,
+    Version {
+        /// Print build metadata as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Execute a single AI provider call (equivalent to ralph-once.sh)
     Once {
-        /// AI provider to use (default: droid)
-        /// Available: droid, codex, claude, gemini
-        #[arg(long, default_value = "droid")]
-        provider: String,
+        #[command(flatten)]
+        run: RunArgs,
     },
     /// Execute AI provider in a loop until completion or iteration limit (equivalent to ralph-loop.sh)
     Loop {
-        /// AI provider to use (default: droid)
-        /// Available: droid, codex, claude, gemini
-        #[arg(long, default_value = "droid")]
-        provider: String,
-        /// Maximum number of iterations (default: 10, must be a positive integer)
-        #[arg(long, default_value = "10")]
-        iterations: String,
+        #[command(flatten)]
+        args: LoopArgs,
+        /// Run a loop in each repository listed in this manifest, one after
+        /// another (a TOML file of `[[repo]]` entries)
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["epic", "sandbox_shared_tracker"])]
+        workspace: Option<PathBuf>,
+        /// With --workspace, skip the remaining repositories once one fails
+        #[arg(long, requires = "workspace")]
+        stop_on_repo_failure: bool,
+    },
+    /// Continue the most recent interrupted `loop` session in this directory
+    Resume {
+        /// Don't ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+        /// Resume even though the system prompt changed since the session last ran
+        #[arg(long)]
+        force: bool,
+        /// Run even if the task tracker (bd) is missing or not initialized
+        #[arg(long)]
+        ignore_missing_bd: bool,
+        /// Run `bd init` without asking when there's no beads database here
+        #[arg(long)]
+        auto_init_bd: bool,
+        /// Don't use a task tracker: same as `tracker = "none"`
+        #[arg(long)]
+        no_bd: bool,
+        /// Run even if this repository's lock (`.ralph/lock`) is held by
+        /// another ralph
+        #[arg(long)]
+        force_lock: bool,
+        /// Re-read the system prompt before every iteration, so edits take
+        /// effect without restarting the loop
+        #[arg(long)]
+        reload_prompt: bool,
+        /// Commit the loop's state (.ralph/state.json) after every
+        /// iteration; a loop that was already portable stays so
+        #[arg(long)]
+        portable_state: bool,
+        /// Run even if the provider doesn't match its pin in
+        /// `[provider_versions]`, with a warning instead of an error
+        #[arg(long)]
+        allow_version_drift: bool,
+        /// Let the provider act without asking for permission; same as
+        /// `auto_approve = true`
+        #[arg(long = "unsafe", visible_alias = "auto-approve")]
+        auto_approve: bool,
+    },
+    /// Work a single bd task: claim it, run the provider once, and report
+    /// whether it was closed (exit 0 closed, 75 still open, 1 error)
+    Run {
+        /// bd issue id, e.g. bd-123
+        id: String,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Poll `bd ready` and run a loop whenever tasks become ready
+    Watch {
+        #[command(flatten)]
+        run: RunArgs,
+        /// Seconds between polls of `bd ready`
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// Iteration budget for each loop started on wake-up (default: 10, or `iterations` from config.toml)
+        #[arg(long)]
+        iterations: Option<String>,
+        #[command(flatten)]
+        marker: MarkerArgs,
+    },
+    /// Set up this repository for ralph: `.ralph/`, `tasks/`, and with a
+    /// template a tailored prompt, quality gates and task conventions
+    Init {
+        /// Project template (default: minimal); see --list-templates
+        #[arg(long, value_name = "NAME")]
+        template: Option<String>,
+        /// List the available templates
+        #[arg(long, conflicts_with = "template")]
+        list_templates: bool,
+    },
+    /// Interactively configure the default provider, iterations and update check
+    Setup {
+        /// Default provider (skips the question)
+        #[arg(long, value_parser = ProviderValueParser)]
+        provider: Option<String>,
+        /// Default iterations for `ralph loop` (skips the question)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: Option<u32>,
+        /// Check for updates after runs (skips the question)
+        #[arg(long)]
+        update_check: Option<bool>,
+        /// Don't check whether bd is installed and initialized
+        #[arg(long)]
+        skip_bd_check: bool,
+        /// Don't use a task tracker: writes `tracker = "none"` and seeds a
+        /// system prompt without bd instructions
+        #[arg(long)]
+        no_bd: bool,
+        /// Don't ask anything; keep current values for unspecified settings
+        #[arg(long)]
+        non_interactive: bool,
+    },
+    /// Work with the system prompt
+    Prompt {
+        #[command(subcommand)]
+        command: PromptCommand,
+    },
+    /// Write a markdown summary of a session's work (e.g. for a PR description)
+    Report {
+        /// Session id (default: the most recent session)
+        #[arg(long)]
+        session: Option<String>,
+        /// Write the report to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// List recorded sessions, or work with them
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        /// Only sessions started since this date (YYYY-MM-DD) or age (e.g. 30d)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only sessions run with this --label
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Summarize what ralph has accomplished across recorded sessions
+    Stats {
+        /// Only count sessions started within this long ago (e.g. 30d, 12h)
+        #[arg(long)]
+        since: Option<String>,
+        /// Break the figures down per repository
+        #[arg(long)]
+        by_repo: bool,
+        /// Break the figures down per `--prompt-variant`
+        #[arg(long)]
+        by_variant: bool,
+        /// Break the figures down per `--label`
+        #[arg(long)]
+        by_label: bool,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Write man pages to a directory (for packagers)
+    #[command(hide = true)]
+    Man {
+        /// Directory to write `ralph.1` and the per-subcommand pages to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Report and prune old logs, sessions, caches and leftover artifacts
+    Clean {
+        /// Delete items older than this (e.g. 30d, 12h; default: 30d, or `[clean] older_than` from config.toml)
+        #[arg(long)]
+        older_than: Option<String>,
+        /// List what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
     },
     /// Upgrade ralph to the latest released version
-    Upgrade,
+    Upgrade {
+        /// Only check that the installed binary runs, and restore the
+        /// backup left by an interrupted upgrade if it doesn't
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Check whether a newer release exists (exit 0: current, 10: update available, 20: check failed)
+    CheckUpdate,
+    /// Remove the ralph binary (and with --purge, ~/.Ralph/)
+    Uninstall {
+        /// Don't ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+        /// Also delete ~/.Ralph/ (config, system prompt, sessions and logs)
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Stop the loop running in a repository from another terminal, as
+    /// Ctrl-C in its own would
+    Kill {
+        /// Repository whose run to stop (default: the current directory's)
+        #[arg(long, value_name = "PATH")]
+        repo: Option<PathBuf>,
+        /// Kill the run and its provider right away instead of interrupting
+        #[arg(long)]
+        force: bool,
+        /// Kill the run if it hasn't stopped this many seconds after the
+        /// interrupt
+        #[arg(long, value_name = "SECS", default_value_t = kill::DEFAULT_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
+    /// Show the effective runtime configuration (read-only)
+    Status {
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Show the captured provider output of a session (the latest by default)
+    Logs {
+        /// Session id to show (default: the most recent session; with
+        /// --follow, the one currently running)
+        #[arg(long)]
+        session: Option<String>,
+        /// Only show this iteration
+        #[arg(long)]
+        iteration: Option<u32>,
+        /// Keep printing output while the session is still running
+        #[arg(long, short = 'f')]
+        follow: bool,
+        /// How to display stored stream-json (default: raw, or pretty with --follow)
+        #[arg(long, value_enum)]
+        output: Option<LogOutput>,
+        /// Show the provider's captured stderr instead of its output
+        #[arg(long)]
+        stderr: bool,
+    },
+    /// List the providers, built in and from config.toml, with where their
+    /// CLIs are and their versions
+    Providers {
+        /// Probe each provider again instead of using cached results
+        #[arg(long)]
+        refresh: bool,
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List or change the model prices `ralph stats` works out cost with
+    Pricing {
+        #[command(subcommand)]
+        command: PricingCommand,
+    },
+    /// Show or check the log of every command ralph has run
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
 }
 
-/// Get the Ralph configuration directory path (~/.Ralph/)
-fn get_config_dir() -> io::Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| {
-        io::Error::new(
-            io::ErrorKind::NotFound,
-            "Could not determine home directory",
-        )
-    })?;
-    Ok(home.join(".Ralph"))
+#[derive(clap::Subcommand, Debug)]
+enum PromptCommand {
+    /// Check the system prompt against what ralph expects (exits 1 on errors)
+    Lint,
+    /// Replace the system prompt with a built-in template
+    Reset {
+        /// Template to use (default: the configured tracker's)
+        #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(prompts::TRACKERS))]
+        tracker: Option<String>,
+        /// Don't ask before replacing an edited prompt
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Send the provider a trivial built-in prompt and check the reply,
+    /// to verify its install, login and output format (exits 1 on failure)
+    Test {
+        /// AI provider to test (default: the configured one)
+        #[arg(long, value_parser = ProviderValueParser)]
+        provider: Option<String>,
+        /// Give up waiting for the reply after this many seconds
+        #[arg(long, value_name = "SECS", default_value_t = smoke::DEFAULT_TIMEOUT.as_secs())]
+        timeout: u64,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum PricingCommand {
+    /// Print each model's price
+    List,
+    /// Set a model's price, in USD per million input and output tokens
+    Set {
+        /// Model name, e.g. claude-sonnet-4-5 (also prices names starting
+        /// with it)
+        model: String,
+        /// USD per million input tokens
+        input: f64,
+        /// USD per million output tokens
+        output: f64,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum AuditCommand {
+    /// Print the recorded commands, oldest first
+    Show {
+        /// Only commands run since this date (YYYY-MM-DD) or age (e.g. 7d)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Check that no line was edited, inserted or removed (exits 1 if so)
+    Verify,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum HistoryCommand {
+    /// Export sessions as CSV or JSON, one row per run (or per iteration)
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Only sessions started since this date (YYYY-MM-DD) or age (e.g. 30d)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only sessions run with this --label
+        #[arg(long)]
+        label: Option<String>,
+        /// One row per iteration instead of one per run
+        #[arg(long)]
+        per_iteration: bool,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+/// File format for `ralph history export`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ExportFormat {
+    Csv,
+    Json,
 }
 
-/// Get the system prompt file path (~/.Ralph/system-prompt.md)
-fn get_system_prompt_path() -> io::Result<PathBuf> {
-    Ok(get_config_dir()?.join("system-prompt.md"))
+/// Display mode for captured provider output
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogOutput {
+    /// What the provider printed, as logged (runs of identical lines
+    /// collapsed, unless `collapse_repeats = 0`)
+    Raw,
+    /// Stream-json events rendered as readable text
+    Pretty,
 }
 
-/// Ensure the configuration directory and default system prompt file exist.
-/// Creates them if they don't exist.
-fn ensure_config() -> io::Result<()> {
-    let config_dir = get_config_dir()?;
+/// Output format for informational subcommands
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
 
-    // Create config directory if it doesn't exist
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir)?;
-        eprintln!("Created configuration directory: {}", config_dir.display());
+/// With `update_check = true` in config.toml, mention a newer release after
+/// a run. Failures are ignored: this must never get in the way.
+fn print_update_hint(update_check: bool) {
+    if !update_check {
+        return;
+    }
+    if let Ok(check @ upgrade::UpdateCheck::Available { .. }) = upgrade::check_update() {
+        reporter::info(&check.message());
     }
+}
 
-    // Create default system prompt file if it doesn't exist
-    let prompt_path = get_system_prompt_path()?;
-    if !prompt_path.exists() {
-        fs::write(&prompt_path, DEFAULT_SYSTEM_PROMPT)?;
-        eprintln!("Created default system prompt: {}", prompt_path.display());
+/// Hand the settings for starting providers and watching their output
+/// (the environment policy, login phrases, the line and output limits) to
+/// the library before the first provider starts.
+fn apply_provider_settings(config: &EffectiveConfig) {
+    providers::set_login_phrases(config.login_phrases.value.clone());
+    providers::set_config_args(config.provider_args.value.clone());
+    providers::set_templates(config.provider_templates.value.clone());
+    providers::set_prompt_via(config.prompt_via.value.clone());
+    providers::set_auto_approve(config.auto_approve.value);
+    env_policy::set(config.env_filter());
+    stream::set_line_limit(LineLimit {
+        max: config.max_line_bytes.value,
+        keep_full: config.keep_long_lines.value,
+    });
+    stream::set_collapse_repeats(config.collapse_repeats.value);
+    stream::set_max_output(config.max_output_bytes.value);
+    let priority = config.priority();
+    if priority.io.is_some() && !cfg!(target_os = "linux") {
+        reporter::warn("ionice only works on Linux; providers keep their I/O priority");
     }
+    priority::set(priority);
+}
 
-    Ok(())
+/// Check `provider` against its pin in `[provider_versions]`, if it has
+/// one, probing it through the cache, and record the version found in the
+/// sessions to come. With `allow_drift` a mismatch is only a warning.
+fn check_provider_version(
+    config: &EffectiveConfig,
+    provider: &str,
+    allow_drift: bool,
+) -> Result<()> {
+    let Some(required) = config.provider_versions.value.get(provider) else {
+        return Ok(());
+    };
+    let probe = probe::probe(provider, false);
+    if let Some(version) = &probe.version {
+        session::set_provider_version(version);
+    }
+    let Err(found) = probe::check_pin(&probe, required) else {
+        return Ok(());
+    };
+    let origin = config.provider_versions.source;
+    if allow_drift {
+        reporter::warn(&format!(
+            "Provider '{provider}' is {found}, but the {origin} pins it to {required}; running anyway"
+        ));
+        return Ok(());
+    }
+    Err(RalphError::ProviderVersion {
+        provider: provider.to_string(),
+        found,
+        required: required.to_string(),
+        origin: origin.to_string(),
+    })
 }
 
-/// Read the system prompt from the configuration file.
-/// This function assumes ensure_config() has been called first.
-pub fn read_system_prompt() -> io::Result<String> {
-    let prompt_path = get_system_prompt_path()?;
-    fs::read_to_string(&prompt_path)
+/// Where to record the commands ralph runs: the audit log, unless `audit`
+/// is turned off. A config that doesn't resolve records nothing; the
+/// command reports what's wrong with it.
+fn audit_log() -> Option<PathBuf> {
+    let config = config::resolve(&Overrides::default()).ok()?;
+    if !config.audit.value {
+        return None;
+    }
+    audit::default_path().ok()
 }
 
-/// Validate that the provider is one of the supported providers.
-fn validate_provider(provider: &str) -> Result<(), String> {
-    if VALID_PROVIDERS.contains(&provider) {
-        Ok(())
-    } else {
-        Err(format!(
-            "Invalid provider '{}'\nAvailable providers: {}",
-            provider,
-            VALID_PROVIDERS.join(", ")
-        ))
+/// Create a sandbox for `loop --sandbox` and move into it. Tracker
+/// commands stay in the checkout unless `opts` copies the backlog into the
+/// sandbox, in which case syncing it (which would push the copy) is refused.
+fn enter_sandbox(config: &EffectiveConfig, opts: SandboxOptions) -> Result<Sandbox> {
+    if opts.copy_beads && config.tracker_sync.value {
+        return Err(RalphError::InvalidInput(
+            "Syncing the tracker after a sandboxed loop would push the sandbox's copy of the backlog; \
+             add --sandbox-shared-tracker to work on (and sync) the real one"
+                .to_string(),
+        ));
     }
+    if !opts.copy_beads {
+        tracker::set_dir(std::env::current_dir()?);
+    }
+    let sandbox = Sandbox::create(opts)?;
+    sandbox.enter()?;
+    reporter::info(&format!("Running in sandbox {}", sandbox.path().display()));
+    Ok(sandbox)
 }
 
-/// Validate that iterations is a positive integer (>0).
-fn validate_iterations(iterations: &str) -> Result<u32, String> {
-    match iterations.parse::<u32>() {
-        Ok(n) if n > 0 => Ok(n),
-        Ok(_) => Err("Error: iterations must be a positive integer".to_string()),
-        Err(_) => Err("Error: iterations must be a positive integer".to_string()),
-    }
-}
-
-/// Execute a provider command with the given system prompt.
-/// Returns the exit code from the provider process.
-fn execute_provider(provider: &str, prompt: &str) -> io::Result<i32> {
-    eprintln!("Using AI provider: {}", provider);
-
-    let status = match provider {
-        "droid" => Command::new("droid")
-            .args([
-                "exec",
-                "--output-format",
-                "stream-json",
-                "--skip-permissions-unsafe",
-            ])
-            .arg(prompt)
-            .status()?,
-        "codex" => Command::new("codex")
-            .args(["exec", "--yolo", "--json"])
-            .arg(prompt)
-            .status()?,
-        "claude" => Command::new("claude")
-            .args([
-                "-p",
-                "--verbose",
-                "--output-format",
-                "stream-json",
-                "--dangerously-skip-permissions",
-            ])
-            .arg(prompt)
-            .status()?,
-        "gemini" => Command::new("gemini")
-            .args(["-p", "--output-format", "stream-json", "--yolo"])
-            .arg(prompt)
-            .status()?,
-        _ => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Unknown provider: {}", provider),
-            ));
+/// `--cwd`'s directory, resolved against the one ralph was started in.
+fn working_dir(path: &Path) -> Result<PathBuf> {
+    let dir = std::env::current_dir()?.join(path);
+    match std::fs::metadata(&dir) {
+        Ok(meta) if meta.is_dir() => Ok(dir),
+        Ok(_) => Err(RalphError::InvalidInput(format!(
+            "--cwd {}: not a directory",
+            path.display()
+        ))),
+        Err(e) => Err(RalphError::InvalidInput(format!(
+            "--cwd {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+/// Take the lock of the repository in the current directory for
+/// `command`, so a second run here refuses to start; `force` takes it over.
+fn take_lock(command: &str, force: bool) -> Result<RepoLock> {
+    lock::acquire(&lock::lock_path()?, command, force)
+}
+
+/// Append `event` to session `session_id`'s transcript, warning on failure.
+fn record_event(session_id: Option<&str>, event: session::Event) {
+    let Some(id) = session_id else {
+        return;
+    };
+    if let Err(e) = session::find_session(Some(id)).and_then(|s| s.append(event)) {
+        reporter::warn(&format!("Failed to write session log: {}", e));
+    }
+}
+
+/// The whole backlog, or `None` when the tracker can't list it: logged with
+/// `--verbose`, or warned about when the tracker timed out.
+fn snapshot_backlog(config: &EffectiveConfig) -> Option<Snapshot> {
+    if tracker::timed_out() {
+        return None;
+    }
+    backlog::snapshot(&SystemRunner, config.tracker().as_ref())
+        .map_err(|e| match e {
+            RalphError::TrackerTimeout { .. } => {
+                reporter::warn(&format!("{}; skipping the backlog summary", e))
+            }
+            e => reporter::debug(&format!("Couldn't read the backlog: {}", e)),
+        })
+        .ok()
+}
+
+/// Print what a loop changed in the backlog since `before` and record it in
+/// session `session_id`, returning what was printed. Without both
+/// snapshots, fall back to the tracker's own overview (bd list --pretty),
+/// unless the tracker has timed out.
+fn report_backlog(
+    config: &EffectiveConfig,
+    before: Option<Snapshot>,
+    session_id: Option<&str>,
+) -> Option<String> {
+    let diff = before.and_then(|before| {
+        snapshot_backlog(config).map(|after| BacklogDiff::between(&before, &after))
+    });
+    match diff {
+        Some(diff) => {
+            let text = diff.render_text();
+            reporter::summary(&text);
+            record_event(session_id, session::Event::Backlog { diff });
+            Some(text)
         }
+        None if tracker::timed_out() => None,
+        None => {
+            if let Err(e) = config.tracker().print_summary(&SystemRunner) {
+                reporter::warn(&e.to_string());
+            }
+            None
+        }
+    }
+}
+
+/// Run the tracker's sync command after a loop and record the result in
+/// session `session_id`. A failed sync is a warning, or an error when
+/// `required`.
+fn sync_tracker(config: &EffectiveConfig, session_id: Option<&str>, required: bool) -> Result<()> {
+    let command = &config.tracker_sync_command.value;
+    let result = tracker::sync(&SystemRunner, command);
+    record_event(
+        session_id,
+        session::Event::TrackerSync {
+            command: command.clone(),
+            exit_code: *result.as_ref().unwrap_or(&-1),
+        },
+    );
+    let err = match result {
+        Ok(0) => {
+            reporter::info(&format!("Synced the task tracker (`{}`)", command));
+            return Ok(());
+        }
+        Ok(code) => RalphError::Tracker {
+            command: command.clone(),
+            message: format!("exited with code {code}"),
+        },
+        Err(e) => e,
     };
+    if required {
+        return Err(err);
+    }
+    reporter::warn(&err.to_string());
+    Ok(())
+}
 
-    Ok(status.code().unwrap_or(1))
-}
-
-/// Execute a provider command with the given system prompt and capture output.
-/// Returns a tuple of (exit_code, output_string).
-/// Used by the loop subcommand to check for COMPLETE marker.
-fn execute_provider_with_output(provider: &str, prompt: &str) -> io::Result<(i32, String)> {
-    use std::io::{BufRead, BufReader};
-
-    let mut child = match provider {
-        "droid" => Command::new("droid")
-            .args(["exec", "--auto", "medium", "--output-format", "stream-json"])
-            .arg(prompt)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?,
-        "codex" => Command::new("codex")
-            .args(["exec", "--yolo", "--json"])
-            .arg(prompt)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?,
-        "claude" => Command::new("claude")
-            .args([
-                "-p",
-                "--verbose",
-                "--output-format",
-                "stream-json",
-                "--dangerously-skip-permissions",
-            ])
-            .arg(prompt)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?,
-        "gemini" => Command::new("gemini")
-            .args(["-p", "--output-format", "stream-json", "--yolo"])
-            .arg(prompt)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?,
-        _ => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Unknown provider: {}", provider),
+/// Run `ralph loop` with `args` in the current directory, using the prompt
+/// in `prompt_file` instead of the system prompt when given. Returns the
+/// configuration it ran with and how the loop went, or `None` after
+/// `--print-config`.
+fn run_loop(
+    args: &LoopArgs,
+    prompt_file: Option<&Path>,
+) -> Result<Option<(EffectiveConfig, runner::RunSummary)>> {
+    let Some(config) = args.run.config(Overrides {
+        iterations: args.iterations.clone(),
+        tracker_sync: (args.bd_sync || args.bd_sync_required).then(|| "true".to_string()),
+        gates: (!args.gate.is_empty()).then(|| args.gate.clone()),
+        metrics_file: args.metrics_file.clone(),
+        ..Overrides::default()
+    })?
+    else {
+        return Ok(None);
+    };
+    let read_prompt = || match prompt_file {
+        Some(path) => std::fs::read_to_string(path).map_err(|source| RalphError::Prompt {
+            path: path.to_path_buf(),
+            source,
+        }),
+        None => config::system_prompt(&config),
+    };
+    let mut opts = RunArgs::resolve(&config)?;
+    if prompt_file.is_some() {
+        opts.prompt = read_prompt()?;
+    }
+    if args.print_prompt {
+        match args.marker.inject() {
+            true => print!(
+                "{}",
+                prompt::with_marker_instructions(&opts.prompt, &config.marker.value)
+            ),
+            false => print!("{}", opts.prompt),
+        }
+        return Ok(None);
+    }
+    let mut plan_prompt = match &args.plan_prompt {
+        _ if !args.plan_first && !args.plan_only => None,
+        Some(path) => Some(std::fs::read_to_string(path).map_err(|e| {
+            RalphError::InvalidInput(format!(
+                "Failed to read the planning prompt {}: {}",
+                path.display(),
+                e
+            ))
+        })?),
+        None => Some(
+            prompts::plan_template(&config.tracker.value)
+                .unwrap_or(prompts::PLAN_NONE)
+                .to_string(),
+        ),
+    };
+    let _lock = args.run.lock("loop")?;
+    // Next to the lock, in this checkout even with --sandbox.
+    let state_path = state::path()?;
+    // Named after, and relative to, this checkout even with --sandbox.
+    let cwd = std::env::current_dir()?;
+    let metrics_path = match config.metrics_file.value.as_str() {
+        "" => None,
+        path => Some(cwd.join(path)),
+    };
+    let repo = cwd
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let sandbox = match args.sandbox {
+        true => Some(enter_sandbox(
+            &config,
+            SandboxOptions {
+                carry_changes: args.sandbox_carry_changes,
+                copy_beads: !args.sandbox_shared_tracker
+                    && config.tracker.value == tracker::DEFAULT_TRACKER,
+                keep: args.sandbox_keep,
+            },
+        )?),
+        false => None,
+    };
+    let tracker_ok = args.run.preflight(&config)?;
+    let epic = match &args.epic {
+        Some(_) if config.tracker.value == tracker::NONE => {
+            return Err(RalphError::InvalidInput(
+                "--epic needs a task tracker, but tracker = \"none\"".to_string(),
             ));
         }
+        Some(id) => Some(epic::find(&SystemRunner, config.tracker().as_ref(), id)?),
+        None => None,
     };
-
-    // Read stdout line by line and print while capturing
-    let stdout = child.stdout.take().expect("Failed to capture stdout");
-    let reader = BufReader::new(stdout);
-    let mut output = String::new();
-
-    for line in reader.lines() {
-        let line = line?;
-        println!("{}", line);
-        output.push_str(&line);
-        output.push('\n');
+    if let Some(epic) = &epic {
+        reporter::info(&format!("Scoped to epic: {} ({})", epic.id, epic.title));
+        opts.prompt = epic::scope_prompt(&opts.prompt, epic);
+        plan_prompt = plan_prompt.map(|p| epic::scope_prompt(&p, epic));
+    }
+    let before = tracker_ok.then(|| snapshot_backlog(&config)).flatten();
+    let mut progress = args.report_file.as_ref().map(|path| {
+        let report = ProgressReport::new(path, config.iterations.value);
+        match tracker_ok {
+            true => {
+                let config = config.clone();
+                report.backlog(move || snapshot_backlog(&config))
+            }
+            false => report,
+        }
+    });
+    let mut metrics = metrics_path.map(|path| {
+        let metrics = LoopMetrics::new(path, &repo, &config.provider.value)
+            .live(args.metrics_live)
+            .label(session::label().as_deref().unwrap_or_default());
+        match tracker_ok {
+            true => {
+                let config = config.clone();
+                metrics.backlog(move || snapshot_backlog(&config))
+            }
+            false => metrics,
+        }
+    });
+    let mut checkpoint =
+        state::Checkpoint::new(state_path, args.portable_state).backlog(before.as_ref());
+    let mut runner = LoopRunner::builder()
+        .provider(opts.provider)
+        .prompt(opts.prompt)
+        .iterations(config.iterations.value)
+        .marker(config.marker.value.as_str())
+        .plan_only(args.plan_only)
+        .carry_plan(args.carry_plan)
+        .stop_on_failure(args.stop_on_failure)
+        .inject_marker_instructions(args.marker.inject())
+        .checkpoint(&mut checkpoint);
+    for gate in &config.gates.value {
+        runner = runner.gate(gate.as_str());
+    }
+    if let Some(plan_prompt) = plan_prompt {
+        runner = runner.plan_prompt(plan_prompt);
+    }
+    if let Some(progress) = progress.as_mut() {
+        runner = runner.progress(progress);
+    }
+    if let Some(metrics) = metrics.as_mut() {
+        runner = runner.metrics(metrics);
+    }
+    if args.reload_prompt {
+        runner = runner.reload_prompt(|| {
+            let prompt = read_prompt()?;
+            Ok(match &epic {
+                Some(epic) => epic::scope_prompt(&prompt, epic),
+                None => prompt,
+            })
+        });
     }
+    let summary = runner.run()?;
 
-    let status = child.wait()?;
-    Ok((status.code().unwrap_or(1), output))
+    if tracker_ok
+        && let Some(text) = report_backlog(&config, before, summary.session_id.as_deref())
+        && let Some(progress) = progress.as_mut()
+    {
+        progress.finish(&text);
+    }
+    if tracker_ok && config.tracker_sync.value {
+        sync_tracker(
+            &config,
+            summary.session_id.as_deref(),
+            args.bd_sync_required,
+        )?;
+    }
+    if let Some(epic) = epic.as_ref().filter(|_| !tracker::timed_out()) {
+        let done = epic::progress(&SystemRunner, config.tracker().as_ref(), epic);
+        let ready = epic::ready(&SystemRunner, config.tracker().as_ref(), epic);
+        match (done, ready) {
+            (Ok(epic_progress), Ok(ready)) => {
+                let line = epic::summary_line(epic, epic_progress, ready.len());
+                reporter::summary(&line);
+                if let Some(progress) = progress.as_mut() {
+                    progress.finish(&line);
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => reporter::warn(&format!(
+                "Failed to read progress of epic {}: {}",
+                epic.id, e
+            )),
+        }
+    }
+    if let Some(sandbox) = &sandbox {
+        sandbox.report();
+    }
+    if summary.stopped_on_failure
+        && let Some(e) = args.run.provider_error(&config, &summary)
+    {
+        return Err(e);
+    }
+    Ok(Some((config, summary)))
 }
 
-/// Run `bd list --pretty` and print its output.
-fn run_bd_list_pretty() -> io::Result<()> {
-    let status = Command::new("bd").args(["list", "--pretty"]).status()?;
-
-    if !status.success() {
-        eprintln!(
-            "Warning: bd list --pretty exited with code {}",
-            status.code().unwrap_or(1)
-        );
+/// Run `ralph loop` with `args` in each repository of the workspace
+/// manifest at `manifest`, in order, then print a table of how each went
+/// and the same as JSON on stdout. A missing repository is reported and
+/// skipped; a failed one stops the rest only with `stop_on_failure`.
+/// Exits 1 unless every repository was found and ran without an error.
+fn run_workspace(manifest: &Path, args: &LoopArgs, stop_on_failure: bool) -> Result<ExitCode> {
+    let repos = workspace::Manifest::load(manifest)?.repos;
+    let origin = std::env::current_dir()?;
+    // The repositories' loops run from their own directories.
+    let args = LoopArgs {
+        plan_prompt: args
+            .plan_prompt
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose()?,
+        ..args.clone()
+    };
+    let mut outcomes = Vec::with_capacity(repos.len());
+    let mut stop: Option<String> = None;
+    let mut update_check = false;
+    for (i, repo) in repos.iter().enumerate() {
+        if let Some(reason) = &stop {
+            outcomes.push(RepoOutcome::not_run(
+                &repo.path,
+                Status::Skipped,
+                Some(reason.clone()),
+            ));
+            continue;
+        }
+        reporter::banner(&format!(
+            "Repository {} / {}: {}",
+            i + 1,
+            repos.len(),
+            repo.path.display()
+        ));
+        if !repo.path.is_dir() {
+            reporter::warn(&format!(
+                "Skipping {}: no such directory",
+                repo.path.display()
+            ));
+            outcomes.push(RepoOutcome::not_run(
+                &repo.path,
+                Status::Missing,
+                Some("no such directory".to_string()),
+            ));
+            continue;
+        }
+        if args.run.print_config {
+            println!("# {}", repo.path.display());
+        }
+        let repo_args = LoopArgs {
+            run: RunArgs {
+                provider: repo.provider.clone().or(args.run.provider.clone()),
+                ..args.run.clone()
+            },
+            iterations: repo
+                .iterations
+                .map(|n| n.to_string())
+                .or(args.iterations.clone()),
+            ..args.clone()
+        };
+        let result = std::env::set_current_dir(&repo.path)
+            .map_err(RalphError::from)
+            .and_then(|()| run_loop(&repo_args, repo.prompt.as_deref()));
+        std::env::set_current_dir(&origin)?;
+        let outcome = match result {
+            Ok(None) => continue,
+            Ok(Some((config, summary))) => {
+                update_check |= config.update_check.value;
+                RepoOutcome {
+                    path: repo.path.clone(),
+                    status: match summary.completed {
+                        true => Status::Completed,
+                        false => Status::Incomplete,
+                    },
+                    session_id: summary.session_id,
+                    iterations: summary.iterations,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                reporter::error(&format!("{}: {}", repo.path.display(), e));
+                if stop_on_failure {
+                    stop = Some(format!("{} failed", repo.path.display()));
+                }
+                RepoOutcome::not_run(&repo.path, Status::Failed, Some(e.to_string()))
+            }
+        };
+        if runner::interrupted() {
+            stop = Some("interrupted".to_string());
+        }
+        outcomes.push(outcome);
+    }
+    if args.run.print_config {
+        return Ok(ExitCode::SUCCESS);
     }
 
-    Ok(())
+    let summary = WorkspaceSummary::new(manifest, outcomes);
+    reporter::summary(&workspace::render_table(&summary));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&summary).expect("workspace summary serializes")
+    );
+    print_update_hint(update_check);
+    Ok(match summary.succeeded() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(1),
+    })
 }
 
-/// The COMPLETE marker that signals the loop should end early.
-const COMPLETE_MARKER: &str = "<promise>COMPLETE</promise>";
-
 fn main() -> ExitCode {
+    // The completion scripts call `ralph __complete <kind>` on every TAB;
+    // it's kept out of clap so it's not offered as a command itself, and
+    // it touches no files and never fails
+    if let Some(kind) = completions::requested(std::env::args()) {
+        for candidate in completions::candidates(kind) {
+            println!("{candidate}");
+        }
+        return ExitCode::SUCCESS;
+    }
+    crash::install(std::env::args().collect());
     let cli = Cli::parse();
+    reporter::set(cli.reporter());
+    let result = run(cli);
+    schema::report();
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            reporter::error(&e.to_string());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    // Always ensure config exists on startup
-    if let Err(e) = ensure_config() {
-        eprintln!("Warning: Failed to initialize configuration: {}", e);
+fn run(cli: Cli) -> Result<ExitCode> {
+    // Ensure config exists on startup; read-only commands and those that
+    // manage ~/.Ralph themselves must not create files
+    if !matches!(
+        cli.command,
+        None | Some(Commands::Version { .. })
+            | Some(Commands::Status { .. })
+            | Some(Commands::Kill { .. })
+            | Some(Commands::Stats { .. })
+            | Some(Commands::History { .. })
+            | Some(Commands::Man { .. })
+            | Some(Commands::Report { .. })
+            | Some(Commands::Uninstall { .. })
+            | Some(Commands::CheckUpdate)
+            | Some(Commands::Setup { .. })
+            | Some(Commands::Init { .. })
+            | Some(Commands::Completions { .. })
+            | Some(Commands::Clean { .. })
+            | Some(Commands::Audit { .. })
+            | Some(Commands::Pricing { .. })
+            | Some(Commands::Providers { .. })
+    ) && let Err(e) = ensure_config()
+    {
+        // Commands that send the prompt to a provider can't do without it;
+        // the rest (upgrade, prompt) carry on.
+        if matches!(
+            cli.command,
+            Some(Commands::Once { .. })
+                | Some(Commands::Loop { .. })
+                | Some(Commands::Resume { .. })
+                | Some(Commands::Run { .. })
+                | Some(Commands::Watch { .. })
+        ) {
+            return Err(e);
+        }
+        reporter::warn(&format!("Failed to initialize configuration: {}", e));
+    }
+
+    audit::set(audit_log());
+    if !matches!(cli.command, Some(Commands::Upgrade { .. }))
+        && let Some(marker) = upgrade::interrupted_upgrade()
+    {
+        reporter::warn(&format!(
+            "The last `ralph upgrade` didn't finish (found {}); run `ralph upgrade --repair` to check the installed binary",
+            marker.display()
+        ));
+    }
+
+    // Providers run in their own process group, out of reach of the
+    // terminal's Ctrl-C; the handler lets ralph stop them (and whatever they
+    // started) instead.
+    if matches!(
+        cli.command,
+        Some(Commands::Once { .. })
+            | Some(Commands::Loop { .. })
+            | Some(Commands::Resume { .. })
+            | Some(Commands::Run { .. })
+            | Some(Commands::Watch { .. })
+    ) {
+        runner::install_interrupt_handler();
+    }
+    if let Some(
+        Commands::Once { run }
+        | Commands::Loop {
+            args: LoopArgs { run, .. },
+            ..
+        }
+        | Commands::Run { run, .. }
+        | Commands::Watch { run, .. },
+    ) = &cli.command
+    {
+        if run.validate_stream {
+            schema::enable();
+        }
+        if let Some(variant) = &run.prompt_variant {
+            session::set_prompt_variant(variant);
+        }
+        if let Some(label) = &run.label {
+            session::set_label(label);
+        }
+        if let Some(model) = &run.model {
+            providers::set_model(model.clone());
+        }
+        if let Some(format) = &run.output_format {
+            providers::set_output_format(format.parse()?);
+        }
+        providers::set_extra_args(run.provider_args.clone());
+        if let Some(secs) = run.timeout {
+            stream::set_timeout(Duration::from_secs(secs));
+        }
+        if let Some(dir) = &run.cwd {
+            let dir = working_dir(dir)?;
+            providers::set_dir(dir.clone());
+            tracker::set_dir(dir);
+        }
     }
 
     match cli.command {
-        Some(Commands::Version) => {
-            println!("ralph {}", env!("CARGO_PKG_VERSION"));
-            ExitCode::SUCCESS
-        }
-        Some(Commands::Once { provider }) => {
-            // Validate provider
-            if let Err(e) = validate_provider(&provider) {
-                eprintln!("Error: {}", e);
-                return ExitCode::from(1);
-            }
-
-            // Read system prompt
-            let prompt = match read_system_prompt() {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("Error: Failed to read system prompt: {}", e);
-                    return ExitCode::from(1);
-                }
+        Some(Commands::Version { json }) => {
+            let info = build_info::BuildInfo::current();
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&info).expect("build info serializes")
+                );
+            } else {
+                print!("{}", info.render_text());
+            }
+        }
+        Some(Commands::Once { run }) => {
+            let Some(config) = run.config(Overrides::default())? else {
+                return Ok(ExitCode::SUCCESS);
             };
-
-            // Execute provider
-            match execute_provider(&provider, &prompt) {
-                Ok(code) => ExitCode::from(code as u8),
-                Err(e) => {
-                    eprintln!("Error: Failed to execute provider '{}': {}", provider, e);
-                    ExitCode::from(1)
-                }
+            let opts = RunArgs::resolve(&config)?;
+            let _lock = run.lock("once")?;
+            run.preflight(&config)?;
+            let summary = runner::run_once(&opts)?;
+            print_update_hint(config.update_check.value);
+            if summary.interrupted {
+                return Ok(ExitCode::from(runner::EXIT_INTERRUPTED));
+            }
+            if let Some(e) = run.provider_error(&config, &summary) {
+                return Err(e);
             }
         }
         Some(Commands::Loop {
-            provider,
-            iterations,
+            args,
+            workspace: None,
+            ..
         }) => {
-            // Validate provider
-            if let Err(e) = validate_provider(&provider) {
-                eprintln!("Error: {}", e);
-                return ExitCode::from(1);
-            }
-
-            // Validate iterations
-            let max_iterations = match validate_iterations(&iterations) {
-                Ok(n) => n,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    return ExitCode::from(1);
+            if let Some((config, summary)) = run_loop(&args, None)? {
+                print_update_hint(config.update_check.value);
+                if summary.interrupted {
+                    return Ok(ExitCode::from(runner::EXIT_INTERRUPTED));
                 }
+            }
+        }
+        Some(Commands::Loop {
+            args,
+            workspace: Some(manifest),
+            stop_on_repo_failure,
+        }) => {
+            return run_workspace(&manifest, &args, stop_on_repo_failure);
+        }
+        Some(Commands::Resume {
+            yes,
+            force,
+            ignore_missing_bd,
+            auto_init_bd,
+            no_bd,
+            force_lock,
+            reload_prompt,
+            portable_state,
+            allow_version_drift,
+            auto_approve,
+        }) => {
+            let config = config::resolve(&Overrides {
+                auto_init_bd: auto_init_bd.then(|| "true".to_string()),
+                tracker: no_bd.then(|| tracker::NONE.to_string()),
+                auto_approve: auto_approve.then(|| "true".to_string()),
+                ..Overrides::default()
+            })?;
+            let cwd = std::env::current_dir().unwrap_or_default();
+            // The repository's state file comes first: after a pull it
+            // describes a loop this machine has no transcript of.
+            let saved = state::read(&state::path()?)?;
+            let plan = match saved.as_ref().and_then(resume::from_state) {
+                Some(plan) => Some(plan),
+                None => resume::find(&cwd)?,
             };
+            let plan = plan.ok_or_else(|| {
+                RalphError::InvalidInput(
+                    "No interrupted loop session to resume in this directory".to_string(),
+                )
+            })?;
+            // Before the provider check: it may be one config.toml defines.
+            apply_provider_settings(&config);
+            ralph::providers::validate_provider(&plan.provider)?;
+            ralph::providers::resolve_provider_binary(&plan.provider)?;
+            if let Some(variant) = &plan.prompt_variant {
+                session::set_prompt_variant(variant);
+            }
+            if let Some(label) = &plan.label {
+                session::set_label(label);
+            }
+            let prompt = config::system_prompt(&config)?;
+            if plan
+                .prompt_hash
+                .as_deref()
+                .is_some_and(|h| h != session::prompt_hash(&prompt))
+            {
+                reporter::warn(&format!(
+                    "The system prompt has changed since session {} last ran",
+                    plan.session_id
+                ));
+                if !force {
+                    return Err(RalphError::InvalidInput(
+                        "Pass --force to resume with the current prompt".to_string(),
+                    ));
+                }
+            }
+
+            reporter::info(&format!("Resuming session {}", plan.session_id));
+            reporter::info(&format!("  Provider:   {}", plan.provider));
+            reporter::info(&format!(
+                "  Iterations: {} of {} done, {} remaining",
+                plan.finished_iterations,
+                plan.max_iterations,
+                plan.remaining_iterations()
+            ));
+            reporter::info(&format!("  Marker:     {}", config.marker.value));
+            if !yes && !clean::confirm("Continue?") {
+                reporter::info("Aborted.");
+                return Ok(ExitCode::SUCCESS);
+            }
 
-            // Read system prompt
-            let prompt = match read_system_prompt() {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("Error: Failed to read system prompt: {}", e);
-                    return ExitCode::from(1);
+            let _lock = take_lock("resume", force_lock)?;
+            check_provider_version(&config, &plan.provider, allow_version_drift)?;
+            let tracker_ok = tracker::preflight(
+                config.tracker().as_ref(),
+                &SystemRunner,
+                PreflightOptions {
+                    ignore_missing: ignore_missing_bd,
+                    auto_init: config.auto_init_bd.value,
+                },
+            )?;
+            // A loop continued from the state file diffs against the backlog
+            // it started with, wherever that was.
+            let saved = saved.filter(|s| s.session_id == plan.session_id);
+            let before = tracker_ok
+                .then(|| {
+                    (saved.as_ref())
+                        .and_then(state::LoopState::backlog_snapshot)
+                        .or_else(|| snapshot_backlog(&config))
+                })
+                .flatten();
+            let checkpoint = state::Checkpoint::new(state::path()?, portable_state);
+            let mut checkpoint = match &saved {
+                Some(saved) => checkpoint.resuming(saved),
+                None => checkpoint.resuming_session(plan.finished_iterations, plan.max_iterations),
+            }
+            .backlog(before.as_ref());
+            let mut runner = LoopRunner::builder()
+                .provider(plan.provider.as_str())
+                .prompt(prompt)
+                .iterations(plan.remaining_iterations())
+                .marker(config.marker.value.as_str())
+                .resumed_from(plan.session_id.as_str())
+                .checkpoint(&mut checkpoint);
+            for gate in &config.gates.value {
+                runner = runner.gate(gate.as_str());
+            }
+            if reload_prompt {
+                runner = runner.reload_prompt(|| config::system_prompt(&config));
+            }
+            let summary = runner.run()?;
+            if tracker_ok {
+                report_backlog(&config, before, summary.session_id.as_deref());
+            }
+            if tracker_ok && config.tracker_sync.value {
+                sync_tracker(&config, summary.session_id.as_deref(), false)?;
+            }
+            if summary.interrupted {
+                return Ok(ExitCode::from(runner::EXIT_INTERRUPTED));
+            }
+        }
+        Some(Commands::Run { id, run }) => {
+            let Some(config) = run.config(Overrides::default())? else {
+                return Ok(ExitCode::SUCCESS);
+            };
+            let opts = RunArgs::resolve(&config)?;
+            let _lock = run.lock("run")?;
+            run.preflight(&config)?;
+            match run::run_task(
+                &SystemRunner,
+                config.tracker().as_ref(),
+                &opts.provider,
+                &id,
+                &opts.prompt,
+            )? {
+                run::TaskOutcome::Closed => reporter::info(&format!("Task {} closed.", id)),
+                run::TaskOutcome::StillOpen { status } => {
+                    reporter::info(&format!("Task {} is still open (status: {}).", id, status));
+                    return Ok(ExitCode::from(run::EXIT_TASK_OPEN));
                 }
+            }
+        }
+        Some(Commands::Watch {
+            run,
+            interval,
+            iterations,
+            marker,
+        }) => {
+            let Some(config) = run.config(Overrides {
+                iterations,
+                ..Overrides::default()
+            })?
+            else {
+                return Ok(ExitCode::SUCCESS);
             };
-
-            eprintln!("Using AI provider: {}", provider);
-            eprintln!("Max iterations: {}", max_iterations);
-            eprintln!();
-
-            let mut completed_early = false;
-            let mut final_iteration = 0;
-
-            for i in 1..=max_iterations {
-                final_iteration = i;
-                eprintln!("==========================================");
-                eprintln!("Iteration {} / {}", i, max_iterations);
-                eprintln!("==========================================");
-
-                match execute_provider_with_output(&provider, &prompt) {
-                    Ok((_, output)) => {
-                        // Check for COMPLETE marker
-                        if output.contains(COMPLETE_MARKER) {
-                            eprintln!();
-                            eprintln!("All tasks complete after {} iterations.", i);
-                            completed_early = true;
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error: Failed to execute provider '{}': {}", provider, e);
-                        return ExitCode::from(1);
-                    }
+            if config.tracker.value == tracker::NONE {
+                return Err(RalphError::InvalidInput(
+                    "`ralph watch` polls a task tracker, but tracker = \"none\"".to_string(),
+                ));
+            }
+            let _lock = run.lock("watch")?;
+            run.preflight(&config)?;
+            let run = RunArgs::resolve(&config)?;
+            let opts = watch::WatchOptions {
+                provider: run.provider,
+                tracker: config.tracker(),
+                interval: Duration::from_secs(interval),
+                iterations: config.iterations.value,
+                marker: config.marker.value.clone(),
+                inject_marker_instructions: marker.inject(),
+                gates: config.gates.value.clone(),
+            };
+            watch::run(&SystemRunner, &opts, &run.prompt);
+        }
+        Some(Commands::Man { out }) => {
+            let pages = man::generate(&out).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to write man pages: {}", e))
+            })?;
+            for page in pages {
+                println!("{}", page.display());
+            }
+        }
+        Some(Commands::Init {
+            list_templates: true,
+            ..
+        }) => {
+            print!("{}", templates::render_list());
+        }
+        Some(Commands::Init { template, .. }) => {
+            let template =
+                templates::get(template.as_deref().unwrap_or(templates::DEFAULT_TEMPLATE))?;
+            let dir = std::env::current_dir()?;
+            for (path, written) in template.apply(&dir)? {
+                let path = path.strip_prefix(&dir).unwrap_or(&path).display();
+                match written {
+                    true => println!("Created {}", path),
+                    false => println!("Kept existing {}", path),
                 }
             }
-
-            if !completed_early {
-                eprintln!();
-                eprintln!("Ralph loop finished after {} iterations", final_iteration);
+            reporter::info(&format!(
+                "Initialized {} for ralph ({} template)",
+                dir.display(),
+                template.name
+            ));
+        }
+        Some(Commands::Setup {
+            provider,
+            iterations,
+            update_check,
+            skip_bd_check,
+            no_bd,
+            non_interactive,
+        }) => {
+            let opts = setup::SetupOptions {
+                provider,
+                iterations,
+                update_check,
+                skip_bd_check,
+                no_bd,
+                non_interactive,
+            };
+            setup::run(&opts)?;
+        }
+        Some(Commands::Prompt {
+            command: PromptCommand::Lint,
+        }) => {
+            let prompt = read_system_prompt()?;
+            let findings = prompt::lint(&prompt, Path::new("."));
+            for finding in &findings {
+                println!("{}: {}", finding.severity, finding.message);
             }
-
-            // Run bd list --pretty at the end
-            if let Err(e) = run_bd_list_pretty() {
-                eprintln!("Warning: Failed to run 'bd list --pretty': {}", e);
+            if findings.is_empty() {
+                println!("No problems found.");
+            }
+            if findings
+                .iter()
+                .any(|f| f.severity == prompt::Severity::Error)
+            {
+                return Ok(ExitCode::from(1));
             }
-
-            ExitCode::SUCCESS
         }
-        Some(Commands::Upgrade) => match upgrade::run_upgrade() {
-            Ok(upgrade::UpgradeOutcome::UpToDate { current }) => {
+        Some(Commands::Prompt {
+            command: PromptCommand::Reset { tracker, yes },
+        }) => {
+            let template = match tracker.as_deref().and_then(prompts::template) {
+                Some(template) => template,
+                None => config::resolve(&Overrides::default())?
+                    .tracker()
+                    .prompt_template(),
+            };
+            let path = config::get_system_prompt_path()?;
+            let edited = std::fs::read_to_string(&path)
+                .is_ok_and(|current| current != template && !prompts::is_builtin(&current));
+            if edited
+                && !yes
+                && !clean::confirm(&format!("Replace your edited {}?", path.display()))
+            {
+                reporter::info("Aborted.");
+                return Ok(ExitCode::SUCCESS);
+            }
+            fsutil::write_atomic(&path, template)?;
+            println!("Wrote {}", path.display());
+        }
+        Some(Commands::Prompt {
+            command:
+                PromptCommand::Test {
+                    provider,
+                    timeout,
+                    format,
+                },
+        }) => {
+            let config = config::resolve(&Overrides {
+                provider,
+                ..Overrides::default()
+            })?;
+            apply_provider_settings(&config);
+            runner::install_interrupt_handler();
+            // Only the verdict is printed, so --format json stays parseable.
+            stream::set_echo(false);
+            let test = smoke::run(
+                &SystemRunner,
+                &config.provider.value,
+                chrono::Local::now().date_naive(),
+                Duration::from_secs(timeout),
+                &runner::interrupt_token(),
+            );
+            match format {
+                OutputFormat::Text => print!("{}", test.render_text()),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&test).expect("smoke test serializes")
+                ),
+            }
+            if !test.passed {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Some(Commands::Report { session, out }) => {
+            let config = config::resolve(&Overrides::default())?;
+            let md =
+                report::generate(&SystemRunner, config.tracker().as_ref(), session.as_deref())?;
+            match &out {
+                Some(path) => std::fs::write(path, md)?,
+                None => print!("{}", md),
+            }
+        }
+        Some(Commands::History {
+            command: None,
+            since,
+            label,
+        }) => {
+            print!(
+                "{}",
+                history::list(&history::ListOptions {
+                    since: since.as_deref().map(history::parse_since).transpose()?,
+                    label,
+                })?
+            );
+        }
+        Some(Commands::History {
+            command:
+                Some(HistoryCommand::Export {
+                    format,
+                    since,
+                    label,
+                    per_iteration,
+                    out,
+                }),
+            ..
+        }) => {
+            let opts = history::ExportOptions {
+                since: since.as_deref().map(history::parse_since).transpose()?,
+                label,
+                per_iteration,
+                json: format == ExportFormat::Json,
+            };
+            match &out {
+                Some(path) => history::export(&opts, &mut File::create(path)?)?,
+                None => history::export(&opts, &mut io::stdout())?,
+            }
+        }
+        Some(Commands::Stats {
+            since,
+            by_repo,
+            by_variant,
+            by_label,
+            format,
+        }) => {
+            let since = since.as_deref().map(clean::parse_age).transpose()?;
+            let report = stats::collect(&stats::StatsOptions {
+                since,
+                by_repo,
+                by_variant,
+                by_label,
+            })?;
+            match format {
+                OutputFormat::Text => print!("{}", stats::render_text(&report)),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("stats report serializes")
+                ),
+            }
+        }
+        Some(Commands::Clean {
+            older_than,
+            dry_run,
+            yes,
+        }) => {
+            let config = config::resolve(&Overrides {
+                older_than,
+                ..Overrides::default()
+            })?;
+            let older_than = config.clean_older_than.value;
+            let opts = clean::CleanOptions {
+                older_than: clean::parse_age(&older_than)?,
+                dry_run,
+                yes,
+            };
+            clean::run(&opts, &older_than)?;
+        }
+        Some(Commands::Upgrade { repair: true }) => {
+            upgrade::report_repair(&upgrade::run_repair()?);
+        }
+        Some(Commands::Upgrade { repair: false }) => match upgrade::run_upgrade()? {
+            upgrade::UpgradeOutcome::UpToDate { current } => {
                 println!("ralph is already up to date (v{current})");
-                ExitCode::SUCCESS
             }
-            Ok(upgrade::UpgradeOutcome::Upgraded { from, to }) => {
+            upgrade::UpgradeOutcome::Upgraded { from, to } => {
                 println!("Upgraded ralph from v{from} to v{to}");
-                ExitCode::SUCCESS
             }
-            Err(upgrade::UpgradeError::PermissionDenied { path }) => {
-                eprintln!("{}", upgrade::permission_denied_suggestions(&path));
-                ExitCode::from(1)
+        },
+        Some(Commands::CheckUpdate) => {
+            let check = upgrade::check_update()?;
+            println!("{}", check.message());
+            if let upgrade::UpdateCheck::Available { .. } = check {
+                return Ok(ExitCode::from(upgrade::EXIT_UPDATE_AVAILABLE));
             }
-            Err(e) => {
-                eprintln!("Error: {e}");
-                ExitCode::from(1)
+        }
+        Some(Commands::Uninstall { yes, purge }) => {
+            uninstall::run(&uninstall::UninstallOptions { yes, purge })?;
+        }
+        Some(Commands::Kill {
+            repo,
+            force,
+            timeout,
+        }) => {
+            let path = match repo {
+                Some(repo) => lock::lock_path_in(&repo)?,
+                None => lock::lock_path()?,
+            };
+            let outcome = kill::run(
+                &path,
+                &kill::KillOptions {
+                    force,
+                    timeout: Duration::from_secs(timeout),
+                },
+            )?;
+            println!("{}", outcome.render_text());
+        }
+        Some(Commands::Status { format }) => {
+            let report = status::collect()?;
+            match format {
+                OutputFormat::Text => print!("{}", status::render_text(&report)),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("status report serializes")
+                ),
             }
-        },
+        }
+        Some(Commands::Logs {
+            session,
+            iteration,
+            follow,
+            output,
+            stderr,
+        }) => {
+            let opts = logs::LogsOptions {
+                session,
+                iteration,
+                follow,
+                pretty: !stderr && output.map_or(follow, |o| o == LogOutput::Pretty),
+                stderr,
+            };
+            logs::show(&opts, &mut io::stdout())?;
+        }
+        Some(Commands::Providers { refresh, json }) => {
+            // A config that doesn't resolve has no providers or pins of its
+            // own to show; the run commands report what's wrong with it.
+            let config = config::resolve(&Overrides::default()).ok();
+            if let Some(config) = &config {
+                providers::set_templates(config.provider_templates.value.clone());
+            }
+            let pins = config
+                .map(|config| config.provider_versions.value)
+                .unwrap_or_default();
+            let probes = probe::probe_known(refresh);
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&probe::availability(&probes, &pins))
+                        .expect("provider availability serializes")
+                );
+            } else {
+                print!("{}", probe::render_text(&probes));
+                if !pins.is_empty() {
+                    print!("\n{}", probe::render_pins(&probes, &pins));
+                }
+            }
+        }
+        Some(Commands::Pricing {
+            command: PricingCommand::List,
+        }) => {
+            print!("{}", pricing::load()?.render_text());
+        }
+        Some(Commands::Pricing {
+            command:
+                PricingCommand::Set {
+                    model,
+                    input,
+                    output,
+                },
+        }) => {
+            let path = pricing::path()?;
+            pricing::set_in(&path, &model, pricing::Price { input, output })?;
+            println!(
+                "Set {model} to ${input} input / ${output} output per million tokens in {}",
+                path.display()
+            );
+        }
+        Some(Commands::Audit { command }) => {
+            let path = audit::default_path()?;
+            match command {
+                AuditCommand::Show { since } => {
+                    let since = since.as_deref().map(history::parse_since).transpose()?;
+                    print!("{}", audit::render_text(&audit::entries(&path, since)?));
+                }
+                AuditCommand::Verify => {
+                    let entries = audit::verify(&path)?;
+                    reporter::info(&format!(
+                        "{}: {} entries, hash chain intact",
+                        path.display(),
+                        entries
+                    ));
+                }
+            }
+        }
+        Some(Commands::Completions { shell }) => {
+            completions::write(shell, &mut io::stdout())?;
+        }
         None => {
             // No subcommand provided, show help
             println!(
@@ -386,120 +1975,90 @@ fn main() -> ExitCode {
             );
             println!();
             println!("Use 'ralph --help' for more information.");
-            ExitCode::SUCCESS
         }
     }
+    Ok(ExitCode::SUCCESS)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::CommandFactory;
 
-    #[test]
-    fn test_default_system_prompt_not_empty() {
-        assert!(!DEFAULT_SYSTEM_PROMPT.is_empty());
-        assert!(DEFAULT_SYSTEM_PROMPT.contains("bd"));
-        assert!(DEFAULT_SYSTEM_PROMPT.contains("beads"));
-    }
-
-    #[test]
-    fn test_get_config_dir() {
-        let config_dir = get_config_dir().expect("Should get config dir");
-        assert!(config_dir.ends_with(".Ralph"));
-    }
-
-    #[test]
-    fn test_get_system_prompt_path() {
-        let prompt_path = get_system_prompt_path().expect("Should get prompt path");
-        assert!(prompt_path.ends_with("system-prompt.md"));
-    }
-
-    #[test]
-    fn test_ensure_config_and_read() {
-        // This test uses the actual home directory
-        // ensure_config should not fail
-        ensure_config().expect("ensure_config should succeed");
-
-        // read_system_prompt should return content
-        let content = read_system_prompt().expect("read_system_prompt should succeed");
-        assert!(!content.is_empty());
-    }
-
-    #[test]
-    fn test_validate_provider_valid() {
-        assert!(validate_provider("droid").is_ok());
-        assert!(validate_provider("codex").is_ok());
-        assert!(validate_provider("claude").is_ok());
-        assert!(validate_provider("gemini").is_ok());
-    }
-
-    #[test]
-    fn test_validate_provider_invalid() {
-        let result = validate_provider("invalid_provider");
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err();
-        assert!(err_msg.contains("Invalid provider 'invalid_provider'"));
-        assert!(err_msg.contains("Available providers: droid, codex, claude, gemini"));
-    }
-
-    #[test]
-    fn test_validate_provider_empty() {
-        let result = validate_provider("");
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_valid_providers_list() {
-        assert_eq!(VALID_PROVIDERS.len(), 4);
-        assert!(VALID_PROVIDERS.contains(&"droid"));
-        assert!(VALID_PROVIDERS.contains(&"codex"));
-        assert!(VALID_PROVIDERS.contains(&"claude"));
-        assert!(VALID_PROVIDERS.contains(&"gemini"));
-    }
-
-    #[test]
-    fn test_validate_iterations_valid() {
-        assert_eq!(validate_iterations("1").unwrap(), 1);
-        assert_eq!(validate_iterations("5").unwrap(), 5);
-        assert_eq!(validate_iterations("10").unwrap(), 10);
-        assert_eq!(validate_iterations("100").unwrap(), 100);
+    fn parse(args: &[&str]) -> Commands {
+        Cli::try_parse_from(args)
+            .expect("arguments parse")
+            .command
+            .expect("a subcommand")
     }
 
     #[test]
-    fn test_validate_iterations_zero() {
-        let result = validate_iterations("0");
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err();
-        assert!(err_msg.contains("positive integer"));
+    fn once_and_loop_share_run_args() {
+        let expected = RunArgs {
+            provider: Some("claude".to_string()),
+            ..RunArgs::default()
+        };
+        match parse(&["ralph", "once", "--provider", "claude"]) {
+            Commands::Once { run } => assert_eq!(run, expected),
+            other => panic!("unexpected {other:?}"),
+        }
+        match parse(&["ralph", "loop", "--provider", "claude", "--iterations", "3"]) {
+            Commands::Loop {
+                args:
+                    LoopArgs {
+                        run,
+                        iterations,
+                        epic,
+                        ..
+                    },
+                workspace,
+                ..
+            } => {
+                assert_eq!(workspace, None);
+                assert_eq!(run, expected);
+                assert_eq!(iterations.as_deref(), Some("3"));
+                assert_eq!(epic, None);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        match parse(&["ralph", "run", "bd-1"]) {
+            Commands::Run { id, run } => {
+                assert_eq!(id, "bd-1");
+                assert_eq!(run, RunArgs::default());
+            }
+            other => panic!("unexpected {other:?}"),
+        }
     }
 
     #[test]
-    fn test_validate_iterations_negative() {
-        // "-1" would fail to parse as u32, so should error
-        let result = validate_iterations("-1");
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err();
-        assert!(err_msg.contains("positive integer"));
+    fn iterations_is_loop_only() {
+        assert!(Cli::try_parse_from(["ralph", "once", "--iterations", "3"]).is_err());
     }
 
     #[test]
-    fn test_validate_iterations_non_numeric() {
-        let result = validate_iterations("abc");
-        assert!(result.is_err());
-        let err_msg = result.unwrap_err();
-        assert!(err_msg.contains("positive integer"));
-
-        let result = validate_iterations("10.5");
-        assert!(result.is_err());
-
-        let result = validate_iterations("");
-        assert!(result.is_err());
+    fn shared_flags_have_identical_help() {
+        let cmd = Cli::command();
+        let help = |sub: &str| {
+            cmd.find_subcommand(sub)
+                .unwrap()
+                .get_arguments()
+                .find(|a| a.get_id() == "provider")
+                .and_then(|a| a.get_help())
+                .map(|h| h.to_string())
+        };
+        assert!(help("once").is_some());
+        for sub in ["loop", "run", "watch"] {
+            assert_eq!(help(sub), help("once"), "--provider help for {sub}");
+        }
     }
 
     #[test]
-    fn test_complete_marker() {
-        assert_eq!(COMPLETE_MARKER, "<promise>COMPLETE</promise>");
-        assert!("Some output with <promise>COMPLETE</promise> in it".contains(COMPLETE_MARKER));
-        assert!(!"Some output without the marker".contains(COMPLETE_MARKER));
+    fn test_bash_completions_mention_loop_and_provider() {
+        let mut out = Vec::new();
+        completions::write(clap_complete::Shell::Bash, &mut out).unwrap();
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("loop"));
+        assert!(script.contains("--provider"));
+        assert!(script.contains("claude"));
     }
 }
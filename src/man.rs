@@ -0,0 +1,323 @@
+use clap::CommandFactory;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Exit codes shared by every command, as `(code, meaning)`.
+const EXIT_CODES: &[(&str, &str)] = &[
+    ("0", "Success."),
+    ("1", "Invalid configuration, or ralph itself failed."),
+    (
+        "2",
+        "Invalid arguments: an unknown flag, a missing or malformed value.",
+    ),
+    (
+        "N",
+        "ralph once: the provider's own exit code is passed through, so any code, \
+         3, 124 and 130 included, may come from the provider itself.",
+    ),
+    (
+        "3",
+        "ralph once: the provider printed more than --max-output-bytes and was killed.",
+    ),
+    (
+        "124",
+        "ralph once, or loop --stop-on-failure: the provider ran past --timeout and was killed.",
+    ),
+    (
+        "130",
+        "ralph once, loop or resume: stopped by Ctrl-C or SIGTERM; the provider was stopped too.",
+    ),
+    (
+        "75",
+        "ralph run: the provider finished but the task is still open; safe to retry.",
+    ),
+    ("10", "ralph check-update: a newer release is available."),
+    (
+        "20",
+        "ralph check-update: the check failed (e.g. network error).",
+    ),
+    (
+        "70",
+        "ralph crashed; the crash report under ~/.Ralph/crashes/ has the details.",
+    ),
+];
+
+/// Keys understood in `config.toml`, as `(key, meaning)`.
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    (
+        "provider",
+        "Default AI provider for once, loop, run and watch.",
+    ),
+    (
+        "iterations",
+        "Default maximum iterations for loop and watch.",
+    ),
+    ("update_check", "Mention new releases after once and loop."),
+    (
+        "tracker",
+        "Task tracker the agent works through: beads (default), github or none. Also [tracker] name.",
+    ),
+    (
+        "[tracker] sync_after_run",
+        "Run the tracker's sync command (bd sync) after loop.",
+    ),
+    (
+        "[tracker] sync_command",
+        "Command to run instead of bd sync.",
+    ),
+    (
+        "[tracker] repo",
+        "GitHub repository (owner/name) for tracker = github; default: the origin remote's.",
+    ),
+    (
+        "[tracker] label",
+        "Label marking GitHub issues as ready (default: ready).",
+    ),
+    (
+        "auto_init_bd",
+        "Run bd init without asking when a run finds no beads database.",
+    ),
+    (
+        "login_phrases",
+        "Extra phrases (case-insensitive) meaning a provider is waiting for an interactive login; the run then stops.",
+    ),
+    (
+        "max_line_bytes",
+        "Longest provider output line kept whole, in bytes (default 4194304); longer lines are cut to their head and shown as a placeholder.",
+    ),
+    (
+        "keep_long_lines",
+        "true to still write over-long lines whole to the captured output log.",
+    ),
+    (
+        "nice",
+        "Lower providers' CPU priority by this much, 0 to 19 (default 0); on Windows any level runs them below normal.",
+    ),
+    (
+        "ionice",
+        "Providers' I/O scheduling class on Linux: none (default), idle or best-effort[:0-7].",
+    ),
+    (
+        "audit",
+        "false to stop recording every command ralph runs in ~/.Ralph/audit.log (default true).",
+    ),
+    (
+        "[clean] older_than",
+        "Default age threshold for ralph clean, e.g. 30d.",
+    ),
+];
+
+/// Environment variables ralph reads, as `(name, meaning)`.
+const ENVIRONMENT: &[(&str, &str)] = &[
+    (
+        "RALPH_HOME",
+        "Directory to use instead of ~/.Ralph for config, the system prompt, logs, sessions and caches.",
+    ),
+    (
+        "RALPH_INTERACTIVE",
+        "1 or 0: ask confirmation prompts even without a terminal, or never ask. By default ralph asks only when stdin and stderr are terminals and no CI variable is set.",
+    ),
+    (
+        "RALPH_PROVIDER",
+        "Provider to use; overrides config.toml, overridden by --provider.",
+    ),
+    (
+        "RALPH_ITERATIONS",
+        "Iteration budget for loop and watch; overrides config.toml, overridden by --iterations.",
+    ),
+    (
+        "RALPH_UPDATE_CHECK",
+        "true or false; overrides update_check in config.toml.",
+    ),
+    (
+        "RALPH_TRACKER",
+        "Task tracker (beads, github or none); overrides tracker in config.toml.",
+    ),
+    (
+        "RALPH_TRACKER_SYNC",
+        "true or false; overrides [tracker] sync_after_run, overridden by --bd-sync.",
+    ),
+    (
+        "RALPH_TRACKER_SYNC_COMMAND",
+        "Overrides [tracker] sync_command in config.toml.",
+    ),
+    (
+        "RALPH_TRACKER_REPO",
+        "Overrides [tracker] repo in config.toml.",
+    ),
+    (
+        "RALPH_TRACKER_LABEL",
+        "Overrides [tracker] label in config.toml.",
+    ),
+    (
+        "RALPH_TRACKER_TIMEOUT_SECS",
+        "Overrides [tracker] timeout_secs in config.toml.",
+    ),
+    (
+        "GITHUB_TOKEN, GH_TOKEN",
+        "API token for tracker = github; needed for private repositories and to update issues.",
+    ),
+    (
+        "RALPH_AUTO_INIT_BD",
+        "true or false; overrides auto_init_bd in config.toml, overridden by --auto-init-bd.",
+    ),
+    (
+        "RALPH_LOGIN_PHRASES",
+        "Login phrases separated by |; overrides login_phrases in config.toml.",
+    ),
+    (
+        "RALPH_MAX_LINE_BYTES",
+        "Overrides max_line_bytes in config.toml.",
+    ),
+    (
+        "RALPH_KEEP_LONG_LINES",
+        "true or false; overrides keep_long_lines in config.toml.",
+    ),
+    (
+        "RALPH_NICE, RALPH_IONICE",
+        "Override nice and ionice in config.toml, overridden by --nice and --ionice.",
+    ),
+    (
+        "RALPH_AUTO_APPROVE",
+        "true or false; overrides auto_approve in config.toml, overridden by --unsafe.",
+    ),
+    (
+        "RALPH_AUDIT",
+        "true or false; overrides audit in config.toml.",
+    ),
+    (
+        "RALPH_ENV_POLICY",
+        "inherit, allowlist or blocklist; overrides [env] policy in config.toml, overridden by --env-policy.",
+    ),
+    (
+        "RALPH_ENV_ALLOW, RALPH_ENV_BLOCK",
+        "Variable names separated by commas; override [env] allow and block in config.toml.",
+    ),
+    (
+        "RALPH_CLEAN_OLDER_THAN",
+        "Age threshold for ralph clean; overrides config.toml, overridden by --older-than.",
+    ),
+    (
+        "RALPH_PROVIDER_CMD",
+        "Run this command (split on whitespace, prompt appended) instead of the provider CLI. Meant for tests.",
+    ),
+];
+
+/// Render `ralph.1` plus one page per visible subcommand into `out_dir`.
+/// Pages carry no date, so the output only changes when the CLI does.
+pub fn generate(out_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(out_dir)?;
+    let mut cmd = crate::Cli::command().disable_help_subcommand(true);
+    cmd.build();
+
+    let mut written = Vec::new();
+    for (name, page) in render_pages(&cmd)? {
+        let path = out_dir.join(name);
+        fs::write(&path, page)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// `(file name, roff source)` for the root command and its subcommands.
+fn render_pages(cmd: &clap::Command) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut pages = vec![render_page(cmd.clone(), true)?];
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let with_exit_codes = matches!(sub.get_name(), "run" | "check-update");
+        pages.push(render_page(sub.clone(), with_exit_codes)?);
+    }
+    Ok(pages)
+}
+
+fn render_page(cmd: clap::Command, with_exit_codes: bool) -> io::Result<(String, Vec<u8>)> {
+    let is_root = cmd.get_name() == "ralph";
+    let man = clap_mangen::Man::new(cmd).manual("ralph manual");
+    let mut page = Vec::new();
+    man.render(&mut page)?;
+    if with_exit_codes {
+        page.extend(roff_table("EXIT STATUS", EXIT_CODES).as_bytes());
+    }
+    if is_root {
+        page.extend(roff_table("CONFIGURATION", CONFIG_KEYS).as_bytes());
+        page.extend(roff_table("ENVIRONMENT", ENVIRONMENT).as_bytes());
+        page.extend(
+            concat!(
+                ".SH FILES\n",
+                ".TP\n~/.Ralph/config.toml\nGlobal configuration.\n",
+                ".TP\n\\&.ralph/config.toml\nProject configuration; overrides the global file.\n",
+                ".TP\n~/.Ralph/system\\-prompt.md\nSystem prompt sent to the provider.\n",
+                ".TP\n~/.Ralph/sessions/\nSession transcripts.\n",
+                ".TP\n~/.Ralph/logs/\nCaptured provider output per session and iteration.\n",
+                ".TP\n~/.Ralph/audit.log\nEvery command ralph has run, one hash\\-chained JSON line each.\n",
+                ".TP\n~/.Ralph/pricing.toml\nModel prices per million tokens, for the cost in \\fBralph stats\\fR.\n",
+            )
+            .as_bytes(),
+        );
+    }
+    Ok((man.get_filename(), page))
+}
+
+fn roff_table(heading: &str, rows: &[(&str, &str)]) -> String {
+    let mut out = format!(".SH {heading}\n");
+    for (term, meaning) in rows {
+        out.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            roff_escape(term),
+            roff_escape(meaning)
+        ));
+    }
+    out
+}
+
+fn roff_escape(s: &str) -> String {
+    let s = s.replace('\\', "\\\\").replace('-', "\\-");
+    if s.starts_with('.') || s.starts_with('\'') {
+        format!("\\&{s}")
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages() -> Vec<(String, String)> {
+        let mut cmd = crate::Cli::command().disable_help_subcommand(true);
+        cmd.build();
+        render_pages(&cmd)
+            .unwrap()
+            .into_iter()
+            .map(|(name, page)| (name, String::from_utf8(page).unwrap()))
+            .collect()
+    }
+
+    fn page(name: &str) -> String {
+        pages()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .unwrap_or_else(|| panic!("no page {name}"))
+            .1
+    }
+
+    #[test]
+    fn pages_cover_subcommands_and_flags() {
+        let names: Vec<_> = pages().into_iter().map(|(n, _)| n).collect();
+        assert!(names.contains(&"ralph.1".to_string()));
+        assert!(names.contains(&"ralph-loop.1".to_string()));
+        assert!(!names.contains(&"ralph-man.1".to_string()));
+
+        let root = page("ralph.1");
+        assert!(root.contains(".SH EXIT STATUS"));
+        assert!(root.contains("Invalid arguments"));
+        assert!(root.contains("older_than"));
+        assert!(page("ralph-loop.1").contains("\\-\\-iterations"));
+        assert!(page("ralph-run.1").contains("still open"));
+    }
+
+    #[test]
+    fn rendering_is_deterministic() {
+        assert_eq!(pages(), pages());
+    }
+}
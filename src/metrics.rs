@@ -0,0 +1,346 @@
+use crate::backlog::{BacklogDiff, Snapshot};
+use crate::fsutil;
+use crate::progress::IterationReport;
+use crate::reporter;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A source of backlog snapshots, for counting the tasks a loop closed.
+type BacklogSource = Box<dyn FnMut() -> Option<Snapshot>>;
+
+/// `loop --metrics-file`: a loop's counters in the Prometheus text format,
+/// for node_exporter's textfile collector. The file is written when the
+/// loop finishes (and with [`live`](Self::live), after every iteration),
+/// atomically, so the exporter never reads half of it: the temporary file
+/// beside it doesn't end in `.prom`, which the collector skips.
+pub struct LoopMetrics {
+    path: PathBuf,
+    live: bool,
+    repo: String,
+    provider: String,
+    session_id: String,
+    label: String,
+    clock: Instant,
+    iterations: u32,
+    failed: u32,
+    input_tokens: u64,
+    output_tokens: u64,
+    tasks_closed: Option<usize>,
+    completed: bool,
+    backlog: Option<(BacklogSource, Option<Snapshot>)>,
+}
+
+impl LoopMetrics {
+    /// Metrics at `path` for a loop in repository `repo` running
+    /// `provider`.
+    pub fn new(path: impl Into<PathBuf>, repo: &str, provider: &str) -> LoopMetrics {
+        LoopMetrics {
+            path: path.into(),
+            live: false,
+            repo: repo.to_string(),
+            provider: provider.to_string(),
+            session_id: String::new(),
+            label: String::new(),
+            clock: Instant::now(),
+            iterations: 0,
+            failed: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            tasks_closed: None,
+            completed: false,
+            backlog: None,
+        }
+    }
+
+    /// Also write the file after every iteration, not just at the end.
+    pub fn live(mut self, live: bool) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Label the samples with the run's `--label`, sanitized down to
+    /// letters, digits and `-_.:/@+` and spaces (anything else becomes `_`)
+    /// so dashboards can match it with plain regexes.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = sanitize(label);
+        self
+    }
+
+    /// Count the tasks closed by comparing what `snapshot` returns with
+    /// what it returned when the loop started. Without it, or while it
+    /// returns `None`, `ralph_tasks_closed` is left out.
+    pub fn backlog(mut self, mut snapshot: impl FnMut() -> Option<Snapshot> + 'static) -> Self {
+        let before = snapshot();
+        self.backlog = Some((Box::new(snapshot), before));
+        self
+    }
+
+    /// Where the metrics are written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Note the session the loop is recorded under.
+    pub fn start(&mut self, session_id: Option<&str>) {
+        self.session_id = session_id.unwrap_or_default().to_string();
+        self.clock = Instant::now();
+        if self.live {
+            self.write();
+        }
+    }
+
+    /// Count `report`'s iteration.
+    pub fn iteration(&mut self, report: &IterationReport) {
+        self.iterations = report.iteration;
+        if report.exit_code != 0 || report.signal.is_some() {
+            self.failed += 1;
+        }
+        if let Some(usage) = report.usage {
+            self.input_tokens += usage.input_tokens;
+            self.output_tokens += usage.output_tokens;
+        }
+        if self.live {
+            self.count_closed();
+            self.write();
+        }
+    }
+
+    /// Note whether the loop finished its work and write the final numbers.
+    pub fn finish(&mut self, completed: bool) {
+        self.completed = completed;
+        self.count_closed();
+        self.write();
+    }
+
+    fn count_closed(&mut self) {
+        let Some((snapshot, Some(before))) = self.backlog.as_mut() else {
+            return;
+        };
+        if let Some(after) = snapshot() {
+            self.tasks_closed = Some(BacklogDiff::between(before, &after).closed.len());
+        }
+    }
+
+    /// The file as it stands now.
+    pub fn render(&self) -> String {
+        self.render_at(self.clock.elapsed())
+    }
+
+    fn render_at(&self, elapsed: Duration) -> String {
+        let labels = format!(
+            "repo=\"{}\",provider=\"{}\",session_id=\"{}\",label=\"{}\"",
+            escape(&self.repo),
+            escape(&self.provider),
+            escape(&self.session_id),
+            escape(&self.label)
+        );
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (extra, value) in samples {
+                let _ = writeln!(out, "{name}{{{labels}{extra}}} {value}");
+            }
+        };
+        metric(
+            "ralph_iterations_total",
+            "counter",
+            "Iterations the loop has run.",
+            &[("", self.iterations.to_string())],
+        );
+        metric(
+            "ralph_iterations_failed",
+            "counter",
+            "Iterations whose provider exited non-zero or was killed.",
+            &[("", self.failed.to_string())],
+        );
+        metric(
+            "ralph_run_duration_seconds",
+            "gauge",
+            "Time since the loop started.",
+            &[("", format!("{:.3}", elapsed.as_secs_f64()))],
+        );
+        if let Some(closed) = self.tasks_closed {
+            metric(
+                "ralph_tasks_closed",
+                "gauge",
+                "Tasks closed in the tracker since the loop started.",
+                &[("", closed.to_string())],
+            );
+        }
+        metric(
+            "ralph_tokens_total",
+            "counter",
+            "Tokens the provider reported, by direction.",
+            &[
+                (",direction=\"input\"", self.input_tokens.to_string()),
+                (",direction=\"output\"", self.output_tokens.to_string()),
+            ],
+        );
+        metric(
+            "ralph_completed",
+            "gauge",
+            "1 once the loop has finished its work, else 0.",
+            &[("", u8::from(self.completed).to_string())],
+        );
+        out
+    }
+
+    /// Replace the file with [`render`](Self::render). A failed write is
+    /// a warning; the loop goes on.
+    fn write(&self) {
+        if let Err(e) = fsutil::write_atomic(&self.path, self.render()) {
+            reporter::warn(&format!(
+                "Failed to write the metrics file {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+    }
+}
+
+/// `label` with every character but letters, digits, spaces and `-_.:/@+`
+/// replaced by `_`.
+fn sanitize(label: &str) -> String {
+    label
+        .trim()
+        .chars()
+        .map(|c| match c.is_alphanumeric() || " -_.:/@+".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// `value` as a Prometheus label value: backslashes, double quotes and
+/// newlines escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlog::BacklogItem;
+    use crate::usage::Usage;
+    use std::cell::Cell;
+    use std::fs;
+    use std::rc::Rc;
+
+    /// Dashboards are built on these names and labels; changing them breaks
+    /// every query using them.
+    #[test]
+    fn metric_names_and_labels_are_stable() {
+        let mut metrics = LoopMetrics::new("ralph.prom", "my-app", "claude").label("nightly");
+        metrics.session_id = "20261016-120000-ab12".to_string();
+        metrics.iteration(&IterationReport {
+            iteration: 1,
+            exit_code: 1,
+            usage: Some(Usage {
+                input_tokens: 1200,
+                output_tokens: 300,
+                cost_usd: None,
+            }),
+            ..IterationReport::default()
+        });
+        metrics.iteration(&IterationReport {
+            iteration: 2,
+            usage: Some(Usage {
+                input_tokens: 800,
+                output_tokens: 200,
+                cost_usd: None,
+            }),
+            ..IterationReport::default()
+        });
+        metrics.tasks_closed = Some(2);
+        metrics.completed = true;
+        let labels =
+            r#"repo="my-app",provider="claude",session_id="20261016-120000-ab12",label="nightly""#;
+        assert_eq!(
+            metrics.render_at(Duration::from_millis(90_500)),
+            format!(
+                "# HELP ralph_iterations_total Iterations the loop has run.\n\
+                 # TYPE ralph_iterations_total counter\n\
+                 ralph_iterations_total{{{labels}}} 2\n\
+                 # HELP ralph_iterations_failed Iterations whose provider exited non-zero or was killed.\n\
+                 # TYPE ralph_iterations_failed counter\n\
+                 ralph_iterations_failed{{{labels}}} 1\n\
+                 # HELP ralph_run_duration_seconds Time since the loop started.\n\
+                 # TYPE ralph_run_duration_seconds gauge\n\
+                 ralph_run_duration_seconds{{{labels}}} 90.500\n\
+                 # HELP ralph_tasks_closed Tasks closed in the tracker since the loop started.\n\
+                 # TYPE ralph_tasks_closed gauge\n\
+                 ralph_tasks_closed{{{labels}}} 2\n\
+                 # HELP ralph_tokens_total Tokens the provider reported, by direction.\n\
+                 # TYPE ralph_tokens_total counter\n\
+                 ralph_tokens_total{{{labels},direction=\"input\"}} 2000\n\
+                 ralph_tokens_total{{{labels},direction=\"output\"}} 500\n\
+                 # HELP ralph_completed 1 once the loop has finished its work, else 0.\n\
+                 # TYPE ralph_completed gauge\n\
+                 ralph_completed{{{labels}}} 1\n"
+            )
+        );
+
+        let odd = LoopMetrics::new("ralph.prom", "a \"quoted\"\\repo\n", "droid")
+            .label(" nightly {\"eu\"}\n");
+        assert!(odd.render().contains(
+            r#"{repo="a \"quoted\"\\repo\n",provider="droid",session_id="",label="nightly __eu__"}"#
+        ));
+    }
+
+    #[test]
+    fn the_file_is_written_at_the_end_or_live() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ralph.prom");
+        let snapshots = Rc::new(Cell::new(0));
+        let counter = snapshots.clone();
+        let mut metrics = LoopMetrics::new(&path, "app", "droid").backlog(move || {
+            counter.set(counter.get() + 1);
+            let status = match counter.get() > 1 {
+                true => "closed",
+                false => "open",
+            };
+            Some(Snapshot::new([BacklogItem {
+                id: "bd-1".to_string(),
+                title: String::new(),
+                status: status.to_string(),
+            }]))
+        });
+        metrics.start(Some("s-1"));
+        metrics.iteration(&IterationReport {
+            iteration: 1,
+            ..IterationReport::default()
+        });
+        assert!(!path.exists());
+        assert_eq!(snapshots.get(), 1);
+        metrics.finish(false);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(
+            "ralph_tasks_closed{repo=\"app\",provider=\"droid\",session_id=\"s-1\",label=\"\"} 1\n"
+        ));
+        assert!(content.contains(
+            "ralph_completed{repo=\"app\",provider=\"droid\",session_id=\"s-1\",label=\"\"} 0\n"
+        ));
+
+        let live = dir.path().join("live.prom");
+        let mut metrics = LoopMetrics::new(&live, "app", "droid").live(true);
+        metrics.start(None);
+        assert!(fs::read_to_string(&live).unwrap().contains(
+            "ralph_iterations_total{repo=\"app\",provider=\"droid\",session_id=\"\",label=\"\"} 0\n"
+        ));
+        metrics.iteration(&IterationReport {
+            iteration: 1,
+            signal: Some(9),
+            ..IterationReport::default()
+        });
+        let content = fs::read_to_string(&live).unwrap();
+        assert!(content.contains(
+            "ralph_iterations_failed{repo=\"app\",provider=\"droid\",session_id=\"\",label=\"\"} 1\n"
+        ));
+        assert!(!content.contains("ralph_tasks_closed"));
+    }
+}
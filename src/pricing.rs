@@ -0,0 +1,221 @@
+use crate::config;
+use crate::error::{RalphError, Result};
+use crate::fsutil;
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The pricing table's file name in the config directory.
+pub const PRICING_FILE: &str = "pricing.toml";
+
+/// The prices `pricing.toml` starts out with, in USD per million input and
+/// output tokens.
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-haiku-4-5", 1.0, 5.0),
+    ("claude-opus-4-1", 15.0, 75.0),
+    ("claude-opus-4-5", 5.0, 25.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-sonnet-4-5", 3.0, 15.0),
+    ("gemini-2.5-flash", 0.3, 2.5),
+    ("gemini-2.5-pro", 1.25, 10.0),
+    ("gpt-5", 1.25, 10.0),
+    ("gpt-5-codex", 1.25, 10.0),
+    ("gpt-5-mini", 0.25, 2.0),
+];
+
+/// What `pricing.toml` says above the table.
+const HEADER: &str = "\
+# Prices in USD per million tokens, used by `ralph stats` to work out cost
+# from the token counts in session transcripts. A model matches the longest
+# name here it starts with, so \"claude-sonnet-4-5\" also prices
+# \"claude-sonnet-4-5-20250929\". Edit freely, or use
+# `ralph pricing set <model> <input> <output>`.
+
+";
+
+/// The price of a model's tokens, in USD per million.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    /// Per million input (prompt) tokens
+    pub input: f64,
+    /// Per million output (completion) tokens
+    pub output: f64,
+}
+
+impl Price {
+    /// What `usage`'s tokens cost at this price.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input + usage.output_tokens as f64 * self.output) / 1e6
+    }
+}
+
+/// The pricing table (`~/.Ralph/pricing.toml`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pricing {
+    /// Prices by model name
+    #[serde(default)]
+    pub models: BTreeMap<String, Price>,
+}
+
+impl Pricing {
+    /// The table ralph ships with.
+    pub fn builtin() -> Pricing {
+        Pricing {
+            models: DEFAULT_PRICES
+                .iter()
+                .map(|&(model, input, output)| (model.to_string(), Price { input, output }))
+                .collect(),
+        }
+    }
+
+    /// The price of `model`: its own entry, or else the longest name in
+    /// the table it starts with.
+    pub fn price(&self, model: &str) -> Option<Price> {
+        self.models.get(model).copied().or_else(|| {
+            self.models
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| *price)
+        })
+    }
+
+    /// The table as `pricing.toml` holds it, with an explanatory header.
+    pub fn render_file(&self) -> String {
+        let table = toml::to_string(self).expect("pricing serializes");
+        format!("{HEADER}{table}")
+    }
+
+    /// One line per model, for `ralph pricing list`.
+    pub fn render_text(&self) -> String {
+        if self.models.is_empty() {
+            return "No prices set.\n".to_string();
+        }
+        let width = self.models.keys().map(|m| m.len()).max().unwrap_or(0);
+        let mut out = format!(
+            "{:<width$}  {:>9}  {:>9}  (USD per million tokens)\n",
+            "MODEL", "INPUT", "OUTPUT"
+        );
+        for (model, price) in &self.models {
+            out.push_str(&format!(
+                "{:<width$}  {:>9.2}  {:>9.2}\n",
+                model, price.input, price.output
+            ));
+        }
+        out
+    }
+}
+
+/// Where the pricing table lives: `pricing.toml` in the config directory.
+pub fn path() -> Result<PathBuf> {
+    Ok(config::get_config_dir()?.join(PRICING_FILE))
+}
+
+/// Read the pricing table at `path`, or the built-in one when there's no
+/// file.
+pub fn load_from(path: &Path) -> Result<Pricing> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Pricing::builtin()),
+        Err(e) => return Err(e.into()),
+    };
+    toml::from_str(&content).map_err(|e| RalphError::Config {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+/// Read the pricing table; see [`load_from`].
+pub fn load() -> Result<Pricing> {
+    load_from(&path()?)
+}
+
+/// Write the built-in table to `path` unless a file is already there.
+pub fn seed(path: &Path) -> io::Result<bool> {
+    fsutil::create_atomic(path, Pricing::builtin().render_file())
+}
+
+/// Set `model`'s price in the table at `path`, keeping the other entries.
+pub fn set_in(path: &Path, model: &str, price: Price) -> Result<()> {
+    let valid = |p: f64| p.is_finite() && p >= 0.0;
+    if !valid(price.input) || !valid(price.output) {
+        return Err(RalphError::InvalidInput(format!(
+            "Prices must be zero or more, got {} and {}",
+            price.input, price.output
+        )));
+    }
+    let mut pricing = load_from(path)?;
+    pricing.models.insert(model.to_string(), price);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fsutil::write_atomic(path, pricing.render_file())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_match_the_longest_prefix() {
+        let pricing = Pricing::builtin();
+        assert_eq!(pricing.price("gpt-5-mini").unwrap().input, 0.25);
+        assert_eq!(pricing.price("gpt-5-2025-08-07").unwrap().input, 1.25);
+        assert_eq!(
+            pricing.price("claude-opus-4-5-20251101").unwrap().output,
+            25.0
+        );
+        assert_eq!(pricing.price("o3"), None);
+    }
+
+    #[test]
+    fn costs_are_per_million_tokens() {
+        let usage = Usage {
+            input_tokens: 2_000_000,
+            output_tokens: 100_000,
+            cost_usd: None,
+        };
+        let price = Price {
+            input: 3.0,
+            output: 15.0,
+        };
+        assert!((price.cost(&usage) - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn seeded_files_round_trip_and_keep_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PRICING_FILE);
+        assert_eq!(load_from(&path).unwrap(), Pricing::builtin());
+        assert!(seed(&path).unwrap());
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# Prices in USD"), "{content}");
+        assert!(content.contains("[models.\"gemini-2.5-pro\"]"), "{content}");
+        assert_eq!(load_from(&path).unwrap(), Pricing::builtin());
+
+        let price = Price {
+            input: 2.0,
+            output: 8.0,
+        };
+        set_in(&path, "o3", price).unwrap();
+        assert!(!seed(&path).unwrap());
+        let pricing = load_from(&path).unwrap();
+        assert_eq!(pricing.price("o3"), Some(price));
+        assert_eq!(pricing.models.len(), DEFAULT_PRICES.len() + 1);
+        assert!(fs::read_to_string(&path).unwrap().starts_with("# Prices"));
+
+        let negative = Price {
+            input: -1.0,
+            output: 1.0,
+        };
+        assert!(set_in(&path, "o3", negative).is_err());
+        fs::write(&path, "[models.x]\ninput = \"free\"\n").unwrap();
+        assert!(matches!(load_from(&path), Err(RalphError::Config { .. })));
+    }
+}
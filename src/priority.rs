@@ -0,0 +1,219 @@
+use crate::error::{RalphError, Result};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Highest `--nice` level: the lowest priority Unix schedulers have.
+pub const MAX_NICE: u8 = 19;
+
+/// A Linux I/O scheduling class for providers (`--ionice`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// Disk time only when nothing else wants it
+    Idle,
+    /// The normal class, at a level from 0 (first) to 7 (last)
+    BestEffort(u8),
+}
+
+impl IoClass {
+    /// Parse `idle`, `best-effort` or `best-effort:<0-7>`; `none` leaves
+    /// the I/O priority alone.
+    pub fn parse(value: &str) -> Result<Option<IoClass>> {
+        let invalid = || {
+            RalphError::InvalidInput(format!(
+                "Invalid ionice class '{}'. Valid classes: none, idle, best-effort[:0-7]",
+                value
+            ))
+        };
+        match value.split_once(':') {
+            None if value == "none" => Ok(None),
+            None if value == "idle" => Ok(Some(IoClass::Idle)),
+            None if value == "best-effort" => Ok(Some(IoClass::BestEffort(4))),
+            Some(("best-effort", level)) => match level.parse() {
+                Ok(level) if level <= 7 => Ok(Some(IoClass::BestEffort(level))),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The `ioprio` value for `ioprio_set(2)`.
+    #[cfg(target_os = "linux")]
+    fn ioprio(self) -> libc::c_int {
+        const CLASS_SHIFT: libc::c_int = 13;
+        match self {
+            IoClass::BestEffort(level) => (2 << CLASS_SHIFT) | libc::c_int::from(level),
+            IoClass::Idle => 3 << CLASS_SHIFT,
+        }
+    }
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoClass::Idle => f.write_str("idle"),
+            IoClass::BestEffort(level) => write!(f, "best-effort:{level}"),
+        }
+    }
+}
+
+impl Serialize for IoClass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Parse a `--nice` level: how much to lower the provider's CPU priority,
+/// from 0 (not at all) to [`MAX_NICE`].
+pub fn parse_nice(value: &str) -> Result<u8> {
+    match value.parse() {
+        Ok(level) if level <= MAX_NICE => Ok(level),
+        _ => Err(RalphError::InvalidInput(format!(
+            "nice must be a level from 0 to {MAX_NICE}, got '{value}'"
+        ))),
+    }
+}
+
+/// How much lower than ralph's own the providers' priority is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Priority {
+    /// Added to the CPU niceness (capped at [`MAX_NICE`]); on Windows any
+    /// level above 0 means `BELOW_NORMAL_PRIORITY_CLASS`
+    pub nice: u8,
+    /// I/O scheduling class, on Linux
+    pub io: Option<IoClass>,
+}
+
+impl Priority {
+    /// "nice 10, ionice idle", or `None` when providers run at ralph's own
+    /// priority.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.nice > 0 {
+            parts.push(format!("nice {}", self.nice));
+        }
+        if let Some(io) = self.io {
+            parts.push(format!("ionice {io}"));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+
+    /// Start `cmd` at this priority. Whatever the provider starts inherits
+    /// it, so it covers the provider's whole process group without touching
+    /// how the group is stopped.
+    #[cfg(unix)]
+    pub fn apply(&self, cmd: &mut Command) {
+        use std::os::unix::process::CommandExt;
+
+        let nice = (self.nice > 0).then(|| {
+            // SAFETY: getpriority(2) has no memory-safety preconditions.
+            let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            (current + libc::c_int::from(self.nice)).min(libc::c_int::from(MAX_NICE))
+        });
+        let io = self.io.filter(|_| cfg!(target_os = "linux"));
+        if nice.is_none() && io.is_none() {
+            return;
+        }
+        // SAFETY: the closure runs in the child between fork and exec and
+        // only makes async-signal-safe system calls.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(nice) = nice
+                    && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                #[cfg(target_os = "linux")]
+                if let Some(io) = io {
+                    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                    let set =
+                        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, io.ioprio());
+                    if set != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Start `cmd` at this priority: below normal for any `nice` level.
+    #[cfg(windows)]
+    pub fn apply(&self, cmd: &mut Command) {
+        use std::os::windows::process::CommandExt;
+
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        if self.nice > 0 {
+            cmd.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+        }
+    }
+}
+
+static PRIORITY: OnceLock<Priority> = OnceLock::new();
+
+/// Set the [`Priority`] for providers started from now on. The first call
+/// wins; without one, providers run at ralph's own priority.
+pub fn set(priority: Priority) {
+    let _ = PRIORITY.set(priority);
+}
+
+/// The priority set with [`set`].
+pub fn get() -> Priority {
+    PRIORITY.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nice_levels_and_io_classes_parse() {
+        assert_eq!(parse_nice("0").unwrap(), 0);
+        assert_eq!(parse_nice("19").unwrap(), 19);
+        assert!(parse_nice("20").is_err());
+        assert!(parse_nice("-5").is_err());
+
+        assert_eq!(IoClass::parse("none").unwrap(), None);
+        assert_eq!(IoClass::parse("idle").unwrap(), Some(IoClass::Idle));
+        assert_eq!(
+            IoClass::parse("best-effort").unwrap(),
+            Some(IoClass::BestEffort(4))
+        );
+        assert_eq!(
+            IoClass::parse("best-effort:7").unwrap(),
+            Some(IoClass::BestEffort(7))
+        );
+        assert!(IoClass::parse("best-effort:8").is_err());
+        assert!(IoClass::parse("realtime").is_err());
+        assert_eq!(IoClass::BestEffort(2).to_string(), "best-effort:2");
+    }
+
+    #[test]
+    fn descriptions_name_what_changed() {
+        assert_eq!(Priority::default().describe(), None);
+        let priority = Priority {
+            nice: 10,
+            io: Some(IoClass::Idle),
+        };
+        assert_eq!(priority.describe().as_deref(), Some("nice 10, ionice idle"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn providers_start_at_the_lowered_priority() {
+        let niceness = |priority: Priority| {
+            let mut cmd = Command::new("nice");
+            priority.apply(&mut cmd);
+            let output = cmd.output().unwrap();
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .trim()
+                .parse::<i32>()
+                .unwrap()
+        };
+        let base = niceness(Priority::default());
+        let lowered = niceness(Priority { nice: 5, io: None });
+        assert_eq!(lowered, (base + 5).min(19));
+    }
+}
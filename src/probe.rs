@@ -0,0 +1,423 @@
+use crate::audit::{self, Kind};
+use crate::config;
+use crate::exec::{CommandRunner, SystemRunner};
+use crate::fsutil;
+use crate::providers;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+/// How long a probe result is trusted, even when the binary looks unchanged.
+pub const PROBE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a provider gets to answer `--version` before it's killed and
+/// counted as of unknown version.
+pub const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where in the config directory's `cache/` probe results are kept.
+const CACHE_FILE: &str = "providers.json";
+
+/// What ralph found out about a provider CLI: where it is and what
+/// `--version` printed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+    /// The binary, when it's on PATH (or `RALPH_PROVIDER_CMD` names it)
+    pub path: Option<PathBuf>,
+    /// The first line `--version` printed, when it worked
+    pub version: Option<String>,
+    /// The binary's modification time (seconds since the epoch) when it
+    /// was probed; a different one means it was upgraded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// When it was probed (seconds since the epoch)
+    pub probed_at: u64,
+}
+
+impl Probe {
+    /// Whether this result still holds for the binary now at `path`: the
+    /// same path, modified at the same time, probed within `ttl`.
+    pub fn is_fresh(&self, path: Option<&Path>, now: u64, ttl: Duration) -> bool {
+        self.path.as_deref() == path
+            && self.mtime == path.and_then(mtime)
+            && now.saturating_sub(self.probed_at) < ttl.as_secs()
+    }
+}
+
+/// The cached probes, by provider name.
+type Cache = BTreeMap<String, Probe>;
+
+/// The probe cache: `cache/providers.json` in the config directory.
+pub fn cache_path() -> Option<PathBuf> {
+    config::get_config_dir()
+        .ok()
+        .map(|dir| dir.join("cache").join(CACHE_FILE))
+}
+
+/// A cache that's missing or unreadable (half-written by an older ralph,
+/// say) is empty: everything gets probed again.
+fn read_cache(path: &Path) -> Cache {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Merge `probes` into the cache at `path`. Best-effort: a failure to cache
+/// never fails the probe. The file is replaced atomically, so a concurrent
+/// ralph reads either the old cache or the new one; when two write at once,
+/// the later one wins and the other's results are just probed again.
+fn write_cache(path: &Path, probes: &Cache) {
+    let mut cache = read_cache(path);
+    cache.extend(probes.iter().map(|(k, v)| (k.clone(), v.clone())));
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(&cache) {
+        let _ = fsutil::write_atomic(path, json);
+    }
+}
+
+/// Probe `provider`, or reuse the cached result while it's fresh (see
+/// [`Probe::is_fresh`]). `refresh` probes regardless.
+pub fn probe(provider: &str, refresh: bool) -> Probe {
+    probe_all(&[provider], refresh)
+        .remove(provider)
+        .expect("every provider asked for is probed")
+}
+
+/// [`probe`] each of `providers`, writing the cache once.
+pub fn probe_all(providers: &[&str], refresh: bool) -> Cache {
+    let path = cache_path();
+    let cached = path.as_deref().map(read_cache).unwrap_or_default();
+    let now = epoch_secs(SystemTime::now()).unwrap_or_default();
+    let mut probes = Cache::new();
+    let mut probed = Cache::new();
+    for provider in providers {
+        let (program, args) = program(provider);
+        let binary = find_binary(&program);
+        let probe = match cached.get(*provider) {
+            Some(probe) if !refresh && probe.is_fresh(binary.as_deref(), now, PROBE_TTL) => {
+                probe.clone()
+            }
+            _ => {
+                let probe = run_probe(binary, args.as_deref(), now);
+                probed.insert(provider.to_string(), probe.clone());
+                probe
+            }
+        };
+        probes.insert(provider.to_string(), probe);
+    }
+    if let Some(path) = path.filter(|_| !probed.is_empty()) {
+        write_cache(&path, &probed);
+    }
+    probes
+}
+
+/// [`probe_all`] of every supported provider and every one config.toml
+/// defines (see [`providers::set_templates`]).
+pub fn probe_known(refresh: bool) -> Cache {
+    probe_all(&providers::names(), refresh)
+}
+
+/// The program that runs `provider` and the arguments before `--version`:
+/// the provider's binary, or what `RALPH_PROVIDER_CMD` runs instead. A
+/// [`Template`](providers::Template) provider's program is only looked for:
+/// a wrapper script would take `--version` for a prompt.
+fn program(provider: &str) -> (String, Option<Vec<String>>) {
+    if let Some(mut words) = providers::command_override() {
+        let program = words.remove(0);
+        return (program, Some(words));
+    }
+    if let Some(template) = providers::template(provider) {
+        let program = match template.shell {
+            true => shlex::split(&template.command).and_then(|words| words.into_iter().next()),
+            false => template
+                .argv("", 1, &[])
+                .ok()
+                .and_then(|argv| argv.into_iter().next()),
+        };
+        return (program.unwrap_or_default(), None);
+    }
+    match providers::spec(provider) {
+        Some(spec) => (spec.binary.to_string(), Some(Vec::new())),
+        None => (provider.to_string(), Some(Vec::new())),
+    }
+}
+
+/// Probe the program at `binary`, running it with `args` and `--version`
+/// (for at most [`VERSION_TIMEOUT`]) unless `args` is `None`.
+fn run_probe(binary: Option<PathBuf>, args: Option<&[String]>, now: u64) -> Probe {
+    let version = binary.as_deref().zip(args).and_then(|(binary, args)| {
+        let mut cmd = Command::new(binary);
+        cmd.args(args).arg("--version").stdin(Stdio::null());
+        let output = audit::run(Kind::Provider, &mut cmd, |cmd| {
+            SystemRunner.output_timeout(cmd, VERSION_TIMEOUT)
+        })
+        .ok()?;
+        let line = output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty())?;
+        (output.code == 0 && output.signal.is_none()).then(|| line.to_string())
+    });
+    Probe {
+        mtime: binary.as_deref().and_then(mtime),
+        path: binary,
+        version,
+        probed_at: now,
+    }
+}
+
+fn mtime(path: &Path) -> Option<u64> {
+    epoch_secs(fs::metadata(path).ok()?.modified().ok()?)
+}
+
+fn epoch_secs(time: SystemTime) -> Option<u64> {
+    Some(time.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Where `program` is: itself when it names a path, otherwise the first
+/// match on PATH (trying the usual extensions on Windows).
+pub fn find_binary(program: &str) -> Option<PathBuf> {
+    find_binary_in(program, &std::env::var_os("PATH").unwrap_or_default())
+}
+
+/// [`find_binary`], searching the directories of `search_path` (a value of
+/// PATH) instead.
+pub fn find_binary_in(program: &str, search_path: &OsStr) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let extensions: &[&str] = if cfg!(windows) {
+        &["", ".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+    std::env::split_paths(search_path)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .find_map(|dir| {
+            extensions
+                .iter()
+                .map(|ext| dir.join(format!("{program}{ext}")))
+                .find(|candidate| candidate.is_file())
+        })
+}
+
+/// The version in what `--version` printed: the first word that reads as
+/// one, missing minor and patch numbers taken as 0. `2.1.3 (Claude Code)`
+/// is 2.1.3, `codex-cli 0.45` is 0.45.0.
+pub fn parse_version(text: &str) -> Option<Version> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ','))
+        .map(|word| word.strip_prefix('v').unwrap_or(word))
+        .find_map(|word| {
+            if let Ok(version) = Version::parse(word) {
+                return Some(version);
+            }
+            let numbers = word
+                .split('.')
+                .map(|n| n.parse::<u64>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            match numbers[..] {
+                [major] => Some(Version::new(major, 0, 0)),
+                [major, minor] => Some(Version::new(major, minor, 0)),
+                [major, minor, patch] => Some(Version::new(major, minor, patch)),
+                _ => None,
+            }
+        })
+}
+
+/// Whether the provider `probe` found satisfies `required`. When it
+/// doesn't, what was found instead: "at version 2.0.5", or why there's no
+/// version to compare.
+pub fn check_pin(probe: &Probe, required: &VersionReq) -> Result<(), String> {
+    let Some(line) = &probe.version else {
+        return Err(match probe.path {
+            Some(_) => "of unknown version (`--version` failed)".to_string(),
+            None => "not found on PATH".to_string(),
+        });
+    };
+    match parse_version(line) {
+        Some(version) if required.matches(&version) => Ok(()),
+        Some(version) => Err(format!("at version {version}")),
+        None => Err(format!("of unknown version ('{line}')")),
+    }
+}
+
+/// One line per pinned provider saying whether `probes` found a matching
+/// version, for `ralph providers`. Empty without pins.
+pub fn render_pins(probes: &Cache, pins: &BTreeMap<String, VersionReq>) -> String {
+    let mut out = String::new();
+    for (provider, required) in pins {
+        let verdict = match probes.get(provider).map(|p| check_pin(p, required)) {
+            Some(Ok(())) => "ok".to_string(),
+            Some(Err(found)) => format!("MISMATCH, {found}"),
+            None => "not probed".to_string(),
+        };
+        out.push_str(&format!("{provider} pinned to {required}: {verdict}\n"));
+    }
+    out
+}
+
+/// One provider as `ralph providers --json` lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Availability {
+    /// The name used with `--provider`
+    pub provider: String,
+    /// Whether config.toml defines it rather than ralph
+    pub configured: bool,
+    /// Whether its program was found
+    pub available: bool,
+    /// Where its program is
+    pub path: Option<PathBuf>,
+    /// The first line `--version` printed
+    pub version: Option<String>,
+    /// The version requirement `[provider_versions]` pins it to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<String>,
+    /// Whether its version meets [`pinned`](Self::pinned)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_ok: Option<bool>,
+}
+
+/// What `probes` found, one entry per provider, with how each fares
+/// against `pins`.
+pub fn availability(probes: &Cache, pins: &BTreeMap<String, VersionReq>) -> Vec<Availability> {
+    probes
+        .iter()
+        .map(|(provider, probe)| {
+            let pin = pins.get(provider);
+            Availability {
+                provider: provider.clone(),
+                configured: providers::template(provider).is_some(),
+                available: probe.path.is_some(),
+                path: probe.path.clone(),
+                version: probe.version.clone(),
+                pinned: pin.map(VersionReq::to_string),
+                pin_ok: pin.map(|required| check_pin(probe, required).is_ok()),
+            }
+        })
+        .collect()
+}
+
+/// One line per provider: its version and where it is, or that it's
+/// missing.
+pub fn render_text(probes: &Cache) -> String {
+    let width = probes.keys().map(|p| p.len()).max().unwrap_or(0).max(8);
+    let mut out = format!("{:<width$}  {:<24}  PATH\n", "PROVIDER", "VERSION");
+    for (provider, probe) in probes {
+        let path = match &probe.path {
+            Some(path) => path.display().to_string(),
+            None => "not found on PATH".to_string(),
+        };
+        out.push_str(&format!(
+            "{:<width$}  {:<24}  {}\n",
+            provider,
+            probe.version.as_deref().unwrap_or("-"),
+            path
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_go_stale_when_the_binary_changes_or_with_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("claude");
+        fs::write(&binary, "").unwrap();
+        let now = epoch_secs(SystemTime::now()).unwrap();
+        let probe = Probe {
+            path: Some(binary.clone()),
+            version: Some("1.0.0".to_string()),
+            mtime: mtime(&binary),
+            probed_at: now,
+        };
+        assert!(probe.is_fresh(Some(&binary), now, PROBE_TTL));
+        assert!(!probe.is_fresh(Some(&dir.path().join("other")), now, PROBE_TTL));
+        assert!(!probe.is_fresh(None, now, PROBE_TTL));
+        let later = now + 25 * 60 * 60;
+        assert!(!probe.is_fresh(Some(&binary), later, PROBE_TTL));
+        let upgraded = Probe {
+            mtime: probe.mtime.map(|m| m - 60),
+            ..probe.clone()
+        };
+        assert!(!upgraded.is_fresh(Some(&binary), now, PROBE_TTL));
+    }
+
+    #[test]
+    fn the_cache_merges_and_survives_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join(CACHE_FILE);
+        let probe = |version: &str| Probe {
+            path: None,
+            version: Some(version.to_string()),
+            mtime: None,
+            probed_at: 0,
+        };
+        write_cache(&path, &Cache::from([("codex".to_string(), probe("1"))]));
+        write_cache(&path, &Cache::from([("claude".to_string(), probe("2"))]));
+        let cache = read_cache(&path);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), ["claude", "codex"]);
+
+        fs::write(&path, "{\"claude\": {\"pa").unwrap();
+        assert!(read_cache(&path).is_empty());
+    }
+
+    #[test]
+    fn versions_are_read_from_version_lines_and_checked_against_pins() {
+        let parsed = |text: &str| parse_version(text).map(|v| v.to_string());
+        assert_eq!(parsed("2.1.3 (Claude Code)").as_deref(), Some("2.1.3"));
+        assert_eq!(parsed("codex-cli 0.45").as_deref(), Some("0.45.0"));
+        assert_eq!(parsed("v1.0.0-beta.2").as_deref(), Some("1.0.0-beta.2"));
+        assert_eq!(parsed("gemini, build abc"), None);
+
+        let probe = |version: Option<&str>| Probe {
+            path: Some(PathBuf::from("/bin/claude")),
+            version: version.map(str::to_string),
+            mtime: None,
+            probed_at: 0,
+        };
+        let pin = VersionReq::parse("2.1.x").unwrap();
+        assert_eq!(check_pin(&probe(Some("2.1.9 (Claude Code)")), &pin), Ok(()));
+        assert_eq!(
+            check_pin(&probe(Some("2.2.0 (Claude Code)")), &pin),
+            Err("at version 2.2.0".to_string())
+        );
+        assert_eq!(
+            check_pin(&probe(None), &pin),
+            Err("of unknown version (`--version` failed)".to_string())
+        );
+        let probes = Cache::from([("claude".to_string(), probe(Some("2.0.1")))]);
+        let pins = BTreeMap::from([
+            ("claude".to_string(), pin),
+            ("codex".to_string(), VersionReq::parse(">=0.45").unwrap()),
+        ]);
+        assert_eq!(
+            render_pins(&probes, &pins),
+            "claude pinned to 2.1.*: MISMATCH, at version 2.0.1\n\
+             codex pinned to >=0.45: not probed\n"
+        );
+    }
+
+    #[test]
+    fn paths_are_found_directly_or_not_at_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("agent");
+        fs::write(&binary, "").unwrap();
+        assert_eq!(find_binary(binary.to_str().unwrap()), Some(binary));
+        assert_eq!(
+            find_binary(dir.path().join("absent").to_str().unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use crate::backlog::{BacklogDiff, Snapshot};
+use crate::exec;
+use crate::fsutil;
+use crate::pricing::{self, Pricing};
+use crate::render;
+use crate::reporter;
+use crate::usage::Usage;
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How many lines of the agent's closing words each iteration's section
+/// quotes.
+const SUMMARY_LINES: usize = 5;
+
+/// A source of backlog snapshots, for telling which tasks each iteration
+/// closed.
+type BacklogSource = Box<dyn FnMut() -> Option<Snapshot>>;
+
+/// What one iteration did, as [`ProgressReport::iteration`] writes it up.
+#[derive(Debug, Clone, Default)]
+pub struct IterationReport {
+    /// The iteration's number, from 1
+    pub iteration: u32,
+    /// Whether it was a planning pass
+    pub planning: bool,
+    /// When the provider started
+    pub started: Option<DateTime<Local>>,
+    /// How long the provider ran
+    pub duration: Duration,
+    /// The provider's exit code
+    pub exit_code: i32,
+    /// The signal that killed the provider, if one did
+    pub signal: Option<i32>,
+    /// Whether the agent reported the work complete and the gates agreed
+    pub complete: bool,
+    /// Each gate's command and exit code
+    pub gates: Vec<(String, i32)>,
+    /// Tokens the provider reported
+    pub usage: Option<Usage>,
+    /// The model the provider reported
+    pub model: Option<String>,
+    /// The provider's raw output, for the agent's closing words
+    pub output: String,
+}
+
+/// `loop --report-file`: a markdown file that grows a section per
+/// iteration under a status header, so a loop can be followed from an
+/// editor or a browser. The whole file is rewritten atomically on every
+/// update, so a reader never sees half of one.
+pub struct ProgressReport {
+    path: PathBuf,
+    max_iterations: u32,
+    started: DateTime<Local>,
+    clock: Instant,
+    pricing: Pricing,
+    session_id: Option<String>,
+    iterations: u32,
+    cost: Option<f64>,
+    sections: Vec<String>,
+    summary: Option<String>,
+    backlog: Option<(BacklogSource, Option<Snapshot>)>,
+}
+
+impl ProgressReport {
+    /// A report at `path` for a loop of up to `max_iterations`. Costs are
+    /// estimated with the pricing table, or the built-in one when it can't
+    /// be read.
+    pub fn new(path: impl Into<PathBuf>, max_iterations: u32) -> ProgressReport {
+        ProgressReport {
+            path: path.into(),
+            max_iterations,
+            started: Local::now(),
+            clock: Instant::now(),
+            pricing: pricing::load().unwrap_or_else(|_| Pricing::builtin()),
+            session_id: None,
+            iterations: 0,
+            cost: None,
+            sections: Vec::new(),
+            summary: None,
+            backlog: None,
+        }
+    }
+
+    /// Tell which tasks each iteration closed by comparing what `snapshot`
+    /// returns after it with what it returned before. A `None` skips the
+    /// comparison for that iteration.
+    pub fn backlog(mut self, mut snapshot: impl FnMut() -> Option<Snapshot> + 'static) -> Self {
+        let before = snapshot();
+        self.backlog = Some((Box::new(snapshot), before));
+        self
+    }
+
+    /// Where the report is written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Note the session the loop is recorded under and write the header.
+    pub fn start(&mut self, session_id: Option<&str>) {
+        self.session_id = session_id.map(str::to_string);
+        self.write();
+    }
+
+    /// Add a section for `report` and update the header.
+    pub fn iteration(&mut self, report: &IterationReport) {
+        let closed = self.closed_tasks();
+        self.iterations = report.iteration;
+        if let Some(usage) = report.usage {
+            let cost = usage.cost_usd.or_else(|| {
+                let price = self.pricing.price(report.model.as_deref()?)?;
+                Some(price.cost(&usage))
+            });
+            if let Some(cost) = cost {
+                *self.cost.get_or_insert(0.0) += cost;
+            }
+        }
+        self.sections
+            .push(render_iteration(report, closed.as_deref()));
+        self.write();
+    }
+
+    /// Add `text` to the closing summary, which mirrors what the terminal
+    /// shows once the loop ends, and mark the loop finished.
+    pub fn finish(&mut self, text: &str) {
+        let summary = self.summary.get_or_insert_with(String::new);
+        summary.push_str(text.trim_end());
+        summary.push('\n');
+        self.write();
+    }
+
+    /// The ids of the tasks closed since the last snapshot, when there's a
+    /// backlog to compare.
+    fn closed_tasks(&mut self) -> Option<Vec<String>> {
+        let (snapshot, previous) = self.backlog.as_mut()?;
+        let after = snapshot()?;
+        let closed = previous.as_ref().map(|before| {
+            BacklogDiff::between(before, &after)
+                .closed
+                .into_iter()
+                .map(|item| item.id)
+                .collect()
+        });
+        *previous = Some(after);
+        closed
+    }
+
+    /// The whole file as it stands now.
+    pub fn render(&self) -> String {
+        let mut out = String::from("# Ralph loop progress\n\n");
+        let status = match self.summary {
+            Some(_) => "finished",
+            None => "running",
+        };
+        let _ = writeln!(out, "- **Status:** {status}");
+        let _ = writeln!(
+            out,
+            "- **Iterations:** {} done, {} remaining (of {})",
+            self.iterations,
+            match self.summary {
+                Some(_) => 0,
+                None => self.max_iterations.saturating_sub(self.iterations),
+            },
+            self.max_iterations
+        );
+        let _ = writeln!(
+            out,
+            "- **Started:** {}",
+            self.started.format("%Y-%m-%d %H:%M:%S")
+        );
+        let _ = writeln!(
+            out,
+            "- **Elapsed:** {}",
+            format_duration(self.clock.elapsed())
+        );
+        match self.cost {
+            Some(cost) => {
+                let _ = writeln!(out, "- **Estimated cost:** ${cost:.2}");
+            }
+            None => out.push_str("- **Estimated cost:** unknown\n"),
+        }
+        if let Some(id) = &self.session_id {
+            let _ = writeln!(out, "- **Session:** {id}");
+        }
+        let _ = writeln!(
+            out,
+            "- **Updated:** {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        for section in &self.sections {
+            out.push('\n');
+            out.push_str(section);
+        }
+        if let Some(summary) = &self.summary {
+            out.push_str("\n## Summary\n\n");
+            out.push_str(summary);
+        }
+        out
+    }
+
+    /// Replace the file with [`render`](Self::render). A failed write is
+    /// a warning; the loop goes on.
+    fn write(&self) {
+        if let Err(e) = fsutil::write_atomic(&self.path, self.render()) {
+            reporter::warn(&format!(
+                "Failed to write the progress report {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+    }
+}
+
+fn render_iteration(report: &IterationReport, closed: Option<&[String]>) -> String {
+    let mut out = match report.planning {
+        true => format!("## Iteration {} (planning)\n\n", report.iteration),
+        false => format!("## Iteration {}\n\n", report.iteration),
+    };
+    if let Some(started) = report.started {
+        let _ = writeln!(
+            out,
+            "- **Started:** {}",
+            started.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    let _ = writeln!(out, "- **Duration:** {}", format_duration(report.duration));
+    match report.signal {
+        Some(signal) => {
+            let _ = writeln!(
+                out,
+                "- **Exit code:** provider {}",
+                exec::describe_signal(signal)
+            );
+        }
+        None => {
+            let _ = writeln!(out, "- **Exit code:** {}", report.exit_code);
+        }
+    }
+    if report.complete {
+        out.push_str("- Reported all tasks complete\n");
+    }
+    match closed {
+        Some([]) => out.push_str("- **Tasks closed:** none\n"),
+        Some(ids) => {
+            let _ = writeln!(out, "- **Tasks closed:** {}", ids.join(", "));
+        }
+        None => {}
+    }
+    for (command, exit_code) in &report.gates {
+        match exit_code {
+            0 => {
+                let _ = writeln!(out, "- **Gate:** `{command}` passed");
+            }
+            code => {
+                let _ = writeln!(out, "- **Gate:** `{command}` failed (exit code {code})");
+            }
+        }
+    }
+    if let Some(usage) = report.usage {
+        let _ = writeln!(
+            out,
+            "- **Tokens:** {} in / {} out",
+            usage.input_tokens, usage.output_tokens
+        );
+    }
+    let text = render::assistant_text(&report.output);
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if !lines.is_empty() {
+        out.push('\n');
+        for line in &lines[lines.len().saturating_sub(SUMMARY_LINES)..] {
+            let _ = writeln!(out, "> {line}");
+        }
+    }
+    out
+}
+
+/// "1m 5s", as the session reports put it.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}m {}s", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlog::BacklogItem;
+    use std::cell::Cell;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn item(id: &str, status: &str) -> BacklogItem {
+        BacklogItem {
+            id: id.to_string(),
+            title: String::new(),
+            status: status.to_string(),
+        }
+    }
+
+    #[test]
+    fn sections_accumulate_under_a_rewritten_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.md");
+        let mut report = ProgressReport::new(&path, 3);
+        report.pricing = Pricing::builtin();
+        report.start(Some("s-1"));
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# Ralph loop progress\n\n- **Status:** running\n"));
+        assert!(content.contains("- **Iterations:** 0 done, 3 remaining (of 3)\n"));
+        assert!(content.contains("- **Estimated cost:** unknown\n"));
+        assert!(content.contains("- **Session:** s-1\n"));
+
+        report.iteration(&IterationReport {
+            iteration: 1,
+            duration: Duration::from_secs(65),
+            exit_code: 1,
+            gates: vec![("cargo test".to_string(), 0), ("cargo fmt".to_string(), 2)],
+            usage: Some(Usage {
+                input_tokens: 1_000_000,
+                output_tokens: 0,
+                cost_usd: None,
+            }),
+            model: Some("claude-sonnet-4-5-20250929".to_string()),
+            output: (1..=7).map(|n| format!("line {n}\n")).collect(),
+            ..IterationReport::default()
+        });
+        report.iteration(&IterationReport {
+            iteration: 2,
+            signal: Some(15),
+            complete: true,
+            usage: Some(Usage {
+                input_tokens: 10,
+                output_tokens: 10,
+                cost_usd: Some(0.5),
+            }),
+            ..IterationReport::default()
+        });
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("- **Iterations:** 2 done, 1 remaining (of 3)\n"));
+        assert!(
+            content.contains("- **Estimated cost:** $3.50\n"),
+            "{content}"
+        );
+        assert!(
+            content.contains(
+                "## Iteration 1\n\n\
+                 - **Duration:** 1m 5s\n\
+                 - **Exit code:** 1\n\
+                 - **Gate:** `cargo test` passed\n\
+                 - **Gate:** `cargo fmt` failed (exit code 2)\n\
+                 - **Tokens:** 1000000 in / 0 out\n\n\
+                 > line 3\n> line 4\n> line 5\n> line 6\n> line 7\n"
+            ),
+            "{content}"
+        );
+        assert!(
+            content.contains("- **Exit code:** provider terminated by SIGTERM\n"),
+            "{content}"
+        );
+        assert!(!content.contains("## Summary"));
+
+        report.finish("All tasks complete after 2 iterations.");
+        report.finish("Backlog: 1 closed\n");
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("- **Status:** finished\n"));
+        assert!(content.contains("- **Iterations:** 2 done, 0 remaining (of 3)\n"));
+        assert!(content.ends_with(
+            "## Summary\n\nAll tasks complete after 2 iterations.\nBacklog: 1 closed\n"
+        ));
+    }
+
+    #[test]
+    fn closed_tasks_are_those_closed_during_the_iteration() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut report =
+            ProgressReport::new(dir.path().join("progress.md"), 5).backlog(move || {
+                counter.set(counter.get() + 1);
+                let status = |closed_after| match counter.get() > closed_after {
+                    true => "closed",
+                    false => "open",
+                };
+                Some(Snapshot::new([
+                    item("bd-1", status(1)),
+                    item("bd-2", status(2)),
+                    item("bd-3", status(1)),
+                ]))
+            });
+        assert_eq!(
+            report.closed_tasks(),
+            Some(vec!["bd-1".into(), "bd-3".into()])
+        );
+        assert_eq!(report.closed_tasks(), Some(vec!["bd-2".into()]));
+        assert_eq!(report.closed_tasks(), Some(Vec::new()));
+        assert_eq!(calls.get(), 4);
+    }
+}
@@ -0,0 +1,242 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+/// Largest prompt the providers can practically receive. Prompts are passed
+/// as a single command-line argument, which Linux caps at 128 KiB.
+pub const MAX_PROMPT_BYTES: usize = 128 * 1024;
+
+/// Template variables ralph substitutes into the prompt. Anything else in
+/// `{{...}}` would reach the agent verbatim.
+pub const KNOWN_VARIABLES: &[&str] = &[];
+
+/// How serious a lint finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Worth a look, but the prompt still works
+    Warning,
+    /// The loop will misbehave; `ralph prompt lint` exits 1
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// One problem found by `lint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// How serious the problem is
+    pub severity: Severity,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: impl Into<String>) -> Finding {
+        Finding {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+/// Check `prompt` against what ralph expects of it. `base_dir` is where
+/// `@path` file references are resolved from.
+pub fn lint(prompt: &str, base_dir: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if prompt.trim().is_empty() {
+        findings.push(Finding::new(Severity::Error, "The prompt is empty"));
+        return findings;
+    }
+
+    if !prompt.contains(crate::runner::COMPLETE_MARKER) {
+        findings.push(Finding::new(
+            Severity::Warning,
+            format!(
+                "The completion marker {} is never mentioned; `ralph loop` appends instructions for it, and with --no-inject-marker-instructions runs its full iteration budget",
+                crate::runner::COMPLETE_MARKER
+            ),
+        ));
+    }
+
+    for var in template_variables(prompt) {
+        if !KNOWN_VARIABLES.contains(&var.as_str()) {
+            findings.push(Finding::new(
+                Severity::Error,
+                format!("Unknown template variable {{{{{var}}}}} would be sent verbatim"),
+            ));
+        }
+    }
+
+    for reference in file_references(prompt) {
+        if !base_dir.join(&reference).exists() {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!("Referenced file @{reference} does not exist"),
+            ));
+        }
+    }
+
+    let size = prompt.len();
+    if size > MAX_PROMPT_BYTES {
+        findings.push(Finding::new(
+            Severity::Error,
+            format!(
+                "The prompt is {size} bytes, over the {MAX_PROMPT_BYTES}-byte limit for a command-line argument"
+            ),
+        ));
+    } else if size > MAX_PROMPT_BYTES * 3 / 4 {
+        findings.push(Finding::new(
+            Severity::Warning,
+            format!("The prompt is {size} bytes, close to the {MAX_PROMPT_BYTES}-byte limit"),
+        ));
+    }
+
+    findings
+}
+
+/// The paragraph telling the agent when and how to print `marker`, for a
+/// loop whose prompt never mentions it.
+pub fn marker_instructions(marker: &str) -> String {
+    format!(
+        "\n\n## Finishing\n\n\
+         You are run in a loop, once per iteration, until you say the work is done. \
+         When every task is complete and nothing is left to work on, print {marker} \
+         on a line of its own as the last thing you output. Print it only then: \
+         while any work remains, end your turn without it and you will be run again.\n"
+    )
+}
+
+/// `prompt` with [`marker_instructions`] appended when it never mentions
+/// `marker`, so a customized prompt can't leave the loop without a way to
+/// stop.
+pub fn with_marker_instructions<'a>(prompt: &'a str, marker: &str) -> Cow<'a, str> {
+    if prompt.contains(marker) {
+        Cow::Borrowed(prompt)
+    } else {
+        Cow::Owned(format!(
+            "{}{}",
+            prompt.trim_end(),
+            marker_instructions(marker)
+        ))
+    }
+}
+
+/// Names inside `{{...}}`, in order of appearance, without duplicates.
+fn template_variables(prompt: &str) -> Vec<String> {
+    let mut vars = Vec::new();
+    let mut rest = prompt;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+        let name = rest[..end].trim().to_string();
+        if !name.is_empty() && !vars.contains(&name) {
+            vars.push(name);
+        }
+        rest = &rest[end + 2..];
+    }
+    vars
+}
+
+/// `@path` attachments as understood by the agent CLIs: an `@` at the start
+/// of a word followed by something that looks like a relative path.
+fn file_references(prompt: &str) -> Vec<String> {
+    let mut refs = Vec::new();
+    for word in prompt.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else {
+            continue;
+        };
+        let path = path.trim_end_matches(|c: char| ",.;:!?)]`'\"".contains(c));
+        if (path.contains('/') || path.contains('.')) && !refs.iter().any(|r| r == path) {
+            refs.push(path.to_string());
+        }
+    }
+    refs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn severities(prompt: &str, dir: &Path) -> Vec<Severity> {
+        lint(prompt, dir).into_iter().map(|f| f.severity).collect()
+    }
+
+    #[test]
+    fn missing_completion_marker_warns() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(severities("Do the work.", dir.path()), [Severity::Warning]);
+        let ok = format!(
+            "Do the work, then print {}.",
+            crate::runner::COMPLETE_MARKER
+        );
+        assert!(lint(&ok, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn marker_instructions_are_only_added_when_missing() {
+        let marker = "<promise>DONE</promise>";
+        let added = with_marker_instructions("Do the work.\n\n", marker);
+        assert!(
+            added.starts_with("Do the work.\n\n## Finishing\n"),
+            "{added}"
+        );
+        assert!(
+            added.contains(&format!("print {marker} on a line")),
+            "{added}"
+        );
+        assert!(!added.contains(crate::runner::COMPLETE_MARKER), "{added}");
+        assert!(matches!(
+            with_marker_instructions(&added, marker),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_template_variables_are_errors() {
+        assert_eq!(
+            template_variables("{{ repo }} and {{task}} and {{repo}} {{"),
+            ["repo", "task"]
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let prompt = format!("{} {{{{repo}}}}", crate::runner::COMPLETE_MARKER);
+        assert_eq!(severities(&prompt, dir.path()), [Severity::Error]);
+    }
+
+    #[test]
+    fn file_references_must_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.md"), "").unwrap();
+        assert_eq!(
+            file_references("See @notes.md, @docs/spec.md and ping @alice"),
+            ["notes.md", "docs/spec.md"]
+        );
+        let prompt = format!(
+            "{} See @notes.md and @docs/spec.md",
+            crate::runner::COMPLETE_MARKER
+        );
+        let findings = lint(&prompt, dir.path());
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].message.contains("@docs/spec.md"));
+    }
+
+    #[test]
+    fn oversized_prompt_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let prompt = format!(
+            "{}{}",
+            crate::runner::COMPLETE_MARKER,
+            "x".repeat(MAX_PROMPT_BYTES)
+        );
+        assert_eq!(severities(&prompt, dir.path()), [Severity::Error]);
+    }
+}
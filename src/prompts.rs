@@ -0,0 +1,193 @@
+/// For beads (`tracker = "beads"`), the default.
+pub const BEADS: &str = r#"Use bd (beads) for task tracking. Follow these steps:
+
+1. Run 'bd ready' to find the next available task (not blocked by dependencies)
+2. Run 'bd show <id>' to read the task details and acceptance criteria
+3. Run 'bd update <id> --status in_progress' to claim the task
+4. Implement the task according to the acceptance criteria. You need to read docs under `tasks` for better understanding of whole context.
+5. Run quality gates (bun run build, cargo build if applicable)
+6. Commit your changes with a descriptive message
+7. Run `bd update <id> ...` to update beads more info. for future references:
+  - Run `bd update <id> --design ...`: update design solution summary in markdown format, around 500 words
+  - Run `bd update <id> --notes ...`: update summaries of code change or document updates in markdown format, around 500 words
+8. Run 'bd close <id>' to mark the task as complete
+
+IMPORTANT:
+- ONLY DO ONE TASK AT A TIME
+- Do not start tasks that are blocked (have uncompleted dependencies)
+- Verify all acceptance criteria before closing the task
+- If 'bd ready' shows no tasks left, output <promise>COMPLETE</promise> and stop
+"#;
+
+/// For GitHub issues, worked through the `gh` CLI.
+pub const GITHUB: &str = r#"Use GitHub issues for task tracking, through the `gh` CLI. Follow these steps:
+
+1. Run 'gh issue list --state open --search "no:assignee"' to find the next available issue
+2. Run 'gh issue view <number>' to read the issue and its acceptance criteria
+3. Run 'gh issue edit <number> --add-assignee @me' to claim the issue
+4. Implement the issue according to the acceptance criteria. You need to read docs under `tasks` for better understanding of whole context.
+5. Run quality gates (bun run build, cargo build if applicable)
+6. Commit your changes with a descriptive message ending in "Refs #<number>"
+7. Run 'gh issue comment <number> --body ...' with a summary of the code change, around 300 words, for future references
+8. Run 'gh issue close <number>' to mark the issue as complete
+
+IMPORTANT:
+- ONLY DO ONE ISSUE AT A TIME
+- Do not start issues that mention an open issue they depend on
+- Verify all acceptance criteria before closing the issue
+- If 'gh issue list' shows no issues left, output <promise>COMPLETE</promise> and stop
+"#;
+
+/// For `tracker = "none"`: the docs under `tasks` are the backlog.
+pub const NONE: &str = r#"Work on this project one task at a time. Follow these steps:
+
+1. Read the docs under `tasks` and pick the next task that isn't done yet and isn't waiting on another one
+2. Implement the task according to its acceptance criteria
+3. Run quality gates (bun run build, cargo build if applicable)
+4. Commit your changes with a descriptive message
+5. Record what you did and what is left in `tasks/progress.md` for future iterations
+
+IMPORTANT:
+- ONLY DO ONE TASK AT A TIME
+- Verify all acceptance criteria before moving on to the next task
+- If every task in `tasks` is done, output <promise>COMPLETE</promise> and stop
+"#;
+
+/// Planning prompt for `loop --plan-first` with beads.
+pub const PLAN_BEADS: &str = r#"This is a planning pass: do not write or change any code, and do not commit. Follow these steps:
+
+1. Run 'bd list' and 'bd ready' to review the whole backlog
+2. Run 'bd show <id>' on the open tasks to read their details and acceptance criteria
+3. Run `bd update <id> --estimate ...` to set or correct each task's estimate
+4. Run `bd dep add <id> <depends-on>` for dependencies the backlog is missing, so tasks come up in a workable order
+5. Split tasks that are too big for one iteration, and note anything unclear with `bd update <id> --notes ...`
+6. Finish with a short plan: the order you would work the ready tasks in, and why
+
+IMPORTANT:
+- DO NOT IMPLEMENT ANY TASK in this pass
+- Do not claim or close tasks
+- Do not output the completion marker: the work starts after this pass
+"#;
+
+/// Planning prompt for `loop --plan-first` with GitHub issues.
+pub const PLAN_GITHUB: &str = r#"This is a planning pass: do not write or change any code, and do not commit. Follow these steps:
+
+1. Run 'gh issue list --state open' to review the whole backlog
+2. Run 'gh issue view <number>' on the open issues to read their details and acceptance criteria
+3. Run 'gh issue comment <number> --body ...' to note an estimate and anything an issue depends on, where that's missing
+4. Finish with a short plan: the order you would work the open issues in, and why
+
+IMPORTANT:
+- DO NOT IMPLEMENT ANY ISSUE in this pass
+- Do not assign or close issues
+- Do not output the completion marker: the work starts after this pass
+"#;
+
+/// Planning prompt for `loop --plan-first` with `tracker = "none"`.
+pub const PLAN_NONE: &str = r#"This is a planning pass: do not write or change any code, and do not commit. Follow these steps:
+
+1. Read the docs under `tasks` and `tasks/progress.md` to review what is done and what is left
+2. Write the remaining tasks to `tasks/plan.md` in the order they should be done, each with an estimate and what it depends on
+3. Finish with a short plan: the order you would work the tasks in, and why
+
+IMPORTANT:
+- DO NOT IMPLEMENT ANY TASK in this pass
+- Do not output the completion marker: the work starts after this pass
+"#;
+
+/// Every built-in planning prompt, by tracker name.
+pub const PLAN_TEMPLATES: &[(&str, &str)] = &[
+    ("beads", PLAN_BEADS),
+    ("github", PLAN_GITHUB),
+    ("none", PLAN_NONE),
+];
+
+/// Every built-in template, by tracker name.
+pub const TEMPLATES: &[(&str, &str)] = &[("beads", BEADS), ("github", GITHUB), ("none", NONE)];
+
+/// Tracker names with a template, for `ralph prompt reset --tracker`.
+pub const TRACKERS: &[&str] = &["beads", "github", "none"];
+
+/// The built-in template for `tracker`, if there is one.
+pub fn template(tracker: &str) -> Option<&'static str> {
+    TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == tracker)
+        .map(|(_, template)| *template)
+}
+
+/// The built-in planning prompt for `tracker`, if there is one.
+pub fn plan_template(tracker: &str) -> Option<&'static str> {
+    PLAN_TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == tracker)
+        .map(|(_, template)| *template)
+}
+
+/// Whether `prompt` is one of the templates, unedited.
+pub fn is_builtin(prompt: &str) -> bool {
+    TEMPLATES.iter().any(|(_, template)| *template == prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt;
+    use crate::runner::COMPLETE_MARKER;
+    use std::path::Path;
+
+    #[test]
+    fn every_tracker_has_a_template() {
+        let names: Vec<&str> = TEMPLATES.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, TRACKERS);
+        for name in crate::tracker::VALID_TRACKERS {
+            assert!(template(name).is_some(), "no template for {name}");
+        }
+        assert_eq!(template("jira"), None);
+    }
+
+    #[test]
+    fn templates_use_their_trackers_commands() {
+        for (name, commands) in [
+            (
+                "beads",
+                &["bd ready", "bd show", "bd update", "bd close"][..],
+            ),
+            (
+                "github",
+                &["gh issue list", "gh issue view", "gh issue close"],
+            ),
+            ("none", &["tasks/progress.md"]),
+        ] {
+            let template = template(name).unwrap();
+            for command in commands {
+                assert!(template.contains(command), "{name} lacks {command}");
+            }
+        }
+        assert!(!NONE.contains("bd "));
+        assert!(!GITHUB.contains("bd "));
+    }
+
+    #[test]
+    fn templates_pass_the_lint() {
+        for (name, template) in TEMPLATES {
+            assert!(template.contains(COMPLETE_MARKER), "{name}");
+            assert_eq!(prompt::lint(template, Path::new(".")), [], "{name}");
+        }
+        assert!(is_builtin(GITHUB));
+        assert!(!is_builtin("mine"));
+    }
+
+    #[test]
+    fn every_tracker_has_a_planning_prompt_that_writes_no_code() {
+        for name in crate::tracker::VALID_TRACKERS {
+            let template = plan_template(name).unwrap_or_else(|| panic!("no plan for {name}"));
+            assert!(
+                template.contains("do not write or change any code"),
+                "{name}"
+            );
+            assert!(!template.contains(COMPLETE_MARKER), "{name}");
+        }
+        assert!(PLAN_BEADS.contains("--estimate") && PLAN_BEADS.contains("bd dep add"));
+    }
+}
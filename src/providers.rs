@@ -0,0 +1,1603 @@
+use crate::audit::{self, Kind};
+use crate::env_policy;
+use crate::error::{RalphError, Result};
+use crate::exec::{CancelToken, CommandRunner, Output};
+use crate::log_target;
+use crate::priority;
+use crate::probe;
+use crate::reporter;
+use crate::schema;
+use crate::session;
+use crate::stream::LoginWatch;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// What ralph knows about one provider CLI: how to run it and how to tell
+/// it's waiting for a login. Adding a provider means adding an entry to
+/// [`PROVIDERS`].
+#[derive(Debug)]
+pub struct ProviderSpec {
+    /// Name used with `--provider` and in config files
+    pub name: &'static str,
+    /// The program run
+    pub binary: &'static str,
+    /// Other names `--provider` takes for it, unless config.toml's
+    /// `[aliases]` gives them another meaning
+    pub aliases: &'static [&'static str],
+    /// Flags before the prompt, the same for every command that runs it
+    pub args: &'static [&'static str],
+    /// Flags that turn its permission checks off, left out unless
+    /// `--unsafe` (`auto_approve`) is on
+    pub unsafe_flags: &'static [&'static str],
+    /// Flags used in place of the [`unsafe_flags`](Self::unsafe_flags) left
+    /// out: file edits are allowed, anything riskier is refused
+    pub safe_args: &'static [&'static str],
+    /// Flags left out when the prompt comes on stdin rather than as an
+    /// argument
+    pub arg_prompt_flags: &'static [&'static str],
+    /// Flags that make it print stream-json rather than text, left out for
+    /// [`OutputFormat::Text`]
+    pub stream_flags: &'static [&'static str],
+    /// Flag whose value is the prompt, put right before a prompt passed as
+    /// an argument (after any extra arguments)
+    pub prompt_flag: Option<&'static str>,
+    /// Flag naming a file to read a long prompt from; without one, long
+    /// prompts go to stdin (see [`PromptTransport`])
+    pub file_flag: Option<&'static str>,
+    /// Flag choosing the model, for `--model`
+    pub model_flag: Option<&'static str>,
+    /// Phrases (case-insensitive) it prints when it wants an interactive
+    /// login, besides [`COMMON_LOGIN_PHRASES`]
+    pub login_phrases: &'static [&'static str],
+    /// How to log in, for the error when it waits for a login
+    pub login_hint: &'static str,
+    /// Variables it authenticates with
+    pub auth_vars: &'static [&'static str],
+    /// How to install it, for when it isn't on PATH
+    pub install: &'static str,
+}
+
+impl ProviderSpec {
+    /// The flags before the prompt when it's passed by `transport` and
+    /// its output is to be in `format`, with the
+    /// [`safe_args`](Self::safe_args) in place of the
+    /// [`unsafe_flags`](Self::unsafe_flags) unless `auto_approve`.
+    pub fn flags(
+        &self,
+        transport: PromptTransport,
+        format: OutputFormat,
+        auto_approve: bool,
+    ) -> Vec<&'static str> {
+        let mut kept: Vec<&'static str> = self
+            .args
+            .iter()
+            .copied()
+            .filter(|arg| {
+                transport != PromptTransport::Stdin || !self.arg_prompt_flags.contains(arg)
+            })
+            .filter(|arg| format == OutputFormat::StreamJson || !self.stream_flags.contains(arg))
+            .filter(|arg| auto_approve || !self.unsafe_flags.contains(arg))
+            .collect();
+        if !auto_approve && self.args.iter().any(|arg| self.unsafe_flags.contains(arg)) {
+            kept.extend(self.safe_args);
+        }
+        kept
+    }
+}
+
+/// Every supported provider, the default first.
+pub const PROVIDERS: &[ProviderSpec] = &[
+    ProviderSpec {
+        name: "droid",
+        binary: "droid",
+        aliases: &["factory"],
+        args: &[
+            "exec",
+            "--output-format",
+            "stream-json",
+            "--skip-permissions-unsafe",
+        ],
+        unsafe_flags: &["--skip-permissions-unsafe"],
+        safe_args: &["--auto", "medium"],
+        arg_prompt_flags: &[],
+        stream_flags: &["--output-format", "stream-json"],
+        prompt_flag: None,
+        file_flag: Some("--file"),
+        model_flag: Some("--model"),
+        login_phrases: &["please log in", "login required"],
+        login_hint: "run `droid` and log in with /login",
+        auth_vars: &["FACTORY_API_KEY"],
+        install: "curl -fsSL https://app.factory.ai/cli | sh",
+    },
+    ProviderSpec {
+        name: "codex",
+        binary: "codex",
+        aliases: &[],
+        args: &["exec", "--yolo", "--json"],
+        unsafe_flags: &["--yolo"],
+        safe_args: &["--sandbox", "workspace-write"],
+        arg_prompt_flags: &[],
+        stream_flags: &["--json"],
+        prompt_flag: None,
+        file_flag: None,
+        model_flag: Some("-m"),
+        login_phrases: &["not logged in", "sign in with chatgpt"],
+        login_hint: "run `codex login`",
+        auth_vars: &["OPENAI_API_KEY", "OPENAI_BASE_URL", "CODEX_HOME"],
+        install: "npm install -g @openai/codex",
+    },
+    ProviderSpec {
+        name: "claude",
+        binary: "claude",
+        aliases: &["cc", "claude-code"],
+        args: &[
+            "-p",
+            "--verbose",
+            "--output-format",
+            "stream-json",
+            "--dangerously-skip-permissions",
+        ],
+        unsafe_flags: &["--dangerously-skip-permissions"],
+        safe_args: &["--permission-mode", "acceptEdits"],
+        arg_prompt_flags: &[],
+        // -p only needs --verbose to stream.
+        stream_flags: &["--verbose", "--output-format", "stream-json"],
+        prompt_flag: None,
+        file_flag: None,
+        model_flag: Some("--model"),
+        login_phrases: &["please run /login", "select login method"],
+        login_hint: "run `claude` and log in with /login",
+        auth_vars: &[
+            "ANTHROPIC_API_KEY",
+            "ANTHROPIC_AUTH_TOKEN",
+            "ANTHROPIC_BASE_URL",
+            "CLAUDE_CONFIG_DIR",
+        ],
+        install: "npm install -g @anthropic-ai/claude-code",
+    },
+    ProviderSpec {
+        name: "gemini",
+        binary: "gemini",
+        aliases: &["gemini-cli"],
+        args: &["-p", "--output-format", "stream-json", "--yolo"],
+        // Without -p, gemini runs non-interactively on the prompt piped to it.
+        unsafe_flags: &["--yolo"],
+        safe_args: &["--approval-mode", "auto_edit"],
+        arg_prompt_flags: &["-p"],
+        stream_flags: &["--output-format", "stream-json"],
+        prompt_flag: None,
+        file_flag: None,
+        model_flag: Some("-m"),
+        login_phrases: &["waiting for auth", "login with google"],
+        login_hint: "run `gemini` and choose how to sign in",
+        auth_vars: &[
+            "GEMINI_API_KEY",
+            "GOOGLE_API_KEY",
+            "GOOGLE_APPLICATION_CREDENTIALS",
+            "GOOGLE_CLOUD_PROJECT",
+        ],
+        install: "npm install -g @google/gemini-cli",
+    },
+    ProviderSpec {
+        name: "copilot",
+        binary: "copilot",
+        aliases: &["gh-copilot"],
+        // Plain text output: copilot has no stream-json mode.
+        args: &["--allow-all-tools"],
+        unsafe_flags: &["--allow-all-tools"],
+        safe_args: &["--allow-tool", "write"],
+        arg_prompt_flags: &[],
+        stream_flags: &[],
+        prompt_flag: Some("-p"),
+        file_flag: None,
+        model_flag: Some("--model"),
+        login_phrases: &["please run /login", "no authentication information found"],
+        login_hint: "run `copilot` and log in with /login, or set GH_TOKEN",
+        auth_vars: &["COPILOT_GITHUB_TOKEN", "GH_TOKEN", "GITHUB_TOKEN"],
+        install: "npm install -g @github/copilot",
+    },
+    ProviderSpec {
+        name: "qwen",
+        binary: "qwen",
+        aliases: &["qwen-code"],
+        // A fork of gemini's CLI, run the same way.
+        args: &["--output-format", "stream-json", "--yolo"],
+        unsafe_flags: &["--yolo"],
+        safe_args: &["--approval-mode", "auto_edit"],
+        arg_prompt_flags: &[],
+        stream_flags: &["--output-format", "stream-json"],
+        prompt_flag: Some("-p"),
+        file_flag: None,
+        model_flag: Some("-m"),
+        login_phrases: &["waiting for auth", "qwen oauth"],
+        login_hint: "run `qwen` and choose how to sign in",
+        auth_vars: &["OPENAI_API_KEY", "OPENAI_BASE_URL", "OPENAI_MODEL"],
+        install: "npm install -g @qwen-code/qwen-code",
+    },
+];
+
+/// Supported AI providers: the names in [`PROVIDERS`].
+pub const VALID_PROVIDERS: &[&str] = &provider_names();
+
+const fn provider_names() -> [&'static str; PROVIDERS.len()] {
+    let mut names = [""; PROVIDERS.len()];
+    let mut i = 0;
+    while i < PROVIDERS.len() {
+        names[i] = PROVIDERS[i].name;
+        i += 1;
+    }
+    names
+}
+
+/// The [`ProviderSpec`] named `provider`, if it's supported.
+pub fn spec(provider: &str) -> Option<&'static ProviderSpec> {
+    PROVIDERS.iter().find(|spec| spec.name == provider)
+}
+
+/// Placeholder in a [`Template`] replaced with the prompt.
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+/// Placeholder in a [`Template`] replaced with the iteration number, from 1.
+pub const ITERATION_PLACEHOLDER: &str = "{iteration}";
+
+/// A provider defined in config.toml rather than built in: a command
+/// template (`[providers.<name>]` with `command`), typically a wrapper
+/// script such as `./scripts/agent.sh {prompt}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Template {
+    /// The command, with [`PROMPT_PLACEHOLDER`] and
+    /// [`ITERATION_PLACEHOLDER`] where the prompt and iteration go
+    pub command: String,
+    /// Run the command with `sh -c` instead of splitting it into arguments
+    /// and running the first
+    pub shell: bool,
+}
+
+impl Template {
+    /// Whether the command takes the prompt; without [`PROMPT_PLACEHOLDER`]
+    /// it's on stdin instead.
+    pub fn takes_prompt(&self) -> bool {
+        self.command.contains(PROMPT_PLACEHOLDER)
+    }
+
+    /// The program and arguments that run the command on `prompt` in
+    /// `iteration`, with `extra` arguments at the end.
+    ///
+    /// The command is split into words as a shell would (quotes and
+    /// backslashes, but no variables or globs) and the placeholders are
+    /// replaced within each word, so a prompt is always one argument
+    /// whatever it contains. With [`shell`](Self::shell) the placeholders
+    /// are replaced with the values quoted for `sh` instead, and the
+    /// command runs as `sh -c <command>`.
+    pub fn argv(&self, prompt: &str, iteration: u32, extra: &[String]) -> Result<Vec<String>> {
+        let quote = |word: &str| {
+            shlex::try_quote(word)
+                .map(|quoted| quoted.into_owned())
+                .map_err(|e| RalphError::InvalidInput(format!("Can't quote {word:?}: {e}")))
+        };
+        let iteration = iteration.to_string();
+        if self.shell {
+            let mut script = substitute(&self.command, &quote(prompt)?, &iteration);
+            for arg in extra {
+                script.push(' ');
+                script += &quote(arg)?;
+            }
+            return Ok(vec!["sh".to_string(), "-c".to_string(), script]);
+        }
+        let words = shlex::split(&self.command).ok_or_else(|| {
+            RalphError::InvalidInput(format!(
+                "command {:?} has an unfinished quote or escape",
+                self.command
+            ))
+        })?;
+        if words.is_empty() {
+            return Err(RalphError::InvalidInput("command is empty".to_string()));
+        }
+        Ok(words
+            .iter()
+            .map(|word| substitute(word, prompt, &iteration))
+            .chain(extra.iter().cloned())
+            .collect())
+    }
+}
+
+/// `text` with the placeholders replaced in one pass, so a prompt that
+/// happens to contain `{iteration}` is left as written.
+fn substitute(text: &str, prompt: &str, iteration: &str) -> String {
+    text.split(PROMPT_PLACEHOLDER)
+        .map(|part| part.replace(ITERATION_PLACEHOLDER, iteration))
+        .collect::<Vec<_>>()
+        .join(prompt)
+}
+
+static TEMPLATES: OnceLock<BTreeMap<String, Template>> = OnceLock::new();
+
+/// Make the providers in `templates` (from config.toml, by name) usable
+/// alongside the built-in ones. Only the first call has an effect.
+pub fn set_templates(templates: BTreeMap<String, Template>) {
+    let _ = TEMPLATES.set(templates);
+}
+
+/// The [`Template`] defining `provider`, if it's one set with
+/// [`set_templates`].
+pub fn template(provider: &str) -> Option<&'static Template> {
+    TEMPLATES.get()?.get(provider)
+}
+
+/// Every provider that can run: the built-in ones, then those set with
+/// [`set_templates`].
+pub fn names() -> Vec<&'static str> {
+    let templates = TEMPLATES.get().into_iter().flat_map(|t| t.keys());
+    VALID_PROVIDERS
+        .iter()
+        .copied()
+        .chain(templates.map(String::as_str))
+        .collect()
+}
+
+/// The template that runs `provider`, unless `RALPH_PROVIDER_CMD` replaces
+/// it.
+fn active_template(provider: &str) -> Option<&'static Template> {
+    template(provider).filter(|_| command_override().is_none())
+}
+
+/// Environment variable that replaces the provider CLI with another
+/// command, for end-to-end tests. The value is split on whitespace (no shell
+/// quoting) and the prompt is appended as the last argument.
+pub const PROVIDER_CMD_ENV: &str = "RALPH_PROVIDER_CMD";
+
+/// Phrases (case-insensitive) that mean any provider stopped to wait for
+/// a login; see [`login_phrases`].
+const COMMON_LOGIN_PHRASES: &[&str] = &[
+    "press enter to open",
+    "press any key to open",
+    "open this url",
+    "waiting for authentication",
+];
+
+/// The phrases a provider prints when it wants an interactive login before
+/// doing anything, besides [`COMMON_LOGIN_PHRASES`]. CLIs change their
+/// wording; the `login_phrases` config key adds more without a release.
+pub fn login_phrases(provider: &str) -> &'static [&'static str] {
+    spec(provider).map_or(&[], |spec| spec.login_phrases)
+}
+
+/// How to log in to `provider`, for the error when it waits for a login.
+pub fn login_hint(provider: &str) -> String {
+    match spec(provider) {
+        Some(spec) => spec.login_hint.to_string(),
+        None => format!("run `{provider}` interactively to log in"),
+    }
+}
+
+/// The variables `provider` authenticates with, kept under every
+/// [`env_policy::EnvPolicy`](crate::env_policy::EnvPolicy).
+pub fn auth_vars(provider: &str) -> &'static [&'static str] {
+    spec(provider).map_or(&[], |spec| spec.auth_vars)
+}
+
+static EXTRA_LOGIN_PHRASES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Add `phrases` (the `login_phrases` config key) to every provider's
+/// built-in ones. Only the first call has an effect.
+pub fn set_login_phrases(phrases: Vec<String>) {
+    let _ = EXTRA_LOGIN_PHRASES.set(phrases);
+}
+
+/// The [`LoginWatch`] for `provider`: its phrases, the common ones and any
+/// set with [`set_login_phrases`].
+pub fn login_watch(provider: &str) -> LoginWatch {
+    let extra = EXTRA_LOGIN_PHRASES.get().map(Vec::as_slice).unwrap_or(&[]);
+    LoginWatch::new(
+        COMMON_LOGIN_PHRASES
+            .iter()
+            .chain(login_phrases(provider))
+            .map(|p| p.to_string())
+            .chain(extra.iter().cloned()),
+    )
+}
+
+/// Validate that the provider is one of the supported providers, or one
+/// set with [`set_templates`].
+pub fn validate_provider(provider: &str) -> Result<()> {
+    validate_provider_in(
+        provider,
+        TEMPLATES.get().unwrap_or(&BTreeMap::new()),
+        &BTreeMap::new(),
+    )
+}
+
+/// [`validate_provider`] with the template providers given, for checking
+/// a configuration before they're set. For a name that isn't one, the
+/// error suggests the closest provider or alias (from `aliases` or the
+/// built-in ones).
+pub fn validate_provider_in(
+    provider: &str,
+    templates: &BTreeMap<String, Template>,
+    aliases: &BTreeMap<String, String>,
+) -> Result<()> {
+    if VALID_PROVIDERS.contains(&provider) || templates.contains_key(provider) {
+        return Ok(());
+    }
+    let available: Vec<&str> = VALID_PROVIDERS
+        .iter()
+        .copied()
+        .chain(templates.keys().map(String::as_str))
+        .collect();
+    let builtin_aliases = PROVIDERS
+        .iter()
+        .flat_map(|spec| spec.aliases.iter().copied());
+    let candidates = (available.iter().copied())
+        .chain(aliases.keys().map(String::as_str))
+        .chain(builtin_aliases);
+    let hint = closest(provider, candidates)
+        .map(|name| format!("\nDid you mean '{name}'?"))
+        .unwrap_or_default();
+    Err(RalphError::InvalidInput(format!(
+        "Invalid provider '{}'\nAvailable providers: {}{}",
+        provider,
+        available.join(", "),
+        hint
+    )))
+}
+
+/// The provider `name` stands for: `name` itself when a provider goes by
+/// it, else the one `aliases` (the `[aliases]` config section) maps it to,
+/// else the one it's a built-in alias of (see [`ProviderSpec::aliases`]).
+/// Any other name comes back unchanged, for [`validate_provider_in`] to
+/// report.
+pub fn resolve_alias(
+    name: &str,
+    templates: &BTreeMap<String, Template>,
+    aliases: &BTreeMap<String, String>,
+) -> String {
+    if VALID_PROVIDERS.contains(&name) || templates.contains_key(name) {
+        return name.to_string();
+    }
+    if let Some(provider) = aliases.get(name) {
+        return provider.clone();
+    }
+    PROVIDERS
+        .iter()
+        .find(|spec| spec.aliases.contains(&name))
+        .map_or(name, |spec| spec.name)
+        .to_string()
+}
+
+/// The candidate `name` most likely misspells: one it's the start of, or
+/// else the one fewest edits away, at most two (one for names of up to
+/// three letters). The first wins a tie; `name` itself is never suggested.
+fn closest<'a>(given: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = given.to_lowercase();
+    let max = if name.chars().count() <= 3 { 1 } else { 2 };
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != given)
+        .filter_map(|candidate| {
+            let distance = match name.len() >= 2 && candidate.starts_with(&name) {
+                true => 0,
+                false => edit_distance(&name, candidate),
+            };
+            (distance <= max).then_some((distance, candidate))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// How many characters must be inserted, deleted or replaced, or pairs of
+/// neighbours swapped, to turn `a` into `b` (the optimal string alignment
+/// distance; a swap is the commonest typo).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j]: the distance between the first i of `a` and the first j of `b`.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The `RALPH_PROVIDER_CMD` override split into program and arguments, if
+/// set to something non-blank.
+pub fn command_override() -> Option<Vec<String>> {
+    let value = std::env::var(PROVIDER_CMD_ENV).ok()?;
+    let words: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+    if words.is_empty() { None } else { Some(words) }
+}
+
+/// Where the program that runs `provider` is on PATH (or the program
+/// `RALPH_PROVIDER_CMD` names instead), checked before the first iteration
+/// so a missing CLI is reported up front rather than as a failed spawn.
+pub fn resolve_provider_binary(provider: &str) -> Result<PathBuf> {
+    resolve_binary_in(
+        provider,
+        command_override(),
+        &std::env::var_os("PATH").unwrap_or_default(),
+    )
+}
+
+/// [`resolve_provider_binary`] with the override and PATH given.
+fn resolve_binary_in(
+    provider: &str,
+    command_override: Option<Vec<String>>,
+    search_path: &OsStr,
+) -> Result<PathBuf> {
+    validate_provider(provider)?;
+    let (program, hint) = match (command_override, template(provider)) {
+        (Some(mut words), _) => (words.swap_remove(0), format!("check {PROVIDER_CMD_ENV}")),
+        (None, Some(template)) => {
+            let mut argv = template.argv("", 1, &[])?;
+            // `./scripts/agent.sh` is relative to where the provider starts.
+            let program = match DIR.get() {
+                Some(dir) if argv[0].contains('/') => dir.join(&argv[0]).display().to_string(),
+                _ => argv.swap_remove(0),
+            };
+            (
+                program,
+                format!("check `command` under [providers.{provider}] in config.toml"),
+            )
+        }
+        (None, None) => {
+            let spec = spec(provider).expect("validated above");
+            (
+                spec.binary.to_string(),
+                format!("install it with `{}`", spec.install),
+            )
+        }
+    };
+    probe::find_binary_in(&program, search_path).ok_or_else(|| RalphError::ProviderNotFound {
+        provider: provider.to_string(),
+        program,
+        hint,
+    })
+}
+
+/// Print which provider is about to run, noting a `RALPH_PROVIDER_CMD`
+/// override so it's never silently in effect, a [`Template`] provider's
+/// command, and a lowered priority.
+pub fn announce(provider: &str) {
+    let with_model = MODEL
+        .get()
+        .map(|model| format!(" with model {model}"))
+        .unwrap_or_default();
+    match command_override() {
+        Some(words) => reporter::info(&format!(
+            "Using AI provider: {}{} (via {}={})",
+            provider,
+            with_model,
+            PROVIDER_CMD_ENV,
+            words.join(" ")
+        )),
+        None => match template(provider) {
+            Some(template) => reporter::info(&format!(
+                "Using AI provider: {}{} ({})",
+                provider, with_model, template.command
+            )),
+            None => reporter::info(&format!("Using AI provider: {}{}", provider, with_model)),
+        },
+    }
+    if auto_approve() {
+        reporter::info("Permission checks: off (--unsafe)");
+    }
+    if let Some(priority) = priority::get().describe() {
+        reporter::info(&format!("Provider priority: {}", priority));
+    }
+}
+
+/// Longest prompt passed as a command-line argument. Linux caps a single
+/// argument at 128 KiB and macOS the whole argument list and environment at
+/// 256 KiB; longer prompts go through stdin or a file instead.
+pub const ARG_PROMPT_LIMIT: usize = 96 * 1024;
+
+/// How a prompt reaches the provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptTransport {
+    /// The last command-line argument
+    Arg,
+    /// The provider's stdin, read from a temporary file (claude, codex,
+    /// gemini and `RALPH_PROVIDER_CMD` read the prompt there when none is
+    /// given as an argument)
+    Stdin,
+    /// A temporary file named with the provider's
+    /// [`file_flag`](ProviderSpec::file_flag) (droid's `--file`)
+    File,
+}
+
+impl PromptTransport {
+    /// The transport for a prompt of `len` bytes: an argument up to
+    /// [`ARG_PROMPT_LIMIT`], otherwise whatever `provider` can read from,
+    /// unless [`set_prompt_via`] chose stdin or a file for it.
+    /// A [`Template`] provider gets it in its command, or on stdin when the
+    /// command doesn't say where it goes.
+    pub fn for_prompt(provider: &str, len: usize) -> PromptTransport {
+        if let Some(template) = active_template(provider) {
+            return match template.takes_prompt() {
+                true => PromptTransport::Arg,
+                false => PromptTransport::Stdin,
+            };
+        }
+        let file = spec(provider).is_some_and(|spec| spec.file_flag.is_some())
+            && command_override().is_none();
+        match prompt_via(provider) {
+            Some(PromptTransport::Stdin) => PromptTransport::Stdin,
+            Some(PromptTransport::File) if file => PromptTransport::File,
+            Some(PromptTransport::File) => PromptTransport::Stdin,
+            _ if len <= ARG_PROMPT_LIMIT => PromptTransport::Arg,
+            _ if file => PromptTransport::File,
+            _ => PromptTransport::Stdin,
+        }
+    }
+
+    /// The name `prompt_via` takes for it.
+    pub fn name(self) -> &'static str {
+        match self {
+            PromptTransport::Arg => "arg",
+            PromptTransport::Stdin => "stdin",
+            PromptTransport::File => "file",
+        }
+    }
+}
+
+/// What a built-in provider prints on stdout (`--output-format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON event per line, which ralph renders, counts tokens in and
+    /// checks with `--validate-stream`
+    #[default]
+    StreamJson,
+    /// The provider's own plain text, without its
+    /// [`stream_flags`](ProviderSpec::stream_flags)
+    Text,
+}
+
+/// The names `--output-format` takes, the default first.
+pub const OUTPUT_FORMATS: &[&str] = &["stream-json", "text"];
+
+impl std::str::FromStr for OutputFormat {
+    type Err = RalphError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stream-json" => Ok(OutputFormat::StreamJson),
+            "text" => Ok(OutputFormat::Text),
+            _ => Err(RalphError::InvalidInput(format!(
+                "output format must be stream-json or text, got '{s}'"
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for PromptTransport {
+    type Err = RalphError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "arg" => Ok(PromptTransport::Arg),
+            "stdin" => Ok(PromptTransport::Stdin),
+            "file" => Ok(PromptTransport::File),
+            _ => Err(RalphError::InvalidInput(format!(
+                "prompt_via must be arg, stdin or file, got '{s}'"
+            ))),
+        }
+    }
+}
+
+static PROMPT_VIA: OnceLock<BTreeMap<String, PromptTransport>> = OnceLock::new();
+
+/// Pass each provider in `via` (`prompt_via` under `[providers.<name>]`)
+/// its prompt that way whatever its length: on stdin, or in a file for a
+/// provider with a [`file_flag`](ProviderSpec::file_flag). Only the first
+/// call has an effect.
+pub fn set_prompt_via(via: BTreeMap<String, PromptTransport>) {
+    let _ = PROMPT_VIA.set(via);
+}
+
+/// How [`set_prompt_via`] said `provider` gets its prompt, if it did.
+fn prompt_via(provider: &str) -> Option<PromptTransport> {
+    PROMPT_VIA.get()?.get(provider).copied()
+}
+
+impl std::fmt::Display for PromptTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PromptTransport::Arg => "as an argument",
+            PromptTransport::Stdin => "on stdin",
+            PromptTransport::File => "in a file",
+        })
+    }
+}
+
+/// A provider command ready to run. Keeps the temporary prompt file, if
+/// there is one, until dropped.
+#[derive(Debug)]
+pub struct ProviderCommand {
+    /// The command, with stdout and stderr piped
+    pub command: Command,
+    /// How it gets the prompt
+    pub transport: PromptTransport,
+    prompt_file: Option<tempfile::TempPath>,
+}
+
+impl ProviderCommand {
+    /// The temporary file holding the prompt, unless it's an argument.
+    pub fn prompt_file(&self) -> Option<&Path> {
+        self.prompt_file.as_deref()
+    }
+}
+
+/// Build the command that runs `provider` non-interactively on `prompt`,
+/// with stdout and stderr piped so they can be streamed. With `RALPH_PROVIDER_CMD` set,
+/// that command runs instead (the provider name is still validated).
+///
+/// The prompt is always the last argument, or where a [`Template`]
+/// provider's command puts it; [`prepare`] moves long ones out of the
+/// argument list.
+pub fn command(provider: &str, prompt: &str) -> Result<Command> {
+    if let Some(template) = active_template(provider) {
+        return template_command(provider, template, prompt);
+    }
+    let mut cmd = base_command(provider, PromptTransport::Arg)?;
+    if let Some(flag) = spec(provider).and_then(|spec| spec.prompt_flag)
+        && command_override().is_none()
+    {
+        cmd.arg(flag);
+    }
+    cmd.arg(prompt);
+    Ok(cmd)
+}
+
+/// Like [`command`], but passing a prompt longer than [`ARG_PROMPT_LIMIT`]
+/// through stdin or a temporary file (see [`PromptTransport::for_prompt`]).
+pub fn prepare(provider: &str, prompt: &str) -> Result<ProviderCommand> {
+    let transport = PromptTransport::for_prompt(provider, prompt.len());
+    if transport == PromptTransport::Arg {
+        return Ok(ProviderCommand {
+            command: command(provider, prompt)?,
+            transport,
+            prompt_file: None,
+        });
+    }
+
+    let mut cmd = match active_template(provider) {
+        Some(template) => template_command(provider, template, "")?,
+        None => base_command(provider, transport)?,
+    };
+    let file = write_prompt_file(prompt)?;
+    match spec(provider).and_then(|spec| spec.file_flag) {
+        Some(flag) if transport == PromptTransport::File => {
+            cmd.arg(flag).arg(&file);
+        }
+        _ => {
+            cmd.stdin(std::fs::File::open(&file)?);
+        }
+    }
+    Ok(ProviderCommand {
+        command: cmd,
+        transport,
+        prompt_file: Some(file),
+    })
+}
+
+fn write_prompt_file(prompt: &str) -> std::io::Result<tempfile::TempPath> {
+    let mut file = tempfile::Builder::new()
+        .prefix("ralph-prompt-")
+        .suffix(".md")
+        .tempfile()?;
+    file.write_all(prompt.as_bytes())?;
+    file.flush()?;
+    Ok(file.into_temp_path())
+}
+
+static AUTO_APPROVE: OnceLock<bool> = OnceLock::new();
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+static MODEL: OnceLock<String> = OnceLock::new();
+static CONFIG_ARGS: OnceLock<BTreeMap<String, Vec<String>>> = OnceLock::new();
+static EXTRA_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Turn the providers' permission checks off (`--unsafe`, the
+/// `auto_approve` setting): they get their
+/// [`unsafe_flags`](ProviderSpec::unsafe_flags) rather than their
+/// [`safe_args`](ProviderSpec::safe_args). Only the first call has an
+/// effect.
+pub fn set_auto_approve(on: bool) {
+    let _ = AUTO_APPROVE.set(on);
+}
+
+fn auto_approve() -> bool {
+    AUTO_APPROVE.get().copied().unwrap_or(false)
+}
+
+/// Have the built-in providers print `format` (`--output-format`). Only
+/// the first call has an effect.
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT_FORMAT.set(format);
+}
+
+fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Run every provider started from now on with `model` (`--model`),
+/// through its [`model_flag`](ProviderSpec::model_flag). Only the first call
+/// has an effect.
+pub fn set_model(model: String) {
+    let _ = MODEL.set(model);
+}
+
+/// The arguments that make `provider` use `model`, or an error when it has
+/// no way to choose one.
+pub fn model_args(provider: &str, model: &str) -> Result<Vec<String>> {
+    validate_provider(provider)?;
+    match spec(provider).and_then(|spec| spec.model_flag) {
+        Some(flag) => Ok(vec![flag.to_string(), model.to_string()]),
+        None => Err(RalphError::InvalidInput(format!(
+            "Provider '{provider}' has no flag for choosing a model; drop --model"
+        ))),
+    }
+}
+
+/// Pass each provider the arguments under its name in `args` (the
+/// `[provider_args]` config section), after its own flags. Only the first
+/// call has an effect.
+pub fn set_config_args(args: BTreeMap<String, Vec<String>>) {
+    let _ = CONFIG_ARGS.set(args);
+}
+
+/// Pass `args` (`--provider-arg`) to every provider started from now on,
+/// after those from [`set_config_args`] and before the prompt. Only the
+/// first call has an effect.
+pub fn set_extra_args(args: Vec<String>) {
+    let _ = EXTRA_ARGS.set(args);
+}
+
+/// What `provider` gets after its own flags: the flag choosing `model`,
+/// its `configured` arguments, then those from the command line.
+fn merge_extra_args(
+    model: Option<&str>,
+    configured: &BTreeMap<String, Vec<String>>,
+    command_line: &[String],
+    provider: &str,
+) -> Result<Vec<String>> {
+    let mut args = match model {
+        Some(model) => model_args(provider, model)?,
+        None => Vec::new(),
+    };
+    args.extend(configured.get(provider).into_iter().flatten().cloned());
+    args.extend(command_line.iter().cloned());
+    Ok(args)
+}
+
+/// The arguments set with [`set_model`], [`set_config_args`] and
+/// [`set_extra_args`] for `provider`; see [`merge_extra_args`].
+fn extra_args(provider: &str) -> Result<Vec<String>> {
+    merge_extra_args(
+        MODEL.get().map(String::as_str),
+        CONFIG_ARGS.get().unwrap_or(&BTreeMap::new()),
+        EXTRA_ARGS.get().map_or(&[], Vec::as_slice),
+        provider,
+    )
+}
+
+/// The provider's program and flags, without the prompt.
+fn base_command(provider: &str, transport: PromptTransport) -> Result<Command> {
+    base_command_with(provider, transport, &extra_args(provider)?)
+}
+
+/// The command `template` runs for `provider` on `prompt`, in the current
+/// iteration (1 outside a loop).
+fn template_command(provider: &str, template: &Template, prompt: &str) -> Result<Command> {
+    let iteration = log_target::context().iteration.unwrap_or(1);
+    let argv = template.argv(prompt, iteration, &extra_args(provider)?)?;
+    let mut cmd = Command::new(&argv[0]);
+    cmd.args(&argv[1..]);
+    configure(&mut cmd, provider)?;
+    Ok(cmd)
+}
+
+/// [`base_command`] with `extra` after the provider's own flags, rather
+/// than those set with [`set_model`], [`set_config_args`] and
+/// [`set_extra_args`].
+fn base_command_with(
+    provider: &str,
+    transport: PromptTransport,
+    extra: &[String],
+) -> Result<Command> {
+    let mut cmd = match command_override() {
+        Some(words) => {
+            validate_provider(provider)?;
+            let mut cmd = Command::new(&words[0]);
+            cmd.args(&words[1..]);
+            cmd
+        }
+        None => {
+            let Some(spec) = spec(provider) else {
+                return Err(RalphError::InvalidInput(format!(
+                    "Unknown provider: {}",
+                    provider
+                )));
+            };
+            let mut cmd = Command::new(spec.binary);
+            cmd.args(spec.flags(transport, output_format(), auto_approve()));
+            cmd
+        }
+    };
+    cmd.args(extra);
+    configure(&mut cmd, provider)?;
+    Ok(cmd)
+}
+
+/// Pipe `cmd`'s stdout and stderr and give it the directory, environment
+/// and priority every `provider` run gets.
+fn configure(cmd: &mut Command, provider: &str) -> Result<()> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(dir) = DIR.get() {
+        cmd.current_dir(dir);
+    }
+    env_policy::get().apply(cmd, provider)?;
+    priority::get().apply(cmd);
+    set_label_env(cmd);
+    Ok(())
+}
+
+/// Start providers in `dir` rather than the current directory (`--cwd`).
+/// The first call wins.
+pub fn set_dir(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+/// Hand the run's `--label` to `cmd` as `RALPH_LABEL`, whatever the
+/// environment policy, so the provider's hooks can see it.
+pub fn set_label_env(cmd: &mut Command) {
+    if let Some(label) = session::label() {
+        cmd.env(session::LABEL_ENV, label);
+    }
+}
+
+/// Execute a provider command with the given system prompt.
+/// Returns how the provider process exited and its captured stdout.
+/// Stdout is passed through and, when `log` is given, also written there.
+/// Cancelling `cancel` terminates the provider.
+pub fn execute(
+    exec: &dyn CommandRunner,
+    provider: &str,
+    prompt: &str,
+    log: Option<&Path>,
+    cancel: &CancelToken,
+) -> Result<Output> {
+    let spawn_error = |source| RalphError::ProviderSpawn {
+        provider: provider.to_string(),
+        source,
+    };
+    let mut prepared = prepare(provider, prompt).map_err(|e| match e {
+        RalphError::Io(source) => spawn_error(source),
+        e => e,
+    })?;
+    let env = env_policy::get();
+    if env.policy != env_policy::EnvPolicy::Inherit {
+        reporter::debug(&format!(
+            "Environment for {} ({}): {}",
+            provider,
+            env.policy,
+            env.names(provider).join(", ")
+        ));
+    }
+    if let Some(file) = prepared.prompt_file() {
+        let why = match prompt_via(provider) {
+            _ if active_template(provider).is_some() => {
+                format!("as its command has no {PROMPT_PLACEHOLDER}")
+            }
+            Some(via) if via != PromptTransport::Arg => {
+                format!("as its prompt_via is {}", via.name())
+            }
+            _ => format!("as it's over the {ARG_PROMPT_LIMIT}-byte argument limit"),
+        };
+        reporter::debug(&format!(
+            "Passing the {}-byte prompt to {} {} ({}), {}",
+            prompt.len(),
+            provider,
+            prepared.transport,
+            file.display(),
+            why
+        ));
+    }
+    let output = audit::run(Kind::Provider, &mut prepared.command, |cmd| {
+        exec.stream(cmd, log, cancel, Some(&login_watch(provider)))
+    })
+    .map_err(spawn_error)?;
+    schema::observe(provider, &output.stdout);
+    if output.long_lines > 0 {
+        reporter::debug(&format!(
+            "{} printed {} line(s) too long to keep whole; only the first bytes of each were checked",
+            provider, output.long_lines
+        ));
+    }
+    match output.login_prompt {
+        Some(prompt) => Err(RalphError::ProviderLogin {
+            provider: provider.to_string(),
+            prompt,
+            hint: login_hint(provider),
+        }),
+        None => Ok(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_provider_valid() {
+        assert!(validate_provider("droid").is_ok());
+        assert!(validate_provider("codex").is_ok());
+        assert!(validate_provider("claude").is_ok());
+        assert!(validate_provider("gemini").is_ok());
+    }
+
+    #[test]
+    fn test_validate_provider_invalid() {
+        let result = validate_provider("invalid_provider");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("Invalid provider 'invalid_provider'"));
+        assert!(
+            err_msg.contains("Available providers: droid, codex, claude, gemini, copilot, qwen")
+        );
+    }
+
+    #[test]
+    fn test_validate_provider_empty() {
+        let result = validate_provider("");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_valid_providers_list() {
+        assert_eq!(VALID_PROVIDERS.len(), 6);
+        assert!(VALID_PROVIDERS.contains(&"droid"));
+        assert!(VALID_PROVIDERS.contains(&"codex"));
+        assert!(VALID_PROVIDERS.contains(&"claude"));
+        assert!(VALID_PROVIDERS.contains(&"gemini"));
+        assert!(VALID_PROVIDERS.contains(&"copilot"));
+        assert!(VALID_PROVIDERS.contains(&"qwen"));
+    }
+
+    #[test]
+    fn execute_maps_spawn_failures_to_the_provider() {
+        let exec = crate::exec::ScriptedRunner::new().spawn_error(std::io::ErrorKind::NotFound);
+        let err = execute(&exec, "codex", "p", None, &CancelToken::new()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Failed to execute provider 'codex': ")
+        );
+    }
+
+    #[test]
+    fn execute_fails_on_a_login_prompt() {
+        let exec = crate::exec::ScriptedRunner::new().login_prompt("Please run /login");
+        let err = execute(&exec, "claude", "p", None, &CancelToken::new()).unwrap_err();
+        assert!(matches!(err, RalphError::ProviderLogin { .. }));
+        assert!(
+            err.to_string()
+                .contains("run `claude` and log in with /login"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn login_watches_combine_common_and_provider_phrases() {
+        let watch = login_watch("codex");
+        assert!(watch.matches("Press Enter to open your browser"));
+        assert!(watch.matches("Error: Not logged in."));
+        assert!(!watch.matches("Please run /login"));
+        for provider in VALID_PROVIDERS {
+            assert!(!login_phrases(provider).is_empty(), "{provider}");
+        }
+    }
+
+    #[test]
+    fn every_provider_gets_its_own_argv() {
+        let argv = |provider| -> String {
+            let cmd = command(provider, "PROMPT").unwrap();
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        // Without --unsafe, the default.
+        let cases: &[(&str, &str)] = &[
+            (
+                "droid",
+                "droid exec --output-format stream-json --auto medium PROMPT",
+            ),
+            (
+                "codex",
+                "codex exec --json --sandbox workspace-write PROMPT",
+            ),
+            (
+                "claude",
+                "claude -p --verbose --output-format stream-json --permission-mode acceptEdits PROMPT",
+            ),
+            (
+                "gemini",
+                "gemini -p --output-format stream-json --approval-mode auto_edit PROMPT",
+            ),
+            ("copilot", "copilot --allow-tool write -p PROMPT"),
+            (
+                "qwen",
+                "qwen --output-format stream-json --approval-mode auto_edit -p PROMPT",
+            ),
+        ];
+        for (provider, expected) in cases {
+            assert_eq!(argv(provider), *expected, "{provider}");
+        }
+        let covered: Vec<&str> = cases.iter().map(|(provider, _)| *provider).collect();
+        for provider in VALID_PROVIDERS {
+            assert!(covered.contains(provider), "{provider} has no argv case");
+        }
+        assert!(matches!(
+            command("nope", "PROMPT"),
+            Err(RalphError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn unsafe_runs_turn_permission_checks_off() {
+        let cases: &[(&str, &str)] = &[
+            (
+                "droid",
+                "exec --output-format stream-json --skip-permissions-unsafe",
+            ),
+            ("codex", "exec --yolo --json"),
+            (
+                "claude",
+                "-p --verbose --output-format stream-json --dangerously-skip-permissions",
+            ),
+            ("gemini", "-p --output-format stream-json --yolo"),
+            ("copilot", "--allow-all-tools"),
+            ("qwen", "--output-format stream-json --yolo"),
+        ];
+        for (provider, expected) in cases {
+            let flags =
+                spec(provider)
+                    .unwrap()
+                    .flags(PromptTransport::Arg, OutputFormat::StreamJson, true);
+            assert_eq!(flags.join(" "), *expected, "{provider}");
+        }
+        for provider in VALID_PROVIDERS {
+            assert!(
+                cases.iter().any(|(p, _)| p == provider),
+                "{provider} has no unsafe case"
+            );
+        }
+    }
+
+    #[test]
+    fn text_output_drops_the_stream_json_flags() {
+        let cases: &[(&str, &str, &str)] = &[
+            (
+                "droid",
+                "exec --output-format stream-json --auto medium",
+                "exec --auto medium",
+            ),
+            (
+                "codex",
+                "exec --json --sandbox workspace-write",
+                "exec --sandbox workspace-write",
+            ),
+            (
+                "claude",
+                "-p --verbose --output-format stream-json --permission-mode acceptEdits",
+                "-p --permission-mode acceptEdits",
+            ),
+            (
+                "gemini",
+                "-p --output-format stream-json --approval-mode auto_edit",
+                "-p --approval-mode auto_edit",
+            ),
+            ("copilot", "--allow-tool write", "--allow-tool write"),
+            (
+                "qwen",
+                "--output-format stream-json --approval-mode auto_edit",
+                "--approval-mode auto_edit",
+            ),
+        ];
+        for (provider, stream_json, text) in cases {
+            let spec = spec(provider).unwrap();
+            for (format, expected) in [
+                (OutputFormat::StreamJson, stream_json),
+                (OutputFormat::Text, text),
+            ] {
+                let flags = spec.flags(PromptTransport::Arg, format, false);
+                assert_eq!(flags.join(" "), *expected, "{provider} {format:?}");
+            }
+        }
+        assert_eq!(
+            spec("gemini")
+                .unwrap()
+                .flags(PromptTransport::Stdin, OutputFormat::Text, true)
+                .join(" "),
+            "--yolo"
+        );
+        for provider in VALID_PROVIDERS {
+            assert!(
+                cases.iter().any(|(p, _, _)| p == provider),
+                "{provider} has no text case"
+            );
+        }
+        assert_eq!("text".parse::<OutputFormat>().unwrap(), OutputFormat::Text);
+        assert_eq!(
+            "json".parse::<OutputFormat>().unwrap_err().to_string(),
+            "output format must be stream-json or text, got 'json'"
+        );
+    }
+
+    /// A directory standing in for PATH, with a dummy executable for each
+    /// of `programs`.
+    fn fake_path(programs: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for program in programs {
+            let path = dir.path().join(program);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn provider_binaries_are_found_on_path() {
+        let bin = fake_path(&["claude", "codex"]);
+        let other = fake_path(&["gemini"]);
+        let search_path = std::env::join_paths([bin.path(), other.path()]).unwrap();
+        assert_eq!(
+            resolve_binary_in("claude", None, &search_path).unwrap(),
+            bin.path().join("claude")
+        );
+        assert_eq!(
+            resolve_binary_in("gemini", None, &search_path).unwrap(),
+            other.path().join("gemini")
+        );
+
+        let err = resolve_binary_in("droid", None, &search_path).unwrap_err();
+        assert!(matches!(err, RalphError::ProviderNotFound { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Provider 'droid' not found: `droid` is not on PATH; \
+             install it with `curl -fsSL https://app.factory.ai/cli | sh`"
+        );
+        let err = resolve_binary_in("nope", None, &search_path).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid provider 'nope'"));
+    }
+
+    #[test]
+    fn the_command_override_is_looked_up_instead() {
+        let bin = fake_path(&["agent"]);
+        let words = |cmd: &str| Some(cmd.split(' ').map(str::to_string).collect());
+        assert_eq!(
+            resolve_binary_in("claude", words("agent --fast"), bin.path().as_os_str()).unwrap(),
+            bin.path().join("agent")
+        );
+        let full = bin.path().join("agent").display().to_string();
+        assert_eq!(
+            resolve_binary_in("claude", words(&full), OsStr::new("")).unwrap(),
+            bin.path().join("agent")
+        );
+        let err = resolve_binary_in("claude", words("missing"), bin.path().as_os_str())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Provider 'claude' not found: `missing` is not on PATH; check RALPH_PROVIDER_CMD"
+        );
+    }
+
+    #[test]
+    fn extra_args_go_between_the_flags_and_the_prompt() {
+        let extra = [
+            "--model".to_string(),
+            "opus 4".to_string(),
+            "--x=a b".to_string(),
+        ];
+        for provider in VALID_PROVIDERS {
+            let mut cmd = base_command_with(provider, PromptTransport::Arg, &extra).unwrap();
+            cmd.arg("PROMPT");
+            let args: Vec<String> = cmd
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            let own = spec(provider).unwrap().flags(
+                PromptTransport::Arg,
+                OutputFormat::StreamJson,
+                false,
+            );
+            assert_eq!(args[..own.len()], own, "{provider}");
+            assert_eq!(
+                args[own.len()..],
+                ["--model", "opus 4", "--x=a b", "PROMPT"],
+                "{provider}"
+            );
+        }
+    }
+
+    #[test]
+    fn configured_args_come_before_the_command_line_ones() {
+        let configured = BTreeMap::from([
+            (
+                "codex".to_string(),
+                vec!["--profile".to_string(), "work".to_string()],
+            ),
+            (
+                "claude".to_string(),
+                vec!["--model".to_string(), "sonnet".to_string()],
+            ),
+        ]);
+        let command_line = ["-c".to_string(), "model=o3 high".to_string()];
+        let extra = merge_extra_args(None, &configured, &command_line, "codex").unwrap();
+        let mut cmd = base_command_with("codex", PromptTransport::Arg, &extra).unwrap();
+        cmd.arg("PROMPT");
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "exec",
+                "--json",
+                "--sandbox",
+                "workspace-write",
+                "--profile",
+                "work",
+                "-c",
+                "model=o3 high",
+                "PROMPT"
+            ]
+        );
+
+        // Nothing configured for a provider, or at all, changes nothing.
+        assert_eq!(
+            merge_extra_args(None, &configured, &[], "gemini").unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            merge_extra_args(None, &BTreeMap::new(), &command_line, "codex").unwrap(),
+            command_line
+        );
+    }
+
+    #[test]
+    fn models_are_chosen_with_each_providers_flag() {
+        let cases = [
+            ("droid", "--model"),
+            ("codex", "-m"),
+            ("claude", "--model"),
+            ("gemini", "-m"),
+            ("copilot", "--model"),
+            ("qwen", "-m"),
+        ];
+        let command_line = ["--verbose".to_string()];
+        for (provider, flag) in cases {
+            let with =
+                merge_extra_args(Some("big-1"), &BTreeMap::new(), &command_line, provider).unwrap();
+            assert_eq!(with, [flag, "big-1", "--verbose"], "{provider}");
+            let without =
+                merge_extra_args(None, &BTreeMap::new(), &command_line, provider).unwrap();
+            assert_eq!(without, ["--verbose"], "{provider}");
+        }
+        for provider in VALID_PROVIDERS {
+            assert!(
+                cases.iter().any(|(p, _)| p == provider),
+                "{provider} has no model case"
+            );
+        }
+        assert!(model_args("nope", "big-1").is_err());
+    }
+
+    #[test]
+    fn valid_providers_are_the_registered_ones() {
+        let names: Vec<&str> = PROVIDERS.iter().map(|spec| spec.name).collect();
+        assert_eq!(VALID_PROVIDERS, names);
+        assert_eq!(VALID_PROVIDERS[0], crate::config::DEFAULT_PROVIDER);
+        for spec in PROVIDERS {
+            assert!(!spec.login_hint.is_empty(), "{}", spec.name);
+            assert!(!spec.auth_vars.is_empty(), "{}", spec.name);
+        }
+    }
+
+    #[test]
+    fn long_prompts_leave_the_argument_list() {
+        let args = |cmd: &Command| -> Vec<String> {
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        let at_limit = "x".repeat(ARG_PROMPT_LIMIT);
+        let prepared = prepare("claude", &at_limit).unwrap();
+        assert_eq!(prepared.transport, PromptTransport::Arg);
+        assert_eq!(args(&prepared.command).last(), Some(&at_limit));
+        assert!(prepared.prompt_file().is_none());
+
+        let over = "x".repeat(ARG_PROMPT_LIMIT + 1);
+        for provider in ["claude", "codex", "gemini"] {
+            let prepared = prepare(provider, &over).unwrap();
+            assert_eq!(prepared.transport, PromptTransport::Stdin, "{provider}");
+            assert!(!args(&prepared.command).contains(&over), "{provider}");
+            let file = prepared.prompt_file().unwrap().to_path_buf();
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), over);
+            drop(prepared);
+            assert!(!file.exists(), "{provider}");
+        }
+        let gemini = prepare("gemini", &over).unwrap();
+        assert!(!args(&gemini.command).contains(&"-p".to_string()));
+
+        let droid = prepare("droid", &over).unwrap();
+        assert_eq!(droid.transport, PromptTransport::File);
+        let droid_args = args(&droid.command);
+        let file = droid.prompt_file().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            droid_args[droid_args.len() - 2..],
+            ["--file".to_string(), file]
+        );
+    }
+
+    fn template(command: &str, shell: bool) -> Template {
+        Template {
+            command: command.to_string(),
+            shell,
+        }
+    }
+
+    #[test]
+    fn templates_split_like_a_shell_and_keep_the_prompt_one_argument() {
+        let prompt = "Fix the \"flaky\" test\nand don't stop: {iteration} $HOME `x`";
+        let wrapper = template(
+            "./scripts/agent.sh --title 'ralph run' \"{prompt}\" --iteration={iteration}",
+            false,
+        );
+        assert!(wrapper.takes_prompt());
+        assert_eq!(
+            wrapper.argv(prompt, 3, &["--fast".to_string()]).unwrap(),
+            [
+                "./scripts/agent.sh",
+                "--title",
+                "ralph run",
+                prompt,
+                "--iteration=3",
+                "--fast"
+            ]
+        );
+        // Unquoted, the placeholder is still one argument.
+        let bare = template("agent {prompt}", false);
+        assert_eq!(bare.argv(prompt, 1, &[]).unwrap(), ["agent", prompt]);
+        assert_eq!(bare.argv("", 1, &[]).unwrap(), ["agent", ""]);
+
+        let stdin = template(r"agent\ cli --quiet", false);
+        assert!(!stdin.takes_prompt());
+        assert_eq!(
+            stdin.argv(prompt, 1, &[]).unwrap(),
+            ["agent cli", "--quiet"]
+        );
+
+        let err = template("agent 'unfinished {prompt}", false)
+            .argv(prompt, 1, &[])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "command \"agent 'unfinished {prompt}\" has an unfinished quote or escape"
+        );
+        let err = template("  ", false).argv(prompt, 1, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "command is empty");
+    }
+
+    #[test]
+    fn shell_templates_quote_what_they_substitute() {
+        let prompt = "Fix the \"flaky\" test\nand don't stop; rm -rf / $(id)";
+        let argv = template("./agent.sh {prompt} {iteration}", true)
+            .argv(prompt, 2, &["--a b".to_string()])
+            .unwrap();
+        assert_eq!(argv[..2], ["sh", "-c"]);
+        assert!(argv[2].starts_with("./agent.sh "), "{}", argv[2]);
+        assert!(argv[2].ends_with(" 2 '--a b'"), "{}", argv[2]);
+        assert_eq!(
+            shlex::split(argv[2].strip_prefix("./agent.sh ").unwrap()).unwrap(),
+            [prompt, "2", "--a b"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_templates_hand_sh_the_prompt_unchanged() {
+        let prompt = "Fix the \"flaky\" test\nand don't stop; echo $HOME `id` \\n {iteration}";
+        let argv = template("printf '%s|' {prompt} {iteration}", true)
+            .argv(prompt, 7, &["--x".to_string()])
+            .unwrap();
+        let out = Command::new(&argv[0]).args(&argv[1..]).output().unwrap();
+        assert_eq!(
+            String::from_utf8(out.stdout).unwrap(),
+            format!("{prompt}|7|--x|")
+        );
+    }
+
+    #[test]
+    fn template_providers_are_valid_alongside_the_built_in_ones() {
+        let templates = BTreeMap::from([("mywrapper".to_string(), template("agent", false))]);
+        let aliases = BTreeMap::new();
+        assert!(validate_provider_in("mywrapper", &templates, &aliases).is_ok());
+        assert!(validate_provider_in("claude", &templates, &aliases).is_ok());
+        let err = validate_provider_in("nope", &templates, &aliases).unwrap_err();
+        assert!(
+            err.to_string().ends_with(
+                "Available providers: droid, codex, claude, gemini, copilot, qwen, mywrapper"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn aliases_resolve_after_provider_names_and_config_before_built_ins() {
+        let templates = BTreeMap::from([("cc".to_string(), template("my-cc {prompt}", false))]);
+        let aliases = BTreeMap::from([
+            ("oai".to_string(), "codex".to_string()),
+            ("claude-code".to_string(), "qwen".to_string()),
+        ]);
+        let resolve = |name| resolve_alias(name, &BTreeMap::new(), &aliases);
+        assert_eq!(resolve("oai"), "codex");
+        assert_eq!(resolve("cc"), "claude");
+        assert_eq!(resolve("factory"), "droid");
+        assert_eq!(resolve("claude-code"), "qwen");
+        assert_eq!(resolve("codex"), "codex");
+        assert_eq!(resolve("nope"), "nope");
+        // A provider's own name is never an alias.
+        assert_eq!(resolve_alias("cc", &templates, &aliases), "cc");
+    }
+
+    #[test]
+    fn unknown_providers_get_the_closest_name_or_alias_suggested() {
+        let templates = BTreeMap::new();
+        let aliases = BTreeMap::from([("oai".to_string(), "codex".to_string())]);
+        let suggestion = |name: &str| {
+            let err = validate_provider_in(name, &templates, &aliases).unwrap_err();
+            let message = err.to_string();
+            message
+                .split_once("\nDid you mean '")
+                .map(|(_, rest)| rest.trim_end_matches("'?").to_string())
+        };
+        assert_eq!(suggestion("cladue").as_deref(), Some("claude"));
+        assert_eq!(suggestion("Claude").as_deref(), Some("claude"));
+        assert_eq!(suggestion("clau").as_deref(), Some("claude"));
+        assert_eq!(suggestion("oia").as_deref(), Some("oai"));
+        assert_eq!(suggestion("gemni").as_deref(), Some("gemini"));
+        assert_eq!(suggestion("copliot").as_deref(), Some("copilot"));
+        assert_eq!(suggestion("ccc").as_deref(), Some("cc"));
+        assert_eq!(suggestion("cursor"), None);
+        assert_eq!(suggestion("x"), None);
+        assert_eq!(suggestion(""), None);
+        assert_eq!(closest("oai", ["oai", "codex"]), None);
+        assert_eq!(closest("Claude", ["claude"]), Some("claude"));
+
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("cladue", "claude"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("codex", "codex"), 0);
+    }
+}
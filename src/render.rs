@@ -0,0 +1,152 @@
+use serde_json::Value;
+
+/// Turn one line of provider stream-json output into human-readable text.
+///
+/// Understands the event shapes emitted by the supported providers
+/// (assistant messages, tool calls, final results). Lines that aren't JSON
+/// are returned unchanged; JSON events with nothing worth showing return
+/// `None`.
+pub fn render_line(line: &str) -> Option<String> {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(v @ Value::Object(_)) => v,
+        _ => return Some(line.to_string()),
+    };
+
+    let kind = value["type"].as_str().unwrap_or("");
+    match kind {
+        // claude: {"type":"assistant","message":{"content":[...]}}
+        "assistant" => {
+            let blocks = value["message"]["content"].as_array()?;
+            let parts: Vec<String> = blocks.iter().filter_map(render_content_block).collect();
+            non_empty(parts.join("\n"))
+        }
+        // claude / gemini final result
+        "result" => value["result"]
+            .as_str()
+            .and_then(|r| non_empty(r.to_string()))
+            .map(|r| format!("[result] {r}")),
+        // gemini / droid assistant messages
+        "message" => {
+            if value["role"].as_str() != Some("assistant") {
+                return None;
+            }
+            value["content"]
+                .as_str()
+                .or_else(|| value["text"].as_str())
+                .and_then(|t| non_empty(t.to_string()))
+        }
+        // gemini / droid tool invocations
+        "tool_use" | "tool_call" => {
+            let name = value["tool_name"]
+                .as_str()
+                .or_else(|| value["toolName"].as_str())
+                .or_else(|| value["name"].as_str())
+                .unwrap_or("tool");
+            Some(format!("→ {name}"))
+        }
+        // droid final message
+        "completion" => value["finalText"]
+            .as_str()
+            .and_then(|t| non_empty(t.to_string())),
+        // codex: {"type":"item.completed","item":{"type":"agent_message","text":"..."}}
+        "item.started" | "item.completed" => render_codex_item(kind, &value["item"]),
+        _ => None,
+    }
+}
+
+/// What the assistant said in provider `output`: its messages without the
+/// tool calls between them, or the final result when it sent no messages.
+/// Lines that aren't JSON count as said.
+pub fn assistant_text(output: &str) -> String {
+    let mut said = Vec::new();
+    let mut result = None;
+    for text in output.lines().filter_map(render_line) {
+        if let Some(text) = text.strip_prefix("[result] ") {
+            result = Some(text.to_string());
+            continue;
+        }
+        said.extend(
+            text.lines()
+                .filter(|l| !l.starts_with("→ "))
+                .map(str::to_string),
+        );
+    }
+    match result {
+        Some(result) if said.iter().all(|l| l.trim().is_empty()) => result,
+        _ => said.join("\n").trim().to_string(),
+    }
+}
+
+fn render_content_block(block: &Value) -> Option<String> {
+    match block["type"].as_str()? {
+        "text" => block["text"]
+            .as_str()
+            .and_then(|t| non_empty(t.to_string())),
+        "tool_use" => Some(format!("→ {}", block["name"].as_str().unwrap_or("tool"))),
+        _ => None,
+    }
+}
+
+fn render_codex_item(kind: &str, item: &Value) -> Option<String> {
+    match (kind, item["type"].as_str()?) {
+        ("item.completed", "agent_message") => {
+            item["text"].as_str().and_then(|t| non_empty(t.to_string()))
+        }
+        ("item.started", "command_execution") => item["command"].as_str().map(|c| format!("→ {c}")),
+        _ => None,
+    }
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.trim().is_empty() { None } else { Some(s) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_json_lines_pass_through() {
+        assert_eq!(render_line("plain text").as_deref(), Some("plain text"));
+    }
+
+    #[test]
+    fn claude_assistant_text_and_tools() {
+        let line = r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Hello"},{"type":"tool_use","name":"Bash","input":{}}]}}"#;
+        assert_eq!(render_line(line).as_deref(), Some("Hello\n→ Bash"));
+    }
+
+    #[test]
+    fn codex_agent_message() {
+        let line = r#"{"type":"item.completed","item":{"type":"agent_message","text":"Done"}}"#;
+        assert_eq!(render_line(line).as_deref(), Some("Done"));
+    }
+
+    #[test]
+    fn gemini_user_messages_are_hidden() {
+        let line = r#"{"type":"message","role":"user","content":"prompt"}"#;
+        assert_eq!(render_line(line), None);
+    }
+
+    #[test]
+    fn assistant_text_leaves_out_tool_calls() {
+        let output = [
+            r#"{"type":"system","subtype":"init"}"#,
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Plan:\n1. bd-1"},{"type":"tool_use","name":"Bash","input":{}}]}}"#,
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"2. bd-2"}]}}"#,
+            r#"{"type":"result","result":"2. bd-2"}"#,
+        ]
+        .join("\n");
+        assert_eq!(assistant_text(&output), "Plan:\n1. bd-1\n2. bd-2");
+        assert_eq!(
+            assistant_text(r#"{"type":"result","result":"Only this"}"#),
+            "Only this"
+        );
+        assert_eq!(assistant_text("plain\ntext\n"), "plain\ntext");
+    }
+
+    #[test]
+    fn unknown_events_are_hidden() {
+        assert_eq!(render_line(r#"{"type":"system","subtype":"init"}"#), None);
+    }
+}
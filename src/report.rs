@@ -0,0 +1,516 @@
+use crate::backlog::{BacklogDiff, BacklogItem};
+use crate::beads::Issue;
+use crate::error::Result;
+use crate::exec::{self, CommandRunner};
+use crate::git::{self, Commit};
+use crate::session::{self, Event, Record};
+use crate::tracker::Tracker;
+use crate::usage::Usage;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::PathBuf;
+
+/// What the transcript says about one iteration.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct IterationInfo {
+    number: u32,
+    started: Option<String>,
+    ended: Option<String>,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    complete: bool,
+    usage: Option<Usage>,
+    head: Option<String>,
+    prompt_hash: Option<String>,
+    planning: bool,
+}
+
+/// What the transcript says about the session as a whole.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct SessionInfo {
+    id: String,
+    command: String,
+    provider: String,
+    cwd: PathBuf,
+    started: String,
+    ended: Option<String>,
+    completed: Option<bool>,
+    head: Option<String>,
+    prompt_hash: Option<String>,
+    iterations: Vec<IterationInfo>,
+    /// Tasks the transcript itself records as closed (`ralph run`)
+    task_ids: Vec<String>,
+    /// Sync command run after the loop, with its exit code
+    tracker_sync: Option<(String, i32)>,
+    /// Backlog before vs. after the loop, when both could be read
+    backlog: Option<BacklogDiff>,
+}
+
+impl SessionInfo {
+    /// Commit after the last iteration that recorded one.
+    fn end_head(&self) -> Option<&str> {
+        self.iterations.iter().rev().find_map(|i| i.head.as_deref())
+    }
+}
+
+/// Data from bd and git; either may be unavailable, in which case the
+/// reason is shown instead.
+#[derive(Debug)]
+struct External {
+    closed: Result<Vec<Issue>, String>,
+    commits: Result<Vec<Commit>, String>,
+    iteration_commits: HashMap<u32, Vec<Commit>>,
+}
+
+fn parse_session(id: &str, records: &[Record]) -> Option<SessionInfo> {
+    let (first, rest) = records.split_first()?;
+    let Event::SessionStart {
+        command,
+        provider,
+        cwd,
+        head,
+        prompt_hash,
+        ..
+    } = &first.event
+    else {
+        return None;
+    };
+    let mut info = SessionInfo {
+        id: id.to_string(),
+        command: command.clone(),
+        provider: provider.clone(),
+        cwd: cwd.clone(),
+        started: first.ts.clone(),
+        head: head.clone(),
+        prompt_hash: prompt_hash.clone(),
+        ..SessionInfo::default()
+    };
+    for record in rest {
+        match &record.event {
+            Event::IterationStart {
+                iteration,
+                prompt_hash,
+                planning,
+            } => info.iterations.push(IterationInfo {
+                number: *iteration,
+                started: Some(record.ts.clone()),
+                prompt_hash: prompt_hash.clone(),
+                planning: *planning,
+                ..IterationInfo::default()
+            }),
+            Event::IterationEnd {
+                iteration,
+                exit_code,
+                signal,
+                complete,
+                usage,
+                head,
+                ..
+            } => {
+                if info.iterations.last().map(|i| i.number) != Some(*iteration) {
+                    info.iterations.push(IterationInfo {
+                        number: *iteration,
+                        ..IterationInfo::default()
+                    });
+                }
+                let it = info.iterations.last_mut().expect("just pushed");
+                it.ended = Some(record.ts.clone());
+                it.exit_code = Some(*exit_code);
+                it.signal = *signal;
+                it.complete = *complete;
+                it.usage = *usage;
+                it.head = head.clone();
+            }
+            Event::SessionEnd { completed, .. } => {
+                info.ended = Some(record.ts.clone());
+                info.completed = Some(*completed);
+            }
+            Event::TaskClosed { id } => info.task_ids.push(id.clone()),
+            Event::TrackerSync { command, exit_code } => {
+                info.tracker_sync = Some((command.clone(), *exit_code))
+            }
+            Event::Backlog { diff } => info.backlog = Some(diff.clone()),
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+fn parse_ts(ts: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(ts).ok().map(|t| t.to_utc())
+}
+
+/// Issues closed while the session ran, plus any it recorded itself.
+fn closed_during(info: &SessionInfo, closed: Vec<Issue>) -> Vec<Issue> {
+    let start = parse_ts(&info.started);
+    let end = info.ended.as_deref().and_then(parse_ts);
+    let mut found: Vec<Issue> = closed
+        .into_iter()
+        .filter(|issue| {
+            if info.task_ids.contains(&issue.id) {
+                return true;
+            }
+            let Some(at) = issue.closed_at.as_deref().and_then(parse_ts) else {
+                return false;
+            };
+            start.is_some_and(|s| at >= s) && end.is_none_or(|e| at <= e)
+        })
+        .collect();
+    for id in &info.task_ids {
+        if !found.iter().any(|i| &i.id == id) {
+            found.push(Issue {
+                id: id.clone(),
+                ..Issue::default()
+            });
+        }
+    }
+    found
+}
+
+fn gather(exec: &dyn CommandRunner, tracker: &dyn Tracker, info: &SessionInfo) -> External {
+    let closed = tracker
+        .list(exec, "closed")
+        .map(|issues| closed_during(info, issues))
+        .map_err(|e| e.to_string());
+
+    let mut iteration_commits = HashMap::new();
+    let commits = match (info.head.as_deref(), info.end_head()) {
+        (Some(start), Some(end)) => {
+            let mut prev = start;
+            for it in &info.iterations {
+                if let Some(head) = it.head.as_deref() {
+                    if let Ok(commits) = git::log(&info.cwd, prev, head) {
+                        iteration_commits.insert(it.number, commits);
+                    }
+                    prev = head;
+                }
+            }
+            git::log(&info.cwd, start, end).map_err(|e| e.to_string())
+        }
+        _ => Err("the session did not record its git commits".to_string()),
+    };
+
+    External {
+        closed,
+        commits,
+        iteration_commits,
+    }
+}
+
+fn write_commits(out: &mut String, commits: &[Commit]) {
+    for c in commits {
+        let _ = writeln!(out, "- `{}` {}", c.hash, c.subject);
+    }
+}
+
+fn write_backlog(out: &mut String, diff: &BacklogDiff) {
+    out.push_str("\n## Backlog changes\n\n");
+    if diff.is_empty() {
+        out.push_str("None.\n");
+        return;
+    }
+    let sections: [(&str, &[BacklogItem]); 3] = [
+        ("Closed", &diff.closed),
+        ("Created", &diff.created),
+        ("Started but not finished", &diff.stalled),
+    ];
+    for (heading, items) in sections {
+        if items.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "**{heading}:**\n");
+        for item in items {
+            let _ = writeln!(out, "- {}: {}", item.id, item.title);
+        }
+        out.push('\n');
+    }
+    out.truncate(out.trim_end().len() + 1);
+}
+
+fn render_markdown(info: &SessionInfo, ext: &External) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# ralph session {}\n", info.id);
+    let _ = writeln!(
+        out,
+        "- **Command:** `ralph {}` with `{}`",
+        info.command, info.provider
+    );
+    let _ = writeln!(out, "- **Directory:** `{}`", info.cwd.display());
+    let _ = writeln!(out, "- **Started:** {}", info.started);
+    let _ = writeln!(
+        out,
+        "- **Ended:** {}",
+        info.ended.as_deref().unwrap_or("still running")
+    );
+    let outcome = match info.completed {
+        Some(true) => "completed",
+        Some(false) => "stopped without completing",
+        None => "in progress",
+    };
+    let _ = writeln!(
+        out,
+        "- **Iterations:** {} ({outcome})",
+        info.iterations.len()
+    );
+    match &info.tracker_sync {
+        Some((command, 0)) => {
+            let _ = writeln!(out, "- **Tracker sync:** `{command}` succeeded");
+        }
+        Some((command, -1)) => {
+            let _ = writeln!(out, "- **Tracker sync:** `{command}` could not be started");
+        }
+        Some((command, code)) => {
+            let _ = writeln!(
+                out,
+                "- **Tracker sync:** `{command}` failed (exit code {code})"
+            );
+        }
+        None => {}
+    }
+
+    out.push_str("\n## Tasks closed\n\n");
+    match &ext.closed {
+        Ok(issues) if issues.is_empty() => out.push_str("None.\n"),
+        Ok(issues) => {
+            for issue in issues {
+                if issue.title.is_empty() {
+                    let _ = writeln!(out, "- {}", issue.id);
+                } else {
+                    let _ = writeln!(out, "- {}: {}", issue.id, issue.title);
+                }
+            }
+        }
+        Err(e) => {
+            let _ = writeln!(out, "_Unavailable: {e}_");
+        }
+    }
+
+    if let Some(diff) = &info.backlog {
+        write_backlog(&mut out, diff);
+    }
+
+    out.push_str("\n## Commits\n\n");
+    match &ext.commits {
+        Ok(commits) if commits.is_empty() => out.push_str("None.\n"),
+        Ok(commits) => write_commits(&mut out, commits),
+        Err(e) => {
+            let _ = writeln!(out, "_Unavailable: {e}_");
+        }
+    }
+
+    let mut prompt_hash = info.prompt_hash.as_deref();
+    for it in &info.iterations {
+        if it.planning {
+            let _ = writeln!(out, "\n## Iteration {} (planning)\n", it.number);
+        } else {
+            let _ = writeln!(out, "\n## Iteration {}\n", it.number);
+        }
+        if let Some(hash) = it.prompt_hash.as_deref().filter(|_| !it.planning) {
+            if prompt_hash.is_some_and(|h| h != hash) {
+                let short = hash.get(..8).unwrap_or(hash);
+                let _ = writeln!(out, "- **Prompt:** reloaded, now `{short}`");
+            }
+            prompt_hash = Some(hash);
+        }
+        if let (Some(start), Some(end)) = (
+            it.started.as_deref().and_then(parse_ts),
+            it.ended.as_deref().and_then(parse_ts),
+        ) {
+            let secs = (end - start).num_seconds();
+            let _ = writeln!(out, "- **Duration:** {}m {}s", secs / 60, secs % 60);
+        }
+        match (it.exit_code, it.signal) {
+            (_, Some(signal)) => {
+                let _ = writeln!(
+                    out,
+                    "- **Exit code:** provider {}",
+                    exec::describe_signal(signal)
+                );
+            }
+            (Some(code), None) => {
+                let _ = writeln!(out, "- **Exit code:** {code}");
+            }
+            (None, None) => out.push_str("- **Exit code:** (did not finish)\n"),
+        }
+        if it.complete {
+            out.push_str("- Reported all tasks complete\n");
+        }
+        if let Some(usage) = it.usage {
+            let _ = write!(
+                out,
+                "- **Tokens:** {} in / {} out",
+                usage.input_tokens, usage.output_tokens
+            );
+            if let Some(cost) = usage.cost_usd {
+                let _ = write!(out, " (${cost:.2})");
+            }
+            out.push('\n');
+        }
+        if let Some(commits) = ext.iteration_commits.get(&it.number)
+            && !commits.is_empty()
+        {
+            out.push_str("- **Commits:**\n");
+            for c in commits {
+                let _ = writeln!(out, "  - `{}` {}", c.hash, c.subject);
+            }
+        }
+    }
+    out
+}
+
+/// Build the markdown report for a session (the latest when `id` is
+/// `None`), asking `tracker` through `exec` which issues were closed.
+pub fn generate(
+    exec: &dyn CommandRunner,
+    tracker: &dyn Tracker,
+    id: Option<&str>,
+) -> Result<String> {
+    let stored = session::find_session(id)?;
+    let info = parse_session(&stored.id, &stored.records()?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Session '{}' has no session_start record", stored.id),
+        )
+    })?;
+    Ok(render_markdown(&info, &gather(exec, tracker, &info)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSCRIPT: &str = concat!(
+        r#"{"ts":"2026-01-10T00:00:00Z","event":"session_start","session":"s","command":"loop","provider":"claude","max_iterations":5,"cwd":"/repo","head":"aaa"}"#,
+        "\n",
+        r#"{"ts":"2026-01-10T00:00:00Z","event":"iteration_start","iteration":1}"#,
+        "\n",
+        r#"{"ts":"2026-01-10T00:02:05Z","event":"iteration_end","iteration":1,"exit_code":0,"complete":true,"head":"bbb"}"#,
+        "\n",
+        r#"{"ts":"2026-01-10T00:02:05Z","event":"session_end","iterations":1,"completed":true}"#,
+        "\n",
+        r#"{"ts":"2026-01-10T00:02:07Z","event":"tracker_sync","command":"bd sync","exit_code":0}"#,
+        "\n",
+        r#"{"ts":"2026-01-10T00:02:07Z","event":"backlog","diff":{"closed":[{"id":"bd-1","title":"Title bd-1","status":"closed"}],"created":[],"stalled":[{"id":"bd-4","title":"Half done","status":"in_progress"}]}}"#,
+    );
+
+    fn info() -> SessionInfo {
+        let records: Vec<Record> = TRANSCRIPT
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        parse_session("s", &records).unwrap()
+    }
+
+    fn closed_issue(id: &str, closed_at: &str) -> Issue {
+        Issue {
+            id: id.to_string(),
+            title: format!("Title {id}"),
+            closed_at: Some(closed_at.to_string()),
+            ..Issue::default()
+        }
+    }
+
+    #[test]
+    fn parse_session_collects_iterations_and_heads() {
+        let info = info();
+        assert_eq!(info.head.as_deref(), Some("aaa"));
+        assert_eq!(info.end_head(), Some("bbb"));
+        assert_eq!(info.iterations.len(), 1);
+        assert_eq!(info.iterations[0].exit_code, Some(0));
+        assert_eq!(info.completed, Some(true));
+        assert_eq!(info.tracker_sync, Some(("bd sync".to_string(), 0)));
+    }
+
+    #[test]
+    fn closed_during_filters_by_session_window() {
+        let issues = vec![
+            closed_issue("bd-1", "2026-01-10T00:01:00Z"),
+            closed_issue("bd-2", "2026-01-09T00:00:00Z"),
+            closed_issue("bd-3", "2026-01-10T00:01:00.123+00:00"),
+        ];
+        let ids: Vec<_> = closed_during(&info(), issues)
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, ["bd-1", "bd-3"]);
+    }
+
+    #[test]
+    fn markdown_degrades_when_bd_and_git_are_unavailable() {
+        let ext = External {
+            closed: Err("bd not found".to_string()),
+            commits: Err("not a git repository".to_string()),
+            iteration_commits: HashMap::new(),
+        };
+        let md = render_markdown(&info(), &ext);
+        assert!(md.starts_with("# ralph session s\n"));
+        assert!(md.contains("- **Tracker sync:** `bd sync` succeeded\n"));
+        assert!(md.contains("## Tasks closed\n\n_Unavailable: bd not found_"));
+        assert!(md.contains(
+            "## Backlog changes\n\n**Closed:**\n\n- bd-1: Title bd-1\n\n\
+             **Started but not finished:**\n\n- bd-4: Half done\n\n## Commits"
+        ));
+        assert!(md.contains("## Iteration 1\n\n- **Duration:** 2m 5s"));
+    }
+
+    #[test]
+    fn markdown_lists_issues_and_commits_per_iteration() {
+        let commit = Commit {
+            hash: "bbb".to_string(),
+            subject: "Fix it".to_string(),
+        };
+        let ext = External {
+            closed: Ok(vec![closed_issue("bd-1", "2026-01-10T00:01:00Z")]),
+            commits: Ok(vec![commit.clone()]),
+            iteration_commits: HashMap::from([(1, vec![commit])]),
+        };
+        let md = render_markdown(&info(), &ext);
+        assert!(md.contains("- bd-1: Title bd-1"));
+        assert!(md.contains("## Commits\n\n- `bbb` Fix it"));
+        assert!(md.contains("  - `bbb` Fix it"));
+    }
+
+    #[test]
+    fn markdown_notes_iterations_that_reloaded_the_prompt() {
+        let mut info = info();
+        info.prompt_hash = Some("1111111111".to_string());
+        info.iterations[0].prompt_hash = Some("1111111111".to_string());
+        info.iterations.push(IterationInfo {
+            number: 2,
+            prompt_hash: Some("2222222222".to_string()),
+            ..IterationInfo::default()
+        });
+        let ext = External {
+            closed: Err("bd not found".to_string()),
+            commits: Err("not a git repository".to_string()),
+            iteration_commits: HashMap::new(),
+        };
+        let md = render_markdown(&info, &ext);
+        assert_eq!(md.matches("**Prompt:**").count(), 1, "{md}");
+        assert!(md.contains("## Iteration 2\n\n- **Prompt:** reloaded, now `22222222`\n"));
+    }
+
+    #[test]
+    fn markdown_labels_the_planning_iteration() {
+        let mut info = info();
+        info.prompt_hash = Some("1111111111".to_string());
+        info.iterations[0].prompt_hash = Some("3333333333".to_string());
+        info.iterations[0].planning = true;
+        info.iterations.push(IterationInfo {
+            number: 2,
+            prompt_hash: Some("1111111111".to_string()),
+            ..IterationInfo::default()
+        });
+        let ext = External {
+            closed: Err("bd not found".to_string()),
+            commits: Err("not a git repository".to_string()),
+            iteration_commits: HashMap::new(),
+        };
+        let md = render_markdown(&info, &ext);
+        assert!(md.contains("## Iteration 1 (planning)\n"), "{md}");
+        assert!(md.contains("## Iteration 2\n"), "{md}");
+        assert!(!md.contains("**Prompt:**"), "{md}");
+    }
+}
@@ -0,0 +1,390 @@
+use serde_json::json;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+/// Where ralph's own messages (banners, warnings, progress; not provider
+/// output or command results) go. The binary picks an implementation from
+/// the global `--quiet`, `--verbose` and `--message-format` flags and installs it with
+/// [`set`]; library code reports through the free functions in this module.
+pub trait Reporter: Send + Sync {
+    /// A status line, e.g. `Using AI provider: droid`. An empty message is
+    /// a blank line.
+    fn info(&self, message: &str);
+    /// Something went wrong but ralph carries on.
+    fn warn(&self, message: &str);
+    /// A failure that ends the command.
+    fn error(&self, message: &str);
+    /// A section heading, e.g. the start of a loop iteration.
+    fn banner(&self, title: &str);
+    /// Progress of a long step, updated in place; `finished` on the last
+    /// update.
+    fn progress(&self, message: &str, finished: bool);
+    /// The outcome of a run, set off from what came before.
+    fn summary(&self, message: &str);
+    /// Diagnostics, e.g. tool output ralph couldn't parse. Dropped unless
+    /// the reporter was made verbose (`--verbose`).
+    fn debug(&self, _message: &str) {}
+}
+
+impl<R: Reporter + ?Sized> Reporter for Box<R> {
+    fn info(&self, message: &str) {
+        (**self).info(message);
+    }
+
+    fn warn(&self, message: &str) {
+        (**self).warn(message);
+    }
+
+    fn error(&self, message: &str) {
+        (**self).error(message);
+    }
+
+    fn banner(&self, title: &str) {
+        (**self).banner(title);
+    }
+
+    fn progress(&self, message: &str, finished: bool) {
+        (**self).progress(message, finished);
+    }
+
+    fn summary(&self, message: &str) {
+        (**self).summary(message);
+    }
+
+    fn debug(&self, message: &str) {
+        (**self).debug(message);
+    }
+}
+
+const RULE: &str = "==========================================";
+
+/// Plain text for a terminal, the default.
+#[derive(Debug)]
+pub struct TerminalReporter<W = io::Stderr> {
+    out: Mutex<W>,
+    verbose: bool,
+    animate: bool,
+}
+
+impl TerminalReporter {
+    /// A reporter writing to stderr, redrawing progress in place only when
+    /// stderr is a capable terminal (see [`crate::term`]).
+    pub fn stderr() -> Self {
+        TerminalReporter::new(io::stderr()).animate(crate::term::get().can_animate())
+    }
+}
+
+impl<W: Write> TerminalReporter<W> {
+    /// A reporter writing to `out`.
+    pub fn new(out: W) -> Self {
+        TerminalReporter {
+            out: Mutex::new(out),
+            verbose: false,
+            animate: true,
+        }
+    }
+
+    /// Redraw [`Reporter::progress`] in place with `\r`; when off, only the
+    /// finished update is written, as a line of its own.
+    pub fn animate(mut self, animate: bool) -> Self {
+        self.animate = animate;
+        self
+    }
+
+    /// Also write [`Reporter::debug`] messages.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// The writer, with everything reported so far.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self, text: &str) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Like eprintln!, but a closed stderr is no reason to panic.
+        let _ = out.write_all(text.as_bytes());
+        let _ = out.flush();
+    }
+}
+
+impl<W: Write + Send> Reporter for TerminalReporter<W> {
+    fn info(&self, message: &str) {
+        self.write(&format!("{message}\n"));
+    }
+
+    fn warn(&self, message: &str) {
+        self.write(&format!("Warning: {message}\n"));
+    }
+
+    fn error(&self, message: &str) {
+        self.write(&format!("Error: {message}\n"));
+    }
+
+    fn banner(&self, title: &str) {
+        self.write(&format!("{RULE}\n{title}\n{RULE}\n"));
+    }
+
+    fn progress(&self, message: &str, finished: bool) {
+        if self.animate {
+            self.write(&format!("\r{message}{}", if finished { "\n" } else { "" }));
+        } else if finished {
+            self.write(&format!("{message}\n"));
+        }
+    }
+
+    fn summary(&self, message: &str) {
+        self.write(&format!("\n{message}\n"));
+    }
+
+    fn debug(&self, message: &str) {
+        if self.verbose {
+            self.write(&format!("Debug: {message}\n"));
+        }
+    }
+}
+
+/// One JSON object per line, for wrappers that parse ralph's messages:
+/// `{"level":"warn","message":"..."}`. Blank info lines are dropped.
+#[derive(Debug)]
+pub struct JsonReporter<W = io::Stderr> {
+    out: TerminalReporter<W>,
+}
+
+impl JsonReporter {
+    /// A reporter writing to stderr.
+    pub fn stderr() -> Self {
+        JsonReporter::new(io::stderr())
+    }
+}
+
+impl<W: Write> JsonReporter<W> {
+    /// A reporter writing to `out`.
+    pub fn new(out: W) -> Self {
+        JsonReporter {
+            out: TerminalReporter::new(out),
+        }
+    }
+
+    /// Also write [`Reporter::debug`] messages.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.out = self.out.verbose(verbose);
+        self
+    }
+
+    /// The writer, with everything reported so far.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner()
+    }
+
+    fn emit(&self, value: serde_json::Value) {
+        self.out.write(&format!("{value}\n"));
+    }
+
+    fn message(&self, level: &str, message: &str) {
+        self.emit(json!({ "level": level, "message": message }));
+    }
+}
+
+impl<W: Write + Send> Reporter for JsonReporter<W> {
+    fn info(&self, message: &str) {
+        if !message.is_empty() {
+            self.message("info", message);
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        self.message("warn", message);
+    }
+
+    fn error(&self, message: &str) {
+        self.message("error", message);
+    }
+
+    fn banner(&self, title: &str) {
+        self.message("banner", title);
+    }
+
+    fn progress(&self, message: &str, finished: bool) {
+        self.emit(json!({ "level": "progress", "message": message, "finished": finished }));
+    }
+
+    fn summary(&self, message: &str) {
+        self.message("summary", message);
+    }
+
+    fn debug(&self, message: &str) {
+        if self.out.verbose {
+            self.message("debug", message);
+        }
+    }
+}
+
+/// `--quiet`: passes on only warnings and errors.
+#[derive(Debug)]
+pub struct QuietReporter<R>(pub R);
+
+impl<R: Reporter> Reporter for QuietReporter<R> {
+    fn info(&self, _message: &str) {}
+
+    fn warn(&self, message: &str) {
+        self.0.warn(message);
+    }
+
+    fn error(&self, message: &str) {
+        self.0.error(message);
+    }
+
+    fn banner(&self, _title: &str) {}
+
+    fn progress(&self, _message: &str, _finished: bool) {}
+
+    fn summary(&self, _message: &str) {}
+}
+
+static REPORTER: OnceLock<Box<dyn Reporter>> = OnceLock::new();
+
+/// Install the process-wide reporter. Only the first call has an effect;
+/// until then messages go to a [`TerminalReporter`] on stderr.
+pub fn set(reporter: Box<dyn Reporter>) {
+    let _ = REPORTER.set(reporter);
+}
+
+/// The process-wide reporter.
+pub fn get() -> &'static dyn Reporter {
+    REPORTER
+        .get_or_init(|| Box::new(TerminalReporter::stderr()))
+        .as_ref()
+}
+
+/// [`Reporter::info`] on the process-wide reporter.
+pub fn info(message: &str) {
+    get().info(message);
+}
+
+/// [`Reporter::warn`] on the process-wide reporter.
+pub fn warn(message: &str) {
+    get().warn(message);
+}
+
+/// [`Reporter::error`] on the process-wide reporter.
+pub fn error(message: &str) {
+    get().error(message);
+}
+
+/// [`Reporter::banner`] on the process-wide reporter.
+pub fn banner(title: &str) {
+    get().banner(title);
+}
+
+/// [`Reporter::progress`] on the process-wide reporter.
+pub fn progress(message: &str, finished: bool) {
+    get().progress(message, finished);
+}
+
+/// [`Reporter::summary`] on the process-wide reporter.
+pub fn summary(message: &str) {
+    get().summary(message);
+}
+
+/// [`Reporter::debug`] on the process-wide reporter.
+pub fn debug(message: &str) {
+    get().debug(message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(reporter: &dyn Reporter) {
+        reporter.info("Using AI provider: droid");
+        reporter.info("");
+        reporter.banner("Iteration 1 / 3");
+        reporter.warn("Failed to write session log: disk full");
+        reporter.progress("Downloaded 1/2 bytes…", false);
+        reporter.progress("Downloaded 2/2 bytes…", true);
+        reporter.summary("All tasks complete after 1 iterations.");
+        reporter.error("Provider 'droid' exited with code 3");
+        reporter.debug("Unparsed bd output: {");
+    }
+
+    #[test]
+    fn terminal_output_matches_the_eprintln_format() {
+        let reporter = TerminalReporter::new(Vec::new());
+        exercise(&reporter);
+        let out = String::from_utf8(reporter.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            "Using AI provider: droid\n\
+             \n\
+             ==========================================\n\
+             Iteration 1 / 3\n\
+             ==========================================\n\
+             Warning: Failed to write session log: disk full\n\
+             \rDownloaded 1/2 bytes…\rDownloaded 2/2 bytes…\n\
+             \n\
+             All tasks complete after 1 iterations.\n\
+             Error: Provider 'droid' exited with code 3\n"
+        );
+    }
+
+    #[test]
+    fn progress_without_animation_is_the_final_line() {
+        let reporter = TerminalReporter::new(Vec::new()).animate(false);
+        reporter.progress("Downloaded 1/2 bytes…", false);
+        reporter.progress("Downloaded 2/2 bytes…", true);
+        let out = String::from_utf8(reporter.into_inner()).unwrap();
+        assert_eq!(out, "Downloaded 2/2 bytes…\n");
+    }
+
+    #[test]
+    fn json_output_is_one_object_per_line() {
+        let reporter = JsonReporter::new(Vec::new());
+        exercise(&reporter);
+        let out = String::from_utf8(reporter.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let levels: Vec<&str> = lines.iter().map(|l| l["level"].as_str().unwrap()).collect();
+        assert_eq!(
+            levels,
+            [
+                "info", "banner", "warn", "progress", "progress", "summary", "error"
+            ]
+        );
+        assert_eq!(lines[1]["message"], "Iteration 1 / 3");
+        assert_eq!(lines[4]["finished"], true);
+    }
+
+    #[test]
+    fn debug_messages_need_verbose() {
+        let reporter = TerminalReporter::new(Vec::new()).verbose(true);
+        reporter.debug("Unparsed bd output: {");
+        let out = String::from_utf8(reporter.into_inner()).unwrap();
+        assert_eq!(out, "Debug: Unparsed bd output: {\n");
+
+        let reporter = JsonReporter::new(Vec::new()).verbose(true);
+        reporter.debug("raw");
+        let out = String::from_utf8(reporter.into_inner()).unwrap();
+        assert_eq!(out, "{\"level\":\"debug\",\"message\":\"raw\"}\n");
+
+        let reporter = QuietReporter(TerminalReporter::new(Vec::new()).verbose(true));
+        reporter.debug("raw");
+        assert!(reporter.0.into_inner().is_empty());
+    }
+
+    #[test]
+    fn quiet_keeps_only_warnings_and_errors() {
+        let reporter = QuietReporter(TerminalReporter::new(Vec::new()));
+        exercise(&reporter);
+        let out = String::from_utf8(reporter.0.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            "Warning: Failed to write session log: disk full\n\
+             Error: Provider 'droid' exited with code 3\n"
+        );
+    }
+}
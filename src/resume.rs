@@ -0,0 +1,214 @@
+use crate::error::Result;
+use crate::session::{self, Event, Record, StoredSession};
+use crate::state::LoopState;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+/// What `ralph resume` would continue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumePlan {
+    /// Session being continued
+    pub session_id: String,
+    /// Provider it ran with
+    pub provider: String,
+    /// Its original iteration budget
+    pub max_iterations: u32,
+    /// Iterations that ran to completion
+    pub finished_iterations: u32,
+    /// `session::prompt_hash` of the prompt the session last ran with (with
+    /// `--reload-prompt` it may have changed since the session started)
+    pub prompt_hash: Option<String>,
+    /// Its `--prompt-variant` label, carried over to the resumed session
+    pub prompt_variant: Option<String>,
+    /// Its `--label`, carried over to the resumed session
+    pub label: Option<String>,
+}
+
+impl ResumePlan {
+    /// Iterations left of the original budget.
+    pub fn remaining_iterations(&self) -> u32 {
+        self.max_iterations - self.finished_iterations
+    }
+}
+
+/// The plan for the loop a repository's `.ralph/state.json` records, or
+/// `None` when it completed or spent its budget. Unlike [`find`], this
+/// works on a machine that never ran the loop.
+pub fn from_state(state: &LoopState) -> Option<ResumePlan> {
+    if state.completed || state.remaining_iterations() == 0 {
+        return None;
+    }
+    Some(ResumePlan {
+        session_id: state.session_id.clone(),
+        provider: state.provider.clone(),
+        max_iterations: state.max_iterations,
+        finished_iterations: state.finished_iterations,
+        prompt_hash: state.prompt_hash.clone(),
+        prompt_variant: state.prompt_variant.clone(),
+        label: state.label.clone(),
+    })
+}
+
+/// A session's transcript, read once.
+struct Transcript {
+    id: String,
+    running: bool,
+    records: Vec<Record>,
+}
+
+/// The plan for a `loop` session in `cwd` that stopped before finishing,
+/// or `None` when `records` describe something that can't be resumed:
+/// another directory or command, a completed run, or a spent budget.
+fn plan_for(id: &str, records: &[Record], cwd: &Path) -> Option<ResumePlan> {
+    let (first, rest) = records.split_first()?;
+    let Event::SessionStart {
+        command,
+        provider,
+        max_iterations,
+        cwd: session_cwd,
+        prompt_hash,
+        prompt_variant,
+        label,
+        ..
+    } = &first.event
+    else {
+        return None;
+    };
+    if command != "loop" || session_cwd != cwd {
+        return None;
+    }
+
+    let mut finished = 0;
+    let mut prompt_hash = prompt_hash.clone();
+    for record in rest {
+        match &record.event {
+            Event::IterationStart {
+                prompt_hash: Some(hash),
+                planning: false,
+                ..
+            } => prompt_hash = Some(hash.clone()),
+            Event::IterationEnd { complete: true, .. } => return None,
+            Event::IterationEnd { .. } => finished += 1,
+            Event::SessionEnd {
+                completed: true, ..
+            } => return None,
+            _ => {}
+        }
+    }
+    if finished >= *max_iterations {
+        return None;
+    }
+    Some(ResumePlan {
+        session_id: id.to_string(),
+        provider: provider.clone(),
+        max_iterations: *max_iterations,
+        finished_iterations: finished,
+        prompt_hash,
+        prompt_variant: prompt_variant.clone(),
+        label: label.clone(),
+    })
+}
+
+/// Newest resumable session among `transcripts` (oldest first), skipping
+/// running sessions and ones already resumed.
+fn find_in(transcripts: &[Transcript], cwd: &Path) -> Option<ResumePlan> {
+    let resumed: HashSet<&str> = transcripts
+        .iter()
+        .flat_map(|t| &t.records)
+        .filter_map(|r| match &r.event {
+            Event::Resumed { from } => Some(from.as_str()),
+            _ => None,
+        })
+        .collect();
+    transcripts
+        .iter()
+        .rev()
+        .filter(|t| !t.running && !resumed.contains(t.id.as_str()))
+        .find_map(|t| plan_for(&t.id, &t.records, cwd))
+}
+
+/// The most recent interrupted or failed `loop` session started in `cwd`.
+pub fn find(cwd: &Path) -> Result<Option<ResumePlan>> {
+    let transcripts = session::list_sessions()?
+        .into_iter()
+        .map(|s: StoredSession| {
+            Ok(Transcript {
+                running: s.is_running(),
+                records: s.records()?,
+                id: s.id,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(find_in(&transcripts, cwd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript(id: &str, lines: &[&str]) -> Transcript {
+        let start = format!(
+            r#"{{"ts":"t","event":"session_start","session":"{id}","command":"loop","provider":"claude","max_iterations":5,"cwd":"/repo","prompt_hash":"h"}}"#
+        );
+        let records = std::iter::once(start.as_str())
+            .chain(lines.iter().copied())
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        Transcript {
+            id: id.to_string(),
+            running: false,
+            records,
+        }
+    }
+
+    const ITERATION_DONE: &str =
+        r#"{"ts":"t","event":"iteration_end","iteration":1,"exit_code":0,"complete":false}"#;
+    const ITERATION_COMPLETE: &str =
+        r#"{"ts":"t","event":"iteration_end","iteration":1,"exit_code":0,"complete":true}"#;
+
+    #[test]
+    fn interrupted_loop_resumes_with_remaining_budget() {
+        let t = transcript("a", &[ITERATION_DONE, ITERATION_DONE]);
+        let plan = find_in(&[t], Path::new("/repo")).unwrap();
+        assert_eq!(plan.session_id, "a");
+        assert_eq!(plan.remaining_iterations(), 3);
+        assert_eq!(plan.prompt_hash.as_deref(), Some("h"));
+    }
+
+    #[test]
+    fn a_reloaded_prompt_is_the_one_resumed_with() {
+        let reloaded = r#"{"ts":"t","event":"iteration_start","iteration":2,"prompt_hash":"h2"}"#;
+        let t = transcript("a", &[ITERATION_DONE, reloaded, ITERATION_DONE]);
+        let plan = find_in(&[t], Path::new("/repo")).unwrap();
+        assert_eq!(plan.prompt_hash.as_deref(), Some("h2"));
+    }
+
+    #[test]
+    fn completed_or_exhausted_sessions_are_not_resumable() {
+        let complete = transcript("a", &[ITERATION_COMPLETE]);
+        let exhausted = transcript("b", &[ITERATION_DONE; 5]);
+        assert_eq!(find_in(&[complete, exhausted], Path::new("/repo")), None);
+    }
+
+    #[test]
+    fn other_directories_running_and_resumed_sessions_are_skipped() {
+        let old = transcript("a", &[ITERATION_DONE]);
+        let resumer = transcript(
+            "b",
+            &[
+                r#"{"ts":"t","event":"resumed","from":"a"}"#,
+                ITERATION_COMPLETE,
+            ],
+        );
+        let mut running = transcript("c", &[]);
+        running.running = true;
+        let sessions = [old, resumer, running];
+        assert_eq!(find_in(&sessions, Path::new("/repo")), None);
+        assert_eq!(find_in(&sessions[..1], Path::new("/elsewhere")), None);
+        assert_eq!(
+            find_in(&sessions[..1], Path::new("/repo")).map(|p| p.session_id),
+            Some("a".to_string())
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::beads::Issue;
+use crate::error::{RalphError, Result};
+use crate::exec::CommandRunner;
+use crate::git;
+use crate::providers;
+use crate::reporter;
+use crate::runner;
+use crate::session::{self, Event};
+use crate::tracker::Tracker;
+use crate::usage;
+
+/// Exit code for `ralph run` when the provider finished but the task is
+/// still open, so scripts can retry: `EX_TEMPFAIL`, which nothing else
+/// returns (clap's usage errors are 2).
+pub const EXIT_TASK_OPEN: u8 = 75;
+
+/// Result of working a single task.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TaskOutcome {
+    /// bd reports the task closed after the provider run
+    Closed,
+    /// The task is still open (with its current status)
+    StillOpen {
+        /// bd status after the provider finished
+        status: String,
+    },
+}
+
+/// Append the task's details to the system prompt so the agent works on
+/// exactly this issue.
+pub fn build_task_prompt(system_prompt: &str, issue: &Issue) -> String {
+    let mut prompt = system_prompt.trim_end().to_string();
+    prompt.push_str("\n\n## Assigned task\n\n");
+    prompt.push_str(&format!(
+        "Work only on this task: {} — {}\nIt has already been marked in_progress for you.\n",
+        issue.id, issue.title
+    ));
+    if !issue.description.trim().is_empty() {
+        prompt.push_str("\n### Description\n\n");
+        prompt.push_str(issue.description.trim());
+        prompt.push('\n');
+    }
+    if !issue.acceptance_criteria.trim().is_empty() {
+        prompt.push_str("\n### Acceptance criteria\n\n");
+        prompt.push_str(issue.acceptance_criteria.trim());
+        prompt.push('\n');
+    }
+    prompt
+}
+
+/// Check that an issue can be worked on right now.
+pub fn check_workable(issue: &Issue) -> Result<()> {
+    if issue.is_closed() {
+        return Err(RalphError::InvalidInput(format!(
+            "Task {} is already closed",
+            issue.id
+        )));
+    }
+    if issue.is_blocked() {
+        let blockers = issue.open_blockers();
+        return Err(RalphError::InvalidInput(if blockers.is_empty() {
+            format!("Task {} is blocked", issue.id)
+        } else {
+            format!("Task {} is blocked by: {}", issue.id, blockers.join(", "))
+        }));
+    }
+    Ok(())
+}
+
+/// Fetch, claim, and execute a single task from `tracker` with `provider`,
+/// then report whether the task ended up closed. The tracker and the
+/// provider run through `exec`.
+pub fn run_task(
+    exec: &dyn CommandRunner,
+    tracker: &dyn Tracker,
+    provider: &str,
+    id: &str,
+    system_prompt: &str,
+) -> Result<TaskOutcome> {
+    let issue = tracker.show(exec, id)?;
+    check_workable(&issue)?;
+
+    tracker.update_status(exec, &issue.id, "in_progress")?;
+    reporter::info(&format!("Working on {}: {}", issue.id, issue.title));
+
+    let prompt = build_task_prompt(system_prompt, &issue);
+
+    let mut session = runner::start_session("run", provider, 1, &prompt);
+    let log_path = session.as_ref().map(|s| s.iteration_log_path(1));
+    runner::record(
+        &mut session,
+        Event::IterationStart {
+            iteration: 1,
+            prompt_hash: Some(session::prompt_hash(&prompt)),
+            planning: false,
+        },
+    );
+
+    providers::announce(provider);
+    let output = providers::execute(
+        exec,
+        provider,
+        &prompt,
+        log_path.as_deref(),
+        &runner::interrupt_token(),
+    )?;
+    runner::report_termination(&output, None);
+
+    let after = tracker.show(exec, &issue.id)?;
+    runner::record(
+        &mut session,
+        Event::IterationEnd {
+            iteration: 1,
+            exit_code: output.code,
+            signal: output.signal,
+            complete: output.stdout.contains(runner::COMPLETE_MARKER),
+            usage: usage::extract(&output.stdout),
+            model: usage::model(&output.stdout),
+            head: git::head(),
+            output_limit: output.output_limit,
+            timeout: output.timed_out.map(|t| t.as_secs()),
+        },
+    );
+    if after.is_closed() {
+        runner::record(
+            &mut session,
+            Event::TaskClosed {
+                id: issue.id.clone(),
+            },
+        );
+    }
+    runner::record(
+        &mut session,
+        Event::SessionEnd {
+            iterations: 1,
+            completed: after.is_closed(),
+        },
+    );
+
+    Ok(if after.is_closed() {
+        TaskOutcome::Closed
+    } else {
+        TaskOutcome::StillOpen {
+            status: after.status,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue(status: &str) -> Issue {
+        Issue {
+            id: "bd-7".to_string(),
+            title: "Fix the thing".to_string(),
+            description: "It is broken.".to_string(),
+            acceptance_criteria: "- It works".to_string(),
+            status: status.to_string(),
+            ..Issue::default()
+        }
+    }
+
+    #[test]
+    fn task_prompt_includes_issue_details() {
+        let prompt = build_task_prompt("SYSTEM\n", &issue("open"));
+        assert!(prompt.starts_with("SYSTEM\n\n## Assigned task"));
+        assert!(prompt.contains("bd-7 — Fix the thing"));
+        assert!(prompt.contains("### Description\n\nIt is broken."));
+        assert!(prompt.contains("### Acceptance criteria\n\n- It works"));
+    }
+
+    #[test]
+    fn task_prompt_omits_empty_sections() {
+        let mut i = issue("open");
+        i.acceptance_criteria.clear();
+        let prompt = build_task_prompt("SYSTEM", &i);
+        assert!(!prompt.contains("Acceptance criteria"));
+    }
+
+    #[test]
+    fn closed_and_blocked_tasks_are_refused() {
+        assert!(check_workable(&issue("open")).is_ok());
+        assert!(check_workable(&issue("in_progress")).is_ok());
+        assert!(
+            check_workable(&issue("closed"))
+                .unwrap_err()
+                .to_string()
+                .contains("already closed")
+        );
+        assert!(
+            check_workable(&issue("blocked"))
+                .unwrap_err()
+                .to_string()
+                .contains("blocked")
+        );
+    }
+}
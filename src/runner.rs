@@ -0,0 +1,1004 @@
+use crate::audit::{self, Kind};
+use crate::config;
+use crate::error::{RalphError, Result};
+use crate::exec::{CancelToken, CommandRunner, Output, SystemRunner};
+use crate::git;
+use crate::log_target;
+use crate::metrics::LoopMetrics;
+use crate::progress::{IterationReport, ProgressReport};
+use crate::prompt;
+use crate::providers;
+use crate::render;
+use crate::reporter;
+use crate::session::{self, Event, Session};
+use crate::state::Checkpoint;
+use crate::usage::{self, Usage};
+use chrono::Local;
+use std::process::Command;
+use std::sync::{LazyLock, Once};
+use std::time::Instant;
+
+/// The COMPLETE marker that signals the loop should end early.
+pub const COMPLETE_MARKER: &str = "<promise>COMPLETE</promise>";
+
+/// Exit code of `once`, `loop` and `resume` when Ctrl-C (or SIGTERM)
+/// stopped them, as a shell reports a command killed by SIGINT.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// What to run: the provider, the prompt, and the iteration budget.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Provider name, one of [`providers::VALID_PROVIDERS`]
+    pub provider: String,
+    /// System prompt passed to every iteration
+    pub prompt: String,
+    /// Iteration budget for [`run_loop`]; [`run_once`] ignores it
+    pub max_iterations: u32,
+    /// Session this loop continues, recorded in the new transcript
+    pub resumed_from: Option<String>,
+}
+
+impl RunOptions {
+    /// Options for `provider` and `prompt` with the default iteration budget.
+    pub fn new(provider: impl Into<String>, prompt: impl Into<String>) -> Self {
+        RunOptions {
+            provider: provider.into(),
+            prompt: prompt.into(),
+            max_iterations: config::DEFAULT_ITERATIONS,
+            resumed_from: None,
+        }
+    }
+}
+
+/// Outcome of a [`run_once`] or [`run_loop`] call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    /// Id of the session the run was recorded under, if logging worked
+    pub session_id: Option<String>,
+    /// Iterations actually run
+    pub iterations: u32,
+    /// Whether the run finished its work: the COMPLETE marker for a loop,
+    /// a zero exit code for a single run
+    pub completed: bool,
+    /// Exit code of the last provider invocation
+    pub exit_code: i32,
+    /// Signal that killed the last provider invocation, if one did
+    pub signal: Option<i32>,
+    /// Iterations whose provider exited non-zero
+    pub failures: u32,
+    /// Iterations whose provider was killed by a signal, counted apart
+    /// from [`failures`](RunSummary::failures)
+    pub killed: u32,
+    /// Iterations whose provider was killed for printing more than the
+    /// output limit (see [`stream::set_max_output`](crate::stream::set_max_output)),
+    /// counted apart from [`killed`](RunSummary::killed)
+    pub over_limit: u32,
+    /// Iterations whose provider was killed for running longer than the
+    /// timeout (see [`stream::set_timeout`](crate::stream::set_timeout)),
+    /// counted apart from [`killed`](RunSummary::killed)
+    pub timed_out: u32,
+    /// Whether the loop stopped after a failed iteration rather than go on
+    /// (see [`LoopRunnerBuilder::stop_on_failure`])
+    pub stopped_on_failure: bool,
+    /// Whether the run was cancelled (by Ctrl-C, unless the loop was given
+    /// another [`cancel_token`](LoopRunnerBuilder::cancel_token)); the
+    /// provider was stopped and the remaining iterations skipped
+    pub interrupted: bool,
+    /// Token usage summed over the iterations that reported any
+    pub usage: Option<Usage>,
+    /// Whether the first iteration was a planning iteration (see
+    /// [`LoopRunnerBuilder::plan_prompt`]); it's included in
+    /// [`iterations`](RunSummary::iterations)
+    pub planned: bool,
+}
+
+/// Start a session transcript. Logging is best-effort: on failure a warning
+/// is printed and the run continues without a session. Messages sent to a
+/// `--log-target` name the session and provider from now on.
+pub fn start_session(
+    command: &str,
+    provider: &str,
+    max_iterations: u32,
+    prompt: &str,
+) -> Option<Session> {
+    log_target::update_context(|c| {
+        c.session = None;
+        c.provider = Some(provider.to_string());
+        c.iteration = None;
+    });
+    match Session::start(command, provider, max_iterations, prompt) {
+        Ok(session) => {
+            log_target::update_context(|c| c.session = Some(session.id.clone()));
+            Some(session)
+        }
+        Err(e) => {
+            reporter::warn(&format!("Failed to start session log: {}", e));
+            None
+        }
+    }
+}
+
+/// Append an event to the session transcript, if there is one.
+pub fn record(session: &mut Option<Session>, event: Event) {
+    if let Event::IterationStart { iteration, .. } = event {
+        log_target::update_context(|c| c.iteration = Some(iteration));
+    }
+    if let Some(s) = session.as_mut()
+        && let Err(e) = s.record(event)
+    {
+        reporter::warn(&format!("Failed to write session log: {}", e));
+    }
+}
+
+/// Cancelled by the Ctrl-C handler once installed.
+static INTERRUPT: LazyLock<CancelToken> = LazyLock::new(CancelToken::new);
+
+/// Install a Ctrl-C handler that cancels [`interrupt_token`] instead of
+/// exiting, so long-running commands can stop between steps. SIGTERM and
+/// SIGHUP (a closed terminal) are handled the same way on unix. Providers
+/// run in their own process group and don't see the terminal's Ctrl-C; the
+/// streaming code terminates them, with everything they started, when the
+/// token is cancelled. Calling this again is a no-op.
+pub fn install_interrupt_handler() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        if let Err(e) = ctrlc::set_handler(|| INTERRUPT.cancel()) {
+            reporter::warn(&format!("Failed to install Ctrl-C handler: {}", e));
+        }
+    });
+}
+
+/// The token Ctrl-C cancels; the default for [`LoopRunner`] runs.
+pub fn interrupt_token() -> CancelToken {
+    INTERRUPT.clone()
+}
+
+/// Whether Ctrl-C has been pressed since the handler was installed.
+pub fn interrupted() -> bool {
+    INTERRUPT.is_cancelled()
+}
+
+/// Run the provider once, recording a `once` session.
+pub fn run_once(opts: &RunOptions) -> Result<RunSummary> {
+    run_once_with(&SystemRunner, opts)
+}
+
+/// [`run_once`] with the provider executed through `exec`.
+pub fn run_once_with(exec: &dyn CommandRunner, opts: &RunOptions) -> Result<RunSummary> {
+    providers::announce(&opts.provider);
+
+    let mut session = start_session("once", &opts.provider, 1, &opts.prompt);
+    let log_path = session.as_ref().map(|s| s.iteration_log_path(1));
+    record(
+        &mut session,
+        Event::IterationStart {
+            iteration: 1,
+            prompt_hash: Some(session::prompt_hash(&opts.prompt)),
+            planning: false,
+        },
+    );
+
+    let output = providers::execute(
+        exec,
+        &opts.provider,
+        &opts.prompt,
+        log_path.as_deref(),
+        &interrupt_token(),
+    )?;
+    let interrupted = interrupted();
+    match interrupted {
+        true => reporter::warn("Interrupted; the provider was stopped"),
+        false => report_termination(&output, None),
+    }
+    let usage = usage::extract(&output.stdout);
+    record(
+        &mut session,
+        Event::IterationEnd {
+            iteration: 1,
+            exit_code: output.code,
+            signal: output.signal,
+            complete: false,
+            usage,
+            model: usage::model(&output.stdout),
+            head: git::head(),
+            output_limit: output.output_limit,
+            timeout: output.timed_out.map(|t| t.as_secs()),
+        },
+    );
+    record(
+        &mut session,
+        Event::SessionEnd {
+            iterations: 1,
+            completed: output.success(),
+        },
+    );
+
+    Ok(RunSummary {
+        session_id: session.as_ref().map(|s| s.id.clone()),
+        iterations: 1,
+        completed: output.success(),
+        exit_code: output.code,
+        signal: output.signal,
+        failures: u32::from(output.signal.is_none() && output.code != 0),
+        killed: u32::from(
+            output.signal.is_some() && output.output_limit.is_none() && output.timed_out.is_none(),
+        ),
+        over_limit: u32::from(output.output_limit.is_some()),
+        timed_out: u32::from(output.timed_out.is_some()),
+        stopped_on_failure: false,
+        interrupted,
+        usage,
+        planned: false,
+    })
+}
+
+/// Warn when the provider was killed by a signal rather than exiting, so
+/// an OOM kill doesn't pass for an ordinary failure, or when it exited
+/// non-zero, quoting the last line it wrote to stderr.
+pub fn report_termination(output: &Output, iteration: Option<u32>) {
+    let termination = match (output.termination(), output.stderr_tail()) {
+        (Some(termination), _) => termination,
+        _ if output.code == 0 => return,
+        (None, Some(tail)) => format!("exited with code {}: {}", output.code, tail),
+        (None, None) => format!("exited with code {}", output.code),
+    };
+    match iteration {
+        Some(i) => reporter::warn(&format!("Iteration {i}: provider {termination}")),
+        None => reporter::warn(&format!("Provider {termination}")),
+    }
+}
+
+/// Run the provider up to `max_iterations` times, stopping early when the
+/// output contains the COMPLETE marker or Ctrl-C was pressed. Records a
+/// `loop` session, noting the session it continues when `resumed_from` is
+/// set. A shorthand for [`LoopRunner`].
+pub fn run_loop(opts: &RunOptions) -> Result<RunSummary> {
+    run_loop_with(&SystemRunner, opts)
+}
+
+/// [`run_loop`] with the provider executed through `exec`.
+pub fn run_loop_with(exec: &dyn CommandRunner, opts: &RunOptions) -> Result<RunSummary> {
+    let mut builder = LoopRunner::builder()
+        .provider(opts.provider.as_str())
+        .prompt(opts.prompt.as_str())
+        .iterations(opts.max_iterations)
+        .exec(exec);
+    if let Some(from) = &opts.resumed_from {
+        builder = builder.resumed_from(from.as_str());
+    }
+    builder.run()
+}
+
+/// Outcome of a [`LoopRunner`] run.
+pub type LoopSummary = RunSummary;
+
+type EventCallback<'a> = Box<dyn FnMut(&Event) + 'a>;
+type PromptSource<'a> = Box<dyn FnMut() -> Result<String> + 'a>;
+
+/// A configured `ralph loop`, built with [`LoopRunner::builder`]. This is
+/// what the CLI's `loop`, `resume` and `watch` run, so an embedding tool
+/// gets exactly the same behaviour: the same messages, session transcript
+/// and stopping rules.
+///
+/// ```no_run
+/// use ralph::runner::LoopRunner;
+///
+/// let summary = LoopRunner::builder()
+///     .provider("claude")
+///     .prompt("Work through `bd ready`. Say <promise>COMPLETE</promise> when done.")
+///     .iterations(10)
+///     .gate("cargo test")
+///     .on_event(|event| eprintln!("{event:?}"))
+///     .run()?;
+/// println!("{} iterations, complete: {}", summary.iterations, summary.completed);
+/// # Ok::<(), ralph::RalphError>(())
+/// ```
+pub struct LoopRunner<'a> {
+    provider: String,
+    prompt: Option<String>,
+    max_iterations: u32,
+    marker: String,
+    inject_marker_instructions: bool,
+    gates: Vec<String>,
+    resumed_from: Option<String>,
+    on_event: Option<EventCallback<'a>>,
+    reload_prompt: Option<PromptSource<'a>>,
+    plan_prompt: Option<String>,
+    plan_only: bool,
+    carry_plan: bool,
+    stop_on_failure: bool,
+    cancel: CancelToken,
+    exec: &'a dyn CommandRunner,
+    progress: Option<&'a mut ProgressReport>,
+    metrics: Option<&'a mut LoopMetrics>,
+    checkpoint: Option<&'a mut Checkpoint>,
+}
+
+/// Builder for [`LoopRunner`]. Only the prompt is required; everything
+/// else defaults to what a bare `ralph loop` would use.
+///
+/// ```
+/// use ralph::exec::{CancelToken, ScriptedRunner};
+/// use ralph::runner::LoopRunner;
+///
+/// // A fake provider that finishes on its second run.
+/// let exec = ScriptedRunner::new()
+///     .reply(0, "still working\n")
+///     .reply(0, "<promise>DONE</promise>\n");
+/// let cancel = CancelToken::new();
+/// let mut seen = 0;
+/// # let home = tempfile::tempdir().unwrap();
+/// # unsafe { std::env::set_var("RALPH_HOME", home.path()) };
+///
+/// let summary = LoopRunner::builder()
+///     .provider("codex")
+///     .prompt("Fix the build, then say <promise>DONE</promise>")
+///     .marker("<promise>DONE</promise>")
+///     .iterations(5)
+///     .exec(&exec)
+///     .cancel_token(cancel.clone())
+///     .on_event(|_| seen += 1)
+///     .run()?;
+///
+/// assert_eq!(summary.iterations, 2);
+/// assert!(summary.completed);
+/// // Two starts, two ends, and the session end
+/// assert_eq!(seen, 5);
+/// # Ok::<(), ralph::RalphError>(())
+/// ```
+pub struct LoopRunnerBuilder<'a> {
+    runner: LoopRunner<'a>,
+}
+
+impl<'a> LoopRunner<'a> {
+    /// Start configuring a loop.
+    pub fn builder() -> LoopRunnerBuilder<'a> {
+        LoopRunnerBuilder {
+            runner: LoopRunner {
+                provider: config::DEFAULT_PROVIDER.to_string(),
+                prompt: None,
+                max_iterations: config::DEFAULT_ITERATIONS,
+                marker: COMPLETE_MARKER.to_string(),
+                inject_marker_instructions: true,
+                gates: Vec::new(),
+                resumed_from: None,
+                on_event: None,
+                reload_prompt: None,
+                plan_prompt: None,
+                plan_only: false,
+                carry_plan: false,
+                stop_on_failure: false,
+                cancel: interrupt_token(),
+                exec: &SystemRunner,
+                progress: None,
+                metrics: None,
+                checkpoint: None,
+            },
+        }
+    }
+
+    /// Run the loop: up to `iterations` provider runs, stopping early once
+    /// an iteration prints the marker and every gate passes, or when the
+    /// cancel token is cancelled (which also terminates a running provider).
+    pub fn run(mut self) -> Result<LoopSummary> {
+        let max_iterations = self.max_iterations;
+        providers::announce(&self.provider);
+        reporter::info(&format!("Max iterations: {}", max_iterations));
+        reporter::info("");
+
+        let mut prompt = self.prompt.take().unwrap_or_default();
+        if !prompt.contains(&self.marker) {
+            reporter::warn(&match self.inject_marker_instructions {
+                true => format!(
+                    "The prompt never mentions the completion marker {}; appending instructions for it",
+                    self.marker
+                ),
+                false => format!(
+                    "The prompt never mentions the completion marker {}, so the loop will likely run all {} iterations",
+                    self.marker, max_iterations
+                ),
+            });
+        }
+        let mut session = start_session("loop", &self.provider, max_iterations, &prompt);
+        let session_id = session.as_ref().map(|s| s.id.as_str());
+        if let Some(progress) = self.progress.as_mut() {
+            progress.start(session_id);
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.start(session_id);
+        }
+        if let (Some(checkpoint), Some(session)) = (self.checkpoint.as_mut(), &session) {
+            checkpoint.start(
+                &session.id,
+                &self.provider,
+                max_iterations,
+                &session::prompt_hash(&prompt),
+                session::prompt_variant(),
+                session::label(),
+            );
+        }
+        if let Some(from) = self.resumed_from.take() {
+            self.emit(&mut session, Event::Resumed { from });
+        }
+        let mut completed_early = false;
+        let mut final_iteration = 0;
+        let mut exit_code = 0;
+        let mut signal = None;
+        let mut failures = 0;
+        let mut killed = 0;
+        let mut over_limit = 0;
+        let mut timed_out = 0;
+        let mut stopped_on_failure = false;
+        let mut interrupted = false;
+        let mut total_usage: Option<Usage> = None;
+        let plan_prompt = self.plan_prompt.take();
+        let planned = plan_prompt.is_some();
+        let mut plan: Option<String> = None;
+        let mut plan_only = false;
+        let mut closing = String::new();
+
+        for i in 1..=max_iterations {
+            if self.cancel.is_cancelled() {
+                interrupted = true;
+                break;
+            }
+            final_iteration = i;
+            let planning = i == 1 && planned;
+            log_target::update_context(|c| c.iteration = Some(i));
+            if planning {
+                reporter::banner(&format!("Iteration {} / {} (planning)", i, max_iterations));
+            } else {
+                reporter::banner(&format!("Iteration {} / {}", i, max_iterations));
+            }
+            if i > 1 {
+                self.reload_prompt(&mut prompt);
+            }
+            let iteration_prompt = match (&plan_prompt, plan.take()) {
+                (Some(plan_prompt), _) if planning => plan_prompt.clone(),
+                (_, Some(plan)) => with_plan(&prompt, &plan),
+                _ => prompt.clone(),
+            };
+            // After any carried plan, which may quote the marker itself.
+            let iteration_prompt = match !planning
+                && self.inject_marker_instructions
+                && !prompt.contains(&self.marker)
+            {
+                true => format!(
+                    "{}{}",
+                    iteration_prompt.trim_end(),
+                    prompt::marker_instructions(&self.marker)
+                ),
+                false => iteration_prompt,
+            };
+
+            self.emit(
+                &mut session,
+                Event::IterationStart {
+                    iteration: i,
+                    prompt_hash: Some(session::prompt_hash(match &plan_prompt {
+                        Some(plan_prompt) if planning => plan_prompt,
+                        _ => &prompt,
+                    })),
+                    planning,
+                },
+            );
+            let log_path = session.as_ref().map(|s| s.iteration_log_path(i));
+            let started = (Local::now(), Instant::now());
+
+            let output = providers::execute(
+                self.exec,
+                &self.provider,
+                &iteration_prompt,
+                log_path.as_deref(),
+                &self.cancel,
+            )?;
+            interrupted = self.cancel.is_cancelled();
+            if !interrupted {
+                report_termination(&output, Some(i));
+            }
+            // A planning iteration writes no code for gates to check, and
+            // isn't the one to declare the work done.
+            let marker_seen = !planning && output.stdout.contains(&self.marker);
+            let duration = started.1.elapsed();
+            let gates = match planning || interrupted {
+                true => Vec::new(),
+                false => self.run_gates(&mut session, i),
+            };
+            let gates_passed = gates.iter().all(|(_, code)| *code == 0);
+            if marker_seen && !gates_passed {
+                reporter::warn("Completion marker ignored because a gate failed");
+            }
+            let complete = marker_seen && gates_passed;
+            let usage = usage::extract(&output.stdout);
+            let model = usage::model(&output.stdout);
+            exit_code = output.code;
+            signal = output.signal;
+            if interrupted {
+                // Stopped, not failed: counted as neither.
+            } else if output.timed_out.is_some() {
+                timed_out += 1;
+            } else if output.output_limit.is_some() {
+                over_limit += 1;
+            } else if output.signal.is_some() {
+                killed += 1;
+            } else if output.code != 0 {
+                failures += 1;
+            }
+            if let Some(usage) = usage {
+                total_usage.get_or_insert_with(Usage::default).add(usage);
+            }
+            self.emit(
+                &mut session,
+                Event::IterationEnd {
+                    iteration: i,
+                    exit_code: output.code,
+                    signal: output.signal,
+                    complete,
+                    usage,
+                    model: model.clone(),
+                    head: git::head(),
+                    output_limit: output.output_limit,
+                    timeout: output.timed_out.map(|t| t.as_secs()),
+                },
+            );
+            if let Some(checkpoint) = self.checkpoint.as_mut() {
+                let hash = (!planning).then(|| session::prompt_hash(&prompt));
+                checkpoint.iteration(i, hash, marker_seen, complete);
+            }
+            if self.progress.is_some() || self.metrics.is_some() {
+                let report = IterationReport {
+                    iteration: i,
+                    planning,
+                    started: Some(started.0),
+                    duration,
+                    exit_code: output.code,
+                    signal: output.signal,
+                    complete,
+                    gates,
+                    usage,
+                    model,
+                    output: output.stdout.clone(),
+                };
+                if let Some(progress) = self.progress.as_mut() {
+                    progress.iteration(&report);
+                }
+                if let Some(metrics) = self.metrics.as_mut() {
+                    metrics.iteration(&report);
+                }
+            }
+
+            if complete {
+                closing = format!(
+                    "All tasks complete after {} iterations{}.",
+                    i,
+                    iteration_note(planned, 0, 0, 0, 0)
+                );
+                reporter::summary(&closing);
+                completed_early = true;
+                break;
+            }
+            if interrupted {
+                break;
+            }
+            if planning && self.plan_only {
+                plan_only = true;
+                break;
+            }
+            if self.stop_on_failure && !output.success() && !self.cancel.is_cancelled() {
+                stopped_on_failure = true;
+                break;
+            }
+            if planning && self.carry_plan {
+                let text = render::assistant_text(&output.stdout);
+                if text.is_empty() {
+                    reporter::warn("The planning iteration produced no plan to carry over");
+                } else {
+                    plan = Some(text);
+                }
+            }
+        }
+
+        if interrupted && !completed_early {
+            closing = match final_iteration {
+                0 => "Ralph loop interrupted before the first iteration".to_string(),
+                n => format!(
+                    "Ralph loop interrupted at iteration {}{}; skipping the remaining iterations",
+                    n,
+                    iteration_note(planned, failures, killed, over_limit, timed_out)
+                ),
+            };
+            reporter::summary(&closing);
+        } else if plan_only {
+            closing = format!(
+                "Planning iteration finished{}; stopping before any work (--plan-only)",
+                iteration_note(false, failures, killed, over_limit, timed_out)
+            );
+            reporter::summary(&closing);
+        } else if stopped_on_failure {
+            closing = format!(
+                "Ralph loop stopped after {} iterations{}: the last one failed (--stop-on-failure)",
+                final_iteration,
+                iteration_note(planned, failures, killed, over_limit, timed_out)
+            );
+            reporter::summary(&closing);
+        } else if !completed_early {
+            closing = format!(
+                "Ralph loop finished after {} iterations{}",
+                final_iteration,
+                iteration_note(planned, failures, killed, over_limit, timed_out)
+            );
+            reporter::summary(&closing);
+        }
+        if let Some(progress) = self.progress.as_mut() {
+            progress.finish(&closing);
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.finish(completed_early);
+        }
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            checkpoint.finish(completed_early);
+        }
+        self.emit(
+            &mut session,
+            Event::SessionEnd {
+                iterations: final_iteration,
+                completed: completed_early,
+            },
+        );
+
+        Ok(LoopSummary {
+            session_id: session.as_ref().map(|s| s.id.clone()),
+            iterations: final_iteration,
+            completed: completed_early,
+            exit_code,
+            signal,
+            failures,
+            killed,
+            over_limit,
+            timed_out,
+            stopped_on_failure,
+            interrupted: interrupted && !completed_early,
+            usage: total_usage,
+            planned,
+        })
+    }
+
+    /// Re-read the prompt into `prompt` when the loop was built with
+    /// [`reload_prompt`](LoopRunnerBuilder::reload_prompt), saying so when it
+    /// changed. If it can't be read, the previous prompt is kept.
+    fn reload_prompt(&mut self, prompt: &mut String) {
+        let Some(reload) = self.reload_prompt.as_mut() else {
+            return;
+        };
+        match reload() {
+            Ok(new) if new != *prompt => {
+                reporter::info(&format!(
+                    "System prompt changed ({})",
+                    prompt_change(prompt, &new)
+                ));
+                *prompt = new;
+            }
+            Ok(_) => {}
+            Err(e) => reporter::warn(&format!(
+                "Failed to reload the system prompt, keeping the previous one: {}",
+                e
+            )),
+        }
+    }
+
+    /// Run every gate after iteration `iteration`, recording each result.
+    /// Returns each gate's command and exit code.
+    fn run_gates(&mut self, session: &mut Option<Session>, iteration: u32) -> Vec<(String, i32)> {
+        let mut results = Vec::new();
+        for command in self.gates.clone() {
+            let exit_code = match audit::run(Kind::Gate, &mut shell(&command), |cmd| {
+                self.exec.output(cmd)
+            }) {
+                Ok(output) => output.code,
+                Err(e) => {
+                    reporter::warn(&format!("Failed to run gate `{}`: {}", command, e));
+                    -1
+                }
+            };
+            if exit_code == 0 {
+                reporter::info(&format!("Gate passed: {}", command));
+            } else {
+                reporter::warn(&format!("Gate failed (exit {}): {}", exit_code, command));
+            }
+            self.emit(
+                session,
+                Event::Gate {
+                    iteration,
+                    command: command.clone(),
+                    exit_code,
+                },
+            );
+            results.push((command, exit_code));
+        }
+        results
+    }
+
+    /// Record `event` in the transcript and hand it to the callback.
+    fn emit(&mut self, session: &mut Option<Session>, event: Event) {
+        if let Some(callback) = self.on_event.as_mut() {
+            callback(&event);
+        }
+        record(session, event);
+    }
+}
+
+/// " (2 failed, 1 killed by a signal)" for the loop's closing line, or
+/// nothing when every iteration exited 0.
+fn iteration_note(
+    planned: bool,
+    failures: u32,
+    killed: u32,
+    over_limit: u32,
+    timed_out: u32,
+) -> String {
+    let mut parts = Vec::new();
+    if planned {
+        parts.push("1 planning".to_string());
+    }
+    if failures > 0 {
+        parts.push(format!("{failures} failed"));
+    }
+    if killed > 0 {
+        parts.push(format!("{killed} killed by a signal"));
+    }
+    if over_limit > 0 {
+        parts.push(format!("{over_limit} over the output limit"));
+    }
+    if timed_out > 0 {
+        parts.push(format!("{timed_out} timed out"));
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!(" ({})", parts.join(", "))
+}
+
+/// `prompt` with the planning iteration's `plan` added, for the iteration
+/// after it.
+fn with_plan(prompt: &str, plan: &str) -> String {
+    format!(
+        "{}\n\n## Plan from the planning iteration\n\n{}\n",
+        prompt.trim_end(),
+        plan
+    )
+}
+
+/// "1a2b3c4d -> 5e6f7a8b, +2 -1 lines": the prompt's hash before and after
+/// a reload, and how many lines appeared and disappeared.
+fn prompt_change(old: &str, new: &str) -> String {
+    let short = |prompt: &str| session::prompt_hash(prompt)[..8].to_string();
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let added = new_lines.iter().filter(|l| !old_lines.contains(l)).count();
+    let removed = old_lines.iter().filter(|l| !new_lines.contains(l)).count();
+    format!(
+        "{} -> {}, +{} -{} lines",
+        short(old),
+        short(new),
+        added,
+        removed
+    )
+}
+
+/// `command` run through the platform shell.
+fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    providers::set_label_env(&mut cmd);
+    cmd
+}
+
+impl<'a> LoopRunnerBuilder<'a> {
+    /// Provider to run (default: droid).
+    pub fn provider(mut self, provider: impl Into<String>) -> Self {
+        self.runner.provider = provider.into();
+        self
+    }
+
+    /// System prompt passed to every iteration.
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.runner.prompt = Some(prompt.into());
+        self
+    }
+
+    /// Iteration budget (default: 10).
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.runner.max_iterations = iterations;
+        self
+    }
+
+    /// Text whose appearance in the provider's output ends the loop
+    /// (default: [`COMPLETE_MARKER`]).
+    pub fn marker(mut self, marker: impl Into<String>) -> Self {
+        self.runner.marker = marker.into();
+        self
+    }
+
+    /// Whether to append instructions for printing the marker to a prompt
+    /// that never mentions it (default: true; see
+    /// [`prompt::with_marker_instructions`]). Without them the loop warns
+    /// that it will likely run its whole budget.
+    pub fn inject_marker_instructions(mut self, inject: bool) -> Self {
+        self.runner.inject_marker_instructions = inject;
+        self
+    }
+
+    /// A shell command run after every iteration, such as `cargo test`.
+    /// The marker only ends the loop when every gate exits 0. May be given
+    /// more than once.
+    pub fn gate(mut self, command: impl Into<String>) -> Self {
+        self.runner.gates.push(command.into());
+        self
+    }
+
+    /// The interrupted session this loop continues, noted in the transcript.
+    pub fn resumed_from(mut self, session_id: impl Into<String>) -> Self {
+        self.runner.resumed_from = Some(session_id.into());
+        self
+    }
+
+    /// Called with every event the loop records in its session transcript
+    /// after `session_start`, as it happens.
+    pub fn on_event(mut self, callback: impl FnMut(&Event) + 'a) -> Self {
+        self.runner.on_event = Some(Box::new(callback));
+        self
+    }
+
+    /// Re-read the prompt with `reload` before every iteration after the
+    /// first, so edits to it take effect without restarting the loop. Each
+    /// iteration's `iteration_start` record names the prompt it ran with.
+    pub fn reload_prompt(mut self, reload: impl FnMut() -> Result<String> + 'a) -> Self {
+        self.runner.reload_prompt = Some(Box::new(reload));
+        self
+    }
+
+    /// Make the first iteration a planning iteration, run with `prompt`
+    /// instead of the system prompt: the agent reviews the backlog without
+    /// writing code. Its output isn't checked for the marker and no gates
+    /// run after it. It counts against the iteration budget.
+    pub fn plan_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.runner.plan_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Stop after the planning iteration (see
+    /// [`plan_prompt`](LoopRunnerBuilder::plan_prompt)).
+    pub fn plan_only(mut self, plan_only: bool) -> Self {
+        self.runner.plan_only = plan_only;
+        self
+    }
+
+    /// Add what the agent said in the planning iteration to the prompt of
+    /// the iteration after it.
+    pub fn carry_plan(mut self, carry_plan: bool) -> Self {
+        self.runner.carry_plan = carry_plan;
+        self
+    }
+
+    /// Stop the loop after an iteration whose provider fails (exits
+    /// non-zero, is killed, or runs past the timeout) instead of going on
+    /// to the next one.
+    pub fn stop_on_failure(mut self, stop_on_failure: bool) -> Self {
+        self.runner.stop_on_failure = stop_on_failure;
+        self
+    }
+
+    /// Token that stops the loop, and the running provider, when cancelled
+    /// (default: [`interrupt_token`], i.e. Ctrl-C).
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.runner.cancel = cancel;
+        self
+    }
+
+    /// Keep `report` up to date as the loop runs: a section per iteration
+    /// and the closing line at the end.
+    pub fn progress(mut self, report: &'a mut ProgressReport) -> Self {
+        self.runner.progress = Some(report);
+        self
+    }
+
+    /// Save the loop's state in `checkpoint` as it runs, so `ralph resume`
+    /// can continue it, possibly on another machine.
+    pub fn checkpoint(mut self, checkpoint: &'a mut Checkpoint) -> Self {
+        self.runner.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Keep `metrics` up to date as the loop runs: counted after every
+    /// iteration and written at the end.
+    pub fn metrics(mut self, metrics: &'a mut LoopMetrics) -> Self {
+        self.runner.metrics = Some(metrics);
+        self
+    }
+
+    /// Run the provider and gates through `exec` instead of spawning real
+    /// processes.
+    pub fn exec(mut self, exec: &'a dyn CommandRunner) -> Self {
+        self.runner.exec = exec;
+        self
+    }
+
+    /// Check the configuration and return the runner.
+    pub fn build(self) -> Result<LoopRunner<'a>> {
+        providers::validate_provider(&self.runner.provider)?;
+        if self.runner.prompt.is_none() {
+            return Err(RalphError::InvalidInput(
+                "LoopRunner needs a prompt".to_string(),
+            ));
+        }
+        if self.runner.max_iterations == 0 {
+            return Err(RalphError::InvalidInput(
+                "iterations must be a positive integer".to_string(),
+            ));
+        }
+        Ok(self.runner)
+    }
+
+    /// [`build`](LoopRunnerBuilder::build) and [`run`](LoopRunner::run).
+    pub fn run(self) -> Result<LoopSummary> {
+        self.build()?.run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_marker() {
+        assert_eq!(COMPLETE_MARKER, "<promise>COMPLETE</promise>");
+        assert!("Some output with <promise>COMPLETE</promise> in it".contains(COMPLETE_MARKER));
+        assert!(!"Some output without the marker".contains(COMPLETE_MARKER));
+    }
+
+    #[test]
+    fn loop_runner_builder_checks_its_configuration() {
+        let err = |b: LoopRunnerBuilder| b.build().err().unwrap().to_string();
+        assert!(err(LoopRunner::builder()).contains("needs a prompt"));
+        assert!(err(LoopRunner::builder().prompt("p").provider("nope")).contains("nope"));
+        assert!(err(LoopRunner::builder().prompt("p").iterations(0)).contains("positive"));
+        assert!(LoopRunner::builder().prompt("").build().is_ok());
+    }
+
+    #[test]
+    fn iteration_notes_count_planning_and_signal_deaths_apart() {
+        assert_eq!(iteration_note(false, 0, 0, 0, 0), "");
+        assert_eq!(
+            iteration_note(false, 2, 1, 0, 0),
+            " (2 failed, 1 killed by a signal)"
+        );
+        assert_eq!(iteration_note(false, 0, 1, 0, 0), " (1 killed by a signal)");
+        assert_eq!(iteration_note(true, 1, 0, 0, 0), " (1 planning, 1 failed)");
+        assert_eq!(
+            iteration_note(false, 1, 0, 2, 0),
+            " (1 failed, 2 over the output limit)"
+        );
+        assert_eq!(iteration_note(false, 0, 0, 0, 3), " (3 timed out)");
+    }
+
+    #[test]
+    fn prompt_changes_show_both_hashes_and_the_lines_touched() {
+        let change = prompt_change("a\nb\n", "a\nc\nd\n");
+        assert_eq!(
+            change,
+            format!(
+                "{} -> {}, +2 -1 lines",
+                &session::prompt_hash("a\nb\n")[..8],
+                &session::prompt_hash("a\nc\nd\n")[..8]
+            )
+        );
+    }
+}
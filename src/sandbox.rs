@@ -0,0 +1,233 @@
+use crate::audit::{self, Kind};
+use crate::config;
+use crate::error::{RalphError, Result};
+use crate::git;
+use crate::lock;
+use crate::reporter;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory under `.ralph/` holding the worktrees ralph creates.
+pub const WORKTREES_DIR: &str = "worktrees";
+
+/// How `loop --sandbox` sets up its copy of the repository.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SandboxOptions {
+    /// Apply the checkout's uncommitted changes to tracked files in the
+    /// sandbox (`git stash create`, then `git stash apply` there)
+    pub carry_changes: bool,
+    /// Copy the checkout's `.beads/` into the sandbox, so bd works on a
+    /// copy of the backlog
+    pub copy_beads: bool,
+    /// Leave the sandbox in place when it's dropped
+    pub keep: bool,
+}
+
+/// A detached `git worktree` of the current repository under
+/// `.ralph/worktrees/`, at the commit checked out, for a loop to run in
+/// without touching the real checkout. Dropping it goes back to the
+/// directory ralph started in and, unless it's kept, removes the worktree.
+#[derive(Debug)]
+pub struct Sandbox {
+    path: PathBuf,
+    repo: PathBuf,
+    origin: PathBuf,
+    base: String,
+    keep: bool,
+}
+
+impl Sandbox {
+    /// Create a sandbox of the repository containing the current directory.
+    pub fn create(opts: SandboxOptions) -> Result<Sandbox> {
+        let origin = std::env::current_dir()?;
+        let repo = git_text(&origin, &["rev-parse", "--show-toplevel"])
+            .map(|root| PathBuf::from(root.trim()))
+            .map_err(|_| RalphError::Sandbox("--sandbox needs a git repository".to_string()))?;
+        let base = git::head_in(&repo)
+            .ok_or_else(|| RalphError::Sandbox("the repository has no commits yet".to_string()))?;
+
+        let project = repo.join(config::PROJECT_DIR_NAME);
+        fs::create_dir_all(project.join(WORKTREES_DIR))?;
+        lock::ignore_in_git(&project, &format!("{WORKTREES_DIR}/"))?;
+        let name = format!(
+            "sandbox-{}-{}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+            std::process::id()
+        );
+        let path = project.join(WORKTREES_DIR).join(name);
+        let mut add = Command::new("git");
+        add.args(["worktree", "add", "--detach", "--quiet"])
+            .arg(&path)
+            .arg(&base);
+        run_git(&repo, &mut add).map_err(|e| RalphError::Sandbox(e.to_string()))?;
+
+        // From here on, dropping the sandbox cleans up after a failure.
+        let sandbox = Sandbox {
+            path,
+            repo,
+            origin,
+            base,
+            keep: opts.keep,
+        };
+        if opts.carry_changes {
+            sandbox.carry_changes()?;
+        }
+        let beads = sandbox.repo.join(".beads");
+        if opts.copy_beads && beads.is_dir() {
+            copy_dir(&beads, &sandbox.path.join(".beads"))
+                .map_err(|e| RalphError::Sandbox(format!("copying .beads: {e}")))?;
+        }
+        Ok(sandbox)
+    }
+
+    /// The worktree's top directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where in the sandbox the directory ralph started in is.
+    pub fn workdir(&self) -> PathBuf {
+        let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        match canonical(&self.origin).strip_prefix(canonical(&self.repo)) {
+            Ok(relative) => self.path.join(relative),
+            Err(_) => self.path.clone(),
+        }
+    }
+
+    /// Make [`workdir`](Sandbox::workdir) the current directory, so
+    /// providers (and, unless they were pointed elsewhere, tracker
+    /// commands) run in the sandbox.
+    pub fn enter(&self) -> io::Result<()> {
+        std::env::set_current_dir(self.workdir())
+    }
+
+    /// `git diff --stat` of the sandbox against the commit it started from:
+    /// commits made there, uncommitted edits and new files alike.
+    pub fn diff_stat(&self) -> io::Result<String> {
+        run_git(
+            &self.path,
+            Command::new("git").args(["add", "--all", "--intent-to-add"]),
+        )?;
+        git_text(&self.path, &["diff", "--stat", &self.base])
+    }
+
+    /// Print where the sandbox is, what changed in it and whether it's
+    /// about to be removed.
+    pub fn report(&self) {
+        reporter::info(&format!("Sandbox: {}", self.path.display()));
+        match self.diff_stat() {
+            Ok(stat) if stat.trim().is_empty() => reporter::info("No changes in the sandbox"),
+            Ok(stat) => stat
+                .lines()
+                .for_each(|line| reporter::info(line.trim_end())),
+            Err(e) => reporter::warn(&format!("Failed to diff the sandbox: {e}")),
+        }
+        if self.keep {
+            reporter::info(&format!(
+                "Kept the sandbox; remove it with `git worktree remove --force {}`",
+                self.path.display()
+            ));
+        } else {
+            reporter::info("Removing the sandbox (pass --sandbox-keep to keep it)");
+        }
+    }
+
+    fn carry_changes(&self) -> Result<()> {
+        let stash = git_text(&self.repo, &["stash", "create"])
+            .map_err(|e| RalphError::Sandbox(format!("saving uncommitted changes: {e}")))?;
+        let stash = stash.trim();
+        if stash.is_empty() {
+            return Ok(());
+        }
+        run_git(
+            &self.path,
+            Command::new("git").args(["stash", "apply", "--quiet", stash]),
+        )
+        .map_err(|e| RalphError::Sandbox(format!("applying uncommitted changes: {e}")))?;
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.origin);
+        if self.keep {
+            return;
+        }
+        let mut remove = Command::new("git");
+        remove
+            .args(["worktree", "remove", "--force"])
+            .arg(&self.path);
+        if let Err(e) = run_git(&self.repo, &mut remove) {
+            reporter::warn(&format!(
+                "Failed to remove the sandbox {}: {e}",
+                self.path.display()
+            ));
+        }
+    }
+}
+
+/// Run `cmd` (a git command) in `dir`, failing with its stderr when it
+/// exits non-zero. Returns its stdout.
+fn run_git(dir: &Path, cmd: &mut Command) -> io::Result<String> {
+    let output = audit::run(Kind::Sandbox, cmd.current_dir(dir), |cmd| cmd.output())?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git exited with code {}: {}",
+            output.status.code().unwrap_or(1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn git_text(dir: &Path, args: &[&str]) -> io::Result<String> {
+    run_git(dir, Command::new("git").args(args))
+}
+
+/// Copy the files and directories under `from` into `to`, replacing files
+/// already there. Anything else (a daemon's socket) is skipped.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if kind.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if kind.is_file() {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_skip_what_isnt_a_file_or_directory() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        fs::create_dir(from.path().join("nested")).unwrap();
+        fs::write(from.path().join("issues.jsonl"), "{}\n").unwrap();
+        fs::write(from.path().join("nested").join("beads.db"), "db").unwrap();
+        fs::write(to.path().join("issues.jsonl"), "old\n").unwrap();
+        #[cfg(unix)]
+        let _socket = std::os::unix::net::UnixListener::bind(from.path().join("bd.sock")).unwrap();
+
+        copy_dir(from.path(), to.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(to.path().join("issues.jsonl")).unwrap(),
+            "{}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(to.path().join("nested").join("beads.db")).unwrap(),
+            "db"
+        );
+        assert!(!to.path().join("bd.sock").exists());
+    }
+}
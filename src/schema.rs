@@ -0,0 +1,230 @@
+use crate::reporter;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// The event types each provider's stream-json is expected to carry, with
+/// the fields ralph reads from them (dotted paths into the event).
+fn shapes(provider: &str) -> &'static [(&'static str, &'static [&'static str])] {
+    match provider {
+        "claude" => &[
+            ("system", &["subtype"]),
+            ("assistant", &["message.content"]),
+            ("user", &["message.content"]),
+            ("result", &["usage"]),
+        ],
+        "codex" => &[
+            ("thread.started", &["thread_id"]),
+            ("turn.started", &[]),
+            ("turn.completed", &["usage"]),
+            ("turn.failed", &["error"]),
+            ("item.started", &["item.type"]),
+            ("item.updated", &["item.type"]),
+            ("item.completed", &["item.type"]),
+            ("error", &["message"]),
+        ],
+        // qwen's CLI is a fork of gemini's.
+        "gemini" | "qwen" => &[
+            ("init", &[]),
+            ("message", &["role", "content"]),
+            ("tool_use", &["tool_name"]),
+            ("tool_result", &[]),
+            ("error", &[]),
+            ("result", &["stats"]),
+        ],
+        "droid" => &[
+            ("system", &[]),
+            ("message", &["role", "text"]),
+            ("tool_call", &["toolName"]),
+            ("tool_result", &[]),
+            ("error", &[]),
+            ("completion", &["finalText"]),
+        ],
+        _ => &[],
+    }
+}
+
+/// What `--validate-stream` found in provider output: how many events
+/// there were, and which of them didn't look the way ralph expects.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamCheck {
+    /// JSON events seen
+    pub events: u64,
+    /// Non-blank lines that weren't a JSON object
+    pub not_json: u64,
+    /// Events of a type ralph doesn't know, by type (`(none)` without one)
+    pub unrecognized: BTreeMap<String, u64>,
+    /// Known events lacking a field ralph reads, by `type: field`
+    pub missing_fields: BTreeMap<String, u64>,
+}
+
+impl StreamCheck {
+    /// Check each line of `provider`'s `output` against its expected event
+    /// shapes.
+    pub fn check(&mut self, provider: &str, output: &str) {
+        let shapes = shapes(provider);
+        for line in output.lines().filter(|l| !l.trim().is_empty()) {
+            let Ok(event @ Value::Object(_)) = serde_json::from_str::<Value>(line) else {
+                self.not_json += 1;
+                continue;
+            };
+            self.events += 1;
+            let kind = event["type"].as_str().unwrap_or("(none)");
+            let Some((_, fields)) = shapes.iter().find(|(k, _)| *k == kind) else {
+                *self.unrecognized.entry(kind.to_string()).or_default() += 1;
+                continue;
+            };
+            for field in *fields {
+                let value = field.split('.').fold(&event, |v, key| &v[key]);
+                if value.is_null() {
+                    *self
+                        .missing_fields
+                        .entry(format!("{kind}: {field}"))
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// Events of an unknown type.
+    pub fn unrecognized_events(&self) -> u64 {
+        self.unrecognized.values().sum()
+    }
+
+    /// Whether everything looked as expected.
+    pub fn is_clean(&self) -> bool {
+        self.not_json == 0 && self.unrecognized.is_empty() && self.missing_fields.is_empty()
+    }
+
+    /// A one-line verdict, followed by a line per kind of problem.
+    pub fn render_text(&self) -> String {
+        if self.is_clean() {
+            return format!(
+                "Stream check: {} events, all in the expected format\n",
+                self.events
+            );
+        }
+        let missing: u64 = self.missing_fields.values().sum();
+        let mut out = format!(
+            "Stream check: {} events, {} unrecognized, {} missing fields, {} lines not JSON; \
+             the provider's output format may have changed (see its raw output with \
+             `ralph logs` and file an issue)\n",
+            self.events,
+            self.unrecognized_events(),
+            missing,
+            self.not_json
+        );
+        for (kind, count) in &self.unrecognized {
+            out.push_str(&format!("  {count} x unknown event type {kind}\n"));
+        }
+        for (field, count) in &self.missing_fields {
+            out.push_str(&format!("  {count} x missing {field}\n"));
+        }
+        out
+    }
+}
+
+/// The check of every provider run so far, once [`enable`]d.
+static CHECK: Mutex<Option<StreamCheck>> = Mutex::new(None);
+
+/// Check the output of every provider run from now on (`--validate-stream`).
+pub fn enable() {
+    if let Ok(mut check) = CHECK.lock() {
+        check.get_or_insert_with(StreamCheck::default);
+    }
+}
+
+/// Add `provider`'s `output` to the check, if it's [`enable`]d.
+pub fn observe(provider: &str, output: &str) {
+    if let Ok(mut check) = CHECK.lock()
+        && let Some(check) = check.as_mut()
+    {
+        check.check(provider, output);
+    }
+}
+
+/// Print what the check found, if it's [`enable`]d: a warning when
+/// something didn't look as expected.
+pub fn report() {
+    let Some(check) = CHECK.lock().ok().and_then(|c| c.clone()) else {
+        return;
+    };
+    let text = check.render_text();
+    let mut lines = text.lines();
+    let verdict = lines.next().unwrap_or_default();
+    if check.is_clean() {
+        reporter::info(verdict);
+    } else {
+        reporter::warn(verdict);
+    }
+    lines.for_each(reporter::info);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_events_pass() {
+        let mut check = StreamCheck::default();
+        check.check(
+            "claude",
+            concat!(
+                r#"{"type":"system","subtype":"init"}"#,
+                "\n",
+                r#"{"type":"assistant","message":{"content":[]}}"#,
+                "\n\n",
+                r#"{"type":"result","result":"ok","usage":{"input_tokens":1}}"#,
+                "\n"
+            ),
+        );
+        assert_eq!(check.events, 3);
+        assert!(check.is_clean(), "{check:?}");
+        assert_eq!(
+            check.render_text(),
+            "Stream check: 3 events, all in the expected format\n"
+        );
+    }
+
+    #[test]
+    fn unknown_types_and_missing_fields_are_counted() {
+        let mut check = StreamCheck::default();
+        let output = concat!(
+            r#"{"type":"item.completed","item":{"type":"agent_message"}}"#,
+            "\n",
+            r#"{"type":"item.done","item":{}}"#,
+            "\n",
+            r#"{"type":"item.done"}"#,
+            "\n",
+            r#"{"type":"turn.completed"}"#,
+            "\n",
+            r#"{"msg":"no type"}"#,
+            "\n",
+            "plain text\n"
+        );
+        check.check("codex", output);
+        check.check("codex", r#"{"type":"turn.completed","usage":{}}"#);
+        assert_eq!(check.events, 6);
+        assert_eq!(check.not_json, 1);
+        assert_eq!(check.unrecognized_events(), 3);
+        let text = check.render_text();
+        assert!(
+            text.starts_with(
+                "Stream check: 6 events, 3 unrecognized, 1 missing fields, 1 lines not JSON; "
+            ),
+            "{text}"
+        );
+        assert!(
+            text.contains("\n  2 x unknown event type item.done\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("\n  1 x unknown event type (none)\n"),
+            "{text}"
+        );
+        assert!(
+            text.ends_with("\n  1 x missing turn.completed: usage\n"),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,945 @@
+use crate::backlog::BacklogDiff;
+use crate::config;
+use crate::crash;
+use crate::fsutil;
+use crate::git;
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Get the session transcript directory (~/.Ralph/sessions/)
+pub fn get_sessions_dir() -> io::Result<PathBuf> {
+    Ok(config::get_config_dir()?.join("sessions"))
+}
+
+/// Get the captured provider output directory (~/.Ralph/logs/)
+pub fn get_logs_dir() -> io::Result<PathBuf> {
+    Ok(config::get_config_dir()?.join("logs"))
+}
+
+/// One line of a session transcript (`~/.Ralph/sessions/<id>.jsonl`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// RFC 3339 timestamp (UTC)
+    pub ts: String,
+    /// Parallel worker that recorded it; `None` for the run itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker: Option<u32>,
+    /// What happened
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// One line of a session transcript.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// First record of every session
+    SessionStart {
+        /// Session id
+        session: String,
+        /// `once`, `loop`, `run` or `watch`
+        command: String,
+        /// Provider name
+        provider: String,
+        /// Iteration budget
+        max_iterations: u32,
+        /// Working directory
+        cwd: PathBuf,
+        /// Commit checked out when the session started
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        head: Option<String>,
+        /// `prompt_hash` of the prompt the session runs with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prompt_hash: Option<String>,
+        /// `--prompt-variant` label, for comparing prompts with
+        /// `ralph stats --by-variant`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prompt_variant: Option<String>,
+        /// `--label` text, for telling sessions apart in `ralph history`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// What the provider's `--version` printed, when it was checked
+        /// against a `[provider_versions]` pin
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider_version: Option<String>,
+    },
+    /// The provider is about to run
+    IterationStart {
+        /// Iteration number, from 1
+        iteration: u32,
+        /// `prompt_hash` of the prompt this iteration runs with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        prompt_hash: Option<String>,
+        /// Whether this is a `--plan-first` planning iteration, run with
+        /// the planning prompt (whose hash `prompt_hash` is)
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        planning: bool,
+    },
+    /// The provider exited
+    IterationEnd {
+        /// Iteration number, from 1
+        iteration: u32,
+        /// Provider exit code
+        exit_code: i32,
+        /// Signal that killed the provider, if one did
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+        /// Whether the output contained the COMPLETE marker
+        complete: bool,
+        /// Tokens/cost reported by the provider, when it reports any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
+        /// The model the provider said it used, when it says
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        /// Commit checked out after the iteration
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        head: Option<String>,
+        /// The output limit in bytes the provider crossed, when it was
+        /// killed for that
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output_limit: Option<u64>,
+        /// The timeout in seconds the provider ran past, when it was killed
+        /// for that
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<u64>,
+    },
+    /// A gate command ran after an iteration
+    Gate {
+        /// Iteration number, from 1
+        iteration: u32,
+        /// The shell command
+        command: String,
+        /// Its exit code (-1 when it couldn't be started)
+        exit_code: i32,
+    },
+    /// Last record of a session that wasn't killed
+    SessionEnd {
+        /// Iterations run
+        iterations: u32,
+        /// Whether the session finished its work
+        completed: bool,
+    },
+    /// The tracker's sync command ran after the loop (`--bd-sync`)
+    TrackerSync {
+        /// The command line, e.g. `bd sync`
+        command: String,
+        /// Its exit code (-1 when it couldn't be started)
+        exit_code: i32,
+    },
+    /// What the loop changed in the tracker's backlog
+    Backlog {
+        /// Issues closed, created, and started but not finished
+        diff: BacklogDiff,
+    },
+    /// This session continues an interrupted one (`ralph resume`)
+    Resumed {
+        /// Id of the interrupted session
+        from: String,
+    },
+    /// A tracker task was closed during this session
+    TaskClosed {
+        /// Tracker id of the task
+        id: String,
+    },
+    /// `ralph watch` found ready work and is starting a loop
+    WatchWake {
+        /// Number of ready tasks
+        ready: usize,
+    },
+    /// The loop started by the last wake-up finished
+    WatchWakeEnd {
+        /// Session id of the loop, if logging worked
+        loop_session: Option<String>,
+        /// Iterations the loop ran
+        iterations: u32,
+        /// Whether the loop saw the COMPLETE marker
+        completed: bool,
+        /// Why the loop failed, if it did
+        error: Option<String>,
+    },
+    /// Polling the tracker for ready work failed
+    WatchPollFailed {
+        /// The tracker's error
+        error: String,
+        /// Failures in a row, including this one
+        consecutive: u32,
+    },
+}
+
+/// How often a running session refreshes its lock file's mtime.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A lock whose heartbeat is older than this belongs to a crashed run.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// An in-progress session: owns the transcript file and knows where each
+/// iteration's captured output goes.
+pub struct Session {
+    /// Session id, `YYYYMMDD-HHMMSS-<pid>`
+    pub id: String,
+    transcript: TranscriptWriter,
+    log_dir: PathBuf,
+    /// `<id>.lock` holding our pid while the session runs; removed on drop
+    lock_path: PathBuf,
+    heartbeat: Option<Heartbeat>,
+}
+
+/// Background thread touching a lock file every `HEARTBEAT_INTERVAL`, so
+/// other processes can tell a live lock from one left by a crash.
+pub(crate) struct Heartbeat {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    pub(crate) fn start(lock_path: PathBuf) -> Option<Heartbeat> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let handle = thread::Builder::new()
+            .name("session-heartbeat".to_string())
+            .spawn(move || {
+                while !flag.load(Ordering::SeqCst) {
+                    thread::park_timeout(HEARTBEAT_INTERVAL);
+                    if let Ok(file) = OpenOptions::new().write(true).open(&lock_path) {
+                        let _ = file.set_modified(SystemTime::now());
+                    }
+                }
+            })
+            .ok()?;
+        Some(Heartbeat { stop, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        let _ = self.handle.join();
+    }
+}
+
+impl Session {
+    /// Create the transcript and log directory for a new session and record
+    /// its start.
+    pub fn start(
+        command: &str,
+        provider: &str,
+        max_iterations: u32,
+        prompt: &str,
+    ) -> io::Result<Session> {
+        let sessions_dir = get_sessions_dir()?;
+        fs::create_dir_all(&sessions_dir)?;
+        let (id, transcript) = create_transcript(&sessions_dir, &new_session_id())?;
+        let log_dir = get_logs_dir()?.join(&id);
+        fs::create_dir_all(&log_dir)?;
+        let lock_path = lock_path(&sessions_dir, &id);
+        fsutil::write_atomic(&lock_path, std::process::id().to_string())?;
+
+        crash::session_started(&id);
+        let mut session = Session {
+            id: id.clone(),
+            transcript: TranscriptWriter::start(transcript)?,
+            log_dir,
+            heartbeat: Heartbeat::start(lock_path.clone()),
+            lock_path,
+        };
+        session.record(Event::SessionStart {
+            session: id,
+            command: command.to_string(),
+            provider: provider.to_string(),
+            max_iterations,
+            cwd: std::env::current_dir().unwrap_or_default(),
+            head: git::head(),
+            prompt_hash: Some(prompt_hash(prompt)),
+            prompt_variant: prompt_variant(),
+            label: label(),
+            provider_version: PROVIDER_VERSION.get().cloned(),
+        })?;
+        Ok(session)
+    }
+
+    /// Path of the captured stdout for `iteration`. Its stderr goes next
+    /// to it, see [`stderr_log_path`].
+    pub fn iteration_log_path(&self, iteration: u32) -> PathBuf {
+        iteration_log_path(&self.log_dir, iteration)
+    }
+
+    /// Append an event to the transcript.
+    pub fn record(&mut self, event: Event) -> io::Result<()> {
+        self.transcript.recorder.record(event)
+    }
+
+    /// A [`Recorder`] for parallel worker `worker`, tagging what it records
+    /// with the worker's id.
+    pub fn recorder(&self, worker: u32) -> Recorder {
+        Recorder {
+            worker: Some(worker),
+            ..self.transcript.recorder.clone()
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.transcript.close();
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+        let _ = fs::remove_file(&self.lock_path);
+        crash::session_ended(&self.id);
+    }
+}
+
+enum Message {
+    Line(String),
+    Close,
+}
+
+/// Records events in a session transcript from any thread. Every record
+/// goes to the one thread writing the file, so lines from parallel workers
+/// never interleave.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Message>,
+    /// The write error that stopped the writer thread, if one did
+    failed: Arc<Mutex<Option<String>>>,
+    worker: Option<u32>,
+}
+
+impl Recorder {
+    /// Queue `event` for the transcript. Fails once writing the transcript
+    /// has failed or the session has ended.
+    pub fn record(&self, event: Event) -> io::Result<()> {
+        if let Some(e) = self.failed.lock().unwrap().as_ref() {
+            return Err(io::Error::other(e.clone()));
+        }
+        let record = Record {
+            ts: now_rfc3339(),
+            worker: self.worker,
+            event,
+        };
+        let line = serde_json::to_string(&record).map_err(io::Error::other)?;
+        self.tx
+            .send(Message::Line(line))
+            .map_err(|_| io::Error::other("the session has ended"))
+    }
+}
+
+/// The thread appending [`Recorder`]s' lines to a transcript, one write and
+/// flush per line.
+struct TranscriptWriter {
+    recorder: Recorder,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TranscriptWriter {
+    fn start(mut file: File) -> io::Result<TranscriptWriter> {
+        let (tx, rx) = mpsc::channel();
+        let failed = Arc::new(Mutex::new(None));
+        let error = Arc::clone(&failed);
+        let handle = thread::Builder::new()
+            .name("session-transcript".to_string())
+            .spawn(move || {
+                for message in rx {
+                    let Message::Line(line) = message else {
+                        break;
+                    };
+                    if let Err(e) = writeln!(file, "{line}").and_then(|()| file.flush()) {
+                        *error.lock().unwrap() = Some(e.to_string());
+                        break;
+                    }
+                }
+            })?;
+        Ok(TranscriptWriter {
+            recorder: Recorder {
+                tx,
+                failed,
+                worker: None,
+            },
+            handle: Some(handle),
+        })
+    }
+
+    /// Write everything recorded so far and stop the thread. Recorders
+    /// still around afterwards fail to record.
+    fn close(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = self.recorder.tx.send(Message::Close);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for TranscriptWriter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn lock_path(sessions_dir: &Path, id: &str) -> PathBuf {
+    sessions_dir.join(format!("{id}.lock"))
+}
+
+/// Whether a process with `pid` exists on this machine.
+#[cfg(unix)]
+pub fn pid_is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks for existence; EPERM means it exists but isn't ours.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process with `pid` exists on this machine. Without a cheap
+/// probe, assume it does so nothing belonging to it is treated as stale.
+#[cfg(not(unix))]
+pub fn pid_is_alive(_pid: u32) -> bool {
+    true
+}
+
+fn iteration_log_path(log_dir: &Path, iteration: u32) -> PathBuf {
+    log_dir.join(format!("iteration-{iteration}.log"))
+}
+
+/// Size at which an iteration's captured stderr moves on to a new file.
+pub const STDERR_CHUNK_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Path of chunk `chunk` (from 1) of the stderr captured alongside the
+/// stdout log `log`: `iteration-3.stderr.log`, then
+/// `iteration-3.stderr.2.log`, and so on.
+pub fn stderr_log_path(log: &Path, chunk: u32) -> PathBuf {
+    let stem = log.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let name = if chunk <= 1 {
+        format!("{stem}.stderr.log")
+    } else {
+        format!("{stem}.stderr.{chunk}.log")
+    };
+    log.with_file_name(name)
+}
+
+/// Writes a provider's stderr next to its stdout log, one line at a time,
+/// starting a new file (see [`stderr_log_path`]) whenever the current one
+/// would grow past the limit. Nothing is created until the first line, so
+/// a quiet provider leaves no file behind.
+#[derive(Debug)]
+pub struct StderrLog {
+    log: PathBuf,
+    limit: u64,
+    chunk: u32,
+    file: Option<File>,
+    written: u64,
+}
+
+impl StderrLog {
+    /// Capture for the stdout log `log`, in [`STDERR_CHUNK_BYTES`] files.
+    pub fn new(log: &Path) -> StderrLog {
+        StderrLog::with_limit(log, STDERR_CHUNK_BYTES)
+    }
+
+    /// Capture for the stdout log `log`, in files of at most `limit` bytes
+    /// (a single longer line still gets a file of its own).
+    pub fn with_limit(log: &Path, limit: u64) -> StderrLog {
+        StderrLog {
+            log: log.to_path_buf(),
+            limit,
+            chunk: 0,
+            file: None,
+            written: 0,
+        }
+    }
+
+    /// Append `line` and a newline, rotating first if needed.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.file.is_none() || (self.written > 0 && self.written + len > self.limit) {
+            self.chunk += 1;
+            self.file = Some(File::create(stderr_log_path(&self.log, self.chunk))?);
+            self.written = 0;
+        }
+        let file = self.file.as_mut().expect("opened above");
+        writeln!(file, "{line}")?;
+        self.written += len;
+        Ok(())
+    }
+}
+
+/// Create a new transcript file named after `base`, adding a `-2`, `-3`, …
+/// suffix when another session (e.g. a loop started by `watch`) already
+/// claimed that id in the same second.
+fn create_transcript(sessions_dir: &Path, base: &str) -> io::Result<(String, File)> {
+    let mut n = 1;
+    loop {
+        let id = if n == 1 {
+            base.to_string()
+        } else {
+            format!("{base}-{n}")
+        };
+        match OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(sessions_dir.join(format!("{id}.jsonl")))
+        {
+            Ok(file) => return Ok((id, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+static PROMPT_VARIANT: OnceLock<String> = OnceLock::new();
+
+/// Label every session started from now on as running prompt variant
+/// `name` (`--prompt-variant`). The first call wins; the label is only
+/// recorded, it changes nothing about the run.
+pub fn set_prompt_variant(name: &str) {
+    let _ = PROMPT_VARIANT.set(name.to_string());
+}
+
+/// The label set with [`set_prompt_variant`], if any.
+pub fn prompt_variant() -> Option<String> {
+    PROMPT_VARIANT.get().cloned()
+}
+
+static LABEL: OnceLock<String> = OnceLock::new();
+
+/// Environment variable holding the `--label` of the run, set for the
+/// provider (and so its hooks) and for gate commands.
+pub const LABEL_ENV: &str = "RALPH_LABEL";
+
+/// Label every session started from now on with `text` (`--label`). The
+/// first call wins.
+pub fn set_label(text: &str) {
+    let _ = LABEL.set(text.to_string());
+}
+
+/// The label set with [`set_label`], if any.
+pub fn label() -> Option<String> {
+    LABEL.get().cloned()
+}
+
+static PROVIDER_VERSION: OnceLock<String> = OnceLock::new();
+
+/// Record `version` (what the provider's `--version` printed) in every
+/// session started from now on. The first call wins.
+pub fn set_provider_version(version: &str) {
+    let _ = PROVIDER_VERSION.set(version.to_string());
+}
+
+/// Fingerprint of a prompt, used to notice when it changed between a session
+/// and its resumption, or between iterations of a `--reload-prompt` loop.
+pub fn prompt_hash(prompt: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(prompt.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Session ids sort chronologically: `<UTC timestamp>-<pid>`.
+fn new_session_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    )
+}
+
+fn now_rfc3339() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// A finished or running session as found on disk.
+#[derive(Debug, Clone)]
+pub struct StoredSession {
+    /// Session id
+    pub id: String,
+    /// The `<id>.jsonl` transcript
+    pub transcript: PathBuf,
+    /// Directory holding the per-iteration logs
+    pub log_dir: PathBuf,
+}
+
+impl StoredSession {
+    /// Read all parseable transcript records; malformed lines are skipped.
+    pub fn records(&self) -> io::Result<Vec<Record>> {
+        read_records(&self.transcript)
+    }
+
+    /// Append an event to the transcript after the run that wrote it has
+    /// ended, e.g. the tracker sync that follows a loop.
+    pub fn append(&self, event: Event) -> io::Result<()> {
+        let record = Record {
+            ts: now_rfc3339(),
+            worker: None,
+            event,
+        };
+        let line = serde_json::to_string(&record).map_err(io::Error::other)?;
+        let mut file = fs::OpenOptions::new().append(true).open(&self.transcript)?;
+        writeln!(file, "{line}")
+    }
+
+    /// Whether the transcript contains a `session_end` record.
+    pub fn is_finished(&self) -> io::Result<bool> {
+        Ok(self
+            .records()?
+            .iter()
+            .any(|r| matches!(r.event, Event::SessionEnd { .. })))
+    }
+
+    /// Whether the session's lock file names a live process and its
+    /// heartbeat is recent. A crashed run leaves a lock behind whose pid
+    /// may since have been reused, so the heartbeat age decides.
+    pub fn is_running(&self) -> bool {
+        let lock = self.transcript.with_extension("lock");
+        let fresh = fs::metadata(&lock)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .is_some_and(|age| age < STALE_AFTER);
+        fresh
+            && fs::read_to_string(lock)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<u32>().ok())
+                .is_some_and(pid_is_alive)
+    }
+
+    /// Captured output files for this session, ordered by iteration.
+    pub fn iteration_logs(&self) -> io::Result<Vec<(u32, PathBuf)>> {
+        let mut logs = Vec::new();
+        let entries = match fs::read_dir(&self.log_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(logs),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let iteration = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("iteration-"))
+                .and_then(|n| n.strip_suffix(".log"))
+                .and_then(|n| n.parse::<u32>().ok());
+            if let Some(iteration) = iteration {
+                logs.push((iteration, path));
+            }
+        }
+        logs.sort();
+        Ok(logs)
+    }
+
+    /// Captured stderr files for this session, ordered by iteration and
+    /// then by chunk.
+    pub fn stderr_logs(&self) -> io::Result<Vec<(u32, PathBuf)>> {
+        let mut logs = Vec::new();
+        let entries = match fs::read_dir(&self.log_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let position = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("iteration-"))
+                .and_then(|n| n.strip_suffix(".log"))
+                .and_then(|n| n.split_once(".stderr"))
+                .and_then(|(iteration, chunk)| {
+                    let chunk = match chunk.strip_prefix('.') {
+                        Some(chunk) => chunk.parse::<u32>().ok()?,
+                        None if chunk.is_empty() => 1,
+                        None => return None,
+                    };
+                    Some((iteration.parse::<u32>().ok()?, chunk))
+                });
+            if let Some(position) = position {
+                logs.push((position, path));
+            }
+        }
+        logs.sort();
+        Ok(logs
+            .into_iter()
+            .map(|((iteration, _), path)| (iteration, path))
+            .collect())
+    }
+}
+
+/// Parse a transcript file, skipping lines that aren't valid records.
+pub fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+    let file = File::open(path)?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// All stored sessions, oldest first.
+pub fn list_sessions() -> io::Result<Vec<StoredSession>> {
+    list_sessions_in(&get_sessions_dir()?, &get_logs_dir()?)
+}
+
+fn list_sessions_in(sessions_dir: &Path, logs_dir: &Path) -> io::Result<Vec<StoredSession>> {
+    let mut sessions = Vec::new();
+    let entries = match fs::read_dir(sessions_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(sessions),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+            sessions.push(StoredSession {
+                id: id.to_string(),
+                log_dir: logs_dir.join(id),
+                transcript: path.clone(),
+            });
+        }
+    }
+    sessions.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(sessions)
+}
+
+/// The most recently started session that is still running, if any.
+pub fn find_active_session() -> io::Result<Option<StoredSession>> {
+    Ok(list_sessions()?.into_iter().rev().find(|s| s.is_running()))
+}
+
+/// Find a session by id, or the most recent one when `id` is `None`.
+pub fn find_session(id: Option<&str>) -> io::Result<StoredSession> {
+    let sessions = list_sessions()?;
+    let found = match id {
+        Some(id) => sessions.into_iter().find(|s| s.id == id),
+        None => sessions.into_iter().last(),
+    };
+    found.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            match id {
+                Some(id) => format!("Session '{id}' not found"),
+                None => "No sessions recorded yet".to_string(),
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trips_with_event_tag() {
+        let record = Record {
+            ts: "2026-01-01T00:00:00Z".to_string(),
+            worker: None,
+            event: Event::IterationEnd {
+                iteration: 2,
+                exit_code: 0,
+                signal: Some(9),
+                complete: true,
+                usage: None,
+                model: Some("claude-sonnet-4-5".to_string()),
+                head: None,
+                output_limit: Some(1024),
+                timeout: None,
+            },
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(r#""event":"iteration_end""#));
+        let parsed: Record = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, record);
+    }
+
+    #[test]
+    fn parallel_recorders_write_whole_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, file) = create_transcript(dir.path(), "20260101-000000-1").unwrap();
+        let mut writer = TranscriptWriter::start(file).unwrap();
+        thread::scope(|scope| {
+            for worker in 1..=8 {
+                let recorder = Recorder {
+                    worker: Some(worker),
+                    ..writer.recorder.clone()
+                };
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        recorder
+                            .record(Event::Gate {
+                                iteration: i,
+                                command: "x".repeat(2000),
+                                exit_code: 0,
+                            })
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        writer.close();
+
+        let text = fs::read_to_string(dir.path().join("20260101-000000-1.jsonl")).unwrap();
+        let records: Vec<Record> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 8 * 200);
+        for worker in 1..=8 {
+            let iterations: Vec<u32> = records
+                .iter()
+                .filter(|r| r.worker == Some(worker))
+                .map(|r| match r.event {
+                    Event::Gate { iteration, .. } => iteration,
+                    _ => unreachable!(),
+                })
+                .collect();
+            assert_eq!(iterations, (0..200).collect::<Vec<_>>());
+        }
+        // Nothing is written once the writer has closed.
+        assert!(
+            writer
+                .recorder
+                .record(Event::WatchWake { ready: 1 })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn create_transcript_never_reuses_an_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, _) = create_transcript(dir.path(), "20260101-000000-1").unwrap();
+        let (second, _) = create_transcript(dir.path(), "20260101-000000-1").unwrap();
+        assert_eq!(first, "20260101-000000-1");
+        assert_eq!(second, "20260101-000000-1-2");
+    }
+
+    #[test]
+    fn list_sessions_sorts_by_id_and_ignores_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = dir.path().join("sessions");
+        fs::create_dir_all(&sessions).unwrap();
+        fs::write(sessions.join("20260102-000000-1.jsonl"), "").unwrap();
+        fs::write(sessions.join("20260101-000000-1.jsonl"), "").unwrap();
+        fs::write(sessions.join("notes.txt"), "").unwrap();
+
+        let found = list_sessions_in(&sessions, &dir.path().join("logs")).unwrap();
+        let ids: Vec<_> = found.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["20260101-000000-1", "20260102-000000-1"]);
+    }
+
+    #[test]
+    fn is_running_checks_lock_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = StoredSession {
+            id: "s".to_string(),
+            transcript: dir.path().join("s.jsonl"),
+            log_dir: dir.path().join("s"),
+        };
+        assert!(!session.is_running());
+        fs::write(dir.path().join("s.lock"), std::process::id().to_string()).unwrap();
+        assert!(session.is_running());
+        fs::write(dir.path().join("s.lock"), "not a pid").unwrap();
+        assert!(!session.is_running());
+    }
+
+    #[test]
+    fn is_running_ignores_stale_heartbeat() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = StoredSession {
+            id: "s".to_string(),
+            transcript: dir.path().join("s.jsonl"),
+            log_dir: dir.path().join("s"),
+        };
+        let lock = dir.path().join("s.lock");
+        fs::write(&lock, std::process::id().to_string()).unwrap();
+        File::options()
+            .write(true)
+            .open(&lock)
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_AFTER * 2)
+            .unwrap();
+        assert!(!session.is_running());
+    }
+
+    #[test]
+    fn iteration_logs_are_ordered_numerically() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = StoredSession {
+            id: "s".to_string(),
+            transcript: dir.path().join("s.jsonl"),
+            log_dir: dir.path().to_path_buf(),
+        };
+        for n in [10, 2, 1] {
+            fs::write(iteration_log_path(dir.path(), n), "").unwrap();
+        }
+        let iterations: Vec<_> = session
+            .iteration_logs()
+            .unwrap()
+            .into_iter()
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(iterations, [1, 2, 10]);
+    }
+
+    #[test]
+    fn stderr_rotates_into_chunks_read_back_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = StoredSession {
+            id: "s".to_string(),
+            transcript: dir.path().join("s.jsonl"),
+            log_dir: dir.path().to_path_buf(),
+        };
+        fs::write(iteration_log_path(dir.path(), 1), "stdout\n").unwrap();
+        let mut log = StderrLog::with_limit(&iteration_log_path(dir.path(), 2), 10);
+        for line in ["aaaa", "bbbb", "a line past the limit", "cc"] {
+            log.write_line(line).unwrap();
+        }
+        let _quiet = StderrLog::new(&iteration_log_path(dir.path(), 3));
+
+        let logs = session.stderr_logs().unwrap();
+        let names: Vec<_> = logs
+            .iter()
+            .map(|(n, p)| (*n, p.file_name().unwrap().to_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (2, "iteration-2.stderr.log".to_string()),
+                (2, "iteration-2.stderr.2.log".to_string()),
+                (2, "iteration-2.stderr.3.log".to_string()),
+            ]
+        );
+        let contents: Vec<_> = logs
+            .iter()
+            .map(|(_, p)| fs::read_to_string(p).unwrap())
+            .collect();
+        assert_eq!(
+            contents,
+            ["aaaa\nbbbb\n", "a line past the limit\n", "cc\n"]
+        );
+        assert_eq!(session.iteration_logs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn read_records_skips_malformed_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s.jsonl");
+        fs::write(
+            &path,
+            "not json\n{\"ts\":\"t\",\"event\":\"session_end\",\"iterations\":1,\"completed\":false}\n",
+        )
+        .unwrap();
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::config::{self, Config};
+use crate::error::{RalphError, Result};
+use crate::reporter;
+use crate::tracker;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Answers given on the command line; anything left `None` is asked for
+/// (or, non-interactively, kept at its current value).
+#[derive(Debug, Clone, Default)]
+pub struct SetupOptions {
+    /// Answer for the provider question
+    pub provider: Option<String>,
+    /// Answer for the iterations question
+    pub iterations: Option<u32>,
+    /// Answer for the update-check question
+    pub update_check: Option<bool>,
+    /// Skip the bd checks
+    pub skip_bd_check: bool,
+    /// Configure `tracker = "none"` (and skip the bd checks)
+    pub no_bd: bool,
+    /// Never prompt; use flags, then current values, then defaults
+    pub non_interactive: bool,
+}
+
+/// Settings the wizard ends up writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answers {
+    /// Default provider
+    pub provider: String,
+    /// Default iteration budget
+    pub iterations: u32,
+    /// Mention new releases after runs
+    pub update_check: bool,
+}
+
+/// Look for `name` as an executable on `PATH`.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidates = if cfg!(windows) {
+            vec![
+                dir.join(format!("{name}.exe")),
+                dir.join(format!("{name}.cmd")),
+            ]
+        } else {
+            vec![dir.join(name)]
+        };
+        candidates.into_iter().find(|p| p.is_file())
+    })
+}
+
+fn ask(
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    question: &str,
+    default: &str,
+) -> io::Result<String> {
+    write!(out, "{question} [{default}]: ")?;
+    out.flush()?;
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let answer = line.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+/// Ask until `parse` accepts the answer.
+fn ask_parsed<T>(
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    question: &str,
+    default: &str,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<T> {
+    loop {
+        let answer = ask(input, out, question, default)?;
+        match parse(&answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => writeln!(out, "{e}")?,
+        }
+        // EOF: stop asking rather than looping forever
+        if input.fill_buf()?.is_empty() {
+            return parse(default);
+        }
+    }
+}
+
+/// The provider `answer` names, with an alias (from `current`'s
+/// `[aliases]` or the built-in ones) taken for the provider it stands for.
+fn parse_provider(answer: &str, current: &Config) -> Result<String> {
+    let templates = current.templates();
+    let aliases = current.aliases.clone().unwrap_or_default();
+    let provider = crate::providers::resolve_alias(answer, &templates, &aliases);
+    crate::providers::validate_provider_in(&provider, &templates, &aliases)?;
+    Ok(provider)
+}
+
+fn parse_iterations(answer: &str) -> Result<u32> {
+    config::validate_iterations(answer)
+}
+
+fn parse_yes_no(answer: &str) -> Result<bool> {
+    match answer.to_ascii_lowercase().as_str() {
+        "y" | "yes" | "true" => Ok(true),
+        "n" | "no" | "false" => Ok(false),
+        _ => Err(RalphError::InvalidInput("Please answer y or n".to_string())),
+    }
+}
+
+/// Work out the settings to write: flags first, then answers (when
+/// interactive), with the current config pre-filled as the default.
+pub fn collect_answers(
+    opts: &SetupOptions,
+    current: &Config,
+    installed: &[(&str, bool)],
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+) -> Result<Answers> {
+    let interactive = !opts.non_interactive;
+
+    let default_provider = current
+        .provider
+        .clone()
+        .or_else(|| {
+            installed
+                .iter()
+                .find(|(_, found)| *found)
+                .map(|(name, _)| name.to_string())
+        })
+        .unwrap_or_else(|| config::DEFAULT_PROVIDER.to_string());
+    let provider = match &opts.provider {
+        Some(p) => parse_provider(p, current)?,
+        None if interactive => {
+            writeln!(out, "Provider CLIs:")?;
+            for (name, found) in installed {
+                if *found {
+                    writeln!(out, "  {name:<7} installed")?;
+                } else {
+                    let hint = crate::providers::spec(name).map_or("", |spec| spec.install);
+                    writeln!(out, "  {name:<7} not found (install: {hint})")?;
+                }
+            }
+            ask_parsed(
+                input,
+                out,
+                "Default provider",
+                &default_provider,
+                |answer| parse_provider(answer, current),
+            )?
+        }
+        None => default_provider,
+    };
+
+    let default_iterations = current.iterations.unwrap_or(config::DEFAULT_ITERATIONS);
+    let iterations = match opts.iterations {
+        Some(n) => parse_iterations(&n.to_string())?,
+        None if interactive => ask_parsed(
+            input,
+            out,
+            "Default iterations for `ralph loop`",
+            &default_iterations.to_string(),
+            parse_iterations,
+        )?,
+        None => default_iterations,
+    };
+
+    let default_update_check = current.update_check.unwrap_or(false);
+    let update_check = match opts.update_check {
+        Some(b) => b,
+        None if interactive => ask_parsed(
+            input,
+            out,
+            "Check for ralph updates after runs? (y/n)",
+            if default_update_check { "y" } else { "n" },
+            parse_yes_no,
+        )?,
+        None => default_update_check,
+    };
+
+    Ok(Answers {
+        provider,
+        iterations,
+        update_check,
+    })
+}
+
+/// Notes about bd for the current directory.
+fn bd_notes(cwd: &Path) -> Vec<String> {
+    let mut notes = Vec::new();
+    if find_in_path("bd").is_none() {
+        notes.push(
+            "bd (beads) is not installed; the default prompt and `ralph run`/`watch` rely on it. See https://github.com/steveyegge/beads".to_string(),
+        );
+    } else if !cwd.join(".beads").is_dir() {
+        notes.push("This directory has no .beads/; run `bd init` in your project.".to_string());
+    }
+    notes
+}
+
+/// Set `tracker` in a config table, keeping the rest of a `[tracker]` table.
+fn set_tracker_name(table: &mut toml::Table, name: &str) {
+    let name = toml::Value::String(name.to_string());
+    match table.get_mut("tracker") {
+        Some(toml::Value::Table(tracker)) => {
+            tracker.insert("name".to_string(), name);
+        }
+        _ => {
+            table.insert("tracker".to_string(), name);
+        }
+    }
+}
+
+/// Run the wizard and write `~/.Ralph/config.toml`.
+pub fn run(opts: &SetupOptions) -> Result<()> {
+    let config_path = config::get_config_file_path()?;
+    let current = config::load_config_file(&config_path)?;
+    let installed: Vec<(&str, bool)> = crate::providers::VALID_PROVIDERS
+        .iter()
+        .map(|p| (*p, find_in_path(p).is_some()))
+        .collect();
+
+    let mut opts = opts.clone();
+    if !opts.non_interactive && !crate::term::get().can_prompt() {
+        reporter::info("Not running at a terminal; using flags and defaults (--non-interactive)");
+        opts.non_interactive = true;
+    }
+    let stdin = io::stdin();
+    let answers = collect_answers(
+        &opts,
+        &current,
+        &installed,
+        &mut stdin.lock(),
+        &mut io::stderr(),
+    )?;
+
+    config::update_config_file(&config_path, |table| {
+        table.insert(
+            "provider".to_string(),
+            toml::Value::String(answers.provider.clone()),
+        );
+        table.insert(
+            "iterations".to_string(),
+            toml::Value::Integer(i64::from(answers.iterations)),
+        );
+        table.insert(
+            "update_check".to_string(),
+            toml::Value::Boolean(answers.update_check),
+        );
+        if opts.no_bd {
+            set_tracker_name(table, tracker::NONE);
+        }
+    })?;
+    // Seeds the prompt for the tracker just written, if there's none yet.
+    config::ensure_config()?;
+    println!("Wrote {}", config_path.display());
+
+    if !installed
+        .iter()
+        .any(|(name, found)| *found && *name == answers.provider)
+    {
+        reporter::warn(&format!(
+            "'{}' is not installed yet; ralph will fail until it is on PATH",
+            answers.provider
+        ));
+    }
+    if !opts.skip_bd_check && !opts.no_bd {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        for note in bd_notes(&cwd) {
+            reporter::info(&format!("Note: {note}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const INSTALLED: &[(&str, bool)] = &[
+        ("droid", false),
+        ("codex", true),
+        ("claude", false),
+        ("gemini", false),
+    ];
+
+    fn answers(opts: &SetupOptions, current: &Config, input: &str) -> (Answers, String) {
+        let mut out = Vec::new();
+        let answers =
+            collect_answers(opts, current, INSTALLED, &mut input.as_bytes(), &mut out).unwrap();
+        (answers, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn defaults_prefer_installed_provider() {
+        let (a, out) = answers(&SetupOptions::default(), &Config::default(), "\n\n\n");
+        assert_eq!(
+            a,
+            Answers {
+                provider: "codex".to_string(),
+                iterations: config::DEFAULT_ITERATIONS,
+                update_check: false
+            }
+        );
+        assert!(
+            out.contains("claude  not found (install: npm install -g @anthropic-ai/claude-code)")
+        );
+    }
+
+    #[test]
+    fn current_values_are_prefilled() {
+        let current = Config {
+            provider: Some("gemini".to_string()),
+            iterations: Some(7),
+            update_check: Some(true),
+            ..Config::default()
+        };
+        let (a, out) = answers(&SetupOptions::default(), &current, "\n\n\n");
+        assert_eq!(a.provider, "gemini");
+        assert_eq!(a.iterations, 7);
+        assert!(a.update_check);
+        assert!(out.contains("Default provider [gemini]"));
+    }
+
+    #[test]
+    fn invalid_answers_are_asked_again() {
+        let (a, out) = answers(
+            &SetupOptions::default(),
+            &Config::default(),
+            "nope\nclaude\n0\n3\ny\n",
+        );
+        assert_eq!(a.provider, "claude");
+        assert_eq!(a.iterations, 3);
+        assert!(a.update_check);
+        assert!(out.contains("Invalid provider 'nope'"));
+    }
+
+    #[test]
+    fn aliases_are_saved_as_the_provider_they_stand_for() {
+        let (a, _) = answers(
+            &SetupOptions::default(),
+            &Config::default(),
+            "cc
+
+
+",
+        );
+        assert_eq!(a.provider, "claude");
+
+        let current = Config {
+            aliases: Some(BTreeMap::from([("oai".to_string(), "codex".to_string())])),
+            ..Config::default()
+        };
+        let opts = SetupOptions {
+            provider: Some("oai".to_string()),
+            non_interactive: true,
+            ..SetupOptions::default()
+        };
+        assert_eq!(answers(&opts, &current, "").0.provider, "codex");
+    }
+
+    #[test]
+    fn no_bd_keeps_the_tracker_table() {
+        let mut table: toml::Table = "tracker = \"beads\"".parse().unwrap();
+        set_tracker_name(&mut table, "none");
+        assert_eq!(table.to_string(), "tracker = \"none\"\n");
+
+        let mut table: toml::Table = "[tracker]\nsync_after_run = true\n".parse().unwrap();
+        set_tracker_name(&mut table, "none");
+        assert_eq!(
+            table.to_string(),
+            "[tracker]\nname = \"none\"\nsync_after_run = true\n"
+        );
+    }
+
+    #[test]
+    fn non_interactive_uses_flags_without_reading_input() {
+        let opts = SetupOptions {
+            provider: Some("claude".to_string()),
+            iterations: Some(2),
+            non_interactive: true,
+            ..SetupOptions::default()
+        };
+        let (a, out) = answers(&opts, &Config::default(), "");
+        assert_eq!(a.provider, "claude");
+        assert_eq!(a.iterations, 2);
+        assert!(!a.update_check);
+        assert!(out.is_empty());
+    }
+}
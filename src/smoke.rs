@@ -0,0 +1,283 @@
+use crate::error::{RalphError, Result};
+use crate::exec::{CancelToken, CommandRunner, Output};
+use crate::providers;
+use crate::render;
+use crate::schema::StreamCheck;
+use crate::usage::{self, Usage};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `ralph prompt test` waits for the reply unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The reply the smoke test asks for on `date`: `RALPH-OK 2026-10-16`.
+/// The date keeps a canned or cached answer from passing.
+pub fn expected_reply(date: NaiveDate) -> String {
+    format!("RALPH-OK {}", date.format("%Y-%m-%d"))
+}
+
+/// The smoke-test prompt for `date`: a task any working setup finishes in
+/// seconds, without tools.
+pub fn prompt(date: NaiveDate) -> String {
+    format!(
+        "This is a setup check from ralph. Don't use any tools or change any files.\n\
+         Reply with exactly: {}\n",
+        expected_reply(date)
+    )
+}
+
+/// How `ralph prompt test` went.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SmokeTest {
+    /// Provider tested
+    pub provider: String,
+    /// The reply asked for
+    pub expected: String,
+    /// Whether the reply came back, through stream-json ralph could read
+    pub passed: bool,
+    /// What the assistant said, as parsed from its stream-json
+    pub reply: String,
+    /// Round trip, from starting the provider to its exit
+    pub duration_ms: u64,
+    /// Tokens (and cost) the provider reported
+    pub usage: Option<Usage>,
+    /// JSON events in the provider's output
+    pub stream_events: u64,
+    /// Lines of output that weren't in the format ralph expects (see
+    /// [`StreamCheck`])
+    pub stream_problems: u64,
+    /// What went wrong and what to do about it, when it failed
+    pub error: Option<String>,
+}
+
+impl SmokeTest {
+    /// The verdict, a line per measurement.
+    pub fn render_text(&self) -> String {
+        let reply = self.reply.lines().next().unwrap_or_default();
+        let reply = match reply.chars().count() {
+            0 => "(none)".to_string(),
+            n if n > 80 => format!("{}...", reply.chars().take(77).collect::<String>()),
+            _ => reply.to_string(),
+        };
+        let tokens = match self.usage {
+            Some(usage) => format!(
+                "{} input, {} output",
+                usage.input_tokens, usage.output_tokens
+            ),
+            None => "not reported".to_string(),
+        };
+        let stream = match (self.stream_events, self.stream_problems) {
+            (0, _) => "no events".to_string(),
+            (events, 0) => format!("{events} events, all in the expected format"),
+            (events, problems) => {
+                format!("{events} events, {problems} lines not in the expected format")
+            }
+        };
+        let result = match &self.error {
+            None => "OK".to_string(),
+            Some(error) => format!("FAILED\n\n{error}"),
+        };
+        format!(
+            "Provider:    {}\nExpected:    {}\nReply:       {}\nRound trip:  {:.1}s\n\
+             Tokens:      {}\nStream-json: {}\nResult:      {}\n",
+            self.provider,
+            self.expected,
+            reply,
+            self.duration_ms as f64 / 1000.0,
+            tokens,
+            stream,
+            result
+        )
+    }
+}
+
+/// Send the smoke-test prompt for `date` to `provider` through `exec`, the
+/// way `ralph once` runs it, giving up after `timeout` or once `cancel` is
+/// cancelled.
+pub fn run(
+    exec: &dyn CommandRunner,
+    provider: &str,
+    date: NaiveDate,
+    timeout: Duration,
+    cancel: &CancelToken,
+) -> SmokeTest {
+    // The provider's own token, cancelled by either `cancel` or the clock.
+    let token = CancelToken::new();
+    let done = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let (token, cancel, done) = (token.clone(), cancel.clone(), done.clone());
+        thread::spawn(move || {
+            let deadline = Instant::now() + timeout;
+            while !done.load(Ordering::SeqCst) {
+                if cancel.is_cancelled() || Instant::now() >= deadline {
+                    token.cancel();
+                    return !cancel.is_cancelled();
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            false
+        })
+    };
+    let started = Instant::now();
+    let result = providers::execute(exec, provider, &prompt(date), None, &token);
+    let elapsed = started.elapsed();
+    done.store(true, Ordering::SeqCst);
+    let timed_out = watcher.join().unwrap_or(false);
+    judge(
+        provider,
+        &expected_reply(date),
+        result,
+        elapsed,
+        timed_out.then_some(timeout),
+    )
+}
+
+/// Judge the provider's `result`: `timed_out` gives the timeout when the
+/// provider was stopped for running past it.
+fn judge(
+    provider: &str,
+    expected: &str,
+    result: Result<Output>,
+    elapsed: Duration,
+    timed_out: Option<Duration>,
+) -> SmokeTest {
+    let mut test = SmokeTest {
+        provider: provider.to_string(),
+        expected: expected.to_string(),
+        passed: false,
+        reply: String::new(),
+        duration_ms: elapsed.as_millis() as u64,
+        usage: None,
+        stream_events: 0,
+        stream_problems: 0,
+        error: None,
+    };
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            test.error = Some(e.to_string());
+            return test;
+        }
+    };
+    let mut check = StreamCheck::default();
+    check.check(provider, &output.stdout);
+    test.stream_events = check.events;
+    test.stream_problems =
+        check.not_json + check.unrecognized_events() + check.missing_fields.values().sum::<u64>();
+    test.usage = usage::extract(&output.stdout);
+    test.reply = render::assistant_text(&output.stdout);
+
+    test.error = if let Some(timeout) = timed_out {
+        Some(format!(
+            "No reply within {}s; the provider may be waiting for input, or unable to reach its \
+             API (pass --timeout to wait longer)",
+            timeout.as_secs()
+        ))
+    } else if let Some(signal) = output.signal {
+        Some(
+            RalphError::ProviderKilled {
+                provider: provider.to_string(),
+                signal,
+            }
+            .to_string(),
+        )
+    } else if output.code != 0 {
+        let failed = RalphError::ProviderFailed {
+            provider: provider.to_string(),
+            code: output.code,
+        };
+        Some(
+            match output.stderr.lines().rfind(|l| !l.trim().is_empty()) {
+                Some(said) => format!("{failed}: {}", said.trim()),
+                None => failed.to_string(),
+            },
+        )
+    } else if check.events == 0 {
+        Some(format!(
+            "{provider} printed no stream-json events, so ralph can't read its replies; its CLI \
+             may no longer accept the flags ralph passes (see `ralph providers` for its version)"
+        ))
+    } else if !test.reply.contains(expected) {
+        Some(format!(
+            "The reply didn't contain '{expected}'; the provider answered, but ralph may be \
+             reading the wrong part of its output (try `--validate-stream` on a run)"
+        ))
+    } else {
+        None
+    };
+    test.passed = test.error.is_none();
+    test
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn output(code: i32, stdout: &str, stderr: &str) -> Result<Output> {
+        Ok(Output {
+            code,
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            ..Output::default()
+        })
+    }
+
+    const REPLY: &str = r#"{"type":"message","role":"assistant","text":"RALPH-OK 2026-10-16"}"#;
+
+    #[test]
+    fn the_expected_reply_through_stream_json_passes() {
+        let date = NaiveDate::from_ymd_opt(2026, 10, 16).unwrap();
+        assert!(prompt(date).contains("Reply with exactly: RALPH-OK 2026-10-16\n"));
+        let expected = expected_reply(date);
+        let usage = r#"{"type":"completion","finalText":"","usage":{"input_tokens":120,"output_tokens":8}}"#;
+        let stdout = format!("{REPLY}\n{usage}\n");
+        let test = judge(
+            "droid",
+            &expected,
+            output(0, &stdout, ""),
+            Duration::from_millis(4200),
+            None,
+        );
+        assert!(test.passed, "{test:?}");
+        assert_eq!(test.reply, "RALPH-OK 2026-10-16");
+        assert_eq!(test.stream_events, 2);
+        let text = test.render_text();
+        assert!(text.contains("Round trip:  4.2s\n"), "{text}");
+        assert!(
+            text.contains("Tokens:      120 input, 8 output\n"),
+            "{text}"
+        );
+        assert!(text.ends_with("Result:      OK\n"), "{text}");
+    }
+
+    #[test]
+    fn failures_say_what_to_fix() {
+        let judged = |result, timed_out| {
+            judge("droid", "RALPH-OK x", result, Duration::ZERO, timed_out)
+                .error
+                .unwrap()
+        };
+        assert_eq!(
+            judged(output(1, "", "Error: invalid API key\n\n"), None),
+            "Provider 'droid' exited with code 1: Error: invalid API key"
+        );
+        assert!(
+            judged(output(0, "RALPH-OK x\n", ""), None).starts_with("droid printed no stream-json")
+        );
+        assert!(judged(output(0, REPLY, ""), None).starts_with("The reply didn't contain"));
+        assert!(
+            judged(output(0, "", ""), Some(Duration::from_secs(9)))
+                .starts_with("No reply within 9s")
+        );
+        let missing = Err(RalphError::ProviderSpawn {
+            provider: "droid".to_string(),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        });
+        assert!(judged(missing, None).starts_with("Failed to execute provider 'droid'"));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::backlog::{BacklogItem, Snapshot};
+use crate::error::{RalphError, Result};
+use crate::fsutil;
+use crate::lock;
+use crate::reporter;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The state file's name in the repository's `.ralph/`.
+pub const STATE_FILE: &str = "state.json";
+
+/// The layout of [`LoopState`] this ralph writes. A file with a higher
+/// number came from a newer ralph and is refused rather than misread.
+pub const STATE_FORMAT: u32 = 1;
+
+/// Where a loop in this repository keeps its state: `.ralph/state.json`
+/// at the top of the git checkout (or in the current directory outside
+/// one), next to the lock.
+pub fn path() -> io::Result<PathBuf> {
+    Ok(lock::lock_path()?.with_file_name(STATE_FILE))
+}
+
+/// Everything `ralph resume` needs to continue a loop, on this machine or
+/// another one after a `git pull`. It holds no paths, so nothing about the
+/// machine that wrote it leaks into the repository.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoopState {
+    /// [`STATE_FORMAT`] of the ralph that wrote it
+    pub format: u32,
+    /// Version of the ralph that wrote it
+    pub ralph_version: String,
+    /// Session the loop last ran under
+    pub session_id: String,
+    /// Provider the loop runs with
+    pub provider: String,
+    /// The loop's whole iteration budget
+    pub max_iterations: u32,
+    /// Iterations that ran to completion, across resumes
+    pub finished_iterations: u32,
+    /// Whether the agent reported the work complete and the gates agreed
+    pub completed: bool,
+    /// `session::prompt_hash` of the prompt the loop last ran with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+    /// The loop's `--prompt-variant` label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_variant: Option<String>,
+    /// The loop's `--label`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Iterations whose output carried the completion marker, counted
+    /// across resumes (a failing gate can keep the loop going anyway)
+    #[serde(default)]
+    pub markers_seen: Vec<u32>,
+    /// The backlog when the loop started, for the diff at its end
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backlog: Option<Vec<BacklogItem>>,
+    /// Whether the file is committed after every iteration
+    /// (`--portable-state`)
+    #[serde(default)]
+    pub portable: bool,
+    /// When it was last written (RFC 3339)
+    pub updated: String,
+}
+
+impl LoopState {
+    /// Iterations left of the budget.
+    pub fn remaining_iterations(&self) -> u32 {
+        self.max_iterations.saturating_sub(self.finished_iterations)
+    }
+
+    /// The backlog the loop started with, when one was taken.
+    pub fn backlog_snapshot(&self) -> Option<Snapshot> {
+        self.backlog.clone().map(Snapshot::new)
+    }
+}
+
+/// Read the state at `path`; `None` when there is none. A file from a
+/// newer ralph is an error saying which version wrote it.
+pub fn read(path: &Path) -> Result<Option<LoopState>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let invalid = |e: &dyn std::fmt::Display| {
+        RalphError::InvalidInput(format!("Invalid loop state {}: {}", path.display(), e))
+    };
+    // The format is checked before the rest, which a newer ralph may have
+    // laid out differently.
+    let value: Value = serde_json::from_str(&content).map_err(|e| invalid(&e))?;
+    let format = value["format"].as_u64().unwrap_or(0);
+    if format > u64::from(STATE_FORMAT) {
+        return Err(RalphError::InvalidInput(format!(
+            "{} was written by ralph {} (state format {}), but this is ralph {}, which reads \
+             format {} at most; upgrade ralph to resume this loop",
+            path.display(),
+            value["ralph_version"].as_str().unwrap_or("(unknown)"),
+            format,
+            env!("CARGO_PKG_VERSION"),
+            STATE_FORMAT
+        )));
+    }
+    serde_json::from_value(value)
+        .map(Some)
+        .map_err(|e| invalid(&e))
+}
+
+/// Keeps a loop's [`LoopState`] up to date, built by `ralph loop` and
+/// `ralph resume` and handed to
+/// [`LoopRunnerBuilder::checkpoint`](crate::runner::LoopRunnerBuilder::checkpoint).
+/// Failures to save are warnings; the loop goes on.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    /// Iterations finished before this run, when resuming
+    base: u32,
+    /// The whole budget, when resuming; otherwise the runner's
+    max_iterations: Option<u32>,
+    markers_seen: Vec<u32>,
+    backlog: Option<Vec<BacklogItem>>,
+    portable: bool,
+    state: Option<LoopState>,
+}
+
+impl Checkpoint {
+    /// A checkpoint at `path`. Unless `portable`, the file is listed in
+    /// `.ralph/.gitignore`, so it stays on this machine.
+    pub fn new(path: impl Into<PathBuf>, portable: bool) -> Checkpoint {
+        Checkpoint {
+            path: path.into(),
+            base: 0,
+            max_iterations: None,
+            markers_seen: Vec::new(),
+            backlog: None,
+            portable,
+            state: None,
+        }
+    }
+
+    /// Record `snapshot` as the backlog the loop started with.
+    pub fn backlog(mut self, snapshot: Option<&Snapshot>) -> Self {
+        self.backlog = snapshot.map(|s| s.items().cloned().collect());
+        self
+    }
+
+    /// Carry on from `previous`: its finished iterations, budget, markers
+    /// and backlog count towards this run's state. A portable state stays
+    /// portable.
+    pub fn resuming(mut self, previous: &LoopState) -> Self {
+        self.base = previous.finished_iterations;
+        self.max_iterations = Some(previous.max_iterations);
+        self.markers_seen = previous.markers_seen.clone();
+        self.backlog = previous.backlog.clone();
+        self.portable |= previous.portable;
+        self
+    }
+
+    /// Like [`resuming`](Self::resuming), for a loop found in a local
+    /// session transcript rather than a state file.
+    pub fn resuming_session(mut self, finished_iterations: u32, max_iterations: u32) -> Self {
+        self.base = finished_iterations;
+        self.max_iterations = Some(max_iterations);
+        self
+    }
+
+    /// The state as last saved.
+    pub fn state(&self) -> Option<&LoopState> {
+        self.state.as_ref()
+    }
+
+    /// Note the session the loop runs under and save the initial state.
+    pub(crate) fn start(
+        &mut self,
+        session_id: &str,
+        provider: &str,
+        max_iterations: u32,
+        prompt_hash: &str,
+        prompt_variant: Option<String>,
+        label: Option<String>,
+    ) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+            let name = self.path.file_name().and_then(|n| n.to_str());
+            if !self.portable
+                && let Err(e) = lock::ignore_in_git(dir, name.unwrap_or(STATE_FILE))
+            {
+                reporter::warn(&format!("Failed to list the loop state in .gitignore: {e}"));
+            }
+        }
+        self.state = Some(LoopState {
+            format: STATE_FORMAT,
+            ralph_version: env!("CARGO_PKG_VERSION").to_string(),
+            session_id: session_id.to_string(),
+            provider: provider.to_string(),
+            max_iterations: self.max_iterations.unwrap_or(max_iterations),
+            finished_iterations: self.base,
+            completed: false,
+            prompt_hash: Some(prompt_hash.to_string()),
+            prompt_variant,
+            label,
+            markers_seen: self.markers_seen.clone(),
+            backlog: self.backlog.clone(),
+            portable: self.portable,
+            updated: String::new(),
+        });
+        self.save(None);
+    }
+
+    /// Record that iteration `iteration` of this run finished, having run
+    /// with the prompt hashed as `prompt_hash`.
+    pub(crate) fn iteration(
+        &mut self,
+        iteration: u32,
+        prompt_hash: Option<String>,
+        marker_seen: bool,
+        complete: bool,
+    ) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        let overall = self.base + iteration;
+        state.finished_iterations = overall;
+        state.completed = complete;
+        if prompt_hash.is_some() {
+            state.prompt_hash = prompt_hash;
+        }
+        if marker_seen {
+            state.markers_seen.push(overall);
+        }
+        self.save(Some(overall));
+    }
+
+    /// Record how the loop ended.
+    pub(crate) fn finish(&mut self, completed: bool) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        if state.completed == completed {
+            return;
+        }
+        state.completed = completed;
+        self.save(None);
+    }
+
+    /// Write the state, and commit it when portable. `iteration` names the
+    /// iteration in the commit message.
+    fn save(&mut self, iteration: Option<u32>) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        state.updated = chrono::Local::now().to_rfc3339();
+        let json = serde_json::to_string_pretty(state).expect("loop state serializes");
+        if let Err(e) = fsutil::write_atomic(&self.path, format!("{json}\n")) {
+            reporter::warn(&format!(
+                "Failed to save the loop state {}: {}",
+                self.path.display(),
+                e
+            ));
+            return;
+        }
+        if self.portable {
+            let message = match iteration {
+                Some(i) => format!(
+                    "ralph: loop state after iteration {} of {}",
+                    i, state.max_iterations
+                ),
+                None => "ralph: loop state".to_string(),
+            };
+            if let Err(e) = commit(&self.path, &message) {
+                reporter::warn(&format!("Failed to commit the loop state: {e}"));
+            }
+        }
+    }
+}
+
+/// Commit the file at `path`, and only it, leaving whatever else is staged
+/// alone. The repository's hooks are skipped: they're for the agent's work,
+/// not ralph's bookkeeping.
+fn commit(path: &Path, message: &str) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default();
+    let git = |args: &[&str]| -> io::Result<bool> {
+        let output = Command::new("git")
+            .args(args)
+            .arg("--")
+            .arg(name)
+            .current_dir(dir)
+            .output()?;
+        Ok(output.status.success())
+    };
+    if !git(&["add", "--force"])? {
+        return Err(io::Error::other(
+            "git add failed (is this a git repository?)",
+        ));
+    }
+    // Nothing to commit when the state is what's already committed.
+    if git(&["diff", "--cached", "--quiet"])? {
+        return Ok(());
+    }
+    match git(&["commit", "--quiet", "--no-verify", "-m", message])? {
+        true => Ok(()),
+        false => Err(io::Error::other("git commit failed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> LoopState {
+        LoopState {
+            format: STATE_FORMAT,
+            ralph_version: "0.2.6".to_string(),
+            session_id: "20250101-000000-abcd".to_string(),
+            provider: "claude".to_string(),
+            max_iterations: 5,
+            finished_iterations: 2,
+            completed: false,
+            prompt_hash: Some("h".to_string()),
+            prompt_variant: None,
+            label: None,
+            markers_seen: vec![2],
+            backlog: None,
+            portable: false,
+            updated: "t".to_string(),
+        }
+    }
+
+    #[test]
+    fn states_round_trip_and_newer_formats_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(STATE_FILE);
+        assert_eq!(read(&path).unwrap(), None);
+
+        fs::write(&path, serde_json::to_string(&state()).unwrap()).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(state()));
+
+        fs::write(
+            &path,
+            r#"{"format": 2, "ralph_version": "9.0.0", "iterations": {"done": 2}}"#,
+        )
+        .unwrap();
+        let err = read(&path).unwrap_err().to_string();
+        assert!(
+            err.contains("was written by ralph 9.0.0 (state format 2)"),
+            "{err}"
+        );
+        assert!(err.contains("upgrade ralph"), "{err}");
+
+        fs::write(&path, "{\"format\": 1}").unwrap();
+        let err = read(&path).unwrap_err().to_string();
+        assert!(err.starts_with("Invalid loop state "), "{err}");
+    }
+
+    #[test]
+    fn checkpoints_count_across_resumes_and_keep_paths_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".ralph").join(STATE_FILE);
+        let backlog = Snapshot::new([BacklogItem {
+            id: "bd-1".to_string(),
+            title: "Fix login".to_string(),
+            status: "open".to_string(),
+        }]);
+        let mut checkpoint = Checkpoint::new(&path, false).backlog(Some(&backlog));
+        checkpoint.start("s1", "codex", 5, "h1", None, None);
+        checkpoint.iteration(1, Some("h1".to_string()), false, false);
+        checkpoint.iteration(2, Some("h2".to_string()), true, false);
+        let first = read(&path).unwrap().unwrap();
+        assert_eq!(first.finished_iterations, 2);
+        assert_eq!(first.markers_seen, [2]);
+        assert_eq!(first.prompt_hash.as_deref(), Some("h2"));
+        assert_eq!(first.backlog_snapshot(), Some(backlog));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".ralph/.gitignore")).unwrap(),
+            "state.json\n"
+        );
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(!json.contains(&*dir.path().to_string_lossy()), "{json}");
+
+        let mut resumed = Checkpoint::new(&path, false).resuming(&first);
+        resumed.start(
+            "s2",
+            "codex",
+            first.remaining_iterations(),
+            "h2",
+            None,
+            None,
+        );
+        resumed.iteration(1, None, true, true);
+        resumed.finish(true);
+        let second = read(&path).unwrap().unwrap();
+        assert_eq!(second.session_id, "s2");
+        assert_eq!((second.max_iterations, second.finished_iterations), (5, 3));
+        assert_eq!(second.markers_seen, [2, 3]);
+        assert!(second.completed);
+        assert_eq!(second.remaining_iterations(), 2);
+    }
+}
@@ -0,0 +1,751 @@
+use crate::error::Result;
+use crate::pricing::{self, Pricing};
+use crate::session::{self, Event, Record};
+use crate::usage::Usage;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Options for `ralph stats`.
+#[derive(Debug, Default)]
+pub struct StatsOptions {
+    /// Only count sessions started within this long ago
+    pub since: Option<Duration>,
+    /// Add a per-repository breakdown
+    pub by_repo: bool,
+    /// Add a per-`--prompt-variant` breakdown
+    pub by_variant: bool,
+    /// Add a per-`--label` breakdown
+    pub by_label: bool,
+}
+
+/// Aggregated figures for a set of sessions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    /// Sessions counted
+    pub runs: u32,
+    /// Iterations over those sessions
+    pub iterations: u32,
+    /// Runs that completed (COMPLETE marker, closed task, or exit code 0
+    /// for `once`)
+    pub successful: u32,
+    /// Share of runs that finished their work, 0.0 to 1.0
+    pub success_rate: f64,
+    /// Iterations over the successful runs
+    pub completed_iterations: u32,
+    /// Iterations a successful run took on average
+    pub avg_iterations_to_complete: Option<f64>,
+    /// Tasks closed by `ralph run`
+    pub tasks_closed: u32,
+    /// Input tokens over runs that reported usage
+    pub input_tokens: u64,
+    /// Output tokens over runs that reported usage
+    pub output_tokens: u64,
+    /// Runs whose provider reported token counts; only these contribute to
+    /// the token totals
+    pub runs_with_usage: u32,
+    /// Cost in USD over runs with a known cost: their tokens at the prices
+    /// in `pricing.toml`, or what the provider reported for models without
+    /// a price
+    pub cost_usd: f64,
+    /// Runs with a known cost; only these contribute to `cost_usd`
+    pub runs_with_cost: u32,
+}
+
+impl Totals {
+    /// Count session `s`, which cost `cost` (see [`session_cost`]).
+    fn add(&mut self, s: &SessionSummary, cost: Option<f64>) {
+        self.runs += 1;
+        self.iterations += s.iterations;
+        self.successful += u32::from(s.completed);
+        self.success_rate = f64::from(self.successful) / f64::from(self.runs);
+        if s.completed {
+            self.completed_iterations += s.iterations;
+            self.avg_iterations_to_complete =
+                Some(f64::from(self.completed_iterations) / f64::from(self.successful));
+        }
+        self.tasks_closed += s.tasks_closed;
+        if let Some(usage) = s.usage {
+            self.runs_with_usage += 1;
+            self.input_tokens += usage.input_tokens;
+            self.output_tokens += usage.output_tokens;
+        }
+        if let Some(cost) = cost {
+            self.runs_with_cost += 1;
+            self.cost_usd += cost;
+        }
+    }
+}
+
+/// What session `s` cost: each iteration's tokens at its model's price in
+/// `pricing`, or what the provider reported when the model has no price
+/// (the model then goes in `unpriced`) or wasn't recorded. `None` when no
+/// iteration's cost is known.
+fn session_cost(
+    s: &SessionSummary,
+    pricing: &Pricing,
+    unpriced: &mut BTreeSet<String>,
+) -> Option<f64> {
+    let mut total = None;
+    for iteration in &s.iteration_details {
+        let Some(usage) = iteration.usage else {
+            continue;
+        };
+        let price = iteration.model.as_deref().and_then(|model| {
+            let price = pricing.price(model);
+            if price.is_none() {
+                unpriced.insert(model.to_string());
+            }
+            price
+        });
+        if let Some(cost) = price.map(|p| p.cost(&usage)).or(usage.cost_usd) {
+            *total.get_or_insert(0.0) += cost;
+        }
+    }
+    total
+}
+
+/// Result of `ralph stats`.
+#[derive(Debug, Serialize)]
+pub struct StatsReport {
+    /// Start of the reporting window, if limited
+    pub since: Option<String>,
+    /// Over all sessions
+    pub totals: Totals,
+    /// Per provider name
+    pub by_provider: BTreeMap<String, Totals>,
+    /// Per working directory, with `--by-repo`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_repo: Option<BTreeMap<String, Totals>>,
+    /// Per `--prompt-variant` label ([`NO_VARIANT`] for sessions without
+    /// one), with `--by-variant`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_variant: Option<BTreeMap<String, Totals>>,
+    /// The `prompt_hash`es each variant ran with, in the order first seen,
+    /// with `--by-variant`; more than one means the prompt changed under
+    /// the same label
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_prompts: Option<BTreeMap<String, Vec<String>>>,
+    /// Per `--label` ([`NO_LABEL`] for sessions without one), with
+    /// `--by-label`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_label: Option<BTreeMap<String, Totals>>,
+    /// Models that ran without a price in `pricing.toml`, so their cost is
+    /// missing or the provider's own figure
+    pub unpriced_models: Vec<String>,
+}
+
+/// The `--by-variant` row of sessions run without `--prompt-variant`.
+pub const NO_VARIANT: &str = "(none)";
+
+/// The `--by-label` row of sessions run without `--label`.
+pub const NO_LABEL: &str = "(none)";
+
+/// What one session transcript adds up to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    /// Session id
+    pub id: String,
+    /// `once`, `loop`, `run` or `watch`
+    pub command: String,
+    /// Time of the first record
+    pub started: DateTime<Utc>,
+    /// When the last record was written
+    pub ended: DateTime<Utc>,
+    /// Provider name
+    pub provider: String,
+    /// Working directory
+    pub cwd: PathBuf,
+    /// `--prompt-variant` label
+    pub prompt_variant: Option<String>,
+    /// `prompt_hash` of the prompt the session started with
+    pub prompt_hash: Option<String>,
+    /// `--label` text
+    pub label: Option<String>,
+    /// Iterations run
+    pub iterations: u32,
+    /// Whether the session finished its work
+    pub completed: bool,
+    /// Tasks closed during the session
+    pub tasks_closed: u32,
+    /// Summed over iterations that reported usage
+    pub usage: Option<Usage>,
+    /// Per-iteration breakdown, in order
+    pub iteration_details: Vec<IterationSummary>,
+}
+
+impl SessionSummary {
+    /// Iterations whose provider exited non-zero.
+    pub fn failures(&self) -> u32 {
+        self.iteration_details
+            .iter()
+            .filter(|i| i.exit_code != 0 && i.signal.is_none())
+            .count() as u32
+    }
+
+    /// Iterations whose provider was killed by a signal; not counted as
+    /// [`failures`](SessionSummary::failures).
+    pub fn killed(&self) -> u32 {
+        self.iteration_details
+            .iter()
+            .filter(|i| i.signal.is_some())
+            .count() as u32
+    }
+}
+
+/// One finished iteration of a session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IterationSummary {
+    /// Iteration number, from 1
+    pub iteration: u32,
+    /// Time of the `iteration_start` record, if any
+    pub started: Option<DateTime<Utc>>,
+    /// Time of the `iteration_end` record
+    pub ended: DateTime<Utc>,
+    /// Provider exit code
+    pub exit_code: i32,
+    /// Signal that killed the provider, if one did
+    pub signal: Option<i32>,
+    /// Tokens/cost, when the provider reported any
+    pub usage: Option<Usage>,
+    /// The model, when the provider named it
+    pub model: Option<String>,
+}
+
+fn parse_ts(ts: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(ts).ok().map(|t| t.to_utc())
+}
+
+/// Summarize a transcript. `watch` sessions are skipped: the loops they
+/// start are recorded as sessions of their own.
+pub fn summarize(id: &str, records: &[Record]) -> Option<SessionSummary> {
+    let (first, rest) = records.split_first()?;
+    let Event::SessionStart {
+        command,
+        provider,
+        cwd,
+        prompt_hash,
+        prompt_variant,
+        label,
+        ..
+    } = &first.event
+    else {
+        return None;
+    };
+    if command == "watch" {
+        return None;
+    }
+    let started = parse_ts(&first.ts)?;
+
+    let mut summary = SessionSummary {
+        id: id.to_string(),
+        command: command.clone(),
+        started,
+        ended: started,
+        provider: provider.clone(),
+        cwd: cwd.clone(),
+        prompt_variant: prompt_variant.clone(),
+        prompt_hash: prompt_hash.clone(),
+        label: label.clone(),
+        iterations: 0,
+        completed: false,
+        tasks_closed: 0,
+        usage: None,
+        iteration_details: Vec::new(),
+    };
+    let mut iteration_started = None;
+    for record in rest {
+        let ts = parse_ts(&record.ts);
+        if let Some(ts) = ts {
+            summary.ended = ts;
+        }
+        match &record.event {
+            Event::IterationStart { .. } => iteration_started = ts,
+            Event::IterationEnd {
+                iteration,
+                exit_code,
+                signal,
+                usage,
+                model,
+                ..
+            } => {
+                summary.iterations += 1;
+                summary.iteration_details.push(IterationSummary {
+                    iteration: *iteration,
+                    started: iteration_started.take(),
+                    ended: ts.unwrap_or(summary.ended),
+                    exit_code: *exit_code,
+                    signal: *signal,
+                    usage: *usage,
+                    model: model.clone(),
+                });
+                if let Some(usage) = usage {
+                    let total = summary.usage.get_or_insert_with(Usage::default);
+                    total.input_tokens += usage.input_tokens;
+                    total.output_tokens += usage.output_tokens;
+                    if let Some(cost) = usage.cost_usd {
+                        *total.cost_usd.get_or_insert(0.0) += cost;
+                    }
+                }
+            }
+            Event::SessionEnd { completed, .. } => summary.completed = *completed,
+            Event::TaskClosed { .. } => summary.tasks_closed += 1,
+            _ => {}
+        }
+    }
+    Some(summary)
+}
+
+fn aggregate(
+    summaries: &[SessionSummary],
+    since: Option<DateTime<Utc>>,
+    opts: &StatsOptions,
+    pricing: &Pricing,
+) -> StatsReport {
+    let mut unpriced = BTreeSet::new();
+    let mut totals = Totals::default();
+    let mut by_provider: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut repos: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut variants: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut variant_prompts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut labels: BTreeMap<String, Totals> = BTreeMap::new();
+
+    for s in summaries {
+        if since.is_some_and(|since| s.started < since) {
+            continue;
+        }
+        let cost = session_cost(s, pricing, &mut unpriced);
+        totals.add(s, cost);
+        by_provider
+            .entry(s.provider.clone())
+            .or_default()
+            .add(s, cost);
+        if opts.by_repo {
+            repos
+                .entry(s.cwd.display().to_string())
+                .or_default()
+                .add(s, cost);
+        }
+        if opts.by_variant {
+            let variant = s.prompt_variant.as_deref().unwrap_or(NO_VARIANT);
+            variants
+                .entry(variant.to_string())
+                .or_default()
+                .add(s, cost);
+            let hashes = variant_prompts.entry(variant.to_string()).or_default();
+            if let Some(hash) = &s.prompt_hash
+                && !hashes.contains(hash)
+            {
+                hashes.push(hash.clone());
+            }
+        }
+        if opts.by_label {
+            labels
+                .entry(s.label.clone().unwrap_or_else(|| NO_LABEL.to_string()))
+                .or_default()
+                .add(s, cost);
+        }
+    }
+
+    StatsReport {
+        since: since.map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        totals,
+        by_provider,
+        by_repo: opts.by_repo.then_some(repos),
+        by_variant: opts.by_variant.then_some(variants),
+        variant_prompts: opts.by_variant.then_some(variant_prompts),
+        by_label: opts.by_label.then_some(labels),
+        unpriced_models: unpriced.into_iter().collect(),
+    }
+}
+
+/// Summaries of every recorded session except `watch` ones, oldest first.
+pub fn load_summaries() -> io::Result<Vec<SessionSummary>> {
+    let mut summaries = Vec::new();
+    for stored in session::list_sessions()? {
+        if let Some(summary) = summarize(&stored.id, &stored.records()?) {
+            summaries.push(summary);
+        }
+    }
+    Ok(summaries)
+}
+
+/// Read every session transcript and aggregate them, pricing their tokens
+/// with `pricing.toml`.
+pub fn collect(opts: &StatsOptions) -> Result<StatsReport> {
+    let summaries = load_summaries()?;
+    let pricing = pricing::load()?;
+    let since = opts
+        .since
+        .and_then(|d| chrono::Duration::from_std(d).ok())
+        .map(|d| Utc::now() - d);
+    Ok(aggregate(&summaries, since, opts, &pricing))
+}
+
+fn format_tokens(n: u64) -> String {
+    match n {
+        0..1_000 => n.to_string(),
+        1_000..1_000_000 => format!("{:.1}k", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+fn render_table(title: &str, rows: &BTreeMap<String, Totals>) -> String {
+    let width = rows
+        .keys()
+        .map(|k| k.len())
+        .max()
+        .unwrap_or(0)
+        .max(title.len());
+    let mut out = format!(
+        "{:<width$}  {:>5}  {:>6}  {:>8}  {:>9}  {:>6}  {:>15}  {:>9}\n",
+        title, "RUNS", "ITERS", "SUCCESS", "AVG ITERS", "TASKS", "TOKENS IN/OUT", "COST"
+    );
+    for (name, t) in rows {
+        let tokens = if t.runs_with_usage > 0 {
+            format!(
+                "{}/{}",
+                format_tokens(t.input_tokens),
+                format_tokens(t.output_tokens)
+            )
+        } else {
+            "-".to_string()
+        };
+        let cost = if t.runs_with_cost > 0 {
+            format!("${:.2}", t.cost_usd)
+        } else {
+            "-".to_string()
+        };
+        let to_complete = match t.avg_iterations_to_complete {
+            Some(avg) => format!("{avg:.1}"),
+            None => "-".to_string(),
+        };
+        out.push_str(&format!(
+            "{:<width$}  {:>5}  {:>6}  {:>7.1}%  {:>9}  {:>6}  {:>15}  {:>9}\n",
+            name,
+            t.runs,
+            t.iterations,
+            t.success_rate * 100.0,
+            to_complete,
+            t.tasks_closed,
+            tokens,
+            cost
+        ));
+    }
+    out
+}
+
+/// Human-readable summary with per-provider (and per-repo) tables.
+pub fn render_text(report: &StatsReport) -> String {
+    let t = &report.totals;
+    let mut out = String::new();
+    match &report.since {
+        Some(since) => out.push_str(&format!("Sessions since {since}\n\n")),
+        None => out.push_str("All recorded sessions\n\n"),
+    }
+    if t.runs == 0 {
+        out.push_str("No sessions recorded.\n");
+        return out;
+    }
+    out.push_str(&format!("Runs:          {}\n", t.runs));
+    out.push_str(&format!("Iterations:    {}\n", t.iterations));
+    out.push_str(&format!(
+        "Success rate:  {:.1}% ({}/{})\n",
+        t.success_rate * 100.0,
+        t.successful,
+        t.runs
+    ));
+    out.push_str(&format!("Tasks closed:  {}\n", t.tasks_closed));
+    out.push_str(&format!(
+        "Tokens:        {} in / {} out ({} of {} runs reported usage)\n",
+        format_tokens(t.input_tokens),
+        format_tokens(t.output_tokens),
+        t.runs_with_usage,
+        t.runs
+    ));
+    if t.runs_with_cost > 0 {
+        out.push_str(&format!(
+            "Cost:          ${:.2} (avg ${:.2} over {} runs with a known cost)\n",
+            t.cost_usd,
+            t.cost_usd / f64::from(t.runs_with_cost),
+            t.runs_with_cost
+        ));
+    } else {
+        out.push_str("Cost:          - (no prices or provider-reported costs)\n");
+    }
+    if !report.unpriced_models.is_empty() {
+        out.push_str(&format!(
+            "Unpriced:      {} (add with `ralph pricing set`)\n",
+            report.unpriced_models.join(", ")
+        ));
+    }
+    out.push('\n');
+    out.push_str(&render_table("PROVIDER", &report.by_provider));
+    if let Some(repos) = &report.by_repo {
+        out.push('\n');
+        out.push_str(&render_table("REPO", repos));
+    }
+    if let Some(variants) = &report.by_variant {
+        out.push('\n');
+        out.push_str(&render_table("VARIANT", variants));
+    }
+    for (variant, hashes) in report.variant_prompts.iter().flatten() {
+        if hashes.len() > 1 {
+            let short: Vec<&str> = hashes.iter().map(|h| &h[..h.len().min(8)]).collect();
+            out.push_str(&format!(
+                "Variant {variant} ran with {} different prompts ({})\n",
+                hashes.len(),
+                short.join(", ")
+            ));
+        }
+    }
+    if let Some(labels) = &report.by_label {
+        out.push('\n');
+        out.push_str(&render_table("LABEL", labels));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(json: &str) -> Vec<Record> {
+        json.lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    fn session(provider: &str, cwd: &str, completed: bool, usage: Option<Usage>) -> SessionSummary {
+        let started = DateTime::parse_from_rfc3339("2026-01-10T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        SessionSummary {
+            id: "s".to_string(),
+            command: "loop".to_string(),
+            started,
+            ended: started,
+            provider: provider.to_string(),
+            cwd: PathBuf::from(cwd),
+            prompt_variant: None,
+            prompt_hash: None,
+            label: None,
+            iterations: 2,
+            completed,
+            tasks_closed: 0,
+            usage,
+            iteration_details: vec![IterationSummary {
+                iteration: 1,
+                started: None,
+                ended: started,
+                exit_code: 0,
+                signal: None,
+                usage,
+                model: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn summarize_counts_iterations_usage_and_tasks() {
+        let summary = summarize("s", &records(concat!(
+            r#"{"ts":"2026-01-10T00:00:00Z","event":"session_start","session":"s","command":"run","provider":"claude","max_iterations":1,"cwd":"/repo"}"#,
+            "\n",
+            r#"{"ts":"2026-01-10T00:01:00Z","event":"iteration_end","iteration":1,"exit_code":0,"complete":false,"usage":{"input_tokens":10,"output_tokens":2,"cost_usd":0.5}}"#,
+            "\n",
+            r#"{"ts":"2026-01-10T00:01:00Z","event":"task_closed","id":"bd-1"}"#,
+            "\n",
+            r#"{"ts":"2026-01-10T00:01:00Z","event":"session_end","iterations":1,"completed":true}"#,
+        )))
+        .unwrap();
+        assert_eq!(summary.iterations, 1);
+        assert!(summary.completed);
+        assert_eq!(summary.tasks_closed, 1);
+        assert_eq!(summary.usage.unwrap().cost_usd, Some(0.5));
+    }
+
+    #[test]
+    fn watch_sessions_are_not_counted() {
+        let summary = summarize(
+            "s",
+            &records(
+                r#"{"ts":"2026-01-10T00:00:00Z","event":"session_start","session":"s","command":"watch","provider":"droid","max_iterations":10,"cwd":"/repo"}"#,
+            ),
+        );
+        assert_eq!(summary, None);
+    }
+
+    #[test]
+    fn sessions_without_usage_do_not_skew_cost() {
+        let usage = Usage {
+            input_tokens: 100,
+            output_tokens: 10,
+            cost_usd: Some(1.0),
+        };
+        let summaries = [
+            session("claude", "/a", true, Some(usage)),
+            session("droid", "/a", false, None),
+        ];
+        let report = aggregate(
+            &summaries,
+            None,
+            &StatsOptions::default(),
+            &Pricing::default(),
+        );
+        assert_eq!(report.totals.runs, 2);
+        assert_eq!(report.totals.success_rate, 0.5);
+        assert_eq!(report.totals.runs_with_cost, 1);
+        assert_eq!(report.totals.cost_usd, 1.0);
+        assert_eq!(report.by_provider["droid"].runs_with_usage, 0);
+        assert!(report.by_repo.is_none());
+    }
+
+    #[test]
+    fn since_and_by_repo() {
+        let mut old = session("claude", "/old", true, None);
+        old.started = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let summaries = [old, session("claude", "/new", true, None)];
+        let since = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let opts = StatsOptions {
+            by_repo: true,
+            ..StatsOptions::default()
+        };
+        let report = aggregate(&summaries, Some(since), &opts, &Pricing::default());
+        assert_eq!(report.totals.runs, 1);
+        let repos: Vec<_> = report.by_repo.unwrap().into_keys().collect();
+        assert_eq!(repos, ["/new"]);
+    }
+
+    #[test]
+    fn costs_come_from_the_pricing_table_when_the_model_has_a_price() {
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cost_usd: Some(9.0),
+        };
+        let mut priced = session("claude", "/a", true, Some(usage));
+        priced.iteration_details[0].model = Some("claude-sonnet-4-5-20250929".to_string());
+        let mut unpriced = session("codex", "/a", true, Some(usage));
+        unpriced.iteration_details[0].model = Some("o3".to_string());
+        let unknown = session(
+            "gemini",
+            "/a",
+            true,
+            Some(Usage {
+                cost_usd: None,
+                ..usage
+            }),
+        );
+
+        let report = aggregate(
+            &[priced, unpriced, unknown],
+            None,
+            &StatsOptions::default(),
+            &Pricing::builtin(),
+        );
+        // $3 + $1.50 at the table's price, plus o3's reported $9.
+        assert_eq!(report.by_provider["claude"].cost_usd, 4.5);
+        assert_eq!(report.totals.cost_usd, 13.5);
+        assert_eq!(report.totals.runs_with_cost, 2);
+        assert_eq!(report.unpriced_models, ["o3"]);
+        let text = render_text(&report);
+        assert!(
+            text.contains("Cost:          $13.50 (avg $6.75 over 2 runs with a known cost)\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("Unpriced:      o3 (add with `ralph pricing set`)\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn variants_compare_success_iterations_and_prompts() {
+        let usage = Usage {
+            input_tokens: 0,
+            output_tokens: 0,
+            cost_usd: Some(0.5),
+        };
+        let run = |variant: Option<&str>, hash: &str, completed: bool, iterations: u32| {
+            let mut s = session("claude", "/a", completed, Some(usage));
+            s.prompt_variant = variant.map(str::to_string);
+            s.prompt_hash = Some(hash.to_string());
+            s.iterations = iterations;
+            s.tasks_closed = u32::from(completed);
+            s
+        };
+        let summaries = [
+            run(Some("terse"), "aaaaaaaaaaaa", true, 2),
+            run(Some("terse"), "aaaaaaaaaaaa", true, 4),
+            run(Some("terse"), "aaaaaaaaaaaa", false, 10),
+            run(Some("verbose"), "bbbbbbbbbbbb", false, 10),
+            run(Some("verbose"), "cccccccccccc", true, 5),
+            run(None, "dddddddddddd", true, 1),
+        ];
+        let opts = StatsOptions {
+            by_variant: true,
+            ..StatsOptions::default()
+        };
+        let report = aggregate(&summaries, None, &opts, &Pricing::default());
+        let variants = report.by_variant.as_ref().unwrap();
+        assert_eq!(
+            variants.keys().collect::<Vec<_>>(),
+            [NO_VARIANT, "terse", "verbose"]
+        );
+        let terse = &variants["terse"];
+        assert_eq!(
+            (terse.runs, terse.successful, terse.tasks_closed),
+            (3, 2, 2)
+        );
+        assert_eq!(terse.avg_iterations_to_complete, Some(3.0));
+        assert_eq!(terse.cost_usd, 1.5);
+        assert_eq!(variants["verbose"].avg_iterations_to_complete, Some(5.0));
+        assert_eq!(report.variant_prompts.as_ref().unwrap()["terse"].len(), 1);
+
+        let text = render_text(&report);
+        assert!(
+            text.contains("\nterse        3      16     66.7%        3.0       2"),
+            "{text}"
+        );
+        assert!(
+            text.ends_with("Variant verbose ran with 2 different prompts (bbbbbbbb, cccccccc)\n"),
+            "{text}"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["by_variant"]["verbose"]["runs"], 2);
+        assert!(json.get("by_repo").is_none());
+    }
+
+    #[test]
+    fn labels_get_a_table_of_their_own() {
+        let labelled = |label: Option<&str>, completed| {
+            let mut s = session("claude", "/a", completed, None);
+            s.label = label.map(str::to_string);
+            s
+        };
+        let summaries = [
+            labelled(Some("nightly"), true),
+            labelled(Some("nightly"), false),
+            labelled(None, true),
+        ];
+        let opts = StatsOptions {
+            by_label: true,
+            ..StatsOptions::default()
+        };
+        let report = aggregate(&summaries, None, &opts, &Pricing::default());
+        let labels = report.by_label.as_ref().unwrap();
+        assert_eq!(labels["nightly"].runs, 2);
+        assert_eq!(labels["nightly"].success_rate, 0.5);
+        assert_eq!(labels[NO_LABEL].runs, 1);
+        let text = render_text(&report);
+        assert!(text.contains("\nLABEL     RUNS  "), "{text}");
+        assert!(text.contains("\nnightly      2  "), "{text}");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::config::{self, Overrides};
+pub use crate::config::{Setting, Source};
+use crate::error::Result;
+use crate::session;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The system prompt file.
+#[derive(Debug, Serialize)]
+pub struct PromptStatus {
+    /// Path of the prompt file
+    pub path: PathBuf,
+    /// Whether it exists
+    pub exists: bool,
+    /// Size, when it exists
+    pub size_bytes: Option<u64>,
+    /// First non-empty line, when it exists
+    pub first_line: Option<String>,
+}
+
+/// Snapshot of the configuration `once`/`loop` would run with.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    /// `~/.Ralph`
+    pub config_dir: PathBuf,
+    /// `~/.Ralph/config.toml`
+    pub config_file: PathBuf,
+    /// Whether the global config file exists
+    pub config_file_exists: bool,
+    /// `./.ralph`, when present
+    pub project_dir: Option<PathBuf>,
+    /// The system prompt
+    pub prompt: PromptStatus,
+    /// Provider `once`/`loop` would use
+    pub provider: Setting<String>,
+    /// Always `None` for now: ralph leaves model selection to the provider.
+    pub model: Option<String>,
+    /// Iteration budget `loop` would use
+    pub iterations: Setting<u32>,
+    /// Whether `once`/`loop` mention newer releases
+    pub update_check: Setting<bool>,
+    /// Task tracker the agent works through
+    pub tracker: Setting<String>,
+    /// Whether a run sets up a missing beads database without asking
+    pub auto_init_bd: Setting<bool>,
+    /// Age threshold `clean` would use
+    pub clean_older_than: Setting<String>,
+    /// Commands a loop runs after every iteration
+    pub gates: Setting<Vec<String>>,
+    /// Marker that ends a loop early, with where it was set
+    pub marker: Setting<String>,
+    /// Markers that end a loop early: just [`marker`](Self::marker)'s value
+    pub completion_markers: Vec<String>,
+    /// Where per-iteration output goes
+    pub logs_dir: PathBuf,
+    /// Where session transcripts go
+    pub sessions_dir: PathBuf,
+}
+
+/// Gather the status report. Strictly read-only: nothing is created.
+pub fn collect() -> Result<StatusReport> {
+    let config_dir = config::get_config_dir()?;
+    let config_file = config::get_config_file_path()?;
+    let project_dir = config::find_project_dir();
+    let effective = config::resolve(&Overrides::default())?;
+
+    Ok(StatusReport {
+        config_file_exists: config_file.is_file(),
+        config_dir,
+        config_file,
+        project_dir,
+        prompt: prompt_status(&config::get_system_prompt_path()?),
+        provider: effective.provider,
+        model: None,
+        iterations: effective.iterations,
+        update_check: effective.update_check,
+        tracker: effective.tracker,
+        auto_init_bd: effective.auto_init_bd,
+        clean_older_than: effective.clean_older_than,
+        gates: effective.gates,
+        completion_markers: vec![effective.marker.value.clone()],
+        marker: effective.marker,
+        logs_dir: session::get_logs_dir()?,
+        sessions_dir: session::get_sessions_dir()?,
+    })
+}
+
+fn prompt_status(path: &Path) -> PromptStatus {
+    match fs::read_to_string(path) {
+        Ok(content) => PromptStatus {
+            path: path.to_path_buf(),
+            exists: true,
+            size_bytes: Some(content.len() as u64),
+            first_line: content.lines().next().map(|l| l.to_string()),
+        },
+        Err(_) => PromptStatus {
+            path: path.to_path_buf(),
+            exists: false,
+            size_bytes: None,
+            first_line: None,
+        },
+    }
+}
+
+/// Render the report as human-readable text.
+pub fn render_text(report: &StatusReport) -> String {
+    let mut lines = vec![
+        format!("Config directory:   {}", report.config_dir.display()),
+        format!(
+            "Config file:        {}{}",
+            report.config_file.display(),
+            if report.config_file_exists {
+                ""
+            } else {
+                " (not found)"
+            }
+        ),
+        format!(
+            "Project config:     {}",
+            match &report.project_dir {
+                Some(dir) => format!("{} (active)", dir.display()),
+                None => "none".to_string(),
+            }
+        ),
+    ];
+
+    match report.prompt.size_bytes {
+        Some(size) => {
+            lines.push(format!(
+                "System prompt:      {} ({} bytes)",
+                report.prompt.path.display(),
+                size
+            ));
+            lines.push(format!(
+                "  First line:       {}",
+                report.prompt.first_line.as_deref().unwrap_or("")
+            ));
+        }
+        None => lines.push(format!(
+            "System prompt:      {} (not found, default will be created on first run)",
+            report.prompt.path.display()
+        )),
+    }
+
+    lines.push(format!(
+        "Provider:           {} ({})",
+        report.provider.value, report.provider.source
+    ));
+    lines.push(format!(
+        "Model:              {}",
+        report.model.as_deref().unwrap_or("(provider default)")
+    ));
+    lines.push(format!(
+        "Iterations:         {} ({})",
+        report.iterations.value, report.iterations.source
+    ));
+    lines.push(format!(
+        "Update check:       {} ({})",
+        report.update_check.value, report.update_check.source
+    ));
+    lines.push(format!(
+        "Tracker:            {} ({})",
+        report.tracker.value, report.tracker.source
+    ));
+    lines.push(format!(
+        "Auto-init bd:       {} ({})",
+        report.auto_init_bd.value, report.auto_init_bd.source
+    ));
+    lines.push(format!(
+        "Clean older than:   {} ({})",
+        report.clean_older_than.value, report.clean_older_than.source
+    ));
+    lines.push(format!(
+        "Gates:              {} ({})",
+        match report.gates.value.is_empty() {
+            true => "none".to_string(),
+            false => report.gates.value.join("; "),
+        },
+        report.gates.source
+    ));
+    lines.push(format!(
+        "Completion markers: {} ({})",
+        report.completion_markers.join(", "),
+        report.marker.source
+    ));
+    lines.push(format!("Log directory:      {}", report.logs_dir.display()));
+    lines.push(format!(
+        "Session directory:  {}",
+        report.sessions_dir.display()
+    ));
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_status_reports_size_and_first_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system-prompt.md");
+        fs::write(&path, "first\nsecond\n").unwrap();
+        let status = prompt_status(&path);
+        assert!(status.exists);
+        assert_eq!(status.size_bytes, Some(13));
+        assert_eq!(status.first_line.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn prompt_status_missing_file_does_not_create_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system-prompt.md");
+        let status = prompt_status(&path);
+        assert!(!status.exists);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn json_uses_snake_case_sources() {
+        let setting = Setting {
+            value: 10,
+            source: Source::GlobalConfig,
+        };
+        let json = serde_json::to_string(&setting).unwrap();
+        assert_eq!(json, r#"{"value":10,"source":"global_config"}"#);
+    }
+}
@@ -0,0 +1,1316 @@
+use crate::encoding;
+use crate::exec::{self, CancelToken, Output};
+use crate::session::StderrLog;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a waiting [`ProviderRun::next_event`] checks whether the child
+/// has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long output is still drained after the child exits. A grandchild
+/// that inherited the pipes could otherwise keep the run open forever.
+const DRAIN_GRACE: Duration = Duration::from_millis(200);
+
+/// How long a terminated provider and its descendants get to exit before
+/// they are killed.
+pub const TERMINATE_GRACE: Duration = Duration::from_secs(3);
+
+/// How long a provider that printed a login phrase must stay quiet before
+/// it counts as waiting for a login (see [`LoginWatch`]).
+pub const LOGIN_QUIET: Duration = Duration::from_secs(2);
+
+/// Longest unfinished line reported as [`RunEvent::Unfinished`]. Questions
+/// waiting for input are short; longer partial lines are just output still
+/// arriving.
+const UNFINISHED_MAX: usize = 1024;
+
+/// What a provider's stderr lines are prefixed with where they're passed
+/// through, so they stand apart from ralph's own messages. The captured
+/// stderr and its log keep the lines as written.
+pub const STDERR_PREFIX: &str = "[stderr] ";
+
+/// Default for [`LineLimit::max`]: 4 MiB.
+pub const DEFAULT_MAX_LINE: usize = 4 * 1024 * 1024;
+
+/// How lines longer than anyone will read are handled. Some providers
+/// print a whole bundled file as one stream-json event, hundreds of MB on
+/// a single line; past `max` bytes only the head of such a line is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLimit {
+    /// Longest line passed on whole, in bytes
+    pub max: usize,
+    /// Still write over-long stdout lines whole to the captured output
+    /// log, streamed through as [`RunEvent::LongLinePart`]s
+    pub keep_full: bool,
+}
+
+impl Default for LineLimit {
+    fn default() -> Self {
+        LineLimit {
+            max: DEFAULT_MAX_LINE,
+            keep_full: false,
+        }
+    }
+}
+
+static LINE_LIMIT: OnceLock<LineLimit> = OnceLock::new();
+
+/// Set the [`LineLimit`] for providers spawned from now on. The first call
+/// wins; without one, [`LineLimit::default`] applies.
+pub fn set_line_limit(limit: LineLimit) {
+    let _ = LINE_LIMIT.set(limit);
+}
+
+fn line_limit() -> LineLimit {
+    LINE_LIMIT.get().copied().unwrap_or_default()
+}
+
+/// Default for [`set_collapse_repeats`].
+pub const DEFAULT_COLLAPSE_REPEATS: usize = 5;
+
+static COLLAPSE_REPEATS: OnceLock<usize> = OnceLock::new();
+
+/// Show and log a run of at least `threshold` identical consecutive lines
+/// from providers spawned from now on as the line once, followed by a
+/// [`repeat_note`]; 0 shows every line. What the loop matches markers
+/// against still has every line. The first call wins; without one,
+/// [`DEFAULT_COLLAPSE_REPEATS`] applies.
+pub fn set_collapse_repeats(threshold: usize) {
+    let _ = COLLAPSE_REPEATS.set(threshold);
+}
+
+fn collapse_repeats() -> usize {
+    COLLAPSE_REPEATS
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_COLLAPSE_REPEATS)
+}
+
+/// Default for [`set_max_output`]: 512 MiB.
+pub const DEFAULT_MAX_OUTPUT: u64 = 512 * 1024 * 1024;
+
+/// Exit code of `ralph once` when the provider crossed the output limit.
+pub const EXIT_OUTPUT_LIMIT: u8 = 3;
+
+static MAX_OUTPUT: OnceLock<u64> = OnceLock::new();
+
+/// Kill providers spawned from now on once a single run has printed more
+/// than `bytes` on stdout and stderr together. What was captured ends at
+/// the limit, followed by an [`output_limit_note`], and the run's
+/// [`Output::output_limit`] is set. The first call wins; without one,
+/// [`DEFAULT_MAX_OUTPUT`] applies.
+pub fn set_max_output(bytes: u64) {
+    let _ = MAX_OUTPUT.set(bytes);
+}
+
+fn max_output() -> u64 {
+    MAX_OUTPUT.get().copied().unwrap_or(DEFAULT_MAX_OUTPUT)
+}
+
+/// Exit code of `ralph once` when the provider ran past `--timeout`, as
+/// `timeout(1)` exits.
+pub const EXIT_TIMEOUT: u8 = 124;
+
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Kill providers spawned from now on, with their process group, once a
+/// single run has gone on for `timeout`, setting the run's
+/// [`Output::timed_out`]. The first call wins; without one, providers may
+/// run as long as they like.
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+/// What's shown and logged where a provider's output crossed the limit of
+/// `limit` bytes.
+pub fn output_limit_note(limit: u64) -> String {
+    format!("[ralph: output limit of {limit} bytes exceeded; the rest was discarded]")
+}
+
+/// Something a running provider did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEvent {
+    /// A line printed on stdout, without its newline
+    Stdout(String),
+    /// A line printed on stderr, without its newline
+    Stderr(String),
+    /// A line longer than [`LineLimit::max`], in place of
+    /// [`RunEvent::Stdout`] or [`RunEvent::Stderr`]
+    LongLine {
+        /// Printed on stderr rather than stdout
+        stderr: bool,
+        /// Its first [`LineLimit::max`] bytes, decoded
+        head: String,
+        /// Its full length in bytes, without the newline
+        len: usize,
+    },
+    /// The next raw bytes of a stdout line that is turning out to be a
+    /// [`RunEvent::LongLine`], when [`LineLimit::keep_full`] is set. The
+    /// `LongLine` follows the last of them.
+    LongLinePart(Vec<u8>),
+    /// Text printed on either stream without a newline yet, such as a
+    /// question waiting for an answer. The whole line follows as
+    /// [`RunEvent::Stdout`] or [`RunEvent::Stderr`] once it's finished.
+    Unfinished(String),
+    /// The process exited with this code. This is the last event.
+    Exited(i32),
+    /// The process was killed by this signal (unix). This is the last
+    /// event.
+    Killed(i32),
+}
+
+/// Which pipe a reader thread is draining.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Stdout,
+    Stderr,
+}
+
+/// What a reader thread passes on.
+#[derive(Debug)]
+enum Chunk {
+    Line(String),
+    Unfinished(String),
+    LongPart(Vec<u8>),
+    LongEnd { head: String, len: usize },
+    End,
+}
+
+/// A spawned provider whose stdout and stderr are read on one thread each
+/// and merged into a single stream of [`RunEvent`]s. Waiting for the next
+/// event takes a timeout, so callers can enforce deadlines, notice Ctrl-C
+/// or redraw progress between lines, and stop the process at any point.
+///
+/// Every way of stopping a provider goes through [`terminate`] or
+/// [`kill`], which take its descendants (dev servers, watchers) down with
+/// it. Dropping a run that hasn't exited kills it.
+///
+/// [`terminate`]: ProviderRun::terminate
+/// [`kill`]: ProviderRun::kill
+#[derive(Debug)]
+pub struct ProviderRun {
+    child: Child,
+    events: Receiver<(Source, Chunk)>,
+    open_streams: usize,
+    exit: Option<(ExitStatus, Instant)>,
+    finished: bool,
+    /// Whether the child leads its own process group (unix)
+    own_group: bool,
+    /// The job holding the child and everything it starts (Windows)
+    #[cfg(windows)]
+    job: Option<Job>,
+}
+
+impl ProviderRun {
+    /// Spawn `cmd` with stdout and stderr piped. On unix it becomes the
+    /// leader of a new process group, so signals reach everything it
+    /// starts; on Windows it goes in a [`Job`] for the same reason.
+    pub fn spawn(cmd: &mut Command) -> io::Result<ProviderRun> {
+        ProviderRun::spawn_with_limit(cmd, line_limit())
+    }
+
+    /// [`spawn`](ProviderRun::spawn), cutting lines at `limit` rather than
+    /// the one set with [`set_line_limit`].
+    pub fn spawn_with_limit(cmd: &mut Command, limit: LineLimit) -> io::Result<ProviderRun> {
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(cmd, 0);
+        let child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        #[cfg(windows)]
+        let job = Job::assign(&child);
+        let mut run = ProviderRun::from_child_with_limit(child, limit);
+        run.own_group = cfg!(unix);
+        #[cfg(windows)]
+        {
+            run.job = job;
+        }
+        Ok(run)
+    }
+
+    /// Take over an already spawned child, reading whichever of its stdout
+    /// and stderr were piped. Only the child itself is signalled when
+    /// stopping it.
+    pub fn from_child(child: Child) -> ProviderRun {
+        ProviderRun::from_child_with_limit(child, line_limit())
+    }
+
+    fn from_child_with_limit(mut child: Child, limit: LineLimit) -> ProviderRun {
+        let (tx, events) = mpsc::channel();
+        let mut open_streams = 0;
+        if let Some(stdout) = child.stdout.take() {
+            read_lines(stdout, Source::Stdout, tx.clone(), limit);
+            open_streams += 1;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            read_lines(stderr, Source::Stderr, tx, limit);
+            open_streams += 1;
+        }
+        ProviderRun {
+            child,
+            events,
+            open_streams,
+            exit: None,
+            finished: false,
+            own_group: false,
+            #[cfg(windows)]
+            job: None,
+        }
+    }
+
+    /// OS process id of the provider.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait up to `timeout` for the next event. `None` means nothing
+    /// happened in time, or the run already reported [`RunEvent::Exited`].
+    pub fn next_event(&mut self, timeout: Duration) -> Option<RunEvent> {
+        let deadline = Instant::now() + timeout;
+        while !self.finished {
+            if let Some((status, exited_at)) = self.exit
+                && (self.open_streams == 0 || exited_at.elapsed() >= DRAIN_GRACE)
+            {
+                // Lines already read still come first.
+                while let Ok((source, chunk)) = self.events.try_recv() {
+                    if !matches!(chunk, Chunk::Unfinished(_))
+                        && let Some(event) = chunk_event(source, chunk)
+                    {
+                        return Some(event);
+                    }
+                }
+                self.finished = true;
+                return Some(match exec::exit_status(status) {
+                    (_, Some(signal)) => RunEvent::Killed(signal),
+                    (code, None) => RunEvent::Exited(code),
+                });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            let wait = POLL_INTERVAL.min(deadline - now);
+            match self.events.recv_timeout(wait) {
+                Ok((_, Chunk::End)) => self.open_streams -= 1,
+                Ok((source, chunk)) => return chunk_event(source, chunk),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.open_streams = 0;
+                    thread::sleep(wait);
+                }
+            }
+
+            self.reap();
+        }
+        None
+    }
+
+    /// Record the exit status if the child has exited, so it no longer
+    /// counts as running.
+    fn reap(&mut self) {
+        if self.exit.is_none()
+            && let Some(status) = self.child.try_wait().ok().flatten()
+        {
+            self.exit = Some((status, Instant::now()));
+        }
+    }
+
+    /// Ask the provider and its descendants to stop (SIGTERM to the process
+    /// group), then kill whatever is left after `grace`. Blocks for at most
+    /// `grace`. Its [`RunEvent::Exited`] still follows.
+    #[cfg(unix)]
+    pub fn terminate(&mut self, grace: Duration) -> io::Result<()> {
+        self.signal(libc::SIGTERM)?;
+        let deadline = Instant::now() + grace;
+        while self.alive() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        if self.alive() {
+            self.signal(libc::SIGKILL)?;
+        }
+        Ok(())
+    }
+
+    /// Stop the provider and its descendants. Windows has no SIGTERM to
+    /// send, so this kills the tree straight away.
+    #[cfg(windows)]
+    pub fn terminate(&mut self, _grace: Duration) -> io::Result<()> {
+        self.kill()
+    }
+
+    /// Kill the provider and its descendants right away. Its
+    /// [`RunEvent::Exited`] still follows.
+    #[cfg(unix)]
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Kill the provider and its descendants right away, through its
+    /// [`Job`] when it has one, so even those whose parent has exited go
+    /// too. Its [`RunEvent::Exited`] still follows.
+    #[cfg(windows)]
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.reap();
+        if let Some(job) = self.job.take()
+            && job.terminate().is_ok()
+        {
+            return Ok(());
+        }
+        if self.exit.is_some() {
+            return Ok(());
+        }
+        // Without a job, the tree as far as live parents lead.
+        let tree_killed = Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &self.child.id().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success());
+        if tree_killed {
+            return Ok(());
+        }
+        match self.child.kill() {
+            // Exited in the meantime.
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => Ok(()),
+            result => result,
+        }
+    }
+
+    /// The process group when the child leads one, else the child itself
+    /// (or nothing once it has been reaped, since its pid may be reused).
+    #[cfg(unix)]
+    fn target(&mut self) -> Option<libc::pid_t> {
+        let pid = libc::pid_t::try_from(self.child.id()).ok()?;
+        if self.own_group {
+            return Some(-pid);
+        }
+        self.reap();
+        self.exit.is_none().then_some(pid)
+    }
+
+    #[cfg(unix)]
+    fn signal(&mut self, signal: libc::c_int) -> io::Result<()> {
+        let Some(target) = self.target() else {
+            return Ok(());
+        };
+        // SAFETY: kill(2) has no memory-safety preconditions.
+        if unsafe { libc::kill(target, signal) } == 0 {
+            return Ok(());
+        }
+        match io::Error::last_os_error() {
+            // Everything has exited already.
+            e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            e => Err(e),
+        }
+    }
+
+    /// Whether the child or any process left in its group is still running.
+    #[cfg(unix)]
+    fn alive(&mut self) -> bool {
+        // Reap first: an exited but unreaped leader would still count.
+        self.reap();
+        match self.target() {
+            // SAFETY: signal 0 only checks that the target exists.
+            Some(target) => (unsafe { libc::kill(target, 0) }) == 0,
+            None => false,
+        }
+    }
+}
+
+impl Drop for ProviderRun {
+    fn drop(&mut self) {
+        self.reap();
+        if self.exit.is_none() {
+            let _ = self.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// A Windows Job Object holding a provider and every process it starts,
+/// which Windows adds to the job whatever becomes of their parents, so a
+/// dev server whose launching shell has exited still goes with it
+/// (`taskkill /T` only follows live parents). Closing it, on
+/// [`ProviderRun::kill`] or when the run is dropped, kills whatever is
+/// still in it (`JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE`).
+#[cfg(windows)]
+#[derive(Debug)]
+struct Job(windows_sys::Win32::Foundation::HANDLE);
+
+// SAFETY: a job handle may be used and closed from any thread.
+#[cfg(windows)]
+unsafe impl Send for Job {}
+
+#[cfg(windows)]
+impl Job {
+    /// Put `child` in a new job, or `None` when Windows refuses, leaving
+    /// [`ProviderRun::kill`] to `taskkill /T`. Anything the child starts
+    /// in the moment before it's assigned isn't in the job.
+    fn assign(child: &Child) -> Option<Job> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::System::JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+            SetInformationJobObject,
+        };
+        // SAFETY: null attributes and name ask for a default, unnamed job.
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return None;
+        }
+        // Closed again by its drop if the rest fails.
+        let job = Job(handle);
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        // SAFETY: `limits` is the structure the information class names,
+        // with its size, and both handles are open.
+        let assigned = unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                (&raw const limits).cast(),
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            ) != 0
+                && AssignProcessToJobObject(job.0, child.as_raw_handle()) != 0
+        };
+        assigned.then_some(job)
+    }
+
+    /// Kill every process in the job.
+    fn terminate(&self) -> io::Result<()> {
+        // SAFETY: the handle is open until drop.
+        match unsafe { windows_sys::Win32::System::JobObjects::TerminateJobObject(self.0, 1) } {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: the handle is open, and closed only here.
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+/// The event for what a reader thread sent; `None` for [`Chunk::End`].
+fn chunk_event(source: Source, chunk: Chunk) -> Option<RunEvent> {
+    let stderr = matches!(source, Source::Stderr);
+    Some(match chunk {
+        Chunk::Line(line) if stderr => RunEvent::Stderr(line),
+        Chunk::Line(line) => RunEvent::Stdout(line),
+        Chunk::Unfinished(text) => RunEvent::Unfinished(text),
+        Chunk::LongPart(bytes) => RunEvent::LongLinePart(bytes),
+        Chunk::LongEnd { head, len } => RunEvent::LongLine { stderr, head, len },
+        Chunk::End => return None,
+    })
+}
+
+/// Send each line of `pipe` (decoded with [`encoding::decode`]) on `tx`,
+/// then [`Chunk::End`] at EOF. A short line left unfinished after a read
+/// is sent as [`Chunk::Unfinished`] too, so a prompt waiting for input is
+/// seen. A line growing past `limit.max` is cut: only its head is kept,
+/// and the rest is counted (and sent on as [`Chunk::LongPart`]s when
+/// `limit.keep_full` is set, for stdout) until its newline, so memory
+/// stays bounded however long the line is.
+fn read_lines(
+    mut pipe: impl Read + Send + 'static,
+    source: Source,
+    tx: Sender<(Source, Chunk)>,
+    limit: LineLimit,
+) {
+    // Only stdout has a log that keeps lines whole.
+    let keep_full = limit.keep_full && matches!(source, Source::Stdout);
+    thread::spawn(move || {
+        let mut pending = Vec::new();
+        // The head and length so far of a line over the limit
+        let mut long: Option<(String, usize)> = None;
+        let mut buf = vec![0; 8192];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let mut data = &buf[..n];
+            while !data.is_empty() {
+                let newline = data.iter().position(|b| *b == b'\n');
+                let (part, rest) = match newline {
+                    Some(pos) => (&data[..pos], &data[pos + 1..]),
+                    None => (data, &[][..]),
+                };
+                data = rest;
+                if let Some((_, len)) = long.as_mut() {
+                    *len += part.len();
+                    if keep_full
+                        && !part.is_empty()
+                        && tx.send((source, Chunk::LongPart(part.to_vec()))).is_err()
+                    {
+                        return;
+                    }
+                } else {
+                    pending.extend_from_slice(part);
+                }
+                if long.is_none() && pending.len() > limit.max {
+                    let head = encoding::decode(&pending[..limit.max]);
+                    long = Some((head, pending.len()));
+                    let bytes = std::mem::take(&mut pending);
+                    if keep_full && tx.send((source, Chunk::LongPart(bytes))).is_err() {
+                        return;
+                    }
+                }
+                if newline.is_none() {
+                    continue;
+                }
+                let chunk = match long.take() {
+                    Some((head, len)) => Chunk::LongEnd { head, len },
+                    None => Chunk::Line(encoding::decode(&pending)),
+                };
+                pending.clear();
+                if tx.send((source, chunk)).is_err() {
+                    return;
+                }
+            }
+            if long.is_none() && !pending.is_empty() && pending.len() <= UNFINISHED_MAX {
+                let text = encoding::decode(&pending);
+                if tx.send((source, Chunk::Unfinished(text))).is_err() {
+                    return;
+                }
+            }
+        }
+        if let Some((head, len)) = long {
+            let _ = tx.send((source, Chunk::LongEnd { head, len }));
+        } else if !pending.is_empty() {
+            let line = encoding::decode(&pending);
+            let _ = tx.send((source, Chunk::Line(line)));
+        }
+        let _ = tx.send((source, Chunk::End));
+    });
+}
+
+/// Recognizes a provider that stopped to wait for an interactive login
+/// ("Press Enter to open your browser"), so a headless run fails fast
+/// instead of hanging: one of the phrases shows up before the provider's
+/// first stream-json event, and stdout stays quiet for a while after.
+#[derive(Debug, Clone)]
+pub struct LoginWatch {
+    phrases: Vec<String>,
+    quiet: Duration,
+}
+
+impl LoginWatch {
+    /// Watch for `phrases`, matched case-insensitively anywhere in a line,
+    /// with [`LOGIN_QUIET`] as the quiet period.
+    pub fn new<S: AsRef<str>>(phrases: impl IntoIterator<Item = S>) -> Self {
+        LoginWatch {
+            phrases: phrases
+                .into_iter()
+                .map(|p| p.as_ref().trim().to_lowercase())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            quiet: LOGIN_QUIET,
+        }
+    }
+
+    /// How long stdout must stay quiet after a phrase.
+    pub fn quiet(mut self, quiet: Duration) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Whether `text` contains one of the phrases.
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.phrases.iter().any(|p| text.contains(p.as_str()))
+    }
+}
+
+/// [`LoginWatch`] applied to one run.
+struct LoginState<'a> {
+    watch: &'a LoginWatch,
+    /// The provider's structured output has started: it's past any login
+    started: bool,
+    /// The text that matched, and when
+    suspect: Option<(String, Instant)>,
+}
+
+impl LoginState<'_> {
+    fn observe(&mut self, event: &RunEvent) {
+        if self.started {
+            return;
+        }
+        let (text, stdout) = match event {
+            RunEvent::Stdout(line) => (line, true),
+            RunEvent::Stderr(line) | RunEvent::Unfinished(line) => (line, false),
+            _ => return,
+        };
+        if stdout && text.trim_start().starts_with('{') {
+            self.started = true;
+            self.suspect = None;
+        } else if self.watch.matches(text) {
+            self.suspect
+                .get_or_insert_with(|| (text.trim().to_string(), Instant::now()));
+        } else if stdout {
+            self.suspect = None;
+        }
+    }
+
+    /// The matched text, once stdout has been quiet long enough after it.
+    fn waiting(&self) -> Option<&str> {
+        self.suspect
+            .as_ref()
+            .filter(|(_, since)| since.elapsed() >= self.watch.quiet)
+            .map(|(text, _)| text.as_str())
+    }
+}
+
+/// What's shown (and logged, unless the line is kept whole) in place of
+/// a [`RunEvent::LongLine`] of `len` bytes.
+pub fn long_line_note(len: usize) -> String {
+    format!("[ralph: {len}-byte line not shown]")
+}
+
+static ECHO: AtomicBool = AtomicBool::new(true);
+
+/// Whether provider output is printed as it arrives (the default). It is
+/// still captured and logged either way; `ralph prompt test` turns it off
+/// to print nothing but its verdict.
+pub fn set_echo(echo: bool) {
+    ECHO.store(echo, Ordering::Relaxed);
+}
+
+fn echo() -> bool {
+    ECHO.load(Ordering::Relaxed)
+}
+
+/// What's shown (and logged) after a line that came `count` times in a
+/// row, in place of the copies.
+pub fn repeat_note(count: usize) -> String {
+    format!("[ralph: repeated {count}\u{d7}]")
+}
+
+/// Collapses runs of identical consecutive lines on one stream. The first
+/// line of a run goes through at once; the copies are held back until the
+/// run ends, then either go through too (a run shorter than the
+/// threshold) or are replaced by a [`repeat_note`]. Blank lines are never
+/// collapsed.
+#[derive(Debug)]
+struct Repeats {
+    threshold: usize,
+    line: String,
+    count: usize,
+}
+
+impl Repeats {
+    fn new(threshold: usize) -> Self {
+        Repeats {
+            threshold,
+            line: String::new(),
+            count: 0,
+        }
+    }
+
+    /// The lines to show for `line`: none while it continues a run.
+    fn push(&mut self, line: &str) -> Vec<String> {
+        if self.threshold == 0 {
+            return vec![line.to_string()];
+        }
+        if self.count > 0 && line == self.line && !line.trim().is_empty() {
+            self.count += 1;
+            return Vec::new();
+        }
+        let mut shown = self.flush();
+        shown.push(line.to_string());
+        self.line = line.to_string();
+        self.count = 1;
+        shown
+    }
+
+    /// End the current run, returning what's left to show of it.
+    fn flush(&mut self) -> Vec<String> {
+        let count = std::mem::take(&mut self.count);
+        match count {
+            0 | 1 => Vec::new(),
+            n if n >= self.threshold => vec![repeat_note(n)],
+            n => vec![self.line.clone(); n - 1],
+        }
+    }
+}
+
+fn show_stdout(lines: Vec<String>, log: Option<&mut File>) -> io::Result<()> {
+    if echo() {
+        for line in &lines {
+            println!("{line}");
+        }
+    }
+    if let Some(file) = log {
+        for line in &lines {
+            writeln!(file, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+fn show_stderr(lines: Vec<String>, mut log: Option<&mut StderrLog>) -> io::Result<()> {
+    for line in &lines {
+        if echo() {
+            eprintln!("{STDERR_PREFIX}{line}");
+        }
+        if let Some(log) = log.as_mut() {
+            log.write_line(line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the child's stdout line by line while capturing it (and copying it
+/// to `log` when given), passing its stderr through (after
+/// [`STDERR_PREFIX`]) while capturing it too
+/// (into a [`StderrLog`] next to `log`), then wait for the child to exit.
+/// Returns how it exited and the captured output.
+/// The child is terminated on Ctrl-C (see
+/// [`runner::install_interrupt_handler`](crate::runner::install_interrupt_handler)).
+pub fn stream_output(child: Child, log: Option<&Path>) -> io::Result<Output> {
+    stream_run(
+        ProviderRun::from_child(child),
+        log,
+        &crate::runner::interrupt_token(),
+        None,
+    )
+}
+
+/// [`stream_output`] for a [`ProviderRun`], terminating the provider when
+/// `cancel` is cancelled, or when `login` sees it waiting for a login (see
+/// [`Output::login_prompt`]), and killing it once it prints more than the
+/// limit set with [`set_max_output`] or runs longer than the timeout set
+/// with [`set_timeout`]; how it exited is returned as usual.
+pub fn stream_run(
+    run: ProviderRun,
+    log: Option<&Path>,
+    cancel: &CancelToken,
+    login: Option<&LoginWatch>,
+) -> io::Result<Output> {
+    stream_run_limited(
+        run,
+        log,
+        cancel,
+        login,
+        max_output(),
+        TIMEOUT.get().copied(),
+    )
+}
+
+/// [`stream_run`] with an output limit of `max_output` bytes and a
+/// `timeout` rather than the ones set with [`set_max_output`] and
+/// [`set_timeout`].
+fn stream_run_limited(
+    mut run: ProviderRun,
+    log: Option<&Path>,
+    cancel: &CancelToken,
+    login: Option<&LoginWatch>,
+    max_output: u64,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut log_file = match log {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+
+    let mut stderr_log = log.map(StderrLog::new);
+    let mut stdout_repeats = Repeats::new(collapse_repeats());
+    let mut stderr_repeats = Repeats::new(collapse_repeats());
+    let mut output = Output::default();
+    let mut terminated = false;
+    // Whether the current long stdout line has gone to the log whole
+    let mut long_line_logged = false;
+    let mut login = login.map(|watch| LoginState {
+        watch,
+        started: false,
+        suspect: None,
+    });
+    let mut budget = OutputBudget::new(max_output);
+    loop {
+        let mut event = run.next_event(POLL_INTERVAL);
+        if let (Some(login), Some(event)) = (login.as_mut(), event.as_ref()) {
+            login.observe(event);
+        }
+        let crossed = match event.as_mut() {
+            Some(event) if output.output_limit.is_none() => budget.spend(event),
+            _ => false,
+        };
+        match event {
+            // Whatever the provider prints while being killed is dropped.
+            Some(
+                RunEvent::Stdout(_)
+                | RunEvent::Stderr(_)
+                | RunEvent::LongLine { .. }
+                | RunEvent::LongLinePart(_)
+                | RunEvent::Unfinished(_),
+            ) if output.output_limit.is_some() => continue,
+            Some(RunEvent::Stdout(line)) => {
+                show_stdout(stdout_repeats.push(&line), log_file.as_mut())?;
+                output.stdout.push_str(&line);
+                output.stdout.push('\n');
+            }
+            Some(RunEvent::Stderr(line)) => {
+                show_stderr(stderr_repeats.push(&line), stderr_log.as_mut())?;
+                output.stderr.push_str(&line);
+                output.stderr.push('\n');
+            }
+            Some(RunEvent::LongLinePart(bytes)) => {
+                show_stdout(stdout_repeats.flush(), log_file.as_mut())?;
+                if let Some(file) = log_file.as_mut() {
+                    file.write_all(&bytes)?;
+                    long_line_logged = true;
+                }
+            }
+            Some(RunEvent::LongLine { stderr, head, len }) => {
+                let note = long_line_note(len);
+                output.long_lines += 1;
+                if stderr {
+                    show_stderr(stderr_repeats.flush(), stderr_log.as_mut())?;
+                    if echo() {
+                        eprintln!("{STDERR_PREFIX}{note}");
+                    }
+                    if let Some(log) = stderr_log.as_mut() {
+                        log.write_line(&note)?;
+                    }
+                    output.stderr.push_str(&head);
+                    output.stderr.push('\n');
+                } else {
+                    show_stdout(stdout_repeats.flush(), log_file.as_mut())?;
+                    if echo() {
+                        println!("{note}");
+                    }
+                    if let Some(file) = log_file.as_mut() {
+                        if long_line_logged {
+                            writeln!(file)?;
+                        } else {
+                            writeln!(file, "{note}")?;
+                        }
+                    }
+                    long_line_logged = false;
+                    output.stdout.push_str(&head);
+                    output.stdout.push('\n');
+                }
+            }
+            Some(RunEvent::Unfinished(_)) => {}
+            Some(RunEvent::Exited(code)) => {
+                show_stdout(stdout_repeats.flush(), log_file.as_mut())?;
+                show_stderr(stderr_repeats.flush(), stderr_log.as_mut())?;
+                output.code = code;
+                return Ok(output);
+            }
+            Some(RunEvent::Killed(signal)) => {
+                show_stdout(stdout_repeats.flush(), log_file.as_mut())?;
+                show_stderr(stderr_repeats.flush(), stderr_log.as_mut())?;
+                output.code = 1;
+                output.signal = Some(signal);
+                return Ok(output);
+            }
+            None if !terminated && cancel.is_cancelled() => {
+                terminated = true;
+                run.terminate(TERMINATE_GRACE)?;
+            }
+            None if !terminated
+                && let Some(text) = login.as_ref().and_then(LoginState::waiting) =>
+            {
+                output.login_prompt = Some(text.to_string());
+                terminated = true;
+                run.terminate(TERMINATE_GRACE)?;
+            }
+            None => {}
+        }
+        if crossed {
+            let note = output_limit_note(budget.limit);
+            show_stdout(stdout_repeats.flush(), log_file.as_mut())?;
+            show_stderr(stderr_repeats.flush(), stderr_log.as_mut())?;
+            if echo() {
+                eprintln!("{note}");
+            }
+            if let Some(file) = log_file.as_mut() {
+                if long_line_logged {
+                    writeln!(file)?;
+                }
+                writeln!(file, "{note}")?;
+            }
+            output.output_limit = Some(budget.limit);
+            terminated = true;
+            run.kill()?;
+        }
+        // Checked after every event too, so a provider that keeps printing
+        // can't run past it.
+        if !terminated && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            output.timed_out = timeout;
+            terminated = true;
+            run.kill()?;
+        }
+    }
+}
+
+/// Counts what a provider prints against the limit set with
+/// [`set_max_output`].
+#[derive(Debug)]
+struct OutputBudget {
+    limit: u64,
+    left: u64,
+    /// Bytes of the current long line already counted as
+    /// [`RunEvent::LongLinePart`]s
+    long_line_parts: usize,
+}
+
+impl OutputBudget {
+    fn new(limit: u64) -> Self {
+        OutputBudget {
+            limit,
+            left: limit,
+            long_line_parts: 0,
+        }
+    }
+
+    /// Count `event` against the budget. When it doesn't fit, it's cut
+    /// down to what does and `true` is returned: the limit was crossed.
+    fn spend(&mut self, event: &mut RunEvent) -> bool {
+        let size = match event {
+            RunEvent::Stdout(line) | RunEvent::Stderr(line) => line.len() + 1,
+            RunEvent::LongLinePart(bytes) => {
+                self.long_line_parts += bytes.len();
+                bytes.len()
+            }
+            RunEvent::LongLine { len, .. } => {
+                (*len + 1).saturating_sub(std::mem::take(&mut self.long_line_parts))
+            }
+            _ => 0,
+        };
+        let fit = usize::try_from(self.left).map_or(size, |left| left.min(size));
+        self.left -= fit as u64;
+        if fit == size {
+            return false;
+        }
+        match event {
+            // Nothing of it fits, not even the newline: drop it.
+            RunEvent::Stdout(_) | RunEvent::Stderr(_) if fit == 0 => {
+                *event = RunEvent::Unfinished(String::new());
+            }
+            RunEvent::Stdout(line) | RunEvent::Stderr(line) => {
+                line.truncate(line.floor_char_boundary(fit));
+            }
+            RunEvent::LongLinePart(bytes) => bytes.truncate(fit),
+            _ => {}
+        }
+        true
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    fn collect(run: &mut ProviderRun) -> Vec<RunEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = run.next_event(Duration::from_secs(10)) {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn events_cover_both_streams_then_the_exit() {
+        let mut run = ProviderRun::spawn(&mut sh("echo out; echo err >&2; exit 4")).unwrap();
+        let events = collect(&mut run);
+        assert!(events.contains(&RunEvent::Stdout("out".to_string())));
+        assert!(events.contains(&RunEvent::Stderr("err".to_string())));
+        assert_eq!(events.last(), Some(&RunEvent::Exited(4)));
+    }
+
+    #[test]
+    fn timeouts_return_none_and_kill_ends_the_run() {
+        let mut run = ProviderRun::spawn(&mut sh("exec sleep 30")).unwrap();
+        assert_eq!(run.next_event(Duration::from_millis(100)), None);
+        run.kill().unwrap();
+        assert_eq!(collect(&mut run), [RunEvent::Killed(libc::SIGKILL)]);
+        run.kill().unwrap();
+    }
+
+    /// Whether `pid` is running; a zombie left for a non-reaping init to
+    /// collect counts as gone.
+    fn running(pid: libc::pid_t) -> bool {
+        let zombie = std::fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+            stat.rsplit(')')
+                .next()
+                .unwrap_or("")
+                .trim_start()
+                .starts_with('Z')
+        });
+        (unsafe { libc::kill(pid, 0) }) == 0 && !zombie
+    }
+
+    #[test]
+    fn terminate_takes_down_the_whole_process_group() {
+        let mut run = ProviderRun::spawn(&mut sh(
+            "trap '' TERM; sleep 300 >/dev/null 2>&1 & echo $!; wait",
+        ))
+        .unwrap();
+        let grandchild: libc::pid_t = match run.next_event(Duration::from_secs(10)) {
+            Some(RunEvent::Stdout(pid)) => pid.parse().unwrap(),
+            other => panic!("unexpected {other:?}"),
+        };
+        assert!(running(grandchild));
+
+        // The shell ignores SIGTERM, so this also exercises the SIGKILL.
+        let started = Instant::now();
+        run.terminate(Duration::from_millis(300)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(
+            collect(&mut run).last(),
+            Some(RunEvent::Killed(_))
+        ));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while running(grandchild) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert!(!running(grandchild), "grandchild {grandchild} survived");
+    }
+
+    #[test]
+    fn dropping_a_run_kills_it() {
+        let mut run = ProviderRun::spawn(&mut sh("echo $$; exec sleep 300")).unwrap();
+        let pid: libc::pid_t = match run.next_event(Duration::from_secs(10)) {
+            Some(RunEvent::Stdout(pid)) => pid.parse().unwrap(),
+            other => panic!("unexpected {other:?}"),
+        };
+        drop(run);
+        assert!(!running(pid));
+    }
+
+    #[test]
+    fn a_grandchild_holding_the_pipe_does_not_block_the_exit() {
+        let started = Instant::now();
+        let mut run = ProviderRun::spawn(&mut sh("sleep 30 & echo done")).unwrap();
+        let events = collect(&mut run);
+        assert_eq!(
+            events,
+            [RunEvent::Stdout("done".to_string()), RunEvent::Exited(0)]
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    fn login_watch() -> LoginWatch {
+        LoginWatch::new(["press enter to open"]).quiet(Duration::from_millis(300))
+    }
+
+    #[test]
+    fn a_login_prompt_without_a_newline_stops_the_run() {
+        let started = Instant::now();
+        let run = ProviderRun::spawn(&mut sh(
+            "printf 'Press Enter to open your browser' >&2; sleep 30",
+        ))
+        .unwrap();
+        let output = stream_run(run, None, &CancelToken::new(), Some(&login_watch())).unwrap();
+        assert_eq!(
+            output.login_prompt.as_deref(),
+            Some("Press Enter to open your browser")
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn login_phrases_after_structured_output_are_ignored() {
+        let run = ProviderRun::spawn(&mut sh(
+            "echo '{\"type\":\"message\"}'; echo 'Press Enter to open the docs' >&2; sleep 1",
+        ))
+        .unwrap();
+        let output = stream_run(run, None, &CancelToken::new(), Some(&login_watch())).unwrap();
+        assert_eq!(output.login_prompt, None);
+        assert!(output.success());
+    }
+
+    fn limit(max: usize, keep_full: bool) -> LineLimit {
+        LineLimit { max, keep_full }
+    }
+
+    #[test]
+    fn long_lines_are_cut_to_their_head() {
+        let mut run = ProviderRun::spawn_with_limit(
+            &mut sh("printf '%0100d\\n' 7; printf '%050d\\n' 7 >&2; echo after"),
+            limit(16, false),
+        )
+        .unwrap();
+        let events = collect(&mut run);
+        assert!(events.contains(&RunEvent::LongLine {
+            stderr: false,
+            head: "0".repeat(16),
+            len: 100,
+        }));
+        assert!(events.contains(&RunEvent::LongLine {
+            stderr: true,
+            head: "0".repeat(16),
+            len: 50,
+        }));
+        assert!(events.contains(&RunEvent::Stdout("after".to_string())));
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, RunEvent::LongLinePart(_)))
+        );
+    }
+
+    #[test]
+    fn kept_long_lines_reach_the_log_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let script =
+            "printf '%01000d' 1; sleep 0.1; printf '%01000d\\n' 2; echo '{\"type\":\"x\"}'";
+        for keep_full in [false, true] {
+            let log = dir.path().join(format!("{keep_full}.jsonl"));
+            let run = ProviderRun::spawn_with_limit(&mut sh(script), limit(64, keep_full)).unwrap();
+            let output = stream_run(run, Some(&log), &CancelToken::new(), None).unwrap();
+            assert_eq!(output.long_lines, 1);
+            assert_eq!(
+                output.stdout,
+                format!("{}\n{{\"type\":\"x\"}}\n", "0".repeat(64))
+            );
+            let logged = std::fs::read_to_string(&log).unwrap();
+            let first = if keep_full {
+                format!("{}1{}2", "0".repeat(999), "0".repeat(999))
+            } else {
+                long_line_note(2000)
+            };
+            assert_eq!(logged, format!("{first}\n{{\"type\":\"x\"}}\n"));
+        }
+    }
+
+    #[test]
+    fn a_run_past_its_timeout_is_killed_with_its_group() {
+        // Still printing, so the deadline can't wait for a quiet moment.
+        let run = ProviderRun::spawn(&mut sh(
+            "sleep 300 >/dev/null 2>&1 & echo $!; while :; do echo tick; sleep 0.05; done",
+        ))
+        .unwrap();
+        let started = Instant::now();
+        let timeout = Some(Duration::from_secs(1));
+        let output =
+            stream_run_limited(run, None, &CancelToken::new(), None, u64::MAX, timeout).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(output.timed_out, timeout);
+        assert_eq!(output.signal, Some(libc::SIGKILL));
+        assert!(!output.success());
+        assert_eq!(
+            output.termination().as_deref(),
+            Some("ran for longer than the 1s timeout and was killed")
+        );
+        assert!(output.stdout.contains("tick\n"));
+        let grandchild: libc::pid_t = output.stdout.lines().next().unwrap().parse().unwrap();
+        let gone = Instant::now() + Duration::from_secs(5);
+        while running(grandchild) && Instant::now() < gone {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!running(grandchild));
+
+        // Without a timeout a run takes as long as it takes.
+        let run = ProviderRun::spawn(&mut sh("sleep 1; echo done")).unwrap();
+        let output =
+            stream_run_limited(run, None, &CancelToken::new(), None, u64::MAX, None).unwrap();
+        assert_eq!((output.code, output.timed_out), (0, None));
+    }
+
+    #[test]
+    fn output_past_the_limit_is_cut_off_and_the_run_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("iteration-1.jsonl");
+        // 1..=277 take 1000 bytes; the limit falls one byte into "278".
+        let run = ProviderRun::spawn(&mut sh("seq 1 100000000")).unwrap();
+        let output =
+            stream_run_limited(run, Some(&log), &CancelToken::new(), None, 1001, None).unwrap();
+        assert_eq!(output.output_limit, Some(1001));
+        assert_eq!(output.signal, Some(libc::SIGKILL));
+        assert_eq!(
+            output.termination().as_deref(),
+            Some("printed more than the 1001-byte output limit and was killed")
+        );
+        assert!(
+            output.stdout.ends_with("\n276\n277\n2\n"),
+            "{}",
+            output.stdout
+        );
+        assert_eq!(output.stdout.len(), 1002);
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            format!("{}{}\n", output.stdout, output_limit_note(1001))
+        );
+
+        let mut budget = OutputBudget::new(5);
+        let mut line = RunEvent::Stderr("0123456789".to_string());
+        assert!(budget.spend(&mut line));
+        assert_eq!(line, RunEvent::Stderr("01234".to_string()));
+        let mut line = RunEvent::Stdout("more".to_string());
+        assert!(budget.spend(&mut line));
+        assert_eq!(line, RunEvent::Unfinished(String::new()));
+    }
+
+    /// Peak resident memory of this process in KiB, where /proc has it.
+    #[test]
+    fn runs_of_identical_lines_collapse_from_the_threshold() {
+        let mut repeats = Repeats::new(3);
+        let mut shown = Vec::new();
+        for line in ["a", "a", "b", "b", "b", "b", "", "", "c"] {
+            shown.extend(repeats.push(line));
+        }
+        shown.extend(repeats.flush());
+        assert_eq!(
+            shown,
+            ["a", "a", "b", "[ralph: repeated 4\u{d7}]", "", "", "c"]
+        );
+
+        let mut off = Repeats::new(0);
+        let shown: Vec<String> = ["x", "x", "x"].iter().flat_map(|l| off.push(l)).collect();
+        assert_eq!(shown, ["x", "x", "x"]);
+        assert!(off.flush().is_empty());
+    }
+
+    fn peak_rss_kib() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+        line.split_whitespace().nth(1)?.parse().ok()
+    }
+
+    #[test]
+    fn a_256_mb_line_is_read_in_bounded_memory() {
+        let before = peak_rss_kib();
+        let run = ProviderRun::spawn_with_limit(
+            &mut sh("head -c 268435456 /dev/zero | tr '\\0' x; echo; echo '<promise>COMPLETE</promise>'"),
+            LineLimit::default(),
+        )
+        .unwrap();
+        let output = stream_run(run, None, &CancelToken::new(), None).unwrap();
+        assert!(output.success());
+        assert_eq!(output.long_lines, 1);
+        assert!(output.stdout.starts_with("xxxx"));
+        assert!(output.stdout.len() < DEFAULT_MAX_LINE + 1024);
+        assert!(output.stdout.ends_with("\n<promise>COMPLETE</promise>\n"));
+        if let (Some(before), Some(after)) = (before, peak_rss_kib()) {
+            // Buffering the line would take 256 MiB at least.
+            assert!(
+                after - before < 64 * 1024,
+                "peak grew by {} KiB",
+                after - before
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::config::{CONFIG_FILE_NAME, PROJECT_DIR_NAME, SYSTEM_PROMPT_FILE_NAME};
+use crate::error::{RalphError, Result};
+use crate::fsutil;
+use crate::prompts;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Template used by a bare `ralph init`.
+pub const DEFAULT_TEMPLATE: &str = "minimal";
+
+/// Where the task docs the prompts point the agent at live.
+const TASKS_DIR: &str = "tasks";
+
+/// The quality-gate step of the built-in beads prompt, which a template
+/// replaces with its own commands.
+const GATE_STEP: &str = "5. Run quality gates (bun run build, cargo build if applicable)";
+
+/// A project scaffold `ralph init --template` can lay down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Template {
+    /// What `--template` calls it
+    pub name: &'static str,
+    /// One line for `--list-templates`
+    pub description: &'static str,
+    /// Quality gates, each a shell command, as `(npm, bun)`: the second is
+    /// used instead when the project has a bun lockfile. `None` for a
+    /// template that only creates directories.
+    gates: Option<(&'static [&'static str], &'static [&'static str])>,
+}
+
+/// Every template, as `--list-templates` shows them.
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "minimal",
+        description: "Just the .ralph/ and tasks/ directories",
+        gates: None,
+    },
+    Template {
+        name: "node",
+        description: "Node.js: build and test with npm (or bun, given a bun lockfile)",
+        gates: Some((
+            &["npm run build", "npm test"],
+            &["bun run build", "bun test"],
+        )),
+    },
+    Template {
+        name: "rust",
+        description: "Rust: cargo build and cargo test as quality gates",
+        gates: Some((
+            &["cargo build", "cargo test"],
+            &["cargo build", "cargo test"],
+        )),
+    },
+];
+
+/// The template called `name`; an unknown name lists the available ones.
+pub fn get(name: &str) -> Result<&'static Template> {
+    TEMPLATES.iter().find(|t| t.name == name).ok_or_else(|| {
+        RalphError::InvalidInput(format!(
+            "Unknown template '{}'\nAvailable templates: {}",
+            name,
+            TEMPLATES
+                .iter()
+                .map(|t| t.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })
+}
+
+/// One line per template, for `ralph init --list-templates`.
+pub fn render_list() -> String {
+    let width = TEMPLATES.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    TEMPLATES
+        .iter()
+        .map(|t| format!("{:<width$}  {}\n", t.name, t.description))
+        .collect()
+}
+
+impl Template {
+    /// The quality gates for the project in `dir`.
+    pub fn gates(&self, dir: &Path) -> &'static [&'static str] {
+        match self.gates {
+            Some((_, bun))
+                if ["bun.lockb", "bun.lock"]
+                    .iter()
+                    .any(|f| dir.join(f).is_file()) =>
+            {
+                bun
+            }
+            Some((gates, _)) => gates,
+            None => &[],
+        }
+    }
+
+    /// The files this template writes in `dir`, with their contents.
+    fn files(&self, dir: &Path) -> Vec<(PathBuf, String)> {
+        if self.gates.is_none() {
+            return Vec::new();
+        }
+        let gates = self.gates(dir);
+        let project = dir.join(PROJECT_DIR_NAME);
+        vec![
+            (project.join(SYSTEM_PROMPT_FILE_NAME), prompt(gates)),
+            (project.join(CONFIG_FILE_NAME), config(self.name, gates)),
+            (
+                dir.join(TASKS_DIR).join("README.md"),
+                TASKS_README.to_string(),
+            ),
+        ]
+    }
+
+    /// Lay the template down in `dir`: the `.ralph/` and `tasks/`
+    /// directories, then the template's files. Files that already exist
+    /// are left alone. Returns each file with whether it was written.
+    pub fn apply(&self, dir: &Path) -> Result<Vec<(PathBuf, bool)>> {
+        for sub in [PROJECT_DIR_NAME, TASKS_DIR] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        self.files(dir)
+            .into_iter()
+            .map(|(path, content)| {
+                let written = fsutil::create_atomic(&path, content)?;
+                Ok((path, written))
+            })
+            .collect()
+    }
+}
+
+/// The beads prompt with the quality-gate step running `gates`.
+fn prompt(gates: &[&str]) -> String {
+    prompts::BEADS.replace(
+        GATE_STEP,
+        &format!(
+            "5. Run quality gates (`{}`) and fix whatever they report",
+            gates.join(" && ")
+        ),
+    )
+}
+
+fn config(template: &str, gates: &[&str]) -> String {
+    let gates: Vec<String> = gates.iter().map(|g| format!("{g:?}")).collect();
+    format!(
+        "# Project settings for ralph, from `ralph init --template {template}`.\n\
+         # They override ~/.Ralph/config.toml; see `ralph status` for the result.\n\
+         \n\
+         # Run after every iteration; the loop only stops as complete when all\n\
+         # of them pass. `ralph loop --gate` replaces them for one run.\n\
+         gates = [{}]\n",
+        gates.join(", ")
+    )
+}
+
+const TASKS_README: &str = r#"# Tasks
+
+Work in this repository is tracked with [beads](https://github.com/steveyegge/beads)
+(`bd`) and carried out by an agent that `ralph loop` runs one task at a time.
+The agent reads this directory for context, so keep design notes and
+specifications that span several tasks here.
+
+## Conventions
+
+- One task per unit of work an agent can finish and commit in one sitting.
+  Split anything bigger into an epic with child tasks.
+- Every task states its acceptance criteria: `bd create "Title" -d "..."`.
+  The agent closes a task only once they're all met.
+- Order work with dependencies, not titles: `bd dep add <task> <blocker>`.
+  `bd ready` lists the tasks with nothing left blocking them.
+- The agent claims a task (`bd update <id> --status in_progress`), records
+  its design and notes on it, and closes it with `bd close <id>`.
+- A task still `in_progress` when a loop ends was started but not
+  finished; the loop's closing summary and `ralph report` list these.
+
+## Quality gates
+
+`.ralph/config.toml` lists the commands that must pass after each
+iteration (`gates`). The loop doesn't stop as complete while one fails.
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_templates_list_the_available_ones() {
+        assert_eq!(get("rust").unwrap().name, "rust");
+        let err = get("python").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Unknown template 'python'\nAvailable templates: minimal, node, rust"
+        );
+        assert!(render_list().starts_with("minimal  Just the .ralph/"));
+    }
+
+    #[test]
+    fn templates_seed_matching_prompts_and_gates() {
+        assert!(prompts::BEADS.contains(GATE_STEP));
+        let dir = tempfile::tempdir().unwrap();
+        let written = get("rust").unwrap().apply(dir.path()).unwrap();
+        assert_eq!(written.len(), 3);
+        assert!(written.iter().all(|(_, written)| *written));
+        let project = dir.path().join(PROJECT_DIR_NAME);
+        let prompt = fs::read_to_string(project.join(SYSTEM_PROMPT_FILE_NAME)).unwrap();
+        assert!(
+            prompt.contains("5. Run quality gates (`cargo build && cargo test`)"),
+            "{prompt}"
+        );
+        let config: crate::config::Config =
+            toml::from_str(&fs::read_to_string(project.join(CONFIG_FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(config.gates.unwrap(), ["cargo build", "cargo test"]);
+        assert!(dir.path().join("tasks/README.md").is_file());
+
+        // Nothing is overwritten on a second run.
+        fs::write(project.join(CONFIG_FILE_NAME), "iterations = 3\n").unwrap();
+        let written = get("rust").unwrap().apply(dir.path()).unwrap();
+        assert!(written.iter().all(|(_, written)| !*written));
+        assert_eq!(
+            fs::read_to_string(project.join(CONFIG_FILE_NAME)).unwrap(),
+            "iterations = 3\n"
+        );
+    }
+
+    #[test]
+    fn node_projects_with_a_bun_lockfile_get_bun_gates() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = get("node").unwrap();
+        assert_eq!(node.gates(dir.path()), ["npm run build", "npm test"]);
+        fs::write(dir.path().join("bun.lock"), "").unwrap();
+        assert_eq!(node.gates(dir.path()), ["bun run build", "bun test"]);
+
+        let minimal = get("minimal").unwrap();
+        assert!(minimal.apply(dir.path()).unwrap().is_empty());
+        assert!(dir.path().join(PROJECT_DIR_NAME).is_dir());
+        assert!(dir.path().join(TASKS_DIR).is_dir());
+    }
+}
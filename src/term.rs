@@ -0,0 +1,166 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// `1`/`true` or `0`/`false`: force prompts on or off, whatever the
+/// terminal looks like.
+pub const INTERACTIVE_ENV: &str = "RALPH_INTERACTIVE";
+
+/// Variables set by CI services. `CI=false` (or `0`) counts as unset.
+const CI_VARS: &[&str] = &[
+    "CI",
+    "CONTINUOUS_INTEGRATION",
+    "BUILD_NUMBER",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "JENKINS_URL",
+    "TF_BUILD",
+];
+
+/// What the environment ralph runs in can do: whether someone can answer
+/// a prompt, and whether output can be redrawn in place. Every feature
+/// that assumes a person at a terminal asks here, so cron, CI, `TERM=dumb`
+/// (an Emacs shell) and pipes all get plain line-by-line output.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Terminal {
+    /// stdin is a terminal
+    pub stdin_tty: bool,
+    /// stdout is a terminal
+    pub stdout_tty: bool,
+    /// stderr, where prompts and progress go, is a terminal
+    pub stderr_tty: bool,
+    /// `TERM` is `dumb` (or unset, as under cron)
+    pub dumb: bool,
+    /// A CI service's variables are set
+    pub ci: bool,
+    /// [`INTERACTIVE_ENV`], when set to a boolean
+    pub forced: Option<bool>,
+}
+
+impl Terminal {
+    /// The process's terminal, from its standard streams and environment.
+    pub fn detect() -> Terminal {
+        Terminal::from_env(
+            [
+                std::io::stdin().is_terminal(),
+                std::io::stdout().is_terminal(),
+                std::io::stderr().is_terminal(),
+            ],
+            |name| std::env::var(name).ok(),
+        )
+    }
+
+    /// A terminal with the given stdin/stdout/stderr TTY flags and
+    /// environment.
+    pub fn from_env(ttys: [bool; 3], var: impl Fn(&str) -> Option<String>) -> Terminal {
+        let [stdin_tty, stdout_tty, stderr_tty] = ttys;
+        Terminal {
+            stdin_tty,
+            stdout_tty,
+            stderr_tty,
+            dumb: var("TERM").is_none_or(|t| t.is_empty() || t == "dumb"),
+            ci: CI_VARS.iter().any(|name| {
+                var(name).is_some_and(|v| !v.trim().is_empty() && parse_bool(&v) != Some(false))
+            }),
+            forced: var(INTERACTIVE_ENV).and_then(|v| parse_bool(&v)),
+        }
+    }
+
+    /// Whether to ask questions: there is a terminal to ask on and to read
+    /// the answer from, and this isn't CI. Line-based prompts work on a
+    /// dumb terminal, so `TERM` doesn't matter here.
+    pub fn can_prompt(&self) -> bool {
+        self.forced
+            .unwrap_or(self.stdin_tty && self.stderr_tty && !self.ci)
+    }
+
+    /// Whether progress can be redrawn in place with `\r` on stderr.
+    pub fn can_animate(&self) -> bool {
+        self.stderr_tty && !self.dumb && !self.ci
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// The process's terminal, detected once.
+pub fn get() -> &'static Terminal {
+    static TERMINAL: OnceLock<Terminal> = OnceLock::new();
+    TERMINAL.get_or_init(Terminal::detect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTY: [bool; 3] = [true, true, true];
+
+    fn term(ttys: [bool; 3], vars: &[(&str, &str)]) -> Terminal {
+        Terminal::from_env(ttys, |name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    #[test]
+    fn a_terminal_prompts_and_animates() {
+        let t = term(TTY, &[("TERM", "xterm-256color")]);
+        assert!(t.can_prompt());
+        assert!(t.can_animate());
+    }
+
+    #[test]
+    fn redirected_streams_turn_features_off() {
+        let vars = [("TERM", "xterm")];
+        // cron and `ralph ... < /dev/null`: nobody to answer.
+        assert!(!term([false, true, true], &vars).can_prompt());
+        // `ralph ... 2> log`: the question wouldn't be seen.
+        let t = term([true, true, false], &vars);
+        assert!(!t.can_prompt());
+        assert!(!t.can_animate());
+        // Only stdout redirected: still a person at stderr.
+        let t = term([true, false, true], &vars);
+        assert!(t.can_prompt());
+        assert!(t.can_animate());
+    }
+
+    #[test]
+    fn dumb_terminals_prompt_but_do_not_animate() {
+        for vars in [&[("TERM", "dumb")][..], &[("TERM", "")], &[]] {
+            let t = term(TTY, vars);
+            assert!(t.dumb, "{vars:?}");
+            assert!(t.can_prompt(), "{vars:?}");
+            assert!(!t.can_animate(), "{vars:?}");
+        }
+    }
+
+    #[test]
+    fn ci_variables_turn_features_off() {
+        for var in CI_VARS {
+            let t = term(TTY, &[("TERM", "xterm"), (var, "true")]);
+            assert!(t.ci, "{var}");
+            assert!(!t.can_prompt(), "{var}");
+            assert!(!t.can_animate(), "{var}");
+        }
+        assert!(!term(TTY, &[("TERM", "xterm"), ("CI", "false")]).ci);
+        assert!(!term(TTY, &[("TERM", "xterm"), ("CI", "0")]).ci);
+        assert!(!term(TTY, &[("TERM", "xterm"), ("CI", "")]).ci);
+    }
+
+    #[test]
+    fn ralph_interactive_overrides_detection() {
+        let forced = term([false; 3], &[(INTERACTIVE_ENV, "1"), ("CI", "true")]);
+        assert!(forced.can_prompt());
+        assert!(!forced.can_animate());
+        assert!(!term(TTY, &[(INTERACTIVE_ENV, "false"), ("TERM", "xterm")]).can_prompt());
+        // Anything else is ignored.
+        assert_eq!(term(TTY, &[(INTERACTIVE_ENV, "maybe")]).forced, None);
+        assert_eq!(term(TTY, &[(INTERACTIVE_ENV, "")]).forced, None);
+    }
+}
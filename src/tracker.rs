@@ -0,0 +1,618 @@
+use crate::audit::{self, Kind};
+use crate::beads::{self, Issue};
+use crate::config::EffectiveConfig;
+use crate::error::{RalphError, Result};
+use crate::exec::CommandRunner;
+use crate::github::{self, GitHub};
+use crate::reporter;
+use std::fmt::Debug;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Task tracker used when the `tracker` config key is not set.
+pub const DEFAULT_TRACKER: &str = "beads";
+
+/// Name of the tracker that does without one.
+pub const NONE: &str = "none";
+
+/// Name of the GitHub Issues tracker.
+pub const GITHUB: &str = "github";
+
+/// Supported values of the `tracker` config key.
+pub const VALID_TRACKERS: &[&str] = &[DEFAULT_TRACKER, GITHUB, NONE];
+
+/// How long a tracker command may run before it's killed, unless
+/// `[tracker] timeout_secs` says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set how long each tracker command may run. The first call wins; without
+/// one it's [`DEFAULT_TIMEOUT`].
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+/// How long each tracker command may run, set with [`set_timeout`].
+pub fn timeout() -> Duration {
+    TIMEOUT.get().copied().unwrap_or(DEFAULT_TIMEOUT)
+}
+
+/// Run tracker commands in `dir` rather than the current directory, e.g.
+/// the real checkout while a loop works in a sandbox
+/// (`--sandbox-shared-tracker`). The first call wins.
+pub fn set_dir(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+/// A tracker command running `program`, in the directory set with
+/// [`set_dir`] if there is one.
+pub fn command(program: &str) -> Command {
+    let mut cmd = Command::new(program);
+    if let Some(dir) = DIR.get() {
+        cmd.current_dir(dir);
+    }
+    cmd
+}
+
+/// Whether a tracker command has timed out in this process, so callers can
+/// skip the tracker steps that would only hang again.
+pub fn timed_out() -> bool {
+    TIMED_OUT.load(Ordering::SeqCst)
+}
+
+/// The error for tracker command `command` failing with `e`:
+/// [`RalphError::TrackerTimeout`] (remembered for [`timed_out`]) when it
+/// was killed for running too long.
+pub fn command_error(command: String, e: io::Error) -> RalphError {
+    if e.kind() != io::ErrorKind::TimedOut {
+        return RalphError::Tracker {
+            command,
+            message: e.to_string(),
+        };
+    }
+    TIMED_OUT.store(true, Ordering::SeqCst);
+    RalphError::TrackerTimeout {
+        command,
+        secs: timeout().as_secs(),
+    }
+}
+
+/// The issue tool the agent works through. ralph asks it for ready work
+/// (`watch`, `loop --epic`), single tasks (`run`), closed tasks (`report`)
+/// and an end-of-loop summary; its prompt template is the default system prompt.
+/// Adding a tracker means one new implementation and an entry in
+/// [`from_config`] (and in [`get`] if it has no settings).
+pub trait Tracker: Debug + Sync {
+    /// Name as written in the `tracker` config key.
+    fn name(&self) -> &'static str;
+
+    /// Default system prompt telling the agent how to use this tracker.
+    fn prompt_template(&self) -> &'static str;
+
+    /// Check the tracker can be used from the current directory, before
+    /// the first provider call. The error says how to set it up.
+    fn preflight(&self, _exec: &dyn CommandRunner) -> Result<()> {
+        Ok(())
+    }
+
+    /// Command that sets the tracker up in a directory that has never used
+    /// it, offered when [`preflight`](Tracker::preflight) fails with
+    /// [`RalphError::TrackerNotInitialized`].
+    fn init_command(&self) -> Option<Command> {
+        None
+    }
+
+    /// Tasks ready to be worked on.
+    fn ready(&self, exec: &dyn CommandRunner) -> Result<Vec<Issue>>;
+
+    /// Default command pushing the tracker's state to its remote, for
+    /// `--bd-sync`; `None` when there's nothing to sync.
+    fn sync_command(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Number of tasks ready to be worked on.
+    fn ready_count(&self, exec: &dyn CommandRunner) -> Result<usize> {
+        self.ready(exec).map(|issues| issues.len())
+    }
+
+    /// Tasks with `status`, e.g. `closed`.
+    fn list(&self, exec: &dyn CommandRunner, status: &str) -> Result<Vec<Issue>>;
+
+    /// A single task.
+    fn show(&self, exec: &dyn CommandRunner, id: &str) -> Result<Issue>;
+
+    /// Set a task's status, e.g. to claim it with `in_progress`.
+    fn update_status(&self, exec: &dyn CommandRunner, id: &str, status: &str) -> Result<()>;
+
+    /// Command printing an overview of the tasks after a loop, if any.
+    fn summary_command(&self) -> Option<Command>;
+
+    /// Run [`summary_command`](Tracker::summary_command) with the
+    /// terminal's stdio, within [`timeout`]. A non-zero exit is only warned
+    /// about.
+    fn print_summary(&self, exec: &dyn CommandRunner) -> Result<()> {
+        let Some(mut cmd) = self.summary_command() else {
+            return Ok(());
+        };
+        let line = command_line(&cmd);
+        let code = audit::run(Kind::Tracker, &mut cmd, |cmd| {
+            exec.status_timeout(cmd, timeout())
+        })
+        .map_err(|e| command_error(line.clone(), e))?;
+        if code != 0 {
+            reporter::warn(&format!("{} exited with code {}", line, code));
+        }
+        Ok(())
+    }
+}
+
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How [`preflight`] deals with a tracker that isn't ready.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreflightOptions {
+    /// Warn and go ahead instead of failing (`--ignore-missing-bd`)
+    pub ignore_missing: bool,
+    /// Set up an uninitialized tracker without asking (`--auto-init-bd`)
+    pub auto_init: bool,
+}
+
+/// Run [`Tracker::preflight`] before a run. An uninitialized tracker is set
+/// up with its [`init_command`](Tracker::init_command) if the user agrees
+/// (or `auto_init` is set); declining aborts. With `ignore_missing` a
+/// failure is a single warning and the run goes ahead. Returns whether the
+/// tracker is usable, so callers can skip the tracker steps that would fail
+/// again; always `false` for `tracker = "none"`.
+pub fn preflight(
+    tracker: &dyn Tracker,
+    exec: &dyn CommandRunner,
+    opts: PreflightOptions,
+) -> Result<bool> {
+    preflight_with(tracker, exec, opts, crate::clean::confirm)
+}
+
+/// [`preflight`] asking through `confirm`.
+pub fn preflight_with(
+    tracker: &dyn Tracker,
+    exec: &dyn CommandRunner,
+    opts: PreflightOptions,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<bool> {
+    if tracker.name() == NONE {
+        return Ok(false);
+    }
+    let result = match tracker.preflight(exec) {
+        Err(RalphError::TrackerNotInitialized(message))
+            if opts.auto_init || !opts.ignore_missing =>
+        {
+            init(tracker, exec, opts.auto_init, confirm, message)
+        }
+        other => other,
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if opts.ignore_missing => {
+            let reason = e.to_string();
+            let reason = reason.lines().next().unwrap_or_default();
+            reporter::warn(&format!("{reason}; continuing without the task tracker"));
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Set up an uninitialized tracker, asking first unless `auto`, then check
+/// it again. `message` is the preflight error, returned when there's no
+/// init command.
+fn init(
+    tracker: &dyn Tracker,
+    exec: &dyn CommandRunner,
+    auto: bool,
+    confirm: impl FnOnce(&str) -> bool,
+    message: String,
+) -> Result<()> {
+    let Some(mut cmd) = tracker.init_command() else {
+        return Err(RalphError::TrackerNotInitialized(message));
+    };
+    let line = command_line(&cmd);
+    let first_line = message.lines().next().unwrap_or_default();
+    if auto {
+        reporter::info(&format!("{first_line}; running `{line}`"));
+    } else if !confirm(&format!("{first_line}. Run `{line}` now?")) {
+        return Err(RalphError::TrackerNotInitialized(format!(
+            "{first_line}; run `{line}` (or pass --auto-init-bd) before starting a run"
+        )));
+    }
+    let code = audit::run(Kind::Tracker, &mut cmd, |cmd| {
+        exec.status_timeout(cmd, timeout())
+    })
+    .map_err(|e| command_error(line.clone(), e))?;
+    if code != 0 {
+        return Err(RalphError::Tracker {
+            command: line,
+            message: format!("exited with code {code}"),
+        });
+    }
+    tracker.preflight(exec)
+}
+
+/// Run `command` (split on whitespace) with the terminal's stdio to push
+/// the tracker's state to its remote after a loop. Returns the exit code;
+/// an empty command, one that can't be started and one still running
+/// after [`timeout`] are errors.
+pub fn sync(exec: &dyn CommandRunner, command: &str) -> Result<i32> {
+    let mut words = command.split_whitespace();
+    let Some(program) = words.next() else {
+        return Err(RalphError::InvalidInput(
+            "No sync command for this tracker; set `sync_command` under [tracker] in config.toml"
+                .to_string(),
+        ));
+    };
+    let mut cmd = self::command(program);
+    cmd.args(words);
+    audit::run(Kind::Tracker, &mut cmd, |cmd| {
+        exec.status_timeout(cmd, timeout())
+    })
+    .map_err(|e| command_error(command.to_string(), e))
+}
+
+/// The tracker called `name`, if ralph knows it and it needs no settings
+/// (GitHub does; see [`from_config`]).
+pub fn get(name: &str) -> Option<&'static dyn Tracker> {
+    match name {
+        DEFAULT_TRACKER => Some(&Beads),
+        NONE => Some(&NoTracker),
+        _ => None,
+    }
+}
+
+/// The tracker `config` selects, with its settings. Its commands get
+/// `[tracker] timeout_secs` from then on (see [`set_timeout`]).
+pub fn from_config(config: &EffectiveConfig) -> Box<dyn Tracker> {
+    set_timeout(Duration::from_secs(config.tracker_timeout_secs.value));
+    match config.tracker.value.as_str() {
+        GITHUB => {
+            let repo = &config.tracker_repo.value;
+            Box::new(GitHub::new(
+                (!repo.is_empty()).then(|| repo.clone()),
+                config.tracker_label.value.as_str(),
+                GitHub::token_from_env(),
+                Box::new(github::ReqwestHttp::default()),
+            ))
+        }
+        NONE => Box::new(NoTracker),
+        _ => Box::new(Beads),
+    }
+}
+
+/// Validate a `tracker` value.
+pub fn validate_tracker(name: &str) -> Result<()> {
+    if VALID_TRACKERS.contains(&name) {
+        return Ok(());
+    }
+    Err(RalphError::InvalidInput(format!(
+        "Invalid tracker '{}'\nAvailable trackers: {}",
+        name,
+        VALID_TRACKERS.join(", ")
+    )))
+}
+
+/// [beads](https://github.com/steveyegge/beads), through the `bd` CLI.
+#[derive(Debug, Clone, Copy)]
+pub struct Beads;
+
+impl Tracker for Beads {
+    fn name(&self) -> &'static str {
+        DEFAULT_TRACKER
+    }
+
+    fn prompt_template(&self) -> &'static str {
+        crate::prompts::BEADS
+    }
+
+    fn preflight(&self, exec: &dyn CommandRunner) -> Result<()> {
+        beads::preflight(exec)
+    }
+
+    fn ready(&self, exec: &dyn CommandRunner) -> Result<Vec<Issue>> {
+        beads::ready(exec)
+    }
+
+    fn list(&self, exec: &dyn CommandRunner, status: &str) -> Result<Vec<Issue>> {
+        beads::list(exec, status)
+    }
+
+    fn show(&self, exec: &dyn CommandRunner, id: &str) -> Result<Issue> {
+        beads::show(exec, id)
+    }
+
+    fn update_status(&self, exec: &dyn CommandRunner, id: &str, status: &str) -> Result<()> {
+        beads::update_status(exec, id, status)
+    }
+
+    fn summary_command(&self) -> Option<Command> {
+        let mut cmd = command("bd");
+        cmd.args(["list", "--pretty"]);
+        Some(cmd)
+    }
+
+    fn init_command(&self) -> Option<Command> {
+        let mut cmd = command("bd");
+        cmd.arg("init");
+        Some(cmd)
+    }
+
+    fn sync_command(&self) -> Option<&'static str> {
+        Some("bd sync")
+    }
+}
+
+impl Tracker for GitHub {
+    fn name(&self) -> &'static str {
+        GITHUB
+    }
+
+    fn prompt_template(&self) -> &'static str {
+        crate::prompts::GITHUB
+    }
+
+    fn preflight(&self, exec: &dyn CommandRunner) -> Result<()> {
+        GitHub::preflight(self, exec)
+    }
+
+    fn ready(&self, exec: &dyn CommandRunner) -> Result<Vec<Issue>> {
+        GitHub::ready(self, exec)
+    }
+
+    fn list(&self, exec: &dyn CommandRunner, status: &str) -> Result<Vec<Issue>> {
+        GitHub::list(self, exec, status)
+    }
+
+    fn show(&self, exec: &dyn CommandRunner, id: &str) -> Result<Issue> {
+        GitHub::show(self, exec, id)
+    }
+
+    fn update_status(&self, exec: &dyn CommandRunner, id: &str, status: &str) -> Result<()> {
+        GitHub::update_status(self, exec, id, status)
+    }
+
+    /// The backlog diff already lists what closed; there's no CLI to show.
+    fn summary_command(&self) -> Option<Command> {
+        None
+    }
+}
+
+/// No tracker (`tracker = "none"`): the prompt alone says what to work on.
+/// Loops run without touching any issue tool; commands that need tasks
+/// (`run`, `watch`) fail.
+#[derive(Debug, Clone, Copy)]
+pub struct NoTracker;
+
+impl NoTracker {
+    fn unsupported<T>(&self) -> Result<T> {
+        Err(RalphError::InvalidInput(
+            "No task tracker configured (tracker = \"none\"); set `tracker` in config.toml"
+                .to_string(),
+        ))
+    }
+}
+
+impl Tracker for NoTracker {
+    fn name(&self) -> &'static str {
+        NONE
+    }
+
+    fn prompt_template(&self) -> &'static str {
+        crate::prompts::NONE
+    }
+
+    fn ready(&self, _exec: &dyn CommandRunner) -> Result<Vec<Issue>> {
+        self.unsupported()
+    }
+
+    fn list(&self, _exec: &dyn CommandRunner, _status: &str) -> Result<Vec<Issue>> {
+        self.unsupported()
+    }
+
+    fn show(&self, _exec: &dyn CommandRunner, _id: &str) -> Result<Issue> {
+        self.unsupported()
+    }
+
+    fn update_status(&self, _exec: &dyn CommandRunner, _id: &str, _status: &str) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn summary_command(&self) -> Option<Command> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::ScriptedRunner;
+
+    #[test]
+    fn beads_goes_through_bd() {
+        let exec = ScriptedRunner::new()
+            .reply(0, r#"[{"id":"bd-1"},{"id":"bd-2"}]"#)
+            .reply(1, "");
+        let beads = get("beads").unwrap();
+        assert_eq!(beads.ready_count(&exec).unwrap(), 2);
+        beads.print_summary(&exec).unwrap();
+        assert_eq!(
+            exec.calls(),
+            [
+                vec!["bd", "ready", "--json"],
+                vec!["bd", "list", "--pretty"]
+            ]
+        );
+    }
+
+    #[test]
+    fn none_runs_nothing() {
+        let exec = ScriptedRunner::new();
+        let none = get("none").unwrap();
+        none.print_summary(&exec).unwrap();
+        assert!(none.show(&exec, "x").is_err());
+        assert!(none.ready_count(&exec).is_err());
+        assert!(exec.calls().is_empty());
+        assert!(!none.prompt_template().contains("bd"));
+    }
+
+    const IGNORE: PreflightOptions = PreflightOptions {
+        ignore_missing: true,
+        auto_init: false,
+    };
+    const AUTO_INIT: PreflightOptions = PreflightOptions {
+        ignore_missing: false,
+        auto_init: true,
+    };
+
+    fn no_questions(question: &str) -> bool {
+        panic!("unexpected question: {question}")
+    }
+
+    #[test]
+    fn preflight_failures_can_be_ignored() {
+        let beads = get("beads").unwrap();
+        let strict = PreflightOptions::default();
+        let exec = ScriptedRunner::new().spawn_error(std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            preflight_with(beads, &exec, strict, no_questions),
+            Err(RalphError::TrackerUnavailable(_))
+        ));
+        let exec = ScriptedRunner::new().spawn_error(std::io::ErrorKind::NotFound);
+        assert!(!preflight_with(beads, &exec, IGNORE, no_questions).unwrap());
+        let exec = ScriptedRunner::new().reply(0, "[]");
+        assert!(preflight_with(beads, &exec, strict, no_questions).unwrap());
+
+        // Nothing to check, and nothing for the caller to do afterwards.
+        let exec = ScriptedRunner::new();
+        assert!(!preflight_with(get("none").unwrap(), &exec, strict, no_questions).unwrap());
+        assert!(exec.calls().is_empty());
+    }
+
+    const NO_DATABASE: &str = "Error: no beads database found\n";
+
+    #[test]
+    fn missing_database_is_initialized_when_the_user_agrees() {
+        let beads = get("beads").unwrap();
+        let exec = ScriptedRunner::new()
+            .fail(1, NO_DATABASE)
+            .reply(0, "")
+            .reply(0, "[]");
+        let mut asked = String::new();
+        let ok = preflight_with(beads, &exec, PreflightOptions::default(), |q| {
+            asked = q.to_string();
+            true
+        })
+        .unwrap();
+        assert!(ok);
+        assert_eq!(
+            asked,
+            "No beads database in this directory. Run `bd init` now?"
+        );
+        assert_eq!(
+            exec.calls(),
+            [
+                vec!["bd", "ready", "--json"],
+                vec!["bd", "init"],
+                vec!["bd", "ready", "--json"]
+            ]
+        );
+    }
+
+    #[test]
+    fn declining_init_aborts() {
+        let beads = get("beads").unwrap();
+        let exec = ScriptedRunner::new().fail(1, NO_DATABASE);
+        let err = preflight_with(beads, &exec, PreflightOptions::default(), |_| false)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "No beads database in this directory; run `bd init` (or pass --auto-init-bd) before starting a run"
+        );
+        assert_eq!(exec.calls().len(), 1);
+    }
+
+    #[test]
+    fn auto_init_runs_bd_init_without_asking() {
+        let beads = get("beads").unwrap();
+        let exec = ScriptedRunner::new()
+            .fail(1, NO_DATABASE)
+            .reply(0, "")
+            .reply(0, "[]");
+        assert!(preflight_with(beads, &exec, AUTO_INIT, no_questions).unwrap());
+
+        let exec = ScriptedRunner::new().fail(1, NO_DATABASE).reply(2, "");
+        let err = preflight_with(beads, &exec, AUTO_INIT, no_questions)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "bd init failed: exited with code 2");
+
+        // --ignore-missing-bd alone doesn't ask; it just warns.
+        let exec = ScriptedRunner::new().fail(1, NO_DATABASE);
+        assert!(!preflight_with(beads, &exec, IGNORE, no_questions).unwrap());
+    }
+
+    #[test]
+    fn sync_runs_the_configured_command() {
+        let exec = ScriptedRunner::new().reply(0, "").reply(3, "");
+        assert_eq!(sync(&exec, "bd sync").unwrap(), 0);
+        assert_eq!(sync(&exec, " make  sync ").unwrap(), 3);
+        assert_eq!(exec.calls(), [vec!["bd", "sync"], vec!["make", "sync"]]);
+
+        let exec = ScriptedRunner::new().spawn_error(std::io::ErrorKind::NotFound);
+        let err = sync(&exec, "bd sync").unwrap_err().to_string();
+        assert_eq!(err, "bd sync failed: scripted spawn failure");
+        assert!(
+            sync(&exec, "")
+                .unwrap_err()
+                .to_string()
+                .contains("sync_command")
+        );
+    }
+
+    #[test]
+    fn timeouts_are_reported_as_such() {
+        let exec = ScriptedRunner::new().spawn_error(io::ErrorKind::TimedOut);
+        let err = get("beads").unwrap().print_summary(&exec).unwrap_err();
+        assert!(matches!(
+            &err,
+            RalphError::TrackerTimeout { command, .. } if command == "bd list --pretty"
+        ));
+        assert!(timed_out());
+
+        let exec = ScriptedRunner::new().spawn_error(io::ErrorKind::TimedOut);
+        let err = sync(&exec, "bd sync").unwrap_err().to_string();
+        assert!(err.starts_with("bd sync timed out after "), "{err}");
+    }
+
+    #[test]
+    fn unknown_trackers_are_rejected() {
+        assert!(get("jira").is_none());
+        let err = validate_tracker("jira").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Invalid tracker 'jira'\nAvailable trackers: beads, github, none"
+        );
+    }
+}
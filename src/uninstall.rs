@@ -0,0 +1,150 @@
+use crate::clean::{confirm, disk_usage, format_size};
+use crate::config;
+use crate::error::RalphError;
+use crate::reporter;
+use crate::upgrade;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Options for `ralph uninstall`.
+#[derive(Debug, Default)]
+pub struct UninstallOptions {
+    /// Don't ask for confirmation
+    pub yes: bool,
+    /// Also delete `~/.Ralph/`
+    pub purge: bool,
+}
+
+/// Whether `ralph uninstall` went ahead.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UninstallOutcome {
+    /// The binary (and with `--purge`, `~/.Ralph`) was removed
+    Removed,
+    /// The user declined the confirmation
+    Aborted,
+}
+
+/// The installed binary, with symlinks (e.g. `~/.local/bin/ralph`) resolved
+/// to the real file.
+pub fn locate_binary() -> io::Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    Ok(fs::canonicalize(&exe).unwrap_or(exe))
+}
+
+/// Symlinks named `ralph` in `dirs` (normally `$PATH`) that point at
+/// `binary`, which would otherwise be left dangling.
+fn links_to(binary: &Path, dirs: impl Iterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let name = binary.file_name().unwrap_or_default();
+    let mut links: Vec<PathBuf> = dirs
+        .map(|dir| dir.join(name))
+        .filter(|p| p.is_symlink())
+        .filter(|p| fs::canonicalize(p).is_ok_and(|target| target == binary))
+        .collect();
+    links.dedup();
+    links
+}
+
+/// Top-level entries of `dir` with their sizes, sorted by name.
+fn list_contents(dir: &Path) -> Vec<(PathBuf, u64)> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|e| {
+                    let path = e.path();
+                    let size = disk_usage(&path);
+                    (path, size)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+/// Remove the ralph binary and, with `--purge`, the config directory.
+pub fn run(opts: &UninstallOptions) -> Result<UninstallOutcome, RalphError> {
+    let binary = locate_binary()?;
+    let config_dir = config::get_config_dir()?;
+
+    let path_dirs = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let links = links_to(&binary, path_dirs.into_iter());
+
+    println!("This will remove the ralph binary: {}", binary.display());
+    for link in &links {
+        println!("and the symlink {}", link.display());
+    }
+    if opts.purge && config_dir.exists() {
+        println!(
+            "and delete {} including everything in it:",
+            config_dir.display()
+        );
+        for (path, size) in list_contents(&config_dir) {
+            println!("  {} ({})", path.display(), format_size(size));
+        }
+    }
+
+    if !opts.yes && !confirm("Continue?") {
+        println!("Aborted.");
+        return Ok(UninstallOutcome::Aborted);
+    }
+
+    upgrade::remove_binary(&binary).map_err(|e| RalphError::upgrade("uninstall", e))?;
+    println!("Removed {}", binary.display());
+    for link in &links {
+        match fs::remove_file(link) {
+            Ok(()) => println!("Removed {}", link.display()),
+            Err(e) => reporter::warn(&format!("Failed to remove {}: {}", link.display(), e)),
+        }
+    }
+
+    if opts.purge && config_dir.exists() {
+        fs::remove_dir_all(&config_dir)?;
+        println!("Removed {}", config_dir.display());
+    }
+    Ok(UninstallOutcome::Removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn links_to_finds_symlinks_to_the_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        let bin = dir.path().join("bin");
+        let other = dir.path().join("other");
+        for d in [&real, &bin, &other] {
+            fs::create_dir(d).unwrap();
+        }
+        let binary = real.join("ralph");
+        fs::write(&binary, "").unwrap();
+        let binary = fs::canonicalize(binary).unwrap();
+        std::os::unix::fs::symlink(&binary, bin.join("ralph")).unwrap();
+        fs::write(other.join("ralph"), "").unwrap();
+
+        let links = links_to(&binary, [bin.clone(), other, real].into_iter());
+        assert_eq!(links, [bin.join("ralph")]);
+    }
+
+    #[test]
+    fn list_contents_reports_sizes_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("system-prompt.md"), "abc").unwrap();
+        fs::create_dir(dir.path().join("logs")).unwrap();
+        fs::write(dir.path().join("logs").join("x.log"), "12345").unwrap();
+        let contents = list_contents(dir.path());
+        assert_eq!(
+            contents,
+            [
+                (dir.path().join("logs"), 5),
+                (dir.path().join("system-prompt.md"), 3)
+            ]
+        );
+    }
+}
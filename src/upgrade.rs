@@ -1,95 +1,335 @@
+use crate::audit::{self, Kind};
+use crate::build_info::BuildInfo;
+use crate::config;
+use crate::error::RalphError;
+use crate::exec::{CommandRunner, SystemRunner};
+use crate::fsutil;
+use crate::reporter;
 use reqwest::blocking::Client;
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
 const GITHUB_OWNER: &str = "1WorldCapture";
 const GITHUB_REPO: &str = "ralph-rust-cli";
 
+/// Result of a successful `ralph upgrade`.
 #[derive(Debug)]
 pub enum UpgradeOutcome {
-    UpToDate { current: Version },
-    Upgraded { from: Version, to: Version },
+    /// The latest release is already installed
+    UpToDate {
+        /// Installed version
+        current: Version,
+    },
+    /// A newer release was installed
+    Upgraded {
+        /// Version replaced
+        from: Version,
+        /// Version installed
+        to: Version,
+    },
 }
 
-#[derive(Debug)]
+/// Why `ralph upgrade` (or `uninstall`) failed.
+#[derive(Debug, thiserror::Error)]
 pub enum UpgradeError {
-    UnsupportedPlatform { os: String, arch: String },
+    /// No release asset is built for this OS/architecture
+    #[error("Unsupported platform: {os} {arch}")]
+    UnsupportedPlatform {
+        /// `std::env::consts::OS`
+        os: String,
+        /// `std::env::consts::ARCH`
+        arch: String,
+    },
+    /// The HTTP request failed
+    #[error("Network error: {0}")]
     Network(String),
+    /// GitHub answered with an error or an unexpected body
+    #[error("GitHub API error: {0}")]
     GithubApi(String),
-    VersionParse { tag: String },
-    AssetNotFound { asset: String },
+    /// The release tag isn't a semver version
+    #[error("Failed to parse version tag: {tag}")]
+    VersionParse {
+        /// Tag as published
+        tag: String,
+    },
+    /// The release has no asset for this platform
+    #[error("Release asset not found: {asset}")]
+    AssetNotFound {
+        /// Expected asset file name
+        asset: String,
+    },
+    /// The published checksum file couldn't be read
+    #[error("Failed to parse checksum file")]
     ChecksumParse,
-    ChecksumMismatch { expected: String, actual: String },
-    PermissionDenied { path: PathBuf },
-    Io(io::Error),
+    /// The download doesn't match its published checksum
+    #[error("Download verification failed (expected {expected}, got {actual})")]
+    ChecksumMismatch {
+        /// Published SHA-256
+        expected: String,
+        /// SHA-256 of the download
+        actual: String,
+    },
+    /// `ralph upgrade --repair` found nothing usable to restore
+    #[error("{0}")]
+    Repair(String),
+    /// The installed binary (or its directory) isn't writable
+    #[error("Cannot write to installation path: {} (permission denied)", path.display())]
+    PermissionDenied {
+        /// Path that couldn't be written
+        path: PathBuf,
+    },
+    /// Any other I/O failure
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
 }
 
-impl std::fmt::Display for UpgradeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+#[derive(Debug, Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+    size: u64,
+}
+
+/// What `ralph upgrade --repair` did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RepairOutcome {
+    /// The installed binary runs; nothing was restored
+    Healthy {
+        /// The installed binary
+        exe: PathBuf,
+        /// The version it reports
+        version: String,
+    },
+    /// The installed binary didn't run and the backup was put back
+    Restored {
+        /// The installed binary
+        exe: PathBuf,
+        /// The backup it was restored from
+        backup: PathBuf,
+        /// The version the restored binary reports
+        version: String,
+    },
+}
+
+/// Exit code of `ralph check-update` when a newer release exists.
+pub const EXIT_UPDATE_AVAILABLE: u8 = 10;
+/// Exit code of `ralph check-update` when the check itself failed.
+pub const EXIT_CHECK_FAILED: u8 = 20;
+
+/// How long `ralph check-update` waits for GitHub; it's meant to be called
+/// from login shells.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Result of comparing the running version with the latest release.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UpdateCheck {
+    /// Nothing newer has been released
+    UpToDate {
+        /// Installed version
+        current: Version,
+    },
+    /// A newer release exists
+    Available {
+        /// Installed version
+        current: Version,
+        /// Latest released version
+        latest: Version,
+    },
+}
+
+impl UpdateCheck {
+    /// The single line `ralph check-update` prints.
+    pub fn message(&self) -> String {
         match self {
-            UpgradeError::UnsupportedPlatform { os, arch } => {
-                write!(f, "Unsupported platform: {os} {arch}")
+            UpdateCheck::UpToDate { current } => format!("ralph is up to date (v{current})"),
+            UpdateCheck::Available { current, latest } => {
+                format!("ralph v{latest} is available (current: v{current}); run `ralph upgrade`")
             }
-            UpgradeError::Network(msg) => write!(f, "Network error: {msg}"),
-            UpgradeError::GithubApi(msg) => write!(f, "GitHub API error: {msg}"),
-            UpgradeError::VersionParse { tag } => write!(f, "Failed to parse version tag: {tag}"),
-            UpgradeError::AssetNotFound { asset } => write!(f, "Release asset not found: {asset}"),
-            UpgradeError::ChecksumParse => write!(f, "Failed to parse checksum file"),
-            UpgradeError::ChecksumMismatch { expected, actual } => write!(
-                f,
-                "Download verification failed (expected {expected}, got {actual})"
-            ),
-            UpgradeError::PermissionDenied { path } => write!(
-                f,
-                "Cannot write to installation path: {} (permission denied)",
-                path.display()
-            ),
-            UpgradeError::Io(err) => write!(f, "{err}"),
         }
     }
 }
 
-impl std::error::Error for UpgradeError {}
+/// Compare the running version to the latest release without touching the
+/// installation. Only the release cache is written.
+pub fn check_update() -> Result<UpdateCheck, RalphError> {
+    latest_release_check().map_err(RalphError::UpdateCheck)
+}
 
-impl From<io::Error> for UpgradeError {
-    fn from(value: io::Error) -> Self {
-        UpgradeError::Io(value)
+fn latest_release_check() -> Result<UpdateCheck, UpgradeError> {
+    let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("CARGO_PKG_VERSION is valid");
+    let client = github_client(CHECK_TIMEOUT)?;
+    let latest = parse_release_version(&get_latest_release(&client)?.tag_name)?;
+    Ok(compare_versions(current, latest))
+}
+
+fn compare_versions(current: Version, latest: Version) -> UpdateCheck {
+    if latest > current {
+        UpdateCheck::Available { current, latest }
+    } else {
+        UpdateCheck::UpToDate { current }
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct GithubRelease {
-    tag_name: String,
-    assets: Vec<GithubAsset>,
+/// Download and install the latest release over the running binary. When
+/// the last upgrade was interrupted (see [`interrupted_upgrade`]), the
+/// installed binary is repaired first.
+pub fn run_upgrade() -> Result<UpgradeOutcome, RalphError> {
+    if interrupted_upgrade().is_some() {
+        reporter::warn("The last upgrade didn't finish; checking the installed binary first");
+        report_repair(&run_repair()?);
+    }
+    upgrade().map_err(|e| RalphError::upgrade("upgrade", e))
 }
 
-#[derive(Debug, Deserialize)]
-struct GithubAsset {
-    name: String,
-    browser_download_url: String,
-    size: u64,
+/// Check that the installed binary runs and, if it doesn't, restore the
+/// backup an interrupted upgrade left next to it. Run from that backup
+/// (`ralph.old upgrade --repair`), it repairs the `ralph` beside it.
+pub fn run_repair() -> Result<RepairOutcome, RalphError> {
+    let exe = std::env::current_exe().map_err(|e| RalphError::upgrade("upgrade", e.into()))?;
+    repair(&SystemRunner, &installed_path(&exe)).map_err(|e| RalphError::upgrade("upgrade", e))
+}
+
+/// Say what [`run_repair`] did.
+pub fn report_repair(outcome: &RepairOutcome) {
+    match outcome {
+        RepairOutcome::Healthy { exe, version } => reporter::info(&format!(
+            "{} runs (ralph {version}); nothing to repair",
+            exe.display()
+        )),
+        RepairOutcome::Restored {
+            exe,
+            backup,
+            version,
+        } => reporter::info(&format!(
+            "Restored {} from {} (ralph {version})",
+            exe.display(),
+            backup.display()
+        )),
+    }
+}
+
+/// The marker left next to the running binary (or, when running its
+/// backup, the binary it backs up) by an upgrade that never finished
+/// replacing it, if there is one.
+pub fn interrupted_upgrade() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let marker = upgrade_marker(&installed_path(&exe));
+    marker.exists().then_some(marker)
 }
 
-pub fn run_upgrade() -> Result<UpgradeOutcome, UpgradeError> {
+/// The installed binary for `exe`: `exe` itself, or the binary it's the
+/// `.old` backup of.
+fn installed_path(exe: &Path) -> PathBuf {
+    match exe.to_str().and_then(|s| s.strip_suffix(".old")) {
+        Some(installed) => PathBuf::from(installed),
+        None => exe.to_path_buf(),
+    }
+}
+
+/// Written next to the binary before an upgrade starts replacing it and
+/// removed once it has, so an interrupted replacement can be told apart.
+fn upgrade_marker(exe: &Path) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".upgrading");
+    exe.with_file_name(name)
+}
+
+/// The file [`move_aside`] moves `exe` to.
+fn backup_path(exe: &Path) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".old");
+    exe.with_file_name(name)
+}
+
+fn repair(exec: &dyn CommandRunner, exe: &Path) -> Result<RepairOutcome, UpgradeError> {
+    let marker = upgrade_marker(exe);
+    if let Some(info) = installed_build_info(exec, exe) {
+        let _ = fs::remove_file(&marker);
+        return Ok(RepairOutcome::Healthy {
+            exe: exe.to_path_buf(),
+            version: info.version,
+        });
+    }
+    let backup = backup_path(exe);
+    if !backup.exists() {
+        return Err(UpgradeError::Repair(format!(
+            "{} doesn't run, and there's no backup at {} to restore; reinstall ralph",
+            exe.display(),
+            backup.display()
+        )));
+    }
+    let Some(info) = installed_build_info(exec, &backup) else {
+        return Err(UpgradeError::Repair(format!(
+            "{} doesn't run, and neither does its backup {}; reinstall ralph",
+            exe.display(),
+            backup.display()
+        )));
+    };
+    let install_dir = exe
+        .parent()
+        .ok_or_else(|| UpgradeError::Io(io::Error::other("Invalid exe path")))?;
+    ensure_install_dir_writable(install_dir, exe)?;
+
+    // A copy, as replacing the binary moves it over the backup.
+    let mut staged = tempfile::NamedTempFile::new_in(install_dir)?;
+    io::copy(&mut fs::File::open(&backup)?, &mut staged)?;
+    let staged = staged.into_temp_path();
+    ensure_executable(&staged)?;
+    reporter::info(&format!(
+        "{} doesn't run; restoring {} (ralph {})",
+        exe.display(),
+        backup.display(),
+        info.version
+    ));
+    let restored = match exe.exists() {
+        true => self_replace(exe, &staged, install_dir),
+        // Moved aside, and the upgrade stopped before the new one arrived.
+        false => fs::rename(&staged, exe).map_err(UpgradeError::Io),
+    };
+    audited(
+        &[
+            "repair",
+            &exe.display().to_string(),
+            &backup.display().to_string(),
+        ],
+        restored,
+    )?;
+    let _ = fs::remove_file(&marker);
+    Ok(RepairOutcome::Restored {
+        exe: exe.to_path_buf(),
+        backup,
+        version: info.version,
+    })
+}
+
+fn upgrade() -> Result<UpgradeOutcome, UpgradeError> {
     let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("CARGO_PKG_VERSION is valid");
     let current_exe = std::env::current_exe().map_err(UpgradeError::Io)?;
-    let install_dir = current_exe.parent().map(Path::to_path_buf).ok_or_else(|| {
-        UpgradeError::Io(io::Error::new(io::ErrorKind::Other, "Invalid exe path"))
-    })?;
+    let install_dir = current_exe
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| UpgradeError::Io(io::Error::other("Invalid exe path")))?;
 
-    let client = github_client()?;
+    let client = github_client(Duration::from_secs(60))?;
 
-    eprintln!("Checking for updates…");
+    reporter::info("Checking for updates…");
     let latest_release = get_latest_release(&client)?;
     let latest = parse_release_version(&latest_release.tag_name)?;
 
-    eprintln!("Current version: v{current}");
-    eprintln!("Latest version:  v{latest}");
+    reporter::info(&format!("Current version: v{current}"));
+    reporter::info(&format!("Latest version:  v{latest}"));
 
     if latest <= current {
         return Ok(UpgradeOutcome::UpToDate { current });
@@ -116,18 +356,22 @@ pub fn run_upgrade() -> Result<UpgradeOutcome, UpgradeError> {
             asset: checksum_name.clone(),
         })?;
 
-    eprintln!("Downloading: {archive_name} ({} bytes)", archive_asset.size);
+    reporter::info(&format!(
+        "Downloading: {archive_name} ({} bytes)",
+        archive_asset.size
+    ));
 
     let tempdir = tempfile::tempdir().map_err(UpgradeError::Io)?;
     let archive_path = tempdir.path().join(&archive_name);
     let checksum_path = tempdir.path().join(&checksum_name);
 
-    download_to_file(
-        &client,
-        &checksum_asset.browser_download_url,
-        &checksum_path,
-    )?;
-    download_to_file(&client, &archive_asset.browser_download_url, &archive_path)?;
+    for (asset, path) in [
+        (checksum_asset, &checksum_path),
+        (archive_asset, &archive_path),
+    ] {
+        let url = &asset.browser_download_url;
+        audited(&["download", url], download_to_file(&client, url, path))?;
+    }
 
     let expected = read_sha256_from_file(&checksum_path)?;
     let actual = sha256_file_hex(&archive_path)?;
@@ -135,28 +379,45 @@ pub fn run_upgrade() -> Result<UpgradeOutcome, UpgradeError> {
         return Err(UpgradeError::ChecksumMismatch { expected, actual });
     }
 
-    eprintln!("Verified SHA256 checksum.");
+    reporter::info("Verified SHA256 checksum.");
 
     let extracted_binary_path =
         tempdir
             .path()
             .join(if cfg!(windows) { "ralph.exe" } else { "ralph" });
-    extract_binary_from_archive(&archive_path, &archive_ext, &extracted_binary_path)?;
+    extract_binary_from_archive(&archive_path, archive_ext, &extracted_binary_path)?;
     ensure_executable(&extracted_binary_path)?;
 
-    eprintln!("Replacing current binary: {}", current_exe.display());
-    self_replace(&current_exe, &extracted_binary_path, &install_dir)?;
-
-    // Confirm version by spawning the freshly replaced binary.
-    let confirmed = Command::new(&current_exe)
-        .arg("--version")
-        .output()
-        .map_err(UpgradeError::Io)
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_default();
-    if !confirmed.trim().is_empty() {
-        eprintln!("Now running: {}", confirmed.trim());
+    reporter::info(&format!(
+        "Replacing current binary: {}",
+        current_exe.display()
+    ));
+    let marker = upgrade_marker(&current_exe);
+    fs::write(&marker, format!("v{current} -> v{latest}\n"))?;
+    audited(
+        &[
+            "replace",
+            &current_exe.display().to_string(),
+            &format!("v{latest}"),
+        ],
+        self_replace(&current_exe, &extracted_binary_path, &install_dir),
+    )?;
+    let _ = fs::remove_file(&marker);
+
+    // Confirm what's installed by asking the freshly replaced binary.
+    match installed_build_info(&SystemRunner, &current_exe) {
+        Some(info) if info.version == latest.to_string() => {
+            reporter::info(&format!(
+                "Now running: ralph {} ({})",
+                info.version,
+                info.short_commit()
+            ));
+        }
+        Some(info) => reporter::warn(&format!(
+            "Installed binary reports version {}, expected {latest}",
+            info.version
+        )),
+        None => {}
     }
 
     Ok(UpgradeOutcome::Upgraded {
@@ -165,45 +426,126 @@ pub fn run_upgrade() -> Result<UpgradeOutcome, UpgradeError> {
     })
 }
 
-pub fn permission_denied_suggestions(path: &Path) -> String {
+/// Build metadata reported by the binary at `exe` via `version --json`.
+fn installed_build_info(exec: &dyn CommandRunner, exe: &Path) -> Option<BuildInfo> {
+    let output = audit::run(
+        Kind::Upgrade,
+        Command::new(exe).args(["version", "--json"]),
+        |cmd| exec.output(cmd),
+    )
+    .ok()?;
+    if !output.success() {
+        return None;
+    }
+    serde_json::from_str(&output.stdout).ok()
+}
+
+/// Record `step` of an upgrade in the audit log, however it went.
+fn audited<T>(step: &[&str], result: Result<T, UpgradeError>) -> Result<T, UpgradeError> {
+    audit::action(
+        Kind::Upgrade,
+        step,
+        result.as_ref().err().map(|e| e.to_string()),
+    );
+    result
+}
+
+/// Advice for when replacing the binary at `path` isn't permitted;
+/// `action` is `upgrade` or `uninstall`.
+pub fn permission_denied_suggestions(path: &Path, action: &str) -> String {
     let mut lines = vec![
-        format!(
-            "Error: Cannot write to {} (permission denied)",
-            path.display()
-        ),
+        format!("Cannot write to {} (permission denied)", path.display()),
         "".to_string(),
         "Solutions:".to_string(),
-        "1. Run with elevated permissions: sudo ralph upgrade".to_string(),
-        "2. Reinstall to a user-writable location (e.g. ~/.local/bin)".to_string(),
-        "3. Download manually from GitHub Releases and replace the binary".to_string(),
+        format!("1. Run with elevated permissions: sudo ralph {action}"),
     ];
-    lines.push("".to_string());
+    if action == "uninstall" {
+        lines.push(format!(
+            "2. Delete the binary manually: sudo rm {}",
+            path.display()
+        ));
+    } else {
+        lines.push("2. Reinstall to a user-writable location (e.g. ~/.local/bin)".to_string());
+        lines.push("3. Download manually from GitHub Releases and replace the binary".to_string());
+    }
     lines.join("\n")
 }
 
-fn github_client() -> Result<Client, UpgradeError> {
+fn github_client(timeout: Duration) -> Result<Client, UpgradeError> {
     Client::builder()
         .user_agent(format!("ralph/{}", env!("CARGO_PKG_VERSION")))
-        .timeout(std::time::Duration::from_secs(60))
+        .timeout(timeout)
         .build()
         .map_err(|e| UpgradeError::Network(e.to_string()))
 }
 
+/// Last `releases/latest` response, kept so repeated checks can send
+/// `If-None-Match` and reuse the body on `304 Not Modified`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedRelease {
+    etag: String,
+    body: String,
+}
+
+fn release_cache_path() -> Option<PathBuf> {
+    config::get_config_dir()
+        .ok()
+        .map(|dir| dir.join("cache").join("latest-release.json"))
+}
+
+fn read_release_cache(path: &Path) -> Option<CachedRelease> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Best-effort: a failure to cache never fails the request.
+fn write_release_cache(path: &Path, cached: &CachedRelease) {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string(cached) {
+        let _ = fsutil::write_atomic(path, json);
+    }
+}
+
 fn get_latest_release(client: &Client) -> Result<GithubRelease, UpgradeError> {
     let url = format!("https://api.github.com/repos/{GITHUB_OWNER}/{GITHUB_REPO}/releases/latest");
+    let cache_path = release_cache_path();
+    let cached = cache_path.as_deref().and_then(read_release_cache);
 
-    let resp = client
+    let mut request = client
         .get(url)
-        .header("Accept", "application/vnd.github+json")
+        .header("Accept", "application/vnd.github+json");
+    if let Some(cached) = &cached {
+        request = request.header("If-None-Match", &cached.etag);
+    }
+    let resp = request
         .send()
         .map_err(|e| UpgradeError::Network(e.to_string()))?;
 
-    if resp.status().is_success() {
-        return resp
-            .json::<GithubRelease>()
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED
+        && let Some(cached) = cached
+    {
+        return serde_json::from_str(&cached.body)
             .map_err(|e| UpgradeError::GithubApi(e.to_string()));
     }
 
+    if resp.status().is_success() {
+        let etag = resp
+            .headers()
+            .get("etag")
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        let body = resp
+            .text()
+            .map_err(|e| UpgradeError::Network(e.to_string()))?;
+        let release = serde_json::from_str::<GithubRelease>(&body)
+            .map_err(|e| UpgradeError::GithubApi(e.to_string()))?;
+        if let (Some(path), Some(etag)) = (cache_path, etag) {
+            write_release_cache(&path, &CachedRelease { etag, body });
+        }
+        return Ok(release);
+    }
+
     let status = resp.status();
     let remaining = resp
         .headers()
@@ -290,12 +632,12 @@ fn download_to_file(client: &Client, url: &str, path: &Path) -> Result<(), Upgra
         out.write_all(&buf[..n]).map_err(UpgradeError::Io)?;
         downloaded += n as u64;
         if let Some(total) = total {
-            eprint!("\rDownloaded {downloaded}/{total} bytes…");
+            reporter::progress(
+                &format!("Downloaded {downloaded}/{total} bytes…"),
+                downloaded >= total,
+            );
         }
     }
-    if total.is_some() {
-        eprintln!();
-    }
     Ok(())
 }
 
@@ -352,12 +694,12 @@ fn extract_binary_from_archive(
 
     if archive_ext == "zip" {
         let file = fs::File::open(archive_path).map_err(UpgradeError::Io)?;
-        let mut zip = zip::ZipArchive::new(file)
-            .map_err(|e| UpgradeError::Io(io::Error::new(io::ErrorKind::Other, e)))?;
+        let mut zip =
+            zip::ZipArchive::new(file).map_err(|e| UpgradeError::Io(io::Error::other(e)))?;
         for i in 0..zip.len() {
             let mut file = zip
                 .by_index(i)
-                .map_err(|e| UpgradeError::Io(io::Error::new(io::ErrorKind::Other, e)))?;
+                .map_err(|e| UpgradeError::Io(io::Error::other(e)))?;
             let name = file.name().rsplit('/').next().unwrap_or("");
             if name.eq_ignore_ascii_case("ralph.exe") {
                 let mut out = fs::File::create(out_path).map_err(UpgradeError::Io)?;
@@ -390,6 +732,28 @@ fn self_replace(
     new_exe: &Path,
     install_dir: &Path,
 ) -> Result<(), UpgradeError> {
+    let backup = move_aside(current_exe, install_dir)?;
+
+    match fs::rename(new_exe, current_exe) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(new_exe, current_exe).map_err(UpgradeError::Io)?;
+            let _ = fs::remove_file(new_exe);
+            ensure_executable(current_exe)?;
+        }
+        Err(e) => {
+            let _ = fs::rename(&backup, current_exe);
+            return Err(UpgradeError::Io(e));
+        }
+    }
+
+    remove_backup(&backup);
+    Ok(())
+}
+
+/// Rename the running binary to `<name>.old` in `install_dir`. Windows
+/// won't delete a running executable but does allow renaming it.
+fn move_aside(current_exe: &Path, install_dir: &Path) -> Result<PathBuf, UpgradeError> {
     let file_name = current_exe
         .file_name()
         .and_then(|s| s.to_str())
@@ -399,35 +763,66 @@ fn self_replace(
     let _ = fs::remove_file(&backup);
 
     match fs::rename(current_exe, &backup) {
-        Ok(_) => {}
+        Ok(_) => Ok(backup),
         Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
-            return Err(UpgradeError::PermissionDenied {
+            Err(UpgradeError::PermissionDenied {
                 path: current_exe.to_path_buf(),
-            });
+            })
         }
-        Err(e) => return Err(UpgradeError::Io(e)),
+        Err(e) => Err(UpgradeError::Io(e)),
     }
+}
 
-    match fs::rename(new_exe, current_exe) {
-        Ok(_) => {}
-        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
-            fs::copy(new_exe, current_exe).map_err(UpgradeError::Io)?;
-            let _ = fs::remove_file(new_exe);
-            ensure_executable(current_exe)?;
-        }
-        Err(e) => {
-            let _ = fs::rename(&backup, current_exe);
-            return Err(UpgradeError::Io(e));
-        }
+/// Delete a binary moved aside by `move_aside`. On Windows the file is still
+/// locked by this process, so a detached shell deletes it once we've exited.
+fn remove_backup(backup: &Path) {
+    if cfg!(windows) {
+        let script = format!(
+            "ping -n 3 127.0.0.1 >NUL & del /F /Q \"{}\"",
+            backup.display()
+        );
+        let _ = Command::new("cmd")
+            .args(["/C", &script])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+    } else {
+        let _ = fs::remove_file(backup);
     }
+}
 
-    let _ = fs::remove_file(&backup);
+/// Remove the installed binary at `exe` (the running one), mapping a
+/// permission error to `UpgradeError::PermissionDenied`.
+pub(crate) fn remove_binary(exe: &Path) -> Result<(), UpgradeError> {
+    let install_dir = exe
+        .parent()
+        .ok_or_else(|| UpgradeError::Io(io::Error::other("Invalid exe path")))?;
+    let backup = move_aside(exe, install_dir)?;
+    remove_backup(&backup);
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::exec::ScriptedRunner;
+
+    #[test]
+    fn installed_build_info_asks_the_binary() {
+        let json = serde_json::to_string(&BuildInfo::current()).unwrap();
+        let exec = ScriptedRunner::new().reply(0, &json).reply(1, &json);
+        let exe = Path::new("/usr/local/bin/ralph");
+
+        let info = installed_build_info(&exec, exe).unwrap();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            exec.calls(),
+            [vec!["/usr/local/bin/ralph", "version", "--json"]]
+        );
+        // A failing `version --json` (e.g. an older binary) is no answer.
+        assert!(installed_build_info(&exec, exe).is_none());
+    }
 
     #[test]
     fn parse_release_version_accepts_v_prefix() {
@@ -447,6 +842,135 @@ mod tests {
         assert_eq!(v, Version::parse("0.2.0").unwrap());
     }
 
+    #[test]
+    fn compare_versions_reports_newer_releases_only() {
+        let v = |s| Version::parse(s).unwrap();
+        assert_eq!(
+            compare_versions(v("0.2.6"), v("0.3.0")),
+            UpdateCheck::Available {
+                current: v("0.2.6"),
+                latest: v("0.3.0")
+            }
+        );
+        assert!(matches!(
+            compare_versions(v("0.2.6"), v("0.2.6")),
+            UpdateCheck::UpToDate { .. }
+        ));
+        assert!(matches!(
+            compare_versions(v("0.3.0"), v("0.2.6")),
+            UpdateCheck::UpToDate { .. }
+        ));
+    }
+
+    #[test]
+    fn release_cache_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join("latest-release.json");
+        assert!(read_release_cache(&path).is_none());
+        write_release_cache(
+            &path,
+            &CachedRelease {
+                etag: "\"abc\"".to_string(),
+                body: r#"{"tag_name":"v1.0.0","assets":[]}"#.to_string(),
+            },
+        );
+        let cached = read_release_cache(&path).unwrap();
+        assert_eq!(cached.etag, "\"abc\"");
+        let release: GithubRelease = serde_json::from_str(&cached.body).unwrap();
+        assert_eq!(release.tag_name, "v1.0.0");
+    }
+
+    #[test]
+    fn an_interrupted_replacement_is_restored_from_its_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("ralph");
+        let new = dir.path().join("download");
+        fs::write(&exe, "old release").unwrap();
+        fs::write(&new, "new release").unwrap();
+
+        // What `ralph upgrade` does, up to a power loss halfway through
+        // copying the new binary into place.
+        let marker = upgrade_marker(&exe);
+        fs::write(&marker, "v0.1.0 -> v0.2.0\n").unwrap();
+        let backup = move_aside(&exe, dir.path()).unwrap();
+        assert_eq!(backup, backup_path(&exe));
+        fs::write(&exe, "new rel").unwrap();
+
+        let json = serde_json::to_string(&BuildInfo::current()).unwrap();
+        let exec = ScriptedRunner::new().fail(126, "").reply(0, &json);
+        let outcome = repair(&exec, &exe).unwrap();
+        assert_eq!(
+            outcome,
+            RepairOutcome::Restored {
+                exe: exe.clone(),
+                backup: backup.clone(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            }
+        );
+        let asked: Vec<_> = exec.calls().into_iter().map(|c| c[0].clone()).collect();
+        assert_eq!(
+            asked,
+            [exe.display().to_string(), backup.display().to_string()]
+        );
+        assert_eq!(fs::read_to_string(&exe).unwrap(), "old release");
+        assert!(!marker.exists());
+        assert!(!backup.exists());
+
+        // Stopped after moving the binary aside: there's nothing to replace.
+        fs::write(&marker, "").unwrap();
+        move_aside(&exe, dir.path()).unwrap();
+        let exec = ScriptedRunner::new()
+            .spawn_error(io::ErrorKind::NotFound)
+            .reply(0, &json);
+        assert!(matches!(
+            repair(&exec, &exe).unwrap(),
+            RepairOutcome::Restored { .. }
+        ));
+        assert_eq!(fs::read_to_string(&exe).unwrap(), "old release");
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn repair_leaves_a_working_binary_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("ralph");
+        fs::write(&exe, "release").unwrap();
+        fs::write(upgrade_marker(&exe), "").unwrap();
+        let json = serde_json::to_string(&BuildInfo::current()).unwrap();
+        let exec = ScriptedRunner::new().reply(0, &json);
+        assert!(matches!(
+            repair(&exec, &exe).unwrap(),
+            RepairOutcome::Healthy { .. }
+        ));
+        assert!(!upgrade_marker(&exe).exists());
+
+        // Broken, with no backup or a broken one: nothing to do but say so.
+        let exec = ScriptedRunner::new().fail(126, "");
+        let err = repair(&exec, &exe).unwrap_err().to_string();
+        assert!(err.contains("there's no backup at"), "{err}");
+        fs::write(backup_path(&exe), "").unwrap();
+        let exec = ScriptedRunner::new().fail(126, "").fail(126, "");
+        let err = repair(&exec, &exe).unwrap_err().to_string();
+        assert!(err.contains("and neither does its backup"), "{err}");
+        assert_eq!(fs::read_to_string(&exe).unwrap(), "release");
+    }
+
+    #[test]
+    fn a_backup_repairs_the_binary_beside_it() {
+        assert_eq!(
+            installed_path(Path::new("/usr/local/bin/ralph.old")),
+            Path::new("/usr/local/bin/ralph")
+        );
+        assert_eq!(
+            installed_path(Path::new("/usr/local/bin/ralph")),
+            Path::new("/usr/local/bin/ralph")
+        );
+        assert_eq!(
+            upgrade_marker(Path::new("/bin/ralph.exe")),
+            Path::new("/bin/ralph.exe.upgrading")
+        );
+    }
+
     #[test]
     fn eq_hex_digest_is_case_insensitive() {
         assert!(eq_hex_digest("ABC", "abc"));
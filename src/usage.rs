@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Token counts (and cost, when the provider reports it) for one provider
+/// run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Prompt/input tokens
+    pub input_tokens: u64,
+    /// Completion/output tokens
+    pub output_tokens: u64,
+    /// Cost in USD, for providers that report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+impl Usage {
+    /// Add `other`'s counts; the cost is known if either side knows it.
+    pub fn add(&mut self, other: Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost_usd = match (self.cost_usd, other.cost_usd) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// Sum the usage reported in a provider's stream-json output. Returns
+/// `None` when the output carries no usage data at all.
+pub fn extract(output: &str) -> Option<Usage> {
+    let mut total: Option<Usage> = None;
+    for line in output.lines() {
+        let Ok(value @ Value::Object(_)) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if let Some(usage) = line_usage(&value) {
+            total.get_or_insert_with(Usage::default).add(usage);
+        }
+    }
+    total
+}
+
+/// The model named in a provider's stream-json output: the first `model`
+/// of an event (claude's, gemini's and droid's start-up events carry one)
+/// or of a claude message.
+pub fn model(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let value = serde_json::from_str::<Value>(line).ok()?;
+        value["model"]
+            .as_str()
+            .or_else(|| value["message"]["model"].as_str())
+            .filter(|m| !m.is_empty())
+            .map(str::to_string)
+    })
+}
+
+fn line_usage(value: &Value) -> Option<Usage> {
+    let cost_usd = value["total_cost_usd"]
+        .as_f64()
+        .or_else(|| value["cost_usd"].as_f64());
+    // claude `result` and codex `turn.completed` carry `usage`; gemini's
+    // `result` carries `stats`.
+    let counts = [&value["usage"], &value["stats"]]
+        .into_iter()
+        .find(|v| v.is_object())?;
+    let input_tokens = counts["input_tokens"]
+        .as_u64()
+        .or_else(|| counts["inputTokens"].as_u64())?;
+    let output_tokens = counts["output_tokens"]
+        .as_u64()
+        .or_else(|| counts["outputTokens"].as_u64())
+        .unwrap_or(0);
+    Some(Usage {
+        input_tokens,
+        output_tokens,
+        cost_usd,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claude_result_with_cost() {
+        let output = concat!(
+            r#"{"type":"assistant","message":{"content":[]}}"#,
+            "\n",
+            r#"{"type":"result","result":"ok","total_cost_usd":0.25,"usage":{"input_tokens":100,"output_tokens":20}}"#,
+            "\n"
+        );
+        assert_eq!(
+            extract(output),
+            Some(Usage {
+                input_tokens: 100,
+                output_tokens: 20,
+                cost_usd: Some(0.25)
+            })
+        );
+    }
+
+    #[test]
+    fn codex_turns_are_summed_without_cost() {
+        let output = concat!(
+            r#"{"type":"turn.completed","usage":{"input_tokens":10,"output_tokens":1}}"#,
+            "\n",
+            r#"{"type":"turn.completed","usage":{"input_tokens":5,"output_tokens":2}}"#,
+            "\n"
+        );
+        let usage = extract(output).unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (15, 3));
+        assert_eq!(usage.cost_usd, None);
+    }
+
+    #[test]
+    fn output_without_usage_is_none() {
+        assert_eq!(extract("plain text\n{\"type\":\"message\"}\n"), None);
+    }
+
+    #[test]
+    fn the_model_comes_from_the_first_event_naming_one() {
+        let output = concat!(
+            "plain text\n",
+            r#"{"type":"system","subtype":"init","model":"claude-sonnet-4-5"}"#,
+            "\n",
+            r#"{"type":"assistant","message":{"model":"claude-haiku-4-5","content":[]}}"#,
+            "\n"
+        );
+        assert_eq!(model(output).as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(
+            model(r#"{"type":"assistant","message":{"model":"claude-opus-4-5"}}"#).as_deref(),
+            Some("claude-opus-4-5")
+        );
+        assert_eq!(model(r#"{"type":"turn.completed"}"#), None);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::exec::CommandRunner;
+use crate::reporter;
+use crate::runner::{self, LoopRunner};
+use crate::session::Event;
+use crate::tracker::Tracker;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Upper bound for the poll delay after repeated tracker failures.
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// Options for `ralph watch`.
+#[derive(Debug)]
+pub struct WatchOptions {
+    /// Provider each loop runs with
+    pub provider: String,
+    /// Tracker polled for ready work
+    pub tracker: Box<dyn Tracker>,
+    /// Delay between polls of the tracker
+    pub interval: Duration,
+    /// Iteration budget for each loop started on wake-up
+    pub iterations: u32,
+    /// Text ending each loop as complete
+    pub marker: String,
+    /// See [`LoopRunnerBuilder::inject_marker_instructions`](crate::runner::LoopRunnerBuilder::inject_marker_instructions)
+    pub inject_marker_instructions: bool,
+    /// Gates each loop runs after every iteration
+    pub gates: Vec<String>,
+}
+
+/// Poll the tracker every `interval` and run a loop whenever work is ready,
+/// until interrupted with Ctrl-C. The tracker and the provider run through
+/// `exec`.
+pub fn run(exec: &dyn CommandRunner, opts: &WatchOptions, prompt: &str) {
+    runner::install_interrupt_handler();
+
+    let mut session = runner::start_session("watch", &opts.provider, opts.iterations, prompt);
+    reporter::info(&format!(
+        "Watching for ready tasks every {}s (Ctrl-C to stop)",
+        opts.interval.as_secs()
+    ));
+
+    let mut failures: u32 = 0;
+    let mut total_iterations = 0;
+
+    while !runner::interrupted() {
+        match opts.tracker.ready_count(exec) {
+            Ok(ready) if ready > 0 => {
+                failures = 0;
+                reporter::info("");
+                reporter::info(&format!("{} ready task(s) found, starting loop", ready));
+                runner::record(&mut session, Event::WatchWake { ready });
+
+                let mut runner = LoopRunner::builder()
+                    .provider(opts.provider.as_str())
+                    .prompt(prompt)
+                    .iterations(opts.iterations)
+                    .marker(opts.marker.as_str())
+                    .inject_marker_instructions(opts.inject_marker_instructions)
+                    .exec(exec);
+                for gate in &opts.gates {
+                    runner = runner.gate(gate.as_str());
+                }
+                let run = runner.run();
+                let event = match run {
+                    Ok(summary) => {
+                        total_iterations += summary.iterations;
+                        Event::WatchWakeEnd {
+                            loop_session: summary.session_id,
+                            iterations: summary.iterations,
+                            completed: summary.completed,
+                            error: None,
+                        }
+                    }
+                    Err(e) => {
+                        reporter::error(&format!(
+                            "Failed to execute provider '{}': {}",
+                            opts.provider, e
+                        ));
+                        Event::WatchWakeEnd {
+                            loop_session: None,
+                            iterations: 0,
+                            completed: false,
+                            error: Some(e.to_string()),
+                        }
+                    }
+                };
+                runner::record(&mut session, event);
+                reporter::info("Waiting for ready tasks…");
+            }
+            Ok(_) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                reporter::warn(&format!("Failed to poll for ready tasks: {}", e));
+                runner::record(
+                    &mut session,
+                    Event::WatchPollFailed {
+                        error: e.to_string(),
+                        consecutive: failures,
+                    },
+                );
+            }
+        }
+
+        sleep_unless_interrupted(backoff_delay(opts.interval, failures));
+    }
+
+    reporter::summary("Watch stopped.");
+    runner::record(
+        &mut session,
+        Event::SessionEnd {
+            iterations: total_iterations,
+            completed: false,
+        },
+    );
+}
+
+/// Poll delay after `failures` consecutive failed polls: the interval,
+/// doubled per failure, capped at `MAX_BACKOFF` (or the interval, if larger).
+fn backoff_delay(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    let factor = 1u32 << failures.min(6);
+    interval
+        .saturating_mul(factor)
+        .min(MAX_BACKOFF.max(interval))
+}
+
+fn sleep_unless_interrupted(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !runner::interrupted() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(200)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let interval = Duration::from_secs(60);
+        assert_eq!(backoff_delay(interval, 0), interval);
+        assert_eq!(backoff_delay(interval, 1), Duration::from_secs(120));
+        assert_eq!(backoff_delay(interval, 3), Duration::from_secs(480));
+        assert_eq!(backoff_delay(interval, 10), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn backoff_never_shortens_long_intervals() {
+        let interval = Duration::from_secs(3600);
+        assert_eq!(backoff_delay(interval, 2), interval);
+    }
+}
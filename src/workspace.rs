@@ -0,0 +1,313 @@
+use crate::error::{RalphError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A `loop --workspace` manifest: the repositories to run a loop in, one
+/// after another.
+///
+/// ```toml
+/// [[repo]]
+/// path = "../billing"
+///
+/// [[repo]]
+/// path = "../search"
+/// provider = "claude"
+/// iterations = 5
+/// prompt = "prompts/search.md"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// The repositories, in the order they run
+    #[serde(default, rename = "repo")]
+    pub repos: Vec<Repo>,
+}
+
+/// One `[[repo]]` of a [`Manifest`]. Unset values come from the command
+/// line and the repository's own configuration, as for a plain `loop`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Repo {
+    /// The repository's directory; [`Manifest::load`] resolves it against
+    /// the manifest's directory
+    pub path: PathBuf,
+    /// Provider to use in this repository
+    pub provider: Option<String>,
+    /// Maximum number of iterations in this repository
+    pub iterations: Option<u32>,
+    /// Prompt file to use instead of the system prompt, resolved like
+    /// [`path`](Repo::path)
+    pub prompt: Option<PathBuf>,
+}
+
+impl Manifest {
+    /// Read the manifest at `path`, resolving the relative paths in it
+    /// against its directory, so they hold wherever the loops run from.
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let content = fs::read_to_string(path).map_err(|e| invalid_manifest(path, e))?;
+        let mut manifest: Manifest =
+            toml::from_str(&content).map_err(|e| invalid_manifest(path, e))?;
+        if manifest.repos.is_empty() {
+            return Err(invalid_manifest(path, "no [[repo]] entries"));
+        }
+        let absolute = std::path::absolute(path)?;
+        let base = absolute.parent().unwrap_or(Path::new("/"));
+        for repo in &mut manifest.repos {
+            repo.path = base.join(&repo.path);
+            repo.prompt = repo.prompt.as_ref().map(|p| base.join(p));
+        }
+        Ok(manifest)
+    }
+}
+
+fn invalid_manifest(path: &Path, err: impl std::fmt::Display) -> RalphError {
+    RalphError::InvalidInput(format!(
+        "Invalid workspace manifest {}: {}",
+        path.display(),
+        err
+    ))
+}
+
+/// How the loop in one repository of a workspace ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The agent reported all tasks complete
+    Completed,
+    /// The loop ran out of iterations first
+    Incomplete,
+    /// The loop couldn't start or stopped with an error
+    Failed,
+    /// The repository's directory doesn't exist
+    Missing,
+    /// Not run, because an earlier repository failed and
+    /// `--stop-on-repo-failure` was given
+    Skipped,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Status::Completed => "completed",
+            Status::Incomplete => "incomplete",
+            Status::Failed => "failed",
+            Status::Missing => "missing",
+            Status::Skipped => "skipped",
+        })
+    }
+}
+
+/// What happened in one repository of a workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoOutcome {
+    /// The repository's directory
+    pub path: PathBuf,
+    /// How its loop ended
+    pub status: Status,
+    /// Id of the session its loop was recorded under
+    pub session_id: Option<String>,
+    /// Iterations run
+    pub iterations: u32,
+    /// Why it failed, or how it's missing
+    pub error: Option<String>,
+}
+
+impl RepoOutcome {
+    /// A repository that wasn't run: `status` says why, `error` how.
+    pub fn not_run(path: &Path, status: Status, error: Option<String>) -> RepoOutcome {
+        RepoOutcome {
+            path: path.to_path_buf(),
+            status,
+            session_id: None,
+            iterations: 0,
+            error,
+        }
+    }
+}
+
+/// The outcome of a whole `loop --workspace`, as printed with
+/// [`render_table`] and as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WorkspaceSummary {
+    /// The manifest
+    pub manifest: PathBuf,
+    /// One outcome per repository, in manifest order
+    pub repos: Vec<RepoOutcome>,
+    /// Repositories whose work is complete
+    pub completed: usize,
+    /// Repositories that ran out of iterations
+    pub incomplete: usize,
+    /// Repositories whose loop failed
+    pub failed: usize,
+    /// Repositories whose directory doesn't exist
+    pub missing: usize,
+    /// Repositories not run after a failure
+    pub skipped: usize,
+    /// Iterations run across all repositories
+    pub iterations: u32,
+}
+
+impl WorkspaceSummary {
+    /// Tally `repos`.
+    pub fn new(manifest: &Path, repos: Vec<RepoOutcome>) -> WorkspaceSummary {
+        let count = |status| repos.iter().filter(|r| r.status == status).count();
+        WorkspaceSummary {
+            manifest: manifest.to_path_buf(),
+            completed: count(Status::Completed),
+            incomplete: count(Status::Incomplete),
+            failed: count(Status::Failed),
+            missing: count(Status::Missing),
+            skipped: count(Status::Skipped),
+            iterations: repos.iter().map(|r| r.iterations).sum(),
+            repos,
+        }
+    }
+
+    /// Whether every repository was found and ran without an error.
+    pub fn succeeded(&self) -> bool {
+        self.failed == 0 && self.missing == 0 && self.skipped == 0
+    }
+}
+
+/// The per-repository outcome table, with a line of totals. Repositories
+/// under the manifest's directory are named relative to it.
+pub fn render_table(summary: &WorkspaceSummary) -> String {
+    let base = std::path::absolute(&summary.manifest)
+        .ok()
+        .and_then(|m| m.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    let paths: Vec<String> = summary
+        .repos
+        .iter()
+        .map(|r| r.path.strip_prefix(&base).unwrap_or(&r.path))
+        .map(|p| p.display().to_string())
+        .collect();
+    let width = paths.iter().map(|p| p.len()).max().unwrap_or(0).max(4);
+    let mut out = format!(
+        "{:<width$}  {:<10}  {:>5}  {}\n",
+        "REPO", "OUTCOME", "ITERS", "SESSION"
+    );
+    for (repo, path) in summary.repos.iter().zip(&paths) {
+        let mut line = format!(
+            "{:<width$}  {:<10}  {:>5}  {}",
+            path,
+            repo.status,
+            repo.iterations,
+            repo.session_id.as_deref().unwrap_or("-")
+        );
+        if let Some(error) = repo.error.as_deref().and_then(|e| e.lines().next()) {
+            line.push_str(&format!(" ({error})"));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "{} repos: {} completed, {} incomplete, {} failed, {} missing, {} skipped; {} iterations\n",
+        summary.repos.len(),
+        summary.completed,
+        summary.incomplete,
+        summary.failed,
+        summary.missing,
+        summary.skipped,
+        summary.iterations
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_resolve_against_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workspace.toml");
+        fs::write(
+            &path,
+            "[[repo]]\npath = \"billing\"\n\n\
+             [[repo]]\npath = \"/srv/search\"\nprovider = \"claude\"\niterations = 5\nprompt = \"search.md\"\n",
+        )
+        .unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(
+            manifest.repos,
+            vec![
+                Repo {
+                    path: dir.path().join("billing"),
+                    provider: None,
+                    iterations: None,
+                    prompt: None,
+                },
+                Repo {
+                    path: PathBuf::from("/srv/search"),
+                    provider: Some("claude".to_string()),
+                    iterations: Some(5),
+                    prompt: Some(dir.path().join("search.md")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_manifests_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("workspace.toml");
+        for (content, expected) in [
+            ("", "no [[repo]] entries"),
+            (
+                "[[repo]]\npath = \"a\"\nmodel = \"x\"\n",
+                "unknown field `model`",
+            ),
+            ("[[repo]]\nprovider = \"claude\"\n", "missing field `path`"),
+        ] {
+            fs::write(&path, content).unwrap();
+            let err = Manifest::load(&path).unwrap_err().to_string();
+            assert!(err.starts_with("Invalid workspace manifest "), "{err}");
+            assert!(err.contains(expected), "{content:?}: {err}");
+        }
+        let err = Manifest::load(&dir.path().join("absent.toml")).unwrap_err();
+        assert!(err.to_string().contains("absent.toml"), "{err}");
+    }
+
+    #[test]
+    fn summaries_tally_and_render_every_repo() {
+        let repo = |path: &str, status, iterations, error: Option<&str>| RepoOutcome {
+            path: Path::new("/srv").join(path),
+            status,
+            session_id: (iterations > 0).then(|| format!("s-{path}")),
+            iterations,
+            error: error.map(str::to_string),
+        };
+        let summary = WorkspaceSummary::new(
+            Path::new("/srv/workspace.toml"),
+            vec![
+                repo("billing", Status::Completed, 2, None),
+                repo(
+                    "search",
+                    Status::Failed,
+                    1,
+                    Some("provider crashed\nsee the log"),
+                ),
+                repo("gone", Status::Missing, 0, Some("no such directory")),
+            ],
+        );
+        assert_eq!(
+            (summary.completed, summary.failed, summary.missing),
+            (1, 1, 1)
+        );
+        assert_eq!(summary.iterations, 3);
+        assert!(!summary.succeeded());
+        assert_eq!(
+            render_table(&summary),
+            "REPO     OUTCOME     ITERS  SESSION\n\
+             billing  completed       2  s-billing\n\
+             search   failed          1  s-search (provider crashed)\n\
+             gone     missing         0  - (no such directory)\n\
+             3 repos: 1 completed, 0 incomplete, 1 failed, 1 missing, 0 skipped; 3 iterations\n"
+        );
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["repos"][2]["status"], "missing");
+        assert_eq!(json["completed"], 1);
+    }
+}
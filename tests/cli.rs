@@ -0,0 +1,2989 @@
+//! End-to-end tests of the `ralph` binary with the `fake-agent` example
+//! standing in for the provider CLI via `RALPH_PROVIDER_CMD`.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The `fake-agent` example, which `cargo test` builds next to the tests.
+fn fake_agent() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    let path = deps
+        .parent()
+        .and_then(Path::parent)
+        .unwrap()
+        .join("examples")
+        .join(format!("fake-agent{}", std::env::consts::EXE_SUFFIX));
+    assert!(
+        path.exists(),
+        "{} is missing: run the whole test suite, or `cargo build --example fake-agent` first",
+        path.display()
+    );
+    path
+}
+
+/// The fake agent's settings besides its call counter, cleared so a test
+/// only sees the ones it sets.
+const FAKE_AGENT_VARS: &[&str] = &[
+    "FAKE_AGENT_EVENTS",
+    "FAKE_AGENT_DELAY_MS",
+    "FAKE_AGENT_REPLAY",
+    "FAKE_AGENT_COMPLETE_ON",
+    "FAKE_AGENT_STDERR",
+    "FAKE_AGENT_EXIT",
+    "FAKE_AGENT_EXIT_ON",
+    "FAKE_AGENT_SIGNAL",
+    "FAKE_AGENT_HANG",
+    "FAKE_AGENT_IGNORE_TERM",
+    "FAKE_AGENT_CHILD",
+    "FAKE_AGENT_ECHO_PROMPT",
+    "FAKE_AGENT_LOGIN_PROMPT",
+    "FAKE_AGENT_ECHO_ENV",
+    "FAKE_AGENT_APPEND_TO",
+];
+
+/// Environment variables that override config.toml.
+const CONFIG_VARS: &[&str] = &[
+    "RALPH_PROVIDER",
+    "RALPH_ITERATIONS",
+    "RALPH_MARKER",
+    "RALPH_UPDATE_CHECK",
+    "RALPH_TRACKER",
+    "RALPH_TRACKER_SYNC",
+    "RALPH_TRACKER_SYNC_COMMAND",
+    "RALPH_TRACKER_REPO",
+    "RALPH_TRACKER_LABEL",
+    "RALPH_TRACKER_TIMEOUT_SECS",
+    "RALPH_AUTO_INIT_BD",
+    "RALPH_CLEAN_OLDER_THAN",
+    "RALPH_LOGIN_PHRASES",
+    "RALPH_MAX_LINE_BYTES",
+    "RALPH_KEEP_LONG_LINES",
+    "RALPH_COLLAPSE_REPEATS",
+    "RALPH_MAX_OUTPUT_BYTES",
+    "RALPH_NICE",
+    "RALPH_IONICE",
+    "RALPH_AUTO_APPROVE",
+    "RALPH_AUDIT",
+    "RALPH_METRICS_FILE",
+    "RALPH_ENV_POLICY",
+    "RALPH_ENV_ALLOW",
+    "RALPH_ENV_BLOCK",
+    "RALPH_INTERACTIVE",
+];
+
+/// A throwaway `RALPH_HOME` and working directory for one test.
+struct Env {
+    home: TempDir,
+    cwd: TempDir,
+}
+
+impl Env {
+    fn new() -> Self {
+        Env {
+            home: tempfile::tempdir().unwrap(),
+            cwd: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    fn state(&self) -> PathBuf {
+        self.home.path().join("fake-agent-calls")
+    }
+
+    fn calls(&self) -> u32 {
+        fs::read_to_string(self.state())
+            .map(|s| s.trim().parse().unwrap())
+            .unwrap_or(0)
+    }
+
+    /// `ralph` with the fake agent as provider and its call counter set up.
+    /// bd isn't installed where the tests run, so the tracker is off unless
+    /// a test removes `RALPH_TRACKER` again.
+    fn ralph(&self) -> Command {
+        let mut cmd = Command::cargo_bin("ralph").unwrap();
+        cmd.current_dir(self.cwd.path())
+            .env("RALPH_HOME", self.home.path())
+            .env("RALPH_PROVIDER_CMD", fake_agent())
+            .env("FAKE_AGENT_STATE", self.state());
+        for var in FAKE_AGENT_VARS.iter().chain(CONFIG_VARS) {
+            cmd.env_remove(var);
+        }
+        cmd.env("RALPH_TRACKER", "none");
+        cmd
+    }
+}
+
+#[test]
+fn loop_stops_at_the_complete_marker() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "5"])
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("RALPH_PROVIDER_CMD="))
+        .stderr(predicate::str::contains(
+            "All tasks complete after 2 iterations.",
+        ));
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn loop_without_marker_uses_the_whole_budget() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "3"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fake-agent call 3"))
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 3 iterations",
+        ));
+    assert_eq!(env.calls(), 3);
+}
+
+#[test]
+fn loop_keeps_going_after_a_failed_iteration() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "3"])
+        .env("FAKE_AGENT_EXIT", "1")
+        .env("FAKE_AGENT_EXIT_ON", "1")
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .assert()
+        .success();
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn loop_succeeds_even_when_every_iteration_fails() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_EXIT", "5")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 2 iterations",
+        ));
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn marker_on_stderr_does_not_end_the_loop() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_STDERR", "<promise>COMPLETE</promise>")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 2 iterations",
+        ));
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn provider_events_and_stderr_pass_through() {
+    let env = Env::new();
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_EVENTS", "3")
+        .env("FAKE_AGENT_DELAY_MS", "20")
+        .env("FAKE_AGENT_STDERR", "agent warning")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("event 1 of 3"))
+        .stdout(predicate::str::contains("event 3 of 3"))
+        .stderr(predicate::str::contains("agent warning"));
+}
+
+#[test]
+fn heavy_output_on_both_streams_is_interleaved_and_kept() {
+    let env = Env::new();
+    // Far more than a pipe buffer on each stream, so reading one while the
+    // other fills up would hang.
+    let out = env
+        .ralph()
+        .args(["loop", "--iterations", "1"])
+        .env("FAKE_AGENT_EVENTS", "20000")
+        .env("FAKE_AGENT_EVENT_WARNINGS", "1")
+        .timeout(Duration::from_secs(60))
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stdout.contains("event 20000 of 20000"));
+    assert!(!stdout.contains("warning 1 of"));
+    assert!(stderr.contains("\n[stderr] warning 1 of 20000\n"));
+    assert!(stderr.contains("\n[stderr] warning 20000 of 20000\n"));
+}
+
+#[test]
+fn a_failed_iteration_quotes_the_providers_stderr() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_STDERR", "Error: 401 Unauthorized")
+        .env("FAKE_AGENT_EXIT", "1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("[stderr] Error: 401 Unauthorized"))
+        .stderr(predicate::str::contains(
+            "Iteration 2: provider exited with code 1: Error: 401 Unauthorized",
+        ));
+}
+
+#[test]
+fn long_prompts_reach_the_provider_on_stdin() {
+    let env = Env::new();
+    let prompt = format!("{}\nEND OF THE LONG PROMPT", "docs ".repeat(40_000));
+    fs::write(env.home.path().join("system-prompt.md"), &prompt).unwrap();
+    env.ralph()
+        .args(["once", "--verbose"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("END OF THE LONG PROMPT"))
+        .stderr(predicate::str::contains(format!(
+            "Passing the {}-byte prompt to droid on stdin",
+            prompt.len()
+        )));
+    // The temporary prompt file is gone once the provider is.
+    let leftovers = fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.file_name().to_string_lossy().starts_with("ralph-prompt-")
+                && fs::read_to_string(e.path()).is_ok_and(|p| p == prompt)
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn prompt_via_stdin_pipes_even_short_prompts() {
+    let env = Env::new();
+    fs::write(env.home.path().join("system-prompt.md"), "THE PROMPT").unwrap();
+    // `cat THE PROMPT` would look for a file of that name.
+    env.ralph()
+        .arg("once")
+        .env("RALPH_PROVIDER_CMD", "cat")
+        .assert()
+        .failure();
+
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[providers.droid]\nprompt_via = \"stdin\"\n",
+    )
+    .unwrap();
+    for args in [
+        &["once", "--verbose"][..],
+        &["loop", "--iterations", "1", "--verbose"],
+    ] {
+        env.ralph()
+            .args(args)
+            .env("RALPH_PROVIDER_CMD", "cat")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("THE PROMPT"))
+            .stderr(predicate::str::contains("byte prompt to droid on stdin"))
+            .stderr(predicate::str::contains("as its prompt_via is stdin"));
+    }
+}
+
+#[test]
+fn a_provider_waiting_for_a_login_fails_fast() {
+    let env = Env::new();
+    let started = std::time::Instant::now();
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_LOGIN_PROMPT", "Please log in to continue")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Provider 'droid' is waiting for an interactive login (\"Please log in to continue\")",
+        ));
+    assert!(started.elapsed() < std::time::Duration::from_secs(30));
+
+    // Phrases from config catch prompts ralph doesn't know.
+    env.ralph()
+        .arg("once")
+        .env(
+            "FAKE_AGENT_LOGIN_PROMPT",
+            "Token expired. Paste a new one: ",
+        )
+        .env("RALPH_LOGIN_PHRASES", "paste a new one")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("waiting for an interactive login"));
+}
+
+#[test]
+fn env_policies_keep_blocked_variables_from_the_provider() {
+    let env = Env::new();
+    let run = |args: &[&str], vars: &[(&str, &str)]| {
+        let mut cmd = env.ralph();
+        cmd.arg("once")
+            .args(args)
+            .env("DEPLOY_TOKEN", "hunter2")
+            .env("FACTORY_API_KEY", "fk-123")
+            .env("FAKE_AGENT_ECHO_ENV", "DEPLOY_TOKEN,FACTORY_API_KEY,PATH");
+        for (name, value) in vars {
+            cmd.env(name, value);
+        }
+        let out = cmd.assert().success().get_output().clone();
+        (
+            String::from_utf8(out.stdout).unwrap(),
+            String::from_utf8(out.stderr).unwrap(),
+        )
+    };
+
+    let (stdout, _) = run(&[], &[]);
+    assert!(stdout.contains("DEPLOY_TOKEN=hunter2"), "{stdout}");
+
+    let (stdout, stderr) = run(
+        &["--env-policy", "blocklist", "--verbose"],
+        &[("RALPH_ENV_BLOCK", "DEPLOY_*,FACTORY_API_KEY")],
+    );
+    assert!(stdout.contains("DEPLOY_TOKEN is unset"), "{stdout}");
+    // Credentials and PATH survive any list.
+    assert!(stdout.contains("FACTORY_API_KEY=fk-123"), "{stdout}");
+    assert!(stdout.contains("PATH="), "{stdout}");
+    assert!(
+        stderr.contains("Environment for droid (blocklist): "),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("DEPLOY_TOKEN"), "{stderr}");
+    assert!(!stderr.contains("fk-123"), "{stderr}");
+
+    // The fake agent's own settings have to be allowed to reach it.
+    let (stdout, _) = run(
+        &["--env-policy", "allowlist"],
+        &[("RALPH_ENV_ALLOW", "FAKE_AGENT_*")],
+    );
+    assert!(stdout.contains("DEPLOY_TOKEN is unset"), "{stdout}");
+    assert!(stdout.contains("FACTORY_API_KEY=fk-123"), "{stdout}");
+
+    // --print-config lists the names the provider would get, never values.
+    env.ralph()
+        .args(["once", "--env-policy", "allowlist", "--print-config"])
+        .env("FACTORY_API_KEY", "fk-123")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("policy = \"allowlist\""))
+        .stdout(predicate::str::contains(
+            "# Environment for droid (allowlist): ",
+        ))
+        .stdout(predicate::str::contains("FACTORY_API_KEY"))
+        .stdout(predicate::str::contains("fk-123").not());
+}
+
+#[test]
+fn config_sets_variables_for_the_provider() {
+    let env = Env::new();
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[env]\nOPENAI_BASE_URL = \"https://proxy\"\nMODEL_HINT = \"global\"\n\
+         [providers.droid.env]\nMODEL_HINT = \"droid\"\nFACTORY_API_KEY = \"env:WORK_KEY\"\n\
+         [providers.codex.env]\nMODEL_HINT = \"codex\"\n",
+    )
+    .unwrap();
+    for args in [&["once"][..], &["loop", "--iterations", "1"]] {
+        env.ralph()
+            .args(args)
+            .env("WORK_KEY", "fk-work")
+            .env(
+                "FAKE_AGENT_ECHO_ENV",
+                "OPENAI_BASE_URL,MODEL_HINT,FACTORY_API_KEY",
+            )
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("OPENAI_BASE_URL=https://proxy"))
+            .stdout(predicate::str::contains("MODEL_HINT=droid"))
+            .stdout(predicate::str::contains("FACTORY_API_KEY=fk-work"));
+    }
+
+    // A variable the config reads from must be set before the first call.
+    env.ralph()
+        .arg("once")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: [providers.droid.env] FACTORY_API_KEY = \"env:WORK_KEY\": WORK_KEY is not set",
+        ));
+    assert_eq!(env.calls(), 2);
+
+    // The config shows the reference, never the secret.
+    env.ralph()
+        .args(["once", "--print-config"])
+        .env("WORK_KEY", "fk-work")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "FACTORY_API_KEY = \"env:WORK_KEY\"",
+        ))
+        .stdout(predicate::str::contains("fk-work").not());
+}
+
+#[test]
+fn provider_help_lists_the_registered_providers_once() {
+    let env = Env::new();
+    for command in ["once", "loop", "run", "watch"] {
+        let out = env.ralph().args([command, "--help"]).output().unwrap();
+        let help = String::from_utf8(out.stdout).unwrap();
+        assert_eq!(help.matches("copilot").count(), 1, "{command}: {help}");
+        assert!(
+            help.contains("[possible values: droid, codex, claude, gemini, copilot, qwen]"),
+            "{command}: {help}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn text_output_drops_the_stream_json_flags_and_still_completes() {
+    use std::os::unix::fs::PermissionsExt;
+    let env = Env::new();
+    let bin = tempfile::tempdir().unwrap();
+    let claude = bin.path().join("claude");
+    // Prints its flags, leaving out the prompt at the end, then the marker.
+    fs::write(
+        &claude,
+        "#!/bin/sh\nprintf 'flags:'\nwhile [ $# -gt 1 ]; do printf ' %s' \"$1\"; shift; done\n\
+         echo\necho 'All done. <promise>COMPLETE</promise>'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.path().display(),
+        std::env::var("PATH").unwrap()
+    );
+    let ralph = |args: &[&str]| {
+        let mut cmd = env.ralph();
+        cmd.env_remove("RALPH_PROVIDER_CMD")
+            .env("PATH", &path)
+            .args(args)
+            .args(["--provider", "claude"]);
+        cmd
+    };
+
+    ralph(&["once"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "flags: -p --verbose --output-format stream-json --permission-mode acceptEdits\n",
+        ));
+    ralph(&["loop", "--iterations", "3", "--output-format", "text"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "flags: -p --permission-mode acceptEdits\n",
+        ))
+        .stderr(predicate::str::contains(
+            "All tasks complete after 1 iterations",
+        ));
+    ralph(&["once", "--output-format", "json"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "[possible values: stream-json, text]",
+        ));
+    ralph(&["once", "--output-format", "text", "--validate-stream"])
+        .assert()
+        .code(2);
+}
+
+#[cfg(unix)]
+#[test]
+fn config_defines_providers_as_command_templates() {
+    use std::os::unix::fs::PermissionsExt;
+    let env = Env::new();
+    let prompt = "Fix the \"flaky\" test\nand don't stop; $HOME `id`";
+    fs::write(env.home.path().join("system-prompt.md"), prompt).unwrap();
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[providers.mywrapper]\n\
+         command = \"./scripts/agent.sh {prompt} --iteration={iteration}\"\n\
+         [providers.mysh]\n\
+         command = \"echo got {prompt} && echo iteration {iteration}\"\n\
+         shell = true\n",
+    )
+    .unwrap();
+    let scripts = env.cwd.path().join("scripts");
+    fs::create_dir_all(&scripts).unwrap();
+    let agent = scripts.join("agent.sh");
+    fs::write(
+        &agent,
+        "#!/bin/sh\nprintf '%s' \"$1\" > prompt.txt\necho \"$# args, $2\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&agent, fs::Permissions::from_mode(0o755)).unwrap();
+    let prompt_file = env.cwd.path().join("prompt.txt");
+
+    env.ralph()
+        .env_remove("RALPH_PROVIDER_CMD")
+        .args(["once", "--provider", "mywrapper"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Using AI provider: mywrapper (./scripts/agent.sh {prompt} --iteration={iteration})",
+        ))
+        .stdout(predicate::str::contains("2 args, --iteration=1\n"));
+    assert_eq!(fs::read_to_string(&prompt_file).unwrap(), prompt);
+
+    env.ralph()
+        .env_remove("RALPH_PROVIDER_CMD")
+        .args(["loop", "--provider", "mywrapper", "--iterations", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 args, --iteration=2\n"));
+    assert!(
+        fs::read_to_string(&prompt_file)
+            .unwrap()
+            .starts_with(prompt)
+    );
+
+    env.ralph()
+        .env_remove("RALPH_PROVIDER_CMD")
+        .args(["once", "--provider", "mysh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "got {prompt}\niteration 1\n"
+        )));
+
+    env.ralph()
+        .args(["once", "--provider", "nope"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Available providers: droid, codex, claude, gemini, copilot, qwen, mysh, mywrapper",
+        ));
+}
+
+#[test]
+fn provider_aliases_run_the_provider_they_stand_for() {
+    let env = Env::new();
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[aliases]\noai = \"codex\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["once", "--provider", "oai"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Using AI provider: codex (via"));
+    assert!(newest_transcript(&env).contains(r#""provider":"codex""#));
+    env.ralph()
+        .args(["loop", "--iterations", "1", "--provider", "cc"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Using AI provider: claude (via"));
+
+    env.ralph()
+        .args(["once", "--provider", "oia"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: Invalid provider 'oia'\nAvailable providers: droid, codex, claude, gemini, copilot, qwen\nDid you mean 'oai'?",
+        ));
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn log_targets_take_ralphs_messages_but_not_provider_output() {
+    let env = Env::new();
+    let log = env.home.path().join("ralph.log");
+    env.ralph()
+        .args(["loop", "--iterations", "2", "--log-target"])
+        .arg(format!("file:{}", log.display()))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fake-agent call 2"))
+        .stderr(predicate::str::contains("Iteration").not());
+    let text = fs::read_to_string(&log).unwrap();
+    assert!(
+        text.lines().any(|l| l.contains(" notice [session=")
+            && l.contains(" provider=droid iteration=2] Iteration 2 / 2")),
+        "{text}"
+    );
+    assert!(!text.contains("fake-agent call"), "{text}");
+
+    env.ralph()
+        .args(["once", "--log-target", "stdout"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "expected one of stderr, syslog, journald, or file:<path>",
+        ));
+}
+
+#[test]
+fn reload_prompt_picks_up_edits_between_iterations() {
+    let env = Env::new();
+    let prompt = env.home.path().join("system-prompt.md");
+    fs::write(&prompt, "Work on the backlog.\n").unwrap();
+
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "2",
+            "--reload-prompt",
+            "--no-inject-marker-instructions",
+        ])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .env("FAKE_AGENT_APPEND_TO", &prompt)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Work on the backlog.\\ncall 1"))
+        .stderr(predicate::str::contains("System prompt changed ("))
+        .stderr(predicate::str::contains(", +1 -0 lines)"));
+
+    let hashes: Vec<String> = newest_transcript(&env)
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .filter(|r| r["event"] == "iteration_start")
+        .map(|r| r["prompt_hash"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(hashes.len(), 2);
+    assert_ne!(hashes[0], hashes[1]);
+
+    // Without the flag the loop keeps the prompt it started with.
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .env("FAKE_AGENT_APPEND_TO", &prompt)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\\ncall 3").not())
+        .stderr(predicate::str::contains("System prompt changed").not());
+}
+
+#[test]
+fn plan_first_runs_a_planning_iteration_before_the_work() {
+    let env = Env::new();
+    fs::write(env.cwd.path().join("plan.md"), "Only plan.\n").unwrap();
+    env.ralph()
+        .args(["loop", "--iterations", "3", "--plan-first"])
+        .args(["--plan-prompt", "plan.md", "--carry-plan"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Only plan."))
+        .stdout(predicate::str::contains(
+            "## Plan from the planning iteration\\n\\nfake-agent call 1",
+        ))
+        .stderr(predicate::str::contains("Iteration 1 / 3 (planning)"))
+        .stderr(predicate::str::contains(
+            "All tasks complete after 2 iterations (1 planning).",
+        ));
+    assert!(newest_transcript(&env).contains(r#""planning":true"#));
+
+    env.ralph()
+        .args(["loop", "--iterations", "3", "--plan-only"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Planning iteration finished; stopping before any work",
+        ));
+    assert_eq!(env.calls(), 3);
+
+    env.ralph()
+        .args(["loop", "--plan-only", "--carry-plan"])
+        .assert()
+        .failure();
+    env.ralph()
+        .args(["loop", "--plan-prompt", "plan.md"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn init_templates_seed_the_prompt_and_gates_loops_use() {
+    let env = Env::new();
+    env.ralph()
+        .args(["init", "--list-templates"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rust "))
+        .stdout(predicate::str::contains("node "));
+    env.ralph()
+        .args(["init", "--template", "python"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Available templates: minimal, node, rust",
+        ));
+    env.ralph()
+        .args(["init", "--template", "rust"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created .ralph/config.toml"))
+        .stdout(predicate::str::contains("Created tasks/README.md"));
+    assert!(!env.home.path().join("config.toml").exists());
+    env.ralph()
+        .args(["loop", "--print-prompt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Run quality gates (`cargo build && cargo test`)",
+        ));
+    env.ralph()
+        .args(["status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Gates:              cargo build; cargo test (project config)",
+        ));
+
+    let config = env.cwd.path().join(".ralph/config.toml");
+    fs::write(&config, "gates = [\"false\"]\n").unwrap();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_COMPLETE_ON", "1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Gate failed (exit 1): false"))
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 2 iterations",
+        ));
+    assert_eq!(env.calls(), 2);
+    env.ralph()
+        .args(["loop", "--iterations", "2", "--gate", "true"])
+        .env("FAKE_AGENT_COMPLETE_ON", "3")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Gate passed: true"))
+        .stderr(predicate::str::contains(
+            "All tasks complete after 1 iteration",
+        ));
+}
+
+#[test]
+fn report_files_grow_a_section_per_iteration() {
+    let env = Env::new();
+    let report = env.home.path().join("progress.md");
+    env.ralph()
+        .args(["loop", "--iterations", "3", "--report-file"])
+        .arg(&report)
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .assert()
+        .success();
+    let content = fs::read_to_string(&report).unwrap();
+    assert!(
+        content.starts_with("# Ralph loop progress\n\n- **Status:** finished\n"),
+        "{content}"
+    );
+    assert!(
+        content.contains("- **Iterations:** 2 done, 0 remaining (of 3)\n"),
+        "{content}"
+    );
+    assert!(content.contains("- **Session:** "), "{content}");
+    assert!(
+        content.contains("## Iteration 1\n\n- **Started:** "),
+        "{content}"
+    );
+    assert!(
+        content.contains("- **Exit code:** 0\n- Reported all tasks complete\n"),
+        "{content}"
+    );
+    assert!(!content.contains("## Iteration 3"), "{content}");
+    assert!(
+        content.contains("\n## Summary\n\nAll tasks complete after 2 iterations.\n"),
+        "{content}"
+    );
+}
+
+#[test]
+fn metrics_files_are_written_for_the_textfile_collector() {
+    let env = Env::new();
+    fs::create_dir(env.cwd.path().join(".ralph")).unwrap();
+    fs::write(
+        env.cwd.path().join(".ralph/config.toml"),
+        "metrics_file = \"ralph.prom\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["loop", "--iterations", "3"])
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .env("FAKE_AGENT_EXIT_ON", "1")
+        .env("FAKE_AGENT_EXIT", "1")
+        .assert()
+        .success();
+    let content = fs::read_to_string(env.cwd.path().join("ralph.prom")).unwrap();
+    let repo = env.cwd.path().file_name().unwrap().to_string_lossy();
+    let labels = format!("{{repo=\"{repo}\",provider=\"droid\",session_id=\"");
+    assert!(
+        content.contains(&format!("\nralph_iterations_total{labels}")),
+        "{content}"
+    );
+    for sample in [
+        "ralph_iterations_total",
+        "ralph_iterations_failed",
+        "ralph_completed",
+    ] {
+        let line = content.lines().find(|l| l.starts_with(sample)).unwrap();
+        let value = line.rsplit(' ').next().unwrap();
+        assert_eq!(
+            value,
+            match sample {
+                "ralph_iterations_total" => "2",
+                _ => "1",
+            },
+            "{line}"
+        );
+    }
+    assert!(content.contains(",direction=\"input\"} "), "{content}");
+
+    // The flag wins over the config, and --metrics-live writes as it goes.
+    let live = env.home.path().join("live.prom");
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "1",
+            "--metrics-live",
+            "--metrics-file",
+        ])
+        .arg(&live)
+        .assert()
+        .success();
+    assert!(
+        fs::read_to_string(&live)
+            .unwrap()
+            .contains("ralph_completed{")
+    );
+}
+
+#[test]
+fn loop_state_lets_a_fresh_checkout_resume() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .assert()
+        .success();
+    let path = env.cwd.path().join(".ralph/state.json");
+    let content = fs::read_to_string(&path).unwrap();
+    for dir in [env.cwd.path(), env.home.path()] {
+        assert!(!content.contains(&*dir.to_string_lossy()), "{content}");
+    }
+    let ignored = fs::read_to_string(env.cwd.path().join(".ralph/.gitignore")).unwrap();
+    assert!(ignored.lines().any(|l| l == "state.json"), "{ignored}");
+    let mut state: serde_json::Value = serde_json::from_str(&content).unwrap();
+    assert_eq!(state["finished_iterations"], 2);
+    assert_eq!(state["completed"], false);
+
+    // As pulled onto a machine with no transcript of the session.
+    state["session_id"] = "elsewhere".into();
+    state["max_iterations"] = 4.into();
+    state["finished_iterations"] = 1.into();
+    fs::write(&path, state.to_string()).unwrap();
+    env.ralph()
+        .args(["resume", "--yes"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Resuming session elsewhere"))
+        .stderr(predicate::str::contains("1 of 4 done, 3 remaining"));
+    assert_eq!(env.calls(), 5);
+    let state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(state["finished_iterations"], 4);
+    assert_ne!(state["session_id"], "elsewhere");
+
+    fs::write(&path, r#"{"format": 99, "ralph_version": "9.9.9"}"#).unwrap();
+    env.ralph()
+        .args(["resume", "--yes"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "was written by ralph 9.9.9 (state format 99)",
+        ))
+        .stderr(predicate::str::contains(
+            "upgrade ralph to resume this loop",
+        ));
+}
+
+#[test]
+fn prompts_without_the_marker_get_instructions_for_it() {
+    let env = Env::new();
+    fs::write(
+        env.home.path().join("system-prompt.md"),
+        "Work on the backlog.\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["loop", "--print-prompt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Work on the backlog.\n\n## Finishing\n",
+        ))
+        .stdout(predicate::str::contains(
+            "print <promise>COMPLETE</promise> on a line",
+        ));
+    assert_eq!(env.calls(), 0);
+    env.ralph()
+        .args(["loop", "--print-prompt", "--no-inject-marker-instructions"])
+        .assert()
+        .success()
+        .stdout("Work on the backlog.\n");
+
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "never mentions the completion marker <promise>COMPLETE</promise>; appending instructions",
+        ))
+        .stderr(predicate::str::contains("All tasks complete after 1 iteration"));
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "2",
+            "--inject-marker-instructions",
+            "--no-inject-marker-instructions",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("will likely run all 2 iterations"));
+    assert_eq!(env.calls(), 3);
+}
+
+#[test]
+fn workspace_loops_run_in_each_repo_in_turn() {
+    let env = Env::new();
+    let root = env.cwd.path();
+    for repo in ["billing", "search", "broken"] {
+        fs::create_dir(root.join(repo)).unwrap();
+    }
+    fs::write(root.join("billing.md"), "Work on billing.\n").unwrap();
+    fs::write(root.join("search.md"), "Work on search.\n").unwrap();
+    fs::write(
+        root.join("workspace.toml"),
+        "[[repo]]\npath = \"billing\"\nprompt = \"billing.md\"\n\n\
+         [[repo]]\npath = \"gone\"\n\n\
+         [[repo]]\npath = \"search\"\niterations = 1\nprompt = \"search.md\"\n\n\
+         [[repo]]\npath = \"broken\"\nprovider = \"nope\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "3",
+            "--workspace",
+            "workspace.toml",
+            "--no-inject-marker-instructions",
+        ])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("Work on billing."))
+        .stdout(predicate::str::contains("Work on search."))
+        .stdout(predicate::str::contains(r#""status": "missing""#))
+        .stdout(predicate::str::contains(r#""completed": 1,"#))
+        .stdout(predicate::str::contains(r#""incomplete": 1,"#))
+        .stdout(predicate::str::contains(r#""failed": 1,"#))
+        .stderr(predicate::str::contains("Repository 4 / 4"))
+        .stderr(predicate::str::contains("Skipping"))
+        .stderr(predicate::str::is_match(r"\nbilling +completed +2 ").unwrap())
+        .stderr(predicate::str::contains(
+            "gone     missing         0  - (no such directory)",
+        ))
+        .stderr(predicate::str::is_match(r"\nsearch +incomplete +1 ").unwrap())
+        .stderr(predicate::str::contains(
+            "broken   failed          0  - (Invalid provider 'nope'",
+        ));
+    assert_eq!(env.calls(), 3);
+    assert!(root.join("search").join(".ralph").is_dir());
+
+    fs::write(
+        root.join("stop.toml"),
+        "[[repo]]\npath = \"broken\"\nprovider = \"nope\"\n\n[[repo]]\npath = \"billing\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["loop", "--workspace", "stop.toml", "--stop-on-repo-failure"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(r#""skipped": 1,"#));
+    assert_eq!(env.calls(), 3);
+}
+
+#[test]
+fn validate_stream_summarizes_unrecognized_events() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--validate-stream"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Stream check: 1 events, all in the expected format",
+        ));
+
+    let replay = env.cwd.path().join("replay.jsonl");
+    fs::write(
+        &replay,
+        "{\"type\":\"message\",\"role\":\"assistant\"}\n{\"type\":\"delta\"}\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["loop", "--iterations", "2", "--validate-stream"])
+        .env("FAKE_AGENT_REPLAY", &replay)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Stream check: 6 events, 2 unrecognized, 2 missing fields, 0 lines not JSON",
+        ))
+        .stderr(predicate::str::contains("2 x unknown event type delta"))
+        .stderr(predicate::str::contains("2 x missing message: text"));
+}
+
+#[test]
+fn repeated_lines_collapse_but_a_marker_among_them_still_counts() {
+    let env = Env::new();
+    let replay = env.home.path().join("replay.jsonl");
+    let line =
+        r#"{"type":"message","role":"assistant","text":"retrying <promise>COMPLETE</promise>"}"#;
+    fs::write(&replay, format!("{line}\n").repeat(412)).unwrap();
+    let output = env
+        .ralph()
+        .args(["loop", "--iterations", "3"])
+        .env("FAKE_AGENT_REPLAY", &replay)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches(line).count(), 1, "{stdout}");
+    assert!(stdout.contains("[ralph: repeated 412\u{d7}]"), "{stdout}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("All tasks complete after 1 iteration"),
+        "{output:?}"
+    );
+    assert_eq!(env.calls(), 1);
+
+    let output = env
+        .ralph()
+        .args(["once", "--no-collapse-repeats"])
+        .env("FAKE_AGENT_REPLAY", &replay)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches(line).count(), 412);
+}
+
+#[test]
+fn stats_break_runs_down_by_prompt_variant() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--prompt-variant", "terse"])
+        .assert()
+        .success();
+    env.ralph().args(["once"]).assert().success();
+    let labelled = fs::read_dir(env.home.path().join("sessions"))
+        .unwrap()
+        .map(|e| fs::read_to_string(e.unwrap().path()).unwrap_or_default())
+        .filter(|t| t.contains("\"prompt_variant\":\"terse\""))
+        .count();
+    assert_eq!(labelled, 1);
+
+    let output = env
+        .ralph()
+        .args(["stats", "--by-variant", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["by_variant"]["terse"]["runs"], 1);
+    assert_eq!(report["by_variant"]["(none)"]["runs"], 1);
+    assert_eq!(
+        report["variant_prompts"]["terse"],
+        report["variant_prompts"]["(none)"]
+    );
+    env.ralph()
+        .args(["stats", "--by-variant"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\nVARIANT "))
+        .stdout(predicate::str::contains("\nterse "));
+
+    env.ralph()
+        .args(["once", "--prompt-variant", ""])
+        .assert()
+        .failure();
+}
+
+// The gate reads RALPH_LABEL the POSIX shell way.
+#[cfg(unix)]
+#[test]
+fn labels_reach_history_stats_and_the_provider() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--label", "nightly"])
+        .env("FAKE_AGENT_ECHO_ENV", "RALPH_LABEL")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RALPH_LABEL=nightly"));
+    env.ralph()
+        .args(["loop", "--iterations", "1", "--label", "nightly", "--gate"])
+        .arg("test \"$RALPH_LABEL\" = nightly")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Gate passed"));
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_ECHO_ENV", "RALPH_LABEL")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RALPH_LABEL is unset"));
+
+    let output = env.ralph().arg("history").output().unwrap();
+    let listing = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(listing.lines().count(), 4, "{listing}");
+    assert!(listing.lines().next().unwrap().ends_with("  LABEL"));
+    let output = env
+        .ralph()
+        .args(["history", "--label", "nightly"])
+        .output()
+        .unwrap();
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = listing.lines().skip(1).collect();
+    assert_eq!(lines.len(), 2, "{listing}");
+    assert!(lines.iter().all(|l| l.ends_with("  nightly")), "{listing}");
+    env.ralph()
+        .args(["history", "--label", "weekly"])
+        .assert()
+        .success()
+        .stdout("No sessions match.\n");
+
+    let output = env
+        .ralph()
+        .args(["stats", "--by-label", "--format", "json"])
+        .output()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["by_label"]["nightly"]["runs"], 2);
+    assert_eq!(report["by_label"]["(none)"]["runs"], 1);
+}
+
+#[test]
+fn provider_args_reach_the_provider_before_the_prompt() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--provider-arg=--stderr", "--provider-arg", "a b=c"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("a b=c"));
+    // The prompt still comes last, where the provider expects it.
+    fs::write(env.home.path().join("system-prompt.md"), "THE PROMPT").unwrap();
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "1",
+            "--provider-arg",
+            "--echo-prompt=true",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("THE PROMPT"));
+
+    // `[provider_args]` adds the provider's own arguments to every run.
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[provider_args]\ndroid = [\"--stderr\", \"from config\"]\ncodex = [\"--hang\", \"60\"]\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["once", "--provider-arg=--echo-prompt=true"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("THE PROMPT"))
+        .stderr(predicate::str::contains("from config"));
+}
+
+#[test]
+fn permission_checks_stay_on_unless_asked_to_turn_them_off() {
+    let env = Env::new();
+    env.ralph()
+        .arg("once")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Permission checks").not());
+    env.ralph()
+        .args(["once", "--unsafe"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Permission checks: off (--unsafe)",
+        ));
+    env.ralph()
+        .args(["loop", "--print-config"])
+        .env("RALPH_AUTO_APPROVE", "true")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "auto_approve = true      # environment",
+        ));
+}
+
+#[test]
+fn model_reaches_the_provider_and_its_banner() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--model", "big-1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("model big-1"))
+        .stderr(predicate::str::contains(
+            "Using AI provider: droid with model big-1 (via RALPH_PROVIDER_CMD=",
+        ));
+    env.ralph()
+        .args(["loop", "--iterations", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("model ").not());
+}
+
+#[test]
+fn stats_price_recorded_tokens_with_the_pricing_table() {
+    let env = Env::new();
+    let replay = env.cwd.path().join("replay.jsonl");
+    fs::write(
+        &replay,
+        "{\"type\":\"system\",\"model\":\"o3\"}\n\
+         {\"type\":\"result\",\"usage\":{\"input_tokens\":1000000,\"output_tokens\":500000}}\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["once"])
+        .env("FAKE_AGENT_REPLAY", &replay)
+        .assert()
+        .success();
+    assert!(env.home.path().join("pricing.toml").is_file());
+    env.ralph()
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Cost:          - (no prices or provider-reported costs)",
+        ))
+        .stdout(predicate::str::contains("Unpriced:      o3"));
+
+    env.ralph()
+        .args(["pricing", "set", "o3", "2", "8"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Set o3 to $2 input / $8 output"));
+    env.ralph()
+        .args(["pricing", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"\no3 +2\.00 +8\.00\n").unwrap());
+    env.ralph()
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cost:          $6.00"))
+        .stdout(predicate::str::contains("Unpriced").not());
+}
+
+#[test]
+fn provider_probes_are_cached_until_refreshed() {
+    let env = Env::new();
+    let probes = || {
+        let log = fs::read_to_string(env.home.path().join("audit.log")).unwrap_or_default();
+        log.lines().filter(|l| l.contains("--version")).count()
+    };
+    env.ralph()
+        .args(["providers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("PROVIDER"))
+        .stdout(predicate::str::contains("claude    fake-agent "));
+    assert_eq!(probes(), 6);
+    assert!(env.home.path().join("cache/providers.json").exists());
+
+    env.ralph().args(["providers"]).assert().success();
+    assert_eq!(probes(), 6);
+
+    env.ralph()
+        .args(["providers", "--refresh"])
+        .assert()
+        .success();
+    assert_eq!(probes(), 12);
+}
+
+#[cfg(unix)]
+#[test]
+fn providers_lists_config_defined_ones_and_prints_json() {
+    use std::os::unix::fs::PermissionsExt;
+    let env = Env::new();
+    let bin = tempfile::tempdir().unwrap();
+    let claude = bin.path().join("claude");
+    fs::write(&claude, "#!/bin/sh\necho '2.1.3 (Claude Code)'\n").unwrap();
+    fs::set_permissions(&claude, fs::Permissions::from_mode(0o755)).unwrap();
+    let scripts = env.cwd.path().join("scripts");
+    fs::create_dir_all(&scripts).unwrap();
+    fs::write(scripts.join("agent.sh"), "#!/bin/sh\n").unwrap();
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[providers.mywrapper]\ncommand = \"./scripts/agent.sh {prompt}\"\n\
+         [provider_versions]\nclaude = \"2.1.x\"\n",
+    )
+    .unwrap();
+    let providers = || {
+        let mut cmd = env.ralph();
+        cmd.env_remove("RALPH_PROVIDER_CMD")
+            .env("PATH", bin.path())
+            .arg("providers");
+        cmd
+    };
+
+    // Missing CLIs are listed as such, not an error.
+    providers()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "\ndroid      -                         not found on PATH\n",
+        ))
+        .stdout(predicate::str::contains(
+            "\nclaude     2.1.3 (Claude Code)  ",
+        ))
+        .stdout(predicate::str::contains(
+            "\nmywrapper  -                         ./scripts/agent.sh\n",
+        ))
+        .stdout(predicate::str::contains("claude pinned to 2.1.*: ok"));
+
+    let out = providers().arg("--json").output().unwrap();
+    assert!(out.status.success());
+    let list: Vec<serde_json::Value> = serde_json::from_slice(&out.stdout).unwrap();
+    let entry = |name: &str| {
+        list.iter()
+            .find(|p| p["provider"] == name)
+            .unwrap_or_else(|| panic!("{name} missing from {list:?}"))
+    };
+    assert_eq!(list.len(), 7);
+    assert_eq!(entry("claude")["available"], true);
+    assert_eq!(entry("claude")["path"], claude.to_str().unwrap());
+    assert_eq!(entry("claude")["version"], "2.1.3 (Claude Code)");
+    assert_eq!(entry("claude")["pinned"], "2.1.*");
+    assert_eq!(entry("claude")["pin_ok"], true);
+    assert_eq!(entry("codex")["available"], false);
+    assert_eq!(entry("codex")["path"], serde_json::Value::Null);
+    assert!(entry("codex").get("pinned").is_none());
+    // A wrapper is only looked for: it would take --version for a prompt.
+    assert_eq!(entry("mywrapper")["configured"], true);
+    assert_eq!(entry("mywrapper")["available"], true);
+    assert_eq!(entry("mywrapper")["version"], serde_json::Value::Null);
+}
+
+#[test]
+fn pinned_provider_versions_are_enforced_before_running() {
+    let env = Env::new();
+    let project = env.cwd.path().join(".ralph");
+    fs::create_dir_all(&project).unwrap();
+    // The fake agent's --version prints its crate version, 0.x.
+    fs::write(
+        project.join("config.toml"),
+        "[provider_versions]\ndroid = \">=99\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .arg("once")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Provider 'droid' is at version 0.",
+        ))
+        .stderr(predicate::str::contains(
+            "but the project config pins it to >=99; install a matching release, \
+             or pass --allow-version-drift",
+        ));
+    assert_eq!(env.calls(), 0);
+    env.ralph()
+        .args(["providers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "droid pinned to >=99: MISMATCH, at version 0.",
+        ));
+
+    env.ralph()
+        .args(["once", "--allow-version-drift"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("pins it to >=99; running anyway"));
+    assert_eq!(env.calls(), 1);
+    assert!(newest_transcript(&env).contains(r#""provider_version":"fake-agent 0."#));
+
+    fs::write(
+        project.join("config.toml"),
+        "[provider_versions]\ndroid = \"0\"\n",
+    )
+    .unwrap();
+    env.ralph().arg("once").assert().success();
+    assert_eq!(env.calls(), 2);
+}
+
+#[test]
+fn provider_runs_are_audited() {
+    let env = Env::new();
+    env.ralph().args(["once"]).assert().success();
+    env.ralph().args(["once"]).assert().success();
+    env.ralph()
+        .args(["audit", "show", "--since", "1d"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(" provider exit 0 "))
+        .stdout(predicate::str::contains("fake-agent"));
+    env.ralph()
+        .args(["audit", "verify"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("2 entries, hash chain intact"));
+
+    let log = env.home.path().join("audit.log");
+    let text = fs::read_to_string(&log).unwrap();
+    fs::write(&log, text.replacen("exit_code\":0", "exit_code\":1", 1)).unwrap();
+    env.ralph()
+        .args(["audit", "verify"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 2 doesn't follow line 1"));
+
+    fs::remove_file(&log).unwrap();
+    env.ralph()
+        .args(["once"])
+        .env("RALPH_AUDIT", "false")
+        .assert()
+        .success();
+    assert!(!log.exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn providers_can_run_at_a_lower_priority() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--nice", "5", "--ionice", "idle", "--print-config"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "nice = 5                 # command line",
+        ))
+        .stdout(predicate::str::contains(
+            "ionice = \"idle\"          # command line",
+        ));
+
+    env.ralph()
+        .args(["once", "--nice", "5", "--ionice", "idle"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Provider priority: nice 5, ionice idle",
+        ));
+    assert_eq!(env.calls(), 1);
+
+    env.ralph()
+        .args(["once", "--nice", "20"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "nice must be a level from 0 to 19",
+        ));
+}
+
+/// Run `git` in `dir`, failing the test if it fails.
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .args([
+            "-c",
+            "user.name=ralph",
+            "-c",
+            "user.email=ralph@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?}: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn sandboxed_loops_leave_the_checkout_alone() {
+    let env = Env::new();
+    let repo = env.cwd.path();
+    git(repo, &["init", "--quiet"]);
+    fs::write(repo.join("notes.txt"), "committed\n").unwrap();
+    git(repo, &["add", "notes.txt"]);
+    git(repo, &["commit", "--quiet", "-m", "Add notes"]);
+    let worktrees = repo.join(".ralph").join("worktrees");
+
+    // The agent edits notes.txt in whatever directory it's started in.
+    env.ralph()
+        .args(["loop", "--iterations", "2", "--sandbox"])
+        .env("FAKE_AGENT_APPEND_TO", "notes.txt")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Running in sandbox "))
+        .stderr(predicate::str::contains("notes.txt | 2 ++"))
+        .stderr(predicate::str::contains("Removing the sandbox"));
+    assert_eq!(
+        fs::read_to_string(repo.join("notes.txt")).unwrap(),
+        "committed\n"
+    );
+    assert_eq!(fs::read_dir(&worktrees).unwrap().count(), 0);
+    assert_eq!(git(repo, &["diff", "--stat"]), "");
+
+    // Uncommitted changes come along, and a kept sandbox stays behind.
+    fs::write(repo.join("notes.txt"), "committed\nuncommitted\n").unwrap();
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "1",
+            "--sandbox",
+            "--sandbox-keep",
+            "--sandbox-carry-changes",
+        ])
+        .env("FAKE_AGENT_APPEND_TO", "notes.txt")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Kept the sandbox"));
+    let kept: Vec<PathBuf> = fs::read_dir(&worktrees)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(
+        fs::read_to_string(kept[0].join("notes.txt")).unwrap(),
+        "committed\nuncommitted\ncall 3\n"
+    );
+    assert_eq!(
+        fs::read_to_string(repo.join("notes.txt")).unwrap(),
+        "committed\nuncommitted\n"
+    );
+}
+
+#[test]
+fn provider_stderr_is_kept_for_logs() {
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2"])
+        .env("FAKE_AGENT_STDERR", "agent warning")
+        .assert()
+        .success();
+    env.ralph()
+        .args(["logs", "--stderr", "--iteration", "2"])
+        .assert()
+        .success()
+        .stdout("agent warning\n")
+        .stderr(predicate::str::contains("iteration 2 stderr"));
+    env.ralph()
+        .args(["logs", "--iteration", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fake-agent call"))
+        .stdout(predicate::str::contains("agent warning").not());
+
+    let env = Env::new();
+    env.ralph().arg("once").assert().success();
+    env.ralph()
+        .args(["logs", "--stderr"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no captured stderr"));
+}
+
+#[test]
+fn an_unusable_config_directory_stops_runs_but_not_others() {
+    let env = Env::new();
+    let blocker = env.home.path().join("file");
+    fs::write(&blocker, "").unwrap();
+    let home = blocker.join("ralph");
+    env.ralph()
+        .env("RALPH_HOME", &home)
+        .arg("once")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(format!(
+            "Error: Failed to initialize configuration at {}: ",
+            home.display()
+        )))
+        .stderr(predicate::str::contains("set RALPH_HOME"));
+    assert_eq!(env.calls(), 0);
+
+    env.ralph()
+        .env("RALPH_HOME", &home)
+        .args(["prompt", "lint"])
+        .assert()
+        .stderr(predicate::str::contains(
+            "Warning: Failed to initialize configuration",
+        ));
+
+    // version needs no directory at all, so it neither creates one nor warns.
+    let missing = env.home.path().join("missing");
+    env.ralph()
+        .env("RALPH_HOME", &missing)
+        .arg("version")
+        .assert()
+        .success()
+        .stderr("");
+    assert!(!missing.exists());
+}
+
+#[test]
+fn once_passes_through_the_provider_exit_code() {
+    let env = Env::new();
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_EXIT", "3")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "Error: Provider 'droid' exited with code 3",
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn signal_deaths_are_reported_as_such() {
+    let env = Env::new();
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_SIGNAL", "9")
+        .assert()
+        .code(137)
+        .stderr(predicate::str::contains(
+            "Error: Provider 'droid' terminated by SIGKILL (possibly out of memory)",
+        ));
+
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "3", "--message-format", "json"])
+        .env("FAKE_AGENT_SIGNAL", "9")
+        .env("FAKE_AGENT_EXIT_ON", "2")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Iteration 2: provider terminated by SIGKILL (possibly out of memory)",
+        ))
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 3 iterations (1 killed by a signal)",
+        ));
+    let transcript = newest_transcript(&env);
+    assert!(
+        transcript.contains(r#""iteration":2,"exit_code":1,"signal":9"#),
+        "{transcript}"
+    );
+    assert!(!transcript.contains(r#""iteration":1,"exit_code":0,"signal""#));
+}
+
+#[cfg(unix)]
+#[test]
+fn providers_printing_past_the_output_limit_are_killed() {
+    let env = Env::new();
+    let started = Instant::now();
+    env.ralph()
+        .args(["once", "--max-output-bytes", "1K"])
+        .env("FAKE_AGENT_EVENTS", "100")
+        .env("FAKE_AGENT_HANG", "30")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "Error: Provider 'droid' printed more than the 1024-byte output limit and was killed",
+        ));
+    assert!(started.elapsed() < Duration::from_secs(20));
+
+    let env = Env::new();
+    env.ralph()
+        .args(["loop", "--iterations", "2", "--message-format", "json"])
+        .env("RALPH_MAX_OUTPUT_BYTES", "1K")
+        .env("FAKE_AGENT_EVENTS", "100")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Iteration 1: provider printed more than the 1024-byte output limit and was killed",
+        ))
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 2 iterations (2 over the output limit)",
+        ));
+    let transcript = newest_transcript(&env);
+    assert_eq!(
+        transcript.matches(r#""output_limit":1024"#).count(),
+        2,
+        "{transcript}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn providers_running_past_the_timeout_are_killed() {
+    use std::os::unix::fs::PermissionsExt;
+    let env = Env::new();
+    let agent = env.home.path().join("agent");
+    fs::write(&agent, "#!/bin/sh\necho started\nsleep 30\necho finished\n").unwrap();
+    fs::set_permissions(&agent, fs::Permissions::from_mode(0o755)).unwrap();
+    let started = Instant::now();
+    env.ralph()
+        .env("RALPH_PROVIDER_CMD", &agent)
+        .args(["once", "--timeout", "1"])
+        .assert()
+        .code(124)
+        .stdout(predicate::str::contains("started"))
+        .stdout(predicate::str::contains("finished").not())
+        .stderr(predicate::str::contains(
+            "Error: Provider 'droid' ran for longer than the 1s timeout and was killed",
+        ));
+    assert!(started.elapsed() < Duration::from_secs(20));
+
+    // A loop goes on to the next iteration...
+    env.ralph()
+        .env("RALPH_PROVIDER_CMD", &agent)
+        .args(["loop", "--iterations", "2", "--timeout", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Iteration 1: provider ran for longer than the 1s timeout and was killed",
+        ))
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 2 iterations (2 timed out)",
+        ));
+    let transcript = newest_transcript(&env);
+    assert_eq!(
+        transcript.matches(r#""timeout":1"#).count(),
+        2,
+        "{transcript}"
+    );
+
+    // ...unless it's to stop at the first failure.
+    env.ralph()
+        .env("RALPH_PROVIDER_CMD", &agent)
+        .args([
+            "loop",
+            "--iterations",
+            "3",
+            "--timeout",
+            "1",
+            "--stop-on-failure",
+        ])
+        .assert()
+        .code(124)
+        .stderr(predicate::str::contains(
+            "Ralph loop stopped after 1 iterations (1 timed out): the last one failed (--stop-on-failure)",
+        ))
+        .stderr(predicate::str::contains("Iteration 2").not());
+    assert!(started.elapsed() < Duration::from_secs(40));
+
+    env.ralph()
+        .args(["once", "--timeout", "0"])
+        .assert()
+        .code(2);
+}
+
+#[cfg(unix)]
+#[test]
+fn upgrade_repair_restores_the_backup_of_an_interrupted_upgrade() {
+    let env = Env::new();
+    let bin = env.cwd.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let (installed, backup) = (bin.join("ralph"), bin.join("ralph.old"));
+    // The upgrade moved the binary aside and died copying the new one in.
+    fs::copy(assert_cmd::cargo::cargo_bin("ralph"), &backup).unwrap();
+    fs::write(&installed, "\x7fELF\x02\x01").unwrap();
+    fs::write(bin.join("ralph.upgrading"), "v0.2.6 -> v0.2.7\n").unwrap();
+
+    // Run from the backup, as the installed binary doesn't start.
+    Command::new(&backup)
+        .args(["upgrade", "--repair"])
+        .env("RALPH_HOME", env.home.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "Restored {} from {}",
+            installed.display(),
+            backup.display()
+        )));
+    assert!(!bin.join("ralph.upgrading").exists());
+    Command::new(&installed)
+        .args(["version", "--json"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn replayed_usage_reaches_history() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--provider", "claude"])
+        .env("FAKE_AGENT_REPLAY", fixture("claude-result.jsonl"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Working on the task"));
+    env.ralph()
+        .args(["history", "export", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""provider": "claude""#))
+        .stdout(predicate::str::contains(r#""input_tokens": 1200"#))
+        .stdout(predicate::str::contains(r#""cost_usd": 0.25"#));
+}
+
+#[test]
+fn loop_without_a_tracker_never_mentions_bd() {
+    let env = Env::new();
+    fs::write(env.home.path().join("config.toml"), "tracker = \"none\"\n").unwrap();
+    let output = env
+        .ralph()
+        .env_remove("RALPH_TRACKER")
+        .args(["loop", "--iterations", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("bd"), "{stderr}");
+    assert!(stderr.contains("Ralph loop finished after 2 iterations"));
+
+    let prompt = fs::read_to_string(env.home.path().join("system-prompt.md")).unwrap();
+    assert!(!prompt.contains("bd"), "{prompt}");
+
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .args(["run", "bd-1"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("No task tracker configured"));
+}
+
+#[test]
+fn prompt_reset_writes_a_tracker_template() {
+    let env = Env::new();
+    let prompt_path = env.home.path().join("system-prompt.md");
+    let prompt = || fs::read_to_string(&prompt_path).unwrap();
+
+    env.ralph()
+        .args(["prompt", "reset", "--tracker", "github"])
+        .assert()
+        .success();
+    assert!(prompt().contains("gh issue list"), "{}", prompt());
+    // Without --tracker, the configured tracker's (RALPH_TRACKER=none).
+    env.ralph().args(["prompt", "reset"]).assert().success();
+    assert!(prompt().contains("tasks/progress.md"), "{}", prompt());
+
+    fs::write(&prompt_path, "mine").unwrap();
+    env.ralph()
+        .args(["prompt", "reset", "--tracker", "beads"])
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Aborted."));
+    assert_eq!(prompt(), "mine");
+    env.ralph()
+        .args(["prompt", "reset", "--tracker", "beads", "--yes"])
+        .assert()
+        .success();
+    assert!(prompt().contains("bd ready"));
+    env.ralph()
+        .args(["prompt", "lint"])
+        .assert()
+        .success()
+        .stdout("No problems found.\n");
+}
+
+#[test]
+fn prompts_answer_no_without_a_terminal() {
+    let env = Env::new();
+    let prompt_path = env.home.path().join("system-prompt.md");
+    fs::write(&prompt_path, "mine").unwrap();
+    // The test's stdin and stderr are pipes, so the "y" is never read.
+    env.ralph()
+        .args(["prompt", "reset", "--tracker", "beads"])
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("[y/N] no (not a terminal"))
+        .stderr(predicate::str::contains("Aborted."));
+    assert_eq!(fs::read_to_string(&prompt_path).unwrap(), "mine");
+
+    env.ralph()
+        .env("RALPH_INTERACTIVE", "1")
+        .args(["prompt", "reset", "--tracker", "beads"])
+        .write_stdin("y\n")
+        .assert()
+        .success();
+    assert!(
+        fs::read_to_string(&prompt_path)
+            .unwrap()
+            .contains("bd ready")
+    );
+
+    env.ralph()
+        .args(["setup", "--provider", "codex", "--no-bd"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Not running at a terminal"));
+}
+
+#[test]
+fn prompt_test_checks_the_reply_and_exits_1_on_failure() {
+    let env = Env::new();
+    // Echoing the prompt repeats the reply it asks for.
+    env.ralph()
+        .args(["prompt", "test"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .assert()
+        .success()
+        // Only the verdict: the provider's own output isn't echoed.
+        .stdout(predicate::str::starts_with("Provider:    droid\n"))
+        .stdout(predicate::str::contains(
+            "Stream-json: 2 events, all in the",
+        ))
+        .stdout(predicate::str::ends_with("Result:      OK\n"));
+    let output = env
+        .ralph()
+        .args(["prompt", "test", "--format", "json"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let test: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(test["passed"], true);
+    assert!(test["expected"].as_str().unwrap().starts_with("RALPH-OK "));
+    assert_eq!(env.calls(), 2);
+
+    env.ralph()
+        .args(["prompt", "test"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("The reply didn't contain"));
+    env.ralph()
+        .args(["prompt", "test", "--format", "json"])
+        .env("FAKE_AGENT_EXIT", "2")
+        .env("FAKE_AGENT_STDERR", "not logged in")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "Provider 'droid' exited with code 2: not logged in",
+        ));
+}
+
+#[test]
+fn missing_bd_stops_the_run_before_the_provider() {
+    let env = Env::new();
+    let empty_path = tempfile::tempdir().unwrap();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", empty_path.path())
+        .args(["loop", "--iterations", "2"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: bd (beads) is not installed or not on PATH",
+        ))
+        .stderr(predicate::str::contains("tracker = \"none\""));
+    assert_eq!(env.calls(), 0);
+
+    let output = env
+        .ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", empty_path.path())
+        .args(["loop", "--iterations", "2", "--ignore-missing-bd"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(env.calls(), 2);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let warnings: Vec<&str> = stderr.lines().filter(|l| l.contains("bd")).collect();
+    assert_eq!(
+        warnings,
+        [
+            "Warning: bd (beads) is not installed or not on PATH; continuing without the task tracker"
+        ],
+        "{stderr}"
+    );
+}
+
+#[test]
+fn missing_provider_is_reported_before_the_first_iteration() {
+    let env = Env::new();
+    let empty_path = tempfile::tempdir().unwrap();
+    let output = env
+        .ralph()
+        .env_remove("RALPH_PROVIDER_CMD")
+        .env("PATH", empty_path.path())
+        .args(["loop", "--provider", "claude", "--iterations", "2"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "Error: Provider 'claude' not found: `claude` is not on PATH; \
+             install it with `npm install -g @anthropic-ai/claude-code`"
+        ),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("Iteration"), "{stderr}");
+
+    env.ralph()
+        .env("RALPH_PROVIDER_CMD", "no-such-agent --fast")
+        .arg("once")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: Provider 'droid' not found: `no-such-agent` is not on PATH; check RALPH_PROVIDER_CMD",
+        ));
+}
+
+#[test]
+fn print_config_shows_each_layer_without_running() {
+    let env = Env::new();
+    fs::write(env.home.path().join("config.toml"), "iterations = 7\n").unwrap();
+    fs::create_dir(env.cwd.path().join(".ralph")).unwrap();
+    fs::write(
+        env.cwd.path().join(".ralph/config.toml"),
+        "[clean]\nolder_than = \"2d\"\n",
+    )
+    .unwrap();
+    env.ralph()
+        .args(["loop", "--print-config"])
+        .env("RALPH_PROVIDER", "claude")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "provider = \"claude\"      # environment",
+        ))
+        .stdout(predicate::str::contains(
+            "iterations = 7           # global config",
+        ))
+        .stdout(predicate::str::contains(
+            "older_than = \"2d\"        # project config",
+        ));
+    env.ralph()
+        .args([
+            "loop",
+            "--iterations",
+            "2",
+            "--provider",
+            "codex",
+            "--print-config",
+        ])
+        .env("RALPH_PROVIDER", "claude")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "provider = \"codex\"       # command line",
+        ))
+        .stdout(predicate::str::contains(
+            "iterations = 2           # command line",
+        ));
+    assert_eq!(env.calls(), 0);
+
+    // once picks the provider the same way: flag, then environment, then
+    // the built-in default.
+    env.ralph()
+        .args(["once", "--provider", "codex"])
+        .env("RALPH_PROVIDER", "claude")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Using AI provider: codex"));
+    env.ralph()
+        .arg("once")
+        .env("RALPH_PROVIDER", "claude")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Using AI provider: claude"));
+    env.ralph()
+        .arg("once")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Using AI provider: droid"));
+    assert_eq!(env.calls(), 3);
+
+    env.ralph()
+        .arg("once")
+        .env("RALPH_PROVIDER", "nope")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: RALPH_PROVIDER: Invalid provider 'nope'",
+        ));
+    assert_eq!(env.calls(), 3);
+}
+
+#[test]
+fn invalid_provider_is_rejected_even_with_an_override() {
+    let env = Env::new();
+    env.ralph()
+        .args(["once", "--provider", "nope"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Invalid provider 'nope'"));
+    assert_eq!(env.calls(), 0);
+}
+
+/// ralph has no provider timeout of its own: a hung provider keeps it
+/// waiting until something outside kills it.
+#[test]
+fn hung_provider_blocks_until_killed() {
+    let env = Env::new();
+    env.ralph()
+        .arg("once")
+        .env("FAKE_AGENT_HANG", "30")
+        .timeout(Duration::from_secs(2))
+        .assert()
+        .interrupted();
+    assert_eq!(env.calls(), 1);
+}
+
+/// Whether `pid` is running; a zombie left for a non-reaping init to
+/// collect counts as gone.
+#[cfg(unix)]
+fn running(pid: i32) -> bool {
+    let zombie = fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+        stat.rsplit(')')
+            .next()
+            .unwrap_or("")
+            .trim_start()
+            .starts_with('Z')
+    });
+    (unsafe { libc::kill(pid, 0) }) == 0 && !zombie
+}
+
+/// A directory holding a stand-in `bd` running `script` (sh), and a `PATH`
+/// that finds it first.
+#[cfg(unix)]
+fn fake_bd(script: &str) -> (TempDir, String) {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let bd = dir.path().join("bd");
+    fs::write(&bd, format!("#!/bin/sh\n{script}")).unwrap();
+    fs::set_permissions(&bd, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        dir.path().display(),
+        std::env::var("PATH").unwrap()
+    );
+    (dir, path)
+}
+
+#[cfg(unix)]
+#[test]
+fn run_exits_75_when_the_task_stays_open_and_2_on_bad_arguments() {
+    let (_bin, path) = fake_bd(
+        "case \"$1\" in\n\
+         show) echo '[{\"id\":\"bd-1\",\"title\":\"a\",\"status\":\"open\"}]' ;;\n\
+         *) echo '[]' ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    let ralph = || {
+        let mut cmd = env.ralph();
+        cmd.env_remove("RALPH_TRACKER").env("PATH", &path);
+        cmd
+    };
+    ralph()
+        .args(["run", "bd-1"])
+        .assert()
+        .code(75)
+        .stderr(predicate::str::contains("Task bd-1 is still open"));
+    // Usage errors are clap's 2, which a retry loop mustn't mistake for it.
+    ralph().arg("run").assert().code(2);
+    ralph().args(["run", "bd-1", "--bogus"]).assert().code(2);
+}
+
+#[test]
+fn completion_helper_lists_config_defined_providers_and_aliases() {
+    let env = Env::new();
+    let complete = || {
+        let mut cmd = env.ralph();
+        cmd.args(["__complete", "provider"]);
+        cmd
+    };
+    let builtin = "droid\ncodex\nclaude\ngemini\ncopilot\nqwen\n";
+    let builtin_aliases = "factory\ncc\nclaude-code\ngemini-cli\ngh-copilot\nqwen-code\n";
+    complete()
+        .assert()
+        .success()
+        .stdout(format!("{builtin}{builtin_aliases}"));
+
+    fs::write(
+        env.home.path().join("config.toml"),
+        "[providers.mywrapper]\ncommand = \"agent {prompt}\"\n[aliases]\noai = \"codex\"\ncc = \"copilot\"\n",
+    )
+    .unwrap();
+    complete()
+        .assert()
+        .success()
+        .stdout(format!("{builtin}mywrapper\n{builtin_aliases}oai\n"));
+
+    // A broken config still completes the built-in providers.
+    fs::write(env.home.path().join("config.toml"), "provider = [").unwrap();
+    complete()
+        .assert()
+        .success()
+        .stdout(format!("{builtin}{builtin_aliases}"));
+}
+
+#[cfg(unix)]
+#[test]
+fn completion_helper_lists_ready_tasks_and_never_fails() {
+    let (_bin, path) = fake_bd(
+        "case \"$*\" in\n\
+         'ready --json') echo '[{\"id\":\"bd-1\",\"title\":\"a\"},{\"id\":\"bd-7\",\"title\":\"b\"}]' ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .args(["__complete", "task"])
+        .assert()
+        .success()
+        .stdout("bd-1\nbd-7\n");
+    assert!(!env.home.path().join("audit.log").exists());
+
+    let (_bin, path) = fake_bd("exec sleep 30\n");
+    let started = Instant::now();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .args(["__complete", "task"])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn cwd_runs_the_provider_and_bd_in_another_directory() {
+    use std::os::unix::fs::PermissionsExt;
+    // bd only finds a database in the project, where the agent says it is.
+    let (bin, path) = fake_bd(
+        "[ -d .beads ] && echo '[]' && exit 0\n\
+         echo 'Error: no beads database found' >&2; exit 1\n",
+    );
+    let agent = bin.path().join("agent");
+    fs::write(&agent, "#!/bin/sh\necho \"cwd $(pwd -P)\"\n").unwrap();
+    fs::set_permissions(&agent, fs::Permissions::from_mode(0o755)).unwrap();
+    let env = Env::new();
+    let project = env.cwd.path().join("project");
+    fs::create_dir_all(project.join(".beads")).unwrap();
+    let expected = format!("cwd {}\n", project.canonicalize().unwrap().display());
+    for args in [&["once"][..], &["loop", "--iterations", "1"]] {
+        env.ralph()
+            .env_remove("RALPH_TRACKER")
+            .env("PATH", &path)
+            .env("RALPH_PROVIDER_CMD", &agent)
+            .args(args)
+            .args(["--cwd", "project"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(&expected));
+    }
+
+    // The directory is checked before the first iteration.
+    fs::write(env.cwd.path().join("notes.txt"), "").unwrap();
+    env.ralph()
+        .args(["once", "--cwd", "notes.txt"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: --cwd notes.txt: not a directory",
+        ));
+    env.ralph()
+        .args(["loop", "--cwd", "missing"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: --cwd missing: No such file",
+        ));
+    assert_eq!(env.calls(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn missing_beads_database_offers_bd_init() {
+    // No database until `bd init` creates .beads/.
+    let (_bin, path) = fake_bd(
+        "case \"$1\" in\n\
+         init) mkdir .beads; echo initialized ;;\n\
+         *) [ -d .beads ] && echo '[]' && exit 0\n\
+            echo 'Error: no beads database found' >&2; exit 1 ;;\n\
+         esac\n",
+    );
+
+    let env = Env::new();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .arg("once")
+        .write_stdin("n\n")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "No beads database in this directory. Run `bd init` now? [y/N]",
+        ))
+        .stderr(predicate::str::contains("before starting a run"));
+    assert_eq!(env.calls(), 0);
+    assert!(!env.cwd.path().join(".beads").exists());
+
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .env("RALPH_INTERACTIVE", "1")
+        .arg("once")
+        .write_stdin("y\n")
+        .assert()
+        .success();
+    assert_eq!(env.calls(), 1);
+    assert!(env.cwd.path().join(".beads").is_dir());
+
+    let env = Env::new();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .args(["once", "--auto-init-bd"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("running `bd init`"));
+    assert_eq!(env.calls(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn loop_scoped_to_an_epic() {
+    let (_bin, path) = fake_bd(
+        r#"case "$1 $2" in
+"show pay-1") echo '{"id":"pay-1","title":"Payments v2","dependents":[
+  {"id":"pay-2","status":"closed","dependency_type":"parent-child"},
+  {"id":"pay-3","status":"open","dependency_type":"parent-child"}]}' ;;
+show*) echo "Error: issue $2 not found" >&2; exit 1 ;;
+ready*) echo '[{"id":"pay-3","dependencies":[{"id":"pay-1","dependency_type":"parent-child"}]},{"id":"ops-1"}]' ;;
+esac
+"#,
+    );
+
+    let env = Env::new();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .args(["loop", "--epic", "nope"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: Unknown epic 'nope': bd show nope --json failed",
+        ));
+    assert_eq!(env.calls(), 0);
+
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .args(["loop", "--epic", "pay-1", "--iterations", "1"])
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Only work on tasks under the epic pay-1",
+        ))
+        .stderr(predicate::str::contains(
+            "Scoped to epic: pay-1 (Payments v2)",
+        ))
+        .stderr(predicate::str::contains(
+            "Epic pay-1 (Payments v2): 1 of 2 tasks closed, 1 ready",
+        ));
+    assert_eq!(env.calls(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn beads_config_supplies_run_defaults() {
+    let (_bin, path) = fake_bd(
+        "echo \"$@\" >> bd-calls.log\n\
+         case \"$*\" in\n\
+         'config list --json') echo '{\"ralph.provider\":\"claude\",\"ralph.iterations\":3,\"ralph.marker\":\"SHIPPED\"}' ;;\n\
+         *) echo '[]' ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    // Without a database there is nothing to ask bd about.
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Provider:           droid (default)",
+        ));
+    assert!(!env.cwd.path().join("bd-calls.log").exists());
+
+    fs::create_dir(env.cwd.path().join(".beads")).unwrap();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .env("RALPH_ITERATIONS", "2")
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Provider:           claude (beads config)",
+        ))
+        .stdout(predicate::str::contains(
+            "Iterations:         2 (environment)",
+        ))
+        .stdout(predicate::str::contains(
+            "Completion markers: SHIPPED (beads config)",
+        ));
+    let calls = fs::read_to_string(env.cwd.path().join("bd-calls.log")).unwrap();
+    assert_eq!(calls, "config list --json\n");
+
+    // The usual marker no longer ends the loop; the project's does.
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .arg("loop")
+        .env("FAKE_AGENT_COMPLETE_ON", "1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Ralph loop finished after 3 iterations",
+        ));
+    assert_eq!(env.calls(), 3);
+    fs::write(
+        env.home.path().join("system-prompt.md"),
+        "Print SHIPPED once every task is closed.\n",
+    )
+    .unwrap();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .arg("loop")
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "All tasks complete after 1 iteration",
+        ));
+    assert_eq!(env.calls(), 4);
+
+    // A bd without `bd config` leaves the defaults alone.
+    let (_old, path) = fake_bd(
+        "case \"$1\" in\nconfig) echo 'Error: unknown command \"config\"' >&2; exit 1 ;;\n*) echo '[]' ;;\nesac\n",
+    );
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Provider:           droid (default)",
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn no_bd_never_runs_bd() {
+    let (_bin, path) = fake_bd("echo \"$@\" >> bd-calls.log\necho '[]'\n");
+    let env = Env::new();
+    env.ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .env("FAKE_AGENT_ECHO_PROMPT", "1")
+        .args([
+            "loop",
+            "--no-bd",
+            "--iterations",
+            "1",
+            "--bd-sync",
+            "--verbose",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Work on this project one task at a time",
+        ))
+        .stdout(predicate::str::contains("bd ready").not())
+        .stderr(predicate::str::contains("Warning").not())
+        .stderr(predicate::str::contains("Debug").not());
+    assert!(!env.cwd.path().join("bd-calls.log").exists());
+    assert_eq!(env.calls(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn bd_sync_runs_after_the_loop() {
+    let (_bin, path) = fake_bd(
+        "case \"$1\" in\n\
+         ready) echo '[]' ;;\n\
+         sync) echo synced >> bd-sync.log; exit ${BD_SYNC_EXIT:-0} ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    let ralph = |args: &[&str], sync_exit: &str| {
+        let mut cmd = env.ralph();
+        cmd.env_remove("RALPH_TRACKER")
+            .env("PATH", &path)
+            .env("BD_SYNC_EXIT", sync_exit)
+            .args(["loop", "--iterations", "1"])
+            .args(args);
+        cmd
+    };
+
+    ralph(&["--bd-sync"], "0")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Synced the task tracker (`bd sync`)",
+        ));
+    let transcript = newest_transcript(&env);
+    assert!(
+        transcript
+            .ends_with("\"event\":\"tracker_sync\",\"command\":\"bd sync\",\"exit_code\":0}\n"),
+        "{transcript}"
+    );
+
+    ralph(&["--bd-sync"], "4")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Warning: bd sync failed: exited with code 4",
+        ));
+    ralph(&["--bd-sync-required"], "4")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Error: bd sync failed: exited with code 4",
+        ));
+    ralph(&[], "0").assert().success();
+    let syncs = fs::read_to_string(env.cwd.path().join("bd-sync.log")).unwrap();
+    assert_eq!(syncs.lines().count(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn loop_prints_a_backlog_diff() {
+    // The first four `bd list` calls (one per status) are the backlog
+    // before the loop, the rest after it.
+    let (_bin, path) = fake_bd(
+        "case \"$1 $2\" in\n\
+         'ready '*) echo '[]' ;;\n\
+         'list --pretty') echo 'pretty listing' ;;\n\
+         'list --status')\n\
+           n=$(( $(cat bd-lists 2>/dev/null || echo 0) + 1 )); echo $n > bd-lists\n\
+           [ $n -le 4 ] && phase=before || phase=after\n\
+           cat $phase-$3.json 2>/dev/null || echo \"${BD_EMPTY:-[]}\" ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    let issue = |id: &str, title: &str, status: &str| {
+        format!(r#"{{"id":"{id}","title":"{title}","status":"{status}"}}"#)
+    };
+    for (file, issues) in [
+        (
+            "before-open",
+            vec![
+                issue("bd-1", "Fix login", "open"),
+                issue("bd-2", "Add docs", "open"),
+            ],
+        ),
+        ("after-closed", vec![issue("bd-1", "Fix login", "closed")]),
+        (
+            "after-in_progress",
+            vec![issue("bd-2", "Add docs", "in_progress")],
+        ),
+        ("after-open", vec![issue("bd-3", "Follow-up", "open")]),
+    ] {
+        fs::write(
+            env.cwd.path().join(format!("{file}.json")),
+            format!("[{}]", issues.join(",")),
+        )
+        .unwrap();
+    }
+    let ralph = || {
+        let mut cmd = env.ralph();
+        cmd.env_remove("RALPH_TRACKER")
+            .env("PATH", &path)
+            .args(["loop", "--iterations", "1"]);
+        cmd
+    };
+
+    ralph().assert().success().stderr(predicate::str::contains(
+        "Backlog changes:\n  \
+         Closed (1):\n    bd-1  Fix login\n  \
+         Created (1):\n    bd-3  Follow-up\n  \
+         Started but not finished (1):\n    bd-2  Add docs\n",
+    ));
+    let transcript = newest_transcript(&env);
+    assert!(
+        transcript.contains(r#""event":"backlog","diff":{"closed":[{"id":"bd-1""#),
+        "{transcript}"
+    );
+
+    // When bd's output can't be read, the plain listing is shown instead.
+    ralph()
+        .env("BD_EMPTY", "not json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pretty listing"))
+        .stderr(predicate::str::contains("Backlog").not());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_hung_bd_costs_one_warning_instead_of_the_run() {
+    let (_bin, path) = fake_bd(
+        "case \"$1 $2\" in\n\
+         'ready '*) echo '[]' ;;\n\
+         'list --pretty') echo 'pretty listing' ;;\n\
+         'list --status') exec sleep 30 ;;\n\
+         esac\n",
+    );
+    let env = Env::new();
+    let started = std::time::Instant::now();
+    let out = env
+        .ralph()
+        .env_remove("RALPH_TRACKER")
+        .env("PATH", &path)
+        .env("RALPH_TRACKER_TIMEOUT_SECS", "1")
+        .args(["loop", "--iterations", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pretty listing").not())
+        .get_output()
+        .clone();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert_eq!(stderr.matches("Warning: ").count(), 1, "{stderr}");
+    assert!(
+        stderr.contains("Warning: bd list --status open --json timed out after 1s"),
+        "{stderr}"
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[test]
+fn a_held_repository_lock_stops_a_second_run() {
+    let env = Env::new();
+    let lock = env.cwd.path().join(".ralph/lock");
+    let holder =
+        r#"{"pid":1,"host":"elsewhere","started":"2026-01-10T00:00:00Z","command":"loop"}"#;
+    fs::create_dir_all(lock.parent().unwrap()).unwrap();
+    fs::write(&lock, holder).unwrap();
+    env.ralph()
+        .arg("once")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Another ralph is running in this repository (pid 1 on elsewhere, `ralph loop` started",
+        ))
+        .stderr(predicate::str::contains("--force-lock"));
+    assert_eq!(env.calls(), 0);
+
+    env.ralph()
+        .args(["once", "--force-lock"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Taking over the lock"));
+    assert!(!lock.exists());
+
+    // A lock whose heartbeat stopped is left over from a crash.
+    fs::write(&lock, holder).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&lock)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(120))
+        .unwrap();
+    env.ralph()
+        .arg("once")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Removing a stale lock"));
+    assert_eq!(env.calls(), 2);
+}
+
+/// The most recently written session transcript.
+fn newest_transcript(env: &Env) -> String {
+    let mut transcripts: Vec<PathBuf> = fs::read_dir(env.home.path().join("sessions"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "jsonl"))
+        .collect();
+    transcripts.sort();
+    fs::read_to_string(transcripts.last().unwrap()).unwrap()
+}
+
+/// Start a loop whose provider hangs after starting a dev server, and wait
+/// for it to be running. Returns ralph and the dev server's pid.
+#[cfg(unix)]
+fn start_hung_loop(env: &Env, vars: &[(&str, &str)]) -> (std::process::Child, i32) {
+    let pid_file = env.cwd.path().join("dev-server.pid");
+    let _ = fs::remove_file(&pid_file);
+    let ralph = std::process::Command::new(assert_cmd::cargo::cargo_bin("ralph"))
+        .args(["loop", "--iterations", "3"])
+        .current_dir(env.cwd.path())
+        .env("RALPH_HOME", env.home.path())
+        .env("RALPH_PROVIDER_CMD", fake_agent())
+        .env("FAKE_AGENT_STATE", env.state())
+        .env("RALPH_TRACKER", "none")
+        .env("FAKE_AGENT_CHILD", &pid_file)
+        .env("FAKE_AGENT_HANG", "300")
+        .envs(vars.iter().copied())
+        .stdout(std::process::Stdio::null())
+        .stderr(fs::File::create(env.home.path().join("ralph.stderr")).unwrap())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let dev_server = loop {
+        if let Some(pid) = fs::read_to_string(&pid_file)
+            .ok()
+            .and_then(|s| s.trim().parse::<i32>().ok())
+        {
+            break pid;
+        }
+        assert!(Instant::now() < deadline, "fake agent never started");
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(running(dev_server));
+    (ralph, dev_server)
+}
+
+/// Wait up to `timeout` for `child` to exit.
+#[cfg(unix)]
+fn wait_for_exit(child: &mut std::process::Child, timeout: Duration) -> std::process::ExitStatus {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        assert!(Instant::now() < deadline, "ralph is still running");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Start a loop whose provider hangs after starting a dev server, send
+/// ralph `signal` (SIGINT for Ctrl-C) once it's running, and wait for it to
+/// exit. Returns the dev server's pid, ralph's exit code and how long ralph
+/// took to exit.
+#[cfg(unix)]
+fn interrupt_hung_loop(
+    env: &Env,
+    signal: libc::c_int,
+    vars: &[(&str, &str)],
+) -> (i32, Option<i32>, Duration) {
+    let (mut ralph, dev_server) = start_hung_loop(env, vars);
+    // SAFETY: signalling our own child process.
+    unsafe { libc::kill(ralph.id() as i32, signal) };
+    let interrupted = Instant::now();
+    let deadline = interrupted + Duration::from_secs(15);
+    let status = loop {
+        if let Some(status) = ralph.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "ralph ignored the signal");
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    (dev_server, status.code(), interrupted.elapsed())
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_stops_the_provider_and_everything_it_started() {
+    let env = Env::new();
+    let (dev_server, code, _) = interrupt_hung_loop(&env, libc::SIGINT, &[]);
+    assert_eq!(code, Some(130));
+    assert_eq!(env.calls(), 1, "the loop stops instead of moving on");
+    assert!(!running(dev_server), "dev server {dev_server} survived");
+    assert!(!env.cwd.path().join(".ralph/lock").exists());
+    let stderr = fs::read_to_string(env.home.path().join("ralph.stderr")).unwrap();
+    assert!(
+        stderr.contains("Ralph loop interrupted at iteration 1; skipping the remaining iterations"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("terminated by"), "{stderr}");
+    assert!(newest_transcript(&env).contains("\"event\":\"session_end\""));
+
+    // SIGTERM, as from `kill` or a supervisor, is handled the same way.
+    let (dev_server, code, _) = interrupt_hung_loop(&env, libc::SIGTERM, &[]);
+    assert_eq!(code, Some(130));
+    assert_eq!(env.calls(), 2);
+    assert!(!running(dev_server), "dev server {dev_server} survived");
+    assert!(!env.cwd.path().join(".ralph/lock").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn ralph_kill_stops_a_loop_from_another_terminal() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let env = Env::new();
+    let (mut ralph, dev_server) = start_hung_loop(&env, &[]);
+    let pid = ralph.id();
+    env.ralph()
+        .current_dir(env.home.path())
+        .arg("kill")
+        .arg("--repo")
+        .arg(env.cwd.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "Interrupting pid {pid} on "
+        )))
+        .stdout(format!(
+            "Stopped `ralph loop` (pid {pid}); it wrapped up and released the lock\n"
+        ));
+    assert!(
+        wait_for_exit(&mut ralph, Duration::from_secs(10))
+            .code()
+            .is_some()
+    );
+    assert_eq!(env.calls(), 1, "the loop stops instead of moving on");
+    assert!(!running(dev_server), "dev server {dev_server} survived");
+    assert!(newest_transcript(&env).contains("\"event\":\"session_end\""));
+
+    // Still running after the timeout: killed, provider and all.
+    let (mut ralph, dev_server) = start_hung_loop(&env, &[("FAKE_AGENT_IGNORE_TERM", "1")]);
+    let pid = ralph.id();
+    env.ralph()
+        .args(["kill", "--timeout", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "pid {pid} didn't stop within 1s; killing it"
+        )))
+        .stdout(predicate::str::starts_with(format!(
+            "Killed `ralph loop` (pid {pid}) and the provider process group "
+        )));
+    let status = wait_for_exit(&mut ralph, Duration::from_secs(10));
+    assert_eq!(status.signal(), Some(libc::SIGKILL));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while running(dev_server) {
+        assert!(
+            Instant::now() < deadline,
+            "dev server {dev_server} survived"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let lock = env.cwd.path().join(".ralph/lock");
+    assert!(!lock.exists());
+
+    env.ralph()
+        .arg("kill")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("No ralph is running here"));
+
+    // A lock whose heartbeat stopped long ago is only cleaned up.
+    fs::write(
+        &lock,
+        format!(
+            r#"{{"pid":{pid},"host":"devbox","started":"2026-01-10T00:00:00Z","command":"loop"}}"#
+        ),
+    )
+    .unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&lock)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(3600))
+        .unwrap();
+    env.ralph().arg("kill").assert().success().stdout(format!(
+        "No ralph is running: pid {pid} (`ralph loop`) is gone. Removed its stale lock\n"
+    ));
+    assert!(!lock.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_kills_a_provider_that_ignores_sigterm() {
+    let env = Env::new();
+    let (dev_server, _, took) =
+        interrupt_hung_loop(&env, libc::SIGINT, &[("FAKE_AGENT_IGNORE_TERM", "1")]);
+    assert_eq!(env.calls(), 1);
+    assert!(!running(dev_server), "dev server {dev_server} survived");
+    assert!(
+        took >= ralph::stream::TERMINATE_GRACE,
+        "SIGKILL only follows the grace period, took {took:?}"
+    );
+}
+
+/// Terminal output of a loop.
+fn loop_stderr(env: &Env, global_flags: &[&str]) -> String {
+    let output = env
+        .ralph()
+        .args(global_flags)
+        .args(["loop", "--iterations", "3"])
+        .env("FAKE_AGENT_COMPLETE_ON", "2")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn terminal_messages_are_unchanged() {
+    let env = Env::new();
+    let expected = format!(
+        "Created default system prompt: {home}/system-prompt.md\n\
+         Using AI provider: droid (via RALPH_PROVIDER_CMD={agent})\n\
+         Max iterations: 3\n\
+         \n\
+         ==========================================\n\
+         Iteration 1 / 3\n\
+         ==========================================\n\
+         ==========================================\n\
+         Iteration 2 / 3\n\
+         ==========================================\n\
+         \n\
+         All tasks complete after 2 iterations.\n",
+        home = env.home.path().display(),
+        agent = fake_agent().display(),
+    );
+    assert_eq!(loop_stderr(&env, &[]), expected);
+}
+
+#[test]
+fn quiet_and_json_message_formats() {
+    let env = Env::new();
+    assert_eq!(loop_stderr(&env, &["--quiet"]), "");
+
+    let env = Env::new();
+    let json = loop_stderr(&env, &["--message-format", "json"]);
+    let levels: Vec<String> = json
+        .lines()
+        .map(|l| {
+            let value: serde_json::Value = serde_json::from_str(l).unwrap();
+            value["level"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        levels,
+        ["info", "info", "info", "banner", "banner", "summary"],
+        "{json}"
+    );
+}
@@ -0,0 +1,75 @@
+//! Drives the library API directly, without going through the binary.
+
+use ralph::config::{self, Config};
+use ralph::providers;
+
+#[test]
+fn provider_resolution_prefers_flag_then_config_then_default() {
+    let config = Config {
+        provider: Some("claude".to_string()),
+        ..Config::default()
+    };
+    assert_eq!(
+        config::resolve_provider(Some("codex".to_string()), &config).unwrap(),
+        "codex"
+    );
+    assert_eq!(config::resolve_provider(None, &config).unwrap(), "claude");
+    assert_eq!(
+        config::resolve_provider(None, &Config::default()).unwrap(),
+        config::DEFAULT_PROVIDER
+    );
+    assert!(config::resolve_provider(Some("nope".to_string()), &config).is_err());
+}
+
+#[test]
+fn iteration_resolution_validates() {
+    let config = Config {
+        iterations: Some(4),
+        ..Config::default()
+    };
+    assert_eq!(config::resolve_iterations(None, &config).unwrap(), 4);
+    assert_eq!(
+        config::resolve_iterations(Some("2".to_string()), &config).unwrap(),
+        2
+    );
+    assert_eq!(
+        config::resolve_iterations(None, &Config::default()).unwrap(),
+        config::DEFAULT_ITERATIONS
+    );
+    assert!(config::resolve_iterations(Some("0".to_string()), &config).is_err());
+}
+
+#[test]
+fn every_provider_has_a_command_ending_with_the_prompt() {
+    for provider in providers::VALID_PROVIDERS {
+        let cmd = providers::command(provider, "do it").unwrap();
+        assert_eq!(cmd.get_program(), *provider);
+        assert_eq!(cmd.get_args().last().unwrap(), "do it");
+    }
+    assert!(providers::command("nope", "do it").is_err());
+}
+
+#[test]
+fn run_options_default_to_the_configured_budget() {
+    let opts = ralph::RunOptions::new("droid", "prompt");
+    assert_eq!(opts.max_iterations, config::DEFAULT_ITERATIONS);
+    assert!(opts.resumed_from.is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn stream_output_captures_logs_and_passes_exit_code() {
+    use std::process::{Command, Stdio};
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("out.log");
+    let child = Command::new("sh")
+        .args(["-c", "echo one; echo two; exit 3"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let output = ralph::stream::stream_output(child, Some(&log)).unwrap();
+    assert_eq!((output.code, output.signal), (3, None));
+    assert_eq!(output.stdout, "one\ntwo\n");
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), output.stdout);
+}
@@ -0,0 +1,74 @@
+//! Runs `run_once` and `run_loop` against a fake `droid` on `PATH`. Kept in
+//! its own test binary because it changes `RALPH_HOME` and `PATH` for the process.
+
+#![cfg(unix)]
+
+use ralph::session::{self, Event};
+use ralph::{RunOptions, run_loop, run_once};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+/// Prints the COMPLETE marker on its second call, counting calls in a file
+/// next to the script; exits 7 when `FAKE_EXIT` says so.
+const FAKE_DROID: &str = r#"#!/bin/sh
+count_file="$(dirname "$0")/calls"
+n=$(( $(cat "$count_file" 2>/dev/null || echo 0) + 1 ))
+echo "$n" > "$count_file"
+echo "call $n"
+if [ "$n" -ge 2 ]; then echo "<promise>COMPLETE</promise>"; fi
+exit "${FAKE_EXIT:-0}"
+"#;
+
+#[test]
+fn run_once_and_run_loop_record_sessions() {
+    let home = tempfile::tempdir().unwrap();
+    let bin = tempfile::tempdir().unwrap();
+    let droid = bin.path().join("droid");
+    fs::write(&droid, FAKE_DROID).unwrap();
+    fs::set_permissions(&droid, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.path().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    // SAFETY: this is the only test in this binary, so nothing else reads
+    // the environment concurrently.
+    unsafe {
+        std::env::set_var("RALPH_HOME", home.path());
+        std::env::set_var("PATH", path);
+        std::env::set_var("FAKE_EXIT", "7");
+    }
+
+    let once = run_once(&RunOptions::new("droid", "prompt")).unwrap();
+    assert_eq!(once.iterations, 1);
+    assert_eq!(once.exit_code, 7);
+    assert!(!once.completed);
+
+    unsafe { std::env::remove_var("FAKE_EXIT") };
+    let opts = RunOptions {
+        max_iterations: 5,
+        resumed_from: Some("earlier".to_string()),
+        ..RunOptions::new("droid", "prompt")
+    };
+    let summary = run_loop(&opts).unwrap();
+    // The call count carries over from `run_once`, so the loop's first
+    // iteration is the second call and prints the marker.
+    assert_eq!(summary.iterations, 1);
+    assert!(summary.completed);
+    assert_eq!(summary.exit_code, 0);
+
+    let id = summary.session_id.expect("session recorded");
+    let stored = session::find_session(Some(&id)).unwrap();
+    let events: Vec<Event> = session::read_records(&stored.transcript)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.event)
+        .collect();
+    assert!(events.contains(&Event::Resumed {
+        from: "earlier".to_string()
+    }));
+    assert!(events.contains(&Event::SessionEnd {
+        iterations: 1,
+        completed: true
+    }));
+}
@@ -0,0 +1,225 @@
+//! Drives the run loop and `ralph run` through a `ScriptedRunner`, so no
+//! provider or bd binary is needed. Kept in its own test binary because it
+//! changes `RALPH_HOME` for the process.
+
+#![cfg(unix)]
+
+use ralph::exec::{CancelToken, ScriptedRunner};
+use ralph::providers::VALID_PROVIDERS;
+use ralph::run::{self, TaskOutcome};
+use ralph::runner::{self, COMPLETE_MARKER, LoopRunner, RunOptions};
+use ralph::session::Event;
+use ralph::tracker::Beads;
+use std::time::Duration;
+
+#[test]
+fn scripted_runs() {
+    let home = tempfile::tempdir().unwrap();
+    // SAFETY: this is the only test in this binary, so nothing else reads
+    // the environment concurrently.
+    unsafe { std::env::set_var("RALPH_HOME", home.path()) };
+
+    // The loop keeps going after a failed iteration and stops at the marker.
+    let exec = ScriptedRunner::new()
+        .reply(3, "crashed\n")
+        .reply(0, &format!("done {COMPLETE_MARKER}\n"));
+    let opts = RunOptions {
+        max_iterations: 5,
+        ..RunOptions::new("claude", "prompt")
+    };
+    let summary = runner::run_loop_with(&exec, &opts).unwrap();
+    assert_eq!(summary.iterations, 2);
+    assert!(summary.completed);
+    assert_eq!(exec.calls().len(), 2);
+    assert_eq!(exec.calls()[0][0], "claude");
+    // The prompt never mentions the marker, so instructions for it follow.
+    let sent = exec.calls()[0].last().unwrap().clone();
+    assert!(sent.starts_with("prompt\n\n## Finishing\n"), "{sent}");
+    assert!(sent.contains(COMPLETE_MARKER), "{sent}");
+
+    // An iteration killed at the output limit is counted apart, and the
+    // loop carries on.
+    let exec = ScriptedRunner::new()
+        .over_limit(1024, "chatty\n")
+        .reply(0, &format!("done {COMPLETE_MARKER}\n"));
+    let summary = runner::run_loop_with(&exec, &opts).unwrap();
+    assert_eq!(summary.iterations, 2);
+    assert!(summary.completed);
+    assert_eq!((summary.over_limit, summary.killed), (1, 0));
+
+    // So is one that ran past the timeout, unless the loop is to stop at
+    // the first failure.
+    let exec = ScriptedRunner::new()
+        .timed_out(Duration::from_secs(60), "stuck\n")
+        .reply(0, &format!("done {COMPLETE_MARKER}\n"));
+    let summary = LoopRunner::builder()
+        .prompt("prompt")
+        .stop_on_failure(true)
+        .exec(&exec)
+        .run()
+        .unwrap();
+    assert_eq!((summary.iterations, summary.completed), (1, false));
+    assert_eq!((summary.timed_out, summary.killed), (1, 0));
+    assert!(summary.stopped_on_failure);
+
+    // The marker only counts once every gate passes.
+    let exec = ScriptedRunner::new()
+        .reply(0, &format!("{COMPLETE_MARKER}\n"))
+        .fail(1, "test failed")
+        .reply(0, &format!("{COMPLETE_MARKER}\n"))
+        .reply(0, "ok");
+    let mut events = Vec::new();
+    let summary = LoopRunner::builder()
+        .provider("codex")
+        .prompt("prompt")
+        .iterations(5)
+        .gate("cargo test")
+        .exec(&exec)
+        .cancel_token(CancelToken::new())
+        .on_event(|e| events.push(e.clone()))
+        .run()
+        .unwrap();
+    assert_eq!(summary.iterations, 2);
+    assert!(summary.completed);
+    assert_eq!(exec.calls()[1], ["sh", "-c", "cargo test"]);
+    let gates: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Gate { exit_code, .. } => Some(*exit_code),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(gates, [1, 0]);
+    assert!(matches!(
+        events[2],
+        Event::IterationEnd {
+            complete: false,
+            ..
+        }
+    ));
+    assert!(matches!(
+        events.last(),
+        Some(Event::SessionEnd {
+            iterations: 2,
+            completed: true
+        })
+    ));
+
+    // Cancelling the token stops the loop before the next iteration.
+    let exec = ScriptedRunner::new().reply(0, "one\n").reply(0, "two\n");
+    let cancel = CancelToken::new();
+    let summary = LoopRunner::builder()
+        .prompt("prompt")
+        .iterations(5)
+        .exec(&exec)
+        .cancel_token(cancel.clone())
+        .on_event(|e| {
+            if matches!(e, Event::IterationEnd { .. }) {
+                cancel.cancel();
+            }
+        })
+        .run()
+        .unwrap();
+    assert_eq!(summary.iterations, 1);
+    assert!(!summary.completed);
+    assert_eq!(exec.calls().len(), 1);
+
+    // A planning iteration runs with its own prompt, ignores the marker and
+    // skips the gates; what it said reaches the next iteration.
+    let exec = ScriptedRunner::new()
+        .reply(0, &format!("Do bd-2 first. {COMPLETE_MARKER}\n"))
+        .reply(0, &format!("{COMPLETE_MARKER}\n"))
+        .reply(0, "ok");
+    let mut events = Vec::new();
+    let summary = LoopRunner::builder()
+        .provider("codex")
+        .prompt("prompt")
+        .plan_prompt("plan only")
+        .carry_plan(true)
+        .iterations(5)
+        .gate("cargo test")
+        .exec(&exec)
+        .cancel_token(CancelToken::new())
+        .on_event(|e| events.push(e.clone()))
+        .run()
+        .unwrap();
+    assert_eq!(summary.iterations, 2);
+    assert!(summary.completed && summary.planned);
+    let calls = exec.calls();
+    assert_eq!(calls[0].last().map(String::as_str), Some("plan only"));
+    assert!(
+        calls[1]
+            .last()
+            .unwrap()
+            .starts_with("prompt\n\n## Plan from the planning iteration\n\nDo bd-2 first.")
+    );
+    assert_eq!(calls[2], ["sh", "-c", "cargo test"]);
+    assert!(matches!(
+        events[0],
+        Event::IterationStart { planning: true, .. }
+    ));
+
+    // --plan-only stops after planning.
+    let exec = ScriptedRunner::new().reply(0, "plan\n").reply(0, "work\n");
+    let summary = LoopRunner::builder()
+        .prompt("prompt")
+        .plan_prompt("plan only")
+        .plan_only(true)
+        .iterations(5)
+        .exec(&exec)
+        .cancel_token(CancelToken::new())
+        .run()
+        .unwrap();
+    assert_eq!((summary.iterations, summary.completed), (1, false));
+    assert_eq!(exec.calls().len(), 1);
+
+    // `ralph run` claims the task, runs the provider, then re-reads bd.
+    let exec = ScriptedRunner::new()
+        .reply(0, r#"{"id":"bd-1","title":"Fix it","status":"open"}"#)
+        .reply(0, "")
+        .reply(0, "working\n")
+        .reply(0, r#"{"id":"bd-1","title":"Fix it","status":"closed"}"#);
+    let outcome = run::run_task(&exec, &Beads, "droid", "bd-1", "SYSTEM").unwrap();
+    assert_eq!(outcome, TaskOutcome::Closed);
+    let calls = exec.calls();
+    assert_eq!(
+        calls[1],
+        ["bd", "update", "bd-1", "--status", "in_progress"]
+    );
+    assert_eq!(calls[2][0], "droid");
+    assert!(
+        calls[2]
+            .last()
+            .unwrap()
+            .contains("Work only on this task: bd-1")
+    );
+
+    // A blocked task never reaches the provider.
+    let exec = ScriptedRunner::new().reply(0, r#"{"id":"bd-2","status":"blocked"}"#);
+    let err = run::run_task(&exec, &Beads, "droid", "bd-2", "SYSTEM").unwrap_err();
+    assert_eq!(err.to_string(), "Task bd-2 is blocked");
+    assert_eq!(exec.calls().len(), 1);
+
+    // `once`, `loop` and `run` start every provider the same way; only the
+    // prompt after the flags differs.
+    for provider in VALID_PROVIDERS {
+        let flags = |calls: Vec<Vec<String>>| {
+            let mut argv = calls.into_iter().find(|c| c[0] == *provider).unwrap();
+            argv.pop();
+            argv
+        };
+        let exec = ScriptedRunner::new().reply(0, "ok\n");
+        runner::run_once_with(&exec, &RunOptions::new(*provider, "prompt")).unwrap();
+        let once = flags(exec.calls());
+        let exec = ScriptedRunner::new().reply(0, &format!("{COMPLETE_MARKER}\n"));
+        runner::run_loop_with(&exec, &RunOptions::new(*provider, "prompt")).unwrap();
+        assert_eq!(flags(exec.calls()), once, "{provider}");
+        let exec = ScriptedRunner::new()
+            .reply(0, r#"{"id":"bd-1","title":"Fix it","status":"open"}"#)
+            .reply(0, "")
+            .reply(0, "working\n")
+            .reply(0, r#"{"id":"bd-1","title":"Fix it","status":"closed"}"#);
+        run::run_task(&exec, &Beads, provider, "bd-1", "SYSTEM").unwrap();
+        assert_eq!(flags(exec.calls()), once, "{provider}");
+    }
+}